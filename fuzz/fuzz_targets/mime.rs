@@ -0,0 +1,25 @@
+/* MIME 解析的模糊测试 */
+/*
+# 模糊测试
+## 用法
+cargo +nightly fuzz run mime fuzz/corpus/mime tests/corpus     <-- 在仓库根目录运行，以 tests/corpus 中的邮件为种子
+## 说明
+mime::parse 不会因为任何输入 panic（见 src/mime/mod.rs），leaves()、text() 与 errors() 同样如此。
+ZitMail 只有二进制文件，这里直接引用 src/mime 的源文件；mime 不依赖其他模块。
+发现的问题修复后，将触发问题的输入精简后放入 tests/corpus，并在 src/mime/mod.rs 的测试中写明预期的结果。
+ */
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/mime/mod.rs"]
+#[allow(dead_code)]
+mod mime;
+
+fuzz_target!(|data: &[u8]| {
+    let message = mime::parse(data);
+    for leaf in message.leaves() {
+        leaf.text();
+    }
+    message.errors();
+});
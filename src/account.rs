@@ -0,0 +1,242 @@
+/* 命令 zmou domain、zmou account、zmou alias */
+/*
+# 域名与邮件账号
+## 用法
+zmou domain add example.com             <-- 添加本机域名，SMTP 服务只接收发往本机域名的邮件
+zmou account add manser@example.com     <-- 添加账号，交互式输入密码，自动创建 INBOX
+zmou account password manser@example.com
+zmou account pop3 manser@example.com delete   <-- POP3 取回邮件后删除，默认 keep 保留在服务器上
+zmou alias add postmaster@example.com manser@example.com   <-- 发往别名的邮件投递到账号，账号也可以使用别名发信
+地址统一转换为小写保存。
+ */
+use std::fmt;
+use std::sync::Arc;
+
+use crate::config::{Backend, Config};
+use crate::password::{self, Params, PasswordError};
+use crate::storage::{self, MailStore, StorageError, split_address};
+use crate::utils::{is_valid_address, is_valid_hostname};
+
+#[derive(Debug)]
+pub enum AccountError {
+    Storage(StorageError),
+    Password(PasswordError),
+    /// 参数不合法
+    Invalid(String),
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::Storage(e) => write!(f, "{}", e),
+            AccountError::Password(e) => write!(f, "{}", e),
+            AccountError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<StorageError> for AccountError {
+    fn from(e: StorageError) -> Self {
+        AccountError::Storage(e)
+    }
+}
+
+impl From<PasswordError> for AccountError {
+    fn from(e: PasswordError) -> Self {
+        AccountError::Password(e)
+    }
+}
+
+/// # 打开存储
+/// 与启动时相同，自动升级数据库结构。内存存储的修改在命令结束后丢失，因此拒绝执行。
+pub fn open(config: &Config) -> Result<Arc<dyn MailStore>, AccountError> {
+    if config.database.backend == Backend::Memory {
+        return Err(AccountError::Invalid(String::from("内存存储不会保留数据，请将 [Database] Backend 设置为 PostgreSQL 或 SQLite")));
+    }
+    let store = storage::open(&config.database)?;
+    store.migrate()?;
+    Ok(store)
+}
+
+/// # 命令 zmou domain
+/// 列出所有本机域名
+pub fn domain_list(config: &Config) -> Result<(), AccountError> {
+    let domains = open(config)?.domains()?;
+    if domains.is_empty() {
+        println!("暂无域名，使用 zmou domain add <域名> 添加");
+    }
+    for domain in domains {
+        println!("{}", domain);
+    }
+    Ok(())
+}
+
+/// # 命令 zmou domain add
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - name: 域名
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn domain_add(config: &Config, name: &str) -> Result<(), AccountError> {
+    if !is_valid_hostname(name) {
+        return Err(AccountError::Invalid(format!("{} 不是有效的域名", name)));
+    }
+    open(config)?.add_domain(name)?;
+    info!("已添加域名 {}", name.to_lowercase());
+    Ok(())
+}
+
+/// # 命令 zmou domain delete
+/// 同时删除该域名下的所有账号与邮件。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - name: 域名
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn domain_delete(config: &Config, name: &str) -> Result<(), AccountError> {
+    open(config)?.delete_domain(name)?;
+    warning!("已删除域名 {} 及其下的所有账号与邮件", name.to_lowercase());
+    Ok(())
+}
+
+/// # 命令 zmou account
+/// 列出所有账号
+pub fn list(config: &Config) -> Result<(), AccountError> {
+    let accounts = open(config)?.accounts()?;
+    if accounts.is_empty() {
+        println!("暂无账号，使用 zmou account add <地址> 添加");
+    }
+    for account in accounts {
+        match account.pop3_delete {
+            true => println!("{}（POP3 取回后删除）", account.address()),
+            false => println!("{}", account.address()),
+        }
+    }
+    Ok(())
+}
+
+/// # 命令 zmou account add
+/// 交互式读取密码，域名需要先通过 zmou domain add 添加。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - address: 邮件地址
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn add(config: &Config, address: &str) -> Result<(), AccountError> {
+    if !is_valid_address(address) {
+        return Err(AccountError::Invalid(format!("{} 不是有效的邮件地址", address)));
+    }
+    let store = open(config)?;
+    let (local_part, domain) = split_address(address).unwrap_or_default();
+    if !store.has_domain(&domain)? {
+        return Err(AccountError::Invalid(format!("域名 {} 不存在，请先使用 zmou domain add {} 添加", domain, domain)));
+    }
+    let plain = password::prompt()?;
+    let phc = password::hash(&plain, &Params::default())?;
+    let account = store.add_account(&local_part, &domain, &phc, &password::scram(&plain))?;
+    info!("已添加账号 {}", account.address());
+    Ok(())
+}
+
+/// # 命令 zmou account delete
+/// 同时删除该账号的所有邮件。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - address: 邮件地址
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn delete(config: &Config, address: &str) -> Result<(), AccountError> {
+    let store = open(config)?;
+    let Some(account) = store.account(address)? else {
+        return Err(StorageError::NotFound(format!("账号 {}", address)).into());
+    };
+    store.delete_account(account.id)?;
+    warning!("已删除账号 {} 及其所有邮件", account.address());
+    Ok(())
+}
+
+/// # 命令 zmou account password
+/// 交互式读取并重置账号密码。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - address: 邮件地址
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn reset_password(config: &Config, address: &str) -> Result<(), AccountError> {
+    let store = open(config)?;
+    let Some(account) = store.account(address)? else {
+        return Err(StorageError::NotFound(format!("账号 {}", address)).into());
+    };
+    let plain = password::prompt()?;
+    let phc = password::hash(&plain, &Params::default())?;
+    store.set_password(account.id, &phc, &password::scram(&plain))?;
+    info!("账号 {} 的密码已重置", account.address());
+    Ok(())
+}
+
+/// # 命令 zmou account pop3
+/// 设置 POP3 客户端取回邮件后是否从服务器删除。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - address: 邮件地址
+/// - delete: 取回后删除
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn set_pop3(config: &Config, address: &str, delete: bool) -> Result<(), AccountError> {
+    let store = open(config)?;
+    let Some(account) = store.account(address)? else {
+        return Err(StorageError::NotFound(format!("账号 {}", address)).into());
+    };
+    store.set_pop3_delete(account.id, delete)?;
+    match delete {
+        true => info!("账号 {} 的邮件将在 POP3 取回后删除", account.address()),
+        false => info!("账号 {} 的邮件将在 POP3 取回后保留在服务器上", account.address()),
+    }
+    Ok(())
+}
+
+/// # 命令 zmou alias
+/// 列出所有别名及其对应的账号
+pub fn alias_list(config: &Config) -> Result<(), AccountError> {
+    let aliases = open(config)?.aliases()?;
+    if aliases.is_empty() {
+        println!("暂无别名，使用 zmou alias add <别名> <账号> 添加");
+    }
+    for (alias, account) in aliases {
+        println!("{} -> {}", alias, account);
+    }
+    Ok(())
+}
+
+/// # 命令 zmou alias add
+/// 别名的域名需要是本机域名，且不能与已有账号相同。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - alias: 别名地址
+/// - address: 账号地址
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn alias_add(config: &Config, alias: &str, address: &str) -> Result<(), AccountError> {
+    if !is_valid_address(alias) {
+        return Err(AccountError::Invalid(format!("{} 不是有效的邮件地址", alias)));
+    }
+    let store = open(config)?;
+    let Some(account) = store.account(address)? else {
+        return Err(StorageError::NotFound(format!("账号 {}", address)).into());
+    };
+    store.add_alias(alias, account.id)?;
+    info!("已添加别名 {} -> {}", alias.to_lowercase(), account.address());
+    Ok(())
+}
+
+/// # 命令 zmou alias delete
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - alias: 别名地址
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn alias_delete(config: &Config, alias: &str) -> Result<(), AccountError> {
+    open(config)?.delete_alias(alias)?;
+    info!("已删除别名 {}", alias.to_lowercase());
+    Ok(())
+}
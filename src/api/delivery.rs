@@ -0,0 +1,93 @@
+/* API /delivery */
+/*
+# 投递状态接口
+## 用法
+GET /delivery?envid=<ENVID>          按提交时的 ENVID 查询
+GET /delivery?messageId=<Message-ID> 按原邮件的 Message-ID 查询，尖括号可省略
+GET /delivery/<编号>                 按队列编号查询，邮件离开队列后仍可查询
+## 说明
+返回每封邮件的每个收件人的最新状态 action：
+queued     等待投递
+sent       已交给支持 DSN 的服务器，之后可能收到对方的投递状态通知
+delayed    暂时无法投递，正在重试
+delivered  已投递到本机账号，或对方通知已投递
+relayed    已交给不支持 DSN 的服务器，不会再有通知
+expanded   对方通知已转发给多个地址
+failed     投递失败，已退信
+状态保留 [Queue] StatusRetention 天。
+ */
+use serde_json::{Value, json};
+
+use super::http::{Request, Response};
+use crate::storage::{Delivery, DeliveryKey, MailStore};
+use crate::utils::to_rfc3339;
+
+/// # 处理 /delivery 下的请求
+/// ## 参数
+/// - request: 请求
+/// - segments: 去掉 delivery 之后的路径
+/// - store: 邮件存储
+/// - time_zone: General.TimeZone
+/// ## 返回值
+/// - Response
+pub fn handle(request: &Request, segments: &[&str], store: &dyn MailStore, time_zone: &str) -> Response {
+    if request.method != "GET" {
+        return Response::error(405, format!("不支持 {} 方法", request.method));
+    }
+    let key = match segments {
+        [] => match (request.param("envid"), request.param("messageId")) {
+            (Some(envid), _) => DeliveryKey::Envid(envid.to_string()),
+            (None, Some(id)) => DeliveryKey::MessageId(id.trim().trim_start_matches('<').trim_end_matches('>').to_string()),
+            (None, None) => return Response::error(400, "请指定 envid 或 messageId"),
+        },
+        [id] => match id.parse::<i64>() {
+            Ok(id) => DeliveryKey::Queue(id),
+            Err(_) => return Response::error(404, format!("{} 不是有效的队列编号", id)),
+        },
+        _ => return Response::error(404, format!("{} 不存在", request.path)),
+    };
+    match store.deliveries(&key) {
+        Ok(deliveries) if deliveries.is_empty() && matches!(key, DeliveryKey::Queue(_)) => {
+            Response::error(404, format!("{} 不存在", request.path))
+        }
+        Ok(deliveries) => Response::json(200, json!({ "messages": group(&deliveries, time_zone) })),
+        Err(e) => {
+            error!("API 无法查询投递状态：{}", e);
+            Response::error(500, e.to_string())
+        }
+    }
+}
+
+/// # 按邮件分组
+/// 同一队列编号的收件人合并为一封邮件，按编号排序。
+fn group(deliveries: &[Delivery], time_zone: &str) -> Vec<Value> {
+    let mut ids: Vec<i64> = deliveries.iter().map(|delivery| delivery.queue_id).collect();
+    ids.sort_unstable();
+    ids.dedup();
+    ids.into_iter()
+        .map(|id| {
+            let recipients: Vec<&Delivery> = deliveries.iter().filter(|delivery| delivery.queue_id == id).collect();
+            let first = recipients[0];
+            let recipients: Vec<Value> = recipients
+                .iter()
+                .map(|delivery| {
+                    json!({
+                        "address": delivery.recipient,
+                        "action": delivery.action,
+                        "status": delivery.status,
+                        "diagnostic": delivery.diagnostic,
+                        "remoteMta": delivery.remote_mta,
+                        "updated": to_rfc3339(delivery.updated_at, time_zone),
+                    })
+                })
+                .collect();
+            json!({
+                "id": id,
+                "envid": first.envid,
+                "messageId": first.message_id,
+                "sender": first.sender,
+                "recipients": recipients,
+            })
+        })
+        .collect()
+}
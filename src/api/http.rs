@@ -0,0 +1,267 @@
+/* HTTP/1.1 */
+/*
+# 请求与响应
+## 用法
+while let Some(request) = http::read_request(&mut connection, http::MAX_BODY)? {
+    let response = Response::json(200, json!({ "ok": true }));
+    response.write(&mut connection, request.keep_alive())?;
+}
+## 说明
+只实现 API 与 JMAP 需要的部分：Content-Length 请求体、持久连接与流式响应，不支持分块传输编码（RFC 9112）。
+ */
+use serde_json::Value;
+use std::io::{self, BufRead, Read};
+
+use crate::tls::Connection;
+
+/// 请求行与每个请求头的长度上限
+const MAX_LINE: u64 = 8192;
+
+/// 请求头的数量上限
+const MAX_HEADERS: usize = 100;
+
+/// 请求体的默认大小上限
+pub const MAX_BODY: usize = 1024 * 1024;
+
+/// HTTP 请求
+#[derive(Debug)]
+pub struct Request {
+    pub method: String,
+    /// 路径，不含查询参数
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    /// 请求头名称为小写
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// HTTP/1.0
+    legacy: bool,
+}
+
+impl Request {
+    /// # 请求头
+    /// ## 参数
+    /// - name: 名称，不区分大小写
+    /// ## 返回值
+    /// - Option<&str>
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// # 查询参数
+    /// ## 参数
+    /// - name: 名称
+    /// ## 返回值
+    /// - Option<&str>，空值视为未提供
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, value)| key == name && !value.is_empty()).map(|(_, value)| value.as_str())
+    }
+
+    /// # 路径的各段
+    /// 例如 /queue/12/retry 为 ["queue", "12", "retry"]
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+
+    /// # 响应后是否保持连接
+    pub fn keep_alive(&self) -> bool {
+        match self.header("Connection") {
+            Some(value) if value.eq_ignore_ascii_case("close") => false,
+            Some(value) if value.eq_ignore_ascii_case("keep-alive") => true,
+            _ => !self.legacy,
+        }
+    }
+}
+
+/// 响应正文
+#[derive(Debug)]
+pub enum Body {
+    Json(Value),
+    /// (Content-Type, 内容)
+    Bytes(String, Vec<u8>),
+}
+
+/// HTTP 响应
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Body,
+}
+
+impl Response {
+    /// # JSON 响应
+    /// ## 参数
+    /// - status: 状态码，例如 200
+    /// - body: 正文
+    /// ## 返回值
+    /// - Response
+    pub fn json(status: u16, body: Value) -> Response {
+        Response { status, headers: Vec::new(), body: Body::Json(body) }
+    }
+
+    /// # 其他类型的响应
+    /// ## 参数
+    /// - status: 状态码
+    /// - content_type: 例如 message/rfc822
+    /// - data: 正文
+    /// ## 返回值
+    /// - Response
+    pub fn bytes(status: u16, content_type: impl Into<String>, data: Vec<u8>) -> Response {
+        Response { status, headers: Vec::new(), body: Body::Bytes(content_type.into(), data) }
+    }
+
+    /// # 错误响应
+    /// 正文为 {"error": 原因}
+    /// ## 参数
+    /// - status: 状态码，例如 404
+    /// - message: 原因
+    /// ## 返回值
+    /// - Response
+    pub fn error(status: u16, message: impl Into<String>) -> Response {
+        Response::json(status, serde_json::json!({ "error": message.into() }))
+    }
+
+    /// # 添加响应头
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Response {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// # 发送响应
+    /// ## 参数
+    /// - connection: 连接
+    /// - keep_alive: 是否保持连接
+    /// ## 返回值
+    /// - io::Result<()>
+    pub fn write(&self, connection: &mut Connection, keep_alive: bool) -> io::Result<()> {
+        let (content_type, body) = match &self.body {
+            Body::Json(value) => ("application/json; charset=utf-8", serde_json::to_vec_pretty(value).map_err(io::Error::other)?),
+            Body::Bytes(content_type, data) => (content_type.as_str(), data.clone()),
+        };
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: {}\r\n",
+            self.status,
+            reason(self.status),
+            content_type,
+            body.len(),
+            if keep_alive { "keep-alive" } else { "close" }
+        );
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        connection.send(&[head.as_bytes(), &body].concat())
+    }
+}
+
+/// # 发送流式响应的头部
+/// 不含 Content-Length，之后的内容直接写入连接，结束时关闭连接。
+/// ## 参数
+/// - connection: 连接
+/// - content_type: 例如 text/event-stream
+/// - headers: 其他响应头
+/// ## 返回值
+/// - io::Result<()>
+pub fn write_stream_head(connection: &mut Connection, content_type: &str, headers: &[(&'static str, String)]) -> io::Result<()> {
+    let mut head = format!("HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n", content_type);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    connection.send(head.as_bytes())
+}
+
+/// # 读取一个请求
+/// ## 参数
+/// - reader: 连接
+/// - max_body: 请求体的大小上限，通常为 MAX_BODY
+/// ## 返回值
+/// - io::Result<Option<Request>>，对方在请求之间关闭连接时为 None，格式错误时为 InvalidData
+pub fn read_request(reader: &mut impl BufRead, max_body: usize) -> io::Result<Option<Request>> {
+    let Some(line) = read_line(reader)? else {
+        return Ok(None);
+    };
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid(format!("无效的请求行 {}", line)));
+    };
+    let legacy = match version {
+        "HTTP/1.1" => false,
+        "HTTP/1.0" => true,
+        _ => return Err(invalid(format!("不支持的版本 {}", version))),
+    };
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if !path.starts_with('/') {
+        return Err(invalid(format!("无效的路径 {}", target)));
+    }
+
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?.ok_or_else(|| invalid(String::from("请求头不完整")))?;
+        if line.is_empty() {
+            break;
+        }
+        if headers.len() >= MAX_HEADERS {
+            return Err(invalid(String::from("请求头过多")));
+        }
+        let (name, value) = line.split_once(':').ok_or_else(|| invalid(format!("无效的请求头 {}", line)))?;
+        headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+
+    let mut request = Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        query: url::form_urlencoded::parse(query.as_bytes()).into_owned().collect(),
+        headers,
+        body: Vec::new(),
+        legacy,
+    };
+    if request.header("Transfer-Encoding").is_some() {
+        return Err(invalid(String::from("不支持 Transfer-Encoding")));
+    }
+    if let Some(length) = request.header("Content-Length") {
+        let length: usize = length.parse().map_err(|_| invalid(format!("无效的 Content-Length {}", length)))?;
+        if length > max_body {
+            return Err(invalid(format!("请求体超过 {} 字节", max_body)));
+        }
+        request.body = vec![0; length];
+        reader.read_exact(&mut request.body)?;
+    }
+    Ok(Some(request))
+}
+
+/// # 读取一行
+/// 连接在行首关闭时返回 None。
+fn read_line(reader: &mut impl BufRead) -> io::Result<Option<String>> {
+    let mut buffer = Vec::new();
+    if reader.take(MAX_LINE).read_until(b'\n', &mut buffer)? == 0 {
+        return Ok(None);
+    }
+    if !buffer.ends_with(b"\n") {
+        return Err(invalid(String::from("行过长或不完整")));
+    }
+    let line = String::from_utf8(buffer).map_err(|_| invalid(String::from("请求头不是有效的 UTF-8")))?;
+    Ok(Some(line.trim_end_matches(['\r', '\n']).to_string()))
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// 状态码的原因短语
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        307 => "Temporary Redirect",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
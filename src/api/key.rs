@@ -0,0 +1,229 @@
+/* API 鉴权密钥 [API] Keygen */
+/*
+# 密钥
+密钥由系统安全随机数生成，格式为 zm_ + 64 位 16 进制字符，仅在生成时显示一次。
+配置文件中只保存 Id 与密钥的 SHA-256 值，以及创建、过期与最后使用时间（RFC 3339，使用 General.TimeZone）。
+ */
+use chrono::{Duration, Utc};
+use rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use subtle::ConstantTimeEq;
+use toml_edit::{Array, InlineTable, Value};
+
+use crate::config::{self, ApiKey, Config, ConfigError};
+use crate::utils::{format_time, parse_rfc3339, to_hex, to_rfc3339};
+
+/// 密钥前缀
+const PREFIX: &str = "zm_";
+
+#[derive(Debug)]
+pub enum KeyError {
+    Config(ConfigError),
+    /// Id 不合法、重复或不存在
+    Id(String),
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Config(e) => write!(f, "{}", e),
+            KeyError::Id(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl From<ConfigError> for KeyError {
+    fn from(e: ConfigError) -> Self {
+        KeyError::Config(e)
+    }
+}
+
+/// # 生成密钥
+/// ## 返回值
+/// - String，例如 zm_3f2a...
+pub fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", PREFIX, to_hex(&bytes))
+}
+
+/// # 密钥的 SHA-256 值
+/// ## 参数
+/// - key: 明文密钥
+/// ## 返回值
+/// - String，64 位 16 进制字符
+pub fn digest(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+/// # 是否为有效的 SHA-256 值
+/// ## 参数
+/// - hash: &str
+/// ## 返回值
+/// - bool
+pub fn is_valid_digest(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// # 是否为合法的密钥 Id
+/// 仅允许字母、数字、- 与 _
+/// ## 参数
+/// - id: &str
+/// ## 返回值
+/// - bool
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// # 命令 zmou api add <id>
+/// ## 参数
+/// - path: 配置文件路径
+/// - id: 密钥 Id
+/// - expires: 有效天数，为空时永不过期
+/// ## 返回值
+/// - Result<(), KeyError>
+pub fn add(path: &Path, id: &str, expires: Option<u32>) -> Result<(), KeyError> {
+    if !is_valid_id(id) {
+        return Err(KeyError::Id(format!("密钥 Id {} 不合法，仅允许字母、数字、- 与 _", id)));
+    }
+    let config = Config::load(path)?;
+    if config.api.keygen.iter().any(|entry| entry.id == id) {
+        return Err(KeyError::Id(format!("密钥 {} 已存在", id)));
+    }
+
+    let key = generate();
+    let now = Utc::now();
+    let time_zone = &config.general.time_zone;
+    let mut entry = InlineTable::new();
+    entry.insert("Id", Value::from(id));
+    entry.insert("Hash", Value::from(digest(&key)));
+    entry.insert("Created", Value::from(to_rfc3339(now, time_zone)));
+    if let Some(days) = expires {
+        entry.insert("Expires", Value::from(to_rfc3339(now + Duration::days(days as i64), time_zone)));
+    }
+    config::update(path, |document| {
+        if let Some(keygen) = document["API"]["Keygen"].as_array_mut() {
+            keygen.push(entry);
+            format_keygen(keygen);
+        }
+    })?;
+
+    info!("已生成密钥 {}", id);
+    println!("{}", key);
+    warning!("密钥仅显示这一次，请立即妥善保存");
+    Ok(())
+}
+
+/// # 命令 zmou api delete <id>
+/// ## 参数
+/// - path: 配置文件路径
+/// - id: 密钥 Id
+/// ## 返回值
+/// - Result<(), KeyError>
+pub fn delete(path: &Path, id: &str) -> Result<(), KeyError> {
+    let config = Config::load(path)?;
+    if !config.api.keygen.iter().any(|entry| entry.id == id) {
+        return Err(KeyError::Id(format!("密钥 {} 不存在", id)));
+    }
+    config::update(path, |document| {
+        if let Some(keygen) = document["API"]["Keygen"].as_array_mut() {
+            keygen.retain(|entry| entry_id(entry) != Some(id));
+            format_keygen(keygen);
+        }
+    })?;
+    info!("已删除密钥 {}", id);
+    Ok(())
+}
+
+/// # 命令 zmou api
+/// 列出所有密钥
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), KeyError>
+pub fn list(path: &Path) -> Result<(), KeyError> {
+    let config = Config::load(path)?;
+    if config.api.keygen.is_empty() {
+        println!("没有任何密钥，使用 zmou api add <id> 生成一个密钥");
+        return Ok(());
+    }
+    let time_zone = &config.general.time_zone;
+    let show = |time: &Option<String>, none: &str| match time.as_deref().and_then(parse_rfc3339) {
+        Some(time) => format_time(time, time_zone),
+        None => none.to_string(),
+    };
+    for entry in &config.api.keygen {
+        let state = if is_expired(entry) { "（已过期）" } else { "" };
+        println!("{}{}", entry.id, state);
+        println!("  创建时间：{}", show(&Some(entry.created.clone()), "未知"));
+        println!("  过期时间：{}", show(&entry.expires, "永不过期"));
+        println!("  最后使用：{}", show(&entry.last_used, "从未使用"));
+    }
+    Ok(())
+}
+
+/// # 校验请求携带的密钥
+/// 与所有未过期密钥的 SHA-256 值做常量时间比较。
+/// ## 参数
+/// - config: &Config
+/// - key: 明文密钥
+/// ## 返回值
+/// - Option<&ApiKey>
+pub fn authenticate<'a>(config: &'a Config, key: &str) -> Option<&'a ApiKey> {
+    let hash = digest(key);
+    config
+        .api
+        .keygen
+        .iter()
+        .find(|entry| bool::from(entry.hash.as_bytes().ct_eq(hash.as_bytes())) && !is_expired(entry))
+}
+
+/// # 记录密钥的最后使用时间
+/// ## 参数
+/// - path: 配置文件路径
+/// - id: 密钥 Id
+/// - time_zone: General.TimeZone
+/// ## 返回值
+/// - Result<(), KeyError>
+pub fn touch(path: &Path, id: &str, time_zone: &str) -> Result<(), KeyError> {
+    let now = to_rfc3339(Utc::now(), time_zone);
+    config::update(path, |document| {
+        let Some(keygen) = document["API"]["Keygen"].as_array_mut() else { return };
+        for entry in keygen.iter_mut() {
+            if entry_id(entry) == Some(id)
+                && let Some(table) = entry.as_inline_table_mut()
+            {
+                table.insert("LastUsed", Value::from(now.as_str()));
+                table.fmt();
+            }
+        }
+        format_keygen(keygen);
+    })?;
+    Ok(())
+}
+
+/// # 密钥是否已过期
+/// ## 参数
+/// - entry: &ApiKey
+/// ## 返回值
+/// - bool
+pub fn is_expired(entry: &ApiKey) -> bool {
+    entry.expires.as_deref().and_then(parse_rfc3339).is_some_and(|expires| expires <= Utc::now())
+}
+
+/// # 读取 Keygen 中一项的 Id
+fn entry_id(entry: &Value) -> Option<&str> {
+    entry.as_inline_table()?.get("Id")?.as_str()
+}
+
+/// # 每个密钥占一行
+fn format_keygen(keygen: &mut Array) {
+    for entry in keygen.iter_mut() {
+        entry.decor_mut().set_prefix("\n    ");
+        entry.decor_mut().set_suffix("");
+    }
+    keygen.set_trailing_comma(!keygen.is_empty());
+    keygen.set_trailing(if keygen.is_empty() { "" } else { "\n" });
+}
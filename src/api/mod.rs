@@ -0,0 +1,54 @@
+/* API 接口 */
+/*
+# API 模块
+## 结构
+http.rs     HTTP/1.1 请求与响应
+server.rs   监听端口、鉴权与分发请求
+queue.rs    /queue 发送队列
+delivery.rs /delivery 投递状态
+key.rs      密钥与命令 zmou api
+ */
+pub mod delivery;
+pub mod http;
+pub mod key;
+pub mod queue;
+pub mod server;
+
+/// 命令 zmou help api
+pub const HELP: &str = "API 接口
+
+启用：在配置文件 [API] 中将 Enable 设为 true，并配置监听地址 Address 与端口 Port。
+
+鉴权：每个请求都需要在请求头中携带密钥
+  Authorization: Bearer <密钥>
+
+密钥管理：
+  zmou api                              列出所有密钥
+  zmou api add <id> [--expires <天数>]  生成一个密钥，密钥仅显示一次
+  zmou api delete <id>                  删除一个密钥
+
+配置文件中只保存密钥的 SHA-256 值。密钥泄露时请删除后重新生成。
+修改密钥后需要重启 ZitMail。
+
+请求与响应的正文均为 JSON，出错时返回 {\"error\": 原因}。
+时间为 General.TimeZone 时区的 RFC 3339 格式。
+
+发送队列：
+  GET    /queue                 列出队列中的邮件，可用 ?sender=<地址>&domain=<域名> 筛选
+  GET    /queue/<编号>          查看一封邮件，包括原邮件的邮件头
+  POST   /queue/flush           立即重试所有未暂停的邮件
+  POST   /queue/<编号>/retry    立即重试
+  POST   /queue/<编号>/hold     暂停投递
+  POST   /queue/<编号>/release  恢复投递
+  DELETE /queue/<编号>          删除，不发送退信
+  DELETE /queue?sender=&domain= 按条件删除，至少指定一个条件
+
+投递状态（邮件离开队列后仍保留 [Queue] StatusRetention 天）：
+  GET    /delivery?envid=<ENVID>       按提交时 MAIL FROM 的 ENVID 参数查询
+  GET    /delivery?messageId=<ID>      按原邮件的 Message-ID 查询
+  GET    /delivery/<编号>              按队列编号查询
+  每个收件人的 action 为 queued、sent、delayed、delivered、relayed、expanded 或 failed，
+  收到对方服务器的投递状态通知（DSN）后自动更新。
+
+示例：
+  curl -H \"Authorization: Bearer <密钥>\" http://127.0.0.1:1012/queue";
@@ -0,0 +1,118 @@
+/* API /queue */
+/*
+# 发送队列接口
+## 用法
+GET    /queue?sender=&domain=      列出队列中的邮件
+GET    /queue/<编号>               查看一封邮件，包括原邮件的邮件头
+POST   /queue/flush                立即重试所有未暂停的邮件
+POST   /queue/<编号>/retry         立即重试
+POST   /queue/<编号>/hold          暂停投递
+POST   /queue/<编号>/release       恢复投递
+DELETE /queue/<编号>               删除，不发送退信
+DELETE /queue?sender=&domain=      按条件删除，至少指定一个条件
+## 说明
+时间为 General.TimeZone 时区的 RFC 3339 格式，age 为已排队的秒数。
+ */
+use chrono::Utc;
+use serde_json::{Value, json};
+
+use super::http::{Request, Response};
+use crate::smtp::{bounce, queue};
+use crate::smtp::queue::Filter;
+use crate::storage::{MailStore, QueueEntry, StorageError};
+use crate::utils::to_rfc3339;
+
+/// # 处理 /queue 下的请求
+/// ## 参数
+/// - request: 请求
+/// - segments: 去掉 queue 之后的路径
+/// - store: 邮件存储
+/// - time_zone: General.TimeZone
+/// ## 返回值
+/// - Response
+pub fn handle(request: &Request, segments: &[&str], store: &dyn MailStore, time_zone: &str) -> Response {
+    let result = match (request.method.as_str(), segments) {
+        ("GET", []) => list(store, &filter(request), time_zone),
+        ("DELETE", []) => purge(store, &filter(request)),
+        ("POST", ["flush"]) => queue::flush(store).map(|count| Response::json(200, json!({ "retried": count }))),
+        (method, [] | ["flush"]) => return Response::error(405, format!("不支持 {} 方法", method)),
+        (method, [id, rest @ ..]) => {
+            let Ok(id) = id.parse::<i64>() else {
+                return Response::error(404, format!("{} 不是有效的队列编号", id));
+            };
+            match (method, rest) {
+                ("GET", []) => show(store, id, time_zone),
+                ("DELETE", []) => store.dequeue(id).map(|_| ok(id)),
+                ("POST", ["retry"]) => queue::retry(store, id).map(|_| ok(id)),
+                ("POST", ["hold"]) => queue::hold(store, id, true).map(|_| ok(id)),
+                ("POST", ["release"]) => queue::hold(store, id, false).map(|_| ok(id)),
+                (_, [] | ["retry" | "hold" | "release"]) => return Response::error(405, format!("不支持 {} 方法", method)),
+                _ => return Response::error(404, format!("{} 不存在", request.path)),
+            }
+        }
+    };
+    match result {
+        Ok(response) => response,
+        Err(StorageError::NotFound(what)) => Response::error(404, format!("{} 不存在", what)),
+        Err(e) => {
+            error!("API 无法访问发送队列：{}", e);
+            Response::error(500, e.to_string())
+        }
+    }
+}
+
+/// # 查询参数中的筛选条件
+fn filter(request: &Request) -> Filter {
+    Filter { sender: request.param("sender").map(String::from), domain: request.param("domain").map(String::from) }
+}
+
+fn list(store: &dyn MailStore, filter: &Filter, time_zone: &str) -> Result<Response, StorageError> {
+    let entries: Vec<Value> = queue::select(store, filter)?.iter().map(|entry| describe(entry, time_zone)).collect();
+    Ok(Response::json(200, json!({ "messages": entries })))
+}
+
+fn show(store: &dyn MailStore, id: i64, time_zone: &str) -> Result<Response, StorageError> {
+    let entry = queue::entry(store, id)?;
+    let raw = store.queue_raw(id)?.ok_or(StorageError::NotFound(format!("队列 #{}", id)))?;
+    let mut body = describe(&entry, time_zone);
+    body["size"] = json!(raw.len());
+    body["headers"] = json!(String::from_utf8_lossy(bounce::headers(&raw)));
+    Ok(Response::json(200, body))
+}
+
+fn purge(store: &dyn MailStore, filter: &Filter) -> Result<Response, StorageError> {
+    if filter.is_empty() {
+        return Ok(Response::error(400, "请指定 sender 或 domain"));
+    }
+    let count = queue::purge(store, filter)?;
+    Ok(Response::json(200, json!({ "deleted": count })))
+}
+
+fn ok(id: i64) -> Response {
+    Response::json(200, json!({ "id": id }))
+}
+
+/// # 一封邮件的 JSON 表示
+fn describe(entry: &QueueEntry, time_zone: &str) -> Value {
+    let recipients: Vec<Value> = entry
+        .recipients
+        .iter()
+        .map(|recipient| {
+            json!({
+                "address": recipient.recipient,
+                "attempts": recipient.attempts,
+                "nextAttempt": to_rfc3339(recipient.next_attempt, time_zone),
+                "lastError": recipient.last_error,
+            })
+        })
+        .collect();
+    json!({
+        "id": entry.id,
+        "sender": entry.sender,
+        "created": to_rfc3339(entry.created_at, time_zone),
+        "age": (Utc::now() - entry.created_at).num_seconds().max(0),
+        "held": entry.held,
+        "envid": entry.dsn.envid,
+        "recipients": recipients,
+    })
+}
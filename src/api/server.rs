@@ -0,0 +1,159 @@
+/* API 监听 */
+use rustls::ServerConfig;
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::http::{self, Request, Response};
+use super::key;
+use crate::config::Config;
+use crate::default::CONFIG_PATH;
+use crate::storage::MailStore;
+use crate::tls::{Connection, Stream};
+
+/// 同时处理的连接数上限
+const MAX_CONNECTIONS: usize = 64;
+
+/// 等待请求的超时
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 同一密钥两次记录最后使用时间的最短间隔，避免频繁写入配置文件
+const TOUCH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// 连接编号
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 当前连接数
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// 所有连接共用的状态
+struct State {
+    /// 启动时读取的配置，修改密钥后需要重启
+    config: Config,
+    store: Arc<dyn MailStore>,
+    /// 密钥 Id 与最后一次记录使用时间的时刻，同时保证配置文件不会被并发写入
+    touched: Mutex<HashMap<String, Instant>>,
+}
+
+/// # 启动 API 服务
+/// 绑定端口后在后台线程中接受连接，每个连接使用一个线程。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - tls: TLS 配置，[API] TLS 为 false 时为 None
+/// - store: 邮件存储
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start(config: &Config, tls: Option<Arc<ServerConfig>>, store: Arc<dyn MailStore>) -> io::Result<JoinHandle<()>> {
+    let section = &config.api;
+    let address: IpAddr = section.address.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, section.address.clone()))?;
+    let socket = TcpListener::bind(SocketAddr::new(address, section.port))?;
+    info!("API 服务已启动，监听 {}", socket.local_addr()?);
+    if section.keygen.is_empty() {
+        warning!("[API] 没有任何密钥，所有请求都会被拒绝，请使用 zmou api add <id> 生成密钥");
+    }
+
+    let state = Arc::new(State { config: config.clone(), store, touched: Mutex::new(HashMap::new()) });
+    thread::Builder::new().name(String::from("api")).spawn(move || {
+        for stream in socket.incoming() {
+            match stream {
+                Ok(stream) => accept(stream, &tls, &state),
+                Err(e) => warning!("API 无法接受连接：{}", e),
+            }
+        }
+    })
+}
+
+/// # 为新连接创建线程
+fn accept(stream: TcpStream, tls: &Option<Arc<ServerConfig>>, state: &Arc<State>) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            debug!("API #{} 无法获取客户端地址：{}", id, e);
+            return;
+        }
+    };
+    debug!("API #{} 来自 {} 的连接", id, peer);
+
+    if CONNECTIONS.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        warning!("API #{} 连接数已达上限 {}，拒绝连接", id, MAX_CONNECTIONS);
+        return;
+    }
+
+    let tls = tls.clone();
+    let state = Arc::clone(state);
+    let spawned = thread::Builder::new().name(format!("api-{}", id)).spawn(move || {
+        if let Err(e) = handle(stream, id, tls, &state) {
+            debug!("API #{} 连接中断：{}", id, e);
+        }
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    });
+    if let Err(e) = spawned {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        error!("API #{} 无法创建线程：{}", id, e);
+    }
+}
+
+/// # 处理一个连接
+/// 依次处理持久连接上的每个请求。
+fn handle(stream: TcpStream, id: u64, tls: Option<Arc<ServerConfig>>, state: &State) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut connection = match &tls {
+        Some(config) => Connection::new(Stream::accept(stream, config)?),
+        None => Connection::new(Stream::Plain(stream)),
+    };
+    loop {
+        let request = match http::read_request(&mut connection, http::MAX_BODY) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("API #{} 无效的请求：{}", id, e);
+                return Response::error(400, e.to_string()).write(&mut connection, false);
+            }
+            Err(e) => return Err(e),
+        };
+        let response = respond(&request, id, state);
+        info!("API #{} {} {} {}", id, request.method, request.path, response.status);
+        let keep_alive = request.keep_alive();
+        response.write(&mut connection, keep_alive)?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// # 鉴权并分发请求
+fn respond(request: &Request, id: u64, state: &State) -> Response {
+    let key = request.header("Authorization").and_then(|value| value.strip_prefix("Bearer ")).map(str::trim);
+    let Some(entry) = key.and_then(|key| key::authenticate(&state.config, key)) else {
+        debug!("API #{} 密钥无效或已过期", id);
+        return Response::error(401, "密钥无效或已过期").header("WWW-Authenticate", "Bearer");
+    };
+    touch(state, &entry.id);
+
+    let time_zone = &state.config.general.time_zone;
+    match request.segments().as_slice() {
+        ["queue", rest @ ..] => super::queue::handle(request, rest, state.store.as_ref(), time_zone),
+        ["delivery", rest @ ..] => super::delivery::handle(request, rest, state.store.as_ref(), time_zone),
+        _ => Response::error(404, format!("{} 不存在", request.path)),
+    }
+}
+
+/// # 记录密钥的最后使用时间
+fn touch(state: &State, id: &str) {
+    let mut touched = state.touched.lock().unwrap_or_else(|e| e.into_inner());
+    if touched.get(id).is_some_and(|time| time.elapsed() < TOUCH_INTERVAL) {
+        return;
+    }
+    touched.insert(id.to_string(), Instant::now());
+    if let Err(e) = key::touch(Path::new(CONFIG_PATH), id, &state.config.general.time_zone) {
+        warning!("无法记录密钥 {} 的使用时间：{}", id, e);
+    }
+}
@@ -0,0 +1,325 @@
+/* 命令行参数解析 */
+/*
+# 命令行
+## 用法
+zmou [--debug] [命令] [参数]
+Cli::parse(std::env::args().skip(1)) 解析参数，失败时返回可直接展示给用户的错误信息。
+ */
+
+use crate::password::Params;
+use crate::smtp::queue::Filter;
+
+/// 帮助信息
+pub const USAGE: &str = "用法: zmou [--debug] [命令]
+
+命令:
+  （无命令）              启动 ZitMail
+  config                  查看配置文件
+  config remake           重置配置文件
+  config edit             使用自带的编辑器编辑配置文件
+  password                重置主账号密码
+  db migrate              升级数据库结构到最新版本
+  db status               查看数据库结构版本
+  db rollback             回滚最近的一个数据库结构版本
+  update                  更新到最新版本
+  update --rollback       回滚到更新前的版本
+  api                     列出所有 API 密钥
+  api add <id>            生成一个 API 密钥，可用 --expires <天数> 设置有效期
+  api delete <id>         删除一个 API 密钥
+  domain                  列出所有本机域名
+  domain add <域名>       添加本机域名
+  domain delete <域名>    删除本机域名及其下的所有账号与邮件
+  account                 列出所有邮件账号
+  account add <地址>      添加邮件账号
+  account delete <地址>   删除邮件账号及其所有邮件
+  account password <地址> 重置邮件账号密码
+  account pop3 <地址> <keep|delete>
+                          POP3 取回邮件后保留在服务器上或删除
+  alias                   列出所有别名
+  alias add <别名> <账号> 添加别名，发往别名的邮件投递到账号
+  alias delete <别名>     删除别名
+  queue [list]            列出发送队列，可用 --sender <地址>、--domain <域名> 筛选
+  queue show <编号>       查看队列中的邮件
+  queue retry <编号>      立即重试一封邮件
+  queue flush             立即重试所有邮件
+  queue hold <编号>       暂停投递
+  queue release <编号>    恢复投递
+  queue delete <编号>     删除一封邮件，不发送退信；
+                          也可用 --sender、--domain 按条件删除
+  help timezone [关键字]  查看时区列表，可按关键字筛选
+  help api                查看 API 帮助
+
+选项:
+  --debug                 临时启用调试模式
+  --memory <KiB>          Argon2 内存参数，仅用于 password
+  --time <次数>           Argon2 迭代次数，仅用于 password
+  --parallelism <并行度>  Argon2 并行度，仅用于 password
+  --sender <地址>         按发件人筛选，仅用于 queue 与 queue delete
+  --domain <域名>         按收件人域名筛选，仅用于 queue 与 queue delete
+  -h, --help              查看帮助";
+
+#[derive(Debug)]
+pub struct Cli {
+    pub debug: bool,
+    pub command: Command,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Serve,
+    Config(ConfigAction),
+    Help(HelpTopic),
+    Password(Params),
+    Api(ApiAction),
+    Update { rollback: bool },
+    Db(DbAction),
+    Domain(DomainAction),
+    Account(AccountAction),
+    Alias(AliasAction),
+    Queue(QueueAction),
+}
+
+#[derive(Debug)]
+pub enum ConfigAction {
+    View,
+    Remake,
+    Edit,
+}
+
+#[derive(Debug)]
+pub enum DbAction {
+    Migrate,
+    Status,
+    Rollback,
+}
+
+#[derive(Debug)]
+pub enum DomainAction {
+    List,
+    Add(String),
+    Delete(String),
+}
+
+#[derive(Debug)]
+pub enum AccountAction {
+    List,
+    Add(String),
+    Delete(String),
+    Password(String),
+    /// 设置 POP3 取回邮件后是否删除
+    Pop3 { address: String, delete: bool },
+}
+
+#[derive(Debug)]
+pub enum AliasAction {
+    List,
+    Add { alias: String, account: String },
+    Delete(String),
+}
+
+#[derive(Debug)]
+pub enum QueueAction {
+    List(Filter),
+    Show(i64),
+    Retry(i64),
+    Flush,
+    Hold(i64),
+    Release(i64),
+    /// 编号为空时按条件删除
+    Delete(Option<i64>, Filter),
+}
+
+#[derive(Debug)]
+pub enum HelpTopic {
+    General,
+    /// -h 或 --help 跟在命令之后，例如 zmou config -h
+    Command(String),
+    TimeZone(Option<String>),
+    Api,
+}
+
+#[derive(Debug)]
+pub enum ApiAction {
+    List,
+    Add { id: String, expires: Option<u32> },
+    Delete(String),
+}
+
+impl Cli {
+    /// # 解析命令行参数
+    /// ## 参数
+    /// - args: 不含程序名的参数列表
+    /// ## 返回值
+    /// - Result<Cli, String>
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+        let mut debug = false;
+        let mut words = Vec::new();
+        let mut params = Params::default();
+        let mut tuned = false;
+        let mut expires = None;
+        let mut rollback = false;
+        let mut help = false;
+        let mut filter = Filter::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--debug" => debug = true,
+                "--rollback" => rollback = true,
+                "--memory" | "--time" | "--parallelism" => {
+                    let value = args.next().ok_or(format!("选项 {} 缺少数值", arg))?;
+                    let value: u32 = value.parse().map_err(|_| format!("选项 {} 的值 {} 不是有效的数字", arg, value))?;
+                    match arg.as_str() {
+                        "--memory" => params.memory = value,
+                        "--time" => params.time = value,
+                        _ => params.parallelism = value,
+                    }
+                    tuned = true;
+                }
+                "--expires" => {
+                    let value = args.next().ok_or("选项 --expires 缺少天数")?;
+                    expires = Some(value.parse::<u32>().map_err(|_| format!("选项 --expires 的值 {} 不是有效的天数", value))?);
+                }
+                "--sender" => filter.sender = Some(args.next().ok_or("选项 --sender 缺少地址")?),
+                "--domain" => filter.domain = Some(args.next().ok_or("选项 --domain 缺少域名")?),
+                "-h" | "--help" => help = true,
+                _ if arg.starts_with('-') => return Err(format!("未知选项 {}", arg)),
+                _ => words.push(arg),
+            }
+        }
+
+        // 只查看帮助时不检查命令的参数，例如 zmou account add -h
+        if help {
+            let topic = match words.first().map(String::as_str) {
+                None | Some("help") => HelpTopic::General,
+                Some(command) if usage(command).is_some() => HelpTopic::Command(command.to_string()),
+                Some(other) => return Err(format!("未知命令 {}", other)),
+            };
+            return Ok(Cli { debug, command: Command::Help(topic) });
+        }
+
+        let mut words = words.into_iter();
+        let command = match words.next().as_deref() {
+            None => Command::Serve,
+            Some("config") => Command::Config(match words.next().as_deref() {
+                None => ConfigAction::View,
+                Some("remake") => ConfigAction::Remake,
+                Some("edit") => ConfigAction::Edit,
+                Some(other) => return Err(format!("未知参数 config {}", other)),
+            }),
+            Some("help") => Command::Help(match words.next().as_deref() {
+                None => HelpTopic::General,
+                Some("timezone") => HelpTopic::TimeZone(words.next()),
+                Some("api") => HelpTopic::Api,
+                Some(other) => return Err(format!("没有关于 {} 的帮助", other)),
+            }),
+            Some("password") => Command::Password(params),
+            Some("update") => Command::Update { rollback },
+            Some("db") => Command::Db(match words.next().as_deref() {
+                Some("migrate") => DbAction::Migrate,
+                Some("status") => DbAction::Status,
+                Some("rollback") => DbAction::Rollback,
+                Some(other) => return Err(format!("未知参数 db {}", other)),
+                None => return Err(String::from("缺少参数 migrate|status|rollback")),
+            }),
+            Some("api") => Command::Api(match words.next().as_deref() {
+                None => ApiAction::List,
+                Some("add") => ApiAction::Add { id: words.next().ok_or("缺少参数 <id>")?, expires },
+                Some("delete") => ApiAction::Delete(words.next().ok_or("缺少参数 <id>")?),
+                Some(other) => return Err(format!("未知参数 api {}", other)),
+            }),
+            Some("domain") => Command::Domain(match words.next().as_deref() {
+                None => DomainAction::List,
+                Some("add") => DomainAction::Add(words.next().ok_or("缺少参数 <域名>")?),
+                Some("delete") => DomainAction::Delete(words.next().ok_or("缺少参数 <域名>")?),
+                Some(other) => return Err(format!("未知参数 domain {}", other)),
+            }),
+            Some("account") => Command::Account(match words.next().as_deref() {
+                None => AccountAction::List,
+                Some("add") => AccountAction::Add(words.next().ok_or("缺少参数 <地址>")?),
+                Some("delete") => AccountAction::Delete(words.next().ok_or("缺少参数 <地址>")?),
+                Some("password") => AccountAction::Password(words.next().ok_or("缺少参数 <地址>")?),
+                Some("pop3") => AccountAction::Pop3 {
+                    address: words.next().ok_or("缺少参数 <地址>")?,
+                    delete: match words.next().as_deref() {
+                        Some("keep") => false,
+                        Some("delete") => true,
+                        Some(other) => return Err(format!("未知参数 account pop3 <地址> {}，应为 keep 或 delete", other)),
+                        None => return Err(String::from("缺少参数 <keep|delete>")),
+                    },
+                },
+                Some(other) => return Err(format!("未知参数 account {}", other)),
+            }),
+            Some("alias") => Command::Alias(match words.next().as_deref() {
+                None => AliasAction::List,
+                Some("add") => AliasAction::Add {
+                    alias: words.next().ok_or("缺少参数 <别名>")?,
+                    account: words.next().ok_or("缺少参数 <账号>")?,
+                },
+                Some("delete") => AliasAction::Delete(words.next().ok_or("缺少参数 <别名>")?),
+                Some(other) => return Err(format!("未知参数 alias {}", other)),
+            }),
+            Some("queue") => Command::Queue(match words.next().as_deref() {
+                None | Some("list") => QueueAction::List(filter.clone()),
+                Some("show") => QueueAction::Show(queue_id(words.next())?),
+                Some("retry") => QueueAction::Retry(queue_id(words.next())?),
+                Some("flush") => QueueAction::Flush,
+                Some("hold") => QueueAction::Hold(queue_id(words.next())?),
+                Some("release") => QueueAction::Release(queue_id(words.next())?),
+                Some("delete") if !filter.is_empty() => QueueAction::Delete(None, filter.clone()),
+                Some("delete") => QueueAction::Delete(Some(queue_id(words.next())?), Filter::default()),
+                Some(other) => return Err(format!("未知参数 queue {}", other)),
+            }),
+            Some(other) => return Err(format!("未知命令 {}", other)),
+        };
+
+        if let Some(extra) = words.next() {
+            return Err(format!("多余的参数 {}", extra));
+        }
+        if tuned && !matches!(command, Command::Password(_)) {
+            return Err(String::from("选项 --memory、--time、--parallelism 仅用于 zmou password"));
+        }
+        if expires.is_some() && !matches!(command, Command::Api(ApiAction::Add { .. })) {
+            return Err(String::from("选项 --expires 仅用于 zmou api add"));
+        }
+        if !filter.is_empty() && !matches!(command, Command::Queue(QueueAction::List(_) | QueueAction::Delete(..))) {
+            return Err(String::from("选项 --sender、--domain 仅用于 zmou queue 与 zmou queue delete"));
+        }
+        if rollback && !matches!(command, Command::Update { .. }) {
+            return Err(String::from("选项 --rollback 仅用于 zmou update"));
+        }
+        Ok(Cli { debug, command })
+    }
+}
+
+/// # 一个命令的帮助
+/// 从 USAGE 中取出这个命令及其子命令的说明。
+/// ## 参数
+/// - command: 命令，例如 queue
+/// ## 返回值
+/// - Option<String>，不是已知的命令时为 None
+pub fn usage(command: &str) -> Option<String> {
+    let mut lines = Vec::new();
+    let mut matched = false;
+    for line in USAGE.lines().skip_while(|line| *line != "命令:").take_while(|line| !line.is_empty()) {
+        let name = line.trim_start();
+        // 缩进较多的行是上一行的续行
+        if line.starts_with("    ") {
+            if matched {
+                lines.push(line);
+            }
+            continue;
+        }
+        matched = name == command || name.starts_with(&format!("{} ", command));
+        if matched {
+            lines.push(line);
+        }
+    }
+    (!lines.is_empty()).then(|| format!("用法: zmou [--debug] {} ...\n\n命令:\n{}", command, lines.join("\n")))
+}
+
+/// # 解析队列编号
+/// 允许带 # 前缀，例如 #12
+fn queue_id(value: Option<String>) -> Result<i64, String> {
+    let value = value.ok_or("缺少参数 <编号>")?;
+    value.trim_start_matches('#').parse().map_err(|_| format!("{} 不是有效的队列编号", value))
+}
@@ -0,0 +1,107 @@
+/* 命令 zmou config */
+use std::fs;
+use std::path::Path;
+use toml_edit::{DocumentMut, Item, Value};
+
+use super::reconcile::backup_path;
+use super::{Config, ConfigError};
+use crate::default::CONFIG;
+use crate::editor::Editor;
+use crate::secret::{MASK, is_reference};
+
+/// 需要打码显示的配置项
+pub const SECRET_KEYS: &[(&str, &str)] = &[("Database", "Password"), ("MainAccount", "Password")];
+
+/// # 查看配置文件
+/// 打印修复后的配置，密码等敏感信息打码显示。
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), ConfigError>
+pub fn view(path: &Path) -> Result<(), ConfigError> {
+    Config::load(path)?;
+    let mut document: DocumentMut = fs::read_to_string(path)?.parse().unwrap();
+    for (section, key) in SECRET_KEYS {
+        if let Some(Item::Value(value)) = document.get_mut(section).and_then(|table| table.get_mut(key)) {
+            mask(value);
+        }
+    }
+
+    for (section, table) in document.iter() {
+        let Some(table) = table.as_table() else { continue };
+        println!("[{}]", section);
+        for (key, item) in table.iter() {
+            let mut value = item.clone().into_value().unwrap_or_else(|_| Value::from(""));
+            value.decor_mut().clear();
+            println!("{} = {}", key, value);
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// # 重置配置文件
+/// 原文件备份为 ZM.toml.bak 后写入默认配置 CONFIG。
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), ConfigError>
+pub fn remake(path: &Path) -> Result<(), ConfigError> {
+    if path.exists() {
+        let backup = backup_path(path);
+        fs::copy(path, &backup)?;
+        info!("原配置文件已备份为 {}", backup.display());
+    }
+    fs::write(path, CONFIG)?;
+    info!("配置文件 {} 已重置", path.display());
+    Ok(())
+}
+
+/// # 使用自带的编辑器编辑配置文件
+/// 保存前校验配置，校验失败时不写入；写入后修复失败则回滚到原文件。
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), ConfigError>
+pub fn edit(path: &Path) -> Result<(), ConfigError> {
+    Config::load(path)?;
+    let original = fs::read_to_string(path)?;
+    let saved = Editor::new(&path.display().to_string(), &original).run(check)?;
+    let Some(text) = saved else {
+        info!("配置文件未修改");
+        return Ok(());
+    };
+
+    let backup = backup_path(path);
+    fs::write(&backup, &original)?;
+    let result = fs::write(path, &text).map_err(ConfigError::from).and_then(|_| Config::load(path));
+    if let Err(e) = result {
+        fs::write(path, &original)?;
+        warning!("保存失败，已回滚配置文件");
+        return Err(e);
+    }
+    info!("配置文件已保存，原文件已备份为 {}", backup.display());
+    Ok(())
+}
+
+/// # 校验编辑器中的配置
+/// ## 参数
+/// - text: &str
+/// ## 返回值
+/// - Result<(), String>
+fn check(text: &str) -> Result<(), String> {
+    let config = Config::parse(text).map_err(|e| e.to_string())?;
+    config.validate().map_err(|issues| {
+        let more = if issues.len() > 1 { format!("（另有 {} 处错误）", issues.len() - 1) } else { String::new() };
+        format!("{}{}", issues[0], more)
+    })
+}
+
+/// # 打码
+/// EnvMode、env:、file: 等引用与空值原样显示。
+fn mask(value: &mut Value) {
+    let secret = value.as_str().is_some_and(|text| !text.is_empty() && !is_reference(text));
+    if secret {
+        *value = Value::from(MASK);
+    }
+}
@@ -0,0 +1,329 @@
+/* 配置文件 ZM.toml */
+/*
+# 配置模块
+## 用法
+let config = Config::load(CONFIG_PATH)?;   <-- 配置文件不存在时会写入默认配置 CONFIG
+config.web_server.port
+ */
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use toml_edit::DocumentMut;
+
+use crate::default::CONFIG;
+use crate::secret::Secret;
+
+pub mod command;
+mod reconcile;
+mod secrets;
+mod validate;
+
+pub use validate::Issue;
+
+/// 配置文件中的所有节
+pub const SECTIONS: &[&str] = &["General", "Log", "Database", "WebServer", "MainAccount", "API", "SMTP", "Queue", "IMAP", "POP3"];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Config {
+    pub general: General,
+    pub log: Log,
+    pub database: Database,
+    pub web_server: WebServer,
+    pub main_account: MainAccount,
+    #[serde(rename = "API")]
+    pub api: Api,
+    #[serde(rename = "SMTP")]
+    pub smtp: Smtp,
+    pub queue: Queue,
+    #[serde(rename = "IMAP")]
+    pub imap: Imap,
+    #[serde(rename = "POP3")]
+    pub pop3: Pop3,
+}
+
+/// 常规设置 [General]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct General {
+    pub time_zone: String,
+    pub server: String,
+    pub update_key: String,
+}
+
+/// 日志设置 [Log]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Log {
+    pub debug: bool,
+    pub record: bool,
+    pub roll: u64,
+    pub color: bool,
+}
+
+/// 数据库配置 [Database]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Database {
+    pub backend: Backend,
+    pub path: String,
+    pub host: String,
+    pub port: u16,
+    pub database: String,
+    pub user: String,
+    pub password: Secret,
+    pub pool: u32,
+}
+
+/// 存储后端 [Database] Backend
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum Backend {
+    PostgreSQL,
+    SQLite,
+    Memory,
+}
+
+/// Web 服务器 [WebServer]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct WebServer {
+    pub enable: bool,
+    pub address: String,
+    pub host_constraint: bool,
+    pub host: String,
+    pub port: u16,
+    #[serde(rename = "TLS")]
+    pub tls: bool,
+    pub cert: String,
+    pub key: String,
+}
+
+/// 主账号 [MainAccount]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct MainAccount {
+    pub password: String,
+}
+
+/// API 配置 [API]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Api {
+    pub enable: bool,
+    pub address: String,
+    pub port: u16,
+    #[serde(rename = "TLS")]
+    pub tls: bool,
+    pub cert: String,
+    pub key: String,
+    pub keygen: Vec<ApiKey>,
+}
+
+/// SMTP 服务 [SMTP]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Smtp {
+    pub enable: bool,
+    pub address: String,
+    pub port: u16,
+    pub hostname: String,
+    pub max_message_size: u64,
+    pub submission: bool,
+    pub submission_port: u16,
+    pub submissions_port: u16,
+    #[serde(rename = "TLS")]
+    pub tls: bool,
+    pub cert: String,
+    pub key: String,
+}
+
+/// 发送队列 [Queue]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Queue {
+    pub workers: u32,
+    /// 分钟
+    pub retry_interval: u64,
+    /// 分钟
+    pub max_retry_interval: u64,
+    /// 小时，0 表示不发送延迟通知
+    pub delay_warning: u64,
+    /// 小时
+    pub lifetime: u64,
+    /// 留空时使用系统配置
+    pub nameserver: String,
+    /// 天
+    pub status_retention: u64,
+}
+
+/// IMAP 服务 [IMAP]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Imap {
+    pub enable: bool,
+    pub address: String,
+    pub port: u16,
+    pub secure_port: u16,
+    #[serde(rename = "TLS")]
+    pub tls: bool,
+    pub cert: String,
+    pub key: String,
+}
+
+/// POP3 服务 [POP3]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Pop3 {
+    pub enable: bool,
+    pub address: String,
+    pub port: u16,
+    pub secure_port: u16,
+    #[serde(rename = "TLS")]
+    pub tls: bool,
+    pub cert: String,
+    pub key: String,
+}
+
+/// API 密钥，[API] Keygen 中的一项
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ApiKey {
+    pub id: String,
+    /// 密钥的 SHA-256 值，明文密钥仅在生成时显示一次
+    pub hash: String,
+    /// 创建时间（RFC 3339）
+    pub created: String,
+    /// 过期时间（RFC 3339），为空时永不过期
+    #[serde(default)]
+    pub expires: Option<String>,
+    /// 最后使用时间（RFC 3339）
+    #[serde(default)]
+    pub last_used: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 读写配置文件失败
+    Io(std::io::Error),
+    /// 解析失败，section 与 key 在无法定位时为空
+    Parse {
+        section: Option<String>,
+        key: Option<String>,
+        line: usize,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "无法读写配置文件：{}", e),
+            ConfigError::Parse { section, key, line, message } => {
+                write!(f, "配置文件第 {} 行", line)?;
+                if let Some(section) = section {
+                    write!(f, " [{}]", section)?;
+                }
+                if let Some(key) = key {
+                    write!(f, " {}", key)?;
+                }
+                write!(f, "：{}", message)
+            }
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(e: std::io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+impl Config {
+    /// # 读取配置文件
+    /// 文件不存在时写入默认配置 CONFIG 后再读取；文件存在时先按模板修复多余或缺失的配置项。
+    /// ## 参数
+    /// - path: 配置文件路径
+    /// ## 返回值
+    /// - Result<Config, ConfigError>
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Config, ConfigError> {
+        let path = path.as_ref();
+        if !path.exists() {
+            fs::write(path, CONFIG)?;
+            info!("配置文件 {} 不存在，已生成默认配置", path.display());
+        }
+        reconcile::reconcile(path)?;
+        let text = fs::read_to_string(path)?;
+        Config::parse(&text)
+    }
+
+    /// # 解析配置文本
+    /// ## 参数
+    /// - text: &str
+    /// ## 返回值
+    /// - Result<Config, ConfigError>
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        toml::from_str(text).map_err(|e| locate(text, &e))
+    }
+}
+
+/// # 修改配置文件
+/// 保留注释与格式；修改前先读取一次配置，确保文件存在且格式正确。
+/// ## 参数
+/// - path: 配置文件路径
+/// - edit: 修改文档的函数
+/// ## 返回值
+/// - Result<(), ConfigError>
+pub fn update<F: FnOnce(&mut DocumentMut)>(path: &Path, edit: F) -> Result<(), ConfigError> {
+    Config::load(path)?;
+    let text = fs::read_to_string(path)?;
+    let mut document: DocumentMut = text.parse().map_err(|e: toml_edit::TomlError| ConfigError::Parse {
+        section: None,
+        key: None,
+        line: text[..e.span().map(|span| span.start).unwrap_or(0)].matches('\n').count() + 1,
+        message: e.message().to_string(),
+    })?;
+    edit(&mut document);
+
+    // 先写入临时文件再替换，避免写入中断导致配置文件损坏
+    let temporary = path.with_extension("toml.tmp");
+    fs::write(&temporary, document.to_string())?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// # 定位解析错误所在的节、键与行
+/// ## 参数
+/// - text: 配置文本
+/// - e: toml 解析错误
+/// ## 返回值
+/// - ConfigError
+fn locate(text: &str, e: &toml::de::Error) -> ConfigError {
+    let message = e.message().trim().to_string();
+    let offset = e.span().map(|span| span.start).unwrap_or(0).min(text.len());
+    let line = text[..offset].matches('\n').count() + 1;
+
+    let mut section = None;
+    for previous in text.lines().take(line) {
+        let previous = previous.trim();
+        if previous.starts_with('[') && previous.ends_with(']') {
+            section = Some(previous.trim_matches(['[', ']']).trim().to_string());
+        }
+    }
+
+    // 缺失的键不在原文中，从错误信息里取出键名
+    let missing = message
+        .strip_prefix("missing field `")
+        .and_then(|rest| rest.split('`').next())
+        .map(str::to_string);
+    if let Some(name) = missing.as_deref().filter(|name| SECTIONS.contains(name)) {
+        return ConfigError::Parse { section: Some(name.to_string()), key: None, line, message };
+    }
+    let key = missing.or_else(|| {
+        let current = text.lines().nth(line - 1)?.trim();
+        let (key, _) = current.split_once('=')?;
+        Some(key.trim().to_string())
+    });
+
+    ConfigError::Parse { section, key, line, message }
+}
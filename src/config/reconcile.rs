@@ -0,0 +1,113 @@
+/* 配置文件自修复 */
+/*
+以默认配置 CONFIG 为模板重建配置文件：
+- 删除模板中不存在的节与配置项
+- 补全缺失的配置项（带默认值与注释）
+- 保留用户填写的值
+有改动时先将原文件备份为 ZM.toml.bak，再写入新文件。
+ */
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table};
+
+use super::ConfigError;
+use crate::default::CONFIG;
+
+/// # 修复配置文件
+/// 配置文件不存在或存在语法错误时不做处理，由 Config::parse 报告具体错误。
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), ConfigError>
+pub fn reconcile(path: &Path) -> Result<(), ConfigError> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let text = fs::read_to_string(path)?;
+    let user = match text.parse::<DocumentMut>() {
+        Ok(user) => user,
+        Err(e) => {
+            debug!("配置文件存在语法错误，跳过自修复：{}", e.message());
+            return Ok(());
+        }
+    };
+
+    let mut merged: DocumentMut = CONFIG.parse().unwrap();
+    let mut changes = Vec::new();
+    merge(merged.as_table_mut(), user.as_table(), None, &mut changes);
+    if changes.is_empty() {
+        return Ok(());
+    }
+
+    let backup = backup_path(path);
+    fs::copy(path, &backup)?;
+    fs::write(path, merged.to_string())?;
+    for change in &changes {
+        warning!("{}", change);
+    }
+    warning!("配置文件已修正 {} 处，原文件已备份为 {}", changes.len(), backup.display());
+    Ok(())
+}
+
+/// # 备份文件路径
+/// ## 参数
+/// - path: &Path
+/// ## 返回值
+/// - PathBuf，例如 ZM.toml.bak
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// # 将用户配置合并进模板
+/// ## 参数
+/// - template: 模板表，合并结果直接写入
+/// - user: 用户配置表
+/// - section: 所在的节，顶层为 None
+/// - changes: 记录所有改动
+fn merge(template: &mut Table, user: &Table, section: Option<&str>, changes: &mut Vec<String>) {
+    for (key, item) in user.iter() {
+        if template.contains_key(key) {
+            continue;
+        }
+        match (section, item) {
+            (None, Item::Table(_)) => changes.push(format!("删除未知的节 [{}]", key)),
+            _ => changes.push(format!("删除未知的配置项 {}", describe(section, key))),
+        }
+    }
+
+    for (key, item) in template.iter_mut() {
+        let name = describe(section, key.get());
+        match (item, user.get(key.get())) {
+            (Item::Table(table), Some(Item::Table(user_table))) => {
+                merge(table, user_table, Some(key.get()), changes);
+            }
+            (Item::Table(_), _) => {
+                changes.push(format!("补全缺失的节 [{}]", key.get()));
+            }
+            (Item::Value(value), Some(user_item)) => match user_item.clone().into_value() {
+                Ok(mut user_value) => {
+                    *user_value.decor_mut() = value.decor().clone();
+                    *value = user_value;
+                }
+                Err(_) => changes.push(format!("配置项 {} 格式错误，已重置为默认值", name)),
+            },
+            (Item::Value(value), None) => {
+                let mut default = value.clone();
+                default.decor_mut().clear();
+                changes.push(format!("补全缺失的配置项 {} = {}", name, default));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// # 配置项的显示名称
+fn describe(section: Option<&str>, key: &str) -> String {
+    match section {
+        Some(section) => format!("[{}] {}", section, key),
+        None => key.to_string(),
+    }
+}
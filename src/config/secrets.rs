@@ -0,0 +1,85 @@
+/* 解析敏感配置项 */
+use super::{Backend, Config, Issue};
+use crate::api::key::digest;
+use crate::secret::{Kind, Secret, env_mode, is_reference, resolve};
+
+/// 建议的敏感值最短长度（字符数）
+const SHORT_SECRET: usize = 4;
+
+impl Config {
+    /// # 解析敏感配置项
+    /// 将 EnvMode、env:、file: 替换为实际的值，一次性返回全部问题。
+    /// 需要在启动服务前调用；查看、编辑配置等命令不需要解析。
+    /// ## 返回值
+    /// - Result<(), Vec<Issue>>
+    pub fn resolve_secrets(&mut self) -> Result<(), Vec<Issue>> {
+        let mut issues = Vec::new();
+        let mut resolve_into = |section: &'static str, key: &'static str, target: &mut String, kind: Kind| {
+            match resolve(target, env_mode(section, key), kind) {
+                Ok(value) => {
+                    // 日志中所有相同的内容都会被替换，过短的密码或密钥会让日志难以阅读，也容易被猜到
+                    if kind == Kind::Value && value.chars().count() < SHORT_SECRET {
+                        warning!("[{}] {} 的值少于 {} 个字符，日志中所有相同的内容都会显示为 ******", section, key, SHORT_SECRET);
+                    }
+                    *target = value;
+                }
+                Err(e) => issues.push(Issue { section, key, message: e.to_string() }),
+            }
+        };
+
+        if self.database.backend == Backend::PostgreSQL {
+            let mut password = self.database.password.expose().to_string();
+            resolve_into("Database", "Password", &mut password, Kind::Value);
+            self.database.password = Secret::from(password);
+        }
+        if self.web_server.tls {
+            resolve_into("WebServer", "Key", &mut self.web_server.key, Kind::Path);
+        }
+        if self.api.tls {
+            resolve_into("API", "Key", &mut self.api.key, Kind::Path);
+        }
+        if self.smtp.tls {
+            resolve_into("SMTP", "Key", &mut self.smtp.key, Kind::Path);
+        }
+        if self.imap.tls {
+            resolve_into("IMAP", "Key", &mut self.imap.key, Kind::Path);
+        }
+        if self.pop3.tls {
+            resolve_into("POP3", "Key", &mut self.pop3.key, Kind::Path);
+        }
+
+        // 密钥可以通过 env: 或 file: 提供明文密钥或其 SHA-256 值
+        for entry in &mut self.api.keygen {
+            if !is_reference(&entry.hash) {
+                continue;
+            }
+            resolve_into("API", "Keygen", &mut entry.hash, Kind::Value);
+            if entry.hash.starts_with("zm_") {
+                entry.hash = digest(&entry.hash);
+            }
+        }
+
+        if issues.is_empty() { Ok(()) } else { Err(issues) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default::CONFIG;
+
+    #[test]
+    fn resolves_tls_keys() {
+        let mut config = Config::parse(CONFIG).unwrap();
+        config.database.backend = Backend::Memory;
+        config.imap.tls = true;
+        config.imap.key = String::from("file:/run/secrets/imap.key");
+        config.pop3.tls = true;
+        config.pop3.key = String::from("file:/run/secrets/pop3.key");
+        config.resolve_secrets().unwrap();
+        assert_eq!(config.imap.key, "/run/secrets/imap.key");
+        assert_eq!(config.pop3.key, "/run/secrets/pop3.key");
+        assert_eq!(env_mode("IMAP", "Key"), "IMAPKey");
+        assert_eq!(env_mode("POP3", "Key"), "POP3Key");
+    }
+}
@@ -0,0 +1,211 @@
+/* 配置校验 */
+use std::fmt;
+
+use super::{Backend, Config};
+use crate::default::TIMEZONES;
+use crate::password::is_valid_hash;
+use crate::timezone::suggest;
+use crate::update::is_valid_key;
+use crate::api::key::{is_valid_digest, is_valid_id};
+use crate::secret::is_reference;
+use crate::smtp::resolver::parse_nameserver;
+use crate::utils::{is_valid_hostname, is_valid_ip, is_valid_url, parse_rfc3339};
+
+/// 配置中的一处问题
+#[derive(Debug)]
+pub struct Issue {
+    pub section: &'static str,
+    pub key: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {}：{}", self.section, self.key, self.message)
+    }
+}
+
+impl Config {
+    /// # 校验配置的取值
+    /// 检查所有配置项，一次性返回全部问题。
+    /// ## 返回值
+    /// - Result<(), Vec<Issue>>
+    pub fn validate(&self) -> Result<(), Vec<Issue>> {
+        let mut issues = Vec::new();
+        let mut issue = |section: &'static str, key: &'static str, message: String| {
+            issues.push(Issue { section, key, message });
+        };
+
+        // [General]
+        if !TIMEZONES.contains(&self.general.time_zone.as_str()) {
+            let message = match suggest(&self.general.time_zone) {
+                Some(name) => format!("未知时区 {}，你是否想填写 {}？", self.general.time_zone, name),
+                None => format!("未知时区 {}，完整时区列表见 zmou help timezone", self.general.time_zone),
+            };
+            issue("General", "TimeZone", message);
+        }
+        let server = &self.general.server;
+        if !(server.starts_with("http://") || server.starts_with("https://")) || !is_valid_url(server) {
+            issue("General", "Server", format!("{} 不是以 http:// 或者 https:// 开头的网址", server));
+        }
+        if !server.ends_with("%file%") {
+            issue("General", "Server", format!("{} 没有以 %file% 结尾", server));
+        }
+        if !self.general.update_key.is_empty() && !is_valid_key(&self.general.update_key) {
+            issue("General", "UpdateKey", String::from("不是有效的 Ed25519 公钥"));
+        }
+
+        // [Database]
+        if self.database.backend == Backend::SQLite && self.database.path.trim().is_empty() {
+            issue("Database", "Path", String::from("使用 SQLite 时必须填写数据库文件路径"));
+        }
+        if self.database.pool == 0 {
+            issue("Database", "Pool", String::from("连接池大小至少为 1"));
+        }
+
+        // [WebServer]
+        let web = &self.web_server;
+        if !is_valid_ip(&web.address) {
+            issue("WebServer", "Address", format!("{} 不是有效的 IP 地址", web.address));
+        }
+        if web.host_constraint && web.host.trim().is_empty() {
+            issue("WebServer", "Host", String::from("启用 Host 约束时不能为空"));
+        }
+        if web.tls && web.cert.trim().is_empty() {
+            issue("WebServer", "Cert", String::from("启用 TLS 时不能为空"));
+        }
+        if web.tls && web.key.trim().is_empty() {
+            issue("WebServer", "Key", String::from("启用 TLS 时不能为空"));
+        }
+
+        // [MainAccount]
+        let password = &self.main_account.password;
+        if !password.is_empty() && !is_valid_hash(password) {
+            issue("MainAccount", "Password", String::from("不是有效的 Argon2 值，请使用 zmou password 重新设置"));
+        }
+
+        // [API]
+        let api = &self.api;
+        if !is_valid_ip(&api.address) {
+            issue("API", "Address", format!("{} 不是有效的 IP 地址", api.address));
+        }
+        if api.tls && api.cert.trim().is_empty() {
+            issue("API", "Cert", String::from("启用 TLS 时不能为空"));
+        }
+        if api.tls && api.key.trim().is_empty() {
+            issue("API", "Key", String::from("启用 TLS 时不能为空"));
+        }
+        for (index, entry) in api.keygen.iter().enumerate() {
+            if !is_valid_id(&entry.id) {
+                issue("API", "Keygen", format!("密钥 Id {} 不合法，仅允许字母、数字、- 与 _", entry.id));
+            }
+            if api.keygen[..index].iter().any(|other| other.id == entry.id) {
+                issue("API", "Keygen", format!("密钥 Id {} 重复", entry.id));
+            }
+            if !is_reference(&entry.hash) && !is_valid_digest(&entry.hash) {
+                issue("API", "Keygen", format!("密钥 {} 的 Hash 不是有效的 SHA-256 值", entry.id));
+            }
+            let times = [("Created", Some(&entry.created)), ("Expires", entry.expires.as_ref()), ("LastUsed", entry.last_used.as_ref())];
+            for (name, time) in times {
+                if let Some(time) = time
+                    && parse_rfc3339(time).is_none()
+                {
+                    issue("API", "Keygen", format!("密钥 {} 的 {} 不是有效的 RFC 3339 时间", entry.id, name));
+                }
+            }
+        }
+        if web.enable && api.enable && web.port == api.port {
+            issue("API", "Port", format!("与 [WebServer] Port 使用了相同的端口 {}", api.port));
+        }
+
+        // [SMTP]
+        let smtp = &self.smtp;
+        if !is_valid_ip(&smtp.address) {
+            issue("SMTP", "Address", format!("{} 不是有效的 IP 地址", smtp.address));
+        }
+        if !is_valid_hostname(&smtp.hostname) {
+            issue("SMTP", "Hostname", format!("{} 不是有效的主机名", smtp.hostname));
+        }
+        if !(1..=1024).contains(&smtp.max_message_size) {
+            issue("SMTP", "MaxMessageSize", String::from("应在 1 到 1024 MiB 之间"));
+        }
+        if smtp.tls && smtp.cert.trim().is_empty() {
+            issue("SMTP", "Cert", String::from("启用 TLS 时不能为空"));
+        }
+        if smtp.tls && smtp.key.trim().is_empty() {
+            issue("SMTP", "Key", String::from("启用 TLS 时不能为空"));
+        }
+
+        // [IMAP]
+        let imap = &self.imap;
+        if !is_valid_ip(&imap.address) {
+            issue("IMAP", "Address", format!("{} 不是有效的 IP 地址", imap.address));
+        }
+        if imap.tls && imap.cert.trim().is_empty() {
+            issue("IMAP", "Cert", String::from("启用 TLS 时不能为空"));
+        }
+        if imap.tls && imap.key.trim().is_empty() {
+            issue("IMAP", "Key", String::from("启用 TLS 时不能为空"));
+        }
+
+        // [POP3]
+        let pop3 = &self.pop3;
+        if !is_valid_ip(&pop3.address) {
+            issue("POP3", "Address", format!("{} 不是有效的 IP 地址", pop3.address));
+        }
+        if pop3.tls && pop3.cert.trim().is_empty() {
+            issue("POP3", "Cert", String::from("启用 TLS 时不能为空"));
+        }
+        if pop3.tls && pop3.key.trim().is_empty() {
+            issue("POP3", "Key", String::from("启用 TLS 时不能为空"));
+        }
+
+        let mut ports = vec![("WebServer", "Port", web.enable, web.port), ("API", "Port", api.enable, api.port)];
+        if smtp.enable {
+            ports.push(("SMTP", "Port", true, smtp.port));
+            ports.push(("SMTP", "SubmissionPort", smtp.submission, smtp.submission_port));
+            ports.push(("SMTP", "SubmissionsPort", smtp.submission && smtp.tls, smtp.submissions_port));
+        }
+        if imap.enable {
+            ports.push(("IMAP", "Port", true, imap.port));
+            ports.push(("IMAP", "SecurePort", imap.tls, imap.secure_port));
+        }
+        if pop3.enable {
+            ports.push(("POP3", "Port", true, pop3.port));
+            ports.push(("POP3", "SecurePort", pop3.tls, pop3.secure_port));
+        }
+        // [WebServer] 与 [API] 的冲突已在上面检查
+        let ports: Vec<_> = ports.into_iter().filter(|(_, _, enable, _)| *enable).collect();
+        for (index, &(section, key, _, port)) in ports.iter().enumerate().filter(|(_, (section, ..))| *section != "WebServer" && *section != "API") {
+            if let Some((other_section, other_key, _, _)) = ports[..index].iter().find(|(_, _, _, other)| *other == port) {
+                issue(section, key, format!("与 [{}] {} 使用了相同的端口 {}", other_section, other_key, port));
+            }
+        }
+
+        // [Queue]
+        let queue = &self.queue;
+        if !(1..=64).contains(&queue.workers) {
+            issue("Queue", "Workers", String::from("应在 1 到 64 之间"));
+        }
+        if queue.retry_interval == 0 {
+            issue("Queue", "RetryInterval", String::from("至少为 1 分钟"));
+        }
+        if queue.max_retry_interval < queue.retry_interval {
+            issue("Queue", "MaxRetryInterval", String::from("不能小于 RetryInterval"));
+        }
+        if queue.lifetime == 0 {
+            issue("Queue", "Lifetime", String::from("至少为 1 小时"));
+        }
+        if queue.delay_warning >= queue.lifetime && queue.delay_warning != 0 {
+            issue("Queue", "DelayWarning", String::from("应小于 Lifetime，否则不会发送延迟通知"));
+        }
+        if !queue.nameserver.is_empty() && parse_nameserver(&queue.nameserver).is_none() {
+            issue("Queue", "Nameserver", format!("{} 不是有效的地址，例如 127.0.0.1 或 127.0.0.1:5353", queue.nameserver));
+        }
+        if queue.status_retention == 0 {
+            issue("Queue", "StatusRetention", String::from("至少为 1 天"));
+        }
+
+        if issues.is_empty() { Ok(()) } else { Err(issues) }
+    }
+}
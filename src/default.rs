@@ -1,6 +1,9 @@
 /* 默认配置文件 ZM.toml */
 use crate::utils::hex_to_ansi;
 
+/// 配置文件路径
+pub const CONFIG_PATH: &str = "ZM.toml";
+
 pub const CONFIG: &str = r#"# ZitMail 配置文件
 # 请不要在此配置文件添加额外内容，ZitMail 启动时会自动删除额外内容。
 # 命令 zmou config <参数>
@@ -12,6 +15,9 @@ pub const CONFIG: &str = r#"# ZitMail 配置文件
 TimeZone = "Asia/Shanghai"
 # 更新服务器，以 http:// 或者 https:// 开头，以 %file% 结尾
 Server = "https://github.com/KirsminX/ZitMail/releases/latest/%file%"
+# 更新公钥，用于校验更新文件的 Ed25519 签名，填写 64 位 16 进制字符。
+# 命令 zmou update 更新到最新版本；zmou update --rollback 回滚到更新前的版本。
+UpdateKey = ""
 
 # 日志设置
 [Log]
@@ -25,10 +31,17 @@ Roll = 1000
 Color = true
 
 # 数据库配置
-# 目前仅支持 PostgreSQL。你需要先手动创建一个数据库和用户便于 ZitMail 访问。
+# 支持 PostgreSQL、SQLite 与 Memory。Memory 仅用于测试，重启后数据丢失。
+# 使用 PostgreSQL 时，你需要先手动创建一个数据库和用户便于 ZitMail 访问。
 [Database]
-# 数据库地址
+# 存储后端
+Backend = "PostgreSQL"
+# SQLite 数据库文件路径，仅 SQLite 使用
+Path = "zitmail.db"
+# 数据库地址，以下配置仅 PostgreSQL 使用
 Host = "localhost"
+# 端口
+Port = 5432
 # 数据库名称
 Database = "zitmail"
 # 用户
@@ -37,9 +50,13 @@ User = "manser"
 # 例如， Password=admin zmou
 # 或者直接填写在下方，但不推荐这样做。
 Password = "EnvMode"
+# 连接池大小
+Pool = 8
 
 # Web 服务器
 # ZitMail 提供了一个简易的 Web 服务器，你也可以通过 API 自行实现 Web 服务器。
+# Web 服务器同时提供 JMAP（RFC 8620、RFC 8621），客户端使用 /.well-known/jmap 或 /jmap/session 发现服务，
+# 以邮件地址与 IMAP 相同的密码登录。
 # 如果有分流等需求，请使用反向代理，将 Address 配置为 '127.0.0.1' 并且关闭 TLS 功能和 Host 约束。
 [WebServer]
 # 启用 Web 服务器
@@ -84,6 +101,90 @@ Key = ""
 # add <id> 生成一个密钥；delete <id> 删除一个密钥；（无参数）列出所有密钥。
 Keygen = []
 
+# SMTP 服务
+# 接收其他邮件服务器发往本机域名的邮件，并为本机账号提供邮件提交服务。
+# 域名、账号与别名使用命令 zmou domain、zmou account、zmou alias 管理。
+[SMTP]
+# 启用 SMTP 服务
+Enable = true
+# 监听地址，若仅本机访问，请填写 '127.0.0.1'。
+Address = "0.0.0.0"
+# 端口，标准端口为 25。监听 1024 以下的端口可能需要管理员权限。
+Port = 25
+# 本机主机名，用于问候语与 Received 邮件头，应与 MX 记录指向的主机名一致。
+Hostname = "mail.example.com"
+# 邮件大小上限（MiB），通过 SIZE 扩展告知发件方，超出时拒绝接收。
+MaxMessageSize = 25
+# 邮件提交
+# 邮件客户端登录后通过提交端口发信，账号只能使用自己的地址或别名作为发件人。
+Submission = true
+# 提交端口，使用 STARTTLS，标准端口为 587。
+SubmissionPort = 587
+# 隐式 TLS 提交端口，标准端口为 465，仅在启用 TLS 时监听。
+SubmissionsPort = 465
+# TLS 加密
+# 启用后，端口 25 与提交端口支持 STARTTLS，提交端口要求加密后才能登录。
+# 可以与 [WebServer] 使用相同的证书，值填写绝对路径。
+TLS = false
+Cert = ""
+Key = ""
+
+# 发送队列
+# 提交的邮件保存在数据库中，按收件人域名分组投递，重启后继续。
+# 投递失败时按指数退避重试，超过最长保留时间后退信给发件人。
+[Queue]
+# 投递线程数
+Workers = 4
+# 首次重试间隔（分钟），之后每次翻倍。
+RetryInterval = 5
+# 最长重试间隔（分钟）
+MaxRetryInterval = 240
+# 投递延迟超过此时长（小时）后通知发件人，0 表示不通知。
+DelayWarning = 4
+# 最长保留时间（小时），默认 5 天。
+Lifetime = 120
+# DNS 服务器，例如 127.0.0.1 或 127.0.0.1:5353，留空时使用系统配置（/etc/resolv.conf）。
+Nameserver = ""
+# 投递状态保留天数，用于 API 查询每个收件人的投递结果与收到的投递状态通知（DSN）。
+StatusRetention = 30
+
+# IMAP 服务
+# 邮件客户端通过 IMAP（RFC 9051，兼容 IMAP4rev1）读取与管理本机账号的邮件，使用账号的完整地址与密码登录。
+[IMAP]
+# 启用 IMAP 服务
+Enable = true
+# 监听地址，若仅本机访问，请填写 '127.0.0.1'。
+Address = "0.0.0.0"
+# 端口，使用 STARTTLS，标准端口为 143。
+Port = 143
+# 隐式 TLS 端口，标准端口为 993，仅在启用 TLS 时监听。
+SecurePort = 993
+# TLS 加密
+# 启用后，端口 143 要求 STARTTLS 后才能登录。
+# 可以与 [SMTP] 使用相同的证书，值填写绝对路径。
+TLS = false
+Cert = ""
+Key = ""
+
+# POP3 服务
+# 只支持 POP3 的客户端通过 POP3（RFC 1939）取回 INBOX 中的邮件，账号与密码与 IMAP 相同。
+# 取回后是否从服务器删除按账号设置，默认保留，见 zmou account pop3。
+[POP3]
+# 启用 POP3 服务
+Enable = false
+# 监听地址，若仅本机访问，请填写 '127.0.0.1'。
+Address = "0.0.0.0"
+# 端口，使用 STLS，标准端口为 110。
+Port = 110
+# 隐式 TLS 端口，标准端口为 995，仅在启用 TLS 时监听。
+SecurePort = 995
+# TLS 加密
+# 启用后，端口 110 要求 STLS 后才能登录。
+# 可以与 [IMAP] 使用相同的证书，值填写绝对路径。
+TLS = false
+Cert = ""
+Key = ""
+
 # 未尽事宜，详见 ZitMail 文档。
 # 文档版本 0.0.1
 "#;
@@ -0,0 +1,266 @@
+/* 自带的终端编辑器 */
+/*
+# 编辑器
+## 用法
+let saved = Editor::new("ZM.toml", &text).run(check)?;   <-- check 用于保存前校验内容
+## 快捷键
+方向键、Home、End、PageUp、PageDown 移动光标
+Ctrl+S 校验并保存退出；Ctrl+Q 退出，有未保存的修改时需要连按两次
+ */
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+use std::io::{self, Write, stdout};
+use unicode_width::UnicodeWidthChar;
+
+/// 编辑器底部的帮助信息
+const HELP: &str = "Ctrl+S 校验并保存  Ctrl+Q 退出";
+
+pub struct Editor {
+    title: String,
+    lines: Vec<Vec<char>>,
+    row: usize,
+    col: usize,
+    top: usize,
+    left: usize,
+    modified: bool,
+    confirm_quit: bool,
+    message: String,
+}
+
+impl Editor {
+    /// # 创建编辑器
+    /// ## 参数
+    /// - title: 状态栏显示的标题
+    /// - text: 初始内容
+    pub fn new(title: &str, text: &str) -> Self {
+        let mut lines: Vec<Vec<char>> = text.lines().map(|line| line.chars().collect()).collect();
+        if lines.is_empty() {
+            lines.push(Vec::new());
+        }
+        Editor {
+            title: title.to_string(),
+            lines,
+            row: 0,
+            col: 0,
+            top: 0,
+            left: 0,
+            modified: false,
+            confirm_quit: false,
+            message: String::from(HELP),
+        }
+    }
+
+    /// # 运行编辑器
+    /// ## 参数
+    /// - check: 保存前的校验函数，返回的错误信息会显示在底部
+    /// ## 返回值
+    /// - Ok(Some(text)) 校验通过并保存的内容；Ok(None) 未保存
+    pub fn run<F: Fn(&str) -> Result<(), String>>(mut self, check: F) -> io::Result<Option<String>> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen)?;
+        let result = self.event_loop(&check);
+        // 无论编辑是否成功都要恢复终端
+        let _ = execute!(stdout(), Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        result
+    }
+
+    fn event_loop<F: Fn(&str) -> Result<(), String>>(&mut self, check: &F) -> io::Result<Option<String>> {
+        loop {
+            self.render()?;
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let control = key.modifiers.contains(KeyModifiers::CONTROL);
+            match key.code {
+                KeyCode::Char('s') if control => {
+                    if !self.modified {
+                        return Ok(None);
+                    }
+                    let text = self.text();
+                    match check(&text) {
+                        Ok(()) => return Ok(Some(text)),
+                        Err(e) => self.message = format!("无法保存：{}", e),
+                    }
+                }
+                KeyCode::Char('q') if control => {
+                    if !self.modified || self.confirm_quit {
+                        return Ok(None);
+                    }
+                    self.confirm_quit = true;
+                    self.message = String::from("有未保存的修改，再按一次 Ctrl+Q 放弃修改并退出");
+                    continue;
+                }
+                _ => self.handle(key),
+            }
+            self.confirm_quit = false;
+        }
+    }
+
+    /// # 处理编辑与移动按键
+    fn handle(&mut self, key: KeyEvent) {
+        let page = terminal::size().map(|(_, height)| height.saturating_sub(2) as usize).unwrap_or(20).max(1);
+        match key.code {
+            KeyCode::Up => self.row = self.row.saturating_sub(1),
+            KeyCode::Down => self.row = (self.row + 1).min(self.lines.len() - 1),
+            KeyCode::PageUp => self.row = self.row.saturating_sub(page),
+            KeyCode::PageDown => self.row = (self.row + page).min(self.lines.len() - 1),
+            KeyCode::Home => self.col = 0,
+            KeyCode::End => self.col = self.lines[self.row].len(),
+            KeyCode::Left => {
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                }
+            }
+            KeyCode::Right => {
+                if self.col < self.lines[self.row].len() {
+                    self.col += 1;
+                } else if self.row + 1 < self.lines.len() {
+                    self.row += 1;
+                    self.col = 0;
+                }
+            }
+            KeyCode::Enter => {
+                let rest = self.lines[self.row].split_off(self.col);
+                self.lines.insert(self.row + 1, rest);
+                self.row += 1;
+                self.col = 0;
+                self.modified = true;
+            }
+            KeyCode::Backspace => {
+                if self.col > 0 {
+                    self.col -= 1;
+                    self.lines[self.row].remove(self.col);
+                    self.modified = true;
+                } else if self.row > 0 {
+                    let line = self.lines.remove(self.row);
+                    self.row -= 1;
+                    self.col = self.lines[self.row].len();
+                    self.lines[self.row].extend(line);
+                    self.modified = true;
+                }
+            }
+            KeyCode::Delete => {
+                if self.col < self.lines[self.row].len() {
+                    self.lines[self.row].remove(self.col);
+                    self.modified = true;
+                } else if self.row + 1 < self.lines.len() {
+                    let line = self.lines.remove(self.row + 1);
+                    self.lines[self.row].extend(line);
+                    self.modified = true;
+                }
+            }
+            KeyCode::Tab => {
+                for _ in 0..4 {
+                    self.lines[self.row].insert(self.col, ' ');
+                    self.col += 1;
+                }
+                self.modified = true;
+            }
+            KeyCode::Char(c) if !key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.lines[self.row].insert(self.col, c);
+                self.col += 1;
+                self.modified = true;
+            }
+            _ => return,
+        }
+        self.col = self.col.min(self.lines[self.row].len());
+        self.message = String::from(HELP);
+    }
+
+    /// # 绘制界面
+    fn render(&mut self) -> io::Result<()> {
+        let (width, height) = terminal::size()?;
+        let width = width.max(1) as usize;
+        let rows = height.saturating_sub(2).max(1) as usize;
+
+        // 保持光标在可见区域内
+        if self.row < self.top {
+            self.top = self.row;
+        } else if self.row >= self.top + rows {
+            self.top = self.row + 1 - rows;
+        }
+        let x = display_width(&self.lines[self.row][..self.col]);
+        if x < self.left {
+            self.left = x;
+        } else if x >= self.left + width {
+            self.left = x + 1 - width;
+        }
+
+        let mut out = stdout();
+        queue!(out, Hide)?;
+        for i in 0..rows {
+            queue!(out, MoveTo(0, i as u16), Clear(ClearType::CurrentLine))?;
+            match self.lines.get(self.top + i) {
+                Some(line) => queue!(out, Print(visible(line, self.left, width)))?,
+                None => queue!(out, Print("~"))?,
+            }
+        }
+
+        let flag = if self.modified { " [已修改]" } else { "" };
+        let status = format!(" {}{}  第 {}/{} 行，第 {} 列", self.title, flag, self.row + 1, self.lines.len(), self.col + 1);
+        queue!(
+            out,
+            MoveTo(0, rows as u16),
+            Clear(ClearType::CurrentLine),
+            SetAttribute(Attribute::Reverse),
+            Print(pad(&status, width)),
+            SetAttribute(Attribute::Reset),
+            MoveTo(0, rows as u16 + 1),
+            Clear(ClearType::CurrentLine),
+            Print(visible(&self.message.chars().collect::<Vec<_>>(), 0, width)),
+            MoveTo((x - self.left) as u16, (self.row - self.top) as u16),
+            Show
+        )?;
+        out.flush()
+    }
+
+    /// # 编辑器中的全部内容
+    fn text(&self) -> String {
+        let mut text = String::new();
+        for line in &self.lines {
+            text.extend(line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// # 字符的显示宽度
+/// 中文等宽字符占两列
+fn display_width(chars: &[char]) -> usize {
+    chars.iter().map(|c| c.width().unwrap_or(0)).sum()
+}
+
+/// # 截取一行中从 left 列开始、宽度不超过 width 的部分
+fn visible(line: &[char], left: usize, width: usize) -> String {
+    let mut result = String::new();
+    let mut x = 0;
+    for c in line {
+        let w = c.width().unwrap_or(0);
+        if x >= left && x + w <= left + width {
+            result.push(*c);
+        }
+        x += w;
+        if x >= left + width {
+            break;
+        }
+    }
+    result
+}
+
+/// # 用空格将文本补齐到 width 列
+fn pad(text: &str, width: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut result = visible(&chars, 0, width);
+    let used = display_width(&result.chars().collect::<Vec<_>>());
+    result.extend(std::iter::repeat_n(' ', width.saturating_sub(used)));
+    result
+}
@@ -0,0 +1,94 @@
+/* 邮箱变化事件 */
+/*
+# 事件
+## 用法
+let subscription = event::subscribe(account.id);                    <-- 登录后订阅账号的变化
+event::publish(Event { account_id, mailbox_id, mailbox, kind: Kind::MessageNew, origin: subscription.id() });
+for event in subscription.events() { ... }                           <-- 不阻塞，取出所有未处理的事件
+## 说明
+投递与 IMAP 等修改邮箱的地方发布事件，IMAP IDLE 与 NOTIFY 据此推送变化，客户端无需轮询。
+事件只说明哪个邮箱发生了什么变化，具体内容由订阅者从存储读取。
+每个订阅最多缓存 MAX_PENDING 个事件，超出时丢弃，订阅者读取存储时仍能得到最新状态。
+Subscription 销毁后自动取消订阅。
+ */
+use crossbeam_channel::{Receiver, Sender, bounded};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// 每个订阅缓存的事件数上限
+const MAX_PENDING: usize = 1024;
+
+/// 订阅编号，从 1 开始，0 表示事件不来自任何订阅者（例如投递）
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// (订阅编号, 账号 id, 发送端)
+static SUBSCRIBERS: Mutex<Vec<(u64, i64, Sender<Event>)>> = Mutex::new(Vec::new());
+
+/// 变化的类型，与 NOTIFY 的事件对应（RFC 5465 5）
+#[derive(Debug, Clone, PartialEq)]
+pub enum Kind {
+    MessageNew,
+    MessageExpunge,
+    FlagChange,
+    /// 邮箱被创建、删除或改名，改名时附带原来的名称
+    MailboxName(Option<String>),
+    SubscriptionChange,
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub account_id: i64,
+    pub mailbox_id: i64,
+    /// 邮箱名称，改名后为新名称
+    pub mailbox: String,
+    pub kind: Kind,
+    /// 发布者的订阅编号，订阅者据此忽略自己造成的变化
+    pub origin: u64,
+}
+
+/// 对一个账号的订阅
+pub struct Subscription {
+    id: u64,
+    receiver: Receiver<Event>,
+}
+
+impl Subscription {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// # 取出所有未处理的事件
+    pub fn events(&self) -> Vec<Event> {
+        self.receiver.try_iter().collect()
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        subscribers().retain(|(id, _, _)| *id != self.id);
+    }
+}
+
+/// # 订阅账号的变化
+/// ## 参数
+/// - account_id: 账号 id
+/// ## 返回值
+/// - Subscription
+pub fn subscribe(account_id: i64) -> Subscription {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let (sender, receiver) = bounded(MAX_PENDING);
+    subscribers().push((id, account_id, sender));
+    Subscription { id, receiver }
+}
+
+/// # 发布事件
+/// 发送给该账号的所有订阅，包括发布者自己。
+pub fn publish(event: Event) {
+    for (_, _, sender) in subscribers().iter().filter(|(_, account_id, _)| *account_id == event.account_id) {
+        let _ = sender.try_send(event.clone());
+    }
+}
+
+fn subscribers() -> std::sync::MutexGuard<'static, Vec<(u64, i64, Sender<Event>)>> {
+    SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,572 @@
+/* IMAP FETCH */
+/*
+# FETCH 数据项
+## 用法
+let items = fetch::parse(&mut parser)?;                       <-- 例如 (FLAGS BODY.PEEK[HEADER.FIELDS (From)]<0.100>)
+let line = fetch::render(sequence, &info, raw.as_deref(), &items);   <-- * 序号 FETCH (...)
+## 说明
+ENVELOPE 与 BODYSTRUCTURE 中的字符串保持邮件中的原样，不解码 encoded-word（RFC 9051 7.5.2）。
+BINARY 按 Content-Transfer-Encoding 解码 base64 与 quoted-printable，其他编码原样返回。
+读取正文且不带 .PEEK 时设置 \Seen 由会话负责。
+ */
+use std::borrow::Cow;
+
+use crate::mime::{self, Part};
+use super::parser::{Bad, Parser, Result};
+use super::{nstring, string};
+use crate::storage::MessageInfo;
+
+/// section 中部分编号之后的内容（section-text），例如 HEADER.FIELDS (From To)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Specifier {
+    /// 整个部分，例如 BODY[] 或 BODY[1]
+    All,
+    Header,
+    HeaderFields(Vec<String>),
+    HeaderFieldsNot(Vec<String>),
+    Text,
+    /// 该部分自己的 MIME 邮件头
+    Mime,
+}
+
+/// BODY[section] 中的 section
+#[derive(Debug, Clone, PartialEq)]
+pub struct Section {
+    /// 部分编号，例如 1.2 为 [1, 2]
+    pub path: Vec<u32>,
+    pub text: Specifier,
+    /// 客户端发送的原文，用于响应
+    pub spec: String,
+}
+
+/// FETCH 的数据项
+#[derive(Debug, Clone, PartialEq)]
+pub enum Item {
+    Uid,
+    Flags,
+    InternalDate,
+    Size,
+    Envelope,
+    /// 不含扩展数据的 BODYSTRUCTURE
+    Body,
+    BodyStructure,
+    /// BODY[section]<partial>、BODY.PEEK、BINARY 与 BINARY.PEEK
+    Section { section: Section, partial: Option<(u32, u32)>, peek: bool, binary: bool },
+    BinarySize(Vec<u32>),
+    Rfc822,
+    Rfc822Header,
+    Rfc822Text,
+    /// 修改序列（RFC 7162 3.1.4.1）
+    ModSeq,
+}
+
+impl Item {
+    /// # 是否需要邮件原文
+    pub fn needs_raw(&self) -> bool {
+        !matches!(self, Item::Uid | Item::Flags | Item::InternalDate | Item::Size | Item::ModSeq)
+    }
+
+    /// # 是否会设置 \Seen 标记
+    pub fn sets_seen(&self) -> bool {
+        matches!(self, Item::Section { peek: false, .. } | Item::Rfc822 | Item::Rfc822Text)
+    }
+}
+
+/// # 解析数据项
+/// 可以是单个数据项、括号中的列表，或者宏 ALL、FAST、FULL。
+/// ## 参数
+/// - parser: 位于数据项开头
+/// ## 返回值
+/// - Result<Vec<Item>>
+pub fn parse(parser: &mut Parser) -> Result<Vec<Item>> {
+    if parser.peek() == Some(b'(') {
+        return parser.list(|parser| item(&parser.item()?));
+    }
+    let name = parser.item()?;
+    Ok(match name.to_ascii_uppercase().as_str() {
+        "ALL" => vec![Item::Flags, Item::InternalDate, Item::Size, Item::Envelope],
+        "FAST" => vec![Item::Flags, Item::InternalDate, Item::Size],
+        "FULL" => vec![Item::Flags, Item::InternalDate, Item::Size, Item::Envelope, Item::Body],
+        _ => vec![item(&name)?],
+    })
+}
+
+fn item(text: &str) -> Result<Item> {
+    let upper = text.to_ascii_uppercase();
+    let simple = match upper.as_str() {
+        "UID" => Some(Item::Uid),
+        "FLAGS" => Some(Item::Flags),
+        "INTERNALDATE" => Some(Item::InternalDate),
+        "RFC822.SIZE" => Some(Item::Size),
+        "ENVELOPE" => Some(Item::Envelope),
+        "BODY" => Some(Item::Body),
+        "BODYSTRUCTURE" => Some(Item::BodyStructure),
+        "RFC822" => Some(Item::Rfc822),
+        "RFC822.HEADER" => Some(Item::Rfc822Header),
+        "RFC822.TEXT" => Some(Item::Rfc822Text),
+        "MODSEQ" => Some(Item::ModSeq),
+        _ => None,
+    };
+    if let Some(item) = simple {
+        return Ok(item);
+    }
+
+    let invalid = Bad("Invalid data item");
+    let (name, rest) = text.split_once('[').ok_or(invalid)?;
+    let (spec, partial) = rest.rsplit_once(']').ok_or(invalid)?;
+    let partial = match partial {
+        "" => None,
+        partial => {
+            let range = partial.strip_prefix('<').and_then(|partial| partial.strip_suffix('>')).ok_or(invalid)?;
+            let (origin, octets) = range.split_once('.').ok_or(invalid)?;
+            let octets: u32 = octets.parse().map_err(|_| invalid)?;
+            if octets == 0 {
+                return Err(invalid);
+            }
+            Some((origin.parse().map_err(|_| invalid)?, octets))
+        }
+    };
+    let (peek, binary) = match name.to_ascii_uppercase().as_str() {
+        "BODY" => (false, false),
+        "BODY.PEEK" => (true, false),
+        "BINARY" => (false, true),
+        "BINARY.PEEK" => (true, true),
+        "BINARY.SIZE" if partial.is_none() => return Ok(Item::BinarySize(path(spec)?)),
+        _ => return Err(invalid),
+    };
+    let section = section(spec)?;
+    if binary && section.text != Specifier::All {
+        return Err(invalid);
+    }
+    Ok(Item::Section { section, partial, peek, binary })
+}
+
+/// # 解析 section，例如 1.2.HEADER.FIELDS (From To)
+fn section(spec: &str) -> Result<Section> {
+    let invalid = Bad("Invalid section");
+    let mut path = Vec::new();
+    let mut rest = spec;
+    while rest.starts_with(|c: char| c.is_ascii_digit()) {
+        let end = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        match rest[..end].parse() {
+            Ok(0) | Err(_) => return Err(invalid),
+            Ok(number) => path.push(number),
+        }
+        rest = &rest[end..];
+        match rest.strip_prefix('.') {
+            Some(next) => rest = next,
+            None if rest.is_empty() => break,
+            None => return Err(invalid),
+        }
+    }
+    let upper = rest.to_ascii_uppercase();
+    let fields = |prefix: &str| -> Result<Vec<String>> {
+        let list = rest[prefix.len()..].trim_start();
+        let mut parser = Parser::new(list.as_bytes());
+        let names = parser.list(|parser| parser.astring())?;
+        parser.end()?;
+        if names.is_empty() {
+            return Err(invalid);
+        }
+        Ok(names.iter().map(|name| String::from_utf8_lossy(name).into_owned()).collect())
+    };
+    let text = match upper.as_str() {
+        "" => Specifier::All,
+        "HEADER" => Specifier::Header,
+        "TEXT" => Specifier::Text,
+        "MIME" if !path.is_empty() => Specifier::Mime,
+        _ if upper.starts_with("HEADER.FIELDS.NOT ") => Specifier::HeaderFieldsNot(fields("HEADER.FIELDS.NOT")?),
+        _ if upper.starts_with("HEADER.FIELDS ") => Specifier::HeaderFields(fields("HEADER.FIELDS")?),
+        _ => return Err(invalid),
+    };
+    if !path.is_empty() && rest.is_empty() && spec.ends_with('.') {
+        return Err(invalid);
+    }
+    Ok(Section { path, text, spec: spec.to_ascii_uppercase() })
+}
+
+/// BINARY.SIZE 的 section 只有部分编号
+fn path(spec: &str) -> Result<Vec<u32>> {
+    let section = section(spec)?;
+    match section.text {
+        Specifier::All => Ok(section.path),
+        _ => Err(Bad("Invalid section")),
+    }
+}
+
+/// # 生成一封邮件的 FETCH 响应
+/// ## 参数
+/// - sequence: 邮件序号
+/// - info: 邮件
+/// - raw: 邮件原文，数据项都不需要原文时可以为 None
+/// - items: 数据项
+/// ## 返回值
+/// - Vec<u8>，* 序号 FETCH (...)，不含结尾的 CRLF
+pub fn render(sequence: u32, info: &MessageInfo, raw: Option<&[u8]>, items: &[Item]) -> Vec<u8> {
+    let message = raw.map(mime::parse);
+    let mut line = format!("* {} FETCH (", sequence).into_bytes();
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            line.push(b' ');
+        }
+        match (item, &message) {
+            (Item::Uid, _) => line.extend_from_slice(format!("UID {}", info.uid).as_bytes()),
+            (Item::Flags, _) => line.extend_from_slice(format!("FLAGS ({})", info.flags.join(" ")).as_bytes()),
+            (Item::InternalDate, _) => {
+                let date = info.internal_date.format("%d-%b-%Y %H:%M:%S %z");
+                line.extend_from_slice(format!("INTERNALDATE \"{}\"", date).as_bytes());
+            }
+            (Item::Size, _) => line.extend_from_slice(format!("RFC822.SIZE {}", info.size).as_bytes()),
+            (Item::ModSeq, _) => line.extend_from_slice(format!("MODSEQ ({})", info.modseq).as_bytes()),
+            (_, None) => line.extend_from_slice(b"NIL"),
+            (Item::Envelope, Some(message)) => {
+                line.extend_from_slice(b"ENVELOPE ");
+                line.extend(envelope(message));
+            }
+            (Item::Body, Some(message)) => {
+                line.extend_from_slice(b"BODY ");
+                line.extend(structure(message, false));
+            }
+            (Item::BodyStructure, Some(message)) => {
+                line.extend_from_slice(b"BODYSTRUCTURE ");
+                line.extend(structure(message, true));
+            }
+            (Item::Rfc822, Some(_)) => {
+                line.extend_from_slice(b"RFC822 ");
+                line.extend(string(raw.unwrap_or_default()));
+            }
+            (Item::Rfc822Header, Some(message)) => {
+                line.extend_from_slice(b"RFC822.HEADER ");
+                line.extend(string(message.header));
+            }
+            (Item::Rfc822Text, Some(message)) => {
+                line.extend_from_slice(b"RFC822.TEXT ");
+                line.extend(string(message.body));
+            }
+            (Item::BinarySize(path), Some(message)) => {
+                let size = message.find(path).map_or(0, |part| part.decode().len());
+                let spec: Vec<String> = path.iter().map(u32::to_string).collect();
+                line.extend_from_slice(format!("BINARY.SIZE[{}] {}", spec.join("."), size).as_bytes());
+            }
+            (Item::Section { section, partial, binary, .. }, Some(message)) => {
+                let content = match binary {
+                    true => message.find(&section.path).map(|part| part.decode().into_owned()),
+                    false => extract(raw.unwrap_or_default(), message, section),
+                };
+                let name = if *binary { "BINARY" } else { "BODY" };
+                line.extend_from_slice(format!("{}[{}]", name, section.spec).as_bytes());
+                let content = match (content, partial) {
+                    (Some(content), Some((origin, octets))) => {
+                        line.extend_from_slice(format!("<{}>", origin).as_bytes());
+                        let start = (*origin as usize).min(content.len());
+                        let end = start.saturating_add(*octets as usize).min(content.len());
+                        Some(content[start..end].to_vec())
+                    }
+                    (content, _) => content,
+                };
+                line.push(b' ');
+                match content {
+                    // BINARY 的内容可能包含 NUL，使用 literal8（RFC 3516 4.2）
+                    Some(content) if *binary && content.contains(&0) => {
+                        line.extend_from_slice(format!("~{{{}}}\r\n", content.len()).as_bytes());
+                        line.extend(content);
+                    }
+                    // 不存在的部分返回空字符串
+                    content => line.extend(string(&content.unwrap_or_default())),
+                }
+            }
+        }
+    }
+    line.push(b')');
+    line
+}
+
+/// # 按 section 取出内容
+/// 部分不存在时为 None。
+fn extract(raw: &[u8], message: &Part, section: &Section) -> Option<Vec<u8>> {
+    let part = message.find(&section.path)?;
+    // 带部分编号时，HEADER、TEXT 等指向 message/rfc822 所附的邮件
+    let target = match (&section.text, section.path.is_empty()) {
+        (Specifier::All | Specifier::Mime, _) | (_, true) => part,
+        (_, false) => part.message.as_deref()?,
+    };
+    Some(match &section.text {
+        Specifier::All if section.path.is_empty() => raw.to_vec(),
+        Specifier::All => part.body.to_vec(),
+        Specifier::Mime => part.header.to_vec(),
+        Specifier::Header => target.header.to_vec(),
+        Specifier::Text => target.body.to_vec(),
+        Specifier::HeaderFields(names) => header_fields(target.header, names, true),
+        Specifier::HeaderFieldsNot(names) => header_fields(target.header, names, false),
+    })
+}
+
+/// # 按字段名筛选邮件头
+/// 保留折叠的行，结尾加上空行（RFC 9051 6.4.5）。
+fn header_fields(header: &[u8], names: &[String], include: bool) -> Vec<u8> {
+    let mut selected = Vec::new();
+    let mut keep = false;
+    for line in header.split_inclusive(|&byte| byte == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            let name = line.split(|&byte| byte == b':').next().unwrap_or_default().trim_ascii();
+            keep = names.iter().any(|wanted| wanted.as_bytes().eq_ignore_ascii_case(name)) == include;
+        }
+        if keep {
+            selected.extend_from_slice(line);
+            if !line.ends_with(b"\n") {
+                selected.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+    selected.extend_from_slice(b"\r\n");
+    selected
+}
+
+/// # ENVELOPE
+/// (date subject from sender reply-to to cc bcc in-reply-to message-id)
+fn envelope(message: &Part) -> Vec<u8> {
+    let field = |name: &str| message.field(name);
+    let text = |name: &str| nstring(field(name).as_deref().map(str::as_bytes));
+    let from = field("From");
+    let mut envelope = b"(".to_vec();
+    envelope.extend(text("Date"));
+    envelope.push(b' ');
+    envelope.extend(text("Subject"));
+    for name in ["From", "Sender", "Reply-To", "To", "Cc", "Bcc"] {
+        envelope.push(b' ');
+        // Sender 与 Reply-To 缺省时与 From 相同
+        let value = match name {
+            "Sender" | "Reply-To" => field(name).filter(|value| !value.is_empty()).or_else(|| from.clone()),
+            _ => field(name),
+        };
+        envelope.extend(addresses(value.as_deref().unwrap_or_default()));
+    }
+    envelope.push(b' ');
+    envelope.extend(text("In-Reply-To"));
+    envelope.push(b' ');
+    envelope.extend(text("Message-ID"));
+    envelope.push(b')');
+    envelope
+}
+
+/// # 地址列表
+/// 每个地址为 (name adl mailbox host)，组以 (NIL NIL 组名 NIL) 开始、(NIL NIL NIL NIL) 结束。
+fn addresses(value: &str) -> Vec<u8> {
+    let mut items: Vec<Vec<u8>> = Vec::new();
+    let mut current = String::new();
+    let (mut quoted, mut escaped, mut comment, mut angle, mut group) = (false, false, 0, false, false);
+    let flush = |current: &mut String, items: &mut Vec<Vec<u8>>| {
+        if let Some(address) = address(current) {
+            items.push(address);
+        }
+        current.clear();
+    };
+    for c in value.chars() {
+        if escaped {
+            escaped = false;
+            if comment == 0 {
+                current.push(c);
+            }
+            continue;
+        }
+        match c {
+            '\\' if quoted || comment > 0 => escaped = true,
+            '"' if comment == 0 => quoted = !quoted,
+            '(' if !quoted => comment += 1,
+            ')' if !quoted && comment > 0 => comment -= 1,
+            _ if quoted => {}
+            _ if comment > 0 => continue,
+            '<' => angle = true,
+            '>' => angle = false,
+            ':' if !angle && !group => {
+                let name = unquote(current.trim());
+                items.push([b"(NIL NIL ".as_slice(), &string(name.as_bytes()), b" NIL)"].concat());
+                current.clear();
+                group = true;
+                continue;
+            }
+            ';' if !angle && group => {
+                flush(&mut current, &mut items);
+                items.push(b"(NIL NIL NIL NIL)".to_vec());
+                group = false;
+                continue;
+            }
+            ',' if !angle => {
+                flush(&mut current, &mut items);
+                continue;
+            }
+            _ => {}
+        }
+        if comment == 0 && c != ')' {
+            current.push(c);
+        }
+    }
+    flush(&mut current, &mut items);
+    if group {
+        items.push(b"(NIL NIL NIL NIL)".to_vec());
+    }
+    if items.is_empty() {
+        return b"NIL".to_vec();
+    }
+    [b"(".as_slice(), &items.concat(), b")"].concat()
+}
+
+/// # 一个地址
+/// 例如 "Manser" <manser@example.com> 或 manser@example.com，空白时为 None。
+fn address(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    let (name, spec) = match (text.find('<'), text.rfind('>')) {
+        (Some(start), Some(end)) if start < end => (Some(unquote(text[..start].trim())), &text[start + 1..end]),
+        _ => (None, text),
+    };
+    // 去掉源路由 @a,@b:
+    let spec = spec.rsplit_once(':').map_or(spec, |(_, spec)| spec).trim();
+    let (mailbox, host) = spec.rsplit_once('@').unwrap_or((spec, ""));
+    let name = name.filter(|name| !name.is_empty());
+    Some(
+        [
+            b"(".as_slice(),
+            &nstring(name.as_deref().map(str::as_bytes)),
+            b" NIL ",
+            &string(unquote(mailbox).as_bytes()),
+            b" ",
+            &string(host.as_bytes()),
+            b")",
+        ]
+        .concat(),
+    )
+}
+
+/// # 去掉引号与转义
+fn unquote(text: &str) -> String {
+    match text.strip_prefix('"').and_then(|text| text.strip_suffix('"')) {
+        Some(inner) => {
+            let mut unquoted = String::new();
+            let mut escaped = false;
+            for c in inner.chars() {
+                if c == '\\' && !escaped {
+                    escaped = true;
+                    continue;
+                }
+                escaped = false;
+                unquoted.push(c);
+            }
+            unquoted
+        }
+        None => text.to_string(),
+    }
+}
+
+/// # BODYSTRUCTURE 与 BODY
+/// ## 参数
+/// - part: 邮件或其中的一部分
+/// - extended: 是否包含扩展数据（BODYSTRUCTURE）
+/// ## 返回值
+/// - Vec<u8>
+fn structure(part: &Part, extended: bool) -> Vec<u8> {
+    let mut structure = b"(".to_vec();
+    if part.is_multipart() {
+        for child in &part.children {
+            structure.extend(self::structure(child, extended));
+        }
+        structure.push(b' ');
+        structure.extend(string(part.subtype.to_ascii_uppercase().as_bytes()));
+        if extended {
+            structure.push(b' ');
+            structure.extend(parameter_list(&part.parameters));
+            structure.push(b' ');
+            structure.extend(extension(part));
+        }
+        structure.push(b')');
+        return structure;
+    }
+
+    let text = |name: &str| nstring(part.field(name).as_deref().map(str::as_bytes));
+    let mut parameters = part.parameters.clone();
+    if part.media_type == "text" && part.field("Content-Type").is_none() {
+        parameters.push((Cow::Borrowed("charset"), Cow::Borrowed("us-ascii")));
+    }
+    let encoding = part.field("Content-Transfer-Encoding").map_or(String::from("7BIT"), |encoding| encoding.to_ascii_uppercase());
+    structure.extend(string(part.media_type.to_ascii_uppercase().as_bytes()));
+    structure.push(b' ');
+    structure.extend(string(part.subtype.to_ascii_uppercase().as_bytes()));
+    structure.push(b' ');
+    structure.extend(parameter_list(&parameters));
+    structure.push(b' ');
+    structure.extend(text("Content-ID"));
+    structure.push(b' ');
+    structure.extend(text("Content-Description"));
+    structure.push(b' ');
+    structure.extend(string(encoding.as_bytes()));
+    structure.extend(format!(" {}", part.body.len()).as_bytes());
+    let lines = part.body.iter().filter(|&&byte| byte == b'\n').count();
+    if let Some(message) = &part.message {
+        structure.push(b' ');
+        structure.extend(envelope(message));
+        structure.push(b' ');
+        structure.extend(self::structure(message, extended));
+        structure.extend(format!(" {}", lines).as_bytes());
+    } else if part.media_type == "text" {
+        structure.extend(format!(" {}", lines).as_bytes());
+    }
+    if extended {
+        structure.push(b' ');
+        structure.extend(text("Content-MD5"));
+        structure.push(b' ');
+        structure.extend(extension(part));
+    }
+    structure.push(b')');
+    structure
+}
+
+/// # 扩展数据：disposition、language 与 location
+fn extension(part: &Part) -> Vec<u8> {
+    let mut extension = match part.field("Content-Disposition") {
+        Some(value) => {
+            let (disposition, parameters) = mime::parameters(&value);
+            [b"(".as_slice(), &string(disposition.to_ascii_uppercase().as_bytes()), b" ", &parameter_list(&parameters), b")"].concat()
+        }
+        None => b"NIL".to_vec(),
+    };
+    extension.push(b' ');
+    let languages: Vec<String> = part
+        .field("Content-Language")
+        .map(|value| value.split(',').map(|language| language.trim().to_string()).filter(|language| !language.is_empty()).collect())
+        .unwrap_or_default();
+    match languages.as_slice() {
+        [] => extension.extend_from_slice(b"NIL"),
+        [language] => extension.extend(string(language.as_bytes())),
+        languages => {
+            let list: Vec<Vec<u8>> = languages.iter().map(|language| string(language.as_bytes())).collect();
+            extension.extend([b"(".as_slice(), &list.join(&b' '), b")"].concat());
+        }
+    }
+    extension.push(b' ');
+    extension.extend(nstring(part.field("Content-Location").as_deref().map(str::as_bytes)));
+    extension
+}
+
+/// # 参数列表
+/// 例如 ("CHARSET" "utf-8")，没有参数时为 NIL。
+fn parameter_list(parameters: &[(Cow<'_, str>, Cow<'_, str>)]) -> Vec<u8> {
+    if parameters.is_empty() {
+        return b"NIL".to_vec();
+    }
+    let mut list = b"(".to_vec();
+    for (index, (name, value)) in parameters.iter().enumerate() {
+        if index > 0 {
+            list.push(b' ');
+        }
+        list.extend(string(name.to_ascii_uppercase().as_bytes()));
+        list.push(b' ');
+        list.extend(string(value.as_bytes()));
+    }
+    list.push(b')');
+    list
+}
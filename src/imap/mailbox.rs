@@ -0,0 +1,185 @@
+/* IMAP 邮箱名称 */
+/*
+# 邮箱名称
+## 用法
+let name = mailbox::normalize(&name);            <-- INBOX 不区分大小写，去掉结尾的分隔符
+let name = mailbox::decode_utf7(&wire)?;          <-- IMAP4rev1 客户端发送的修改版 UTF-7
+mailbox::matches("INBOX/%", "INBOX/Work")          <-- LIST 的通配符
+## 说明
+存储中的邮箱名称为 UTF-8，层级以 / 分隔，例如 INBOX/工作。
+特殊用途（RFC 6154）按常见名称识别，包括中文名称。
+ */
+
+/// 层级分隔符
+pub const DELIMITER: char = '/';
+
+/// 修改版 UTF-7 使用的 Base64 字母表，以 , 代替 /（RFC 3501 5.1.3）
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,";
+
+/// # 规范化邮箱名称
+/// INBOX 及其下级的 INBOX 部分统一为大写，去掉结尾的分隔符。
+/// ## 参数
+/// - name: 客户端提供的名称（UTF-8）
+/// ## 返回值
+/// - String
+pub fn normalize(name: &str) -> String {
+    let name = name.trim_end_matches(DELIMITER);
+    match name.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("INBOX") && (name.len() == 5 || name[5..].starts_with(DELIMITER)) => {
+            format!("INBOX{}", &name[5..])
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// # 是否为有效的邮箱名称
+/// 不能为空，不能包含通配符与控制字符，每一级都不能为空。
+pub fn is_valid(name: &str) -> bool {
+    !name.is_empty()
+        && !name.chars().any(|c| c.is_control() || c == '*' || c == '%')
+        && name.split(DELIMITER).all(|level| !level.is_empty())
+}
+
+/// # 上级邮箱
+/// 例如 a/b/c 为 [a, a/b]
+pub fn parents(name: &str) -> Vec<&str> {
+    name.match_indices(DELIMITER).map(|(index, _)| &name[..index]).collect()
+}
+
+/// # 是否为 parent 的下级
+pub fn is_child(name: &str, parent: &str) -> bool {
+    name.len() > parent.len() + 1 && name.starts_with(parent) && name[parent.len()..].starts_with(DELIMITER)
+}
+
+/// # 按 LIST 的模式匹配
+/// * 匹配任意字符，% 不匹配层级分隔符（RFC 9051 6.3.9）。
+/// ## 参数
+/// - pattern: 已与引用名称拼接的模式
+/// - name: 邮箱名称
+/// ## 返回值
+/// - bool
+pub fn matches(pattern: &str, name: &str) -> bool {
+    let pattern = normalize_pattern(pattern);
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    wildcard(&pattern, &name)
+}
+
+fn normalize_pattern(pattern: &str) -> String {
+    match pattern.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("INBOX") => format!("INBOX{}", &pattern[5..]),
+        _ => pattern.to_string(),
+    }
+}
+
+fn wildcard(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..=name.len()).any(|skip| wildcard(&pattern[1..], &name[skip..])),
+        Some('%') => {
+            let level = name.iter().position(|&c| c == DELIMITER).unwrap_or(name.len());
+            (0..=level).any(|skip| wildcard(&pattern[1..], &name[skip..]))
+        }
+        Some(&c) => name.first() == Some(&c) && wildcard(&pattern[1..], &name[1..]),
+    }
+}
+
+/// # 特殊用途属性（RFC 6154）
+/// 按最后一级的名称识别，不区分大小写。
+/// ## 参数
+/// - name: 邮箱名称
+/// ## 返回值
+/// - Option<&'static str>，例如 \Sent
+pub fn special_use(name: &str) -> Option<&'static str> {
+    let level = name.rsplit(DELIMITER).next().unwrap_or(name).to_lowercase();
+    Some(match level.as_str() {
+        "sent" | "sent messages" | "sent items" | "sent mail" | "已发送" | "已发送邮件" => "\\Sent",
+        "drafts" | "draft" | "草稿" | "草稿箱" => "\\Drafts",
+        "trash" | "deleted messages" | "deleted items" | "已删除" | "已删除邮件" | "垃圾箱" => "\\Trash",
+        "junk" | "spam" | "junk e-mail" | "垃圾邮件" => "\\Junk",
+        "archive" | "archives" | "归档" | "存档" => "\\Archive",
+        _ => return None,
+    })
+}
+
+/// # 编码为修改版 UTF-7
+/// ## 参数
+/// - name: UTF-8 名称
+/// ## 返回值
+/// - String，只包含可打印的 ASCII 字符
+pub fn encode_utf7(name: &str) -> String {
+    let mut encoded = String::new();
+    let mut units: Vec<u16> = Vec::new();
+    let flush = |units: &mut Vec<u16>, encoded: &mut String| {
+        if units.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = units.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+        encoded.push('&');
+        for chunk in bytes.chunks(3) {
+            let value = chunk.iter().enumerate().fold(0u32, |value, (index, &byte)| value | (byte as u32) << (16 - index * 8));
+            for index in 0..chunk.len() + 1 {
+                encoded.push(ALPHABET[(value >> (18 - index * 6)) as usize & 0x3f] as char);
+            }
+        }
+        encoded.push('-');
+        units.clear();
+    };
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut units, &mut encoded);
+            match c {
+                '&' => encoded.push_str("&-"),
+                c => encoded.push(c),
+            }
+        } else {
+            units.extend_from_slice(c.encode_utf16(&mut [0; 2]));
+        }
+    }
+    flush(&mut units, &mut encoded);
+    encoded
+}
+
+/// # 解码修改版 UTF-7
+/// ## 参数
+/// - name: 客户端发送的名称
+/// ## 返回值
+/// - Option<String>，编码无效时为 None
+pub fn decode_utf7(name: &[u8]) -> Option<String> {
+    let mut decoded = String::new();
+    let mut rest = name;
+    while let Some((&byte, tail)) = rest.split_first() {
+        rest = tail;
+        if byte != b'&' {
+            if !(0x20..0x7f).contains(&byte) {
+                return None;
+            }
+            decoded.push(byte as char);
+            continue;
+        }
+        let end = rest.iter().position(|&byte| byte == b'-')?;
+        let (encoded, tail) = (&rest[..end], &rest[end + 1..]);
+        rest = tail;
+        if encoded.is_empty() {
+            decoded.push('&');
+            continue;
+        }
+        let mut bytes = Vec::new();
+        let (mut value, mut bits) = (0u32, 0);
+        for &byte in encoded {
+            value = value << 6 | ALPHABET.iter().position(|&c| c == byte)? as u32;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                bytes.push((value >> bits) as u8);
+                value &= (1 << bits) - 1;
+            }
+        }
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let units: Vec<u16> = bytes.chunks(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect();
+        decoded.push_str(&String::from_utf16(&units).ok()?);
+    }
+    Some(decoded)
+}
@@ -0,0 +1,84 @@
+/* IMAP 服务 */
+/*
+# IMAP 模块
+## 结构
+session.rs  协议状态机（RFC 9051，兼容 RFC 3501），不涉及网络读写，逐条处理命令并返回响应
+server.rs   会话循环：按 listener.rs 接受的连接读写命令，处理字面量与超时
+parser.rs   命令参数的解析：atom、字符串、列表、序号集合与日期
+mailbox.rs  邮箱名称：层级、通配符匹配、特殊用途与修改版 UTF-7（RFC 3501 5.1.3）
+fetch.rs    FETCH 的数据项：ENVELOPE、BODYSTRUCTURE、BODY[section]<partial> 等
+search.rs   SEARCH 的条件
+notify.rs   NOTIFY 的过滤条件与事件（RFC 5465）
+## 用法
+for listener in imap::server::listeners(&config.imap) {
+    let handle = imap::server::start(&config.imap, listener, tls.clone(), store.clone())?;   <-- 在后台线程中监听
+}
+## 扩展
+IMAP4rev1、IMAP4rev2、LITERAL+、SASL-IR、ENABLE、NAMESPACE、UIDPLUS、MOVE、UNSELECT、CHILDREN、
+LIST-EXTENDED、LIST-STATUS、SPECIAL-USE、ESEARCH、STATUS=SIZE、IDLE、CONDSTORE、QRESYNC、NOTIFY，
+端口 143 另有 STARTTLS。
+## 说明
+客户端默认按 IMAP4rev1 处理：邮箱名称使用修改版 UTF-7，SEARCH 返回 * SEARCH。
+客户端发送 ENABLE IMAP4rev2 后按 IMAP4rev2 处理：邮箱名称使用 UTF-8，SEARCH 返回 * ESEARCH。
+邮箱层级分隔符为 /，INBOX 不区分大小写。
+IDLE 与 NOTIFY 通过 event.rs 的事件得知其他连接与投递造成的变化，等待期间定时检查并推送。
+每个连接分配一个递增的编号，日志以「IMAP #编号」开头。
+ */
+pub mod fetch;
+pub mod mailbox;
+pub mod notify;
+pub mod parser;
+pub mod search;
+pub mod server;
+pub mod session;
+
+/// 命令行长度上限（含 CRLF，不含字面量），RFC 7162 4 建议至少 8192
+pub const MAX_LINE: usize = 8192;
+
+/// 单条命令中字面量的总长度上限，APPEND 的邮件受此限制
+pub const MAX_LITERAL: usize = 64 * 1024 * 1024;
+
+/// SASL 响应的长度上限（含 CRLF）
+pub const MAX_AUTH_LINE: usize = 12288;
+
+/// # 字符串
+/// 内容只包含可打印的 ASCII 字符时使用带引号的形式，否则使用字面量（RFC 9051 4.3）。
+/// ## 参数
+/// - bytes: 内容
+/// ## 返回值
+/// - Vec<u8>
+pub fn string(bytes: &[u8]) -> Vec<u8> {
+    if bytes.len() <= 1024 && bytes.iter().all(|&byte| (0x20..0x7f).contains(&byte)) {
+        return quoted(bytes);
+    }
+    literal(bytes)
+}
+
+/// # 带引号的字符串
+/// 转义 " 与 \，不检查其他内容。IMAP4rev2 允许其中包含 UTF-8（RFC 9051 4.3）。
+pub fn quoted(bytes: &[u8]) -> Vec<u8> {
+    let mut quoted = Vec::with_capacity(bytes.len() + 2);
+    quoted.push(b'"');
+    for &byte in bytes {
+        if byte == b'"' || byte == b'\\' {
+            quoted.push(b'\\');
+        }
+        quoted.push(byte);
+    }
+    quoted.push(b'"');
+    quoted
+}
+
+/// # 可以为 NIL 的字符串
+pub fn nstring(bytes: Option<&[u8]>) -> Vec<u8> {
+    match bytes {
+        Some(bytes) => string(bytes),
+        None => b"NIL".to_vec(),
+    }
+}
+
+/// # 字面量
+/// {长度}CRLF 之后紧跟原始内容。
+pub fn literal(bytes: &[u8]) -> Vec<u8> {
+    [format!("{{{}}}\r\n", bytes.len()).as_bytes(), bytes].concat()
+}
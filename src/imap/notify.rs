@@ -0,0 +1,179 @@
+/* IMAP NOTIFY */
+/*
+# NOTIFY 的事件设置（RFC 5465）
+## 用法
+let notify = notify::parse(&mut parser, &decode)?;     <-- NOTIFY SET 之后的参数，decode 将邮箱名称解码为 UTF-8
+if let Some(name) = notify.unsupported() { ... }        <-- 不支持的事件以 NO [BADEVENT] 响应
+notify.selected()                                       <-- 已选择邮箱的设置
+notify.matches("Sent", subscribed, &Kind::MessageNew)   <-- 其他邮箱是否需要推送
+## 说明
+支持 MessageNew、MessageExpunge、FlagChange、MailboxName、SubscriptionChange，
+不支持注释与元数据相关的事件。一个邮箱符合多个过滤条件时使用第一个。
+ */
+use super::fetch::{self, Item};
+use super::mailbox;
+use super::parser::{Bad, Parser, Result};
+use crate::event::Kind;
+
+/// 支持的事件
+pub const EVENTS: &[&str] = &["MessageNew", "MessageExpunge", "FlagChange", "MailboxName", "SubscriptionChange"];
+
+/// 邮箱过滤条件（RFC 5465 6）
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    Selected,
+    /// 与 Selected 相同，但 EXPUNGE 等到允许发送时再发送
+    SelectedDelayed,
+    Inboxes,
+    Personal,
+    Subscribed,
+    /// 邮箱及其所有下级
+    Subtree(Vec<String>),
+    Mailboxes(Vec<String>),
+}
+
+/// 一组过滤条件与事件
+#[derive(Debug, Clone)]
+pub struct Group {
+    pub filter: Filter,
+    /// 事件名称，已统一大小写；未知的事件保留原样
+    pub events: Vec<String>,
+    /// MessageNew 附带的 FETCH 数据项，只用于已选择的邮箱
+    pub fetch: Vec<Item>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Notify {
+    /// 立即发送符合条件的邮箱的 STATUS
+    pub status: bool,
+    pub groups: Vec<Group>,
+}
+
+/// # 解析 NOTIFY SET 的参数
+/// ## 参数
+/// - parser: 位于 SET 之后的空格
+/// - decode: 邮箱名称的解码，无效时返回 None
+/// ## 返回值
+/// - Result<Notify>
+pub fn parse(parser: &mut Parser, decode: &dyn Fn(&[u8]) -> Option<String>) -> Result<Notify> {
+    parser.space()?;
+    let status = parser.keyword("STATUS");
+    if status {
+        parser.space()?;
+    }
+    let mut groups = vec![group(parser, decode)?];
+    while parser.eat(b' ') {
+        groups.push(group(parser, decode)?);
+    }
+    parser.end()?;
+    for group in &groups {
+        let has = |name: &str| group.events.iter().any(|event| event == name);
+        // MessageNew 与 MessageExpunge 必须同时出现，FlagChange 依赖二者（RFC 5465 5）
+        if has("MessageNew") != has("MessageExpunge") || (has("FlagChange") && !has("MessageNew")) {
+            return Err(Bad("MessageNew and MessageExpunge must be specified together"));
+        }
+        if !group.fetch.is_empty() && !matches!(group.filter, Filter::Selected | Filter::SelectedDelayed) {
+            return Err(Bad("Fetch attributes are only allowed for the selected mailbox"));
+        }
+    }
+    Ok(Notify { status, groups })
+}
+
+/// (过滤条件 事件)
+fn group(parser: &mut Parser, decode: &dyn Fn(&[u8]) -> Option<String>) -> Result<Group> {
+    parser.expect(b'(')?;
+    let name = parser.atom()?.to_ascii_uppercase();
+    let filter = match name.as_str() {
+        "SELECTED" => Filter::Selected,
+        "SELECTED-DELAYED" => Filter::SelectedDelayed,
+        "INBOXES" => Filter::Inboxes,
+        "PERSONAL" => Filter::Personal,
+        "SUBSCRIBED" => Filter::Subscribed,
+        "SUBTREE" | "MAILBOXES" => {
+            parser.space()?;
+            let names = match parser.peek() {
+                Some(b'(') => parser.list(|parser| parser.astring())?,
+                _ => vec![parser.astring()?],
+            };
+            let names = names
+                .iter()
+                .map(|name| decode(name).map(|name| mailbox::normalize(&name)).ok_or(Bad("Invalid mailbox name")))
+                .collect::<Result<Vec<_>>>()?;
+            if name == "SUBTREE" { Filter::Subtree(names) } else { Filter::Mailboxes(names) }
+        }
+        _ => return Err(Bad("Unknown mailbox filter")),
+    };
+    parser.space()?;
+    let mut fetch = Vec::new();
+    let mut events: Vec<String> = Vec::new();
+    if !parser.keyword("NONE") {
+        parser.expect(b'(')?;
+        loop {
+            // MessageNew 之后可以跟括号中的 FETCH 数据项
+            if parser.peek() == Some(b'(') && events.last().is_some_and(|event| event == "MessageNew") {
+                fetch = fetch::parse(parser)?;
+                if fetch.iter().any(|item| matches!(item, Item::Section { .. } | Item::Rfc822 | Item::Rfc822Text)) {
+                    return Err(Bad("Message contents are not allowed in MessageNew"));
+                }
+            } else {
+                let name = parser.atom()?;
+                events.push(EVENTS.iter().find(|event| event.eq_ignore_ascii_case(&name)).map_or(name, |event| event.to_string()));
+            }
+            if parser.eat(b')') {
+                break;
+            }
+            parser.space()?;
+        }
+    }
+    parser.expect(b')')?;
+    Ok(Group { filter, events, fetch })
+}
+
+impl Notify {
+    /// # 第一个不支持的事件
+    pub fn unsupported(&self) -> Option<&str> {
+        self.groups.iter().flat_map(|group| &group.events).find(|event| !EVENTS.contains(&event.as_str())).map(String::as_str)
+    }
+
+    /// # 已选择邮箱的设置
+    /// ## 返回值
+    /// - Option<&Group>，没有 SELECTED 或 SELECTED-DELAYED 时为 None
+    pub fn selected(&self) -> Option<&Group> {
+        self.groups.iter().find(|group| matches!(group.filter, Filter::Selected | Filter::SelectedDelayed))
+    }
+
+    /// # 其他邮箱的事件是否需要推送
+    /// ## 参数
+    /// - name: 邮箱名称
+    /// - subscribed: 邮箱是否已订阅
+    /// - kind: 事件
+    /// ## 返回值
+    /// - bool
+    pub fn matches(&self, name: &str, subscribed: bool, kind: &Kind) -> bool {
+        let group = self.groups.iter().find(|group| match &group.filter {
+            Filter::Selected | Filter::SelectedDelayed => false,
+            Filter::Inboxes => name == "INBOX",
+            Filter::Personal => true,
+            Filter::Subscribed => subscribed,
+            Filter::Subtree(names) => names.iter().any(|parent| name == parent || mailbox::is_child(name, parent)),
+            Filter::Mailboxes(names) => names.iter().any(|mailbox| mailbox == name),
+        });
+        group.is_some_and(|group| group.events.iter().any(|event| event == name_of(kind)))
+    }
+
+    /// # 是否需要在 NOTIFY SET STATUS 时发送该邮箱的 STATUS
+    pub fn wants_status(&self, name: &str, subscribed: bool) -> bool {
+        self.status && self.matches(name, subscribed, &Kind::MessageNew)
+    }
+}
+
+/// # 事件的名称
+pub fn name_of(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::MessageNew => "MessageNew",
+        Kind::MessageExpunge => "MessageExpunge",
+        Kind::FlagChange => "FlagChange",
+        Kind::MailboxName(_) => "MailboxName",
+        Kind::SubscriptionChange => "SubscriptionChange",
+    }
+}
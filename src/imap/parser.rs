@@ -0,0 +1,328 @@
+/* IMAP 命令解析 */
+/*
+# 命令解析
+## 用法
+let mut parser = Parser::new(&command);   <-- 完整的命令，字面量已由 server.rs 读入
+let tag = parser.tag()?;
+parser.space()?;
+let name = parser.atom()?;
+...
+parser.end()?;
+## 说明
+字面量在命令中保留原始的 {长度}CRLF 或 {长度+}CRLF 前缀，解析时按长度读取。
+出错时返回 Bad，会话以 BAD 响应，说明文字发给客户端。
+ */
+use chrono::{DateTime, FixedOffset, NaiveDate};
+
+/// 语法错误，附带返回给客户端的说明
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bad(pub &'static str);
+
+pub type Result<T> = std::result::Result<T, Bad>;
+
+/// 序号集合（RFC 9051 9 sequence-set），0 表示 *
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceSet(Vec<(u32, u32)>);
+
+impl SequenceSet {
+    /// # 是否包含
+    /// ## 参数
+    /// - number: 序号或 UID
+    /// - max: * 代表的值，即最后一封邮件的序号或 UID
+    /// ## 返回值
+    /// - bool
+    pub fn contains(&self, number: u32, max: u32) -> bool {
+        let value = |n: u32| if n == 0 { max } else { n };
+        self.0.iter().any(|&(start, end)| {
+            let (start, end) = (value(start), value(end));
+            (start.min(end)..=start.max(end)).contains(&number)
+        })
+    }
+
+    /// # 转换为文本
+    /// 用于 COPYUID 等响应，连续的值合并为范围。
+    /// ## 参数
+    /// - values: 递增的值
+    /// ## 返回值
+    /// - String，例如 1:3,7
+    pub fn format(values: &[u32]) -> String {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &value in values {
+            match ranges.last_mut() {
+                Some((_, end)) if value == *end + 1 => *end = value,
+                _ => ranges.push((value, value)),
+            }
+        }
+        let ranges: Vec<String> =
+            ranges.iter().map(|&(start, end)| if start == end { start.to_string() } else { format!("{}:{}", start, end) }).collect();
+        ranges.join(",")
+    }
+}
+
+pub struct Parser<'a> {
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a [u8]) -> Parser<'a> {
+        Parser { input, position: 0 }
+    }
+
+    /// # 下一个字节，不移动位置
+    pub fn peek(&self) -> Option<u8> {
+        self.input.get(self.position).copied()
+    }
+
+    pub fn is_end(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    /// # 命令应已结束
+    pub fn end(&self) -> Result<()> {
+        if self.is_end() { Ok(()) } else { Err(Bad("Unexpected extra arguments")) }
+    }
+
+    /// # 下一个字节为 byte 时跳过
+    /// ## 返回值
+    /// - bool，是否跳过
+    pub fn eat(&mut self, byte: u8) -> bool {
+        if self.peek() == Some(byte) {
+            self.position += 1;
+            return true;
+        }
+        false
+    }
+
+    pub fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.eat(byte) { Ok(()) } else { Err(Bad("Syntax error")) }
+    }
+
+    /// # 下一个词为 word 时跳过
+    /// 不区分大小写，word 之后应为空格或命令结束。
+    /// ## 返回值
+    /// - bool，是否跳过
+    pub fn keyword(&mut self, word: &str) -> bool {
+        let end = self.position + word.len();
+        let found = self.input.get(self.position..end).is_some_and(|text| text.eq_ignore_ascii_case(word.as_bytes()))
+            && matches!(self.input.get(end), None | Some(b' '));
+        if found {
+            self.position = end;
+        }
+        found
+    }
+
+    /// # 一个空格
+    pub fn space(&mut self) -> Result<()> {
+        if self.eat(b' ') { Ok(()) } else { Err(Bad("Missing argument")) }
+    }
+
+    /// # 命令的标签
+    pub fn tag(&mut self) -> Result<String> {
+        let tag = self.take(|byte| is_astring_char(byte) && byte != b'+');
+        if tag.is_empty() {
+            return Err(Bad("Missing tag"));
+        }
+        Ok(String::from_utf8_lossy(tag).into_owned())
+    }
+
+    /// # atom
+    pub fn atom(&mut self) -> Result<String> {
+        let atom = self.take(is_atom_char);
+        if atom.is_empty() {
+            return Err(Bad("Syntax error"));
+        }
+        Ok(String::from_utf8_lossy(atom).into_owned())
+    }
+
+    /// # astring：atom（允许 ]）或字符串
+    pub fn astring(&mut self) -> Result<Vec<u8>> {
+        match self.peek() {
+            Some(b'"' | b'{') => self.string(),
+            _ => {
+                let atom = self.take(is_astring_char);
+                if atom.is_empty() {
+                    return Err(Bad("Syntax error"));
+                }
+                Ok(atom.to_vec())
+            }
+        }
+    }
+
+    /// # LIST 的邮箱名称模式，atom 中允许 % 与 *
+    pub fn list_mailbox(&mut self) -> Result<Vec<u8>> {
+        match self.peek() {
+            Some(b'"' | b'{') => self.string(),
+            _ => {
+                let atom = self.take(|byte| is_astring_char(byte) || byte == b'%' || byte == b'*');
+                if atom.is_empty() {
+                    return Err(Bad("Syntax error"));
+                }
+                Ok(atom.to_vec())
+            }
+        }
+    }
+
+    /// # 字符串：带引号的字符串或字面量
+    pub fn string(&mut self) -> Result<Vec<u8>> {
+        match self.peek() {
+            Some(b'"') => self.quoted(),
+            Some(b'{') => self.literal(),
+            _ => Err(Bad("Expected string")),
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Vec<u8>> {
+        self.expect(b'"')?;
+        let mut text = Vec::new();
+        loop {
+            match self.next() {
+                Some(b'"') => return Ok(text),
+                Some(b'\\') => match self.next() {
+                    Some(byte @ (b'"' | b'\\')) => text.push(byte),
+                    _ => return Err(Bad("Invalid escape in quoted string")),
+                },
+                Some(b'\r' | b'\n') | None => return Err(Bad("Unterminated quoted string")),
+                Some(byte) => text.push(byte),
+            }
+        }
+    }
+
+    /// {长度}CRLF 或 {长度+}CRLF（RFC 7888）之后的内容
+    fn literal(&mut self) -> Result<Vec<u8>> {
+        self.expect(b'{')?;
+        let size = self.number64()? as usize;
+        self.eat(b'+');
+        self.expect(b'}')?;
+        if !self.input[self.position..].starts_with(b"\r\n") {
+            return Err(Bad("Invalid literal"));
+        }
+        self.position += 2;
+        let end = self.position.checked_add(size).filter(|&end| end <= self.input.len()).ok_or(Bad("Incomplete literal"))?;
+        let literal = self.input[self.position..end].to_vec();
+        self.position = end;
+        Ok(literal)
+    }
+
+    /// # FETCH 数据项
+    /// 例如 BODY.PEEK[HEADER.FIELDS (From To)]<0.100>，方括号内可以包含空格与括号。
+    pub fn item(&mut self) -> Result<String> {
+        let start = self.position;
+        let mut depth = 0;
+        while let Some(byte) = self.peek() {
+            match byte {
+                b'[' => depth += 1,
+                b']' if depth > 0 => depth -= 1,
+                b' ' | b'(' | b')' if depth == 0 => break,
+                b'\r' | b'\n' => break,
+                _ => {}
+            }
+            self.position += 1;
+        }
+        if self.position == start || depth > 0 {
+            return Err(Bad("Invalid data item"));
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.position]).into_owned())
+    }
+
+    /// # 非负整数
+    pub fn number(&mut self) -> Result<u32> {
+        u32::try_from(self.number64()?).map_err(|_| Bad("Number out of range"))
+    }
+
+    pub fn number64(&mut self) -> Result<u64> {
+        let digits = self.take(|byte| byte.is_ascii_digit());
+        std::str::from_utf8(digits).ok().and_then(|digits| digits.parse().ok()).ok_or(Bad("Expected number"))
+    }
+
+    /// # 序号集合，例如 1:5,7,9:*
+    pub fn sequence_set(&mut self) -> Result<SequenceSet> {
+        let mut ranges = Vec::new();
+        loop {
+            let start = self.sequence_number()?;
+            let end = if self.eat(b':') { self.sequence_number()? } else { start };
+            ranges.push((start, end));
+            if !self.eat(b',') {
+                return Ok(SequenceSet(ranges));
+            }
+        }
+    }
+
+    fn sequence_number(&mut self) -> Result<u32> {
+        if self.eat(b'*') {
+            return Ok(0);
+        }
+        match self.number() {
+            Ok(0) | Err(_) => Err(Bad("Invalid sequence set")),
+            Ok(number) => Ok(number),
+        }
+    }
+
+    /// # 标记，例如 \Seen 或 $Forwarded
+    pub fn flag(&mut self) -> Result<String> {
+        let system = self.eat(b'\\');
+        let atom = self.atom().map_err(|_| Bad("Invalid flag"))?;
+        Ok(if system { format!("\\{}", atom) } else { atom })
+    }
+
+    /// # 括号中以空格分隔的列表
+    /// ## 参数
+    /// - item: 解析一项
+    /// ## 返回值
+    /// - Result<Vec<T>>
+    pub fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T>) -> Result<Vec<T>> {
+        self.expect(b'(')?;
+        let mut items = Vec::new();
+        if self.eat(b')') {
+            return Ok(items);
+        }
+        loop {
+            items.push(item(self)?);
+            if self.eat(b')') {
+                return Ok(items);
+            }
+            self.space()?;
+        }
+    }
+
+    /// # 日期，例如 1-Feb-1994，可以带引号
+    pub fn date(&mut self) -> Result<NaiveDate> {
+        let text = match self.peek() {
+            Some(b'"') => self.quoted()?,
+            _ => self.take(|byte| byte.is_ascii_alphanumeric() || byte == b'-').to_vec(),
+        };
+        let text = String::from_utf8_lossy(&text);
+        NaiveDate::parse_from_str(&text, "%d-%b-%Y").map_err(|_| Bad("Invalid date"))
+    }
+
+    /// # 日期时间，例如 "17-Jul-1996 02:44:25 -0700"
+    pub fn date_time(&mut self) -> Result<DateTime<FixedOffset>> {
+        let text = self.quoted()?;
+        let text = String::from_utf8_lossy(&text);
+        DateTime::parse_from_str(text.trim_start(), "%d-%b-%Y %H:%M:%S %z").map_err(|_| Bad("Invalid date-time"))
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let byte = self.peek()?;
+        self.position += 1;
+        Some(byte)
+    }
+
+    fn take(&mut self, accept: impl Fn(u8) -> bool) -> &'a [u8] {
+        let start = self.position;
+        while self.peek().is_some_and(&accept) {
+            self.position += 1;
+        }
+        &self.input[start..self.position]
+    }
+}
+
+/// ATOM-CHAR：除 atom-specials 以外的 ASCII 字符
+fn is_atom_char(byte: u8) -> bool {
+    byte.is_ascii() && !byte.is_ascii_control() && !matches!(byte, b'(' | b')' | b'{' | b' ' | b'%' | b'*' | b'"' | b'\\' | b']')
+}
+
+/// ASTRING-CHAR：ATOM-CHAR 或 ]
+fn is_astring_char(byte: u8) -> bool {
+    is_atom_char(byte) || byte == b']'
+}
@@ -0,0 +1,280 @@
+/* IMAP SEARCH */
+/*
+# SEARCH 条件
+## 用法
+let key = search::parse(&mut parser, UTF_8)?;          <-- 多个条件之间为 AND，字符串按 CHARSET 解码
+if key.matches(&Candidate { sequence, info, raw, last_sequence, last_uid }) { ... }
+## 说明
+文本条件不区分大小写，按子串匹配；原文与解码 encoded-word、传输编码及字符集之后的文本任一包含即符合。
+BEFORE、ON、SINCE 比较内部日期（UTC），SENTBEFORE 等比较 Date 字段的日期，不考虑时间与时区（RFC 9051 6.4.4）。
+ */
+use chrono::{DateTime, NaiveDate};
+use encoding_rs::Encoding;
+
+use crate::mime::header::decode_words;
+use crate::mime::{self, Part};
+use super::parser::{Bad, Parser, Result, SequenceSet};
+use crate::storage::MessageInfo;
+
+/// 嵌套的 NOT、OR 与括号的层数上限
+const MAX_DEPTH: usize = 32;
+
+/// 一个搜索条件
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    All,
+    /// 标记或关键字，bool 为是否应存在
+    Flag(String, bool),
+    /// 字段名与子串
+    Header(String, String),
+    Body(String),
+    Text(String),
+    Before(NaiveDate),
+    On(NaiveDate),
+    Since(NaiveDate),
+    SentBefore(NaiveDate),
+    SentOn(NaiveDate),
+    SentSince(NaiveDate),
+    Larger(u64),
+    Smaller(u64),
+    Uid(SequenceSet),
+    /// 修改序列不小于该值（RFC 7162 3.1.5），忽略 entry
+    ModSeq(u64),
+    Sequence(SequenceSet),
+    Not(Box<Key>),
+    Or(Box<Key>, Box<Key>),
+    And(Vec<Key>),
+}
+
+/// 参与搜索的一封邮件
+pub struct Candidate<'a> {
+    pub sequence: u32,
+    pub info: &'a MessageInfo,
+    /// 邮件原文，条件都不需要原文时可以为 None
+    pub raw: Option<&'a [u8]>,
+    /// 序号集合中 * 代表的值
+    pub last_sequence: u32,
+    pub last_uid: u32,
+}
+
+/// # 解析搜索条件
+/// 解析到命令结束，多个条件之间为 AND。
+/// ## 参数
+/// - parser: 位于第一个条件开头
+/// - charset: CHARSET 指定的字符集，字符串按此解码
+/// ## 返回值
+/// - Result<Key>
+pub fn parse(parser: &mut Parser, charset: &'static Encoding) -> Result<Key> {
+    let mut keys = vec![key(parser, 0, charset)?];
+    while !parser.is_end() {
+        parser.space()?;
+        keys.push(key(parser, 0, charset)?);
+    }
+    Ok(if keys.len() == 1 { keys.remove(0) } else { Key::And(keys) })
+}
+
+fn key(parser: &mut Parser, depth: usize, charset: &'static Encoding) -> Result<Key> {
+    if depth > MAX_DEPTH {
+        return Err(Bad("Search program too complex"));
+    }
+    if parser.peek() == Some(b'(') {
+        let mut keys = parser.list(|parser| key(parser, depth + 1, charset))?;
+        return Ok(if keys.len() == 1 { keys.remove(0) } else { Key::And(keys) });
+    }
+    if parser.peek().is_some_and(|byte| byte.is_ascii_digit() || byte == b'*') {
+        return Ok(Key::Sequence(parser.sequence_set()?));
+    }
+    let name = parser.atom()?.to_ascii_uppercase();
+    let flag = |name: &str, present: bool| Ok(Key::Flag(name.to_string(), present));
+    match name.as_str() {
+        "ALL" => Ok(Key::All),
+        "ANSWERED" => flag("\\Answered", true),
+        "DELETED" => flag("\\Deleted", true),
+        "DRAFT" => flag("\\Draft", true),
+        "FLAGGED" => flag("\\Flagged", true),
+        "SEEN" => flag("\\Seen", true),
+        "UNANSWERED" => flag("\\Answered", false),
+        "UNDELETED" => flag("\\Deleted", false),
+        "UNDRAFT" => flag("\\Draft", false),
+        "UNFLAGGED" => flag("\\Flagged", false),
+        "UNSEEN" => flag("\\Seen", false),
+        // 不支持 \Recent（RFC 9051 已移除），NEW 与 UNSEEN 相同，OLD 与 RECENT 对应全部与空
+        "NEW" => flag("\\Seen", false),
+        "OLD" => Ok(Key::All),
+        "RECENT" => Ok(Key::Not(Box::new(Key::All))),
+        "KEYWORD" | "UNKEYWORD" => {
+            parser.space()?;
+            Ok(Key::Flag(parser.atom()?, name == "KEYWORD"))
+        }
+        "FROM" | "TO" | "CC" | "BCC" | "SUBJECT" => {
+            parser.space()?;
+            let field = match name.as_str() {
+                "FROM" => "From",
+                "TO" => "To",
+                "CC" => "Cc",
+                "BCC" => "Bcc",
+                _ => "Subject",
+            };
+            Ok(Key::Header(field.to_string(), text(parser, charset)?))
+        }
+        "HEADER" => {
+            parser.space()?;
+            let field = text(parser, charset)?;
+            parser.space()?;
+            Ok(Key::Header(field, text(parser, charset)?))
+        }
+        "BODY" | "TEXT" => {
+            parser.space()?;
+            let value = text(parser, charset)?;
+            Ok(if name == "BODY" { Key::Body(value) } else { Key::Text(value) })
+        }
+        "BEFORE" | "ON" | "SINCE" | "SENTBEFORE" | "SENTON" | "SENTSINCE" => {
+            parser.space()?;
+            let date = parser.date()?;
+            Ok(match name.as_str() {
+                "BEFORE" => Key::Before(date),
+                "ON" => Key::On(date),
+                "SINCE" => Key::Since(date),
+                "SENTBEFORE" => Key::SentBefore(date),
+                "SENTON" => Key::SentOn(date),
+                _ => Key::SentSince(date),
+            })
+        }
+        "LARGER" | "SMALLER" => {
+            parser.space()?;
+            let size = parser.number64()?;
+            Ok(if name == "LARGER" { Key::Larger(size) } else { Key::Smaller(size) })
+        }
+        "UID" => {
+            parser.space()?;
+            Ok(Key::Uid(parser.sequence_set()?))
+        }
+        "MODSEQ" => {
+            parser.space()?;
+            // [entry-name entry-type-req]，只支持整封邮件的修改序列，按 all 处理
+            if parser.peek() == Some(b'"') {
+                parser.string()?;
+                parser.space()?;
+                match parser.atom()?.to_ascii_lowercase().as_str() {
+                    "priv" | "shared" | "all" => parser.space()?,
+                    _ => return Err(Bad("Invalid entry type")),
+                }
+            }
+            Ok(Key::ModSeq(parser.number64()?))
+        }
+        "NOT" => {
+            parser.space()?;
+            Ok(Key::Not(Box::new(key(parser, depth + 1, charset)?)))
+        }
+        "OR" => {
+            parser.space()?;
+            let left = key(parser, depth + 1, charset)?;
+            parser.space()?;
+            Ok(Key::Or(Box::new(left), Box::new(key(parser, depth + 1, charset)?)))
+        }
+        _ => Err(Bad("Unknown search key")),
+    }
+}
+
+fn text(parser: &mut Parser, charset: &'static Encoding) -> Result<String> {
+    Ok(charset.decode_without_bom_handling(&parser.astring()?).0.into_owned())
+}
+
+impl Key {
+    /// # 是否需要邮件原文
+    pub fn needs_raw(&self) -> bool {
+        match self {
+            Key::Header(..) | Key::Body(_) | Key::Text(_) | Key::SentBefore(_) | Key::SentOn(_) | Key::SentSince(_) => true,
+            Key::Not(key) => key.needs_raw(),
+            Key::Or(left, right) => left.needs_raw() || right.needs_raw(),
+            Key::And(keys) => keys.iter().any(Key::needs_raw),
+            _ => false,
+        }
+    }
+
+    /// # 是否包含 MODSEQ 条件
+    /// 包含时 SEARCH 的结果附带修改序列的最大值。
+    pub fn has_modseq(&self) -> bool {
+        match self {
+            Key::ModSeq(_) => true,
+            Key::Not(key) => key.has_modseq(),
+            Key::Or(left, right) => left.has_modseq() || right.has_modseq(),
+            Key::And(keys) => keys.iter().any(Key::has_modseq),
+            _ => false,
+        }
+    }
+
+    /// # 邮件是否符合条件
+    pub fn matches(&self, candidate: &Candidate) -> bool {
+        let message = candidate.raw.map(mime::parse);
+        self.test(candidate, message.as_ref())
+    }
+
+    fn test(&self, candidate: &Candidate, message: Option<&Part>) -> bool {
+        let info = candidate.info;
+        let date = info.internal_date.date_naive();
+        let sent = || message.and_then(sent_date);
+        let raw = candidate.raw.unwrap_or_default();
+        match self {
+            Key::All => true,
+            Key::Flag(flag, present) => info.flags.iter().any(|item| item.eq_ignore_ascii_case(flag)) == *present,
+            Key::Header(name, value) => message.is_some_and(|message| {
+                crate::mime::fields(message.header).iter().any(|(key, field)| key.eq_ignore_ascii_case(name) && header_contains(field, value))
+            }),
+            Key::Body(value) => message.is_some_and(|message| body_contains(message, value)),
+            Key::Text(value) => {
+                contains(raw, value)
+                    || message.is_some_and(|message| {
+                        crate::mime::fields(message.header).iter().any(|(_, field)| header_contains(field, value)) || body_contains(message, value)
+                    })
+            }
+            Key::Before(day) => date < *day,
+            Key::On(day) => date == *day,
+            Key::Since(day) => date >= *day,
+            Key::SentBefore(day) => sent().is_some_and(|sent| sent < *day),
+            Key::SentOn(day) => sent().is_some_and(|sent| sent == *day),
+            Key::SentSince(day) => sent().is_some_and(|sent| sent >= *day),
+            Key::Larger(size) => info.size > *size,
+            Key::Smaller(size) => info.size < *size,
+            Key::Uid(set) => set.contains(info.uid, candidate.last_uid),
+            Key::ModSeq(modseq) => info.modseq >= *modseq,
+            Key::Sequence(set) => set.contains(candidate.sequence, candidate.last_sequence),
+            Key::Not(key) => !key.test(candidate, message),
+            Key::Or(left, right) => left.test(candidate, message) || right.test(candidate, message),
+            Key::And(keys) => keys.iter().all(|key| key.test(candidate, message)),
+        }
+    }
+}
+
+/// # 不区分大小写的子串匹配
+fn contains(haystack: &[u8], needle: &str) -> bool {
+    if needle.is_empty() {
+        return true;
+    }
+    if needle.is_ascii() {
+        let needle = needle.as_bytes();
+        return haystack.windows(needle.len()).any(|window| window.eq_ignore_ascii_case(needle));
+    }
+    String::from_utf8_lossy(haystack).to_lowercase().contains(&needle.to_lowercase())
+}
+
+/// # 字段值是否包含字符串
+/// 同时匹配原文与解码 encoded-word 之后的文本（RFC 9051 6.4.4）。
+fn header_contains(field: &str, needle: &str) -> bool {
+    contains(field.as_bytes(), needle) || (field.contains("=?") && contains(decode_words(field).as_bytes(), needle))
+}
+
+/// # 正文是否包含字符串
+/// 同时匹配原文与各文本部分解码传输编码、转换字符集之后的内容。
+fn body_contains(message: &Part, needle: &str) -> bool {
+    contains(message.body, needle)
+        || message.leaves().iter().filter(|part| part.media_type == "text").any(|part| contains(part.text().0.as_bytes(), needle))
+}
+
+/// # Date 字段的日期
+/// 按邮件自身的时区取日期，忽略结尾的注释，例如 (CST)。
+fn sent_date(message: &Part) -> Option<NaiveDate> {
+    let date = message.field("Date")?;
+    let date = date.split(" (").next().unwrap_or(&date);
+    DateTime::parse_from_rfc2822(date.trim()).ok().map(|date| date.date_naive())
+}
@@ -0,0 +1,264 @@
+/* IMAP 监听 */
+use rustls::ServerConfig;
+use std::io::{self, BufRead, Read};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use super::session::{Next, Session};
+use super::{MAX_AUTH_LINE, MAX_LINE, MAX_LITERAL};
+use crate::config::Imap;
+use crate::listener::{self, Client, Counter, Line, Listener, Service, read_line};
+use crate::secret::MASK;
+use crate::storage::MailStore;
+use crate::tls::Connection;
+
+/// 未登录与空闲连接的超时（RFC 9051 5.4 要求至少 30 分钟）
+const TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// IDLE 与 NOTIFY 等待期间检查变化的间隔
+const POLL: Duration = Duration::from_secs(1);
+
+/// 连接编号与连接数，所有端口共用
+static COUNTER: Counter = Counter::new();
+
+/// # 根据配置列出需要监听的端口
+/// ## 参数
+/// - config: [IMAP]
+/// ## 返回值
+/// - Vec<Listener>
+pub fn listeners(config: &Imap) -> Vec<Listener> {
+    let mut listeners = vec![Listener { name: "IMAP", port: config.port, implicit_tls: false }];
+    if config.tls {
+        listeners.push(Listener { name: "IMAPS", port: config.secure_port, implicit_tls: true });
+    }
+    listeners
+}
+
+/// # 启动 IMAP 服务
+/// ## 参数
+/// - config: [IMAP]
+/// - listener: 监听的端口
+/// - tls: TLS 配置，未启用 TLS 时为 None
+/// - store: 邮件存储
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start(config: &Imap, listener: Listener, tls: Option<Arc<ServerConfig>>, store: Arc<dyn MailStore>) -> io::Result<JoinHandle<()>> {
+    listener::start(&config.address, listener, tls, ImapService { store })
+}
+
+/// IMAP 的会话循环
+struct ImapService {
+    store: Arc<dyn MailStore>,
+}
+
+impl Service for ImapService {
+    const PROTOCOL: &'static str = "IMAP";
+    const TIMEOUT: Duration = TIMEOUT;
+
+    fn counter(&self) -> &'static Counter {
+        &COUNTER
+    }
+
+    fn busy(&self) -> Vec<u8> {
+        b"* BYE Too many connections, try again later\r\n".to_vec()
+    }
+
+    fn serve(&self, connection: Connection, client: Client) -> io::Result<()> {
+        let session = Session::new(client.id, client.peer, Arc::clone(&self.store), client.state);
+        handle(connection, session, client.id, client.tls)
+    }
+}
+
+/// # 处理一个连接
+fn handle(mut connection: Connection, mut session: Session, id: u64, tls: Option<Arc<ServerConfig>>) -> io::Result<()> {
+    send(&mut connection, &session.greeting(), id)?;
+
+    loop {
+        if session.pushing() && !wait(&mut connection, &mut session, id)? {
+            return Ok(());
+        }
+        let command = match read_command(&mut connection, id) {
+            Ok(Command::Text(command)) => command,
+            Ok(Command::TooLong) => {
+                send(&mut connection, b"* BAD Command line too long\r\n", id)?;
+                continue;
+            }
+            Ok(Command::TooBig(tag)) => {
+                send(&mut connection, format!("{} NO [TOOBIG] Literal too large\r\n", tag).as_bytes(), id)?;
+                continue;
+            }
+            Ok(Command::Overflow(tag)) => {
+                let reply = format!("{} BAD [TOOBIG] Literal too large\r\n* BYE Literal too large\r\n", tag);
+                return send(&mut connection, reply.as_bytes(), id);
+            }
+            Ok(Command::Closed) => return Ok(()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                let _ = send(&mut connection, &session.timeout(), id);
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        debug!("IMAP #{} C: {}", id, redact(&command));
+
+        let mut response = session.command(&command);
+        loop {
+            send(&mut connection, &response.data, id)?;
+            match response.next {
+                Next::Continue => break,
+                Next::Close => return Ok(()),
+                Next::StartTls => {
+                    let Some(config) = &tls else { break };
+                    connection = connection.starttls(config)?;
+                    session.secured();
+                    break;
+                }
+                // SASL 响应包含密码或其摘要，不记录内容
+                Next::Auth => {
+                    response = match read_line(&mut connection, MAX_AUTH_LINE)? {
+                        Line::Text(line) => {
+                            debug!("IMAP #{} C: {}", id, MASK);
+                            session.auth(&String::from_utf8_lossy(&line))
+                        }
+                        // 过长的响应按客户端取消处理（RFC 9051 6.2.2）
+                        Line::TooLong => session.auth("*"),
+                        Line::Closed => return Ok(()),
+                    };
+                }
+                Next::Idle => {
+                    if !wait(&mut connection, &mut session, id)? {
+                        return Ok(());
+                    }
+                    response = match read_line(&mut connection, MAX_LINE)? {
+                        Line::Text(line) => {
+                            debug!("IMAP #{} C: {}", id, String::from_utf8_lossy(&line));
+                            session.done(&line)
+                        }
+                        Line::TooLong => session.done(b""),
+                        Line::Closed => return Ok(()),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// # 等待客户端发送数据，期间推送变化
+/// 每隔 POLL 调用 Session::poll，超过 TIMEOUT 仍无数据时发送 BYE。
+/// ## 返回值
+/// - io::Result<bool>，有数据可读时为 true，连接已关闭时为 false
+fn wait(connection: &mut Connection, session: &mut Session, id: u64) -> io::Result<bool> {
+    let start = Instant::now();
+    connection.set_read_timeout(POLL)?;
+    let result = loop {
+        match connection.fill_buf() {
+            Ok(buffer) => break Ok(!buffer.is_empty()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                if start.elapsed() >= TIMEOUT {
+                    let _ = send(connection, &session.timeout(), id);
+                    break Err(e);
+                }
+                let updates = session.poll();
+                if !updates.is_empty()
+                    && let Err(e) = send(connection, &updates, id)
+                {
+                    break Err(e);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => break Err(e),
+        }
+    };
+    connection.set_read_timeout(TIMEOUT)?;
+    result
+}
+
+fn send(connection: &mut Connection, data: &[u8], id: u64) -> io::Result<()> {
+    listener::send(connection, data, "IMAP", id)
+}
+
+/// # 调试日志中的命令
+/// 只显示第一行，隐藏 LOGIN 的密码与 AUTHENTICATE 的初始响应。
+fn redact(command: &[u8]) -> String {
+    let end = command.windows(2).position(|window| window == b"\r\n").unwrap_or(command.len());
+    let line = String::from_utf8_lossy(&command[..end]);
+    let words: Vec<&str> = line.splitn(4, ' ').collect();
+    match words.as_slice() {
+        [tag, verb, username, _] if verb.eq_ignore_ascii_case("LOGIN") => format!("{} {} {} {}", tag, verb, username, MASK),
+        [tag, verb, mechanism, _] if verb.eq_ignore_ascii_case("AUTHENTICATE") => format!("{} {} {} {}", tag, verb, mechanism, MASK),
+        _ if end < command.len() => format!("{} …（共 {} 字节）", line, command.len()),
+        _ => line.into_owned(),
+    }
+}
+
+/// 读取一条命令的结果
+enum Command {
+    /// 完整的命令，不含结尾的 CRLF，字面量保留 {长度}CRLF 前缀
+    Text(Vec<u8>),
+    /// 某一行超出长度上限，已丢弃到行尾
+    TooLong,
+    /// 同步字面量超出上限，附带命令的标签
+    TooBig(String),
+    /// 非同步字面量超出上限，附带命令的标签
+    Overflow(String),
+    /// 客户端关闭连接
+    Closed,
+}
+
+/// # 读取一条命令
+/// 行尾为 {长度} 或 {长度+} 时继续读取字面量与之后的内容（RFC 9051 4.3、RFC 7888）。
+/// 同步字面量发送 + 后客户端才会继续发送，超出上限时直接拒绝，客户端不会发送内容；
+/// 非同步字面量已在路上，超出上限时无法继续同步命令，只能断开连接（RFC 7888 4）。
+fn read_command(connection: &mut Connection, id: u64) -> io::Result<Command> {
+    let mut command = Vec::new();
+    loop {
+        let line = match read_line(connection, MAX_LINE)? {
+            Line::Text(line) => line,
+            Line::TooLong => return Ok(Command::TooLong),
+            Line::Closed => return Ok(Command::Closed),
+        };
+        command.extend_from_slice(&line);
+        let Some((size, synchronizing)) = literal(&line) else {
+            return Ok(Command::Text(command));
+        };
+        if command.len().saturating_add(size) > MAX_LITERAL {
+            return Ok(match synchronizing {
+                true => Command::TooBig(tag(&command)),
+                false => Command::Overflow(tag(&command)),
+            });
+        }
+        command.extend_from_slice(b"\r\n");
+        if synchronizing {
+            send(connection, b"+ Ready for literal data\r\n", id)?;
+        }
+        if connection.take(size as u64).read_to_end(&mut command)? < size {
+            return Ok(Command::Closed);
+        }
+    }
+}
+
+/// # 行尾的字面量长度
+/// ## 返回值
+/// - Option<(长度, 是否为同步字面量)>
+fn literal(line: &[u8]) -> Option<(usize, bool)> {
+    let inner = line.strip_suffix(b"}")?;
+    let start = inner.iter().rposition(|&byte| byte == b'{')?;
+    let inner = &inner[start + 1..];
+    let (digits, synchronizing) = match inner.strip_suffix(b"+") {
+        Some(digits) => (digits, false),
+        None => (inner, true),
+    };
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some((std::str::from_utf8(digits).ok()?.parse().ok()?, synchronizing))
+}
+
+/// # 命令的标签，用于拒绝过大的字面量
+fn tag(command: &[u8]) -> String {
+    let tag = command.split(|&byte| byte == b' ').next().unwrap_or_default();
+    match tag.is_empty() {
+        true => String::from("*"),
+        false => String::from_utf8_lossy(tag).into_owned(),
+    }
+}
@@ -0,0 +1,2035 @@
+/* IMAP 会话 */
+/*
+# 会话状态机
+## 用法
+let mut session = Session::new(id, peer, store, Tls::Available);
+write(session.greeting());
+for command in commands {                                   <-- 完整的命令，包括字面量
+    let response = session.command(&command);
+    write(response.data);
+    match response.next {
+        Next::Continue => {}
+        Next::StartTls => { 升级连接; session.secured(); }
+        Next::Auth => write(session.auth(读取到的一行).data),   <-- 直到 next 不再是 Auth
+        Next::Idle => { 等待期间定时 write(session.poll()); write(session.done(读取到的一行).data); }
+        Next::Close => break,
+    }
+    if session.pushing() { 等待下一条命令期间定时 write(session.poll()); }
+}
+## 说明
+状态：未登录 → 已登录 → 已选择邮箱，LOGOUT 后关闭连接（RFC 9051 3）。
+选择邮箱后记录客户端已知的邮件，每条命令结束前与存储比较，通过 EXISTS、EXPUNGE 与 FETCH FLAGS
+告知其他连接造成的变化；FETCH、STORE、SEARCH（不含 UID 版本）期间不发送 EXPUNGE（RFC 9051 7.5.1）。
+登录后订阅账号的事件（event.rs），IDLE 期间与启用 NOTIFY 后由 poll 推送变化，自己造成的事件忽略。
+启用 CONDSTORE 后响应附带修改序列，启用 QRESYNC 后以 VANISHED 代替 EXPUNGE（RFC 7162）。
+端口 143 在 STARTTLS 之前不允许登录（LOGINDISABLED）。
+ */
+use chrono::Utc;
+use encoding_rs::UTF_8;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use super::fetch::{self, Item};
+use super::mailbox::{self, DELIMITER};
+use super::notify::{self, Filter, Notify};
+use super::parser::{Bad, Parser, SequenceSet};
+use super::search::{self, Candidate};
+use super::{quoted, string};
+use crate::event::{self, Event, Kind, Subscription};
+use crate::mime::charset;
+use crate::sasl::{Authenticator, Mechanism, SaslError, Step};
+use crate::storage::{Account, MailStore, Mailbox, MessageInfo, StorageError};
+use crate::tls::Tls;
+
+/// 连续错误命令的上限，超过后断开连接
+const MAX_ERRORS: u32 = 10;
+
+/// 系统标记（RFC 9051 2.3.2），不支持已移除的 \Recent
+const SYSTEM_FLAGS: &[&str] = &["\\Answered", "\\Flagged", "\\Deleted", "\\Seen", "\\Draft"];
+
+/// 处理命令后的下一步
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Next {
+    /// 继续读取命令
+    Continue,
+    /// 升级为 TLS，然后调用 Session::secured
+    StartTls,
+    /// 读取一行 SASL 响应，然后调用 Session::auth
+    Auth,
+    /// IDLE：等待期间定时调用 Session::poll，读取到一行后调用 Session::done
+    Idle,
+    /// 关闭连接
+    Close,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    /// 未标记的响应与最后带标签的响应
+    pub data: Vec<u8>,
+    pub next: Next,
+}
+
+/// 命令失败的原因
+enum Failure {
+    /// 语法错误或当前状态不允许，以 BAD 响应
+    Bad(&'static str),
+    /// 以 NO 响应，文本可以带响应码
+    No(String),
+    Storage(StorageError),
+}
+
+impl From<Bad> for Failure {
+    fn from(e: Bad) -> Self {
+        Failure::Bad(e.0)
+    }
+}
+
+impl From<StorageError> for Failure {
+    fn from(e: StorageError) -> Self {
+        Failure::Storage(e)
+    }
+}
+
+/// 命令的结果，成功时为 OK 响应的文本
+type Outcome = Result<String, Failure>;
+
+/// SELECT 的 QRESYNC 参数（RFC 7162 3.2.5）
+struct Resync {
+    uid_validity: u32,
+    modseq: u64,
+    /// 客户端已知的 UID，省略时为全部
+    known: Option<SequenceSet>,
+}
+
+/// 已选择的邮箱
+struct Selected {
+    mailbox: Mailbox,
+    /// 使用 EXAMINE 打开
+    read_only: bool,
+    /// 客户端已知的邮件，按 UID 递增，下标加 1 为序号
+    messages: Vec<MessageInfo>,
+}
+
+impl Selected {
+    /// # 序号集合中 * 代表的值
+    /// ## 返回值
+    /// - (最后的序号, 最后的 UID)
+    fn last(&self) -> (u32, u32) {
+        (self.messages.len() as u32, self.messages.last().map_or(0, |message| message.uid))
+    }
+
+    /// # 集合中的邮件下标
+    fn indices(&self, set: &SequenceSet, uid: bool) -> Vec<usize> {
+        let (last_sequence, last_uid) = self.last();
+        (0..self.messages.len())
+            .filter(|&index| match uid {
+                true => set.contains(self.messages[index].uid, last_uid),
+                false => set.contains(index as u32 + 1, last_sequence),
+            })
+            .collect()
+    }
+}
+
+pub struct Session {
+    id: u64,
+    peer: IpAddr,
+    store: Arc<dyn MailStore>,
+    tls: Tls,
+    /// 已登录的账号
+    user: Option<Account>,
+    /// 正在进行的 SASL 认证与 AUTHENTICATE 命令的标签
+    authenticator: Option<(String, Authenticator)>,
+    selected: Option<Selected>,
+    /// 客户端已发送 ENABLE IMAP4rev2
+    rev2: bool,
+    /// 已启用 CONDSTORE，响应附带修改序列
+    condstore: bool,
+    /// 已启用 QRESYNC
+    qresync: bool,
+    /// 账号的事件，登录后订阅
+    subscription: Option<Subscription>,
+    /// NOTIFY 的设置
+    notify: Option<Notify>,
+    /// 正在进行的 IDLE 命令的标签
+    idle: Option<String>,
+    /// 本条命令的未标记响应
+    untagged: Vec<u8>,
+    errors: u32,
+}
+
+impl Session {
+    /// # 创建会话
+    /// ## 参数
+    /// - id: 连接编号
+    /// - peer: 客户端地址
+    /// - store: 邮件存储
+    /// - tls: 连接的加密状态
+    /// ## 返回值
+    /// - Session
+    pub fn new(id: u64, peer: IpAddr, store: Arc<dyn MailStore>, tls: Tls) -> Session {
+        Session {
+            id,
+            peer,
+            store,
+            tls,
+            user: None,
+            authenticator: None,
+            selected: None,
+            rev2: false,
+            condstore: false,
+            qresync: false,
+            subscription: None,
+            notify: None,
+            idle: None,
+            untagged: Vec::new(),
+            errors: 0,
+        }
+    }
+
+    /// # 问候语
+    pub fn greeting(&self) -> Vec<u8> {
+        format!("* OK [CAPABILITY {}] ZitMail IMAP ready\r\n", self.capabilities()).into_bytes()
+    }
+
+    /// # 当前状态下的能力
+    fn capabilities(&self) -> String {
+        let mut capabilities: Vec<String> =
+            ["IMAP4rev1", "IMAP4rev2", "LITERAL+", "SASL-IR", "ENABLE", "ID"].map(String::from).to_vec();
+        if self.user.is_none() {
+            match self.tls {
+                Tls::Available => capabilities.extend(["STARTTLS", "LOGINDISABLED"].map(String::from)),
+                _ => capabilities.extend(Mechanism::ALL.iter().map(|name| format!("AUTH={}", name))),
+            }
+        } else {
+            capabilities.extend([
+                "NAMESPACE",
+                "UIDPLUS",
+                "MOVE",
+                "UNSELECT",
+                "CHILDREN",
+                "LIST-EXTENDED",
+                "LIST-STATUS",
+                "SPECIAL-USE",
+                "ESEARCH",
+                "STATUS=SIZE",
+                "IDLE",
+                "CONDSTORE",
+                "QRESYNC",
+                "NOTIFY",
+            ]
+            .map(String::from));
+        }
+        capabilities.join(" ")
+    }
+
+    /// # 处理一条命令
+    /// ## 参数
+    /// - line: 不含结尾 CRLF 的命令，字面量保留 {长度}CRLF 前缀
+    /// ## 返回值
+    /// - Response
+    pub fn command(&mut self, line: &[u8]) -> Response {
+        self.untagged.clear();
+        let events = self.events();
+        let mut parser = Parser::new(line);
+        let Ok(tag) = parser.tag() else {
+            return self.tally(Response { data: b"* BAD Missing tag\r\n".to_vec(), next: Next::Continue });
+        };
+        let mut name = match parser.space().and_then(|_| parser.atom()) {
+            Ok(name) => name.to_ascii_uppercase(),
+            Err(_) => return self.finish(&tag, Err(Failure::Bad("Missing command")), Next::Continue),
+        };
+        let uid = name == "UID";
+        if uid {
+            name = match parser.space().and_then(|_| parser.atom()) {
+                Ok(name) if matches!(name.to_ascii_uppercase().as_str(), "FETCH" | "STORE" | "SEARCH" | "COPY" | "MOVE" | "EXPUNGE") => {
+                    name.to_ascii_uppercase()
+                }
+                _ => return self.finish(&tag, Err(Failure::Bad("Invalid UID command")), Next::Continue),
+            };
+        }
+        match name.as_str() {
+            "AUTHENTICATE" => return self.authenticate(&tag, &mut parser),
+            "IDLE" => return self.idle(&tag, &mut parser),
+            _ => {}
+        }
+
+        let outcome = self.dispatch(&tag, &name, uid, &mut parser);
+        let next = match (&outcome, name.as_str()) {
+            (Ok(_), "LOGOUT") => Next::Close,
+            (Ok(_), "STARTTLS") => Next::StartTls,
+            _ => Next::Continue,
+        };
+        if next == Next::Continue {
+            let expunge = uid || !matches!(name.as_str(), "FETCH" | "STORE" | "SEARCH");
+            if let Err(e) = self.sync(expunge) {
+                error!("IMAP #{} 无法读取邮箱：{}", self.id, e);
+            }
+            self.notifications(&events);
+        }
+        self.finish(&tag, outcome, next)
+    }
+
+    /// # 是否需要在等待命令期间调用 poll
+    /// 启用 NOTIFY 后，变化在客户端发送下一条命令之前推送。
+    pub fn pushing(&self) -> bool {
+        self.notify.is_some()
+    }
+
+    /// # 推送其他连接与投递造成的变化
+    /// IDLE 期间与启用 NOTIFY 后等待命令时定时调用。
+    /// ## 返回值
+    /// - Vec<u8>，未标记的响应，没有变化时为空
+    pub fn poll(&mut self) -> Vec<u8> {
+        let events = self.events();
+        if events.is_empty() {
+            return Vec::new();
+        }
+        self.untagged.clear();
+        let selected = self.selected.as_ref().map(|selected| selected.mailbox.id);
+        if events.iter().any(|event| Some(event.mailbox_id) == selected) {
+            // IDLE 期间可以发送 EXPUNGE，否则按 NOTIFY 的 SELECTED 或 SELECTED-DELAYED
+            let expunge = match (&self.idle, &self.notify) {
+                (Some(_), _) => Some(true),
+                (None, Some(notify)) => notify.selected().map(|group| group.filter == Filter::Selected),
+                (None, None) => None,
+            };
+            if let Some(expunge) = expunge
+                && let Err(e) = self.sync(expunge)
+            {
+                error!("IMAP #{} 无法读取邮箱：{}", self.id, e);
+            }
+        }
+        self.notifications(&events);
+        std::mem::take(&mut self.untagged)
+    }
+
+    /// # 结束 IDLE
+    /// ## 参数
+    /// - line: IDLE 期间客户端发送的一行，不含 CRLF，应为 DONE
+    /// ## 返回值
+    /// - Response
+    pub fn done(&mut self, line: &[u8]) -> Response {
+        self.untagged.clear();
+        let Some(tag) = self.idle.take() else {
+            return Response { data: b"* BAD Not idling\r\n".to_vec(), next: Next::Continue };
+        };
+        let outcome = match line.eq_ignore_ascii_case(b"DONE") {
+            true => Ok(String::from("IDLE terminated")),
+            false => Err(Failure::Bad("Expected DONE")),
+        };
+        let events = self.events();
+        if let Err(e) = self.sync(true) {
+            error!("IMAP #{} 无法读取邮箱：{}", self.id, e);
+        }
+        self.notifications(&events);
+        self.finish(&tag, outcome, Next::Continue)
+    }
+
+    /// # 连接已升级为 TLS
+    pub fn secured(&mut self) {
+        self.tls = Tls::Active;
+        debug!("IMAP #{} 已启用 TLS", self.id);
+    }
+
+    /// # 处理一行 SASL 响应
+    /// ## 参数
+    /// - line: 不含 CRLF 的 Base64 文本
+    /// ## 返回值
+    /// - Response
+    pub fn auth(&mut self, line: &str) -> Response {
+        let Some((tag, mut authenticator)) = self.authenticator.take() else {
+            return Response { data: b"* BAD No authentication in progress\r\n".to_vec(), next: Next::Continue };
+        };
+        let step = authenticator.step(line);
+        self.auth_step(tag, authenticator, step)
+    }
+
+    /// # 以空闲超时结束会话
+    pub fn timeout(&self) -> Vec<u8> {
+        b"* BYE Autologout; idle for too long\r\n".to_vec()
+    }
+
+    /// 生成带标签的响应
+    fn finish(&mut self, tag: &str, outcome: Outcome, next: Next) -> Response {
+        let (status, text) = match outcome {
+            Ok(text) => ("OK", text),
+            Err(Failure::Bad(text)) => ("BAD", text.to_string()),
+            Err(Failure::No(text)) => ("NO", text),
+            Err(Failure::Storage(e)) => {
+                error!("IMAP #{} 存储操作失败：{}", self.id, e);
+                ("NO", String::from("[UNAVAILABLE] Temporary failure, try again later"))
+            }
+        };
+        let mut data = std::mem::take(&mut self.untagged);
+        data.extend_from_slice(format!("{} {} {}\r\n", tag, status, text).as_bytes());
+        self.tally(Response { data, next })
+    }
+
+    /// 统计连续的 BAD 响应，超过上限后断开连接
+    fn tally(&mut self, mut response: Response) -> Response {
+        let bad = response.data.split(|&byte| byte == b'\n').rev().nth(1).is_some_and(|line| {
+            line.split(|&byte| byte == b' ').nth(1) == Some(b"BAD")
+        });
+        if !bad {
+            self.errors = 0;
+            return response;
+        }
+        self.errors += 1;
+        if self.errors >= MAX_ERRORS {
+            response.data.extend_from_slice(b"* BYE Too many errors\r\n");
+            response.next = Next::Close;
+        }
+        response
+    }
+
+    fn dispatch(&mut self, tag: &str, name: &str, uid: bool, parser: &mut Parser) -> Outcome {
+        match name {
+            "CAPABILITY" => {
+                parser.end()?;
+                let capabilities = self.capabilities();
+                push(&mut self.untagged, format!("* CAPABILITY {}", capabilities));
+                Ok(String::from("CAPABILITY completed"))
+            }
+            "NOOP" => {
+                parser.end()?;
+                Ok(String::from("NOOP completed"))
+            }
+            "LOGOUT" => {
+                parser.end()?;
+                push(&mut self.untagged, "* BYE Logging out");
+                Ok(String::from("LOGOUT completed"))
+            }
+            "ID" => self.id_command(parser),
+            "STARTTLS" => self.starttls(parser),
+            "LOGIN" => self.login(parser),
+            "ENABLE" => self.enable(parser),
+            "SELECT" => self.select(parser, false),
+            "EXAMINE" => self.select(parser, true),
+            "CREATE" => self.create(parser),
+            "DELETE" => self.delete(parser),
+            "RENAME" => self.rename(parser),
+            "SUBSCRIBE" => self.subscribe(parser, true),
+            "UNSUBSCRIBE" => self.subscribe(parser, false),
+            "LIST" => self.list(parser, false),
+            "LSUB" => self.list(parser, true),
+            "NOTIFY" => self.notify(parser),
+            "NAMESPACE" => {
+                self.account()?;
+                parser.end()?;
+                push(&mut self.untagged, format!("* NAMESPACE ((\"\" \"{}\")) NIL NIL", DELIMITER));
+                Ok(String::from("NAMESPACE completed"))
+            }
+            "STATUS" => self.status(parser),
+            "APPEND" => self.append(parser),
+            "CHECK" => {
+                self.selected()?;
+                parser.end()?;
+                Ok(String::from("CHECK completed"))
+            }
+            "CLOSE" | "UNSELECT" => self.close(parser, name == "CLOSE"),
+            "EXPUNGE" => self.expunge(parser, uid),
+            "SEARCH" => self.search(tag, parser, uid),
+            "FETCH" => self.fetch(parser, uid),
+            "STORE" => self.store_flags(parser, uid),
+            "COPY" => self.copy(parser, uid, false),
+            "MOVE" => self.copy(parser, uid, true),
+            _ => Err(Failure::Bad("Unknown command")),
+        }
+    }
+
+    /// 已登录的账号
+    fn account(&self) -> Result<Account, Failure> {
+        self.user.clone().ok_or(Failure::Bad("Not authenticated"))
+    }
+
+    fn selected(&mut self) -> Result<&mut Selected, Failure> {
+        self.selected.as_mut().ok_or(Failure::Bad("No mailbox selected"))
+    }
+
+    /// # 读取邮箱名称
+    /// IMAP4rev1 客户端使用修改版 UTF-7，仍兼容直接发送 UTF-8 的客户端。
+    fn mailbox_name(&self, parser: &mut Parser) -> Result<String, Failure> {
+        let name = parser.astring()?;
+        Ok(mailbox::normalize(&self.decode_name(&name)?))
+    }
+
+    fn decode_name(&self, name: &[u8]) -> Result<String, Failure> {
+        let decoded = if self.rev2 { None } else { mailbox::decode_utf7(name) };
+        match decoded {
+            Some(name) => Ok(name),
+            None => String::from_utf8(name.to_vec()).map_err(|_| Failure::Bad("Invalid mailbox name")),
+        }
+    }
+
+    /// # 响应中的邮箱名称
+    fn encode_name(&self, name: &str) -> Vec<u8> {
+        match self.rev2 {
+            true => quoted(name.as_bytes()),
+            false => string(mailbox::encode_utf7(name).as_bytes()),
+        }
+    }
+
+    /// # 查找邮箱
+    fn find_mailbox(&self, account: &Account, name: &str) -> Result<Mailbox, Failure> {
+        self.store.mailbox(account.id, name)?.ok_or_else(|| Failure::No(String::from("[NONEXISTENT] Mailbox does not exist")))
+    }
+
+    /// ID（RFC 2971），不透露服务器版本
+    fn id_command(&mut self, parser: &mut Parser) -> Outcome {
+        parser.space()?;
+        if !parser.keyword("NIL") {
+            parser.list(|parser| parser.astring().or_else(|_| parser.atom().map(String::into_bytes)))?;
+        }
+        parser.end()?;
+        push(&mut self.untagged, "* ID (\"name\" \"ZitMail\")");
+        Ok(String::from("ID completed"))
+    }
+
+    fn starttls(&mut self, parser: &mut Parser) -> Outcome {
+        parser.end()?;
+        if self.user.is_some() {
+            return Err(Failure::Bad("Already authenticated"));
+        }
+        match self.tls {
+            Tls::Unavailable => Err(Failure::Bad("STARTTLS is not available")),
+            Tls::Active => Err(Failure::Bad("TLS already active")),
+            Tls::Available => Ok(String::from("Begin TLS negotiation now")),
+        }
+    }
+
+    /// LOGIN 用户名 密码
+    fn login(&mut self, parser: &mut Parser) -> Outcome {
+        parser.space()?;
+        let username = parser.astring()?;
+        parser.space()?;
+        let password = parser.astring()?;
+        parser.end()?;
+        if self.user.is_some() {
+            return Err(Failure::Bad("Already authenticated"));
+        }
+        if self.tls == Tls::Available {
+            return Err(Failure::No(String::from("[PRIVACYREQUIRED] Use STARTTLS first")));
+        }
+        let (Ok(username), Ok(password)) = (String::from_utf8(username), String::from_utf8(password)) else {
+            return Err(Failure::Bad("Invalid credentials encoding"));
+        };
+        let step = Authenticator::login(Arc::clone(&self.store), &username, &password);
+        self.logged_in(step)
+    }
+
+    /// AUTHENTICATE 机制 [初始响应]（RFC 9051 6.2.2、RFC 4959）
+    fn authenticate(&mut self, tag: &str, parser: &mut Parser) -> Response {
+        let started = (|| {
+            parser.space()?;
+            let name = parser.atom()?;
+            let initial = if parser.eat(b' ') { Some(parser.atom()?) } else { None };
+            parser.end()?;
+            if self.user.is_some() {
+                return Err(Failure::Bad("Already authenticated"));
+            }
+            if self.tls == Tls::Available {
+                return Err(Failure::No(String::from("[PRIVACYREQUIRED] Use STARTTLS first")));
+            }
+            let Some(mechanism) = Mechanism::parse(&name) else {
+                return Err(Failure::No(String::from("[CANNOT] Unsupported authentication mechanism")));
+            };
+            let mut authenticator = Authenticator::new(mechanism, Arc::clone(&self.store));
+            let step = authenticator.start(initial.as_deref());
+            Ok((authenticator, step))
+        })();
+        match started {
+            Ok((authenticator, step)) => self.auth_step(tag.to_string(), authenticator, step),
+            Err(failure) => self.finish(tag, Err(failure), Next::Continue),
+        }
+    }
+
+    fn auth_step(&mut self, tag: String, authenticator: Authenticator, step: Result<Step, SaslError>) -> Response {
+        if let Ok(Step::Challenge(challenge)) = &step {
+            let data = format!("+ {}\r\n", challenge).into_bytes();
+            self.authenticator = Some((tag, authenticator));
+            return Response { data, next: Next::Auth };
+        }
+        let outcome = self.logged_in(step);
+        self.finish(&tag, outcome, Next::Continue)
+    }
+
+    /// 认证结束
+    fn logged_in(&mut self, step: Result<Step, SaslError>) -> Outcome {
+        match step {
+            Ok(Step::Success(account)) => {
+                info!("IMAP #{} 账号 {} 已登录", self.id, account.address());
+                self.subscription = Some(event::subscribe(account.id));
+                // 与投递时相同，INBOX 不存在时重新创建
+                if self.store.mailbox(account.id, "INBOX")?.is_none() {
+                    let inbox = self.store.create_mailbox(account.id, "INBOX")?;
+                    self.publish(&inbox, Kind::MailboxName(None));
+                }
+                self.user = Some(account);
+                Ok(format!("[CAPABILITY {}] Logged in", self.capabilities()))
+            }
+            Ok(Step::Failure(username)) => {
+                warning!("IMAP #{} 来自 {} 的登录失败，用户名 {}", self.id, self.peer, username);
+                Err(Failure::No(String::from("[AUTHENTICATIONFAILED] Authentication failed")))
+            }
+            Ok(Step::Challenge(_)) | Err(SaslError::Malformed) => Err(Failure::Bad("Malformed authentication data")),
+            Err(SaslError::Cancelled) => Err(Failure::Bad("Authentication cancelled")),
+            Err(SaslError::Storage(e)) => Err(Failure::Storage(e)),
+        }
+    }
+
+    /// ENABLE（RFC 5161），支持 IMAP4rev2、CONDSTORE 与 QRESYNC
+    fn enable(&mut self, parser: &mut Parser) -> Outcome {
+        self.account()?;
+        if self.selected.is_some() {
+            return Err(Failure::Bad("ENABLE is not allowed while a mailbox is selected"));
+        }
+        let mut enabled = Vec::new();
+        loop {
+            parser.space()?;
+            let capability = parser.atom()?;
+            let capability = capability.to_ascii_uppercase();
+            if capability == "IMAP4REV2" && !self.rev2 {
+                self.rev2 = true;
+                enabled.push("IMAP4rev2");
+            }
+            // QRESYNC 同时启用 CONDSTORE（RFC 7162 3.2.3）
+            if matches!(capability.as_str(), "CONDSTORE" | "QRESYNC") && !self.condstore {
+                self.condstore = true;
+                enabled.push("CONDSTORE");
+            }
+            if capability == "QRESYNC" && !self.qresync {
+                self.qresync = true;
+                enabled.push("QRESYNC");
+            }
+            if parser.is_end() {
+                break;
+            }
+        }
+        let line = if enabled.is_empty() { String::from("* ENABLED") } else { format!("* ENABLED {}", enabled.join(" ")) };
+        push(&mut self.untagged, line);
+        Ok(String::from("ENABLE completed"))
+    }
+
+    /// IDLE（RFC 2177），等待 DONE 期间由 server.rs 调用 poll 推送变化
+    fn idle(&mut self, tag: &str, parser: &mut Parser) -> Response {
+        if let Err(failure) = parser.end().map_err(Failure::from).and_then(|_| self.account()) {
+            return self.finish(tag, Err(failure), Next::Continue);
+        }
+        let events = self.events();
+        if let Err(e) = self.sync(true) {
+            error!("IMAP #{} 无法读取邮箱：{}", self.id, e);
+        }
+        self.notifications(&events);
+        self.idle = Some(tag.to_string());
+        self.errors = 0;
+        let mut data = std::mem::take(&mut self.untagged);
+        data.extend_from_slice(b"+ idling\r\n");
+        Response { data, next: Next::Idle }
+    }
+
+    /// NOTIFY NONE 或 NOTIFY SET [STATUS] (过滤条件 事件)...（RFC 5465）
+    fn notify(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        if parser.keyword("NONE") {
+            parser.end()?;
+            self.notify = None;
+            return Ok(String::from("NOTIFY completed"));
+        }
+        if !parser.keyword("SET") {
+            return Err(Failure::Bad("Expected SET or NONE"));
+        }
+        let notify = notify::parse(parser, &|name| self.decode_name(name).ok())?;
+        if let Some(event) = notify.unsupported() {
+            return Err(Failure::No(format!("[BADEVENT ({})] Unsupported event {}", notify::EVENTS.join(" "), event)));
+        }
+        if notify.status {
+            let selected = self.selected.as_ref().map(|selected| selected.mailbox.id);
+            let items = self.notify_items(false);
+            for mailbox in self.store.mailboxes(account.id)? {
+                if Some(mailbox.id) != selected && notify.wants_status(&mailbox.name, mailbox.subscribed) {
+                    let line = self.status_line(&mailbox, &items)?;
+                    push(&mut self.untagged, line);
+                }
+            }
+        }
+        self.notify = Some(notify);
+        Ok(String::from("NOTIFY completed"))
+    }
+
+    /// SELECT 与 EXAMINE，参数可以为 (CONDSTORE) 或 (QRESYNC (...))
+    fn select(&mut self, parser: &mut Parser, read_only: bool) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        let (mut condstore, mut resync) = (false, None);
+        if parser.eat(b' ') {
+            parser.list(|parser| {
+                match parser.atom()?.to_ascii_uppercase().as_str() {
+                    "CONDSTORE" => condstore = true,
+                    "QRESYNC" => {
+                        parser.space()?;
+                        resync = Some(qresync_parameter(parser)?);
+                    }
+                    _ => return Err(Bad("Unknown SELECT parameter")),
+                }
+                Ok(())
+            })?;
+        }
+        parser.end()?;
+        if resync.is_some() && !self.qresync {
+            return Err(Failure::Bad("QRESYNC is not enabled"));
+        }
+        if condstore {
+            self.condstore = true;
+        }
+        if self.selected.take().is_some() {
+            push(&mut self.untagged, "* OK [CLOSED] Previous mailbox is now closed");
+        }
+        let mailbox = self.find_mailbox(&account, &name)?;
+        let messages = self.store.messages(mailbox.id)?;
+
+        let flags = SYSTEM_FLAGS.join(" ");
+        push(&mut self.untagged, format!("* {} EXISTS", messages.len()));
+        if !self.rev2 {
+            push(&mut self.untagged, "* 0 RECENT");
+        }
+        push(&mut self.untagged, format!("* FLAGS ({})", flags));
+        match read_only {
+            true => push(&mut self.untagged, "* OK [PERMANENTFLAGS ()] Read-only mailbox"),
+            false => push(&mut self.untagged, format!("* OK [PERMANENTFLAGS ({} \\*)] Flags permitted", flags)),
+        }
+        if !self.rev2
+            && let Some(index) = messages.iter().position(|message| !has_flag(&message.flags, "\\Seen"))
+        {
+            push(&mut self.untagged, format!("* OK [UNSEEN {}] First unseen", index + 1));
+        }
+        push(&mut self.untagged, format!("* OK [UIDVALIDITY {}] UIDs valid", mailbox.uid_validity));
+        push(&mut self.untagged, format!("* OK [UIDNEXT {}] Predicted next UID", mailbox.uid_next));
+        if self.condstore {
+            push(&mut self.untagged, format!("* OK [HIGHESTMODSEQ {}] Highest", mailbox.highest_modseq));
+        }
+        if self.rev2 {
+            let line = [format!("* LIST () \"{}\" ", DELIMITER).as_bytes(), &self.encode_name(&mailbox.name)].concat();
+            push(&mut self.untagged, line);
+        }
+
+        // 客户端的缓存仍然有效时，告知之后删除的 UID 与修改过的邮件（RFC 7162 3.2.5.1）
+        if let Some(resync) = resync.filter(|resync| resync.uid_validity == mailbox.uid_validity) {
+            let known = |uid: u32| resync.known.as_ref().is_none_or(|known| known.contains(uid, u32::MAX));
+            let mut vanished = self.store.vanished(mailbox.id, resync.modseq)?;
+            vanished.retain(|&uid| known(uid));
+            vanished.sort_unstable();
+            if !vanished.is_empty() {
+                push(&mut self.untagged, format!("* VANISHED (EARLIER) {}", SequenceSet::format(&vanished)));
+            }
+            for (index, message) in messages.iter().enumerate() {
+                if message.modseq > resync.modseq && known(message.uid) {
+                    push(&mut self.untagged, fetch::render(index as u32 + 1, message, None, &[Item::Uid, Item::Flags, Item::ModSeq]));
+                }
+            }
+        }
+
+        debug!("IMAP #{} 已选择邮箱 {}", self.id, mailbox.name);
+        self.selected = Some(Selected { mailbox, read_only, messages });
+        Ok(match read_only {
+            true => String::from("[READ-ONLY] EXAMINE completed"),
+            false => String::from("[READ-WRITE] SELECT completed"),
+        })
+    }
+
+    /// CREATE，同时创建不存在的上级邮箱
+    fn create(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.end()?;
+        if !mailbox::is_valid(&name) {
+            return Err(Failure::No(String::from("[CANNOT] Invalid mailbox name")));
+        }
+        self.create_parents(&account, &name)?;
+        match self.store.create_mailbox(account.id, &name) {
+            Ok(created) => {
+                self.publish(&created, Kind::MailboxName(None));
+                Ok(String::from("CREATE completed"))
+            }
+            Err(StorageError::Conflict(_)) => Err(Failure::No(String::from("[ALREADYEXISTS] Mailbox already exists"))),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// # 创建不存在的上级邮箱
+    fn create_parents(&self, account: &Account, name: &str) -> Result<(), Failure> {
+        for parent in mailbox::parents(name) {
+            if self.store.mailbox(account.id, parent)?.is_none() {
+                let created = self.store.create_mailbox(account.id, parent)?;
+                self.publish(&created, Kind::MailboxName(None));
+            }
+        }
+        Ok(())
+    }
+
+    /// DELETE，不能删除 INBOX 与有下级的邮箱
+    fn delete(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.end()?;
+        if name == "INBOX" {
+            return Err(Failure::No(String::from("[CANNOT] INBOX cannot be deleted")));
+        }
+        let target = self.find_mailbox(&account, &name)?;
+        if self.store.mailboxes(account.id)?.iter().any(|other| mailbox::is_child(&other.name, &name)) {
+            return Err(Failure::No(String::from("[HASCHILDREN] Mailbox has children, delete them first")));
+        }
+        self.store.delete_mailbox(target.id)?;
+        self.publish(&target, Kind::MailboxName(None));
+        if self.selected.as_ref().is_some_and(|selected| selected.mailbox.id == target.id) {
+            self.selected = None;
+            push(&mut self.untagged, "* OK [CLOSED] Mailbox deleted");
+        }
+        Ok(String::from("DELETE completed"))
+    }
+
+    /// RENAME，下级邮箱一并改名；INBOX 改名时移动其中的邮件，INBOX 保留（RFC 9051 6.3.6）
+    fn rename(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let from = self.mailbox_name(parser)?;
+        parser.space()?;
+        let to = self.mailbox_name(parser)?;
+        parser.end()?;
+        if !mailbox::is_valid(&to) {
+            return Err(Failure::No(String::from("[CANNOT] Invalid mailbox name")));
+        }
+        let source = self.find_mailbox(&account, &from)?;
+        if to == "INBOX" || self.store.mailbox(account.id, &to)?.is_some() {
+            return Err(Failure::No(String::from("[ALREADYEXISTS] Mailbox already exists")));
+        }
+        if mailbox::is_child(&to, &from) {
+            return Err(Failure::No(String::from("[CANNOT] Cannot move a mailbox into itself")));
+        }
+        self.create_parents(&account, &to)?;
+
+        if from == "INBOX" {
+            let target = self.store.create_mailbox(account.id, &to)?;
+            let messages = self.store.messages(source.id)?;
+            for message in &messages {
+                self.store.copy(message.id, target.id)?;
+            }
+            self.store.expunge(&messages.iter().map(|message| message.id).collect::<Vec<_>>())?;
+            self.publish(&target, Kind::MailboxName(None));
+            self.publish(&source, Kind::MessageExpunge);
+            return Ok(String::from("RENAME completed"));
+        }
+        let children: Vec<Mailbox> =
+            self.store.mailboxes(account.id)?.into_iter().filter(|other| mailbox::is_child(&other.name, &from)).collect();
+        self.store.rename_mailbox(source.id, &to)?;
+        self.publish(&Mailbox { name: to.clone(), ..source.clone() }, Kind::MailboxName(Some(from.clone())));
+        for child in children {
+            let name = format!("{}{}", to, &child.name[from.len()..]);
+            self.store.rename_mailbox(child.id, &name)?;
+            self.publish(&Mailbox { name, ..child.clone() }, Kind::MailboxName(Some(child.name)));
+        }
+        if let Some(selected) = self.selected.as_mut().filter(|selected| selected.mailbox.id == source.id) {
+            selected.mailbox.name = to;
+        }
+        Ok(String::from("RENAME completed"))
+    }
+
+    /// SUBSCRIBE 与 UNSUBSCRIBE，只能订阅已存在的邮箱
+    fn subscribe(&mut self, parser: &mut Parser, subscribed: bool) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.end()?;
+        let target = self.find_mailbox(&account, &name)?;
+        self.store.set_subscribed(target.id, subscribed)?;
+        self.publish(&target, Kind::SubscriptionChange);
+        Ok(String::from(if subscribed { "SUBSCRIBE completed" } else { "UNSUBSCRIBE completed" }))
+    }
+
+    /// LIST（含 LIST-EXTENDED、LIST-STATUS、SPECIAL-USE）与 LSUB
+    fn list(&mut self, parser: &mut Parser, lsub: bool) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let mut selection = Vec::new();
+        if !lsub && parser.peek() == Some(b'(') {
+            selection = parser.list(|parser| parser.atom().map(|option| option.to_ascii_uppercase()))?;
+            parser.space()?;
+        }
+        let reference = parser.astring()?;
+        let reference = self.decode_name(&reference)?;
+        parser.space()?;
+        let patterns = match parser.peek() {
+            Some(b'(') if !lsub => parser.list(|parser| parser.list_mailbox())?,
+            _ => vec![parser.list_mailbox()?],
+        };
+        let mut returns = Vec::new();
+        let mut status_items = None;
+        if !lsub && parser.eat(b' ') {
+            if !parser.keyword("RETURN") {
+                return Err(Failure::Bad("Expected RETURN"));
+            }
+            parser.space()?;
+            returns = parser.list(|parser| {
+                let option = parser.atom()?.to_ascii_uppercase();
+                if option == "STATUS" {
+                    parser.space()?;
+                    status_items = Some(parser.list(|parser| parser.atom())?);
+                }
+                Ok(option)
+            })?;
+        }
+        parser.end()?;
+        for option in &selection {
+            if !matches!(option.as_str(), "SUBSCRIBED" | "REMOTE" | "RECURSIVEMATCH" | "SPECIAL-USE") {
+                return Err(Failure::Bad("Unknown LIST selection option"));
+            }
+        }
+        for option in &returns {
+            if !matches!(option.as_str(), "SUBSCRIBED" | "CHILDREN" | "SPECIAL-USE" | "STATUS") {
+                return Err(Failure::Bad("Unknown LIST return option"));
+            }
+        }
+        let verb = if lsub { "LSUB" } else { "LIST" };
+        let patterns = patterns.iter().map(|pattern| self.decode_name(pattern)).collect::<Result<Vec<_>, _>>()?;
+
+        // 空模式用于查询层级分隔符
+        if patterns.iter().all(String::is_empty) {
+            push(&mut self.untagged, format!("* {} (\\Noselect) \"{}\" \"\"", verb, DELIMITER));
+            return Ok(format!("{} completed", verb));
+        }
+
+        let subscribed_only = lsub || selection.iter().any(|option| option == "SUBSCRIBED");
+        let special_only = selection.iter().any(|option| option == "SPECIAL-USE");
+        let show_subscribed = (!lsub && subscribed_only) || returns.iter().any(|option| option == "SUBSCRIBED");
+        let mailboxes = self.store.mailboxes(account.id)?;
+        // 已存在的邮箱与只作为上级出现的名称
+        let mut names: BTreeSet<&str> = mailboxes.iter().map(|mailbox| mailbox.name.as_str()).collect();
+        for mailbox in &mailboxes {
+            names.extend(mailbox::parents(&mailbox.name));
+        }
+        let mut names: Vec<&str> = names.into_iter().collect();
+        names.sort_by_key(|name| *name != "INBOX");
+
+        for name in names {
+            let full = |pattern: &String| format!("{}{}", reference, pattern);
+            if !patterns.iter().any(|pattern| mailbox::matches(&full(pattern), name)) {
+                continue;
+            }
+            let existing = mailboxes.iter().find(|mailbox| mailbox.name == name);
+            let subscribed = existing.is_some_and(|mailbox| mailbox.subscribed);
+            let special = existing.and_then(|_| mailbox::special_use(name));
+            if (subscribed_only && !subscribed) || (special_only && special.is_none()) {
+                continue;
+            }
+            let mut attributes = Vec::new();
+            if existing.is_none() {
+                attributes.push("\\Noselect");
+            }
+            match mailboxes.iter().any(|mailbox| mailbox::is_child(&mailbox.name, name)) {
+                true => attributes.push("\\HasChildren"),
+                false => attributes.push("\\HasNoChildren"),
+            }
+            if show_subscribed && subscribed {
+                attributes.push("\\Subscribed");
+            }
+            attributes.extend(special);
+            let line = [format!("* {} ({}) \"{}\" ", verb, attributes.join(" "), DELIMITER).as_bytes(), &self.encode_name(name)].concat();
+            push(&mut self.untagged, line);
+            if let (Some(items), Some(mailbox)) = (&status_items, existing) {
+                let line = self.status_line(mailbox, items)?;
+                push(&mut self.untagged, line);
+            }
+        }
+        Ok(format!("{} completed", verb))
+    }
+
+    /// STATUS 邮箱 (数据项)
+    fn status(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.space()?;
+        let items = parser.list(|parser| parser.atom())?;
+        parser.end()?;
+        if items.iter().any(|item| item.eq_ignore_ascii_case("HIGHESTMODSEQ")) {
+            self.enable_condstore();
+        }
+        let mailbox = self.find_mailbox(&account, &name)?;
+        let line = self.status_line(&mailbox, &items)?;
+        push(&mut self.untagged, line);
+        Ok(String::from("STATUS completed"))
+    }
+
+    /// # * STATUS 响应
+    fn status_line(&self, mailbox: &Mailbox, items: &[String]) -> Result<Vec<u8>, Failure> {
+        let messages = self.store.messages(mailbox.id)?;
+        let count = |flag: &str| messages.iter().filter(|message| has_flag(&message.flags, flag)).count();
+        let mut values = Vec::new();
+        for item in items {
+            let item = item.to_ascii_uppercase();
+            let value = match item.as_str() {
+                "MESSAGES" => messages.len() as u64,
+                "UIDNEXT" => mailbox.uid_next as u64,
+                "UIDVALIDITY" => mailbox.uid_validity as u64,
+                "UNSEEN" => (messages.len() - count("\\Seen")) as u64,
+                "DELETED" => count("\\Deleted") as u64,
+                "SIZE" => messages.iter().map(|message| message.size).sum(),
+                "HIGHESTMODSEQ" => mailbox.highest_modseq,
+                "RECENT" => 0,
+                _ => return Err(Failure::Bad("Unknown STATUS item")),
+            };
+            values.push(format!("{} {}", item, value));
+        }
+        Ok([b"* STATUS ".as_slice(), &self.encode_name(&mailbox.name), format!(" ({})", values.join(" ")).as_bytes()].concat())
+    }
+
+    /// APPEND 邮箱 [(标记)] [日期时间] 邮件
+    fn append(&mut self, parser: &mut Parser) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.space()?;
+        let mut flags = Vec::new();
+        if parser.peek() == Some(b'(') {
+            flags = parser.list(|parser| parser.flag())?;
+            parser.space()?;
+        }
+        let date = match parser.peek() {
+            Some(b'"') => {
+                let date = parser.date_time()?;
+                parser.space()?;
+                Some(date.with_timezone(&Utc))
+            }
+            _ => None,
+        };
+        // literal8（RFC 3516）
+        parser.eat(b'~');
+        let message = parser.string()?;
+        parser.end()?;
+        let flags = normalize_flags(flags)?;
+        let Some(mailbox) = self.store.mailbox(account.id, &name)? else {
+            return Err(Failure::No(String::from("[TRYCREATE] Mailbox does not exist")));
+        };
+        if message.is_empty() {
+            return Err(Failure::No(String::from("[CANNOT] Empty message")));
+        }
+        let info = self.store.append(mailbox.id, &message, &flags, date.unwrap_or_else(Utc::now))?;
+        self.publish(&mailbox, Kind::MessageNew);
+        Ok(format!("[APPENDUID {} {}] APPEND completed", mailbox.uid_validity, info.uid))
+    }
+
+    /// CLOSE 删除带 \Deleted 标记的邮件后取消选择，UNSELECT 直接取消选择
+    fn close(&mut self, parser: &mut Parser, expunge: bool) -> Outcome {
+        parser.end()?;
+        let selected = self.selected.take().ok_or(Failure::Bad("No mailbox selected"))?;
+        if expunge && !selected.read_only {
+            let messages = self.store.messages(selected.mailbox.id)?;
+            let deleted: Vec<i64> =
+                messages.iter().filter(|message| has_flag(&message.flags, "\\Deleted")).map(|message| message.id).collect();
+            if !deleted.is_empty() {
+                self.store.expunge(&deleted)?;
+                self.publish(&selected.mailbox, Kind::MessageExpunge);
+            }
+        }
+        Ok(String::from(if expunge { "CLOSE completed" } else { "UNSELECT completed" }))
+    }
+
+    /// EXPUNGE 与 UID EXPUNGE（RFC 4315）
+    /// EXPUNGE 响应在命令结束前统一发送。
+    fn expunge(&mut self, parser: &mut Parser, uid: bool) -> Outcome {
+        let account = self.account()?;
+        let set = match uid {
+            true => {
+                parser.space()?;
+                Some(parser.sequence_set()?)
+            }
+            false => None,
+        };
+        parser.end()?;
+        if self.selected()?.read_only {
+            return Err(Failure::No(String::from("[READ-ONLY] Mailbox is read-only")));
+        }
+        self.sync(true)?;
+        let selected = self.selected()?;
+        let last_uid = selected.last().1;
+        let deleted: Vec<i64> = selected
+            .messages
+            .iter()
+            .filter(|message| has_flag(&message.flags, "\\Deleted"))
+            .filter(|message| set.as_ref().is_none_or(|set| set.contains(message.uid, last_uid)))
+            .map(|message| message.id)
+            .collect();
+        let mailbox = selected.mailbox.clone();
+        if !deleted.is_empty() {
+            self.store.expunge(&deleted)?;
+            self.publish(&mailbox, Kind::MessageExpunge);
+        }
+        if self.condstore
+            && let Some(mailbox) = self.store.mailbox(account.id, &mailbox.name)?
+        {
+            return Ok(format!("[HIGHESTMODSEQ {}] EXPUNGE completed", mailbox.highest_modseq));
+        }
+        Ok(String::from("EXPUNGE completed"))
+    }
+
+    /// SEARCH [RETURN (选项)] [CHARSET 字符集] 条件（含 ESEARCH，RFC 4731）
+    fn search(&mut self, tag: &str, parser: &mut Parser, uid: bool) -> Outcome {
+        parser.space()?;
+        let mut options = None;
+        if parser.keyword("RETURN") {
+            parser.space()?;
+            options = Some(parser.list(|parser| parser.atom().map(|option| option.to_ascii_uppercase()))?);
+            parser.space()?;
+        }
+        // 除 UTF-8 外也接受 GB2312、Big5 等字符集，旧的客户端按本地字符集发送搜索的字符串
+        let mut encoding = UTF_8;
+        if parser.keyword("CHARSET") {
+            parser.space()?;
+            let name = parser.astring()?;
+            parser.space()?;
+            encoding = match charset::lookup(&String::from_utf8_lossy(&name)) {
+                Some(encoding) => encoding,
+                None => return Err(Failure::No(String::from("[BADCHARSET (UTF-8 US-ASCII GB18030 GBK GB2312 BIG5 ISO-2022-JP SHIFT_JIS EUC-JP EUC-KR ISO-8859-1)] Unsupported charset"))),
+            };
+        }
+        let key = search::parse(parser, encoding)?;
+        if let Some(options) = &options
+            && options.iter().any(|option| !matches!(option.as_str(), "MIN" | "MAX" | "COUNT" | "ALL"))
+        {
+            return Err(Failure::Bad("Unknown SEARCH return option"));
+        }
+        if key.has_modseq() {
+            self.enable_condstore();
+        }
+
+        let store = Arc::clone(&self.store);
+        let selected = self.selected()?;
+        let (last_sequence, last_uid) = selected.last();
+        let (mut found, mut highest) = (Vec::new(), 0);
+        for (index, info) in selected.messages.iter().enumerate() {
+            let raw = match key.needs_raw() {
+                true => match store.raw(info.id)? {
+                    Some(raw) => Some(raw),
+                    None => continue,
+                },
+                false => None,
+            };
+            let sequence = index as u32 + 1;
+            let candidate = Candidate { sequence, info, raw: raw.as_deref(), last_sequence, last_uid };
+            if key.matches(&candidate) {
+                found.push(if uid { info.uid } else { sequence });
+                highest = highest.max(info.modseq);
+            }
+        }
+        // 条件包含 MODSEQ 时附带结果中修改序列的最大值（RFC 7162 3.1.5）
+        let modseq = key.has_modseq() && !found.is_empty();
+
+        let line = match (options, self.rev2) {
+            (None, false) => {
+                let numbers: Vec<String> = found.iter().map(u32::to_string).collect();
+                let mut line = format!("* SEARCH{}{}", if numbers.is_empty() { "" } else { " " }, numbers.join(" "));
+                if modseq {
+                    line.push_str(&format!(" (MODSEQ {})", highest));
+                }
+                line
+            }
+            (options, _) => {
+                let options = options.filter(|options| !options.is_empty()).unwrap_or_else(|| vec![String::from("ALL")]);
+                let mut line = format!("* ESEARCH (TAG {})", String::from_utf8_lossy(&quoted(tag.as_bytes())));
+                if uid {
+                    line.push_str(" UID");
+                }
+                for option in options {
+                    match (option.as_str(), found.first(), found.last()) {
+                        ("MIN", Some(min), _) => line.push_str(&format!(" MIN {}", min)),
+                        ("MAX", _, Some(max)) => line.push_str(&format!(" MAX {}", max)),
+                        ("COUNT", ..) => line.push_str(&format!(" COUNT {}", found.len())),
+                        ("ALL", Some(_), _) => line.push_str(&format!(" ALL {}", SequenceSet::format(&found))),
+                        _ => {}
+                    }
+                }
+                if modseq {
+                    line.push_str(&format!(" MODSEQ {}", highest));
+                }
+                line
+            }
+        };
+        push(&mut self.untagged, line);
+        Ok(String::from("SEARCH completed"))
+    }
+
+    /// FETCH 集合 数据项 [(CHANGEDSINCE 修改序列 [VANISHED])]
+    fn fetch(&mut self, parser: &mut Parser, uid: bool) -> Outcome {
+        parser.space()?;
+        let set = parser.sequence_set()?;
+        parser.space()?;
+        let mut items = fetch::parse(parser)?;
+        let (mut changed_since, mut vanished) = (None, false);
+        if parser.eat(b' ') {
+            parser.list(|parser| {
+                match parser.atom()?.to_ascii_uppercase().as_str() {
+                    "CHANGEDSINCE" => {
+                        parser.space()?;
+                        changed_since = Some(parser.number64()?);
+                    }
+                    "VANISHED" => vanished = true,
+                    _ => return Err(Bad("Unknown FETCH modifier")),
+                }
+                Ok(())
+            })?;
+        }
+        parser.end()?;
+        if vanished && !(uid && self.qresync && changed_since.is_some()) {
+            return Err(Failure::Bad("VANISHED requires UID FETCH with CHANGEDSINCE and QRESYNC enabled"));
+        }
+        self.selected()?;
+        if changed_since.is_some() || items.contains(&Item::ModSeq) {
+            self.enable_condstore();
+        }
+        if uid && !items.contains(&Item::Uid) {
+            items.insert(0, Item::Uid);
+        }
+        if changed_since.is_some() && !items.contains(&Item::ModSeq) {
+            items.push(Item::ModSeq);
+        }
+        let needs_raw = items.iter().any(Item::needs_raw);
+        let condstore = self.condstore;
+        let store = Arc::clone(&self.store);
+        let selected = self.selected.as_mut().ok_or(Failure::Bad("No mailbox selected"))?;
+        if let Some(since) = changed_since.filter(|_| vanished) {
+            // 已删除的 UID 可能大于当前最后的 UID，* 不限制上界
+            let mut uids = store.vanished(selected.mailbox.id, since)?;
+            uids.retain(|&uid| set.contains(uid, u32::MAX));
+            uids.sort_unstable();
+            if !uids.is_empty() {
+                push(&mut self.untagged, format!("* VANISHED (EARLIER) {}", SequenceSet::format(&uids)));
+            }
+        }
+        let seen = !selected.read_only && items.iter().any(Item::sets_seen);
+        let (mut missing, mut changed) = (false, false);
+        for index in selected.indices(&set, uid) {
+            let info = &mut selected.messages[index];
+            if changed_since.is_some_and(|since| info.modseq <= since) {
+                continue;
+            }
+            let raw = match needs_raw {
+                true => match store.raw(info.id)? {
+                    Some(raw) => Some(raw),
+                    None => {
+                        missing = true;
+                        continue;
+                    }
+                },
+                false => None,
+            };
+            // 读取正文时设置 \Seen，并在响应中告知新的标记
+            let mut extra = None;
+            if seen && !has_flag(&info.flags, "\\Seen") {
+                let mut flags = info.flags.clone();
+                flags.push(String::from("\\Seen"));
+                if let Some(modseq) = store.set_flags(info.id, &flags, None)? {
+                    info.modseq = modseq;
+                }
+                info.flags = flags;
+                changed = true;
+                let mut updated = items.clone();
+                if !updated.contains(&Item::Flags) {
+                    updated.push(Item::Flags);
+                }
+                if condstore && !updated.contains(&Item::ModSeq) {
+                    updated.push(Item::ModSeq);
+                }
+                extra = Some(updated);
+            }
+            let line = fetch::render(index as u32 + 1, info, raw.as_deref(), extra.as_deref().unwrap_or(&items));
+            push(&mut self.untagged, line);
+        }
+        if changed {
+            let mailbox = selected.mailbox.clone();
+            self.publish(&mailbox, Kind::FlagChange);
+        }
+        match missing {
+            true => Err(Failure::No(String::from("[EXPUNGEISSUED] Some messages were expunged"))),
+            false => Ok(String::from("FETCH completed")),
+        }
+    }
+
+    /// STORE 集合 [(UNCHANGEDSINCE 修改序列)] [+|-]FLAGS[.SILENT] 标记
+    fn store_flags(&mut self, parser: &mut Parser, uid: bool) -> Outcome {
+        parser.space()?;
+        let set = parser.sequence_set()?;
+        parser.space()?;
+        let mut unchanged_since = None;
+        if parser.peek() == Some(b'(') {
+            parser.list(|parser| {
+                if !parser.keyword("UNCHANGEDSINCE") {
+                    return Err(Bad("Unknown STORE modifier"));
+                }
+                parser.space()?;
+                unchanged_since = Some(parser.number64()?);
+                Ok(())
+            })?;
+            parser.space()?;
+        }
+        let action = parser.atom()?.to_ascii_uppercase();
+        parser.space()?;
+        let flags = match parser.peek() {
+            Some(b'(') => parser.list(|parser| parser.flag())?,
+            _ => {
+                let mut flags = vec![parser.flag()?];
+                while parser.eat(b' ') {
+                    flags.push(parser.flag()?);
+                }
+                flags
+            }
+        };
+        parser.end()?;
+        let (action, silent) = match action.strip_suffix(".SILENT") {
+            Some(action) => (action, true),
+            None => (action.as_str(), false),
+        };
+        if !matches!(action, "FLAGS" | "+FLAGS" | "-FLAGS") {
+            return Err(Failure::Bad("Invalid STORE data item"));
+        }
+        let flags = normalize_flags(flags)?;
+        self.selected()?;
+        if unchanged_since.is_some() {
+            self.enable_condstore();
+        }
+
+        let condstore = self.condstore;
+        let store = Arc::clone(&self.store);
+        let selected = self.selected.as_mut().ok_or(Failure::Bad("No mailbox selected"))?;
+        if selected.read_only {
+            return Err(Failure::No(String::from("[READ-ONLY] Mailbox is read-only")));
+        }
+        let mut items = if uid { vec![Item::Uid, Item::Flags] } else { vec![Item::Flags] };
+        if condstore {
+            items.push(Item::ModSeq);
+        }
+        // .SILENT 时仍告知修改后的修改序列（RFC 7162 3.1.3）
+        let silent_items = if uid { vec![Item::Uid, Item::ModSeq] } else { vec![Item::ModSeq] };
+        let (mut modified, mut changed) = (Vec::new(), false);
+        for index in selected.indices(&set, uid) {
+            let info = &mut selected.messages[index];
+            let updated: Vec<String> = match action {
+                "FLAGS" => flags.clone(),
+                "+FLAGS" => {
+                    let mut updated = info.flags.clone();
+                    updated.extend(flags.iter().filter(|flag| !has_flag(&info.flags, flag)).cloned());
+                    updated
+                }
+                _ => info.flags.iter().filter(|flag| !has_flag(&flags, flag)).cloned().collect(),
+            };
+            let number = if uid { info.uid } else { index as u32 + 1 };
+            let mut written = false;
+            if updated != info.flags {
+                match store.set_flags(info.id, &updated, unchanged_since) {
+                    Ok(Some(modseq)) => {
+                        info.flags = updated;
+                        info.modseq = modseq;
+                        written = true;
+                    }
+                    Ok(None) => {
+                        modified.push(number);
+                        continue;
+                    }
+                    // 已被其他连接删除
+                    Err(StorageError::NotFound(_)) => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            } else if unchanged_since.is_some_and(|since| info.modseq > since) {
+                modified.push(number);
+                continue;
+            }
+            changed |= written;
+            match (silent, condstore && written) {
+                (false, _) => push(&mut self.untagged, fetch::render(index as u32 + 1, info, None, &items)),
+                (true, true) => push(&mut self.untagged, fetch::render(index as u32 + 1, info, None, &silent_items)),
+                (true, false) => {}
+            }
+        }
+        if changed {
+            let mailbox = selected.mailbox.clone();
+            self.publish(&mailbox, Kind::FlagChange);
+        }
+        if !modified.is_empty() {
+            return Ok(format!("[MODIFIED {}] Conditional STORE failed", SequenceSet::format(&modified)));
+        }
+        Ok(String::from("STORE completed"))
+    }
+
+    /// COPY 与 MOVE（RFC 6851），返回 COPYUID（RFC 4315）
+    fn copy(&mut self, parser: &mut Parser, uid: bool, moving: bool) -> Outcome {
+        let account = self.account()?;
+        parser.space()?;
+        let set = parser.sequence_set()?;
+        parser.space()?;
+        let name = self.mailbox_name(parser)?;
+        parser.end()?;
+        let verb = if moving { "MOVE" } else { "COPY" };
+        let store = Arc::clone(&self.store);
+        let selected = self.selected.as_mut().ok_or(Failure::Bad("No mailbox selected"))?;
+        if moving && selected.read_only {
+            return Err(Failure::No(String::from("[READ-ONLY] Mailbox is read-only")));
+        }
+        let Some(target) = store.mailbox(account.id, &name)? else {
+            return Err(Failure::No(String::from("[TRYCREATE] Mailbox does not exist")));
+        };
+
+        let (mut sources, mut copies, mut ids) = (Vec::new(), Vec::new(), Vec::new());
+        for index in selected.indices(&set, uid) {
+            let info = &selected.messages[index];
+            match store.copy(info.id, target.id) {
+                Ok(copy) => {
+                    sources.push(info.uid);
+                    copies.push(copy.uid);
+                    ids.push(info.id);
+                }
+                Err(StorageError::NotFound(_)) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        if sources.is_empty() {
+            return Ok(format!("{} completed, no messages", verb));
+        }
+        let source = selected.mailbox.clone();
+        self.publish(&target, Kind::MessageNew);
+        let code = format!("COPYUID {} {} {}", target.uid_validity, SequenceSet::format(&sources), SequenceSet::format(&copies));
+        if moving {
+            // EXPUNGE 响应在命令结束前统一发送
+            push(&mut self.untagged, format!("* OK [{}] Moved", code));
+            store.expunge(&ids)?;
+            self.publish(&source, Kind::MessageExpunge);
+            return Ok(String::from("MOVE completed"));
+        }
+        Ok(format!("[{}] COPY completed", code))
+    }
+
+    /// # 与存储同步已选择的邮箱
+    /// ## 参数
+    /// - expunge: 是否可以发送 EXPUNGE 响应，不可以时已删除的邮件保留在序号中
+    /// ## 返回值
+    /// - Result<(), StorageError>
+    fn sync(&mut self, expunge: bool) -> Result<(), StorageError> {
+        let Some(selected) = self.selected.as_mut() else {
+            return Ok(());
+        };
+        let current = self.store.messages(selected.mailbox.id)?;
+        let find = |uid: u32| current.binary_search_by_key(&uid, |message| message.uid).ok();
+        if expunge {
+            // 从后往前发送，之前的序号不受影响；启用 QRESYNC 时合并为一条 VANISHED
+            let mut vanished = Vec::new();
+            for index in (0..selected.messages.len()).rev() {
+                if find(selected.messages[index].uid).is_none() {
+                    let removed = selected.messages.remove(index);
+                    match self.qresync {
+                        true => vanished.push(removed.uid),
+                        false => push(&mut self.untagged, format!("* {} EXPUNGE", index + 1)),
+                    }
+                }
+            }
+            if !vanished.is_empty() {
+                vanished.reverse();
+                push(&mut self.untagged, format!("* VANISHED {}", SequenceSet::format(&vanished)));
+            }
+        }
+        let items: &[Item] = if self.condstore { &[Item::Uid, Item::Flags, Item::ModSeq] } else { &[Item::Uid, Item::Flags] };
+        for (index, known) in selected.messages.iter_mut().enumerate() {
+            if let Some(position) = find(known.uid)
+                && (current[position].flags != known.flags || current[position].modseq != known.modseq)
+            {
+                let changed = current[position].flags != known.flags;
+                known.flags = current[position].flags.clone();
+                known.modseq = current[position].modseq;
+                if changed || self.condstore {
+                    push(&mut self.untagged, fetch::render(index as u32 + 1, known, None, items));
+                }
+            }
+        }
+        let last = selected.last().1;
+        let added: Vec<MessageInfo> = current.into_iter().filter(|message| message.uid > last).collect();
+        if !added.is_empty() {
+            let first = selected.messages.len();
+            selected.messages.extend(added);
+            push(&mut self.untagged, format!("* {} EXISTS", selected.messages.len()));
+            // NOTIFY 的 MessageNew 附带 FETCH 数据项时一并发送（RFC 5465 5.2）
+            let items = self.notify.as_ref().and_then(Notify::selected).map(|group| group.fetch.clone()).unwrap_or_default();
+            if !items.is_empty() {
+                let needs_raw = items.iter().any(Item::needs_raw);
+                for index in first..selected.messages.len() {
+                    let info = &selected.messages[index];
+                    let raw = if needs_raw { self.store.raw(info.id)? } else { None };
+                    push(&mut self.untagged, fetch::render(index as u32 + 1, info, raw.as_deref(), &items));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// # 启用 CONDSTORE
+    /// 由使用修改序列的命令隐式启用，已选择邮箱时告知当前的最大修改序列（RFC 7162 3.1）。
+    fn enable_condstore(&mut self) {
+        if self.condstore {
+            return;
+        }
+        self.condstore = true;
+        if let Some(selected) = &self.selected {
+            let modseq = selected.messages.iter().map(|message| message.modseq).max().unwrap_or(0).max(selected.mailbox.highest_modseq);
+            push(&mut self.untagged, format!("* OK [HIGHESTMODSEQ {}] Highest", modseq));
+        }
+    }
+
+    /// # 发布事件
+    /// ## 参数
+    /// - mailbox: 发生变化的邮箱，改名后为新名称
+    /// - kind: 变化的类型
+    fn publish(&self, mailbox: &Mailbox, kind: Kind) {
+        let origin = self.subscription.as_ref().map_or(0, Subscription::id);
+        event::publish(Event { account_id: mailbox.account_id, mailbox_id: mailbox.id, mailbox: mailbox.name.clone(), kind, origin });
+    }
+
+    /// # 取出其他连接与投递造成的事件
+    fn events(&self) -> Vec<Event> {
+        let Some(subscription) = &self.subscription else {
+            return Vec::new();
+        };
+        subscription.events().into_iter().filter(|event| event.origin != subscription.id()).collect()
+    }
+
+    /// # 按 NOTIFY 的设置告知其他邮箱的变化
+    /// 已选择邮箱的邮件变化由 sync 告知；邮件的变化以 STATUS 告知，邮箱的变化以 LIST 告知（RFC 5465 5）。
+    fn notifications(&mut self, events: &[Event]) {
+        let (Some(notify), Some(account)) = (self.notify.clone(), self.user.clone()) else {
+            return;
+        };
+        let selected = self.selected.as_ref().map(|selected| selected.mailbox.id);
+        // 同一邮箱只发送一次 STATUS
+        let mut reported = Vec::new();
+        for event in events {
+            let message = matches!(event.kind, Kind::MessageNew | Kind::MessageExpunge | Kind::FlagChange);
+            if message && Some(event.mailbox_id) == selected {
+                continue;
+            }
+            let mailbox = match self.store.mailbox(account.id, &event.mailbox) {
+                // 名称相同但编号不同时，原来的邮箱已被删除或改名
+                Ok(mailbox) => mailbox.filter(|mailbox| mailbox.id == event.mailbox_id),
+                Err(e) => {
+                    error!("IMAP #{} 无法读取邮箱：{}", self.id, e);
+                    continue;
+                }
+            };
+            let subscribed = mailbox.as_ref().is_some_and(|mailbox| mailbox.subscribed);
+            if !notify.matches(&event.mailbox, subscribed, &event.kind) {
+                continue;
+            }
+            let line = match (&event.kind, mailbox) {
+                (Kind::MailboxName(old), mailbox) => {
+                    let attributes = if mailbox.is_none() { "\\NonExistent" } else { "" };
+                    let mut line = [format!("* LIST ({}) \"{}\" ", attributes, DELIMITER).as_bytes(), &self.encode_name(&event.mailbox)].concat();
+                    if let Some(old) = old.as_ref().filter(|_| mailbox.is_some()) {
+                        line.extend_from_slice(b" (\"OLDNAME\" (");
+                        line.extend(self.encode_name(old));
+                        line.extend_from_slice(b"))");
+                    }
+                    line
+                }
+                (Kind::SubscriptionChange, mailbox) => {
+                    let attributes = if subscribed { "\\Subscribed" } else { "" };
+                    let attributes = if mailbox.is_none() { "\\NonExistent" } else { attributes };
+                    [format!("* LIST ({}) \"{}\" ", attributes, DELIMITER).as_bytes(), &self.encode_name(&event.mailbox)].concat()
+                }
+                (_, None) => continue,
+                (kind, Some(mailbox)) => {
+                    let flags = *kind == Kind::FlagChange;
+                    if reported.contains(&(mailbox.id, flags)) {
+                        continue;
+                    }
+                    reported.push((mailbox.id, flags));
+                    match self.status_line(&mailbox, &self.notify_items(flags)) {
+                        Ok(line) => line,
+                        Err(_) => continue,
+                    }
+                }
+            };
+            push(&mut self.untagged, line);
+        }
+    }
+
+    /// # NOTIFY 的 STATUS 数据项
+    /// ## 参数
+    /// - flags: 是否为标记的变化
+    fn notify_items(&self, flags: bool) -> Vec<String> {
+        let items: &[&str] = match (flags, self.condstore) {
+            (false, false) => &["MESSAGES", "UIDNEXT", "UIDVALIDITY"],
+            (false, true) => &["MESSAGES", "UIDNEXT", "UIDVALIDITY", "HIGHESTMODSEQ"],
+            (true, false) => &["UIDVALIDITY", "UNSEEN"],
+            (true, true) => &["UIDVALIDITY", "HIGHESTMODSEQ"],
+        };
+        items.iter().map(|item| item.to_string()).collect()
+    }
+}
+
+/// # 添加一行未标记的响应
+fn push(buffer: &mut Vec<u8>, line: impl AsRef<[u8]>) {
+    buffer.extend_from_slice(line.as_ref());
+    buffer.extend_from_slice(b"\r\n");
+}
+
+/// # 是否有某个标记，不区分大小写
+fn has_flag(flags: &[String], flag: &str) -> bool {
+    flags.iter().any(|item| item.eq_ignore_ascii_case(flag))
+}
+
+/// # 检查并规范化客户端提供的标记
+/// 系统标记统一大小写，不允许 \Recent 与未知的系统标记，去掉重复项。
+fn normalize_flags(flags: Vec<String>) -> Result<Vec<String>, Failure> {
+    let mut normalized: Vec<String> = Vec::new();
+    for flag in flags {
+        let flag = match flag.starts_with('\\') {
+            true => match SYSTEM_FLAGS.iter().find(|system| system.eq_ignore_ascii_case(&flag)) {
+                Some(system) => system.to_string(),
+                None => return Err(Failure::Bad("Invalid flag")),
+            },
+            false => flag,
+        };
+        if !has_flag(&normalized, &flag) {
+            normalized.push(flag);
+        }
+    }
+    Ok(normalized)
+}
+
+/// # SELECT 的 QRESYNC 参数
+/// (UIDVALIDITY 修改序列 [已知的 UID [(序号集合 UID 集合)]])，序号与 UID 的对应关系只检查语法。
+fn qresync_parameter(parser: &mut Parser) -> Result<Resync, Bad> {
+    parser.expect(b'(')?;
+    let uid_validity = parser.number()?;
+    parser.space()?;
+    let modseq = parser.number64()?;
+    let mut known = None;
+    if parser.eat(b' ') {
+        known = Some(parser.sequence_set()?);
+        if parser.eat(b' ') {
+            parser.expect(b'(')?;
+            parser.sequence_set()?;
+            parser.space()?;
+            parser.sequence_set()?;
+            parser.expect(b')')?;
+        }
+    }
+    parser.expect(b')')?;
+    Ok(Resync { uid_validity, modseq, known })
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::{self, Params};
+    use crate::storage::memory::MemoryStore;
+
+    /// 测试账号 bob@example.com，密码 secret，INBOX 中有三封邮件
+    fn prepare() -> (Arc<dyn MailStore>, Account) {
+        isolated(0)
+    }
+
+    /// # 与并行的其他测试编号不同的测试账号
+    /// 事件按账号 id 分发，检查推送内容的测试先添加 skip 个域名，使账号与邮箱的 id 不与其他测试相同。
+    fn isolated(skip: usize) -> (Arc<dyn MailStore>, Account) {
+        let store: Arc<dyn MailStore> = Arc::new(MemoryStore::new());
+        for index in 0..skip {
+            store.add_domain(&format!("skip{}.test", index)).unwrap();
+        }
+        store.add_domain("example.com").unwrap();
+        let params = Params { memory: 8, time: 1, parallelism: 1 };
+        let account = store.add_account("bob", "example.com", &password::hash("secret", &params).unwrap(), &password::scram("secret")).unwrap();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        for raw in MESSAGES {
+            store.append(inbox.id, raw.as_bytes(), &[], Utc::now()).unwrap();
+        }
+        (store, account)
+    }
+
+    const MESSAGES: [&str; 3] = [
+        "From: alice@remote.test\r\nSubject: hello\r\nContent-Type: multipart/mixed; boundary=b\r\n\r\n--b\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nfirst part\r\n--b\r\nContent-Type: text/html\r\n\r\n<p>second</p>\r\n--b--\r\n",
+        "From: carol@remote.test\r\nSubject: meeting\r\n\r\nsee you tomorrow\r\n",
+        "From: alice@remote.test\r\nSubject: report\r\n\r\nquarterly numbers\r\n",
+    ];
+
+    /// 已登录的会话
+    fn signed_in(store: &Arc<dyn MailStore>) -> Session {
+        let mut session = Session::new(1, "192.0.2.1".parse().unwrap(), Arc::clone(store), Tls::Active);
+        assert!(send(&mut session, "a0 LOGIN bob@example.com secret").starts_with("a0 OK"));
+        session
+    }
+
+    fn send(session: &mut Session, line: &str) -> String {
+        String::from_utf8(session.command(line.as_bytes()).data).unwrap()
+    }
+
+    /// # 按顺序发送命令，检查完整的响应
+    /// 响应中的 UIDVALIDITY 替换为 V。
+    fn script(session: &mut Session, validity: u32, steps: &[(&str, &str)]) {
+        for (line, expected) in steps {
+            let response = send(session, line).replace(&validity.to_string(), "V");
+            assert_eq!(response, *expected, "C: {}", line);
+        }
+    }
+
+    #[test]
+    fn login() {
+        let (store, _) = prepare();
+        let mut session = Session::new(1, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Available);
+        assert!(String::from_utf8(session.greeting()).unwrap().contains(" STARTTLS LOGINDISABLED]"));
+        assert_eq!(send(&mut session, "a1 LOGIN bob@example.com secret"), "a1 NO [PRIVACYREQUIRED] Use STARTTLS first\r\n");
+
+        let mut session = Session::new(2, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Active);
+        script(
+            &mut session,
+            0,
+            &[
+                ("a1 SELECT INBOX", "a1 BAD Not authenticated\r\n"),
+                ("a2 LOGIN bob@example.com wrong", "a2 NO [AUTHENTICATIONFAILED] Authentication failed\r\n"),
+                ("a3 LOGIN nobody@example.com secret", "a3 NO [AUTHENTICATIONFAILED] Authentication failed\r\n"),
+            ],
+        );
+        let response = send(&mut session, "a4 LOGIN \"BOB@example.com\" {6+}\r\nsecret");
+        assert!(response.starts_with("a4 OK [CAPABILITY IMAP4rev1 IMAP4rev2 ") && response.contains(" MOVE ") && response.ends_with("] Logged in\r\n"));
+        script(&mut session, 0, &[("a5 LOGIN bob@example.com secret", "a5 BAD Already authenticated\r\n")]);
+    }
+
+    #[test]
+    fn select_and_fetch() {
+        let (store, account) = prepare();
+        let validity = store.mailbox(account.id, "INBOX").unwrap().unwrap().uid_validity;
+        let mut session = signed_in(&store);
+        script(
+            &mut session,
+            validity,
+            &[
+                ("a1 FETCH 1 FLAGS", "a1 BAD No mailbox selected\r\n"),
+                ("a2 SELECT Nowhere", "a2 NO [NONEXISTENT] Mailbox does not exist\r\n"),
+                (
+                    "a3 SELECT INBOX",
+                    "* 3 EXISTS\r\n\
+                     * 0 RECENT\r\n\
+                     * FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+                     * OK [PERMANENTFLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft \\*)] Flags permitted\r\n\
+                     * OK [UNSEEN 1] First unseen\r\n\
+                     * OK [UIDVALIDITY V] UIDs valid\r\n\
+                     * OK [UIDNEXT 4] Predicted next UID\r\n\
+                     a3 OK [READ-WRITE] SELECT completed\r\n",
+                ),
+                (
+                    "a4 FETCH 1 BODYSTRUCTURE",
+                    "* 1 FETCH (BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"utf-8\") NIL NIL \"7BIT\" 10 0 NIL NIL NIL NIL)\
+                     (\"TEXT\" \"HTML\" NIL NIL NIL \"7BIT\" 13 0 NIL NIL NIL NIL) \"MIXED\" (\"BOUNDARY\" \"b\") NIL NIL NIL))\r\n\
+                     a4 OK FETCH completed\r\n",
+                ),
+                // BODY[] 设置 \Seen，BODY.PEEK[] 不设置
+                ("a5 FETCH 2 BODY.PEEK[]<0.10>", "* 2 FETCH (BODY[]<0> \"From: caro\")\r\na5 OK FETCH completed\r\n"),
+                ("a6 FETCH 1 BODY[]<0.10>", "* 1 FETCH (BODY[]<0> \"From: alic\" FLAGS (\\Seen))\r\na6 OK FETCH completed\r\n"),
+                (
+                    "a7 FETCH 1 (FLAGS BODY.PEEK[1]<6.4> BODY.PEEK[2.MIME] BODY.PEEK[1]<100.5>)",
+                    "* 1 FETCH (FLAGS (\\Seen) BODY[1]<6> \"part\" BODY[2.MIME] {27}\r\nContent-Type: text/html\r\n\r\n BODY[1]<100> \"\")\r\n\
+                     a7 OK FETCH completed\r\n",
+                ),
+                ("a8 FETCH 4 FLAGS", "a8 OK FETCH completed\r\n"),
+                ("a9 EXAMINE INBOX", "* OK [CLOSED] Previous mailbox is now closed\r\n* 3 EXISTS\r\n* 0 RECENT\r\n* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+                  * OK [PERMANENTFLAGS ()] Read-only mailbox\r\n* OK [UNSEEN 2] First unseen\r\n\
+                  * OK [UIDVALIDITY V] UIDs valid\r\n* OK [UIDNEXT 4] Predicted next UID\r\na9 OK [READ-ONLY] EXAMINE completed\r\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn original_bytes() {
+        // 声明为 gb2312 的 Big5 正文与未编码的 GBK 邮件头：解码时纠正字符集，BODY[] 与 BINARY[] 仍返回原文
+        let (store, account) = prepare();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        let (subject, _, _) = encoding_rs::GBK.encode("会议通知");
+        let (body, _, _) = encoding_rs::BIG5.encode("下週一上午九點在三樓會議室召開季度會議");
+        let header = [&b"From: alice@remote.test\r\nSubject: "[..], &subject, b"\r\nContent-Type: text/plain; charset=gb2312\r\n\r\n"].concat();
+        let raw = [&header[..], &body, b"\r\n"].concat();
+        store.append(inbox.id, &raw, &[], Utc::now()).unwrap();
+
+        let mut session = signed_in(&store);
+        send(&mut session, "a1 SELECT INBOX");
+        let fetched = |session: &mut Session, line: &str, section: &str, data: &[u8]| {
+            let expected = [format!("* 4 FETCH ({} {{{}}}\r\n", section, data.len()).as_bytes(), data, b")\r\na OK FETCH completed\r\n"].concat();
+            assert_eq!(session.command(line.as_bytes()).data, expected, "C: {}", line);
+        };
+        fetched(&mut session, "a FETCH 4 BODY.PEEK[]", "BODY[]", &raw);
+        fetched(&mut session, "a FETCH 4 BODY.PEEK[TEXT]", "BODY[TEXT]", &raw[header.len()..]);
+        fetched(&mut session, "a FETCH 4 BINARY.PEEK[1]", "BINARY[1]", &raw[header.len()..]);
+    }
+
+    #[test]
+    fn search() {
+        let (store, _) = prepare();
+        let mut session = signed_in(&store);
+        send(&mut session, "a1 SELECT INBOX");
+        script(
+            &mut session,
+            0,
+            &[
+                ("a2 STORE 1 +FLAGS.SILENT (\\Seen)", "a2 OK STORE completed\r\n"),
+                ("a3 SEARCH SUBJECT hello", "* SEARCH 1\r\na3 OK SEARCH completed\r\n"),
+                ("a4 SEARCH UNSEEN FROM alice", "* SEARCH 3\r\na4 OK SEARCH completed\r\n"),
+                ("a5 SEARCH OR SUBJECT meeting BODY quarterly", "* SEARCH 2 3\r\na5 OK SEARCH completed\r\n"),
+                ("a6 SEARCH NOT FROM alice", "* SEARCH 2\r\na6 OK SEARCH completed\r\n"),
+                ("a7 SEARCH TEXT nothing", "* SEARCH\r\na7 OK SEARCH completed\r\n"),
+                ("a8 SEARCH BOGUS", "a8 BAD Unknown search key\r\n"),
+                ("a9 SEARCH RETURN (MIN MAX COUNT) FROM alice", "* ESEARCH (TAG \"a9\") MIN 1 MAX 3 COUNT 2\r\na9 OK SEARCH completed\r\n"),
+                ("b1 UID SEARCH 2:* SEEN", "* SEARCH\r\nb1 OK SEARCH completed\r\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn copy_and_move() {
+        let (store, account) = prepare();
+        let mut session = signed_in(&store);
+        send(&mut session, "a1 SELECT INBOX");
+        assert_eq!(send(&mut session, "a2 COPY 1 Archive"), "a2 NO [TRYCREATE] Mailbox does not exist\r\n");
+        assert_eq!(send(&mut session, "a3 CREATE Archive"), "a3 OK CREATE completed\r\n");
+        // COPYUID 中为目标邮箱的 UIDVALIDITY
+        let validity = store.mailbox(account.id, "Archive").unwrap().unwrap().uid_validity;
+        script(
+            &mut session,
+            validity,
+            &[
+                ("a4 COPY 1 Archive", "a4 OK [COPYUID V 1 1] COPY completed\r\n"),
+                ("a5 MOVE 2 Archive", "* OK [COPYUID V 2 2] Moved\r\n* 2 EXPUNGE\r\na5 OK MOVE completed\r\n"),
+                ("a6 STATUS Archive (MESSAGES UIDNEXT)", "* STATUS \"Archive\" (MESSAGES 2 UIDNEXT 3)\r\na6 OK STATUS completed\r\n"),
+                ("a7 FETCH 1:* (UID)", "* 1 FETCH (UID 1)\r\n* 2 FETCH (UID 3)\r\na7 OK FETCH completed\r\n"),
+                ("a8 MOVE 5 Archive", "a8 OK MOVE completed, no messages\r\n"),
+            ],
+        );
+        // 邮件内容与原邮件相同
+        let archive = store.mailbox(account.id, "Archive").unwrap().unwrap();
+        let raws: Vec<Vec<u8>> = store.messages(archive.id).unwrap().iter().map(|message| store.raw(message.id).unwrap().unwrap()).collect();
+        assert_eq!(raws, [MESSAGES[0].as_bytes(), MESSAGES[1].as_bytes()]);
+    }
+
+    #[test]
+    fn uid_commands() {
+        let (store, account) = prepare();
+        let mut session = signed_in(&store);
+        send(&mut session, "a1 SELECT INBOX");
+        send(&mut session, "a2 CREATE Archive");
+        let validity = store.mailbox(account.id, "Archive").unwrap().unwrap().uid_validity;
+        script(
+            &mut session,
+            validity,
+            &[
+                ("a3 UID STORE 2 +FLAGS (\\Deleted)", "* 2 FETCH (UID 2 FLAGS (\\Deleted))\r\na3 OK STORE completed\r\n"),
+                ("a4 UID FETCH 2:* (UID FLAGS)", "* 2 FETCH (UID 2 FLAGS (\\Deleted))\r\n* 3 FETCH (UID 3 FLAGS ())\r\na4 OK FETCH completed\r\n"),
+                ("a5 UID EXPUNGE 3", "a5 OK EXPUNGE completed\r\n"),
+                ("a6 UID EXPUNGE 1:*", "* 2 EXPUNGE\r\na6 OK EXPUNGE completed\r\n"),
+                ("a7 UID FETCH 1:* (UID)", "* 1 FETCH (UID 1)\r\n* 2 FETCH (UID 3)\r\na7 OK FETCH completed\r\n"),
+                ("a8 UID COPY 99 Archive", "a8 OK COPY completed, no messages\r\n"),
+                ("a9 UID COPY 1,3 Archive", "a9 OK [COPYUID V 1,3 1:2] COPY completed\r\n"),
+                ("b2 UID MOVE 3 Archive", "* OK [COPYUID V 3 3] Moved\r\n* 2 EXPUNGE\r\nb2 OK MOVE completed\r\n"),
+                ("b3 UID SEARCH ALL", "* SEARCH 1\r\nb3 OK SEARCH completed\r\n"),
+                ("b4 UID FETCH 3 FLAGS", "b4 OK FETCH completed\r\n"),
+            ],
+        );
+    }
+
+    /// # 收到的推送
+    fn pushed(session: &mut Session) -> String {
+        String::from_utf8(session.poll()).unwrap()
+    }
+
+    #[test]
+    fn idle() {
+        let (store, account) = isolated(100);
+        let mut session = signed_in(&store);
+        let mut other = signed_in(&store);
+        send(&mut session, "a1 SELECT INBOX");
+        send(&mut other, "b1 SELECT INBOX");
+
+        let response = session.command(b"a2 IDLE");
+        assert_eq!((response.data.as_slice(), response.next), (&b"+ idling\r\n"[..], Next::Idle));
+        assert!(pushed(&mut session).is_empty());
+        assert_eq!(send(&mut other, "b2 STORE 2 +FLAGS.SILENT (\\Flagged)"), "b2 OK STORE completed\r\n");
+        assert_eq!(pushed(&mut session), "* 2 FETCH (UID 2 FLAGS (\\Flagged))\r\n");
+        // IDLE 期间可以发送 EXPUNGE
+        send(&mut other, "b3 STORE 1 +FLAGS.SILENT (\\Deleted)");
+        send(&mut other, "b4 EXPUNGE");
+        crate::smtp::deliver(store.as_ref(), &account, b"From: dave@remote.test\r\nSubject: new\r\n\r\nhi\r\n").unwrap();
+        assert_eq!(pushed(&mut session), "* 1 EXPUNGE\r\n* 3 EXISTS\r\n");
+        assert!(pushed(&mut session).is_empty());
+        assert_eq!(session.done(b"DONE").data, b"a2 OK IDLE terminated\r\n");
+        assert_eq!(session.done(b"DONE").data, b"* BAD Not idling\r\n");
+
+        // 自己造成的变化不推送，IDLE 开始时先告知之前的变化
+        send(&mut session, "a3 STORE 1 +FLAGS.SILENT (\\Seen)");
+        send(&mut other, "b5 NOOP");
+        send(&mut other, "b6 STORE 3 +FLAGS.SILENT (\\Seen)");
+        assert_eq!(send(&mut session, "a4 IDLE"), "* 3 FETCH (UID 4 FLAGS (\\Seen))\r\n+ idling\r\n");
+        assert!(pushed(&mut session).is_empty());
+        assert_eq!(session.done(b"STOP").data, b"a4 BAD Expected DONE\r\n");
+
+        let mut anonymous = Session::new(3, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Active);
+        assert_eq!(send(&mut anonymous, "c1 IDLE"), "c1 BAD Not authenticated\r\n");
+    }
+
+    #[test]
+    fn condstore() {
+        let (store, account) = prepare();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        let before: Vec<u64> = store.messages(inbox.id).unwrap().iter().map(|message| message.modseq).collect();
+        let modseq = |index: usize| store.messages(inbox.id).unwrap()[index].modseq;
+        let mut session = signed_in(&store);
+        let mut other = signed_in(&store);
+        send(&mut other, "b1 SELECT INBOX");
+
+        let response = send(&mut session, "a1 SELECT INBOX (CONDSTORE)");
+        assert!(response.ends_with(&format!("* OK [HIGHESTMODSEQ {}] Highest\r\na1 OK [READ-WRITE] SELECT completed\r\n", inbox.highest_modseq)));
+        let response = send(&mut session, "a2 STORE 2 +FLAGS (\\Flagged)");
+        assert_eq!(response, format!("* 2 FETCH (FLAGS (\\Flagged) MODSEQ ({}))\r\na2 OK STORE completed\r\n", modseq(1)));
+        assert!(modseq(1) > before[2]);
+
+        // 只返回修改序列大于 CHANGEDSINCE 的邮件
+        let response = send(&mut session, &format!("a3 FETCH 1:* (FLAGS) (CHANGEDSINCE {})", before[2]));
+        assert_eq!(response, format!("* 2 FETCH (FLAGS (\\Flagged) MODSEQ ({}))\r\na3 OK FETCH completed\r\n", modseq(1)));
+        let response = send(&mut session, &format!("a4 UID FETCH 1:* FLAGS (CHANGEDSINCE {})", modseq(1)));
+        assert_eq!(response, "a4 OK FETCH completed\r\n");
+
+        // 修改序列大于 UNCHANGEDSINCE 的邮件不修改，在 MODIFIED 中列出
+        let response = send(&mut session, &format!("a5 STORE 1:2 (UNCHANGEDSINCE {}) +FLAGS (\\Seen)", before[0]));
+        assert_eq!(response, format!("* 1 FETCH (FLAGS (\\Seen) MODSEQ ({}))\r\na5 OK [MODIFIED 2] Conditional STORE failed\r\n", modseq(0)));
+        let response = send(&mut session, &format!("a6 UID STORE 3 (UNCHANGEDSINCE {}) FLAGS.SILENT (\\Draft)", before[2]));
+        assert_eq!(response, format!("* 3 FETCH (UID 3 MODSEQ ({}))\r\na6 OK STORE completed\r\n", modseq(2)));
+        assert_eq!(store.messages(inbox.id).unwrap()[1].flags, ["\\Flagged"]);
+
+        // 其他连接的修改附带修改序列
+        send(&mut other, "b2 NOOP");
+        send(&mut other, "b3 STORE 2 -FLAGS.SILENT (\\Flagged)");
+        assert_eq!(send(&mut session, "a7 NOOP"), format!("* 2 FETCH (UID 2 FLAGS () MODSEQ ({}))\r\na7 OK NOOP completed\r\n", modseq(1)));
+    }
+
+    #[test]
+    fn qresync() {
+        let (store, account) = prepare();
+        let mut session = signed_in(&store);
+        let mut other = signed_in(&store);
+        script(
+            &mut session,
+            0,
+            &[
+                ("a1 SELECT INBOX (QRESYNC (1 1))", "a1 BAD QRESYNC is not enabled\r\n"),
+                ("a2 ENABLE QRESYNC", "* ENABLED CONDSTORE QRESYNC\r\na2 OK ENABLE completed\r\n"),
+                ("a3 ENABLE CONDSTORE QRESYNC", "* ENABLED\r\na3 OK ENABLE completed\r\n"),
+            ],
+        );
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        let (validity, highest) = (inbox.uid_validity, inbox.highest_modseq);
+
+        // 客户端断开后，其他连接修改标记、删除邮件，并投递新邮件
+        send(&mut other, "b1 SELECT INBOX");
+        send(&mut other, "b2 STORE 2 +FLAGS.SILENT (\\Seen)");
+        send(&mut other, "b3 STORE 1 +FLAGS.SILENT (\\Deleted)");
+        send(&mut other, "b4 EXPUNGE");
+        crate::smtp::deliver(store.as_ref(), &account, b"From: dave@remote.test\r\nSubject: new\r\n\r\nhi\r\n").unwrap();
+        assert_eq!(store.vanished(inbox.id, highest).unwrap(), [1]);
+        let messages = store.messages(inbox.id).unwrap();
+        assert_eq!(messages.iter().map(|message| message.uid).collect::<Vec<_>>(), [2, 3, 4]);
+        let (seen, new) = (messages[0].modseq, messages[2].modseq);
+        assert!(seen > highest && new > highest && messages[1].modseq <= highest);
+        let highest = store.mailbox(account.id, "INBOX").unwrap().unwrap().highest_modseq;
+        let before = inbox.highest_modseq;
+
+        let response = send(&mut session, &format!("a4 SELECT INBOX (QRESYNC ({} {}))", validity, before));
+        let expected = format!(
+            "* OK [HIGHESTMODSEQ {highest}] Highest\r\n\
+             * VANISHED (EARLIER) 1\r\n\
+             * 1 FETCH (UID 2 FLAGS (\\Seen) MODSEQ ({seen}))\r\n\
+             * 3 FETCH (UID 4 FLAGS () MODSEQ ({new}))\r\n\
+             a4 OK [READ-WRITE] SELECT completed\r\n"
+        );
+        assert!(response.starts_with("* 3 EXISTS\r\n") && response.ends_with(&expected), "{}", response);
+
+        // 只告知客户端已知的 UID
+        let response = send(&mut session, &format!("a5 SELECT INBOX (QRESYNC ({} {} 3:4 (1:2 3:4)))", validity, before));
+        let expected = format!("* OK [HIGHESTMODSEQ {highest}] Highest\r\n* 3 FETCH (UID 4 FLAGS () MODSEQ ({new}))\r\na5 OK [READ-WRITE] SELECT completed\r\n");
+        assert!(response.starts_with("* OK [CLOSED]") && response.ends_with(&expected), "{}", response);
+
+        // UIDVALIDITY 不同时缓存无效，不告知变化
+        let response = send(&mut session, &format!("a6 SELECT INBOX (QRESYNC ({} {}))", validity + 1, before));
+        assert!(response.ends_with(&format!("* OK [HIGHESTMODSEQ {highest}] Highest\r\na6 OK [READ-WRITE] SELECT completed\r\n")), "{}", response);
+
+        let response = send(&mut session, &format!("a7 UID FETCH 1:* FLAGS (CHANGEDSINCE {} VANISHED)", before));
+        let expected = format!("* VANISHED (EARLIER) 1\r\n* 1 FETCH (UID 2 FLAGS (\\Seen) MODSEQ ({seen}))\r\n* 3 FETCH (UID 4 FLAGS () MODSEQ ({new}))\r\na7 OK FETCH completed\r\n");
+        assert_eq!(response, expected);
+        let response = send(&mut session, &format!("a8 FETCH 1:* FLAGS (CHANGEDSINCE {} VANISHED)", before));
+        assert_eq!(response, "a8 BAD VANISHED requires UID FETCH with CHANGEDSINCE and QRESYNC enabled\r\n");
+
+        // 启用 QRESYNC 后以 VANISHED 代替 EXPUNGE
+        send(&mut other, "b5 NOOP");
+        send(&mut other, "b6 STORE 2:3 +FLAGS.SILENT (\\Deleted)");
+        send(&mut other, "b7 EXPUNGE");
+        assert_eq!(send(&mut session, "a9 NOOP"), "* VANISHED 3:4\r\na9 OK NOOP completed\r\n");
+    }
+
+    #[test]
+    fn notify() {
+        let (store, account) = isolated(200);
+        let mut session = signed_in(&store);
+        let mut other = signed_in(&store);
+        send(&mut session, "a1 CREATE Archive");
+        send(&mut session, "a2 SELECT INBOX");
+        send(&mut other, "b1 SELECT INBOX");
+        let validity = store.mailbox(account.id, "Archive").unwrap().unwrap().uid_validity;
+        script(
+            &mut session,
+            validity,
+            &[
+                ("a3 NOTIFY SET (SELECTED (MessageNew))", "a3 BAD MessageNew and MessageExpunge must be specified together\r\n"),
+                (
+                    "a4 NOTIFY SET (PERSONAL (AnnotationChange))",
+                    "a4 NO [BADEVENT (MessageNew MessageExpunge FlagChange MailboxName SubscriptionChange)] Unsupported event AnnotationChange\r\n",
+                ),
+                (
+                    "a5 NOTIFY SET STATUS (SELECTED (MessageNew (UID FLAGS) MessageExpunge)) (PERSONAL (MessageNew MessageExpunge MailboxName))",
+                    "* STATUS \"Archive\" (MESSAGES 0 UIDNEXT 1 UIDVALIDITY V)\r\na5 OK NOTIFY completed\r\n",
+                ),
+            ],
+        );
+        assert!(session.pushing());
+        assert!(pushed(&mut session).is_empty());
+
+        // 其他邮箱以 STATUS 告知，已选择的邮箱附带 FETCH 数据项
+        send(&mut other, "b2 COPY 2 Archive");
+        assert_eq!(pushed(&mut session).replace(&validity.to_string(), "V"), "* STATUS \"Archive\" (MESSAGES 1 UIDNEXT 2 UIDVALIDITY V)\r\n");
+        crate::smtp::deliver(store.as_ref(), &account, b"From: dave@remote.test\r\nSubject: new\r\n\r\nhi\r\n").unwrap();
+        assert_eq!(pushed(&mut session), "* 4 EXISTS\r\n* 4 FETCH (UID 4 FLAGS ())\r\n");
+        send(&mut other, "b3 STORE 1 +FLAGS.SILENT (\\Deleted)");
+        send(&mut other, "b4 EXPUNGE");
+        assert_eq!(pushed(&mut session), "* 1 EXPUNGE\r\n");
+
+        // 邮箱的变化以 LIST 告知
+        send(&mut other, "b5 CREATE Later");
+        assert_eq!(pushed(&mut session), "* LIST () \"/\" \"Later\"\r\n");
+        send(&mut other, "b6 RENAME Later Sooner");
+        assert_eq!(pushed(&mut session), "* LIST () \"/\" \"Sooner\" (\"OLDNAME\" (\"Later\"))\r\n");
+        send(&mut other, "b7 DELETE Sooner");
+        assert_eq!(pushed(&mut session), "* LIST (\\NonExistent) \"/\" \"Sooner\"\r\n");
+        // 已选择邮箱的标记变化由 FETCH 告知，其他邮箱没有订阅 FlagChange
+        send(&mut other, "b8 STORE 1 +FLAGS.SILENT (\\Seen)");
+        assert_eq!(pushed(&mut session), "* 1 FETCH (UID 2 FLAGS (\\Seen))\r\n");
+        send(&mut other, "b9 SELECT Archive");
+        send(&mut other, "c1 STORE 1 +FLAGS.SILENT (\\Seen)");
+        assert!(pushed(&mut session).is_empty());
+
+        assert_eq!(send(&mut session, "a6 NOTIFY NONE"), "a6 OK NOTIFY completed\r\n");
+        assert!(!session.pushing());
+        send(&mut other, "c2 CREATE Ignored");
+        assert!(pushed(&mut session).is_empty());
+    }
+}
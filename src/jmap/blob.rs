@@ -0,0 +1,129 @@
+/* JMAP blob */
+/*
+# blob
+## 结构
+B<邮件>            邮件原文
+B<邮件>-<部分>     邮件的一部分，部分编号以 _ 分隔，例如 B12-1_2 为邮件 #12 的 1.2 部分，内容已解码
+U<摘要>            上传的内容，摘要为 SHA-256 的前 32 个十六进制字符
+## 用法
+let id = blob::upload(account_id, content_type, data)?;
+let (content_type, data) = blob::read(store, account_id, &id)?.ok_or(...)?;
+## 说明
+上传的内容只保存在内存中，RETENTION 之后删除，每个账号最多保存 MAX_ACCOUNT 字节。
+邮件的 blob 只能由邮件所属的账号读取。
+ */
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::mime;
+use crate::storage::{MailStore, StorageError};
+use crate::utils::to_hex;
+
+/// 上传的内容保存的时间
+pub const RETENTION: Duration = Duration::from_secs(3600);
+
+/// 每个账号上传的内容的总大小上限
+pub const MAX_ACCOUNT: usize = 64 * 1024 * 1024;
+
+/// 上传的内容
+struct Upload {
+    account_id: i64,
+    id: String,
+    content_type: String,
+    data: Vec<u8>,
+    created: Instant,
+}
+
+static UPLOADS: Mutex<Vec<Upload>> = Mutex::new(Vec::new());
+
+/// blob id 指向的内容
+pub enum Blob {
+    /// 邮件 id 与部分编号，编号为空时为邮件原文
+    Message(i64, Vec<u32>),
+    Upload(String),
+}
+
+/// # 邮件或其中一部分的 blob id
+/// ## 参数
+/// - message_id: 邮件 id
+/// - path: 部分编号，为空时为邮件原文
+pub fn message_blob(message_id: i64, path: &[u32]) -> String {
+    match path.is_empty() {
+        true => super::id('B', message_id),
+        false => format!("B{}-{}", message_id, path.iter().map(u32::to_string).collect::<Vec<_>>().join("_")),
+    }
+}
+
+/// # 解析 blob id
+/// ## 返回值
+/// - Option<Blob>，格式错误时为 None
+pub fn parse(id: &str) -> Option<Blob> {
+    if id.starts_with('U') {
+        return Some(Blob::Upload(id.to_string()));
+    }
+    let (message, path) = match id.split_once('-') {
+        Some((message, path)) => (message, path.split('_').map(|number| number.parse().ok()).collect::<Option<Vec<u32>>>()?),
+        None => (id, Vec::new()),
+    };
+    Some(Blob::Message(super::parse_id('B', message)?, path))
+}
+
+/// # 保存上传的内容
+/// ## 参数
+/// - account_id: 账号 id
+/// - content_type: 客户端提供的 Content-Type
+/// - data: 内容
+/// ## 返回值
+/// - Option<String>，blob id，超出账号的上限时为 None
+pub fn upload(account_id: i64, content_type: &str, data: Vec<u8>) -> Option<String> {
+    let id = format!("U{}", &to_hex(&Sha256::digest(&data))[..32]);
+    let mut uploads = UPLOADS.lock().unwrap_or_else(|e| e.into_inner());
+    uploads.retain(|upload| upload.created.elapsed() < RETENTION);
+    if uploads.iter().any(|upload| upload.account_id == account_id && upload.id == id) {
+        return Some(id);
+    }
+    let used: usize = uploads.iter().filter(|upload| upload.account_id == account_id).map(|upload| upload.data.len()).sum();
+    if used + data.len() > MAX_ACCOUNT {
+        return None;
+    }
+    uploads.push(Upload { account_id, id: id.clone(), content_type: content_type.to_string(), data, created: Instant::now() });
+    Some(id)
+}
+
+/// # 读取 blob
+/// ## 参数
+/// - store: 存储
+/// - account_id: 账号 id
+/// - id: blob id
+/// ## 返回值
+/// - Result<Option<(String, Vec<u8>)>, StorageError>，(Content-Type, 内容)，不存在或不属于该账号时为 None
+pub fn read(store: &dyn MailStore, account_id: i64, id: &str) -> Result<Option<(String, Vec<u8>)>, StorageError> {
+    match parse(id) {
+        None => Ok(None),
+        Some(Blob::Upload(id)) => {
+            let uploads = UPLOADS.lock().unwrap_or_else(|e| e.into_inner());
+            Ok(uploads
+                .iter()
+                .find(|upload| upload.account_id == account_id && upload.id == id && upload.created.elapsed() < RETENTION)
+                .map(|upload| (upload.content_type.clone(), upload.data.clone())))
+        }
+        Some(Blob::Message(message_id, path)) => {
+            let mut owned = false;
+            for mailbox in store.mailboxes(account_id)? {
+                if store.messages(mailbox.id)?.iter().any(|message| message.id == message_id) {
+                    owned = true;
+                    break;
+                }
+            }
+            let Some(raw) = owned.then(|| store.raw(message_id)).transpose()?.flatten() else {
+                return Ok(None);
+            };
+            if path.is_empty() {
+                return Ok(Some((String::from("message/rfc822"), raw)));
+            }
+            let message = mime::parse(&raw);
+            Ok(message.find(&path).map(|part| (format!("{}/{}", part.media_type, part.subtype), part.decode().into_owned())))
+        }
+    }
+}
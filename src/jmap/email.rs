@@ -0,0 +1,699 @@
+/* JMAP Email */
+/*
+# Email、Thread 与 SearchSnippet 的方法
+## 说明
+Email 的 id 为 E<邮件>，每封邮件单独作为一个 Thread（T<邮件>），Thread 的 emailIds 只有这封邮件。
+每封邮件只属于一个邮箱，mailboxIds 必须恰好包含一个邮箱，修改 mailboxIds 即为移动邮件，id 不变。
+关键字与 IMAP 标志对应：$seen、$flagged、$answered 与 $draft 对应系统标志，其他关键字原样保存（小写）；
+\Deleted 等其他系统标志不作为关键字返回，修改 keywords 时保留。
+Email/query 的文本条件不区分大小写，在解码后的邮件头与文本部分中按子串匹配；queryChanges 不支持。
+SearchSnippet 不生成高亮的摘要，subject 与 preview 为 null。
+ */
+use chrono::Utc;
+use serde_json::{Map, Value, json};
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
+
+use super::blob::{self, Blob};
+use super::message::{self, BodyOptions};
+use super::request::{self, Context, MethodError, Result, SetResponse, set_error};
+use super::{MAX_OBJECTS_GET, parse_id, state};
+use crate::event::{self, Event, Kind};
+use crate::storage::{Mailbox, MessageInfo, StorageError};
+use crate::utils::parse_rfc3339;
+
+/// Email/get 默认返回的属性（RFC 8621 4.2）
+const DEFAULT_PROPERTIES: [&str; 24] = [
+    "id",
+    "blobId",
+    "threadId",
+    "mailboxIds",
+    "keywords",
+    "size",
+    "receivedAt",
+    "messageId",
+    "inReplyTo",
+    "references",
+    "sender",
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "replyTo",
+    "subject",
+    "sentAt",
+    "hasAttachment",
+    "preview",
+    "bodyValues",
+    "textBody",
+    "htmlBody",
+    "attachments",
+];
+
+/// 与系统标志对应的关键字
+const SYSTEM_KEYWORDS: [(&str, &str); 4] = [("$seen", "\\Seen"), ("$flagged", "\\Flagged"), ("$answered", "\\Answered"), ("$draft", "\\Draft")];
+
+/// # 标志对应的关键字
+/// ## 返回值
+/// - Option<String>，没有对应关键字的系统标志为 None
+fn keyword(flag: &str) -> Option<String> {
+    if let Some((keyword, _)) = SYSTEM_KEYWORDS.iter().find(|(_, system)| system.eq_ignore_ascii_case(flag)) {
+        return Some(keyword.to_string());
+    }
+    (!flag.starts_with('\\')).then(|| flag.to_lowercase())
+}
+
+/// # 关键字对应的标志
+/// ## 返回值
+/// - Option<String>，关键字无效时为 None（RFC 8621 4.1.1）
+fn flag(keyword: &str) -> Option<String> {
+    let keyword = keyword.to_lowercase();
+    if let Some((_, system)) = SYSTEM_KEYWORDS.iter().find(|(name, _)| *name == keyword) {
+        return Some(system.to_string());
+    }
+    let valid = !keyword.is_empty() && keyword.len() <= 255 && keyword.bytes().all(|byte| (0x21..=0x7e).contains(&byte) && !b"(){]%*\"\\".contains(&byte));
+    valid.then_some(keyword)
+}
+
+/// # 邮件的 keywords 对象
+fn keywords(message: &MessageInfo) -> Value {
+    Value::Object(message.flags.iter().filter_map(|flag| keyword(flag)).map(|keyword| (keyword, Value::Bool(true))).collect())
+}
+
+/// # 解析 keywords 对象
+/// ## 返回值
+/// - Option<BTreeSet<String>>，格式错误时为 None
+fn parse_keywords(value: Option<&Value>) -> Option<BTreeSet<String>> {
+    match value {
+        None | Some(Value::Null) => Some(BTreeSet::new()),
+        Some(Value::Object(map)) => map.iter().map(|(keyword, value)| (value == &Value::Bool(true)).then(|| flag(keyword)).flatten()).collect(),
+        Some(_) => None,
+    }
+}
+
+/// # 按关键字生成标志
+/// ## 参数
+/// - flags: 关键字对应的标志
+/// - current: 邮件当前的标志，保留其中没有对应关键字的系统标志
+fn merge_flags(flags: &BTreeSet<String>, current: &[String]) -> Vec<String> {
+    let mut merged: Vec<String> = current.iter().filter(|flag| keyword(flag).is_none()).cloned().collect();
+    merged.extend(flags.iter().cloned());
+    merged
+}
+
+/// # 账号的所有邮箱与邮件
+fn load(context: &Context) -> Result<(Vec<Mailbox>, Vec<MessageInfo>)> {
+    let mailboxes = context.mailboxes()?;
+    let messages = context.messages(&mailboxes)?;
+    Ok((mailboxes, messages))
+}
+
+/// # 读取邮件原文
+fn raw(context: &Context, message_id: i64) -> Result<Vec<u8>> {
+    context.store.raw(message_id)?.ok_or_else(|| MethodError::describe("serverFail", "Message content missing"))
+}
+
+/// # 邮件的元数据属性
+fn metadata(message: &MessageInfo) -> Map<String, Value> {
+    let value = json!({
+        "id": super::id('E', message.id),
+        "blobId": blob::message_blob(message.id, &[]),
+        "threadId": super::id('T', message.id),
+        "mailboxIds": { super::id('M', message.mailbox_id): true },
+        "keywords": keywords(message),
+        "size": message.size,
+        "receivedAt": message::utc_date(message.internal_date),
+    });
+    value.as_object().cloned().unwrap_or_default()
+}
+
+/// # 正文参数
+/// ## 参数
+/// - arguments: Email/get 或 Email/parse 的参数
+fn body_options(arguments: &Map<String, Value>) -> Result<BodyOptions> {
+    let properties = request::strings(arguments, "bodyProperties")?.unwrap_or_else(|| message::BODY_PROPERTIES.iter().map(|property| property.to_string()).collect());
+    if let Some(unknown) = properties.iter().find(|property| !message::is_body_property(property)) {
+        return Err(request::invalid(format!("Unknown body property {}", unknown)));
+    }
+    let max_bytes = match request::integer(arguments, "maxBodyValueBytes")? {
+        Some(max) if max <= 0 => return Err(request::invalid("maxBodyValueBytes must be positive")),
+        max => max.unwrap_or(0) as usize,
+    };
+    Ok(BodyOptions {
+        properties,
+        fetch_text: request::boolean(arguments, "fetchTextBodyValues", false)?,
+        fetch_html: request::boolean(arguments, "fetchHTMLBodyValues", false)?,
+        fetch_all: request::boolean(arguments, "fetchAllBodyValues", false)?,
+        max_bytes,
+    })
+}
+
+/// # 请求的属性
+/// ## 参数
+/// - defaults: 默认的属性
+fn properties(arguments: &Map<String, Value>, defaults: &[&str]) -> Result<Vec<String>> {
+    let properties = request::strings(arguments, "properties")?.unwrap_or_else(|| defaults.iter().map(|property| property.to_string()).collect());
+    if let Some(unknown) = properties.iter().find(|property| !message::is_property(property)) {
+        return Err(request::invalid(format!("Unknown property {}", unknown)));
+    }
+    Ok(properties)
+}
+
+/// # Email/get
+pub fn get(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::get_ids(arguments, context, MAX_OBJECTS_GET)?;
+    let properties = properties(arguments, &DEFAULT_PROPERTIES)?;
+    let options = body_options(arguments)?;
+    let content = properties.iter().any(|property| message::CONTENT_PROPERTIES.contains(&property.as_str()) || property.starts_with("header:"));
+
+    let (mailboxes, messages) = load(context)?;
+    let (found, not_found): (Vec<Option<&MessageInfo>>, Vec<String>) = match &ids {
+        None if messages.len() > MAX_OBJECTS_GET => return Err(MethodError::new("requestTooLarge")),
+        None => (messages.iter().map(Some).collect(), Vec::new()),
+        Some(ids) => {
+            let index: HashMap<i64, &MessageInfo> = messages.iter().map(|message| (message.id, message)).collect();
+            let mut found = Vec::new();
+            let mut not_found = Vec::new();
+            for id in ids {
+                match parse_id('E', id).and_then(|number| index.get(&number)) {
+                    Some(message) => found.push(Some(*message)),
+                    None => not_found.push(id.clone()),
+                }
+            }
+            (found, not_found)
+        }
+    };
+    let mut list = Vec::new();
+    for message in found.into_iter().flatten() {
+        let mut object = metadata(message);
+        if content {
+            let raw = raw(context, message.id)?;
+            object.extend(message::properties(&raw, Some((message.id, Vec::new())), &properties, &options));
+        }
+        list.push(request::pick(object, Some(&properties)));
+    }
+    Ok(json!({ "accountId": context.account_id(), "state": state::state(&mailboxes), "list": list, "notFound": not_found }))
+}
+
+/// # Email/changes
+pub fn changes(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let (since, max) = request::changes_arguments(arguments)?;
+    let mailboxes = context.mailboxes()?;
+    let changes = state::emails(context.store, &mailboxes, &since)?;
+    request::changes_response(context.account_id(), since, state::state(&mailboxes), changes, 'E', max)
+}
+
+/// # Thread/get
+pub fn threads(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::get_ids(arguments, context, MAX_OBJECTS_GET)?;
+    let (mailboxes, messages) = load(context)?;
+    let thread = |message: &MessageInfo| json!({ "id": super::id('T', message.id), "emailIds": [super::id('E', message.id)] });
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    match ids {
+        None if messages.len() > MAX_OBJECTS_GET => return Err(MethodError::new("requestTooLarge")),
+        None => list.extend(messages.iter().map(thread)),
+        Some(ids) => {
+            for id in ids {
+                match parse_id('T', &id).and_then(|number| messages.iter().find(|message| message.id == number)) {
+                    Some(message) => list.push(thread(message)),
+                    None => not_found.push(id),
+                }
+            }
+        }
+    }
+    Ok(json!({ "accountId": context.account_id(), "state": state::state(&mailboxes), "list": list, "notFound": not_found }))
+}
+
+/// # Thread/changes
+/// Thread 随邮件创建与删除，移动邮件与修改关键字不改变 Thread。
+pub fn thread_changes(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let (since, max) = request::changes_arguments(arguments)?;
+    let mailboxes = context.mailboxes()?;
+    let changes = state::emails(context.store, &mailboxes, &since)?.map(|changes| state::Changes { updated: Vec::new(), ..changes });
+    request::changes_response(context.account_id(), since, state::state(&mailboxes), changes, 'T', max)
+}
+
+/// 参与过滤与排序的一封邮件，原文在需要时读取
+struct Candidate<'a> {
+    message: &'a MessageInfo,
+    raw: Option<Vec<u8>>,
+}
+
+impl Candidate<'_> {
+    fn raw(&mut self, context: &Context) -> Result<&[u8]> {
+        if self.raw.is_none() {
+            self.raw = Some(raw(context, self.message.id)?);
+        }
+        Ok(self.raw.as_deref().unwrap_or_default())
+    }
+}
+
+/// # 是否符合过滤条件
+/// ## 参数
+/// - filter: FilterOperator 或 FilterCondition（RFC 8621 4.4.1）
+fn matches(filter: &Map<String, Value>, candidate: &mut Candidate, context: &Context) -> Result<bool> {
+    if let Some(operator) = filter.get("operator") {
+        let conditions = filter.get("conditions").and_then(Value::as_array).ok_or_else(|| request::invalid("FilterOperator requires conditions"))?;
+        let mut results = Vec::new();
+        for condition in conditions {
+            let condition = condition.as_object().ok_or_else(|| request::invalid("Filter must be an object"))?;
+            results.push(matches(condition, candidate, context)?);
+        }
+        return match operator.as_str() {
+            Some("AND") => Ok(results.iter().all(|result| *result)),
+            Some("OR") => Ok(results.iter().any(|result| *result)),
+            Some("NOT") => Ok(!results.iter().any(|result| *result)),
+            _ => Err(MethodError::describe("unsupportedFilter", "Unknown operator")),
+        };
+    }
+    let message = candidate.message;
+    let invalid = |key: &str| request::invalid(format!("Invalid value for {}", key));
+    for (key, value) in filter {
+        let matched = match key.as_str() {
+            "inMailbox" => value.as_str().ok_or_else(|| invalid(key))? == super::id('M', message.mailbox_id),
+            "inMailboxOtherThan" => {
+                let ids = value.as_array().ok_or_else(|| invalid(key))?;
+                !ids.iter().any(|id| id.as_str() == Some(super::id('M', message.mailbox_id).as_str()))
+            }
+            "before" | "after" => {
+                let date = value.as_str().and_then(parse_rfc3339).ok_or_else(|| invalid(key))?;
+                (message.internal_date < date) == (key == "before")
+            }
+            "minSize" => message.size >= value.as_u64().ok_or_else(|| invalid(key))?,
+            "maxSize" => message.size < value.as_u64().ok_or_else(|| invalid(key))?,
+            "hasKeyword" | "allInThreadHaveKeyword" | "someInThreadHaveKeyword" | "notKeyword" | "noneInThreadHaveKeyword" => {
+                let wanted = value.as_str().and_then(flag).ok_or_else(|| invalid(key))?;
+                let present = message.flags.iter().any(|flag| flag.eq_ignore_ascii_case(&wanted));
+                present == !matches!(key.as_str(), "notKeyword" | "noneInThreadHaveKeyword")
+            }
+            "hasAttachment" => message::has_attachment(candidate.raw(context)?) == value.as_bool().ok_or_else(|| invalid(key))?,
+            "text" | "from" | "to" | "cc" | "bcc" | "subject" | "body" => {
+                let wanted = value.as_str().ok_or_else(|| invalid(key))?.to_lowercase();
+                message::search_text(candidate.raw(context)?, key).to_lowercase().contains(&wanted)
+            }
+            "header" => {
+                let items: Vec<&str> = value.as_array().ok_or_else(|| invalid(key))?.iter().filter_map(Value::as_str).collect();
+                let (name, wanted) = match items.as_slice() {
+                    [name] => (*name, None),
+                    [name, wanted] => (*name, Some(wanted.to_lowercase())),
+                    _ => return Err(invalid(key)),
+                };
+                let values = message::header_text(candidate.raw(context)?, name);
+                match wanted {
+                    None => !values.is_empty(),
+                    Some(wanted) => values.iter().any(|value| value.to_lowercase().contains(&wanted)),
+                }
+            }
+            _ => return Err(MethodError::describe("unsupportedFilter", format!("Unsupported filter {}", key))),
+        };
+        if !matched {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// 排序的键
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortKey {
+    Number(i64),
+    Text(String),
+}
+
+/// # 排序的键
+/// ## 参数
+/// - comparator: Comparator 对象
+fn sort_key(comparator: &Value, candidate: &mut Candidate, context: &Context) -> Result<SortKey> {
+    let message = candidate.message;
+    Ok(match comparator["property"].as_str().unwrap_or_default() {
+        "receivedAt" => SortKey::Number(message.internal_date.timestamp()),
+        "size" => SortKey::Number(message.size as i64),
+        "sentAt" => SortKey::Number(message::sent_at(candidate.raw(context)?).unwrap_or_default()),
+        field @ ("from" | "to" | "subject") => SortKey::Text(message::search_text(candidate.raw(context)?, field).to_lowercase()),
+        "hasKeyword" | "allInThreadHaveKeyword" | "someInThreadHaveKeyword" => {
+            let wanted = comparator["keyword"].as_str().and_then(flag).ok_or_else(|| request::invalid("Comparator requires keyword"))?;
+            SortKey::Number(message.flags.iter().any(|flag| flag.eq_ignore_ascii_case(&wanted)) as i64)
+        }
+        _ => return Err(MethodError::new("unsupportedSort")),
+    })
+}
+
+/// # 过滤并排序
+/// ## 返回值
+/// - Result<Vec<&MessageInfo>>
+fn search<'m>(arguments: &Map<String, Value>, messages: &'m [MessageInfo], context: &Context) -> Result<Vec<&'m MessageInfo>> {
+    let filter = match arguments.get("filter") {
+        None | Some(Value::Null) => None,
+        Some(Value::Object(filter)) => Some(filter),
+        Some(_) => return Err(request::invalid("filter must be an object")),
+    };
+    let comparators: Vec<Value> = match arguments.get("sort") {
+        None | Some(Value::Null) => vec![json!({ "property": "receivedAt", "isAscending": false })],
+        Some(Value::Array(comparators)) => comparators.clone(),
+        Some(_) => return Err(request::invalid("sort must be an array")),
+    };
+    let mut matched = Vec::new();
+    for message in messages {
+        let mut candidate = Candidate { message, raw: None };
+        if let Some(filter) = filter
+            && !matches(filter, &mut candidate, context)?
+        {
+            continue;
+        }
+        let keys = comparators.iter().map(|comparator| sort_key(comparator, &mut candidate, context)).collect::<Result<Vec<_>>>()?;
+        matched.push((keys, message));
+    }
+    let ascending: Vec<bool> = comparators.iter().map(|comparator| comparator["isAscending"].as_bool().unwrap_or(true)).collect();
+    matched.sort_by(|(a, first), (b, second)| {
+        for ((a, b), ascending) in a.iter().zip(b).zip(&ascending) {
+            match a.cmp(b) {
+                Ordering::Equal => continue,
+                ordering if *ascending => return ordering,
+                ordering => return ordering.reverse(),
+            }
+        }
+        first.id.cmp(&second.id)
+    });
+    Ok(matched.into_iter().map(|(_, message)| message).collect())
+}
+
+/// # Email/query
+/// 每封邮件单独作为一个 Thread，collapseThreads 不影响结果。
+pub fn query(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let (mailboxes, messages) = load(context)?;
+    let matched = search(arguments, &messages, context)?;
+    let ids = matched.iter().map(|message| super::id('E', message.id)).collect();
+    let mut response = super::mailbox::window(context, ids, arguments, state::state(&mailboxes))?;
+    response["collapseThreads"] = json!(request::boolean(arguments, "collapseThreads", false)?);
+    Ok(response)
+}
+
+/// # SearchSnippet/get
+pub fn snippets(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::strings(arguments, "emailIds")?.ok_or_else(|| request::invalid("emailIds is required"))?;
+    if ids.len() > MAX_OBJECTS_GET {
+        return Err(MethodError::new("requestTooLarge"));
+    }
+    let (_, messages) = load(context)?;
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    for id in ids {
+        match parse_id('E', &id).filter(|number| messages.iter().any(|message| message.id == *number)) {
+            Some(_) => list.push(json!({ "emailId": id, "subject": null, "preview": null })),
+            None => not_found.push(id),
+        }
+    }
+    Ok(json!({ "accountId": context.account_id(), "list": list, "notFound": not_found }))
+}
+
+/// 创建、修改或删除单个对象的结果，错误为 SetError
+type SetResult<T> = std::result::Result<T, Value>;
+
+/// # 存储的错误转换为 SetError
+fn server_fail(e: StorageError) -> Value {
+    error!("JMAP 存储操作失败：{}", e);
+    set_error("serverFail", "Storage error")
+}
+
+/// # invalidProperties 错误
+fn invalid_properties(property: &str, description: impl Into<String>) -> Value {
+    json!({ "type": "invalidProperties", "properties": [property], "description": description.into() })
+}
+
+/// # 发布邮箱的变化
+fn publish(mailbox: &Mailbox, kind: Kind) {
+    event::publish(Event { account_id: mailbox.account_id, mailbox_id: mailbox.id, mailbox: mailbox.name.clone(), kind, origin: 0 });
+}
+
+/// # 解析 mailboxIds
+/// ## 返回值
+/// - SetResult<Mailbox>，必须恰好包含一个已存在的邮箱
+fn target(value: Option<&Value>, mailboxes: &[Mailbox], context: &Context) -> SetResult<Mailbox> {
+    let ids: Vec<&String> = match value {
+        Some(Value::Object(map)) => map.iter().filter(|(_, value)| **value == Value::Bool(true)).map(|(id, _)| id).collect(),
+        _ => return Err(invalid_properties("mailboxIds", "mailboxIds is required")),
+    };
+    let [id] = ids.as_slice() else {
+        return Err(invalid_properties("mailboxIds", "An email must belong to exactly one mailbox"));
+    };
+    context
+        .resolve(id)
+        .and_then(|id| parse_id('M', &id))
+        .and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number))
+        .cloned()
+        .ok_or_else(|| invalid_properties("mailboxIds", "Mailbox not found"))
+}
+
+/// # 保存新邮件
+/// ## 参数
+/// - properties: 包括 mailboxIds、keywords 与 receivedAt
+/// - raw: 邮件原文
+fn store(properties: &Map<String, Value>, raw: &[u8], mailboxes: &[Mailbox], context: &Context) -> SetResult<MessageInfo> {
+    let mailbox = target(properties.get("mailboxIds"), mailboxes, context)?;
+    let flags = parse_keywords(properties.get("keywords")).ok_or_else(|| invalid_properties("keywords", "Invalid keywords"))?;
+    let received = match properties.get("receivedAt") {
+        None | Some(Value::Null) => Utc::now(),
+        Some(value) => value.as_str().and_then(parse_rfc3339).ok_or_else(|| invalid_properties("receivedAt", "Invalid UTCDate"))?,
+    };
+    let message = context.store.append(mailbox.id, raw, &flags.into_iter().collect::<Vec<_>>(), received).map_err(server_fail)?;
+    publish(&mailbox, Kind::MessageNew);
+    Ok(message)
+}
+
+/// # 创建的邮件在响应中的属性
+fn created(message: &MessageInfo) -> Value {
+    json!({
+        "id": super::id('E', message.id),
+        "blobId": blob::message_blob(message.id, &[]),
+        "threadId": super::id('T', message.id),
+        "size": message.size,
+    })
+}
+
+/// # Email/set
+pub fn set(arguments: &Map<String, Value>, context: &mut Context) -> Result<Value> {
+    let (mailboxes, _) = load(context)?;
+    let old_state = state::state(&mailboxes);
+    let set = request::set_request(arguments, &old_state)?;
+    let mut response = SetResponse::default();
+
+    for (creation, properties) in set.create {
+        let blobs = |id: &str| blob::read(context.store, context.account.id, &context.resolve(id)?).ok().flatten();
+        let composed = message::compose(&properties, &blobs, context.hostname)
+            .map_err(|(property, description)| invalid_properties(&property, description))
+            .and_then(|raw| store(&properties, &raw, &mailboxes, context));
+        match composed {
+            Ok(message) => {
+                context.created.insert(creation.clone(), super::id('E', message.id));
+                response.created.insert(creation, created(&message));
+            }
+            Err(error) => {
+                response.not_created.insert(creation, error);
+            }
+        }
+    }
+    let messages = context.messages(&mailboxes)?;
+    for (id, patch) in set.update {
+        let id = context.resolve(&id).unwrap_or(id);
+        match update(&id, &patch, &mailboxes, &messages, context) {
+            Ok(()) => {
+                response.updated.insert(id, Value::Null);
+            }
+            Err(error) => {
+                response.not_updated.insert(id, error);
+            }
+        }
+    }
+    for id in set.destroy {
+        let id = context.resolve(&id).unwrap_or(id);
+        match destroy(&id, &mailboxes, context) {
+            Ok(()) => response.destroyed.push(id),
+            Err(error) => {
+                response.not_destroyed.insert(id, error);
+            }
+        }
+    }
+    let new_state = state::state(&context.mailboxes()?);
+    Ok(response.into_value(context.account_id(), old_state, new_state))
+}
+
+/// # 修改邮件
+/// 支持 keywords 与 mailboxIds，以及 keywords/<关键字> 与 mailboxIds/<邮箱> 形式的补丁。
+fn update(id: &str, patch: &Map<String, Value>, mailboxes: &[Mailbox], messages: &[MessageInfo], context: &Context) -> SetResult<()> {
+    let Some(message) = context.resolve(id).and_then(|id| parse_id('E', &id)).and_then(|number| messages.iter().find(|message| message.id == number)) else {
+        return Err(set_error("notFound", "Email not found"));
+    };
+    let mut flags: BTreeSet<String> = message.flags.iter().filter(|flag| keyword(flag).is_some()).cloned().collect();
+    let mut ids: BTreeSet<String> = BTreeSet::from([super::id('M', message.mailbox_id)]);
+    let mut flags_changed = false;
+    for (key, value) in patch {
+        let key = key.replace("~1", "/").replace("~0", "~");
+        if key == "keywords" {
+            flags = parse_keywords(Some(value)).ok_or_else(|| invalid_properties("keywords", "Invalid keywords"))?;
+            flags_changed = true;
+        } else if let Some(name) = key.strip_prefix("keywords/") {
+            let flag = flag(name).ok_or_else(|| invalid_properties(&key, "Invalid keyword"))?;
+            match value {
+                Value::Bool(true) => flags.insert(flag),
+                Value::Null => flags.remove(&flag),
+                _ => return Err(invalid_properties(&key, "Keyword patch must be true or null")),
+            };
+            flags_changed = true;
+        } else if key == "mailboxIds" {
+            let map = value.as_object().ok_or_else(|| invalid_properties("mailboxIds", "mailboxIds must be an object"))?;
+            ids = map.iter().filter(|(_, value)| **value == Value::Bool(true)).filter_map(|(id, _)| context.resolve(id)).collect();
+        } else if let Some(mailbox) = key.strip_prefix("mailboxIds/") {
+            let mailbox = context.resolve(mailbox).ok_or_else(|| invalid_properties(&key, "Unknown creation id"))?;
+            match value {
+                Value::Bool(true) => ids.insert(mailbox),
+                Value::Null => ids.remove(&mailbox),
+                _ => return Err(invalid_properties(&key, "Mailbox patch must be true or null")),
+            };
+        } else {
+            return Err(invalid_properties(&key, "Property is immutable or unknown"));
+        }
+    }
+    let ids: Map<String, Value> = ids.into_iter().map(|id| (id, Value::Bool(true))).collect();
+    let target = target(Some(&Value::Object(ids)), mailboxes, context)?;
+
+    let mut current = mailboxes.iter().find(|mailbox| mailbox.id == message.mailbox_id).cloned().unwrap_or_else(|| target.clone());
+    if target.id != message.mailbox_id {
+        context.store.move_message(message.id, target.id).map_err(server_fail)?;
+        publish(&current, Kind::MessageExpunge);
+        publish(&target, Kind::MessageNew);
+        current = target;
+    }
+    let merged = merge_flags(&flags, &message.flags);
+    if flags_changed && merged.iter().collect::<BTreeSet<_>>() != message.flags.iter().collect::<BTreeSet<_>>() {
+        context.store.set_flags(message.id, &merged, None).map_err(server_fail)?;
+        publish(&current, Kind::FlagChange);
+    }
+    Ok(())
+}
+
+/// # 删除邮件
+fn destroy(id: &str, mailboxes: &[Mailbox], context: &Context) -> SetResult<()> {
+    let messages = context.messages(mailboxes).map_err(|_| set_error("serverFail", "Storage error"))?;
+    let Some(message) = parse_id('E', id).and_then(|number| messages.iter().find(|message| message.id == number)) else {
+        return Err(set_error("notFound", "Email not found"));
+    };
+    context.store.expunge(&[message.id]).map_err(server_fail)?;
+    if let Some(mailbox) = mailboxes.iter().find(|mailbox| mailbox.id == message.mailbox_id) {
+        publish(mailbox, Kind::MessageExpunge);
+    }
+    Ok(())
+}
+
+/// # Email/import
+pub fn import(arguments: &Map<String, Value>, context: &mut Context) -> Result<Value> {
+    let mailboxes = context.mailboxes()?;
+    let old_state = state::state(&mailboxes);
+    if let Some(expected) = request::string(arguments, "ifInState")?
+        && expected != old_state
+    {
+        return Err(MethodError::new("stateMismatch"));
+    }
+    let emails = arguments.get("emails").and_then(Value::as_object).ok_or_else(|| request::invalid("emails is required"))?;
+    if emails.len() > super::MAX_OBJECTS_SET {
+        return Err(MethodError::new("requestTooLarge"));
+    }
+    let mut created_map = Map::new();
+    let mut not_created = Map::new();
+    for (creation, email) in emails {
+        let Some(email) = email.as_object() else {
+            not_created.insert(creation.clone(), set_error("invalidProperties", "EmailImport must be an object"));
+            continue;
+        };
+        let blob_id = email.get("blobId").and_then(Value::as_str).and_then(|id| context.resolve(id));
+        let data = match blob_id.as_deref().map(|id| blob::read(context.store, context.account.id, id)).transpose()? {
+            Some(Some((_, data))) => data,
+            _ => {
+                not_created.insert(creation.clone(), json!({ "type": "blobNotFound", "notFound": [email.get("blobId")] }));
+                continue;
+            }
+        };
+        match store(email, &data, &mailboxes, context) {
+            Ok(message) => {
+                context.created.insert(creation.clone(), super::id('E', message.id));
+                created_map.insert(creation.clone(), created(&message));
+            }
+            Err(error) => {
+                not_created.insert(creation.clone(), error);
+            }
+        }
+    }
+    let optional = |map: Map<String, Value>| if map.is_empty() { Value::Null } else { Value::Object(map) };
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": old_state,
+        "newState": state::state(&context.mailboxes()?),
+        "created": optional(created_map),
+        "notCreated": optional(not_created),
+    }))
+}
+
+/// # Email/parse
+/// 邮件或 message/rfc822 部分的 blob 可以引用其中各部分的 blob id，上传的 blob 中各部分的 blobId 为 null。
+pub fn parse(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    const DEFAULTS: [&str; 17] = [
+        "messageId",
+        "inReplyTo",
+        "references",
+        "sender",
+        "from",
+        "to",
+        "cc",
+        "bcc",
+        "replyTo",
+        "subject",
+        "sentAt",
+        "hasAttachment",
+        "preview",
+        "bodyValues",
+        "textBody",
+        "htmlBody",
+        "attachments",
+    ];
+    let ids = request::strings(arguments, "blobIds")?.ok_or_else(|| request::invalid("blobIds is required"))?;
+    if ids.len() > MAX_OBJECTS_GET {
+        return Err(MethodError::new("requestTooLarge"));
+    }
+    let properties = properties(arguments, &DEFAULTS)?;
+    let options = body_options(arguments)?;
+    let mut parsed = Map::new();
+    let mut not_parsable = Vec::new();
+    let mut not_found = Vec::new();
+    for id in ids {
+        let Some((_, data)) = blob::read(context.store, context.account.id, &id)? else {
+            not_found.push(id);
+            continue;
+        };
+        if data.is_empty() {
+            not_parsable.push(id);
+            continue;
+        }
+        let base = match blob::parse(&id) {
+            Some(Blob::Message(message_id, path)) => Some((message_id, path)),
+            _ => None,
+        };
+        let mut object = message::properties(&data, base, &properties, &options);
+        for property in &properties {
+            let value = match property.as_str() {
+                "blobId" => json!(id),
+                "size" => json!(data.len()),
+                "id" | "threadId" | "mailboxIds" | "keywords" | "receivedAt" => Value::Null,
+                _ => continue,
+            };
+            object.insert(property.clone(), value);
+        }
+        parsed.insert(id, Value::Object(object));
+    }
+    let optional = |items: Vec<String>| if items.is_empty() { Value::Null } else { json!(items) };
+    Ok(json!({
+        "accountId": context.account_id(),
+        "parsed": if parsed.is_empty() { Value::Null } else { Value::Object(parsed) },
+        "notParsable": optional(not_parsable),
+        "notFound": optional(not_found),
+    }))
+}
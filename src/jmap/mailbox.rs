@@ -0,0 +1,433 @@
+/* JMAP Mailbox */
+/*
+# Mailbox 的方法
+## 说明
+邮箱与 IMAP 共用，存储中的名称为完整路径（例如 INBOX/工作），name 为去掉上级邮箱之后的部分，
+parentId 为最近的已存在的上级邮箱。role 由名称得出（INBOX 与特殊用途名称），不能单独设置。
+名称不能包含层级分隔符 /，改名或移动时下级邮箱一并改名，与 IMAP RENAME 相同。
+INBOX 不能删除或改名；删除有邮件的邮箱需要 onDestroyRemoveEmails。
+ */
+use serde_json::{Map, Value, json};
+
+use super::request::{self, Context, MethodError, Result, SetResponse, set_error};
+use super::{MAX_OBJECTS_GET, parse_id, state};
+use crate::event::{self, Event, Kind};
+use crate::imap::mailbox::{self as names, DELIMITER};
+use crate::storage::{Mailbox, StorageError};
+
+/// # 最近的已存在的上级邮箱
+fn parent<'m>(mailbox: &Mailbox, mailboxes: &'m [Mailbox]) -> Option<&'m Mailbox> {
+    names::parents(&mailbox.name).into_iter().rev().find_map(|name| mailboxes.iter().find(|other| other.name == name))
+}
+
+/// # 邮箱的角色（RFC 8621 2）
+fn role(name: &str) -> Option<String> {
+    match name {
+        "INBOX" => Some(String::from("inbox")),
+        _ => names::special_use(name).map(|special| special.trim_start_matches('\\').to_lowercase()),
+    }
+}
+
+/// # 邮箱在 JMAP 中的名称
+fn local_name(mailbox: &Mailbox, mailboxes: &[Mailbox]) -> String {
+    match parent(mailbox, mailboxes) {
+        Some(parent) => mailbox.name[parent.name.len() + 1..].to_string(),
+        None => mailbox.name.clone(),
+    }
+}
+
+/// # 用户对邮箱的权限
+fn rights(inbox: bool) -> Value {
+    json!({
+        "mayReadItems": true,
+        "mayAddItems": true,
+        "mayRemoveItems": true,
+        "maySetSeen": true,
+        "maySetKeywords": true,
+        "mayCreateChild": true,
+        "mayRename": !inbox,
+        "mayDelete": !inbox,
+        "maySubmit": true,
+    })
+}
+
+/// # Mailbox 对象
+/// ## 参数
+/// - mailbox: 邮箱
+/// - mailboxes: 账号的所有邮箱
+/// - counts: 是否统计邮件数量
+fn object(context: &Context, mailbox: &Mailbox, mailboxes: &[Mailbox], counts: bool) -> Result<Map<String, Value>> {
+    let (total, unread) = match counts {
+        true => {
+            let messages = context.store.messages(mailbox.id)?;
+            (messages.len(), messages.iter().filter(|message| !message.flags.iter().any(|flag| flag == "\\Seen")).count())
+        }
+        false => (0, 0),
+    };
+    let value = json!({
+        "id": super::id('M', mailbox.id),
+        "name": local_name(mailbox, mailboxes),
+        "parentId": parent(mailbox, mailboxes).map(|parent| super::id('M', parent.id)),
+        "role": role(&mailbox.name),
+        "sortOrder": 0,
+        "totalEmails": total,
+        "unreadEmails": unread,
+        "totalThreads": total,
+        "unreadThreads": unread,
+        "myRights": rights(mailbox.name == "INBOX"),
+        "isSubscribed": mailbox.subscribed,
+    });
+    Ok(value.as_object().cloned().unwrap_or_default())
+}
+
+/// # Mailbox/get
+pub fn get(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::get_ids(arguments, context, MAX_OBJECTS_GET)?;
+    let properties = request::strings(arguments, "properties")?;
+    const PROPERTIES: [&str; 11] = [
+        "id",
+        "name",
+        "parentId",
+        "role",
+        "sortOrder",
+        "totalEmails",
+        "unreadEmails",
+        "totalThreads",
+        "unreadThreads",
+        "myRights",
+        "isSubscribed",
+    ];
+    if let Some(unknown) = properties.iter().flatten().find(|property| !PROPERTIES.contains(&property.as_str())) {
+        return Err(request::invalid(format!("Unknown property {}", unknown)));
+    }
+    let counts = properties.as_ref().is_none_or(|properties| properties.iter().any(|property| property.contains("Emails") || property.contains("Threads")));
+
+    let mailboxes = context.mailboxes()?;
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    match ids {
+        None => {
+            for mailbox in &mailboxes {
+                list.push(request::pick(object(context, mailbox, &mailboxes, counts)?, properties.as_deref()));
+            }
+        }
+        Some(ids) => {
+            for id in ids {
+                match parse_id('M', &id).and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number)) {
+                    Some(mailbox) => list.push(request::pick(object(context, mailbox, &mailboxes, counts)?, properties.as_deref())),
+                    None => not_found.push(id),
+                }
+            }
+        }
+    }
+    Ok(json!({ "accountId": context.account_id(), "state": state::state(&mailboxes), "list": list, "notFound": not_found }))
+}
+
+/// # Mailbox/changes
+pub fn changes(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let (since, max) = request::changes_arguments(arguments)?;
+    let mailboxes = context.mailboxes()?;
+    let changes = state::mailboxes(&mailboxes, &since);
+    let counts_only = changes.as_ref().is_some_and(|changes| changes.counts_only && !changes.updated.is_empty());
+    let mut response = request::changes_response(context.account_id(), since, state::state(&mailboxes), changes, 'M', max)?;
+    response["updatedProperties"] = match counts_only {
+        true => json!(["totalEmails", "unreadEmails", "totalThreads", "unreadThreads"]),
+        false => Value::Null,
+    };
+    Ok(response)
+}
+
+/// # Mailbox/query
+/// 支持 parentId、name、role、hasAnyRole 与 isSubscribed 过滤，按 sortOrder 或 name 排序。
+pub fn query(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let mailboxes = context.mailboxes()?;
+    let mut matched: Vec<&Mailbox> = mailboxes.iter().collect();
+    if let Some(filter) = arguments.get("filter").filter(|filter| !filter.is_null()) {
+        let filter = filter.as_object().ok_or_else(|| request::invalid("filter must be a FilterCondition"))?;
+        for (key, value) in filter {
+            matched.retain(|mailbox| match key.as_str() {
+                "parentId" => parent(mailbox, &mailboxes).map(|parent| super::id('M', parent.id)).as_deref() == value.as_str(),
+                "name" => value.as_str().is_some_and(|name| local_name(mailbox, &mailboxes).to_lowercase().contains(&name.to_lowercase())),
+                "role" => role(&mailbox.name).as_deref() == value.as_str(),
+                "hasAnyRole" => role(&mailbox.name).is_some() == (value.as_bool() == Some(true)),
+                "isSubscribed" => mailbox.subscribed == (value.as_bool() == Some(true)),
+                _ => true,
+            });
+            if !["parentId", "name", "role", "hasAnyRole", "isSubscribed"].contains(&key.as_str()) {
+                return Err(MethodError::describe("unsupportedFilter", format!("Unsupported filter {}", key)));
+            }
+        }
+    }
+    for comparator in arguments.get("sort").and_then(Value::as_array).into_iter().flatten().rev() {
+        let ascending = comparator["isAscending"].as_bool().unwrap_or(true);
+        let property = comparator["property"].as_str().unwrap_or_default();
+        if !["name", "sortOrder"].contains(&property) {
+            return Err(MethodError::new("unsupportedSort"));
+        }
+        // INBOX 排在最前，其他邮箱的 sortOrder 都为 0
+        let key = |mailbox: &Mailbox| match property {
+            "name" => local_name(mailbox, &mailboxes),
+            _ => String::from(if mailbox.name == "INBOX" { "0" } else { "1" }),
+        };
+        matched.sort_by(|a, b| {
+            let ordering = key(a).cmp(&key(b));
+            if ascending { ordering } else { ordering.reverse() }
+        });
+    }
+    let ids: Vec<String> = matched.iter().map(|mailbox| super::id('M', mailbox.id)).collect();
+    window(context, ids, arguments, state::state(&mailboxes))
+}
+
+/// # /query 的分页与响应
+/// ## 参数
+/// - ids: 排序后的所有结果
+/// - arguments: 包括 position、limit、anchor、anchorOffset 与 calculateTotal
+/// - state: queryState
+pub fn window(context: &Context, ids: Vec<String>, arguments: &Map<String, Value>, state: String) -> Result<Value> {
+    let total = ids.len();
+    let mut position = request::integer(arguments, "position")?.unwrap_or(0);
+    if let Some(anchor) = request::string(arguments, "anchor")? {
+        let index = ids.iter().position(|id| *id == anchor).ok_or_else(|| MethodError::new("anchorNotFound"))?;
+        position = index as i64 + request::integer(arguments, "anchorOffset")?.unwrap_or(0);
+        position = position.max(0);
+    } else if position < 0 {
+        position = (total as i64 + position).max(0);
+    }
+    let limit = match request::integer(arguments, "limit")? {
+        Some(limit) if limit < 0 => return Err(request::invalid("limit must not be negative")),
+        Some(limit) => limit as usize,
+        None => total,
+    };
+    let start = (position as usize).min(total);
+    let page: Vec<String> = ids.into_iter().skip(start).take(limit).collect();
+    let mut response = json!({
+        "accountId": context.account_id(),
+        "queryState": state,
+        "canCalculateChanges": false,
+        "position": start,
+        "ids": page,
+    });
+    if request::boolean(arguments, "calculateTotal", false)? {
+        response["total"] = json!(total);
+    }
+    if arguments.get("limit").is_some_and(|limit| limit.as_u64().is_some_and(|limit| limit as usize > total)) {
+        response["limit"] = json!(total);
+    }
+    Ok(response)
+}
+
+/// # 发布邮箱的变化
+fn publish(mailbox: &Mailbox, kind: Kind) {
+    event::publish(Event { account_id: mailbox.account_id, mailbox_id: mailbox.id, mailbox: mailbox.name.clone(), kind, origin: 0 });
+}
+
+/// # 解析 parentId
+/// ## 返回值
+/// - Result<Option<Mailbox>, Value>，错误为 SetError
+fn parent_of(value: Option<&Value>, mailboxes: &[Mailbox], context: &Context) -> std::result::Result<Option<Mailbox>, Value> {
+    match value {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(id)) => {
+            let id = context.resolve(id).ok_or_else(|| set_error("invalidProperties", "parentId refers to an unknown creation id"))?;
+            parse_id('M', &id)
+                .and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number))
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| json!({ "type": "invalidProperties", "properties": ["parentId"], "description": "Parent mailbox not found" }))
+        }
+        Some(_) => Err(json!({ "type": "invalidProperties", "properties": ["parentId"] })),
+    }
+}
+
+/// # 检查名称
+fn check_name(name: &str) -> std::result::Result<(), Value> {
+    if name.is_empty() || name.contains(DELIMITER) || !names::is_valid(name) {
+        return Err(json!({ "type": "invalidProperties", "properties": ["name"], "description": format!("Name must not be empty or contain {}", DELIMITER) }));
+    }
+    Ok(())
+}
+
+/// # Mailbox/set
+pub fn set(arguments: &Map<String, Value>, context: &mut Context) -> Result<Value> {
+    let old_state = state::state(&context.mailboxes()?);
+    let set = request::set_request(arguments, &old_state)?;
+    let remove_emails = request::boolean(arguments, "onDestroyRemoveEmails", false)?;
+    let mut response = SetResponse::default();
+
+    // 父邮箱在同一请求中创建时，先创建父邮箱
+    let mut pending = set.create;
+    while !pending.is_empty() {
+        let waiting = |properties: &Map<String, Value>| {
+            let parent = properties.get("parentId").and_then(Value::as_str).and_then(|parent| parent.strip_prefix('#'));
+            parent.is_some_and(|parent| pending.iter().any(|(creation, _)| creation == parent))
+        };
+        let index = pending.iter().position(|(_, properties)| !waiting(properties)).unwrap_or(0);
+        let (creation, properties) = pending.remove(index);
+        match create(&properties, context) {
+            Ok(created) => {
+                context.created.insert(creation.clone(), super::id('M', created.id));
+                let mailboxes = context.mailboxes()?;
+                let mut value = object(context, &created, &mailboxes, false)?;
+                value.retain(|key, _| !properties.contains_key(key));
+                response.created.insert(creation, Value::Object(value));
+            }
+            Err(error) => {
+                response.not_created.insert(creation, error);
+            }
+        }
+    }
+    for (id, properties) in set.update {
+        let id = context.resolve(&id).unwrap_or(id);
+        match update(&id, &properties, context) {
+            Ok(()) => {
+                response.updated.insert(id, Value::Null);
+            }
+            Err(error) => {
+                response.not_updated.insert(id, error);
+            }
+        }
+    }
+    // 子邮箱在父邮箱之前删除
+    let mut ids: Vec<String> = set.destroy.into_iter().map(|id| context.resolve(&id).unwrap_or(id)).collect();
+    let mailboxes = context.mailboxes()?;
+    let depth = |id: &String| {
+        let mailbox = parse_id('M', id).and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number));
+        mailbox.map_or(0, |mailbox| mailbox.name.matches(DELIMITER).count())
+    };
+    ids.sort_by_key(|id| std::cmp::Reverse(depth(id)));
+    for id in ids {
+        match destroy(&id, remove_emails, context) {
+            Ok(()) => response.destroyed.push(id),
+            Err(error) => {
+                response.not_destroyed.insert(id, error);
+            }
+        }
+    }
+    let new_state = state::state(&context.mailboxes()?);
+    Ok(response.into_value(context.account_id(), old_state, new_state))
+}
+
+/// 创建、修改或删除单个对象的结果，错误为 SetError
+type SetResult<T> = std::result::Result<T, Value>;
+
+/// # 存储的错误转换为 SetError
+fn server_fail(e: StorageError) -> Value {
+    error!("JMAP 存储操作失败：{}", e);
+    set_error("serverFail", "Storage error")
+}
+
+/// # 创建邮箱
+fn create(properties: &Map<String, Value>, context: &Context) -> SetResult<Mailbox> {
+    let mailboxes = context.mailboxes().map_err(|_| set_error("serverFail", "Storage error"))?;
+    for key in properties.keys() {
+        if !["name", "parentId", "role", "sortOrder", "isSubscribed"].contains(&key.as_str()) {
+            return Err(json!({ "type": "invalidProperties", "properties": [key], "description": "Unknown or server-set property" }));
+        }
+    }
+    let name = properties.get("name").and_then(Value::as_str).unwrap_or_default();
+    check_name(name)?;
+    let parent = parent_of(properties.get("parentId"), &mailboxes, context)?;
+    let full = match &parent {
+        Some(parent) => format!("{}{}{}", parent.name, DELIMITER, name),
+        None => names::normalize(name),
+    };
+    if let Some(requested) = properties.get("role").filter(|role| !role.is_null())
+        && requested.as_str() != role(&full).as_deref()
+    {
+        return Err(json!({ "type": "invalidProperties", "properties": ["role"], "description": "The role is derived from the mailbox name" }));
+    }
+    let created = match context.store.create_mailbox(context.account.id, &full) {
+        Ok(created) => created,
+        Err(StorageError::Conflict(_)) => return Err(set_error("alreadyExists", "A mailbox with this name already exists")),
+        Err(e) => return Err(server_fail(e)),
+    };
+    publish(&created, Kind::MailboxName(None));
+    info!("JMAP 账号 {} 创建了邮箱 {}", context.account.address(), full);
+    if let Some(subscribed) = properties.get("isSubscribed").and_then(Value::as_bool)
+        && subscribed != created.subscribed
+    {
+        context.store.set_subscribed(created.id, subscribed).map_err(server_fail)?;
+        return Ok(Mailbox { subscribed, ..created });
+    }
+    Ok(created)
+}
+
+/// # 修改邮箱
+fn update(id: &str, properties: &Map<String, Value>, context: &Context) -> SetResult<()> {
+    let mailboxes = context.mailboxes().map_err(|_| set_error("serverFail", "Storage error"))?;
+    let Some(mailbox) = parse_id('M', id).and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number)).cloned() else {
+        return Err(set_error("notFound", "Mailbox not found"));
+    };
+    let mut name = local_name(&mailbox, &mailboxes);
+    let mut parent = parent(&mailbox, &mailboxes).cloned();
+    for (key, value) in properties {
+        match key.as_str() {
+            "name" => {
+                name = value.as_str().unwrap_or_default().to_string();
+                check_name(&name)?;
+            }
+            "parentId" => parent = parent_of(Some(value), &mailboxes, context)?,
+            "isSubscribed" if value.is_boolean() => {}
+            "sortOrder" if value.as_u64() == Some(0) => {}
+            "role" if value.as_str() == role(&mailbox.name).as_deref() => {}
+            _ => return Err(json!({ "type": "invalidProperties", "properties": [key], "description": "Invalid or unsupported property" })),
+        }
+    }
+
+    let full = match &parent {
+        Some(parent) => format!("{}{}{}", parent.name, DELIMITER, name),
+        None => names::normalize(&name),
+    };
+    if full != mailbox.name {
+        if mailbox.name == "INBOX" {
+            return Err(set_error("forbidden", "INBOX cannot be renamed"));
+        }
+        if full == "INBOX" || mailboxes.iter().any(|other| other.name == full) {
+            return Err(set_error("alreadyExists", "A mailbox with this name already exists"));
+        }
+        if names::is_child(&full, &mailbox.name) {
+            return Err(json!({ "type": "invalidProperties", "properties": ["parentId"], "description": "Cannot move a mailbox into itself" }));
+        }
+        context.store.rename_mailbox(mailbox.id, &full).map_err(server_fail)?;
+        publish(&Mailbox { name: full.clone(), ..mailbox.clone() }, Kind::MailboxName(Some(mailbox.name.clone())));
+        for child in mailboxes.iter().filter(|other| names::is_child(&other.name, &mailbox.name)) {
+            let renamed = format!("{}{}", full, &child.name[mailbox.name.len()..]);
+            context.store.rename_mailbox(child.id, &renamed).map_err(server_fail)?;
+            publish(&Mailbox { name: renamed, ..child.clone() }, Kind::MailboxName(Some(child.name.clone())));
+        }
+        info!("JMAP 账号 {} 将邮箱 {} 改名为 {}", context.account.address(), mailbox.name, full);
+    }
+    if let Some(subscribed) = properties.get("isSubscribed").and_then(Value::as_bool)
+        && subscribed != mailbox.subscribed
+    {
+        context.store.set_subscribed(mailbox.id, subscribed).map_err(server_fail)?;
+        publish(&Mailbox { name: full, ..mailbox }, Kind::SubscriptionChange);
+    }
+    Ok(())
+}
+
+/// # 删除邮箱
+fn destroy(id: &str, remove_emails: bool, context: &Context) -> SetResult<()> {
+    let mailboxes = context.mailboxes().map_err(|_| set_error("serverFail", "Storage error"))?;
+    let Some(mailbox) = parse_id('M', id).and_then(|number| mailboxes.iter().find(|mailbox| mailbox.id == number)) else {
+        return Err(set_error("notFound", "Mailbox not found"));
+    };
+    if mailbox.name == "INBOX" {
+        return Err(set_error("forbidden", "INBOX cannot be deleted"));
+    }
+    if mailboxes.iter().any(|other| names::is_child(&other.name, &mailbox.name)) {
+        return Err(set_error("mailboxHasChild", "Mailbox has children, delete them first"));
+    }
+    let messages = context.store.messages(mailbox.id).map_err(server_fail)?;
+    if !messages.is_empty() {
+        if !remove_emails {
+            return Err(set_error("mailboxHasEmail", "Mailbox is not empty"));
+        }
+        context.store.expunge(&messages.iter().map(|message| message.id).collect::<Vec<_>>()).map_err(server_fail)?;
+        publish(mailbox, Kind::MessageExpunge);
+    }
+    context.store.delete_mailbox(mailbox.id).map_err(server_fail)?;
+    publish(mailbox, Kind::MailboxName(None));
+    info!("JMAP 账号 {} 删除了邮箱 {}", context.account.address(), mailbox.name);
+    Ok(())
+}
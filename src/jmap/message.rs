@@ -0,0 +1,1123 @@
+/* JMAP 邮件内容 */
+/*
+# 邮件与 Email 的属性
+## 用法
+let values = message::properties(&raw, Some((message_id, Vec::new())), &properties, &options);   <-- 邮件头与正文的属性
+let raw = message::compose(&email, &blobs, hostname)?;                                        <-- Email/set 创建邮件
+## 说明
+部分编号与 IMAP 相同（例如 1.2），multipart 没有编号；blob id 见 blob.rs。
+textBody、htmlBody 与 attachments 按 RFC 8621 4.1.4 的算法得出，message/rfc822 部分不展开。
+邮件头的形式见 RFC 8621 4.1.2：asRaw 保留折叠的行，asText 解码 encoded-word（RFC 2047）。
+正文按 charset 解码（见 mime/charset.rs，声明有误时识别实际的字符集），传输编码有误或仍有无法解码的内容时标记 isEncodingProblem。
+生成的邮件中，非 ASCII 的邮件头使用 UTF-8 的 encoded-word，文本部分使用 quoted-printable，其他部分使用 base64。
+ */
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use chrono::{DateTime, SecondsFormat, Utc};
+use rand_core::{OsRng, RngCore};
+use serde_json::{Map, Value, json};
+
+use super::blob;
+use crate::mime::header::{decode_words, parameter};
+use crate::mime::{self, Part, charset};
+use crate::utils::to_hex;
+
+/// preview 的最大长度（字符）
+const PREVIEW: usize = 256;
+
+/// 邮件头一行的建议长度（RFC 5322 2.1.1）
+const LINE: usize = 78;
+
+/// 由邮件内容得出的属性
+pub const CONTENT_PROPERTIES: [&str; 19] = [
+    "messageId",
+    "inReplyTo",
+    "references",
+    "sender",
+    "from",
+    "to",
+    "cc",
+    "bcc",
+    "replyTo",
+    "subject",
+    "sentAt",
+    "headers",
+    "bodyStructure",
+    "textBody",
+    "htmlBody",
+    "attachments",
+    "hasAttachment",
+    "preview",
+    "bodyValues",
+];
+
+/// 默认的 EmailBodyPart 属性（RFC 8621 4.2）
+pub const BODY_PROPERTIES: [&str; 10] = ["partId", "blobId", "size", "name", "type", "charset", "disposition", "cid", "language", "location"];
+
+/// Email/get 的正文参数
+pub struct BodyOptions {
+    /// EmailBodyPart 的属性
+    pub properties: Vec<String>,
+    pub fetch_text: bool,
+    pub fetch_html: bool,
+    pub fetch_all: bool,
+    /// bodyValues 的最大字节数，0 为不限
+    pub max_bytes: usize,
+}
+
+/// 邮件头的形式（RFC 8621 4.1.2）
+#[derive(Clone, Copy, PartialEq)]
+enum Form {
+    Raw,
+    Text,
+    Addresses,
+    GroupedAddresses,
+    MessageIds,
+    Date,
+    Urls,
+}
+
+/// # 解析 header:名称:形式[:all] 属性
+/// ## 返回值
+/// - Option<(名称, 形式, 是否返回全部)>，格式错误时为 None
+fn header_property(property: &str) -> Option<(&str, Form, bool)> {
+    let mut segments = property.strip_prefix("header:")?.split(':');
+    let name = segments.next().filter(|name| !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_graphic()))?;
+    let mut form = Form::Raw;
+    let mut all = false;
+    for segment in segments {
+        match segment {
+            "all" if !all => all = true,
+            _ if all => return None,
+            "asRaw" => form = Form::Raw,
+            "asText" => form = Form::Text,
+            "asAddresses" => form = Form::Addresses,
+            "asGroupedAddresses" => form = Form::GroupedAddresses,
+            "asMessageIds" => form = Form::MessageIds,
+            "asDate" => form = Form::Date,
+            "asURLs" => form = Form::Urls,
+            _ => return None,
+        }
+    }
+    Some((name, form, all))
+}
+
+/// # 是否为有效的 Email 属性
+/// 包括 header:名称:形式 属性。
+pub fn is_property(property: &str) -> bool {
+    const METADATA: [&str; 7] = ["id", "blobId", "threadId", "mailboxIds", "keywords", "size", "receivedAt"];
+    METADATA.contains(&property) || CONTENT_PROPERTIES.contains(&property) || header_property(property).is_some()
+}
+
+/// # 是否为有效的 EmailBodyPart 属性
+pub fn is_body_property(property: &str) -> bool {
+    BODY_PROPERTIES.contains(&property) || matches!(property, "headers" | "subParts") || header_property(property).is_some()
+}
+
+/// # 邮件头的原始字段
+/// 与 mime::fields 不同，值保留冒号之后的原文，包括折叠的行；未编码的 8 位内容同样按识别的字符集解码。
+fn raw_fields(header: &[u8]) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = Vec::new();
+    for line in charset::header(header).split_inclusive('\n') {
+        let content = line.trim_end_matches(['\r', '\n']);
+        if content.is_empty() {
+            break;
+        }
+        if content.starts_with([' ', '\t']) {
+            if let Some((_, value)) = fields.last_mut() {
+                value.push_str("\r\n");
+                value.push_str(content);
+            }
+            continue;
+        }
+        if let Some((name, value)) = content.split_once(':') {
+            fields.push((name.trim_end().to_string(), value.to_string()));
+        }
+    }
+    fields
+}
+
+/// # 展开折叠的行
+fn unfold(raw: &str) -> String {
+    raw.replace("\r\n", "").replace('\n', "")
+}
+
+/// 地址列表中的一组，不在组中的地址组名为 None
+type Group = (Option<String>, Vec<(Option<String>, String)>);
+
+/// # 解析地址列表（RFC 5322 3.4）
+/// 容错解析：注释只在没有显示名时作为名称，格式错误的地址原样作为 email。
+fn address_list(value: &str) -> Vec<Group> {
+    let mut groups: Vec<Group> = vec![(None, Vec::new())];
+    let mut phrase = String::new();
+    let mut comment = String::new();
+    let mut angle: Option<String> = None;
+    let mut in_group = false;
+    let mut chars = value.chars().peekable();
+
+    let finish = |phrase: &mut String, comment: &mut String, angle: &mut Option<String>, groups: &mut Vec<Group>| {
+        let text = decode_words(phrase.trim());
+        let (name, email) = match angle.take() {
+            Some(address) => (Some(text).filter(|text| !text.is_empty()), address.trim().to_string()),
+            None => (None, text.split_whitespace().collect::<String>()),
+        };
+        let name = name.or_else(|| Some(decode_words(comment.trim())).filter(|comment| !comment.is_empty()));
+        if (!email.is_empty() || name.is_some())
+            && let Some((_, addresses)) = groups.last_mut()
+        {
+            addresses.push((name, email));
+        }
+        phrase.clear();
+        comment.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                let mut quoted = String::new();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => quoted.extend(chars.next()),
+                        '"' => break,
+                        _ => quoted.push(c),
+                    }
+                }
+                phrase.push_str(&quoted);
+            }
+            '(' => {
+                let mut depth = 1;
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '(' => depth += 1,
+                        ')' if depth == 1 => break,
+                        ')' => depth -= 1,
+                        _ => comment.push(c),
+                    }
+                }
+            }
+            '<' => {
+                let mut address = String::new();
+                for c in chars.by_ref() {
+                    if c == '>' {
+                        break;
+                    }
+                    address.push(c);
+                }
+                angle = Some(address);
+            }
+            ':' if !in_group && angle.is_none() => {
+                let name = decode_words(phrase.trim());
+                groups.push((Some(name), Vec::new()));
+                phrase.clear();
+                comment.clear();
+                in_group = true;
+            }
+            ',' => finish(&mut phrase, &mut comment, &mut angle, &mut groups),
+            ';' if in_group => {
+                finish(&mut phrase, &mut comment, &mut angle, &mut groups);
+                groups.push((None, Vec::new()));
+                in_group = false;
+            }
+            _ => phrase.push(c),
+        }
+    }
+    finish(&mut phrase, &mut comment, &mut angle, &mut groups);
+    // 合并相邻的无组地址
+    let mut merged: Vec<Group> = Vec::new();
+    for (name, addresses) in groups {
+        match merged.last_mut() {
+            Some((None, previous)) if name.is_none() => previous.extend(addresses),
+            _ if name.is_none() && addresses.is_empty() => {}
+            _ => merged.push((name, addresses)),
+        }
+    }
+    merged.retain(|(name, addresses)| name.is_some() || !addresses.is_empty());
+    merged
+}
+
+/// # EmailAddress 对象
+fn address_value((name, email): &(Option<String>, String)) -> Value {
+    json!({ "name": name, "email": email })
+}
+
+/// # 按形式解析字段值
+/// ## 参数
+/// - raw: 字段的原始值
+/// - form: 形式
+fn form_value(raw: &str, form: Form) -> Value {
+    let text = unfold(raw);
+    match form {
+        Form::Raw => json!(raw),
+        Form::Text => json!(decode_words(text.trim())),
+        Form::Addresses => json!(address_list(&text).iter().flat_map(|(_, addresses)| addresses.iter().map(address_value)).collect::<Vec<_>>()),
+        Form::GroupedAddresses => json!(
+            address_list(&text)
+                .iter()
+                .map(|(name, addresses)| json!({ "name": name, "addresses": addresses.iter().map(address_value).collect::<Vec<_>>() }))
+                .collect::<Vec<_>>()
+        ),
+        Form::MessageIds => {
+            let ids = bracketed(&text);
+            let ids = match ids.is_empty() {
+                true => text.split_whitespace().filter(|token| token.contains('@')).map(String::from).collect(),
+                false => ids,
+            };
+            if ids.is_empty() { Value::Null } else { json!(ids) }
+        }
+        Form::Date => {
+            // 去掉结尾的注释，例如 (CST)
+            let text = text.split('(').next().unwrap_or_default().trim();
+            match DateTime::parse_from_rfc2822(text) {
+                Ok(date) => json!(date.to_rfc3339_opts(SecondsFormat::Secs, true)),
+                Err(_) => Value::Null,
+            }
+        }
+        Form::Urls => {
+            let urls = bracketed(&text);
+            if urls.is_empty() { Value::Null } else { json!(urls) }
+        }
+    }
+}
+
+/// # 尖括号中的内容
+fn bracketed(text: &str) -> Vec<String> {
+    text.split('<').skip(1).filter_map(|segment| segment.split_once('>')).map(|(inner, _)| inner.trim().to_string()).filter(|inner| !inner.is_empty()).collect()
+}
+
+/// # header:名称:形式 属性的值
+/// ## 参数
+/// - fields: 邮件头的原始字段
+/// - property: 属性名
+/// ## 返回值
+/// - Value，没有该字段时为 null，:all 时为空数组
+fn header_value(fields: &[(String, String)], property: &str) -> Value {
+    let Some((name, form, all)) = header_property(property) else {
+        return Value::Null;
+    };
+    let mut values = fields.iter().filter(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, raw)| form_value(raw, form));
+    match all {
+        true => Value::Array(values.collect()),
+        false => values.next_back().unwrap_or(Value::Null),
+    }
+}
+
+/// MIME 结构中的一部分
+struct Node<'p, 'a> {
+    part: &'p Part<'a>,
+    /// 部分编号，multipart 为 None
+    path: Option<Vec<u32>>,
+    /// 例如 text/plain
+    kind: String,
+    disposition: Option<String>,
+    name: Option<String>,
+    children: Vec<Node<'p, 'a>>,
+}
+
+/// # 建立 MIME 结构
+/// ## 参数
+/// - part: 邮件或其中一部分
+/// - path: 部分编号，邮件本身为空
+fn node<'p, 'a>(part: &'p Part<'a>, path: Vec<u32>) -> Node<'p, 'a> {
+    let field = part.field("Content-Disposition");
+    let disposition = field.as_deref().map(mime::parameters);
+    let name = disposition.as_ref().and_then(|(_, parameters)| parameter(parameters, "filename")).or_else(|| parameter(&part.parameters, "name"));
+    let kind = format!("{}/{}", part.media_type, part.subtype);
+    let disposition = disposition.map(|(value, _)| value.to_ascii_lowercase()).filter(|value| !value.is_empty());
+    if part.is_multipart() {
+        let children = part
+            .children
+            .iter()
+            .enumerate()
+            .map(|(index, child)| node(child, [path.as_slice(), &[index as u32 + 1]].concat()))
+            .collect();
+        return Node { part, path: None, kind, disposition, name, children };
+    }
+    let path = if path.is_empty() { vec![1] } else { path };
+    Node { part, path: Some(path), kind, disposition, name, children: Vec::new() }
+}
+
+/// # 是否为可以内嵌显示的媒体类型
+fn is_inline_media(kind: &str) -> bool {
+    kind.starts_with("image/") || kind.starts_with("audio/") || kind.starts_with("video/")
+}
+
+type Nodes<'n, 'p, 'a> = Vec<&'n Node<'p, 'a>>;
+
+/// # 找出 textBody、htmlBody 与 attachments（RFC 8621 4.1.4）
+fn classify<'n, 'p, 'a>(
+    nodes: &'n [Node<'p, 'a>],
+    multipart: &str,
+    in_alternative: bool,
+    mut html: Option<&mut Nodes<'n, 'p, 'a>>,
+    mut text: Option<&mut Nodes<'n, 'p, 'a>>,
+    attachments: &mut Nodes<'n, 'p, 'a>,
+) {
+    let text_length = text.as_ref().map(|text| text.len());
+    let html_length = html.as_ref().map(|html| html.len());
+    for (index, node) in nodes.iter().enumerate() {
+        let inline_media = is_inline_media(&node.kind);
+        let inline = node.disposition.as_deref() != Some("attachment")
+            && (node.kind == "text/plain" || node.kind == "text/html" || inline_media)
+            && (index == 0 || (multipart != "related" && (inline_media || node.name.is_none())));
+        if node.path.is_none() {
+            let subtype = node.part.subtype.as_ref();
+            classify(&node.children, subtype, in_alternative || subtype == "alternative", html.as_deref_mut(), text.as_deref_mut(), attachments);
+        } else if inline {
+            if multipart == "alternative" {
+                match node.kind.as_str() {
+                    "text/plain" => text.as_deref_mut().into_iter().for_each(|text| text.push(node)),
+                    "text/html" => html.as_deref_mut().into_iter().for_each(|html| html.push(node)),
+                    _ => attachments.push(node),
+                }
+                continue;
+            } else if in_alternative {
+                if node.kind == "text/plain" {
+                    html = None;
+                }
+                if node.kind == "text/html" {
+                    text = None;
+                }
+            }
+            if let Some(text) = text.as_deref_mut() {
+                text.push(node);
+            }
+            if let Some(html) = html.as_deref_mut() {
+                html.push(node);
+            }
+            if (text.is_none() || html.is_none()) && inline_media {
+                attachments.push(node);
+            }
+        } else {
+            attachments.push(node);
+        }
+    }
+    if multipart == "alternative"
+        && let (Some(text), Some(html), Some(text_length), Some(html_length)) = (text, html, text_length, html_length)
+    {
+        if text_length == text.len() && html_length != html.len() {
+            text.extend_from_slice(&html[html_length..]);
+        }
+        if html_length == html.len() && text_length != text.len() {
+            html.extend_from_slice(&text[text_length..]);
+        }
+    }
+}
+
+/// # 去掉 HTML 标签
+fn strip_html(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' if in_tag => {
+                in_tag = false;
+                output.push(' ');
+            }
+            _ if !in_tag => output.push(c),
+            _ => {}
+        }
+    }
+    output.replace("&nbsp;", " ").replace("&lt;", "<").replace("&gt;", ">").replace("&quot;", "\"").replace("&amp;", "&")
+}
+
+/// 解析后的邮件
+struct Parsed<'p, 'a> {
+    root: Node<'p, 'a>,
+    /// blob id 的基础：邮件 id 与部分编号，无法引用时为 None
+    base: Option<(i64, Vec<u32>)>,
+}
+
+impl Parsed<'_, '_> {
+    /// # EmailBodyPart 对象
+    fn body_part(&self, node: &Node, properties: &[String], structure: bool) -> Value {
+        let mut object = Map::new();
+        let fields = raw_fields(node.part.header);
+        for property in properties {
+            let value = match property.as_str() {
+                "partId" => json!(node.path.as_ref().map(|path| path.iter().map(u32::to_string).collect::<Vec<_>>().join("."))),
+                "blobId" => json!(node.path.as_ref().and_then(|path| {
+                    let (message_id, base) = self.base.as_ref()?;
+                    Some(blob::message_blob(*message_id, &[base.as_slice(), path].concat()))
+                })),
+                "size" => json!(if node.path.is_some() { node.part.decode().len() } else { 0 }),
+                "headers" => json!(fields.iter().map(|(name, value)| json!({ "name": name, "value": value })).collect::<Vec<_>>()),
+                "name" => json!(node.name),
+                "type" => json!(node.kind),
+                "charset" => match node.part.media_type.as_ref() {
+                    "text" => json!(node.part.parameter("charset").unwrap_or("us-ascii")),
+                    _ => json!(node.part.parameter("charset")),
+                },
+                "disposition" => json!(node.disposition),
+                "cid" => json!(node.part.field("Content-ID").map(|id| id.trim().trim_start_matches('<').trim_end_matches('>').to_string())),
+                "language" => json!(node.part.field("Content-Language").map(|value| value.split(',').map(|tag| tag.trim().to_string()).collect::<Vec<_>>())),
+                "location" => json!(node.part.field("Content-Location")),
+                "subParts" => continue,
+                property => header_value(&fields, property),
+            };
+            object.insert(property.clone(), value);
+        }
+        if node.path.is_none() && (structure || properties.iter().any(|property| property == "subParts")) {
+            let children: Vec<Value> = node.children.iter().map(|child| self.body_part(child, properties, structure)).collect();
+            object.insert(String::from("subParts"), json!(children));
+        }
+        Value::Object(object)
+    }
+}
+
+/// # 邮件内容的属性
+/// ## 参数
+/// - raw: 邮件原文
+/// - base: blob id 的基础：邮件 id 与部分编号，无法引用时为 None
+/// - properties: 要返回的属性，忽略元数据属性
+/// - options: 正文参数
+/// ## 返回值
+/// - Map<String, Value>
+pub fn properties(raw: &[u8], base: Option<(i64, Vec<u32>)>, properties: &[String], options: &BodyOptions) -> Map<String, Value> {
+    let message = mime::parse(raw);
+    let parsed = Parsed { root: node(&message, Vec::new()), base };
+    let fields = raw_fields(message.header);
+
+    let mut text_body = Vec::new();
+    let mut html_body = Vec::new();
+    let mut attachments = Vec::new();
+    classify(std::slice::from_ref(&parsed.root), "mixed", false, Some(&mut html_body), Some(&mut text_body), &mut attachments);
+
+    let mut values = Map::new();
+    for property in properties {
+        let value = match property.as_str() {
+            "messageId" => header_value(&fields, "header:Message-ID:asMessageIds"),
+            "inReplyTo" => header_value(&fields, "header:In-Reply-To:asMessageIds"),
+            "references" => header_value(&fields, "header:References:asMessageIds"),
+            "sender" => header_value(&fields, "header:Sender:asAddresses"),
+            "from" => header_value(&fields, "header:From:asAddresses"),
+            "to" => header_value(&fields, "header:To:asAddresses"),
+            "cc" => header_value(&fields, "header:Cc:asAddresses"),
+            "bcc" => header_value(&fields, "header:Bcc:asAddresses"),
+            "replyTo" => header_value(&fields, "header:Reply-To:asAddresses"),
+            "subject" => header_value(&fields, "header:Subject:asText"),
+            "sentAt" => header_value(&fields, "header:Date:asDate"),
+            "headers" => json!(fields.iter().map(|(name, value)| json!({ "name": name, "value": value })).collect::<Vec<_>>()),
+            "bodyStructure" => parsed.body_part(&parsed.root, &options.properties, true),
+            "textBody" => json!(text_body.iter().map(|node| parsed.body_part(node, &options.properties, false)).collect::<Vec<_>>()),
+            "htmlBody" => json!(html_body.iter().map(|node| parsed.body_part(node, &options.properties, false)).collect::<Vec<_>>()),
+            "attachments" => json!(attachments.iter().map(|node| parsed.body_part(node, &options.properties, false)).collect::<Vec<_>>()),
+            "hasAttachment" => json!(!attachments.is_empty()),
+            "preview" => json!(preview(&text_body)),
+            "bodyValues" => {
+                let mut selected: Vec<&Node> = Vec::new();
+                let mut leaves = Vec::new();
+                collect_leaves(&parsed.root, &mut leaves);
+                if options.fetch_all {
+                    selected.extend(leaves.iter().filter(|node| node.part.media_type == "text"));
+                }
+                if options.fetch_text {
+                    selected.extend(text_body.iter().filter(|node| node.part.media_type == "text"));
+                }
+                if options.fetch_html {
+                    selected.extend(html_body.iter().filter(|node| node.part.media_type == "text"));
+                }
+                let mut body_values = Map::new();
+                for node in selected {
+                    let Some(path) = &node.path else { continue };
+                    let (mut value, problem) = node.part.text();
+                    let problem = problem || node.part.try_decode().is_err();
+                    let truncated = options.max_bytes > 0 && value.len() > options.max_bytes;
+                    if truncated {
+                        let end = (0..=options.max_bytes).rev().find(|&index| value.is_char_boundary(index)).unwrap_or(0);
+                        value.truncate(end);
+                    }
+                    let id = path.iter().map(u32::to_string).collect::<Vec<_>>().join(".");
+                    body_values.insert(id, json!({ "value": value, "isEncodingProblem": problem, "isTruncated": truncated }));
+                }
+                Value::Object(body_values)
+            }
+            property if property.starts_with("header:") => header_value(&fields, property),
+            _ => continue,
+        };
+        values.insert(property.clone(), value);
+    }
+    values
+}
+
+/// # 所有非 multipart 的部分
+fn collect_leaves<'n, 'p, 'a>(node: &'n Node<'p, 'a>, leaves: &mut Nodes<'n, 'p, 'a>) {
+    match node.path {
+        Some(_) => leaves.push(node),
+        None => node.children.iter().for_each(|child| collect_leaves(child, leaves)),
+    }
+}
+
+/// # 邮件的摘要
+/// textBody 中第一个文本部分的开头，空白合并为一个空格。
+fn preview(text_body: &[&Node]) -> String {
+    let Some(node) = text_body.iter().find(|node| node.part.media_type == "text") else {
+        return String::new();
+    };
+    let (content, _) = node.part.text();
+    let content = if node.part.subtype == "html" { strip_html(&content) } else { content };
+    content.split_whitespace().collect::<Vec<_>>().join(" ").chars().take(PREVIEW).collect()
+}
+
+/// # 是否有附件
+pub fn has_attachment(raw: &[u8]) -> bool {
+    let message = mime::parse(raw);
+    let root = node(&message, Vec::new());
+    let (mut text_body, mut html_body, mut attachments) = (Vec::new(), Vec::new(), Vec::new());
+    classify(std::slice::from_ref(&root), "mixed", false, Some(&mut html_body), Some(&mut text_body), &mut attachments);
+    !attachments.is_empty()
+}
+
+/// # 邮件头字段解码后的文本
+/// ## 参数
+/// - raw: 邮件原文
+/// - name: 字段名，不区分大小写
+/// ## 返回值
+/// - Vec<String>，每个同名字段一项
+pub fn header_text(raw: &[u8], name: &str) -> Vec<String> {
+    let message = mime::parse(raw);
+    raw_fields(message.header)
+        .into_iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| decode_words(unfold(&value).trim()))
+        .collect()
+}
+
+/// # 邮件头字段中的地址
+/// ## 参数
+/// - raw: 邮件原文
+/// - name: 字段名，例如 To
+/// ## 返回值
+/// - Vec<String>，组中的地址也包括在内
+pub fn header_addresses(raw: &[u8], name: &str) -> Vec<String> {
+    let message = mime::parse(raw);
+    raw_fields(message.header)
+        .iter()
+        .filter(|(key, _)| key.eq_ignore_ascii_case(name))
+        .flat_map(|(_, value)| address_list(&unfold(value)))
+        .flat_map(|(_, addresses)| addresses.into_iter().map(|(_, email)| email))
+        .collect()
+}
+
+/// # 用于搜索的文本
+/// ## 参数
+/// - raw: 邮件原文
+/// - field: body 为所有文本部分，text 为所有邮件头与文本部分，其他为同名的邮件头（例如 from）
+/// ## 返回值
+/// - String
+pub fn search_text(raw: &[u8], field: &str) -> String {
+    let message = mime::parse(raw);
+    let headers = || raw_fields(message.header).iter().map(|(name, value)| format!("{}: {}", name, decode_words(unfold(value).trim()))).collect::<Vec<_>>().join("\n");
+    match field {
+        "body" => body_text(&message),
+        "text" => format!("{}\n{}", headers(), body_text(&message)),
+        name => header_text(raw, name).join("\n"),
+    }
+}
+
+/// # 所有文本部分的内容
+/// HTML 去掉标签，不包括 message/rfc822 所附的邮件。
+fn body_text(part: &Part) -> String {
+    if part.is_multipart() {
+        return part.children.iter().map(body_text).filter(|text| !text.is_empty()).collect::<Vec<_>>().join("\n");
+    }
+    let disposition = part.field("Content-Disposition").unwrap_or_default().to_ascii_lowercase();
+    if part.media_type != "text" || disposition.starts_with("attachment") {
+        return String::new();
+    }
+    let (content, _) = part.text();
+    if part.subtype == "html" { strip_html(&content) } else { content }
+}
+
+/// # 发送时间
+/// ## 返回值
+/// - Option<i64>，Date 字段的 Unix 时间戳，缺少或格式错误时为 None
+pub fn sent_at(raw: &[u8]) -> Option<i64> {
+    let date = header_text(raw, "Date").pop()?;
+    DateTime::parse_from_rfc2822(date.split('(').next().unwrap_or_default().trim()).ok().map(|date| date.timestamp())
+}
+
+/// # 编码邮件头中的文本
+/// 非 ASCII 的文本使用 UTF-8 的 encoded-word（RFC 2047），每个不超过 75 个字符。
+fn encode_text(text: &str) -> String {
+    if text.is_ascii() && !text.contains(|c: char| c.is_ascii_control() && c != '\t') {
+        return text.to_string();
+    }
+    let mut words = Vec::new();
+    let mut chunk = String::new();
+    for c in text.chars() {
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&chunk)));
+            chunk.clear();
+        }
+        chunk.push(c);
+    }
+    if !chunk.is_empty() {
+        words.push(format!("=?UTF-8?B?{}?=", BASE64.encode(&chunk)));
+    }
+    words.join("\r\n ")
+}
+
+/// # 编码显示名
+/// 只含 atext 与空格时原样使用，其他 ASCII 加引号，非 ASCII 使用 encoded-word。
+fn encode_phrase(name: &str) -> String {
+    if !name.is_ascii() {
+        return encode_text(name);
+    }
+    if name.chars().all(|c| c.is_ascii_alphanumeric() || " !#$%&'*+-/=?^_`{|}~".contains(c)) {
+        return name.to_string();
+    }
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// # 生成字段
+/// 超过 LINE 时在逗号或空格之后折叠。
+fn field(name: &str, items: &[String], separator: &str) -> String {
+    let mut line = format!("{}: ", name);
+    let mut length = line.len();
+    for (index, item) in items.iter().enumerate() {
+        if index > 0 {
+            line.push_str(separator.trim_end());
+            length += separator.trim_end().len();
+            if length + item.len() + 1 > LINE {
+                line.push_str("\r\n ");
+                length = 1;
+            } else {
+                line.push(' ');
+                length += 1;
+            }
+        }
+        line.push_str(item);
+        length += item.rsplit('\n').next().unwrap_or_default().len();
+    }
+    line.push_str("\r\n");
+    line
+}
+
+/// 生成邮件时的错误：属性名与说明，对应 invalidProperties
+pub type Invalid = (String, String);
+
+/// 按 blob id 读取内容，返回 (Content-Type, 内容)
+pub type Blobs<'a> = dyn Fn(&str) -> Option<(String, Vec<u8>)> + 'a;
+
+/// # 按形式生成字段值
+/// ## 返回值
+/// - Option<Vec<String>>，值与形式不符时为 None，多个值用于 :all
+fn format_header(value: &Value, form: Form, all: bool) -> Option<Vec<String>> {
+    let values: Vec<&Value> = match all {
+        true => value.as_array()?.iter().collect(),
+        false => vec![value],
+    };
+    values
+        .into_iter()
+        .map(|value| {
+            Some(match form {
+                Form::Raw => value.as_str()?.trim_start().to_string(),
+                Form::Text => encode_text(value.as_str()?),
+                Form::Addresses => addresses(value)?.join(", "),
+                Form::GroupedAddresses => value
+                    .as_array()?
+                    .iter()
+                    .map(|group| {
+                        let list = addresses(&group["addresses"])?.join(", ");
+                        Some(match group["name"].as_str() {
+                            Some(name) => format!("{}: {};", encode_phrase(name), list),
+                            None => list,
+                        })
+                    })
+                    .collect::<Option<Vec<_>>>()?
+                    .join(", "),
+                Form::MessageIds => message_ids(value)?.join(" "),
+                Form::Date => DateTime::parse_from_rfc3339(value.as_str()?).ok()?.to_rfc2822(),
+                Form::Urls => value.as_array()?.iter().map(|url| url.as_str().map(|url| format!("<{}>", url))).collect::<Option<Vec<_>>>()?.join(", "),
+            })
+        })
+        .collect()
+}
+
+/// # 生成地址
+fn addresses(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|address| {
+            let email = address["email"].as_str()?;
+            if email.contains(['<', '>', '\r', '\n', ',']) {
+                return None;
+            }
+            Some(match address["name"].as_str().filter(|name| !name.is_empty()) {
+                Some(name) => format!("{} <{}>", encode_phrase(name), email),
+                None => email.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// # 生成 Message-ID 列表
+fn message_ids(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|id| id.as_str().filter(|id| !id.is_empty() && !id.contains(['<', '>', ' ', '\r', '\n'])).map(|id| format!("<{}>", id)))
+        .collect()
+}
+
+/// 要生成的一部分
+struct Spec {
+    /// 例如 text/plain
+    kind: String,
+    /// Content-Type、Content-Disposition 与 header:* 之外的邮件头
+    headers: Vec<String>,
+    disposition: Option<String>,
+    name: Option<String>,
+    cid: Option<String>,
+    language: Option<Vec<String>>,
+    location: Option<String>,
+    content: Content,
+}
+
+enum Content {
+    Text(String),
+    Binary(Vec<u8>),
+    Multipart(Vec<Spec>),
+}
+
+/// # 随机的十六进制字符串
+fn random_hex(length: usize) -> String {
+    let mut bytes = vec![0u8; length.div_ceil(2)];
+    OsRng.fill_bytes(&mut bytes);
+    to_hex(&bytes)[..length].to_string()
+}
+
+/// # 解析 Email/set 中的 EmailBodyPart
+/// ## 参数
+/// - value: EmailBodyPart 对象
+/// - property: 所在的属性，用于错误信息
+/// - values: bodyValues
+/// - blobs: 读取 blob，返回 (Content-Type, 内容)
+fn spec(value: &Value, property: &str, values: &Map<String, Value>, blobs: &Blobs) -> Result<Spec, Invalid> {
+    let invalid = |description: &str| (property.to_string(), description.to_string());
+    let object = value.as_object().ok_or_else(|| invalid("EmailBodyPart must be an object"))?;
+    let optional = |key: &str| object.get(key).and_then(Value::as_str).map(String::from);
+    let mut kind = optional("type").map(|kind| kind.to_ascii_lowercase());
+    let content = if let Some(parts) = object.get("subParts").filter(|parts| !parts.is_null()) {
+        let parts = parts.as_array().ok_or_else(|| invalid("subParts must be an array"))?;
+        let kind = kind.get_or_insert_with(|| String::from("multipart/mixed"));
+        if !kind.starts_with("multipart/") {
+            return Err(invalid("Parts with subParts must be multipart"));
+        }
+        Content::Multipart(parts.iter().map(|part| spec(part, property, values, blobs)).collect::<Result<_, _>>()?)
+    } else if let Some(id) = object.get("partId").and_then(Value::as_str) {
+        if object.get("blobId").is_some_and(|blob| !blob.is_null()) {
+            return Err(invalid("Cannot specify both partId and blobId"));
+        }
+        if object.get("charset").is_some_and(|charset| !charset.is_null()) {
+            return Err(invalid("charset must be omitted when partId is given"));
+        }
+        let value = values.get(id).and_then(|value| value["value"].as_str()).ok_or_else(|| invalid(&format!("bodyValues has no {}", id)))?;
+        let kind = kind.get_or_insert_with(|| String::from("text/plain"));
+        if !kind.starts_with("text/") {
+            return Err(invalid("partId can only be used for text parts"));
+        }
+        Content::Text(value.to_string())
+    } else if let Some(id) = object.get("blobId").and_then(Value::as_str) {
+        let (content_type, data) = blobs(id).ok_or_else(|| (String::from("blobId"), format!("Blob {} not found", id)))?;
+        kind.get_or_insert(content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase());
+        Content::Binary(data)
+    } else {
+        return Err(invalid("EmailBodyPart requires partId, blobId or subParts"));
+    };
+    let kind = kind.unwrap_or_default();
+    if !kind.contains('/') || kind.contains(|c: char| c.is_ascii_whitespace() || c == ';') {
+        return Err(invalid("Invalid type"));
+    }
+
+    let mut headers = Vec::new();
+    for (key, value) in object {
+        if let Some((name, form, all)) = header_property(key) {
+            if name.to_ascii_lowercase().starts_with("content-") {
+                return Err(invalid("Content-* headers must be set through EmailBodyPart properties"));
+            }
+            let items = format_header(value, form, all).ok_or_else(|| invalid(&format!("Invalid value for {}", key)))?;
+            headers.extend(items.iter().map(|item| field(name, std::slice::from_ref(item), "")));
+        } else if key == "headers" {
+            for header in value.as_array().ok_or_else(|| invalid("headers must be an array"))? {
+                let (Some(name), Some(value)) = (header["name"].as_str(), header["value"].as_str()) else {
+                    return Err(invalid("Invalid headers"));
+                };
+                headers.push(format!("{}:{}\r\n", name, value));
+            }
+        }
+    }
+    Ok(Spec {
+        kind,
+        headers,
+        disposition: optional("disposition"),
+        name: optional("name"),
+        cid: optional("cid"),
+        language: object.get("language").and_then(Value::as_array).map(|tags| tags.iter().filter_map(|tag| tag.as_str().map(String::from)).collect()),
+        location: optional("location"),
+        content,
+    })
+}
+
+/// # 参数值
+/// 非 ASCII 时使用 RFC 2231 的编码。
+fn parameter_value(name: &str, value: &str) -> String {
+    if value.is_ascii() {
+        return format!("; {}=\"{}\"", name, value.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+    let encoded: String = value
+        .bytes()
+        .map(|byte| match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!(";\r\n {}*=UTF-8''{}", name, encoded)
+}
+
+/// # 编码 quoted-printable（RFC 2045 6.7）
+fn encode_quoted_printable(text: &str) -> String {
+    let mut output = String::with_capacity(text.len() * 2);
+    for line in text.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut length = 0;
+        let bytes = line.as_bytes();
+        for (index, &byte) in bytes.iter().enumerate() {
+            let last = index + 1 == bytes.len();
+            let encoded = match byte {
+                b' ' | b'\t' if last => format!("={:02X}", byte),
+                b'=' => String::from("=3D"),
+                b' ' | b'\t' | b'!'..=b'~' => (byte as char).to_string(),
+                _ => format!("={:02X}", byte),
+            };
+            if length + encoded.len() > 75 {
+                output.push_str("=\r\n");
+                length = 0;
+            }
+            length += encoded.len();
+            output.push_str(&encoded);
+        }
+        output.push_str("\r\n");
+    }
+    // split 在结尾的换行之后产生一个空行
+    if text.ends_with('\n') || text.is_empty() {
+        output.truncate(output.len() - 2);
+    }
+    output
+}
+
+/// # 生成一部分
+/// ## 参数
+/// - spec: 要生成的部分
+/// - output: 输出，包括邮件头与正文
+fn render(spec: &Spec, output: &mut String, binary: &mut Vec<u8>) {
+    let mut content_type = spec.kind.clone();
+    let boundary = format!("=_{}", random_hex(24));
+    match &spec.content {
+        Content::Text(_) => content_type.push_str("; charset=utf-8"),
+        Content::Multipart(_) => content_type.push_str(&format!("; boundary=\"{}\"", boundary)),
+        Content::Binary(_) => {}
+    }
+    if let (Some(name), None) = (&spec.name, &spec.disposition) {
+        content_type.push_str(&parameter_value("name", name));
+    }
+    output.push_str(&format!("Content-Type: {}\r\n", content_type));
+    if let Some(disposition) = &spec.disposition {
+        let filename = spec.name.as_deref().map(|name| parameter_value("filename", name)).unwrap_or_default();
+        output.push_str(&format!("Content-Disposition: {}{}\r\n", disposition, filename));
+    }
+    if let Some(cid) = &spec.cid {
+        output.push_str(&format!("Content-ID: <{}>\r\n", cid.trim_start_matches('<').trim_end_matches('>')));
+    }
+    if let Some(language) = &spec.language {
+        output.push_str(&format!("Content-Language: {}\r\n", language.join(", ")));
+    }
+    if let Some(location) = &spec.location {
+        output.push_str(&format!("Content-Location: {}\r\n", location));
+    }
+    for header in &spec.headers {
+        output.push_str(header);
+    }
+    match &spec.content {
+        Content::Text(text) if text.is_ascii() && text.lines().all(|line| line.len() <= 998) => {
+            output.push_str("Content-Transfer-Encoding: 7bit\r\n\r\n");
+            output.push_str(&text.replace("\r\n", "\n").replace('\n', "\r\n"));
+        }
+        Content::Text(text) => {
+            output.push_str("Content-Transfer-Encoding: quoted-printable\r\n\r\n");
+            output.push_str(&encode_quoted_printable(text));
+        }
+        Content::Binary(data) if spec.kind.starts_with("message/") => {
+            output.push_str("\r\n");
+            flush(output, binary);
+            binary.extend_from_slice(data);
+        }
+        Content::Binary(data) => {
+            output.push_str("Content-Transfer-Encoding: base64\r\n\r\n");
+            let encoded = BASE64.encode(data);
+            let lines: Vec<&str> = encoded.as_bytes().chunks(76).map(|line| std::str::from_utf8(line).unwrap_or_default()).collect();
+            output.push_str(&lines.join("\r\n"));
+        }
+        Content::Multipart(parts) => {
+            output.push_str("\r\n");
+            for part in parts {
+                output.push_str(&format!("--{}\r\n", boundary));
+                render(part, output, binary);
+                output.push_str("\r\n");
+            }
+            output.push_str(&format!("--{}--", boundary));
+        }
+    }
+}
+
+/// # 把文本输出移到字节输出中
+/// message/* 部分原样输出，可能不是 UTF-8。
+fn flush(output: &mut String, binary: &mut Vec<u8>) {
+    binary.extend_from_slice(output.as_bytes());
+    output.clear();
+}
+
+/// # 生成邮件（Email/set 的 create）
+/// 忽略 mailboxIds、keywords 与 receivedAt，由调用者处理。缺少 Date 与 Message-ID 时自动添加。
+/// ## 参数
+/// - email: Email 对象
+/// - blobs: 读取 blob，返回 (Content-Type, 内容)
+/// - hostname: 用于生成 Message-ID
+/// ## 返回值
+/// - Result<Vec<u8>, Invalid>
+pub fn compose(email: &Map<String, Value>, blobs: &Blobs, hostname: &str) -> Result<Vec<u8>, Invalid> {
+    let empty = Map::new();
+    let values = match email.get("bodyValues") {
+        None | Some(Value::Null) => &empty,
+        Some(Value::Object(values)) => values,
+        Some(_) => return Err((String::from("bodyValues"), String::from("bodyValues must be an object"))),
+    };
+    let mut header = String::new();
+    let mut names: Vec<String> = Vec::new();
+    for (key, value) in email {
+        let convenience = match key.as_str() {
+            "mailboxIds" | "keywords" | "receivedAt" | "bodyValues" | "bodyStructure" | "textBody" | "htmlBody" | "attachments" => continue,
+            "messageId" => Some(("Message-ID", Form::MessageIds)),
+            "inReplyTo" => Some(("In-Reply-To", Form::MessageIds)),
+            "references" => Some(("References", Form::MessageIds)),
+            "sender" => Some(("Sender", Form::Addresses)),
+            "from" => Some(("From", Form::Addresses)),
+            "to" => Some(("To", Form::Addresses)),
+            "cc" => Some(("Cc", Form::Addresses)),
+            "bcc" => Some(("Bcc", Form::Addresses)),
+            "replyTo" => Some(("Reply-To", Form::Addresses)),
+            "subject" => Some(("Subject", Form::Text)),
+            "sentAt" => Some(("Date", Form::Date)),
+            _ => None,
+        };
+        let (name, form, all) = match convenience {
+            Some((name, form)) => (name, form, false),
+            None if key == "headers" => {
+                for item in value.as_array().ok_or_else(|| (key.clone(), String::from("headers must be an array")))? {
+                    let (Some(name), Some(raw)) = (item["name"].as_str(), item["value"].as_str()) else {
+                        return Err((key.clone(), String::from("Invalid EmailHeader")));
+                    };
+                    names.push(name.to_ascii_lowercase());
+                    header.push_str(&format!("{}:{}\r\n", name, raw));
+                }
+                continue;
+            }
+            None => header_property(key).ok_or_else(|| (key.clone(), String::from("Unknown or server-set property")))?,
+        };
+        if value.is_null() {
+            continue;
+        }
+        if name.to_ascii_lowercase().starts_with("content-") {
+            return Err((key.clone(), String::from("Content-* headers must be set through body parts")));
+        }
+        let items = format_header(value, form, all).ok_or_else(|| (key.clone(), format!("Invalid value for {}", key)))?;
+        names.push(name.to_ascii_lowercase());
+        let separator = if matches!(form, Form::Addresses | Form::GroupedAddresses | Form::Urls) { "," } else { "" };
+        for item in items {
+            match separator {
+                "," => header.push_str(&field(name, &item.split(", ").map(String::from).collect::<Vec<_>>(), ",")),
+                _ => header.push_str(&format!("{}: {}\r\n", name, item)),
+            }
+        }
+    }
+    if !names.iter().any(|name| name == "date") {
+        header.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+    }
+    if !names.iter().any(|name| name == "message-id") {
+        header.push_str(&format!("Message-ID: <{}.{}@{}>\r\n", Utc::now().timestamp_millis(), random_hex(16), hostname));
+    }
+    header.push_str("MIME-Version: 1.0\r\n");
+
+    let single = |key: &str| -> Result<Option<Spec>, Invalid> {
+        match email.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Array(parts)) if parts.len() == 1 => spec(&parts[0], key, values, blobs).map(Some),
+            Some(_) => Err((key.to_string(), format!("{} must contain exactly one part", key))),
+        }
+    };
+    let structure = match email.get("bodyStructure").filter(|structure| !structure.is_null()) {
+        Some(structure) => {
+            if ["textBody", "htmlBody", "attachments"].iter().any(|key| email.get(*key).is_some_and(|value| !value.is_null())) {
+                return Err((String::from("bodyStructure"), String::from("Cannot combine bodyStructure with textBody, htmlBody or attachments")));
+            }
+            spec(structure, "bodyStructure", values, blobs)?
+        }
+        None => {
+            let text = single("textBody")?;
+            let html = single("htmlBody")?;
+            if text.as_ref().is_some_and(|text| text.kind != "text/plain") || html.as_ref().is_some_and(|html| html.kind != "text/html") {
+                return Err((String::from("textBody"), String::from("textBody must be text/plain and htmlBody must be text/html")));
+            }
+            let body = match (text, html) {
+                (Some(text), Some(html)) => Some(multipart("alternative", vec![text, html])),
+                (text, html) => text.or(html),
+            };
+            let attachments = match email.get("attachments") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Array(parts)) => parts
+                    .iter()
+                    .map(|part| {
+                        // 附件未指定 disposition 时标记为 attachment，否则接收方可能把文本附件当作正文显示
+                        let mut spec = spec(part, "attachments", values, blobs)?;
+                        spec.disposition.get_or_insert_with(|| String::from("attachment"));
+                        Ok(spec)
+                    })
+                    .collect::<Result<_, Invalid>>()?,
+                Some(_) => return Err((String::from("attachments"), String::from("attachments must be an array"))),
+            };
+            let body = body.unwrap_or_else(|| leaf(String::from("text/plain"), Content::Text(String::new())));
+            match attachments.is_empty() {
+                true => body,
+                false => multipart("mixed", std::iter::once(body).chain(attachments).collect()),
+            }
+        }
+    };
+
+    let mut output = header;
+    let mut binary = Vec::new();
+    render(&structure, &mut output, &mut binary);
+    output.push_str("\r\n");
+    flush(&mut output, &mut binary);
+    Ok(binary)
+}
+
+/// # 只有类型与内容的部分
+fn leaf(kind: String, content: Content) -> Spec {
+    Spec { kind, headers: Vec::new(), disposition: None, name: None, cid: None, language: None, location: None, content }
+}
+
+/// # multipart 部分
+fn multipart(subtype: &str, parts: Vec<Spec>) -> Spec {
+    leaf(format!("multipart/{}", subtype), Content::Multipart(parts))
+}
+
+/// # 接收时间的格式
+/// UTCDate（RFC 8620 1.4），例如 2024-01-01T00:00:00Z。
+pub fn utc_date(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
@@ -0,0 +1,84 @@
+/* JMAP 接口 */
+/*
+# JMAP 模块
+## 结构
+server.rs      监听 [WebServer] 的端口：鉴权、会话资源、上传、下载与 EventSource 推送
+request.rs     API 请求：方法调用、结果引用与创建 id（RFC 8620 3）
+state.rs       状态字符串与 /changes 的计算
+blob.rs        blob id 与上传的内容
+message.rs     邮件解析为 Email 的属性，由 Email 的属性生成邮件（RFC 8621 4）
+mailbox.rs     Mailbox 的方法
+email.rs       Email、Thread 与 SearchSnippet 的方法
+submission.rs  Identity 与 EmailSubmission 的方法
+## 用法
+let handle = jmap::server::start(&config, tls, store)?;   <-- 在后台线程中监听
+GET  /.well-known/jmap                      重定向到 /jmap/session
+GET  /jmap/session                          会话资源
+POST /jmap/api                              方法调用
+POST /jmap/upload/<账号>/                   上传 blob
+GET  /jmap/download/<账号>/<blob>/<文件名>  下载 blob
+GET  /jmap/eventsource                      推送状态变化（text/event-stream）
+## 扩展
+urn:ietf:params:jmap:core、urn:ietf:params:jmap:mail、urn:ietf:params:jmap:submission。
+## 说明
+使用 HTTP Basic 认证，用户名为完整的邮件地址，密码与 IMAP 相同；每个邮件账号对应一个 JMAP 账号。
+邮箱与 IMAP 共用，每封邮件只属于一个邮箱，移动邮件时 id 不变；每封邮件单独作为一个 Thread。
+Mailbox、Email 与 Thread 的状态由各邮箱的修改序列得出，IMAP 与投递造成的变化同样体现在 /changes 中。
+上传的 blob 保存在内存中，RETENTION 之后或重启后失效；EmailSubmission 同样只保存在内存中，
+重启后不再列出，投递状态仍可通过 API /delivery 查询。
+每个连接分配一个递增的编号，日志以「JMAP #编号」开头。
+ */
+pub mod blob;
+pub mod email;
+pub mod mailbox;
+pub mod message;
+pub mod request;
+pub mod server;
+pub mod state;
+pub mod submission;
+
+/// 核心能力（RFC 8620 2）
+pub const CORE: &str = "urn:ietf:params:jmap:core";
+
+/// 邮件能力（RFC 8621 1.3.1）
+pub const MAIL: &str = "urn:ietf:params:jmap:mail";
+
+/// 发送能力（RFC 8621 1.3.2）
+pub const SUBMISSION: &str = "urn:ietf:params:jmap:submission";
+
+/// API 请求的大小上限
+pub const MAX_REQUEST: usize = 10 * 1024 * 1024;
+
+/// 单个请求中方法调用的数量上限
+pub const MAX_CALLS: usize = 64;
+
+/// 单次 /get 的对象数量上限
+pub const MAX_OBJECTS_GET: usize = 1000;
+
+/// 单次 /set 的对象数量上限
+pub const MAX_OBJECTS_SET: usize = 500;
+
+/// # 对象的 id
+/// JMAP 的 id 只能包含字母、数字、- 与 _（RFC 8620 1.2），以前缀区分对象类型，例如 M12 为邮箱 #12。
+/// ## 参数
+/// - prefix: 前缀
+/// - number: 存储中的 id
+/// ## 返回值
+/// - String
+pub fn id(prefix: char, number: i64) -> String {
+    format!("{}{}", prefix, number)
+}
+
+/// # 解析对象的 id
+/// ## 参数
+/// - prefix: 前缀
+/// - id: 客户端提供的 id
+/// ## 返回值
+/// - Option<i64>，前缀不符或格式错误时为 None
+pub fn parse_id(prefix: char, id: &str) -> Option<i64> {
+    let digits = id.strip_prefix(prefix)?;
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
@@ -0,0 +1,477 @@
+/* JMAP API 请求 */
+/*
+# 方法调用
+## 用法
+let mut context = Context { store, account: &account, peer, hostname, created: HashMap::new() };
+let response = request::process(&request.body, &mut context);   <-- POST /jmap/api 的响应
+## 说明
+依次执行每个方法调用，前一个调用出错不影响后续调用（RFC 8620 3.6.2）。
+参数名以 # 开头时为结果引用，按 JSON Pointer 从之前的响应中取值，支持 * 展开数组（RFC 8620 3.7）。
+/set 创建的对象可以在之后的调用中以 #创建 id 引用，Context.created 记录创建 id 与实际 id 的对应关系。
+请求级别的错误以 HTTP 400 与 problem details（RFC 7807）返回，方法级别的错误以 error 响应返回。
+ */
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use super::{CORE, MAIL, MAX_CALLS, MAX_OBJECTS_SET, SUBMISSION};
+use super::state::Changes;
+use super::{email, mailbox, submission};
+use crate::api::http::Response;
+use crate::storage::{Account, MailStore, Mailbox, MessageInfo, StorageError};
+
+/// 方法级别的错误（RFC 8620 3.6.2）
+#[derive(Debug)]
+pub struct MethodError {
+    /// 例如 invalidArguments
+    pub kind: &'static str,
+    pub description: Option<String>,
+}
+
+impl MethodError {
+    pub fn new(kind: &'static str) -> MethodError {
+        MethodError { kind, description: None }
+    }
+
+    /// # 带说明的错误
+    pub fn describe(kind: &'static str, description: impl Into<String>) -> MethodError {
+        MethodError { kind, description: Some(description.into()) }
+    }
+}
+
+impl From<StorageError> for MethodError {
+    fn from(e: StorageError) -> Self {
+        error!("JMAP 存储操作失败：{}", e);
+        MethodError::new("serverFail")
+    }
+}
+
+pub type Result<T> = std::result::Result<T, MethodError>;
+
+/// 一次方法调用的响应，EmailSubmission/set 之后可以附带 Email/set 的响应
+pub type Output = Vec<(&'static str, Value)>;
+
+/// 方法调用的上下文
+pub struct Context<'a> {
+    pub store: &'a dyn MailStore,
+    pub account: &'a Account,
+    /// 客户端地址，用于发送邮件的 Received 邮件头
+    pub peer: IpAddr,
+    /// [SMTP] Hostname
+    pub hostname: &'a str,
+    /// 本次请求中创建的对象，创建 id 与实际 id
+    pub created: HashMap<String, String>,
+}
+
+impl Context<'_> {
+    /// # 账号的 JMAP id
+    pub fn account_id(&self) -> String {
+        super::id('A', self.account.id)
+    }
+
+    /// # 引用创建的对象
+    /// ## 参数
+    /// - id: 对象的 id，或以 # 开头的创建 id
+    /// ## 返回值
+    /// - Option<String>，创建 id 不存在时为 None
+    pub fn resolve(&self, id: &str) -> Option<String> {
+        match id.strip_prefix('#') {
+            Some(creation) => self.created.get(creation).cloned(),
+            None => Some(id.to_string()),
+        }
+    }
+
+    /// # 账号的所有邮箱
+    pub fn mailboxes(&self) -> Result<Vec<Mailbox>> {
+        Ok(self.store.mailboxes(self.account.id)?)
+    }
+
+    /// # 账号的所有邮件
+    /// ## 参数
+    /// - mailboxes: 账号的所有邮箱
+    pub fn messages(&self, mailboxes: &[Mailbox]) -> Result<Vec<MessageInfo>> {
+        let mut messages = Vec::new();
+        for mailbox in mailboxes {
+            messages.extend(self.store.messages(mailbox.id)?);
+        }
+        Ok(messages)
+    }
+}
+
+/// # 处理 API 请求
+/// ## 参数
+/// - body: 请求体
+/// - context: 上下文
+/// ## 返回值
+/// - Response
+pub fn process(body: &[u8], context: &mut Context) -> Response {
+    let Ok(request) = serde_json::from_slice::<Value>(body) else {
+        return problem("notJSON", "The request body is not valid JSON");
+    };
+    let (Some(using), Some(calls)) = (request["using"].as_array(), request["methodCalls"].as_array()) else {
+        return problem("notRequest", "The request is not a valid JMAP Request object");
+    };
+    let Some(using) = using.iter().map(|item| item.as_str().map(String::from)).collect::<Option<Vec<String>>>() else {
+        return problem("notRequest", "using must be an array of strings");
+    };
+    if let Some(unknown) = using.iter().find(|capability| ![CORE, MAIL, SUBMISSION].contains(&capability.as_str())) {
+        return problem("unknownCapability", &format!("Unknown capability {}", unknown));
+    }
+    if calls.len() > MAX_CALLS {
+        return problem_limit("maxCallsInRequest");
+    }
+    if let Some(created) = request["createdIds"].as_object() {
+        context.created.extend(created.iter().filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string()))));
+    }
+
+    let mut responses: Vec<(String, Value, String)> = Vec::new();
+    for call in calls {
+        let Some([Value::String(name), arguments, Value::String(call_id)]) = call.as_array().map(Vec::as_slice) else {
+            return problem("notRequest", "Each method call must be [name, arguments, callId]");
+        };
+        let output = references(arguments, &responses).and_then(|arguments| dispatch(name, arguments, &using, context));
+        match output {
+            Ok(output) => {
+                for (name, response) in output {
+                    responses.push((name.to_string(), response, call_id.clone()));
+                }
+            }
+            Err(e) => {
+                debug!("JMAP {} 出错：{} {}", name, e.kind, e.description.as_deref().unwrap_or_default());
+                let mut error = json!({ "type": e.kind });
+                if let Some(description) = e.description {
+                    error["description"] = json!(description);
+                }
+                responses.push((String::from("error"), error, call_id.clone()));
+            }
+        }
+    }
+
+    let responses: Vec<Value> = responses.into_iter().map(|(name, response, call_id)| json!([name, response, call_id])).collect();
+    let mut body = json!({ "methodResponses": responses, "sessionState": session_state(context.account) });
+    if request.get("createdIds").is_some() {
+        body["createdIds"] = json!(context.created);
+    }
+    Response::json(200, body)
+}
+
+/// # 会话资源的状态
+/// 会话资源只取决于账号，账号不变时状态不变。
+pub fn session_state(account: &Account) -> String {
+    format!("{:x}", super::state::digest(account.address().as_bytes()))
+}
+
+/// # 请求级别的错误
+/// ## 参数
+/// - kind: 错误类型，例如 notJSON
+/// - detail: 说明
+/// ## 返回值
+/// - Response，状态码为 400
+pub fn problem(kind: &str, detail: &str) -> Response {
+    Response::json(400, json!({ "type": format!("urn:ietf:params:jmap:error:{}", kind), "status": 400, "detail": detail }))
+}
+
+/// # 超出限制的请求
+fn problem_limit(limit: &str) -> Response {
+    Response::json(
+        400,
+        json!({ "type": "urn:ietf:params:jmap:error:limit", "status": 400, "limit": limit, "detail": format!("Request exceeds {}", limit) }),
+    )
+}
+
+/// # 按名称调用方法
+fn dispatch(name: &str, arguments: Map<String, Value>, using: &[String], context: &mut Context) -> Result<Output> {
+    let capability = match name.split('/').next().unwrap_or_default() {
+        "Core" => CORE,
+        "Mailbox" | "Email" | "Thread" | "SearchSnippet" => MAIL,
+        "Identity" | "EmailSubmission" => SUBMISSION,
+        _ => return Err(MethodError::new("unknownMethod")),
+    };
+    if !using.iter().any(|item| item == capability) {
+        return Err(MethodError::describe("unknownMethod", format!("{} requires {}", name, capability)));
+    }
+    if name == "Core/echo" {
+        return Ok(vec![("Core/echo", Value::Object(arguments))]);
+    }
+    match arguments.get("accountId").and_then(Value::as_str) {
+        Some(id) if id == context.account_id() => {}
+        Some(_) => return Err(MethodError::new("accountNotFound")),
+        None => return Err(invalid("accountId is required")),
+    }
+    let single = |name: &'static str| move |response: Value| vec![(name, response)];
+    match name {
+        "Mailbox/get" => mailbox::get(&arguments, context).map(single("Mailbox/get")),
+        "Mailbox/changes" => mailbox::changes(&arguments, context).map(single("Mailbox/changes")),
+        "Mailbox/query" => mailbox::query(&arguments, context).map(single("Mailbox/query")),
+        "Mailbox/queryChanges" => Err(MethodError::new("cannotCalculateChanges")),
+        "Mailbox/set" => mailbox::set(&arguments, context).map(single("Mailbox/set")),
+        "Thread/get" => email::threads(&arguments, context).map(single("Thread/get")),
+        "Thread/changes" => email::thread_changes(&arguments, context).map(single("Thread/changes")),
+        "Email/get" => email::get(&arguments, context).map(single("Email/get")),
+        "Email/changes" => email::changes(&arguments, context).map(single("Email/changes")),
+        "Email/query" => email::query(&arguments, context).map(single("Email/query")),
+        "Email/queryChanges" => Err(MethodError::new("cannotCalculateChanges")),
+        "Email/set" => email::set(&arguments, context).map(single("Email/set")),
+        "Email/import" => email::import(&arguments, context).map(single("Email/import")),
+        "Email/parse" => email::parse(&arguments, context).map(single("Email/parse")),
+        "SearchSnippet/get" => email::snippets(&arguments, context).map(single("SearchSnippet/get")),
+        "Identity/get" => submission::identities(&arguments, context).map(single("Identity/get")),
+        "Identity/changes" => submission::identity_changes(&arguments, context).map(single("Identity/changes")),
+        "Identity/set" => submission::set_identities(&arguments, context).map(single("Identity/set")),
+        "EmailSubmission/get" => submission::get(&arguments, context).map(single("EmailSubmission/get")),
+        "EmailSubmission/changes" => Err(MethodError::new("cannotCalculateChanges")),
+        "EmailSubmission/query" => submission::query(&arguments, context).map(single("EmailSubmission/query")),
+        "EmailSubmission/queryChanges" => Err(MethodError::new("cannotCalculateChanges")),
+        "EmailSubmission/set" => submission::set(&arguments, context),
+        _ => Err(MethodError::new("unknownMethod")),
+    }
+}
+
+/// # 解析结果引用
+/// ## 参数
+/// - arguments: 方法的参数
+/// - responses: 之前的响应 (名称, 参数, callId)
+/// ## 返回值
+/// - Result<Map<String, Value>>，引用已替换为实际的值
+fn references(arguments: &Value, responses: &[(String, Value, String)]) -> Result<Map<String, Value>> {
+    let Some(arguments) = arguments.as_object() else {
+        return Err(invalid("Arguments must be an object"));
+    };
+    let mut resolved = Map::new();
+    for (key, value) in arguments {
+        let Some(name) = key.strip_prefix('#') else {
+            resolved.insert(key.clone(), value.clone());
+            continue;
+        };
+        if arguments.contains_key(name) {
+            return Err(invalid(format!("Both {} and #{} are present", name, name)));
+        }
+        let (Some(result_of), Some(method), Some(path)) = (value["resultOf"].as_str(), value["name"].as_str(), value["path"].as_str())
+        else {
+            return Err(MethodError::describe("invalidResultReference", format!("#{} is not a valid ResultReference", name)));
+        };
+        let response = responses.iter().find(|(name, _, call_id)| call_id == result_of && name == method);
+        let Some(target) = response.and_then(|(_, response, _)| pointer(response, path)) else {
+            return Err(MethodError::describe("invalidResultReference", format!("Cannot resolve {} of {}", path, result_of)));
+        };
+        resolved.insert(name.to_string(), target);
+    }
+    Ok(resolved)
+}
+
+/// # JSON Pointer（RFC 6901），* 展开数组
+/// 对数组的每一项应用余下的路径，结果为数组时合并到输出中（RFC 8620 3.7）。
+fn pointer(value: &Value, path: &str) -> Option<Value> {
+    if path.is_empty() {
+        return Some(value.clone());
+    }
+    let path = path.strip_prefix('/')?;
+    let (token, rest) = match path.find('/') {
+        Some(index) => (&path[..index], &path[index..]),
+        None => (path, ""),
+    };
+    let token = token.replace("~1", "/").replace("~0", "~");
+    match value {
+        Value::Array(items) if token == "*" => {
+            let mut output = Vec::new();
+            for item in items {
+                match pointer(item, rest)? {
+                    Value::Array(values) => output.extend(values),
+                    value => output.push(value),
+                }
+            }
+            Some(Value::Array(output))
+        }
+        Value::Array(items) => pointer(items.get(token.parse::<usize>().ok()?)?, rest),
+        Value::Object(map) => pointer(map.get(&token)?, rest),
+        _ => None,
+    }
+}
+
+/// # 参数错误
+pub fn invalid(description: impl Into<String>) -> MethodError {
+    MethodError::describe("invalidArguments", description)
+}
+
+/// # 字符串数组参数
+/// ## 返回值
+/// - Result<Option<Vec<String>>>，不存在或为 null 时为 None
+pub fn strings(arguments: &Map<String, Value>, key: &str) -> Result<Option<Vec<String>>> {
+    match arguments.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::Array(items)) => items
+            .iter()
+            .map(|item| item.as_str().map(String::from))
+            .collect::<Option<Vec<_>>>()
+            .map(Some)
+            .ok_or_else(|| invalid(format!("{} must be an array of strings", key))),
+        Some(_) => Err(invalid(format!("{} must be an array of strings", key))),
+    }
+}
+
+/// # 字符串参数
+pub fn string(arguments: &Map<String, Value>, key: &str) -> Result<Option<String>> {
+    match arguments.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(invalid(format!("{} must be a string", key))),
+    }
+}
+
+/// # 布尔参数
+pub fn boolean(arguments: &Map<String, Value>, key: &str, default: bool) -> Result<bool> {
+    match arguments.get(key) {
+        None | Some(Value::Null) => Ok(default),
+        Some(Value::Bool(value)) => Ok(*value),
+        Some(_) => Err(invalid(format!("{} must be a boolean", key))),
+    }
+}
+
+/// # 整数参数
+pub fn integer(arguments: &Map<String, Value>, key: &str) -> Result<Option<i64>> {
+    match arguments.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_i64().map(Some).ok_or_else(|| invalid(format!("{} must be an integer", key))),
+    }
+}
+
+/// # /get 的 ids 参数
+/// ## 参数
+/// - limit: 数量上限
+pub fn get_ids(arguments: &Map<String, Value>, context: &Context, limit: usize) -> Result<Option<Vec<String>>> {
+    let Some(ids) = strings(arguments, "ids")? else {
+        return Ok(None);
+    };
+    if ids.len() > limit {
+        return Err(MethodError::new("requestTooLarge"));
+    }
+    Ok(Some(ids.iter().map(|id| context.resolve(id).unwrap_or_else(|| id.clone())).collect()))
+}
+
+/// # /changes 的参数
+/// ## 返回值
+/// - (sinceState, maxChanges)
+pub fn changes_arguments(arguments: &Map<String, Value>) -> Result<(String, Option<usize>)> {
+    let since = string(arguments, "sinceState")?.ok_or_else(|| invalid("sinceState is required"))?;
+    let max = match integer(arguments, "maxChanges")? {
+        Some(max) if max <= 0 => return Err(invalid("maxChanges must be positive")),
+        max => max.map(|max| max as usize),
+    };
+    Ok((since, max))
+}
+
+/// # /changes 的响应
+/// 变化超过 maxChanges 时无法给出中间状态，返回 cannotCalculateChanges。
+/// ## 参数
+/// - account_id: 账号的 JMAP id
+/// - since: 旧状态
+/// - state: 当前状态
+/// - changes: 变化，无法计算时为 None
+/// - prefix: 对象 id 的前缀
+/// - max: maxChanges
+/// ## 返回值
+/// - Result<Value>
+pub fn changes_response(account_id: String, since: String, state: String, changes: Option<Changes>, prefix: char, max: Option<usize>) -> Result<Value> {
+    let Some(changes) = changes else {
+        return Err(MethodError::new("cannotCalculateChanges"));
+    };
+    if max.is_some_and(|max| changes.len() > max) {
+        return Err(MethodError::describe("cannotCalculateChanges", "Too many changes, fetch the objects again"));
+    }
+    let ids = |ids: &[i64]| ids.iter().map(|id| super::id(prefix, *id)).collect::<Vec<_>>();
+    Ok(json!({
+        "accountId": account_id,
+        "oldState": since,
+        "newState": state,
+        "hasMoreChanges": false,
+        "created": ids(&changes.created),
+        "updated": ids(&changes.updated),
+        "destroyed": ids(&changes.destroyed),
+    }))
+}
+
+/// # 只保留请求的属性
+/// ## 参数
+/// - object: 对象的所有属性
+/// - properties: 请求的属性，None 为全部；id 总是返回
+pub fn pick(mut object: Map<String, Value>, properties: Option<&[String]>) -> Value {
+    if let Some(properties) = properties {
+        object.retain(|key, _| key == "id" || properties.iter().any(|property| property == key));
+    }
+    Value::Object(object)
+}
+
+/// # /set 的错误（RFC 8620 5.3）
+pub fn set_error(kind: &str, description: impl Into<String>) -> Value {
+    json!({ "type": kind, "description": description.into() })
+}
+
+/// # /set 的参数
+pub struct SetRequest {
+    /// (创建 id, 属性)
+    pub create: Vec<(String, Map<String, Value>)>,
+    /// (id, 修改的属性)
+    pub update: Vec<(String, Map<String, Value>)>,
+    pub destroy: Vec<String>,
+}
+
+/// # /set 的结果
+#[derive(Default)]
+pub struct SetResponse {
+    pub created: Map<String, Value>,
+    pub updated: Map<String, Value>,
+    pub destroyed: Vec<String>,
+    pub not_created: Map<String, Value>,
+    pub not_updated: Map<String, Value>,
+    pub not_destroyed: Map<String, Value>,
+}
+
+impl SetResponse {
+    /// # 生成响应
+    pub fn into_value(self, account_id: String, old_state: String, new_state: String) -> Value {
+        let optional = |map: Map<String, Value>| if map.is_empty() { Value::Null } else { Value::Object(map) };
+        json!({
+            "accountId": account_id,
+            "oldState": old_state,
+            "newState": new_state,
+            "created": optional(self.created),
+            "updated": optional(self.updated),
+            "destroyed": if self.destroyed.is_empty() { Value::Null } else { json!(self.destroyed) },
+            "notCreated": optional(self.not_created),
+            "notUpdated": optional(self.not_updated),
+            "notDestroyed": optional(self.not_destroyed),
+        })
+    }
+}
+
+/// # 解析 /set 的参数
+/// ## 参数
+/// - arguments: 方法的参数
+/// - state: 当前状态，用于检查 ifInState
+/// ## 返回值
+/// - Result<SetRequest>
+pub fn set_request(arguments: &Map<String, Value>, state: &str) -> Result<SetRequest> {
+    if let Some(expected) = string(arguments, "ifInState")?
+        && expected != state
+    {
+        return Err(MethodError::new("stateMismatch"));
+    }
+    let objects = |key: &str| -> Result<Vec<(String, Map<String, Value>)>> {
+        match arguments.get(key) {
+            None | Some(Value::Null) => Ok(Vec::new()),
+            Some(Value::Object(map)) => map
+                .iter()
+                .map(|(id, value)| match value {
+                    Value::Object(object) => Ok((id.clone(), object.clone())),
+                    _ => Err(invalid(format!("{}/{} must be an object", key, id))),
+                })
+                .collect(),
+            Some(_) => Err(invalid(format!("{} must be an object", key))),
+        }
+    };
+    let request = SetRequest { create: objects("create")?, update: objects("update")?, destroy: strings(arguments, "destroy")?.unwrap_or_default() };
+    if request.create.len() + request.update.len() + request.destroy.len() > MAX_OBJECTS_SET {
+        return Err(MethodError::new("requestTooLarge"));
+    }
+    Ok(request)
+}
@@ -0,0 +1,593 @@
+/* JMAP 监听 */
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rustls::ServerConfig;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::request::{self, Context};
+use super::{CORE, MAIL, MAX_CALLS, MAX_OBJECTS_GET, MAX_OBJECTS_SET, MAX_REQUEST, SUBMISSION, blob, state, submission};
+use crate::api::http::{self, Request, Response};
+use crate::config::Config;
+use crate::event::{self, Kind};
+use crate::sasl::{Authenticator, SaslError, Step};
+use crate::storage::{Account, MailStore};
+use crate::tls::{Connection, Stream};
+use crate::utils::from_hex;
+
+/// 同时处理的连接数上限，包括 EventSource 连接
+const MAX_CONNECTIONS: usize = 64;
+
+/// 等待请求的超时
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+/// EventSource 检查变化的间隔
+const POLL: Duration = Duration::from_secs(1);
+
+/// EventSource ping 的最长间隔
+const MAX_PING: u64 = 600;
+
+/// 客户端不要求 ping 时，EventSource 检查连接是否断开的间隔
+const KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// 连接编号
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// 当前连接数
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// 所有连接共用的状态
+struct State {
+    config: Config,
+    store: Arc<dyn MailStore>,
+    /// 请求体的大小上限，不小于邮件的大小上限，以便上传完整的邮件
+    max_body: usize,
+}
+
+/// 连接上已通过认证的账号，与 Authorization 请求头对应，同一连接上的后续请求不再查询密码
+type Login = Option<(String, Account)>;
+
+/// # 启动 JMAP 服务
+/// 绑定 [WebServer] 的端口后在后台线程中接受连接，每个连接使用一个线程。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - tls: TLS 配置，[WebServer] TLS 为 false 时为 None
+/// - store: 邮件存储
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start(config: &Config, tls: Option<Arc<ServerConfig>>, store: Arc<dyn MailStore>) -> io::Result<JoinHandle<()>> {
+    let section = &config.web_server;
+    let address: IpAddr = section.address.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, section.address.clone()))?;
+    let socket = TcpListener::bind(SocketAddr::new(address, section.port))?;
+    info!("JMAP 服务已启动，监听 {}，会话资源为 /jmap/session", socket.local_addr()?);
+
+    let max_body = (config.smtp.max_message_size as usize * 1024 * 1024).max(MAX_REQUEST);
+    let state = Arc::new(State { config: config.clone(), store, max_body });
+    thread::Builder::new().name(String::from("jmap")).spawn(move || {
+        for stream in socket.incoming() {
+            match stream {
+                Ok(stream) => accept(stream, &tls, &state),
+                Err(e) => warning!("JMAP 无法接受连接：{}", e),
+            }
+        }
+    })
+}
+
+/// # 为新连接创建线程
+fn accept(stream: TcpStream, tls: &Option<Arc<ServerConfig>>, state: &Arc<State>) {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            debug!("JMAP #{} 无法获取客户端地址：{}", id, e);
+            return;
+        }
+    };
+    debug!("JMAP #{} 来自 {} 的连接", id, peer);
+
+    if CONNECTIONS.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        warning!("JMAP #{} 连接数已达上限 {}，拒绝连接", id, MAX_CONNECTIONS);
+        return;
+    }
+
+    let tls = tls.clone();
+    let state = Arc::clone(state);
+    let spawned = thread::Builder::new().name(format!("jmap-{}", id)).spawn(move || {
+        if let Err(e) = handle(stream, id, peer.ip(), tls, &state) {
+            debug!("JMAP #{} 连接中断：{}", id, e);
+        }
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+    });
+    if let Err(e) = spawned {
+        CONNECTIONS.fetch_sub(1, Ordering::SeqCst);
+        error!("JMAP #{} 无法创建线程：{}", id, e);
+    }
+}
+
+/// # 处理一个连接
+/// 依次处理持久连接上的每个请求，EventSource 请求占用连接直到客户端断开。
+fn handle(stream: TcpStream, id: u64, peer: IpAddr, tls: Option<Arc<ServerConfig>>, state: &State) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut connection = match &tls {
+        Some(config) => Connection::new(Stream::accept(stream, config)?),
+        None => Connection::new(Stream::Plain(stream)),
+    };
+    let mut login: Login = None;
+    loop {
+        let request = match http::read_request(&mut connection, state.max_body) {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                debug!("JMAP #{} 无效的请求：{}", id, e);
+                return Response::error(400, e.to_string()).write(&mut connection, false);
+            }
+            Err(e) => return Err(e),
+        };
+        if request.method == "GET"
+            && request.path == "/jmap/eventsource"
+            && host_allowed(&request, state)
+            && let Some(account) = authenticate(&request, &mut login, id, peer, state)
+        {
+            info!("JMAP #{} {} {} 200", id, request.method, request.path);
+            return event_source(&mut connection, &request, id, &account, state);
+        }
+        let response = respond(&request, &mut login, id, peer, state, tls.is_some()).header("Access-Control-Allow-Origin", "*");
+        info!("JMAP #{} {} {} {}", id, request.method, request.path, response.status);
+        let keep_alive = request.keep_alive();
+        response.write(&mut connection, keep_alive)?;
+        if !keep_alive {
+            return Ok(());
+        }
+    }
+}
+
+/// # 使用 HTTP Basic 认证登录
+/// ## 返回值
+/// - Option<Account>，未提供或认证失败时为 None
+fn authenticate(request: &Request, login: &mut Login, id: u64, peer: IpAddr, state: &State) -> Option<Account> {
+    let credentials = request.header("Authorization")?.strip_prefix("Basic ")?.trim();
+    if let Some((cached, account)) = login.as_ref()
+        && cached == credentials
+    {
+        return Some(account.clone());
+    }
+    let decoded = BASE64.decode(credentials).ok().and_then(|decoded| String::from_utf8(decoded).ok())?;
+    let (username, password) = decoded.split_once(':')?;
+    match Authenticator::login(Arc::clone(&state.store), username, password) {
+        Ok(Step::Success(account)) => {
+            info!("JMAP #{} 来自 {} 的 {} 登录成功", id, peer, account.address());
+            *login = Some((credentials.to_string(), account.clone()));
+            Some(account)
+        }
+        Ok(Step::Failure(username)) => {
+            warning!("JMAP #{} 来自 {} 的登录失败，用户名 {}", id, peer, username);
+            None
+        }
+        Ok(Step::Challenge(_)) | Err(SaslError::Malformed | SaslError::Cancelled) => None,
+        Err(SaslError::Storage(e)) => {
+            error!("JMAP #{} 无法读取账号：{}", id, e);
+            None
+        }
+    }
+}
+
+/// # 请求的 Host 是否符合 [WebServer] 的 Host 约束
+fn host_allowed(request: &Request, state: &State) -> bool {
+    let section = &state.config.web_server;
+    let host = request.header("Host").unwrap_or_default();
+    !section.host_constraint || host.rsplit_once(':').map_or(host, |(name, _)| name).eq_ignore_ascii_case(&section.host)
+}
+
+/// # 校验 Host、鉴权并分发请求
+fn respond(request: &Request, login: &mut Login, id: u64, peer: IpAddr, state: &State, secure: bool) -> Response {
+    let section = &state.config.web_server;
+    let host = request.header("Host").unwrap_or_default();
+    if !host_allowed(request, state) {
+        debug!("JMAP #{} Host {} 与配置不符", id, host);
+        return Response::error(400, format!("Host {} 与配置不符", host));
+    }
+    if request.method == "OPTIONS" {
+        return Response::bytes(204, "text/plain", Vec::new())
+            .header("Access-Control-Allow-Methods", "GET, POST, OPTIONS")
+            .header("Access-Control-Allow-Headers", "Authorization, Content-Type, Accept")
+            .header("Access-Control-Max-Age", "86400");
+    }
+    if request.path == "/.well-known/jmap" {
+        return Response::bytes(307, "text/plain", Vec::new()).header("Location", "/jmap/session");
+    }
+
+    let Some(account) = authenticate(request, login, id, peer, state) else {
+        return Response::error(401, "用户名或密码错误").header("WWW-Authenticate", "Basic realm=\"ZitMail\", charset=\"UTF-8\"");
+    };
+    let base = match host.is_empty() {
+        true => format!("{}://{}:{}", if secure { "https" } else { "http" }, section.address, section.port),
+        false => format!("{}://{}", if secure { "https" } else { "http" }, host),
+    };
+    let store = state.store.as_ref();
+    match (request.method.as_str(), request.segments().as_slice()) {
+        ("GET", ["jmap", "session"]) => Response::json(200, session(&account, &base, state)),
+        ("POST", ["jmap", "api"]) => {
+            if request.body.len() > MAX_REQUEST {
+                return request::problem("limit", "The request is larger than maxSizeRequest");
+            }
+            let hostname = &state.config.smtp.hostname;
+            let mut context = Context { store, account: &account, peer, hostname, created: HashMap::new() };
+            request::process(&request.body, &mut context)
+        }
+        ("POST", ["jmap", "upload", account_id]) => {
+            if super::parse_id('A', account_id) != Some(account.id) {
+                return Response::error(404, format!("账号 {} 不存在", account_id));
+            }
+            let content_type = request.header("Content-Type").unwrap_or("application/octet-stream").to_string();
+            let size = request.body.len();
+            match blob::upload(account.id, &content_type, request.body.clone()) {
+                Some(blob_id) => Response::json(201, json!({ "accountId": account_id, "blobId": blob_id, "type": content_type, "size": size })),
+                None => request::problem("limit", "Too many uploaded blobs for this account"),
+            }
+        }
+        ("GET", ["jmap", "download", account_id, blob_id, rest @ ..]) => {
+            if super::parse_id('A', account_id) != Some(account.id) {
+                return Response::error(404, format!("账号 {} 不存在", account_id));
+            }
+            match blob::read(store, account.id, blob_id) {
+                Ok(Some((content_type, data))) => {
+                    // accept 由客户端任意指定，不是 type/subtype 时使用保存的类型，避免写入响应头的内容被拆分
+                    let content_type = request.param("accept").and_then(media_type).unwrap_or(&content_type).to_string();
+                    let name = rest.first().copied().unwrap_or(blob_id);
+                    Response::bytes(200, content_type, data)
+                        .header("Cache-Control", "private, immutable, max-age=31536000")
+                        .header("X-Content-Type-Options", "nosniff")
+                        .header("Content-Disposition", disposition(name))
+                }
+                Ok(None) => Response::error(404, format!("blob {} 不存在", blob_id)),
+                Err(e) => {
+                    error!("JMAP #{} 无法读取 blob {}：{}", id, blob_id, e);
+                    Response::error(500, "存储操作失败")
+                }
+            }
+        }
+        _ => Response::error(404, format!("{} 不存在", request.path)),
+    }
+}
+
+/// # 校验下载请求的 accept 参数
+/// ## 返回值
+/// - Option<&str>，不是 type/subtype 或含有 token 以外的字符时为 None
+fn media_type(value: &str) -> Option<&str> {
+    let token = |part: &str| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte));
+    let (kind, subtype) = value.split_once('/')?;
+    (token(kind) && token(subtype)).then_some(value)
+}
+
+/// # 下载的 Content-Disposition
+/// 文件名为下载地址中 {name} 一段，filename 只保留可打印的 ASCII 字符，完整的名称放在 filename*（RFC 6266）。
+/// ## 参数
+/// - name: 路径中未解码的 {name} 一段
+fn disposition(name: &str) -> String {
+    let raw = name.as_bytes();
+    let mut decoded = Vec::new();
+    let mut index = 0;
+    while index < raw.len() {
+        let escaped = raw.get(index + 1..index + 3).and_then(|hex| from_hex(std::str::from_utf8(hex).ok()?));
+        match (raw[index], escaped) {
+            (b'%', Some(value)) => {
+                decoded.extend(value);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    let name = String::from_utf8_lossy(&decoded);
+    let fallback: String = name.chars().map(|c| if c.is_ascii_graphic() && c != '"' && c != '\\' || c == ' ' { c } else { '_' }).collect();
+    let encoded: String = name
+        .bytes()
+        .map(|byte| match byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            true => (byte as char).to_string(),
+            false => format!("%{:02X}", byte),
+        })
+        .collect();
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+/// # 会话资源（RFC 8620 2）
+/// ## 参数
+/// - account: 已登录的账号
+/// - base: 客户端访问时使用的地址，例如 https://mail.example.com
+fn session(account: &Account, base: &str, state: &State) -> Value {
+    let account_id = super::id('A', account.id);
+    let max_size = state.config.smtp.max_message_size * 1024 * 1024;
+    json!({
+        "capabilities": {
+            CORE: {
+                "maxSizeUpload": max_size,
+                "maxConcurrentUpload": 4,
+                "maxSizeRequest": MAX_REQUEST,
+                "maxConcurrentRequests": 4,
+                "maxCallsInRequest": MAX_CALLS,
+                "maxObjectsInGet": MAX_OBJECTS_GET,
+                "maxObjectsInSet": MAX_OBJECTS_SET,
+                "collationAlgorithms": ["i;ascii-casemap"],
+            },
+            MAIL: {},
+            SUBMISSION: {},
+        },
+        "accounts": {
+            account_id.clone(): {
+                "name": account.address(),
+                "isPersonal": true,
+                "isReadOnly": false,
+                "accountCapabilities": {
+                    MAIL: {
+                        "maxMailboxesPerEmail": 1,
+                        "maxMailboxDepth": null,
+                        "maxSizeMailboxName": 255,
+                        "maxSizeAttachmentsPerEmail": max_size,
+                        "emailQuerySortOptions": ["receivedAt", "size", "sentAt", "from", "to", "subject", "hasKeyword"],
+                        "mayCreateTopLevelMailbox": true,
+                    },
+                    SUBMISSION: { "maxDelayedSend": 0, "submissionExtensions": {} },
+                },
+            },
+        },
+        "primaryAccounts": { MAIL: account_id.clone(), SUBMISSION: account_id },
+        "username": account.address(),
+        "apiUrl": format!("{}/jmap/api", base),
+        "downloadUrl": format!("{}/jmap/download/{{accountId}}/{{blobId}}/{{name}}?accept={{type}}", base),
+        "uploadUrl": format!("{}/jmap/upload/{{accountId}}/", base),
+        "eventSourceUrl": format!("{}/jmap/eventsource?types={{types}}&closeafter={{closeafter}}&ping={{ping}}", base),
+        "state": request::session_state(account),
+    })
+}
+
+/// # 各类型当前的状态
+/// Mailbox、Email 与 Thread 的状态相同，都由各邮箱的修改序列得出。
+fn states(account: &Account, state: &State) -> Map<String, Value> {
+    let mailboxes = match state.store.mailboxes(account.id) {
+        Ok(mailboxes) => mailboxes,
+        Err(e) => {
+            error!("JMAP 无法读取账号 {} 的邮箱：{}", account.address(), e);
+            return Map::new();
+        }
+    };
+    let current = state::state(&mailboxes);
+    let mut states = Map::new();
+    for name in ["Mailbox", "Email", "Thread"] {
+        states.insert(name.to_string(), json!(current));
+    }
+    states.insert(String::from("EmailSubmission"), json!(submission::submission_state(account.id)));
+    states
+}
+
+/// # 推送状态变化（RFC 8620 7.3）
+/// 每 POLL 检查一次账号的事件，有变化时发送 state 事件；投递新邮件时额外包含 EmailDelivery。
+fn event_source(connection: &mut Connection, request: &Request, id: u64, account: &Account, state: &State) -> io::Result<()> {
+    let types: Vec<&str> = request.param("types").unwrap_or("*").split(',').collect();
+    let wanted = |name: &str| types.contains(&"*") || types.contains(&name);
+    let close_after = request.param("closeafter") == Some("state");
+    let ping = match request.param("ping").map(str::parse::<u64>) {
+        None => 0,
+        Some(Ok(ping)) => ping.min(MAX_PING),
+        Some(Err(_)) => return Response::error(400, "ping 必须是非负整数").write(connection, false),
+    };
+
+    let subscription = event::subscribe(account.id);
+    let headers = [("Cache-Control", String::from("no-cache")), ("Access-Control-Allow-Origin", String::from("*"))];
+    http::write_stream_head(connection, "text/event-stream", &headers)?;
+    let mut last = states(account, state);
+    let mut pinged = Instant::now();
+    loop {
+        thread::sleep(POLL);
+        let events = subscription.events();
+        if !events.is_empty() {
+            let current = states(account, state);
+            let mut changed: Map<String, Value> =
+                current.iter().filter(|(name, value)| wanted(name) && last.get(*name) != Some(value)).map(|(name, value)| (name.clone(), value.clone())).collect();
+            if wanted("EmailDelivery") && events.iter().any(|event| event.kind == Kind::MessageNew && event.origin == 0) {
+                changed.insert(String::from("EmailDelivery"), current["Email"].clone());
+            }
+            if !changed.is_empty() {
+                let data = json!({ "@type": "StateChange", "changed": { super::id('A', account.id): changed } });
+                connection.send(format!("event: state\ndata: {}\n\n", data).as_bytes())?;
+                debug!("JMAP #{} 推送状态变化 {}", id, data);
+                if close_after {
+                    return Ok(());
+                }
+            }
+            last = current;
+        }
+        // 不要求 ping 时发送注释行，以便发现已断开的连接
+        if ping > 0 && pinged.elapsed() >= Duration::from_secs(ping) {
+            connection.send(format!("event: ping\ndata: {}\n\n", json!({ "interval": ping })).as_bytes())?;
+            pinged = Instant::now();
+        } else if ping == 0 && pinged.elapsed() >= KEEPALIVE {
+            connection.send(b": keepalive\n\n")?;
+            pinged = Instant::now();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::{self, Params};
+    use crate::jmap::id;
+    use crate::storage::memory::MemoryStore;
+    use chrono::Utc;
+
+    /// bob@example.com:secret
+    const BOB: &str = "Ym9iQGV4YW1wbGUuY29tOnNlY3JldA==";
+
+    /// # 测试用的状态
+    /// 有 bob@example.com 与 carol@example.com 两个账号，密码都是 secret，bob 的 INBOX 中有一封邮件。
+    /// ## 返回值
+    /// - (State, bob 的账号, carol 的账号, 邮件 id)
+    fn prepare() -> (State, Account, Account, i64) {
+        let store: Arc<dyn MailStore> = Arc::new(MemoryStore::new());
+        store.add_domain("example.com").unwrap();
+        let params = Params { memory: 8, time: 1, parallelism: 1 };
+        let hash = password::hash("secret", &params).unwrap();
+        let bob = store.add_account("bob", "example.com", &hash, &password::scram("secret")).unwrap();
+        let carol = store.add_account("carol", "example.com", &hash, &password::scram("secret")).unwrap();
+        let inbox = store.mailbox(bob.id, "INBOX").unwrap().unwrap();
+        let message = store.append(inbox.id, b"From: alice@remote.test\r\nSubject: hello\r\n\r\nhi\r\n", &[], Utc::now()).unwrap();
+        let config = Config::parse(crate::default::CONFIG).unwrap();
+        (State { config, store, max_body: 1024 * 1024 }, bob, carol, message.id)
+    }
+
+    /// # 发送一个请求
+    /// ## 参数
+    /// - head: 请求行与请求头，不含 Content-Length 与结尾的空行
+    /// - body: 请求体
+    fn call(state: &State, head: &str, body: &[u8]) -> Response {
+        let raw = [format!("{}\r\nContent-Length: {}\r\n\r\n", head, body.len()).as_bytes(), body].concat();
+        let request = http::read_request(&mut &raw[..], state.max_body).unwrap().unwrap();
+        respond(&request, &mut None, 1, "192.0.2.1".parse().unwrap(), state, true)
+    }
+
+    /// # bob 的 API 请求
+    /// ## 返回值
+    /// - Value，各方法的响应
+    fn api(state: &State, calls: Value) -> Vec<Value> {
+        let body = json!({ "using": [CORE, MAIL], "methodCalls": calls }).to_string();
+        let response = call(state, &format!("POST /jmap/api HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}", BOB), body.as_bytes());
+        assert_eq!(response.status, 200);
+        json_body(&response)["methodResponses"].as_array().unwrap().clone()
+    }
+
+    fn json_body(response: &Response) -> Value {
+        match &response.body {
+            http::Body::Json(value) => value.clone(),
+            http::Body::Bytes(..) => panic!("不是 JSON 响应"),
+        }
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response.headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn session_discovery() {
+        let (state, bob, ..) = prepare();
+        let response = call(&state, "GET /.well-known/jmap HTTP/1.1\r\nHost: mail.example.com", b"");
+        assert_eq!((response.status, header(&response, "Location")), (307, Some("/jmap/session")));
+
+        let response = call(&state, &format!("GET /jmap/session HTTP/1.1\r\nHost: mail.example.com\r\nAuthorization: Basic {}", BOB), b"");
+        assert_eq!(response.status, 200);
+        let session = json_body(&response);
+        let account_id = id('A', bob.id);
+        assert_eq!(session["username"], "bob@example.com");
+        assert_eq!(session["primaryAccounts"][MAIL], json!(account_id));
+        assert_eq!(session["accounts"][&account_id]["name"], "bob@example.com");
+        assert_eq!(session["apiUrl"], "https://mail.example.com/jmap/api");
+        assert_eq!(session["downloadUrl"], "https://mail.example.com/jmap/download/{accountId}/{blobId}/{name}?accept={type}");
+    }
+
+    #[test]
+    fn wrong_password() {
+        let (state, ..) = prepare();
+        let wrong = BASE64.encode("bob@example.com:wrong");
+        for authorization in [format!("\r\nAuthorization: Basic {}", wrong), String::new()] {
+            let response = call(&state, &format!("GET /jmap/session HTTP/1.1\r\nHost: localhost{}", authorization), b"");
+            assert_eq!(response.status, 401);
+            assert!(header(&response, "WWW-Authenticate").unwrap().starts_with("Basic "));
+        }
+    }
+
+    #[test]
+    fn host_constraint() {
+        let (mut state, ..) = prepare();
+        state.config.web_server.host_constraint = true;
+        state.config.web_server.host = String::from("mail.example.com");
+        let request = |host: &str| call(&state, &format!("GET /jmap/session HTTP/1.1\r\nHost: {}\r\nAuthorization: Basic {}", host, BOB), b"");
+        assert_eq!(request("evil.test").status, 400);
+        assert_eq!(json_body(&request("evil.test"))["error"], "Host evil.test 与配置不符");
+        // 端口与大小写不影响比较
+        assert_eq!(request("Mail.Example.com:8080").status, 200);
+    }
+
+    #[test]
+    fn state_round_trip() {
+        let (state, bob, _, message) = prepare();
+        let account_id = id('A', bob.id);
+        let email_id = id('E', message);
+        let responses = api(&state, json!([["Mailbox/get", { "accountId": account_id, "ids": null }, "0"]]));
+        let old_state = responses[0][1]["state"].as_str().unwrap().to_string();
+
+        let update = json!({ email_id.clone(): { "keywords/$seen": true } });
+        let responses = api(&state, json!([["Email/set", { "accountId": account_id, "ifInState": old_state, "update": update }, "1"]]));
+        let set = &responses[0][1];
+        assert_eq!(set["oldState"], json!(old_state));
+        assert_eq!(set["updated"], json!({ email_id.clone(): null }));
+        let new_state = set["newState"].as_str().unwrap().to_string();
+        assert_ne!(new_state, old_state);
+
+        let responses = api(&state, json!([["Email/changes", { "accountId": account_id, "sinceState": old_state }, "2"]]));
+        let changes = &responses[0][1];
+        assert_eq!(changes["newState"], json!(new_state));
+        assert_eq!(changes["updated"], json!([email_id]));
+        assert_eq!(changes["created"], json!([]));
+        assert_eq!(changes["hasMoreChanges"], json!(false));
+
+        // 旧状态已失效
+        let responses = api(&state, json!([["Email/set", { "accountId": account_id, "ifInState": old_state, "update": {} }, "3"]]));
+        assert_eq!(responses[0], json!(["error", { "type": "stateMismatch" }, "3"]));
+    }
+
+    #[test]
+    fn foreign_account() {
+        let (state, _, carol, _) = prepare();
+        let foreign = id('A', carol.id);
+        let responses = api(&state, json!([["Mailbox/get", { "accountId": foreign, "ids": null }, "0"]]));
+        assert_eq!(responses[0], json!(["error", { "type": "accountNotFound" }, "0"]));
+    }
+
+    #[test]
+    fn upload_and_download() {
+        let (state, bob, carol, _) = prepare();
+        let account_id = id('A', bob.id);
+        let head = format!("POST /jmap/upload/{}/ HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}\r\nContent-Type: text/plain", account_id, BOB);
+        let response = call(&state, &head, b"attachment body");
+        assert_eq!(response.status, 201);
+        let uploaded = json_body(&response);
+        assert_eq!((uploaded["type"].as_str(), uploaded["size"].as_u64()), (Some("text/plain"), Some(15)));
+        let blob_id = uploaded["blobId"].as_str().unwrap();
+
+        let download = |account_id: &str, query: &str| {
+            let head = format!("GET /jmap/download/{}/{}/r%C3%A9sum%C3%A9%22.txt{} HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}", account_id, blob_id, query, BOB);
+            call(&state, &head, b"")
+        };
+        let response = download(&account_id, "?accept=application/pdf");
+        assert_eq!(response.status, 200);
+        match &response.body {
+            http::Body::Bytes(content_type, data) => assert_eq!((content_type.as_str(), data.as_slice()), ("application/pdf", &b"attachment body"[..])),
+            http::Body::Json(_) => panic!("不是 blob 内容"),
+        }
+        assert_eq!(header(&response, "X-Content-Type-Options"), Some("nosniff"));
+        assert_eq!(header(&response, "Content-Disposition"), Some("attachment; filename=\"r_sum__.txt\"; filename*=UTF-8''r%C3%A9sum%C3%A9%22.txt"));
+
+        // 无效的 accept 使用保存的类型，不写入响应头
+        for accept in ["?accept=text/html%0D%0ASet-Cookie:%20a=b", "?accept=html", "?accept=text/"] {
+            match &download(&account_id, accept).body {
+                http::Body::Bytes(content_type, _) => assert_eq!(content_type, "text/plain", "{}", accept),
+                http::Body::Json(_) => panic!("不是 blob 内容"),
+            }
+        }
+
+        // 其他账号的路径与其他账号上传的内容都不存在
+        assert_eq!(download(&id('A', carol.id), "").status, 404);
+        let carol_auth = BASE64.encode("carol@example.com:secret");
+        let head = format!("GET /jmap/download/{}/{}/a.txt HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic {}", id('A', carol.id), blob_id, carol_auth);
+        assert_eq!(call(&state, &head, b"").status, 404);
+    }
+}
@@ -0,0 +1,184 @@
+/* JMAP 状态 */
+/*
+# 状态字符串
+## 结构
+状态由账号的所有邮箱组成，每个邮箱为「id:修改序列:下一个 UID:名称摘要」，以 , 分隔，没有邮箱时为 0。
+Mailbox、Email 与 Thread 共用同一个状态字符串。
+## 用法
+let state = state::state(&mailboxes);
+let changes = state::emails(store, &mailboxes, &since)?;   <-- None 表示无法计算（cannotCalculateChanges）
+## 说明
+邮件的修改序列大于旧状态时：UID 不小于旧状态的下一个 UID 或邮箱是新建的则为新邮件，否则为修改了标志的邮件。
+删除与移出的邮件记录在 vanished 中；移动的邮件同时出现在移出与移入的邮箱中，视为修改（mailboxIds 变化）。
+旧状态中的邮箱已被删除时，邮件的变化无法计算。
+ */
+use std::collections::{HashMap, HashSet};
+
+use crate::storage::{Mailbox, MailStore, StorageError};
+
+/// 邮箱在状态中的记录
+#[derive(Clone, Copy, PartialEq)]
+struct Entry {
+    id: i64,
+    modseq: u64,
+    uid_next: u32,
+    /// 名称与订阅状态的摘要
+    digest: u64,
+}
+
+/// # FNV-1a 摘要
+/// ## 参数
+/// - bytes: 内容
+/// ## 返回值
+/// - u64
+pub fn digest(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| (hash ^ *byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// # 邮箱的记录
+fn entry(mailbox: &Mailbox) -> Entry {
+    let digest = digest(format!("{}\0{}", mailbox.name, mailbox.subscribed).as_bytes());
+    Entry { id: mailbox.id, modseq: mailbox.highest_modseq, uid_next: mailbox.uid_next, digest }
+}
+
+/// # 当前状态
+/// ## 参数
+/// - mailboxes: 账号的所有邮箱
+/// ## 返回值
+/// - String
+pub fn state(mailboxes: &[Mailbox]) -> String {
+    if mailboxes.is_empty() {
+        return String::from("0");
+    }
+    let mut entries: Vec<Entry> = mailboxes.iter().map(entry).collect();
+    entries.sort_by_key(|entry| entry.id);
+    entries
+        .iter()
+        .map(|entry| format!("{}:{}:{}:{:x}", entry.id, entry.modseq, entry.uid_next, entry.digest))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// # 解析状态
+/// ## 返回值
+/// - Option<HashMap<i64, Entry>>，格式错误时为 None
+fn parse(state: &str) -> Option<HashMap<i64, Entry>> {
+    if state == "0" {
+        return Some(HashMap::new());
+    }
+    let mut entries = HashMap::new();
+    for item in state.split(',') {
+        let mut fields = item.split(':');
+        let entry = Entry {
+            id: fields.next()?.parse().ok()?,
+            modseq: fields.next()?.parse().ok()?,
+            uid_next: fields.next()?.parse().ok()?,
+            digest: u64::from_str_radix(fields.next()?, 16).ok()?,
+        };
+        if fields.next().is_some() {
+            return None;
+        }
+        entries.insert(entry.id, entry);
+    }
+    Some(entries)
+}
+
+/// 两个状态之间的变化，元素为存储中的 id
+#[derive(Default)]
+pub struct Changes {
+    pub created: Vec<i64>,
+    pub updated: Vec<i64>,
+    pub destroyed: Vec<i64>,
+    /// Mailbox/changes：修改的邮箱只有邮件数量变化
+    pub counts_only: bool,
+}
+
+impl Changes {
+    /// # 变化的总数
+    pub fn len(&self) -> usize {
+        self.created.len() + self.updated.len() + self.destroyed.len()
+    }
+}
+
+/// # 邮箱的变化
+/// ## 参数
+/// - mailboxes: 账号的所有邮箱
+/// - since: 旧状态
+/// ## 返回值
+/// - Option<Changes>，旧状态无效时为 None
+pub fn mailboxes(mailboxes: &[Mailbox], since: &str) -> Option<Changes> {
+    let old = parse(since)?;
+    let mut changes = Changes { counts_only: true, ..Changes::default() };
+    for mailbox in mailboxes {
+        let current = entry(mailbox);
+        match old.get(&mailbox.id) {
+            None => changes.created.push(mailbox.id),
+            Some(entry) if *entry == current => {}
+            Some(entry) => {
+                changes.counts_only &= entry.digest == current.digest;
+                changes.updated.push(mailbox.id);
+            }
+        }
+    }
+    let current: HashSet<i64> = mailboxes.iter().map(|mailbox| mailbox.id).collect();
+    changes.destroyed = old.keys().filter(|id| !current.contains(id)).copied().collect();
+    changes.destroyed.sort();
+    // 创建或删除邮箱会改变子邮箱的 parentId
+    if !changes.created.is_empty() || !changes.destroyed.is_empty() {
+        changes.counts_only = false;
+        let created: Vec<&str> = mailboxes.iter().filter(|mailbox| changes.created.contains(&mailbox.id)).map(|mailbox| mailbox.name.as_str()).collect();
+        for mailbox in mailboxes {
+            let affected = !changes.destroyed.is_empty() || created.iter().any(|parent| crate::imap::mailbox::is_child(&mailbox.name, parent));
+            if affected && old.contains_key(&mailbox.id) && !changes.updated.contains(&mailbox.id) {
+                changes.updated.push(mailbox.id);
+            }
+        }
+    }
+    Some(changes)
+}
+
+/// # 邮件的变化
+/// ## 参数
+/// - store: 存储
+/// - mailboxes: 账号的所有邮箱
+/// - since: 旧状态
+/// ## 返回值
+/// - Result<Option<Changes>, StorageError>，无法计算时为 None
+pub fn emails(store: &dyn MailStore, mailboxes: &[Mailbox], since: &str) -> Result<Option<Changes>, StorageError> {
+    let Some(old) = parse(since) else {
+        return Ok(None);
+    };
+    if old.keys().any(|id| !mailboxes.iter().any(|mailbox| mailbox.id == *id)) {
+        return Ok(None);
+    }
+    let mut created = Vec::new();
+    let mut updated = Vec::new();
+    let mut destroyed = Vec::new();
+    for mailbox in mailboxes {
+        let entry = old.get(&mailbox.id);
+        if entry.is_some_and(|entry| entry.modseq == mailbox.highest_modseq) {
+            continue;
+        }
+        let (modseq, uid_next) = entry.map(|entry| (entry.modseq, entry.uid_next)).unwrap_or((0, 0));
+        for message in store.messages(mailbox.id)? {
+            if message.modseq <= modseq {
+                continue;
+            }
+            if message.uid >= uid_next { created.push(message.id) } else { updated.push(message.id) }
+        }
+        if entry.is_some() {
+            destroyed.extend(store.removed(mailbox.id, modseq)?);
+        }
+    }
+    // 移动的邮件在移出的邮箱中删除、在移入的邮箱中新建
+    let moved: HashSet<i64> = destroyed.iter().filter(|id| created.contains(id)).copied().collect();
+    let current: HashSet<i64> = created.iter().chain(updated.iter()).copied().collect();
+    created.retain(|id| !moved.contains(id));
+    destroyed.retain(|id| !current.contains(id));
+    updated.extend(moved);
+    for list in [&mut created, &mut updated, &mut destroyed] {
+        list.sort();
+        list.dedup();
+    }
+    Ok(Some(Changes { created, updated, destroyed, counts_only: false }))
+}
@@ -0,0 +1,414 @@
+/* JMAP Identity 与 EmailSubmission */
+/*
+# Identity 与 EmailSubmission 的方法
+## 说明
+账号的地址与指向该账号的别名各为一个 Identity，id 为 I 加上地址的 base64url 编码；Identity 不能创建、修改或删除。
+EmailSubmission 把邮件加入发送队列，id 为 S<队列编号>，发件人必须是账号自己的地址或别名，与 SMTP 提交相同。
+没有 envelope 时，发件人为 Identity 的地址，收件人取自 To、Cc 与 Bcc；发送前去掉 Bcc 并添加 Received。
+邮件立即加入队列，不支持 sendAt 与撤销，undoStatus 总是 final；deliveryStatus 来自投递状态记录。
+EmailSubmission 只保存在内存中，每个账号保留最近的 MAX_SUBMISSIONS 个。
+ */
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value, json};
+use std::sync::Mutex;
+
+use super::request::{self, Context, MethodError, Output, Result, SetResponse, set_error};
+use super::{MAX_OBJECTS_GET, email, message, parse_id, state};
+use crate::smtp::{self, queue};
+use crate::storage::{DeliveryKey, Dsn, RecipientDsn};
+use crate::utils::is_valid_address;
+
+/// 每个账号保留的 EmailSubmission 数量
+const MAX_SUBMISSIONS: usize = 1000;
+
+/// 已提交的邮件
+#[derive(Clone)]
+struct Submission {
+    /// 队列编号
+    id: i64,
+    account_id: i64,
+    identity_id: String,
+    email_id: i64,
+    envelope: Value,
+    send_at: DateTime<Utc>,
+}
+
+static SUBMISSIONS: Mutex<Vec<Submission>> = Mutex::new(Vec::new());
+
+fn submissions() -> std::sync::MutexGuard<'static, Vec<Submission>> {
+    SUBMISSIONS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// # 账号的所有地址
+/// ## 返回值
+/// - Result<Vec<String>>，账号的地址在前，之后为别名
+fn addresses(context: &Context) -> Result<Vec<String>> {
+    let address = context.account.address();
+    let mut addresses = vec![address.clone()];
+    addresses.extend(context.store.aliases()?.into_iter().filter(|(_, target)| target.eq_ignore_ascii_case(&address)).map(|(alias, _)| alias));
+    Ok(addresses)
+}
+
+/// # 地址对应的 Identity id
+fn identity_id(address: &str) -> String {
+    format!("I{}", URL_SAFE_NO_PAD.encode(address.to_lowercase()))
+}
+
+/// # Identity 的状态
+fn identity_state(addresses: &[String]) -> String {
+    format!("{:x}", state::digest(addresses.join(",").as_bytes()))
+}
+
+/// # Identity/get
+pub fn identities(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::get_ids(arguments, context, MAX_OBJECTS_GET)?;
+    let properties = request::strings(arguments, "properties")?;
+    let addresses = addresses(context)?;
+    let identity = |address: &String| {
+        let value = json!({
+            "id": identity_id(address),
+            "name": "",
+            "email": address,
+            "replyTo": null,
+            "bcc": null,
+            "textSignature": "",
+            "htmlSignature": "",
+            "mayDelete": false,
+        });
+        request::pick(value.as_object().cloned().unwrap_or_default(), properties.as_deref())
+    };
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    match ids {
+        None => list.extend(addresses.iter().map(identity)),
+        Some(ids) => {
+            for id in ids {
+                match addresses.iter().find(|address| identity_id(address) == id) {
+                    Some(address) => list.push(identity(address)),
+                    None => not_found.push(id),
+                }
+            }
+        }
+    }
+    Ok(json!({ "accountId": context.account_id(), "state": identity_state(&addresses), "list": list, "notFound": not_found }))
+}
+
+/// # Identity/changes
+/// 别名的变化无法逐项计算，状态改变时返回 cannotCalculateChanges。
+pub fn identity_changes(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let (since, _) = request::changes_arguments(arguments)?;
+    let state = identity_state(&addresses(context)?);
+    if since != state {
+        return Err(MethodError::new("cannotCalculateChanges"));
+    }
+    Ok(json!({
+        "accountId": context.account_id(),
+        "oldState": since,
+        "newState": state,
+        "hasMoreChanges": false,
+        "created": [],
+        "updated": [],
+        "destroyed": [],
+    }))
+}
+
+/// # Identity/set
+/// Identity 由账号与别名决定，所有修改都返回 forbidden。
+pub fn set_identities(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let state = identity_state(&addresses(context)?);
+    let set = request::set_request(arguments, &state)?;
+    let mut response = SetResponse::default();
+    let forbidden = || set_error("forbidden", "Identities are managed through accounts and aliases");
+    for (creation, _) in set.create {
+        response.not_created.insert(creation, forbidden());
+    }
+    for (id, _) in set.update {
+        response.not_updated.insert(id, forbidden());
+    }
+    for id in set.destroy {
+        response.not_destroyed.insert(id, forbidden());
+    }
+    Ok(response.into_value(context.account_id(), state.clone(), state))
+}
+
+/// # EmailSubmission 的状态
+pub fn submission_state(account_id: i64) -> String {
+    let submissions = submissions();
+    let owned = submissions.iter().filter(|submission| submission.account_id == account_id);
+    format!("{}", owned.map(|submission| submission.id).max().unwrap_or(0))
+}
+
+/// # EmailSubmission 对象
+/// deliveryStatus 按收件人列出投递状态（RFC 8621 7）。
+fn object(submission: &Submission, context: &Context) -> Result<Map<String, Value>> {
+    let mut status = Map::new();
+    for delivery in context.store.deliveries(&DeliveryKey::Queue(submission.id))? {
+        let delivered = match delivery.action.as_str() {
+            "queued" | "delayed" => "queued",
+            "delivered" | "relayed" | "expanded" | "sent" => "yes",
+            "failed" => "no",
+            _ => "unknown",
+        };
+        let reply = match (&delivery.status, &delivery.diagnostic) {
+            (_, Some(diagnostic)) if diagnostic.len() > 3 && diagnostic.as_bytes()[..3].iter().all(u8::is_ascii_digit) => diagnostic.clone(),
+            (Some(status), diagnostic) if status.starts_with('5') => format!("550 {} {}", status, diagnostic.as_deref().unwrap_or_default()),
+            (Some(status), diagnostic) if status.starts_with('4') => format!("451 {} {}", status, diagnostic.as_deref().unwrap_or_default()),
+            _ if delivered == "yes" => String::from("250 2.0.0 OK"),
+            _ => String::from("250 2.0.0 Queued"),
+        };
+        status.insert(delivery.recipient.clone(), json!({ "smtpReply": reply.trim_end(), "delivered": delivered, "displayed": "unknown" }));
+    }
+    let value = json!({
+        "id": super::id('S', submission.id),
+        "identityId": submission.identity_id,
+        "emailId": super::id('E', submission.email_id),
+        "threadId": super::id('T', submission.email_id),
+        "envelope": submission.envelope,
+        "sendAt": message::utc_date(submission.send_at),
+        "undoStatus": "final",
+        "deliveryStatus": status,
+        "dsnBlobIds": [],
+        "mdnBlobIds": [],
+    });
+    Ok(value.as_object().cloned().unwrap_or_default())
+}
+
+/// # EmailSubmission/get
+pub fn get(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let ids = request::get_ids(arguments, context, MAX_OBJECTS_GET)?;
+    let properties = request::strings(arguments, "properties")?;
+    let owned: Vec<Submission> = submissions().iter().filter(|submission| submission.account_id == context.account.id).cloned().collect();
+    let mut list = Vec::new();
+    let mut not_found = Vec::new();
+    match ids {
+        None => {
+            for submission in &owned {
+                list.push(request::pick(object(submission, context)?, properties.as_deref()));
+            }
+        }
+        Some(ids) => {
+            for id in ids {
+                match parse_id('S', &id).and_then(|number| owned.iter().find(|submission| submission.id == number)) {
+                    Some(submission) => list.push(request::pick(object(submission, context)?, properties.as_deref())),
+                    None => not_found.push(id),
+                }
+            }
+        }
+    }
+    Ok(json!({ "accountId": context.account_id(), "state": submission_state(context.account.id), "list": list, "notFound": not_found }))
+}
+
+/// # EmailSubmission/query
+/// 支持 identityIds、emailIds、threadIds 与 undoStatus 过滤，按 sentAt 排序。
+pub fn query(arguments: &Map<String, Value>, context: &Context) -> Result<Value> {
+    let mut owned: Vec<Submission> = submissions().iter().filter(|submission| submission.account_id == context.account.id).cloned().collect();
+    if let Some(filter) = arguments.get("filter").filter(|filter| !filter.is_null()) {
+        let filter = filter.as_object().ok_or_else(|| request::invalid("filter must be a FilterCondition"))?;
+        for (key, value) in filter {
+            let list = |value: &Value| value.as_array().map(|items| items.iter().filter_map(Value::as_str).map(String::from).collect::<Vec<_>>());
+            match key.as_str() {
+                "identityIds" => {
+                    let ids = list(value).ok_or_else(|| request::invalid("identityIds must be an array"))?;
+                    owned.retain(|submission| ids.contains(&submission.identity_id));
+                }
+                "emailIds" | "threadIds" => {
+                    let prefix = if key == "emailIds" { 'E' } else { 'T' };
+                    let ids = list(value).ok_or_else(|| request::invalid(format!("{} must be an array", key)))?;
+                    owned.retain(|submission| ids.contains(&super::id(prefix, submission.email_id)));
+                }
+                "undoStatus" => owned.retain(|_| value.as_str() == Some("final")),
+                "before" | "after" => {
+                    let date = value.as_str().and_then(crate::utils::parse_rfc3339).ok_or_else(|| request::invalid(format!("Invalid {}", key)))?;
+                    owned.retain(|submission| (submission.send_at < date) == (key == "before"));
+                }
+                _ => return Err(MethodError::describe("unsupportedFilter", format!("Unsupported filter {}", key))),
+            }
+        }
+    }
+    for comparator in arguments.get("sort").and_then(Value::as_array).into_iter().flatten() {
+        match comparator["property"].as_str() {
+            Some("sentAt" | "emailId" | "threadId") => {}
+            _ => return Err(MethodError::new("unsupportedSort")),
+        }
+        if comparator["isAscending"].as_bool() == Some(false) {
+            owned.reverse();
+        }
+    }
+    let ids = owned.iter().map(|submission| super::id('S', submission.id)).collect();
+    super::mailbox::window(context, ids, arguments, submission_state(context.account.id))
+}
+
+/// # 解析 envelope 中的地址
+fn envelope_address(value: &Value) -> Option<String> {
+    let address = value["email"].as_str()?;
+    if !value["parameters"].is_null() && value["parameters"].as_object().is_none_or(|parameters| !parameters.is_empty()) {
+        return None;
+    }
+    is_valid_address(address).then(|| address.to_string())
+}
+
+/// # 邮件中的收件人
+/// To、Cc 与 Bcc 中的所有地址，去掉重复。
+fn recipients(raw: &[u8]) -> Vec<String> {
+    let mut recipients: Vec<String> = Vec::new();
+    for name in ["To", "Cc", "Bcc"] {
+        for address in message::header_addresses(raw, name) {
+            if is_valid_address(&address) && !recipients.iter().any(|existing| existing.eq_ignore_ascii_case(&address)) {
+                recipients.push(address);
+            }
+        }
+    }
+    recipients
+}
+
+/// # 去掉 Bcc 邮件头（RFC 5322 3.6.3）
+fn strip_bcc(raw: &[u8]) -> Vec<u8> {
+    let end = raw.windows(4).position(|window| window == b"\r\n\r\n").map_or(raw.len(), |position| position + 2);
+    let (header, body) = raw.split_at(end);
+    let mut output = Vec::with_capacity(raw.len());
+    let mut skipping = false;
+    for line in header.split_inclusive(|&byte| byte == b'\n') {
+        if !line.starts_with(b" ") && !line.starts_with(b"\t") {
+            skipping = line.len() >= 4 && line[..4].eq_ignore_ascii_case(b"bcc:");
+        }
+        if !skipping {
+            output.extend_from_slice(line);
+        }
+    }
+    output.extend_from_slice(body);
+    output
+}
+
+/// # 提交一封邮件
+/// ## 参数
+/// - properties: EmailSubmission 的属性
+/// - addresses: 账号的所有地址
+/// ## 返回值
+/// - std::result::Result<Submission, Value>，错误为 SetError
+fn submit(properties: &Map<String, Value>, addresses: &[String], context: &Context) -> std::result::Result<Submission, Value> {
+    let invalid = |property: &str, description: &str| json!({ "type": "invalidProperties", "properties": [property], "description": description });
+    for key in properties.keys() {
+        if !["identityId", "emailId", "envelope"].contains(&key.as_str()) {
+            return Err(invalid(key, "Unknown or server-set property"));
+        }
+    }
+    let wanted = properties.get("identityId").and_then(Value::as_str).ok_or_else(|| invalid("identityId", "identityId is required"))?;
+    let Some(identity) = addresses.iter().find(|address| identity_id(address) == wanted) else {
+        return Err(invalid("identityId", "Identity not found"));
+    };
+    let email_id = properties.get("emailId").and_then(Value::as_str).and_then(|id| context.resolve(id)).and_then(|id| parse_id('E', &id));
+    let mailboxes = context.mailboxes().map_err(|_| set_error("serverFail", "Storage error"))?;
+    let messages = context.messages(&mailboxes).map_err(|_| set_error("serverFail", "Storage error"))?;
+    let Some(email_id) = email_id.filter(|id| messages.iter().any(|message| message.id == *id)) else {
+        return Err(invalid("emailId", "Email not found"));
+    };
+    let raw = context.store.raw(email_id).ok().flatten().ok_or_else(|| set_error("serverFail", "Message content missing"))?;
+
+    let (sender, rcpt) = match properties.get("envelope").filter(|envelope| !envelope.is_null()) {
+        Some(envelope) => {
+            let sender = envelope_address(&envelope["mailFrom"]).ok_or_else(|| invalid("envelope", "Invalid mailFrom"))?;
+            let rcpt = envelope["rcptTo"].as_array().ok_or_else(|| invalid("envelope", "Invalid rcptTo"))?;
+            let rcpt: Vec<String> = rcpt.iter().map(envelope_address).collect::<Option<_>>().ok_or_else(|| invalid("envelope", "Invalid rcptTo"))?;
+            (sender, rcpt)
+        }
+        None => (identity.clone(), recipients(&raw)),
+    };
+    if !addresses.iter().any(|address| address.eq_ignore_ascii_case(&sender)) {
+        warning!("JMAP 账号 {} 尝试使用发件人 <{}>，已拒绝", context.account.address(), sender);
+        return Err(set_error("forbiddenFrom", "Sender address not owned by the account"));
+    }
+    if rcpt.is_empty() {
+        return Err(set_error("noRecipients", "No recipients"));
+    }
+    if rcpt.len() > smtp::MAX_RECIPIENTS {
+        return Err(set_error("tooManyRecipients", format!("At most {} recipients", smtp::MAX_RECIPIENTS)));
+    }
+
+    let trace = format!(
+        "Received: from [{}] ({})\r\n\tby {} (ZitMail) via JMAP with HTTP;\r\n\t{}\r\n",
+        context.peer,
+        context.account.address(),
+        context.hostname,
+        Utc::now().to_rfc2822()
+    );
+    let raw = [trace.as_bytes(), &strip_bcc(&raw)].concat();
+    let recipients: Vec<(String, RecipientDsn)> = rcpt.iter().map(|address| (address.clone(), RecipientDsn::default())).collect();
+    let id = queue::enqueue(context.store, &sender, &Dsn::default(), &recipients, &raw).map_err(|e| {
+        error!("JMAP 无法将邮件加入发送队列：{}", e);
+        set_error("serverFail", "Storage error")
+    })?;
+    info!("JMAP 已将 <{}> 发往 {} 的邮件加入发送队列 #{}（{} 字节）", sender, rcpt.join("、"), id, raw.len());
+    let envelope = json!({
+        "mailFrom": { "email": sender, "parameters": null },
+        "rcptTo": rcpt.iter().map(|address| json!({ "email": address, "parameters": null })).collect::<Vec<_>>(),
+    });
+    Ok(Submission { id, account_id: context.account.id, identity_id: wanted.to_string(), email_id, envelope, send_at: Utc::now() })
+}
+
+/// # EmailSubmission/set
+/// 提交成功后按 onSuccessUpdateEmail 与 onSuccessDestroyEmail 修改邮件，附带一个 Email/set 响应（RFC 8621 7.5）。
+pub fn set(arguments: &Map<String, Value>, context: &mut Context) -> Result<Output> {
+    let old_state = submission_state(context.account.id);
+    let set = request::set_request(arguments, &old_state)?;
+    let addresses = addresses(context)?;
+    let mut response = SetResponse::default();
+    // 提交的创建 id 与邮件 id
+    let mut submitted: Vec<(String, i64, i64)> = Vec::new();
+
+    for (creation, properties) in set.create {
+        match submit(&properties, &addresses, context) {
+            Ok(submission) => {
+                let id = super::id('S', submission.id);
+                context.created.insert(creation.clone(), id.clone());
+                submitted.push((creation.clone(), submission.id, submission.email_id));
+                response.created.insert(creation, json!({ "id": id, "sendAt": message::utc_date(submission.send_at), "undoStatus": "final" }));
+                let mut submissions = submissions();
+                submissions.push(submission);
+                let owned = submissions.iter().filter(|other| other.account_id == context.account.id).count();
+                if owned > MAX_SUBMISSIONS
+                    && let Some(index) = submissions.iter().position(|other| other.account_id == context.account.id)
+                {
+                    submissions.remove(index);
+                }
+            }
+            Err(error) => {
+                response.not_created.insert(creation, error);
+            }
+        }
+    }
+    // 邮件已加入队列，不能撤销
+    for (id, _) in set.update {
+        response.not_updated.insert(id, set_error("cannotUnsend", "The message has already been queued"));
+    }
+    for id in set.destroy {
+        response.not_destroyed.insert(id, set_error("forbidden", "EmailSubmission cannot be destroyed"));
+    }
+
+    // #创建 id 或 S 开头的 id 对应提交的邮件
+    let email_of = |reference: &str| -> Option<String> {
+        let found = match reference.strip_prefix('#') {
+            Some(creation) => submitted.iter().find(|(id, _, _)| id == creation),
+            None => submitted.iter().find(|(_, id, _)| Some(*id) == parse_id('S', reference)),
+        };
+        found.map(|(_, _, email_id)| super::id('E', *email_id))
+    };
+    let mut update = Map::new();
+    if let Some(patches) = arguments.get("onSuccessUpdateEmail").and_then(Value::as_object) {
+        for (reference, patch) in patches {
+            if let Some(email) = email_of(reference) {
+                update.insert(email, patch.clone());
+            }
+        }
+    }
+    let destroy: Vec<String> = request::strings(arguments, "onSuccessDestroyEmail")?.unwrap_or_default().iter().filter_map(|reference| email_of(reference)).collect();
+
+    let mut output = vec![("EmailSubmission/set", response.into_value(context.account_id(), old_state, submission_state(context.account.id)))];
+    if !update.is_empty() || !destroy.is_empty() {
+        let arguments = json!({ "accountId": context.account_id(), "update": update, "destroy": destroy });
+        let arguments = arguments.as_object().cloned().unwrap_or_default();
+        output.push(("Email/set", email::set(&arguments, context)?));
+    }
+    Ok(output)
+}
@@ -0,0 +1,245 @@
+/* 监听与连接 */
+/*
+# SMTP、IMAP、POP3 共用的监听
+## 用法
+impl Service for Imap { ... }                                     <-- 协议只提供会话循环
+let handle = listener::start(&config.address, listener, tls, Imap { store })?;
+match listener::read_line(&mut connection, MAX_LINE)? { Line::Text(line) => ..., Line::TooLong => ..., Line::Closed => ... }
+listener::send(&mut connection, &data, "IMAP", id)?;
+## 说明
+绑定端口后在后台线程中接受连接，每个连接使用一个线程。
+同一协议的所有端口共用连接数上限 MAX_CONNECTIONS，超出时以协议的明文应答拒绝，隐式 TLS 端口直接关闭。
+每个连接分配一个在该协议内递增的编号，日志以「协议 #编号」开头。
+ */
+use rustls::ServerConfig;
+use std::io::{self, BufRead, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::tls::{Connection, Stream, Tls};
+
+/// 同时处理的连接数上限，同一协议的所有端口共用
+const MAX_CONNECTIONS: usize = 256;
+
+/// 调试日志中单次响应显示的长度上限，FETCH、RETR 的邮件内容不完整记录
+const MAX_LOG: usize = 1024;
+
+/// 监听的端口
+#[derive(Debug, Clone, Copy)]
+pub struct Listener {
+    /// 服务名称，用于日志
+    pub name: &'static str,
+    pub port: u16,
+    /// 连接建立后立即进行 TLS 握手（RFC 8314）
+    pub implicit_tls: bool,
+}
+
+/// 一种协议的连接计数
+pub struct Counter {
+    next_id: AtomicU64,
+    connections: AtomicUsize,
+}
+
+impl Counter {
+    pub const fn new() -> Counter {
+        Counter { next_id: AtomicU64::new(1), connections: AtomicUsize::new(0) }
+    }
+}
+
+/// 新连接
+pub struct Client {
+    /// 连接编号
+    pub id: u64,
+    pub peer: IpAddr,
+    /// 连接的加密状态
+    pub state: Tls,
+    /// TLS 配置，未启用 TLS 时为 None
+    pub tls: Option<Arc<ServerConfig>>,
+}
+
+/// 协议的会话循环
+pub trait Service: Send + Sync + 'static {
+    /// 协议名称，用于日志，例如 IMAP
+    const PROTOCOL: &'static str;
+    /// 等待客户端数据的超时
+    const TIMEOUT: Duration;
+
+    /// # 连接计数，同一协议的所有端口共用同一个
+    fn counter(&self) -> &'static Counter;
+
+    /// # 连接数已达上限时发送的明文应答
+    fn busy(&self) -> Vec<u8>;
+
+    /// # 处理一个连接
+    /// 连接已设置超时，隐式 TLS 端口在第一次读写时握手。
+    /// ## 参数
+    /// - connection: 连接
+    /// - client: 连接编号、客户端地址与加密状态
+    /// ## 返回值
+    /// - io::Result<()>，连接中断时返回错误
+    fn serve(&self, connection: Connection, client: Client) -> io::Result<()>;
+}
+
+/// # 启动服务
+/// 绑定端口后在后台线程中接受连接，每个连接使用一个线程。
+/// ## 参数
+/// - address: 监听的地址
+/// - listener: 监听的端口
+/// - tls: TLS 配置，未启用 TLS 时为 None
+/// - service: 协议的会话循环
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start<S: Service>(address: &str, listener: Listener, tls: Option<Arc<ServerConfig>>, service: S) -> io::Result<JoinHandle<()>> {
+    let ip: IpAddr = address.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, address.to_string()))?;
+    let socket = TcpListener::bind(SocketAddr::new(ip, listener.port))?;
+    info!("{} 服务已启动，监听 {}", listener.name, socket.local_addr()?);
+
+    let service = Arc::new(service);
+    thread::Builder::new().name(listener.name.to_lowercase()).spawn(move || {
+        for stream in socket.incoming() {
+            match stream {
+                Ok(stream) => accept(stream, listener, &tls, &service),
+                Err(e) => warning!("{} 无法接受连接：{}", listener.name, e),
+            }
+        }
+    })
+}
+
+/// # 为新连接创建线程
+fn accept<S: Service>(stream: TcpStream, listener: Listener, tls: &Option<Arc<ServerConfig>>, service: &Arc<S>) {
+    let counter = service.counter();
+    let id = counter.next_id.fetch_add(1, Ordering::Relaxed);
+    let peer = match stream.peer_addr() {
+        Ok(peer) => peer,
+        Err(e) => {
+            debug!("{} #{} 无法获取客户端地址：{}", S::PROTOCOL, id, e);
+            return;
+        }
+    };
+    info!("{} #{} 来自 {} 的连接（{}）", S::PROTOCOL, id, peer, listener.name);
+
+    if counter.connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+        counter.connections.fetch_sub(1, Ordering::SeqCst);
+        warning!("{} #{} 连接数已达上限 {}，拒绝连接", S::PROTOCOL, id, MAX_CONNECTIONS);
+        // 隐式 TLS 端口无法发送明文应答
+        if !listener.implicit_tls {
+            let _ = (&stream).write_all(&service.busy());
+        }
+        return;
+    }
+
+    let state = match (tls, listener.implicit_tls) {
+        (None, _) => Tls::Unavailable,
+        (Some(_), false) => Tls::Available,
+        (Some(_), true) => Tls::Active,
+    };
+    let client = Client { id, peer: peer.ip(), state, tls: tls.clone() };
+    let service = Arc::clone(service);
+    let spawned = thread::Builder::new().name(format!("{}-{}", S::PROTOCOL.to_lowercase(), id)).spawn(move || {
+        match connect::<S>(stream, &client).and_then(|connection| service.serve(connection, client)) {
+            Ok(()) => info!("{} #{} 连接关闭", S::PROTOCOL, id),
+            Err(e) => info!("{} #{} 连接中断：{}", S::PROTOCOL, id, e),
+        }
+        counter.connections.fetch_sub(1, Ordering::SeqCst);
+    });
+    if let Err(e) = spawned {
+        counter.connections.fetch_sub(1, Ordering::SeqCst);
+        error!("{} #{} 无法创建线程：{}", S::PROTOCOL, id, e);
+    }
+}
+
+/// # 设置超时并创建连接
+fn connect<S: Service>(stream: TcpStream, client: &Client) -> io::Result<Connection> {
+    stream.set_read_timeout(Some(S::TIMEOUT))?;
+    stream.set_write_timeout(Some(S::TIMEOUT))?;
+    match &client.tls {
+        Some(config) if client.state == Tls::Active => Ok(Connection::new(Stream::accept(stream, config)?)),
+        _ => Ok(Connection::new(Stream::Plain(stream))),
+    }
+}
+
+/// # 发送并记录调试日志
+/// 超过 MAX_LOG 的内容只记录第一行与长度。
+/// ## 参数
+/// - connection: 连接
+/// - data: 发送的内容
+/// - protocol: 协议名称
+/// - id: 连接编号
+/// ## 返回值
+/// - io::Result<()>
+pub fn send(connection: &mut Connection, data: &[u8], protocol: &str, id: u64) -> io::Result<()> {
+    if data.len() <= MAX_LOG {
+        debug!("{} #{} S: {}", protocol, id, String::from_utf8_lossy(data).trim_end().replace("\r\n", " | "));
+    } else {
+        let first = data.split(|&byte| byte == b'\n').next().unwrap_or_default();
+        debug!("{} #{} S: {} …（共 {} 字节）", protocol, id, String::from_utf8_lossy(first).trim_end(), data.len());
+    }
+    connection.send(data)
+}
+
+/// 读取一行的结果
+#[derive(Debug, PartialEq)]
+pub enum Line {
+    /// 不含行尾的内容
+    Text(Vec<u8>),
+    /// 超出长度上限，已丢弃到行尾
+    TooLong,
+    /// 客户端关闭连接
+    Closed,
+}
+
+/// # 读取一行
+/// 行尾可以是 CRLF 或 LF。
+/// ## 参数
+/// - reader: 输入
+/// - limit: 长度上限（含 CRLF）
+/// ## 返回值
+/// - io::Result<Line>
+pub fn read_line(reader: &mut impl BufRead, limit: usize) -> io::Result<Line> {
+    let mut buffer = Vec::new();
+    reader.take(limit as u64).read_until(b'\n', &mut buffer)?;
+    if buffer.is_empty() {
+        return Ok(Line::Closed);
+    }
+    if !buffer.ends_with(b"\n") {
+        if buffer.len() < limit {
+            return Ok(Line::Closed);
+        }
+        // 丢弃到行尾
+        let mut rest = Vec::new();
+        while !rest.ends_with(b"\n") {
+            rest.clear();
+            if reader.take(limit as u64).read_until(b'\n', &mut rest)? == 0 {
+                return Ok(Line::Closed);
+            }
+        }
+        return Ok(Line::TooLong);
+    }
+    buffer.truncate(buffer.len() - 1);
+    if buffer.ends_with(b"\r") {
+        buffer.truncate(buffer.len() - 1);
+    }
+    Ok(Line::Text(buffer))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let mut reader = &b"NOOP\r\nbare\nabcdefghij\r\nQUIT\r\nlast"[..];
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::Text(b"NOOP".to_vec()));
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::Text(b"bare".to_vec()));
+        // 过长的行丢弃到行尾，之后的行不受影响
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::TooLong);
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::Text(b"QUIT".to_vec()));
+        // 没有行尾时连接已关闭
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::Closed);
+        assert_eq!(read_line(&mut reader, 8).unwrap(), Line::Closed);
+        assert_eq!(read_line(&mut &b"abcdefghij"[..], 8).unwrap(), Line::Closed);
+    }
+}
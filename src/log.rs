@@ -36,6 +36,7 @@ const FILE_PATH: &str = "LM.log";
 struct LoggerConfig {
     debug: bool,
     record: bool,
+    #[allow(dead_code)]
     roll: u64,
     color: bool,
     time_zone: String,
@@ -65,6 +66,23 @@ pub static LOGGER: OnceLock<Logger> = OnceLock::new();
 
 static THREAD_HANDLE: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
 
+// 已注册的敏感信息，输出日志前替换为 ******
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// # 注册敏感信息
+/// 之后的日志中出现该内容时显示为 ******
+/// ## 参数
+/// - secret: &str
+pub fn redact(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+    }
+}
+
 pub struct LoggerBuilder {
     debug: bool,
     record: bool,
@@ -126,7 +144,6 @@ impl LoggerBuilder {
                 let mut writer = BufWriter::new(
                     OpenOptions::new()
                         .create(true)
-                        .write(true)
                         .append(true)
                         .open(file_path)
                         .unwrap(),
@@ -152,7 +169,7 @@ impl LoggerBuilder {
                                         .unwrap();
                                     let reader = BufReader::new(file);
                                     let lines: Vec<String> = reader.lines()
-                                        .filter_map(Result::ok)
+                                        .map_while(Result::ok)
                                         .collect();
                                     
                                     // 如果超过最大行数，只保留最新的roll行
@@ -166,7 +183,6 @@ impl LoggerBuilder {
                                     writer = BufWriter::new(
                                         OpenOptions::new()
                                             .create(true)
-                                            .write(true)
                                             .append(true)
                                             .open(file_path)
                                             .unwrap(),
@@ -237,6 +253,11 @@ impl Logger {
             LogLevel::Error => "错误",
         };
 
+        let mut message = message.to_string();
+        for secret in SECRETS.lock().unwrap().iter() {
+            message = message.replace(secret.as_str(), "******");
+        }
+
         let time = get_current_time(&self.config.time_zone);
         if self.config.color {
             // 颜色渲染
@@ -254,11 +275,11 @@ impl Logger {
         }
 
         // Log formatting and printing logic...
-        if self.config.record {
-            if let Some(sender) = &self.sender {
-                let log_line = format!("|{}|{}|{}", time, display_level, message);
-                let _ = sender.send(LogMessage::Log(log_line));
-            }
+        if self.config.record
+            && let Some(sender) = &self.sender
+        {
+            let log_line = format!("|{}|{}|{}", time, display_level, message);
+            let _ = sender.send(LogMessage::Log(log_line));
         }
     }
 
@@ -271,10 +292,10 @@ impl Logger {
     pub fn error(&self, message: &str) { self.log(LogLevel::Error, message); }
     
 pub fn quit() {
-    if let Some(logger) = LOGGER.get() {
-        if let Some(sender) = &logger.sender {
-            let _ = sender.send(LogMessage::Quit);
-        }
+    if let Some(logger) = LOGGER.get()
+        && let Some(sender) = &logger.sender
+    {
+        let _ = sender.send(LogMessage::Quit);
     }
     if let Some(handle) = THREAD_HANDLE.lock().unwrap().take() {
         let _ = handle.join();
@@ -285,12 +306,12 @@ pub fn quit() {
 #[macro_export]
 macro_rules! debug {
     ($msg:expr) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.debug($msg);
         }
     };
     ($($arg:tt)*) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.debug(&format!($($arg)*));
         }
     };
@@ -299,12 +320,12 @@ macro_rules! debug {
 #[macro_export]
 macro_rules! info {
     ($msg:expr) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.info($msg);
         }
     };
     ($($arg:tt)*) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.info(&format!($($arg)*));
         }
     };
@@ -313,12 +334,12 @@ macro_rules! info {
 #[macro_export]
 macro_rules! warning {
     ($msg:expr) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.warning($msg);
         }
     };
     ($($arg:tt)*) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.warning(&format!($($arg)*));
         }
     };
@@ -327,12 +348,12 @@ macro_rules! warning {
 #[macro_export]
 macro_rules! error {
     ($msg:expr) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.error($msg);
         }
     };
     ($($arg:tt)*) => {
-        if let Some(logger) = crate::log::LOGGER.get() {
+        if let Some(logger) = $crate::log::LOGGER.get() {
             logger.error(&format!($($arg)*));
         }
     };
@@ -341,6 +362,6 @@ macro_rules! error {
 #[macro_export]
 macro_rules! quit {
     () => {
-        crate::log::Logger::quit();
+        $crate::log::Logger::quit();
     };
 }
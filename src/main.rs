@@ -0,0 +1,215 @@
+#[macro_use]
+mod log;
+mod account;
+mod api;
+mod cli;
+mod config;
+mod default;
+mod editor;
+mod event;
+mod imap;
+mod jmap;
+mod listener;
+mod mime;
+mod password;
+mod pop3;
+mod sasl;
+mod secret;
+mod server;
+mod smtp;
+mod storage;
+mod timezone;
+mod tls;
+mod update;
+mod utils;
+
+use std::fmt::Display;
+use std::path::Path;
+use std::process::ExitCode;
+
+use crate::cli::{AccountAction, AliasAction, ApiAction, Cli, Command, ConfigAction, DbAction, DomainAction, HelpTopic, QueueAction};
+use crate::config::Config;
+use crate::default::{CONFIG, CONFIG_PATH};
+use crate::log::Logger;
+
+fn main() -> ExitCode {
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::USAGE);
+            quit!();
+            return ExitCode::from(2);
+        }
+    };
+
+    init_logger(cli.debug);
+    debug!("命令 {:?}", cli.command);
+    let code = run(cli.command);
+    quit!();
+    code
+}
+
+/// # 根据配置文件初始化日志模块
+/// 配置文件不存在或无法解析时使用默认配置，参数 --debug 会覆盖 Log.Debug。
+/// ## 参数
+/// - debug: bool
+fn init_logger(debug: bool) {
+    let parsed = std::fs::read_to_string(CONFIG_PATH).ok().map(|text| Config::parse(&text));
+    let config = match &parsed {
+        Some(Ok(config)) => config.clone(),
+        _ => Config::parse(CONFIG).unwrap(),
+    };
+
+    Logger::init()
+        .debug(debug || config.log.debug)
+        .record(config.log.record)
+        .roll(config.log.roll)
+        .color(config.log.color)
+        .time_zone(&config.general.time_zone)
+        .build();
+
+    if let Some(Err(e)) = parsed {
+        warning!("配置文件 {} 无法解析，日志模块使用默认设置：{}", CONFIG_PATH, e);
+    }
+}
+
+/// # 执行命令
+/// ## 参数
+/// - command: Command
+/// ## 返回值
+/// - ExitCode
+fn run(command: Command) -> ExitCode {
+    match command {
+        Command::Serve => {
+            info!("ZitMail {} 启动", env!("CARGO_PKG_VERSION"));
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            // 主账号用于登录控制台与 Web 服务器，未设置密码时一律拒绝启动
+            if config.main_account.password.is_empty() {
+                error!("主账号未设置密码，拒绝启动，请先使用 zmou password 设置密码");
+                return ExitCode::FAILURE;
+            }
+            debug!("配置 {:?}", config);
+
+            finish(server::run(&config))
+        }
+        Command::Config(ConfigAction::View) => finish(config::command::view(Path::new(CONFIG_PATH))),
+        Command::Config(ConfigAction::Remake) => finish(config::command::remake(Path::new(CONFIG_PATH))),
+        Command::Config(ConfigAction::Edit) => finish(config::command::edit(Path::new(CONFIG_PATH))),
+        Command::Help(HelpTopic::General) => {
+            println!("{}", cli::USAGE);
+            ExitCode::SUCCESS
+        }
+        Command::Help(HelpTopic::Command(command)) => {
+            println!("{}", cli::usage(&command).unwrap_or_default());
+            ExitCode::SUCCESS
+        }
+        Command::Help(HelpTopic::TimeZone(filter)) => {
+            if timezone::list(filter.as_deref()) > 0 { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+        }
+        Command::Help(HelpTopic::Api) => {
+            println!("{}", api::HELP);
+            ExitCode::SUCCESS
+        }
+        Command::Password(params) => finish(password::reset(Path::new(CONFIG_PATH), &params)),
+        Command::Api(ApiAction::List) => finish(api::key::list(Path::new(CONFIG_PATH))),
+        Command::Api(ApiAction::Add { id, expires }) => finish(api::key::add(Path::new(CONFIG_PATH), &id, expires)),
+        Command::Api(ApiAction::Delete(id)) => finish(api::key::delete(Path::new(CONFIG_PATH), &id)),
+        Command::Update { rollback: false } => finish(update::update(Path::new(CONFIG_PATH))),
+        Command::Update { rollback: true } => finish(update::rollback()),
+        Command::Db(action) => {
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            finish(match action {
+                DbAction::Migrate => storage::command::migrate(&config),
+                DbAction::Status => storage::command::status(&config),
+                DbAction::Rollback => storage::command::rollback(&config),
+            })
+        }
+        Command::Domain(action) => {
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            finish(match action {
+                DomainAction::List => account::domain_list(&config),
+                DomainAction::Add(name) => account::domain_add(&config, &name),
+                DomainAction::Delete(name) => account::domain_delete(&config, &name),
+            })
+        }
+        Command::Account(action) => {
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            finish(match action {
+                AccountAction::List => account::list(&config),
+                AccountAction::Add(address) => account::add(&config, &address),
+                AccountAction::Delete(address) => account::delete(&config, &address),
+                AccountAction::Password(address) => account::reset_password(&config, &address),
+                AccountAction::Pop3 { address, delete } => account::set_pop3(&config, &address, delete),
+            })
+        }
+        Command::Alias(action) => {
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            finish(match action {
+                AliasAction::List => account::alias_list(&config),
+                AliasAction::Add { alias, account } => account::alias_add(&config, &alias, &account),
+                AliasAction::Delete(alias) => account::alias_delete(&config, &alias),
+            })
+        }
+        Command::Queue(action) => {
+            let Some(config) = load_config() else {
+                return ExitCode::FAILURE;
+            };
+            finish(match action {
+                QueueAction::List(filter) => smtp::command::list(&config, &filter),
+                QueueAction::Show(id) => smtp::command::show(&config, id),
+                QueueAction::Retry(id) => smtp::command::retry(&config, id),
+                QueueAction::Flush => smtp::command::flush(&config),
+                QueueAction::Hold(id) => smtp::command::hold(&config, id, true),
+                QueueAction::Release(id) => smtp::command::hold(&config, id, false),
+                QueueAction::Delete(id, filter) => smtp::command::delete(&config, id, &filter),
+            })
+        }
+    }
+}
+
+/// # 读取配置
+/// 校验配置并解析敏感配置项，出错时记录全部问题。
+/// ## 返回值
+/// - Option<Config>
+fn load_config() -> Option<Config> {
+    let mut config = match Config::load(CONFIG_PATH) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{}", e);
+            return None;
+        }
+    };
+    if let Err(issues) = config.validate().and_then(|_| config.resolve_secrets()) {
+        for issue in &issues {
+            error!("{}", issue);
+        }
+        error!("配置文件共有 {} 处错误，请修正后重新启动", issues.len());
+        return None;
+    }
+    Some(config)
+}
+
+/// # 将命令的执行结果转换为退出码
+/// ## 参数
+/// - result: Result<(), E>
+/// ## 返回值
+/// - ExitCode
+fn finish<E: Display>(result: Result<(), E>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
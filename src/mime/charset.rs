@@ -0,0 +1,240 @@
+/* 字符集 */
+/*
+# 字符集
+## 用法
+let (text, lossy) = charset::decode("gb2312", &bytes);     <-- 按声明的字符集解码为 UTF-8，声明有误时识别实际的字符集
+let header = charset::header(part.header);                <-- 未编码的 8 位邮件头
+let encoding = charset::lookup("big5")?;                   <-- 按名称查找，例如 SEARCH CHARSET
+if !charset::matches("gb2312", &bytes) { ... }            <-- 内容是否符合声明的字符集
+## 说明
+由 encoding_rs 解码，支持 WHATWG Encoding Standard 的字符集：UTF-8、GBK 与 GB18030（GB2312 按 GB18030 解码）、Big5（含 HKSCS）、
+ISO-2022-JP、Shift_JIS、EUC-JP、EUC-KR、UTF-16、ISO-8859 与 windows-125x 等，另外补充了邮件中常见的别名，例如 cp936。
+ISO-8859-1 按 windows-1252 解码（与浏览器相同）；US-ASCII 与没有声明字符集相同。
+邮件中的字符集声明常常有误：内容按声明的字符集无法解码、字符集不受支持、US-ASCII 或未声明时出现 8 位内容，
+以及 GBK、Big5、单字节字符集等能解码的内容实为其他多字节字符集时，由 chardetng 识别实际的字符集；
+识别的结果也无法解码时按声明的字符集解码，无法解码的内容替换为 U+FFFD 并标记 lossy。
+只用于显示与搜索，IMAP FETCH 与 JMAP 下载仍返回原文。
+ */
+use std::borrow::Cow;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+
+/// 邮件中常见、WHATWG 标准没有收录的别名
+const ALIASES: [(&str, &str); 23] = [
+    ("cp936", "gbk"),
+    ("ms936", "gbk"),
+    ("windows-936", "gbk"),
+    ("euc-cn", "gbk"),
+    ("x-euc-cn", "gbk"),
+    ("gb18030-2000", "gb18030"),
+    ("cp950", "big5"),
+    ("ms950", "big5"),
+    ("windows-950", "big5"),
+    ("x-windows-950", "big5"),
+    ("big5hkscs", "big5"),
+    ("x-big5", "big5"),
+    ("cp932", "shift_jis"),
+    ("windows-932", "shift_jis"),
+    ("iso-2022-jp-1", "iso-2022-jp"),
+    ("iso-2022-jp-2", "iso-2022-jp"),
+    ("iso-2022-jp-3", "iso-2022-jp"),
+    ("csiso2022jp2", "iso-2022-jp"),
+    ("cp949", "euc-kr"),
+    ("ms949", "euc-kr"),
+    ("uhc", "euc-kr"),
+    ("ks_c_5601", "euc-kr"),
+    ("cp65001", "utf-8"),
+];
+
+/// 推翻能无误解码的字符集声明所需的 8 位字节数，约为 16 个汉字
+const EVIDENCE: usize = 32;
+
+/// US-ASCII 的名称，WHATWG 标准将其视为 windows-1252
+const ASCII: [&str; 4] = ["us-ascii", "ascii", "ansi_x3.4-1968", "iso646-us"];
+
+/// # 按名称查找字符集
+/// ## 参数
+/// - charset: 字符集名称，不区分大小写，可以带引号
+/// ## 返回值
+/// - Option<&Encoding>，不支持的字符集为 None；ISO-2022-KR 等只能替换为 U+FFFD 的字符集同样为 None
+pub fn lookup(charset: &str) -> Option<&'static Encoding> {
+    let label = charset.trim().trim_matches('"').to_ascii_lowercase();
+    let label = ALIASES.iter().find(|(alias, _)| *alias == label).map_or(label.as_str(), |(_, name)| name);
+    Encoding::for_label_no_replacement(label.as_bytes())
+}
+
+/// # 按字符集解码为 UTF-8
+/// 声明有误时识别实际的字符集，见文件开头的说明。
+/// ## 参数
+/// - charset: 字符集名称，不区分大小写
+/// - bytes: 内容
+/// ## 返回值
+/// - (String, bool)，bool 为是否有无法解码的内容
+pub fn decode(charset: &str, bytes: &[u8]) -> (String, bool) {
+    let charset = charset.trim().trim_matches('"');
+    let declared = match ASCII.iter().any(|name| name.eq_ignore_ascii_case(charset)) {
+        true => None,
+        false => lookup(charset),
+    };
+    let Some(declared) = declared else {
+        return undeclared(bytes);
+    };
+    let (text, malformed) = declared.decode_without_bom_handling(bytes);
+    // 单字节字符集能解码任何内容，GBK 与 Big5 等多字节字符集的编码范围也大量重叠，都无法据此判断声明是否正确
+    if !malformed && (bytes.is_ascii() || is_unicode(declared)) {
+        return (text.into_owned(), false);
+    }
+    // 内容能无误地解码时，只在识别的结果可信、8 位内容足够多时推翻声明，较短的内容常被识别为其他字符集；
+    // chardetng 只在内容是有效的 UTF-8 时识别为 UTF-8，这本身就足以推翻声明
+    let (guessed, confident) = detect(bytes);
+    let evident = guessed == UTF_8 || (confident && !guessed.is_single_byte() && bytes.iter().filter(|byte| !byte.is_ascii()).count() >= EVIDENCE);
+    if guessed != declared && (malformed || evident) {
+        let (guessed_text, guessed_malformed) = guessed.decode_without_bom_handling(bytes);
+        if !guessed_malformed {
+            return (guessed_text.into_owned(), false);
+        }
+    }
+    (text.into_owned(), malformed)
+}
+
+/// # 内容是否符合声明的字符集
+/// 用于检查邮件的问题，decode() 会自动纠正这些问题。
+/// ## 参数
+/// - charset: 字符集名称
+/// - bytes: 内容
+/// ## 返回值
+/// - bool，能按声明的字符集无误地解码时为 true；US-ASCII 与不支持的字符集只允许 7 位内容
+pub fn matches(charset: &str, bytes: &[u8]) -> bool {
+    let charset = charset.trim().trim_matches('"');
+    match lookup(charset).filter(|_| !ASCII.iter().any(|name| name.eq_ignore_ascii_case(charset))) {
+        Some(encoding) => encoding.decode_without_bom_handling_and_without_replacement(bytes).is_some(),
+        None => bytes.is_ascii(),
+    }
+}
+
+/// # 解码未编码的 8 位邮件头
+/// RFC 6532 之前的客户端常在邮件头中直接使用 GBK 等本地字符集。有效的 UTF-8 行原样保留，其他行的内容往往太少，难以识别，
+/// 优先采用同一邮件头中 encoded-word 或 charset 参数声明的字符集，无法解码时再按这些行的全部内容识别。
+/// ## 参数
+/// - header: 邮件头
+/// ## 返回值
+/// - Cow<str>，全部为有效的 UTF-8 时引用原文
+pub fn header(header: &[u8]) -> Cow<'_, str> {
+    if let Ok(text) = std::str::from_utf8(header) {
+        return Cow::Borrowed(text);
+    }
+    let lines = header.split_inclusive(|&byte| byte == b'\n');
+    let invalid = lines.clone().filter(|line| std::str::from_utf8(line).is_err()).flatten().copied().collect::<Vec<_>>();
+    let hinted = hint(header).filter(|encoding| encoding.decode_without_bom_handling_and_without_replacement(&invalid).is_some());
+    let encoding = hinted.unwrap_or_else(|| detect(&invalid).0);
+    let mut text = String::with_capacity(header.len());
+    for line in lines {
+        match std::str::from_utf8(line) {
+            Ok(line) => text.push_str(line),
+            Err(_) => text.push_str(&encoding.decode_without_bom_handling(line).0),
+        }
+    }
+    Cow::Owned(text)
+}
+
+/// # 邮件头中声明的字符集
+/// 来自 encoded-word（例如 =?gb2312?B?...?=）或 charset 参数。只采用中日韩等多字节字符集，单字节字符集与 UTF-16 能解码几乎任何内容，无法验证。
+fn hint(header: &[u8]) -> Option<&'static Encoding> {
+    let text = String::from_utf8_lossy(header).to_ascii_lowercase();
+    let words = text.split("=?").skip(1).filter_map(|rest| rest.split_once('?').map(|(charset, _)| charset));
+    let parameters = text.split("charset=").skip(1).map(|rest| rest.split([';', ' ', '\t', '\r', '\n']).next().unwrap_or_default());
+    words.chain(parameters).filter_map(lookup).find(|encoding| !encoding.is_single_byte() && !is_unicode(encoding))
+}
+
+/// # 解码没有声明字符集的内容
+/// 有效的 UTF-8 按 UTF-8 解码，其他内容识别字符集；7 位的 ISO-2022-JP 也是有效的 UTF-8，含有 ESC 时同样识别。
+fn undeclared(bytes: &[u8]) -> (String, bool) {
+    if let Ok(text) = std::str::from_utf8(bytes)
+        && !bytes.contains(&0x1b)
+    {
+        return (text.to_string(), false);
+    }
+    let (encoding, _) = detect(bytes);
+    let (text, malformed) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), malformed)
+}
+
+/// # 是否为 UTF-8 或 UTF-16
+fn is_unicode(encoding: &Encoding) -> bool {
+    [UTF_8, UTF_16LE, UTF_16BE].contains(&encoding)
+}
+
+/// # 识别字符集
+/// ## 返回值
+/// - (&Encoding, bool)，bool 为结果是否可信，内容太少或各字符集的可能性相近时为 false
+fn detect(bytes: &[u8]) -> (&'static Encoding, bool) {
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    detector.guess_assess(None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use encoding_rs::{BIG5, EUC_KR, GBK};
+
+    const SIMPLIFIED: &str = "各位同事：下周一上午九点在三楼会议室召开季度工作总结会议，请各部门负责人提前准备好本季度的工作报告，并于周五之前发送给行政部。";
+    const TRADITIONAL: &str = "各位同事：下週一上午九點在三樓會議室召開季度工作總結會議，請各部門負責人提前準備好本季度的工作報告，並於週五之前發送給行政部。";
+
+    /// # 按字符集编码
+    fn encode(encoding: &'static Encoding, text: &str) -> Vec<u8> {
+        let (bytes, _, unmappable) = encoding.encode(text);
+        assert!(!unmappable);
+        bytes.into_owned()
+    }
+
+    #[test]
+    fn aliases() {
+        assert_eq!(lookup("cp936"), Some(GBK));
+        assert_eq!(lookup(" \"CP950\" "), Some(BIG5));
+        assert_eq!(lookup("ks_c_5601"), Some(EUC_KR));
+        assert_eq!(lookup("GB2312"), Some(GBK));
+        assert_eq!(lookup("x-unknown"), None);
+        // ISO-2022-KR 只能替换为 U+FFFD
+        assert_eq!(lookup("iso-2022-kr"), None);
+    }
+
+    #[test]
+    fn big5_labelled_gb2312() {
+        let bytes = encode(BIG5, TRADITIONAL);
+        assert_ne!(GBK.decode_without_bom_handling(&bytes).0, TRADITIONAL);
+        assert_eq!(decode("gb2312", &bytes), (TRADITIONAL.to_string(), false));
+    }
+
+    #[test]
+    fn gbk_labelled_latin1() {
+        let bytes = encode(GBK, SIMPLIFIED);
+        assert!(matches("iso-8859-1", &bytes));
+        assert_eq!(decode("iso-8859-1", &bytes), (SIMPLIFIED.to_string(), false));
+    }
+
+    #[test]
+    fn utf8_labelled_gbk() {
+        let text = "你好，世界";
+        assert_eq!(decode("gbk", text.as_bytes()), (text.to_string(), false));
+    }
+
+    #[test]
+    fn short_gbk_keeps_declaration() {
+        // 内容太短，识别结果不足以推翻能无误解码的声明
+        let text = "会议改期";
+        let bytes = encode(GBK, text);
+        assert!(bytes.iter().filter(|byte| !byte.is_ascii()).count() < EVIDENCE);
+        assert_eq!(decode("gbk", &bytes), (text.to_string(), false));
+        assert_eq!(decode("cp936", &bytes), (text.to_string(), false));
+    }
+
+    #[test]
+    fn undeclared_charset() {
+        let bytes = encode(GBK, SIMPLIFIED);
+        assert_eq!(decode("us-ascii", &bytes), (SIMPLIFIED.to_string(), false));
+        assert_eq!(decode("x-unknown", &bytes), (SIMPLIFIED.to_string(), false));
+        assert!(!matches("us-ascii", &bytes));
+    }
+}
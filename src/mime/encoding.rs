@@ -0,0 +1,129 @@
+/* 内容传输编码 */
+/*
+# 解码
+## 用法
+let (data, error) = encoding::base64(body);               <-- 尽量解码，error 为发现的第一个问题
+let (data, error) = encoding::quoted_printable(body);
+let data = encoding::q(text)?;                            <-- encoded-word 的 Q 编码
+## 说明
+格式错误时不会失败：base64 跳过字母表之外的字符（RFC 2045 6.8），quoted-printable 保留无效的 =（RFC 2045 6.7 注释 1），
+同时返回 MimeError 说明问题的位置，调用者决定忽略还是拒绝。
+ */
+use super::MimeError;
+
+/// base64 字母表中字符的值
+fn sextet(byte: u8) -> Option<u8> {
+    match byte {
+        b'A'..=b'Z' => Some(byte - b'A'),
+        b'a'..=b'z' => Some(byte - b'a' + 26),
+        b'0'..=b'9' => Some(byte - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    }
+}
+
+/// # 解码 base64（RFC 2045 6.8）
+/// 忽略空白；= 之后的内容视为结束。
+/// ## 参数
+/// - body: 编码后的内容
+/// ## 返回值
+/// - (Vec<u8>, Option<MimeError>)，有字母表之外的字符、= 之后仍有内容或结尾多出一个字符时附带错误
+pub fn base64(body: &[u8]) -> (Vec<u8>, Option<MimeError>) {
+    let mut decoded = Vec::with_capacity(body.len() / 4 * 3);
+    let mut error = None;
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    let mut padded = false;
+    for (offset, &byte) in body.iter().enumerate() {
+        if byte.is_ascii_whitespace() {
+            continue;
+        }
+        if byte == b'=' {
+            padded = true;
+            continue;
+        }
+        let Some(value) = sextet(byte).filter(|_| !padded) else {
+            error.get_or_insert(MimeError::InvalidBase64(offset));
+            continue;
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    // 剩余 6 位时少了一个字符，无法组成完整的字节
+    if bits == 6 {
+        error.get_or_insert(MimeError::InvalidBase64(body.len()));
+    }
+    (decoded, error)
+}
+
+/// # 解码 quoted-printable（RFC 2045 6.7）
+/// = 加换行为软换行，= 与换行之间允许有空白；无效的 = 原样保留。
+/// ## 参数
+/// - body: 编码后的内容
+/// ## 返回值
+/// - (Vec<u8>, Option<MimeError>)，有无效的 = 时附带错误
+pub fn quoted_printable(body: &[u8]) -> (Vec<u8>, Option<MimeError>) {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut error = None;
+    let mut index = 0;
+    while index < body.len() {
+        let byte = body[index];
+        if byte != b'=' {
+            decoded.push(byte);
+            index += 1;
+            continue;
+        }
+        let rest = &body[index + 1..];
+        let padding = rest.iter().take_while(|&&byte| byte == b' ' || byte == b'\t').count();
+        if rest[padding..].starts_with(b"\r\n") {
+            index += padding + 3;
+        } else if rest[padding..].starts_with(b"\n") || padding == rest.len() {
+            index += padding + 2;
+        } else if let Some(value) = rest.get(..2).and_then(hex) {
+            decoded.push(value);
+            index += 3;
+        } else {
+            error.get_or_insert(MimeError::InvalidQuotedPrintable(index));
+            decoded.push(byte);
+            index += 1;
+        }
+    }
+    (decoded, error)
+}
+
+/// # 解码 encoded-word 的 Q 编码（RFC 2047 4.2）
+/// 与 quoted-printable 相似，_ 表示空格，不允许软换行。
+/// ## 返回值
+/// - Option<Vec<u8>>，有无效的 = 时为 None
+pub fn q(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'_' => decoded.push(b' '),
+            b'=' => {
+                decoded.push(hex(bytes.get(index + 1..index + 3)?)?);
+                index += 2;
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    Some(decoded)
+}
+
+/// # 两个十六进制字符的值
+/// 允许小写，RFC 2045 要求大写，但有的客户端使用小写。
+pub fn hex(pair: &[u8]) -> Option<u8> {
+    let digit = |byte: u8| (byte as char).to_digit(16);
+    match pair {
+        [high, low] => Some((digit(*high)? * 16 + digit(*low)?) as u8),
+        _ => None,
+    }
+}
@@ -0,0 +1,311 @@
+/* 邮件头字段 */
+/*
+# 字段与字段值
+## 用法
+let fields = header::fields(part.header);                  <-- 展开折叠的行，也用于 SMTP 与 IMAP SEARCH
+let (value, parameters) = header::parameters("attachment; filename*=UTF-8''%E4%BD%A0.txt");
+let name = header::parameter(&parameters, "filename");     <-- 合并 RFC 2231 的续行并按字符集解码
+let subject = header::decode_words(&subject);              <-- 解码 encoded-word（RFC 2047）
+let subject = header::try_decode_words(&subject)?;         <-- 同上，格式错误时返回 MimeError
+## 说明
+parameters() 引用字段值，只在参数值含有转义、折叠的行或参数名含有大写字母时复制，因此可以直接解析邮件头的原文。
+ */
+use std::borrow::Cow;
+
+use super::{MimeError, charset, encoding};
+
+/// 字段值的参数，(参数名, 参数值)
+pub type Parameters<'a> = Vec<(Cow<'a, str>, Cow<'a, str>)>;
+
+/// # 解析邮件头字段
+/// 展开折叠的行（RFC 5322 2.2.3），也用于 message/delivery-status 的字段组；未编码的 8 位内容按识别的字符集解码。
+/// ## 参数
+/// - headers: 邮件头部分，遇到空行时结束
+/// ## 返回值
+/// - Vec<(名称, 值)>，值已去除首尾的空白
+pub fn fields(headers: &[u8]) -> Vec<(String, String)> {
+    folded_fields(&charset::header(headers)).into_iter().map(|(name, value)| (name.to_string(), unfold(value).into_owned())).collect()
+}
+
+/// # 拆分邮件头字段，不展开折叠的行
+/// 不是字段也不是续行的行结束上一个字段。
+/// ## 参数
+/// - header: 邮件头，遇到空行时结束
+/// ## 返回值
+/// - Vec<(名称, 值)>，值为冒号之后的原文，包括折叠的行，不含结尾的换行
+pub fn folded_fields(header: &str) -> Vec<(&str, &str)> {
+    let mut fields = Vec::new();
+    // (名称, 值的起点, 值的终点)
+    let mut current: Option<(&str, usize, usize)> = None;
+    let mut offset = 0;
+    for line in header.split('\n') {
+        let start = offset;
+        offset += line.len() + 1;
+        let content = line.strip_suffix('\r').unwrap_or(line);
+        if content.is_empty() {
+            break;
+        }
+        if content.starts_with([' ', '\t']) {
+            if let Some((_, _, end)) = &mut current {
+                *end = start + content.len();
+            }
+            continue;
+        }
+        fields.extend(current.take().map(|(name, start, end)| (name, &header[start..end])));
+        if let Some((name, _)) = content.split_once(':') {
+            current = Some((name.trim(), start + name.len() + 1, start + content.len()));
+        }
+    }
+    fields.extend(current.map(|(name, start, end)| (name, &header[start..end])));
+    fields
+}
+
+/// # 展开折叠的行
+/// ## 参数
+/// - value: 字段值的原文
+/// ## 返回值
+/// - Cow<str>，已去除首尾的空白，每个续行替换为一个空格；没有折叠的行时引用原文
+pub fn unfold(value: &str) -> Cow<'_, str> {
+    let value = value.trim();
+    match value.contains('\n') {
+        true => Cow::Owned(value.split('\n').map(str::trim).collect::<Vec<_>>().join(" ")),
+        false => Cow::Borrowed(value),
+    }
+}
+
+/// # 转换为小写
+/// 没有大写字母时不复制。
+pub fn lowercase(value: Cow<'_, str>) -> Cow<'_, str> {
+    match value.bytes().any(|byte| byte.is_ascii_uppercase()) {
+        true => Cow::Owned(value.to_ascii_lowercase()),
+        false => value,
+    }
+}
+
+/// # 拆分字段值与参数
+/// 例如 text/plain; charset="utf-8" 为 (text/plain, [(charset, utf-8)])，用于 Content-Type 与 Content-Disposition。
+/// 字段值可以含有折叠的行。
+/// ## 参数
+/// - value: 字段值
+/// ## 返回值
+/// - (&str, Parameters)，参数名为小写，参数值已去掉引号与转义
+pub fn parameters(value: &str) -> (&str, Parameters<'_>) {
+    let mut segments = Vec::new();
+    let (mut start, mut quoted, mut escaped) = (0, false, false);
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                segments.push(&value[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&value[start..]);
+
+    let mut segments = segments.into_iter();
+    let main = segments.next().unwrap_or_default().trim();
+    let parameters = segments
+        .filter_map(|segment| {
+            let (name, value) = segment.split_once('=')?;
+            let value = match unfold(value) {
+                value if value.contains('\\') => Cow::Owned(unescape(&value)),
+                value => value,
+            };
+            let value = match value {
+                Cow::Borrowed(value) => Cow::Borrowed(unquote(value)),
+                Cow::Owned(value) => Cow::Owned(unquote(&value).to_string()),
+            };
+            Some((lowercase(unfold(name)), value))
+        })
+        .filter(|(name, _)| !name.is_empty())
+        .collect();
+    (main, parameters)
+}
+
+/// # 去掉引号内的转义（RFC 5322 3.2.4 quoted-pair）
+/// 引号保留，引号之外的反斜杠原样保留。
+fn unescape(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let (mut quoted, mut escaped) = (false, false);
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => {
+                escaped = true;
+                continue;
+            }
+            '"' => quoted = !quoted,
+            _ => {}
+        }
+        output.push(c);
+    }
+    output
+}
+
+/// # 去掉首尾的空白与两端的引号
+fn unquote(value: &str) -> &str {
+    let value = value.trim();
+    value.strip_prefix('"').and_then(|value| value.strip_suffix('"')).unwrap_or(value)
+}
+
+/// # 参数的值
+/// 支持 RFC 2231 的续行与字符集，例如 filename*=UTF-8''%E4%BD%A0.txt 或 filename*0*=...; filename*1*=...；
+/// 普通的参数值中有 encoded-word 时同样解码，有的客户端这样编码文件名。
+/// ## 参数
+/// - parameters: parameters() 得到的参数
+/// - name: 参数名，小写
+/// ## 返回值
+/// - Option<String>
+pub fn parameter(parameters: &[(Cow<'_, str>, Cow<'_, str>)], name: &str) -> Option<String> {
+    let find = |key: &str| parameters.iter().find(|(parameter, _)| parameter == key).map(|(_, value)| value.as_ref());
+    if let Some(value) = find(name) {
+        return Some(decode_words(value));
+    }
+    if let Some(value) = find(&format!("{}*", name)) {
+        let (charset, bytes) = extended(value, true);
+        return Some(charset::decode(charset.unwrap_or("utf-8"), &bytes).0);
+    }
+    let mut charset = None;
+    let mut bytes = Vec::new();
+    for index in 0.. {
+        if let Some(value) = find(&format!("{}*{}*", name, index)) {
+            let (section_charset, section) = extended(value, index == 0);
+            charset = charset.or(section_charset);
+            bytes.extend(section);
+        } else if let Some(value) = find(&format!("{}*{}", name, index)) {
+            bytes.extend(value.as_bytes());
+        } else if index == 0 {
+            return None;
+        } else {
+            break;
+        }
+    }
+    Some(charset::decode(charset.unwrap_or("utf-8"), &bytes).0)
+}
+
+/// # 解码 RFC 2231 的扩展参数值
+/// ## 参数
+/// - value: 参数值
+/// - first: 是否为第一段，只有第一段带有字符集与语言，例如 UTF-8'zh'
+/// ## 返回值
+/// - (Option<&str>, Vec<u8>)，(字符集, 内容)，无效的 % 原样保留
+fn extended(value: &str, first: bool) -> (Option<&str>, Vec<u8>) {
+    let (charset, text) = match (first, value.splitn(3, '\'').collect::<Vec<_>>().as_slice()) {
+        (true, [charset, _, text]) => (Some(*charset).filter(|charset| !charset.is_empty()), *text),
+        _ => (None, value),
+    };
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], bytes.get(index + 1..index + 3).and_then(encoding::hex)) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    (charset, decoded)
+}
+
+/// # 解码 encoded-word（RFC 2047）
+/// 相邻的 encoded-word 之间的空白被忽略，格式错误的 encoded-word 保持原样。
+/// ## 参数
+/// - value: 展开后的字段值
+/// ## 返回值
+/// - String
+pub fn decode_words(value: &str) -> String {
+    words(value).0
+}
+
+/// # 解码 encoded-word，格式错误时返回错误
+/// ## 参数
+/// - value: 展开后的字段值
+/// ## 返回值
+/// - Result<String, MimeError>，encoded-word 无法解码或内容不符合其字符集时为 InvalidEncodedWord
+pub fn try_decode_words(value: &str) -> Result<String, MimeError> {
+    match words(value) {
+        (text, None) => Ok(text),
+        (_, Some(e)) => Err(e),
+    }
+}
+
+/// # 解码 encoded-word
+/// ## 返回值
+/// - (String, Option<MimeError>)，解码的结果与发现的第一个问题
+fn words(value: &str) -> (String, Option<MimeError>) {
+    let mut output = String::with_capacity(value.len());
+    let mut error = None;
+    let mut rest = value;
+    // 上一个 encoded-word 之后的空白，下一个仍是 encoded-word 时丢弃
+    let mut pending = "";
+    while let Some(start) = rest.find("=?") {
+        let decoded = match decode_word(&rest[start + 2..]) {
+            Some((length, Some((text, lossy)))) => {
+                if lossy {
+                    error.get_or_insert_with(|| MimeError::InvalidEncodedWord(rest[start..start + 2 + length].to_string()));
+                }
+                Some((length, text))
+            }
+            Some((length, None)) => {
+                error.get_or_insert_with(|| MimeError::InvalidEncodedWord(rest[start..start + 2 + length].to_string()));
+                None
+            }
+            None => None,
+        };
+        let Some((length, text)) = decoded else {
+            output.push_str(pending);
+            output.push_str(&rest[..start + 2]);
+            rest = &rest[start + 2..];
+            pending = "";
+            continue;
+        };
+        output.push_str(&rest[..start]);
+        output.push_str(&text);
+        rest = &rest[start + 2 + length..];
+        let space = rest.len() - rest.trim_start().len();
+        pending = "";
+        if rest[space..].starts_with("=?") {
+            pending = &rest[..space];
+            rest = &rest[space..];
+        }
+    }
+    output.push_str(pending);
+    output.push_str(rest);
+    (output, error)
+}
+
+/// # 解码一个 encoded-word
+/// ## 参数
+/// - input: =? 之后的内容，例如 UTF-8?B?5L2g5aW9?= ...
+/// ## 返回值
+/// - Option<(usize, Option<(String, bool)>)>，不是 encoded-word 时为 None；
+///   否则为 (包括 ?= 的长度, 解码后的文本与是否有无法解码的内容)，编码错误时文本为 None
+fn decode_word(input: &str) -> Option<(usize, Option<(String, bool)>)> {
+    let (charset, tail) = input.split_once('?')?;
+    let (encoding, tail) = tail.split_once('?')?;
+    let text = &tail[..tail.find("?=")?];
+    if charset.is_empty() || charset.contains(|c: char| c.is_ascii_whitespace()) || text.contains(|c: char| c.is_ascii_whitespace()) {
+        return None;
+    }
+    let length = charset.len() + encoding.len() + text.len() + 4;
+    // 去掉 RFC 2231 的语言标记，例如 UTF-8*zh
+    let charset = charset.split('*').next().unwrap_or_default();
+    let bytes = match encoding {
+        "B" | "b" => match encoding::base64(text.as_bytes()) {
+            (bytes, None) => Some(bytes),
+            (_, Some(_)) => None,
+        },
+        "Q" | "q" => encoding::q(text),
+        _ => None,
+    };
+    Some((length, bytes.map(|bytes| charset::decode(charset, &bytes))))
+}
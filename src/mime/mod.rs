@@ -0,0 +1,532 @@
+/* 邮件的 MIME 结构 */
+/*
+# MIME 结构
+## 结构
+mod.rs        邮件头与正文的分界、Content-Type、multipart 的各部分与 message/rfc822 所附的邮件（RFC 2045、RFC 2046）
+header.rs     字段的拆分与折叠（RFC 5322）、参数（RFC 2231）与 encoded-word（RFC 2047）
+encoding.rs   base64 与 quoted-printable（RFC 2045 6）
+charset.rs    字符集的解码与识别
+## 用法
+let message = mime::parse(&raw);
+message.find(&[1, 2])          <-- 按部分编号查找，例如 BODY[1.2]
+message.field("Subject")       <-- 邮件头，折叠的行已展开
+part.decode()                  <-- 按 Content-Transfer-Encoding 解码的正文，没有编码时引用原文
+part.try_decode()?             <-- 同上，编码错误时返回 MimeError
+part.text()                    <-- 文本部分按 charset 解码为 UTF-8，声明有误时识别实际的字符集
+message.errors()               <-- 结构与编码的所有问题
+## 说明
+IMAP、JMAP、SMTP 与搜索共用。各部分引用原文，不复制；Content-Type 的类型与参数同样引用邮件头，
+只在邮件头不是有效的 UTF-8、参数值需要转义或展开折叠的行时复制。
+解析不会失败，也不会因为任何输入 panic：缺少 Content-Type 或格式错误时按 text/plain 处理（multipart/digest 中为 message/rfc822），
+缺少结束分隔线时最后一部分延续到正文结束，嵌套超过 MAX_DEPTH 层时不再拆分。
+发现的问题记录在各部分的 defects 中，errors() 汇总这些问题并检查正文的编码，调用者据此决定容忍、记录或拒绝。
+ */
+use std::borrow::Cow;
+use std::fmt;
+
+pub mod charset;
+pub mod encoding;
+pub mod header;
+
+pub use header::{Parameters, fields, parameters};
+
+/// 最大嵌套层数
+const MAX_DEPTH: usize = 32;
+
+/// 邮件或其中的一部分
+#[derive(Debug, Clone)]
+pub struct Part<'a> {
+    /// 邮件头，含结尾的空行
+    pub header: &'a [u8],
+    pub body: &'a [u8],
+    /// 小写，例如 text
+    pub media_type: Cow<'a, str>,
+    /// 小写，例如 plain
+    pub subtype: Cow<'a, str>,
+    /// Content-Type 的参数，参数名为小写
+    pub parameters: Parameters<'a>,
+    /// multipart 的各部分
+    pub children: Vec<Part<'a>>,
+    /// message/rfc822 所附的邮件
+    pub message: Option<Box<Part<'a>>>,
+    /// 解析这一部分时发现的问题，不含各子部分的问题
+    pub defects: Vec<MimeError>,
+}
+
+/// 邮件格式的问题
+#[derive(Debug, Clone, PartialEq)]
+pub enum MimeError {
+    /// 邮件头中既不是字段也不是续行的行，附带行号（从 1 开始）
+    InvalidHeader(usize),
+    /// 无法解析的 Content-Type
+    InvalidContentType(String),
+    /// multipart 没有 boundary 参数
+    MissingBoundary,
+    /// multipart 中没有任何部分
+    EmptyMultipart,
+    /// multipart 没有结束分隔线
+    UnterminatedMultipart,
+    /// 嵌套超过 MAX_DEPTH 层，更深的部分没有拆分
+    TooDeep,
+    /// 不支持的 Content-Transfer-Encoding
+    UnknownEncoding(String),
+    /// base64 中的无效内容，附带在正文中的偏移
+    InvalidBase64(usize),
+    /// quoted-printable 中无效的 =，附带在正文中的偏移
+    InvalidQuotedPrintable(usize),
+    /// 无法解码的 encoded-word
+    InvalidEncodedWord(String),
+    /// 正文不符合声明的字符集
+    InvalidCharset(String),
+}
+
+impl fmt::Display for MimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MimeError::InvalidHeader(line) => write!(f, "邮件头第 {} 行格式错误", line),
+            MimeError::InvalidContentType(value) => write!(f, "无法解析的 Content-Type {}", value),
+            MimeError::MissingBoundary => write!(f, "multipart 缺少 boundary 参数"),
+            MimeError::EmptyMultipart => write!(f, "multipart 中没有任何部分"),
+            MimeError::UnterminatedMultipart => write!(f, "multipart 缺少结束分隔线"),
+            MimeError::TooDeep => write!(f, "嵌套超过 {} 层", MAX_DEPTH),
+            MimeError::UnknownEncoding(encoding) => write!(f, "不支持的 Content-Transfer-Encoding {}", encoding),
+            MimeError::InvalidBase64(offset) => write!(f, "base64 在第 {} 字节处无效", offset),
+            MimeError::InvalidQuotedPrintable(offset) => write!(f, "quoted-printable 在第 {} 字节处无效", offset),
+            MimeError::InvalidEncodedWord(word) => write!(f, "无法解码的 encoded-word {}", word),
+            MimeError::InvalidCharset(charset) => write!(f, "内容不符合字符集 {}", charset),
+        }
+    }
+}
+
+impl<'a> Part<'a> {
+    /// # 邮件头字段
+    /// ## 参数
+    /// - name: 字段名，不区分大小写
+    /// ## 返回值
+    /// - Option<String>，有多个同名字段时为第一个
+    pub fn field(&self, name: &str) -> Option<String> {
+        fields(self.header).into_iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+    }
+
+    /// # Content-Type 的参数
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_ref())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.media_type == "multipart"
+    }
+
+    /// # 按 Content-Transfer-Encoding 解码正文
+    /// 解码 base64 与 quoted-printable，格式错误的内容尽量解码；其他编码引用原文。
+    pub fn decode(&self) -> Cow<'a, [u8]> {
+        self.decoded().0
+    }
+
+    /// # 按 Content-Transfer-Encoding 解码正文
+    /// ## 返回值
+    /// - Result<Cow<[u8]>, MimeError>，编码错误或不支持的编码时返回错误
+    pub fn try_decode(&self) -> Result<Cow<'a, [u8]>, MimeError> {
+        match self.decoded() {
+            (data, None) => Ok(data),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    fn decoded(&self) -> (Cow<'a, [u8]>, Option<MimeError>) {
+        let encoding = self.field("Content-Transfer-Encoding").unwrap_or_default().to_ascii_lowercase();
+        match encoding.as_str() {
+            "base64" => {
+                let (data, error) = encoding::base64(self.body);
+                (Cow::Owned(data), error)
+            }
+            "quoted-printable" => {
+                let (data, error) = encoding::quoted_printable(self.body);
+                (Cow::Owned(data), error)
+            }
+            "" | "7bit" | "8bit" | "binary" => (Cow::Borrowed(self.body), None),
+            _ => (Cow::Borrowed(self.body), Some(MimeError::UnknownEncoding(encoding))),
+        }
+    }
+
+    /// # 文本内容
+    /// 解码正文后按 charset 参数转换为 UTF-8，没有 charset 时按 US-ASCII（RFC 2045 5.2）；声明有误时识别实际的字符集。
+    /// ## 返回值
+    /// - (String, bool)，bool 为是否有无法解码的内容
+    pub fn text(&self) -> (String, bool) {
+        charset::decode(self.parameter("charset").unwrap_or("us-ascii"), &self.decode())
+    }
+
+    /// # 所有不是 multipart 的部分
+    /// 按顺序列出，包括 message/rfc822 所附邮件中的部分。
+    pub fn leaves(&self) -> Vec<&Part<'a>> {
+        let mut leaves = Vec::new();
+        let mut stack = vec![self];
+        while let Some(part) = stack.pop() {
+            match (&part.message, part.children.is_empty()) {
+                (Some(message), _) => stack.push(message),
+                (None, false) => stack.extend(part.children.iter().rev()),
+                (None, true) => leaves.push(part),
+            }
+        }
+        leaves
+    }
+
+    /// # 邮件的所有问题
+    /// 汇总各部分解析时发现的问题，并检查邮件头的 encoded-word、正文的编码与文本部分的字符集。
+    /// ## 返回值
+    /// - Vec<MimeError>，没有问题时为空
+    pub fn errors(&self) -> Vec<MimeError> {
+        let mut errors = Vec::new();
+        let mut stack = vec![self];
+        while let Some(part) = stack.pop() {
+            errors.extend(part.defects.iter().cloned());
+            errors.extend(fields(part.header).iter().filter_map(|(_, value)| header::try_decode_words(value).err()));
+            if let Some(message) = &part.message {
+                stack.push(message);
+            }
+            stack.extend(part.children.iter().rev());
+            if part.is_multipart() || part.message.is_some() {
+                continue;
+            }
+            match part.try_decode() {
+                Err(e) => errors.push(e),
+                Ok(data) if part.media_type == "text" => {
+                    let charset = part.parameter("charset").unwrap_or("us-ascii");
+                    if !charset::matches(charset, &data) {
+                        errors.push(MimeError::InvalidCharset(charset.to_string()));
+                    }
+                }
+                Ok(_) => {}
+            }
+        }
+        errors
+    }
+
+    /// # 按部分编号查找
+    /// 非 multipart 邮件的正文为第 1 部分；message/rfc822 部分之后的编号指向所附邮件的各部分（RFC 9051 6.4.5）。
+    /// ## 参数
+    /// - path: 部分编号，例如 [1, 2] 表示 1.2
+    /// ## 返回值
+    /// - Option<&Part>
+    pub fn find(&self, path: &[u32]) -> Option<&Part<'a>> {
+        let mut part = self;
+        for (depth, &number) in path.iter().enumerate() {
+            let container = match (&part.message, depth) {
+                (_, 0) => part,
+                (Some(message), _) => message.as_ref(),
+                (None, _) if part.is_multipart() => part,
+                (None, _) => return None,
+            };
+            part = match container.is_multipart() {
+                true => container.children.get(number.checked_sub(1)? as usize)?,
+                false if number == 1 => container,
+                false => return None,
+            };
+        }
+        Some(part)
+    }
+}
+
+/// # 解析邮件
+/// ## 参数
+/// - raw: 邮件原文
+/// ## 返回值
+/// - Part
+pub fn parse(raw: &[u8]) -> Part<'_> {
+    parse_part(raw, false, 0)
+}
+
+/// # 解析一部分
+/// - digest: 是否位于 multipart/digest 中，决定缺少 Content-Type 时的类型
+fn parse_part(raw: &[u8], digest: bool, depth: usize) -> Part<'_> {
+    let (header, body) = split(raw);
+    let mut defects = check_header(header);
+    let content_type = content_type(header);
+    let parsed = match &content_type {
+        Some(Cow::Borrowed(value)) => media(value),
+        Some(Cow::Owned(value)) => media(value).map(|(media_type, subtype, parameters)| {
+            let parameters = parameters.into_iter().map(|(name, value)| (owned(name), owned(value))).collect();
+            (owned(media_type), owned(subtype), parameters)
+        }),
+        None => None,
+    };
+    let (media_type, subtype, parameters) = match parsed {
+        Some(parsed) => parsed,
+        None => {
+            if let Some(value) = content_type {
+                defects.push(MimeError::InvalidContentType(header::unfold(&value).into_owned()));
+            }
+            match digest {
+                true => (Cow::Borrowed("message"), Cow::Borrowed("rfc822"), Vec::new()),
+                false => (Cow::Borrowed("text"), Cow::Borrowed("plain"), Vec::new()),
+            }
+        }
+    };
+
+    let mut part = Part { header, body, media_type, subtype, parameters, children: Vec::new(), message: None, defects };
+    let nested = part.is_multipart() || (part.media_type == "message" && matches!(part.subtype.as_ref(), "rfc822" | "global"));
+    if nested && depth >= MAX_DEPTH {
+        part.defects.push(MimeError::TooDeep);
+        return part;
+    }
+    if part.is_multipart() {
+        match part.parameter("boundary").filter(|boundary| !boundary.is_empty()) {
+            Some(boundary) => {
+                let digest = part.subtype == "digest";
+                let (raws, closed) = parts(body, boundary);
+                if !closed && !raws.is_empty() {
+                    part.defects.push(MimeError::UnterminatedMultipart);
+                }
+                part.children = raws.into_iter().map(|raw| parse_part(raw, digest, depth + 1)).collect();
+            }
+            None => part.defects.push(MimeError::MissingBoundary),
+        }
+        // 没有任何部分的 multipart 按 text/plain 处理，BODYSTRUCTURE 不允许空的 multipart
+        if part.children.is_empty() {
+            if !part.defects.contains(&MimeError::MissingBoundary) {
+                part.defects.push(MimeError::EmptyMultipart);
+            }
+            (part.media_type, part.subtype) = (Cow::Borrowed("text"), Cow::Borrowed("plain"));
+        }
+    } else if nested {
+        part.message = Some(Box::new(parse_part(body, false, depth + 1)));
+    }
+    part
+}
+
+/// # Content-Type 字段的值
+/// 邮件头是有效的 UTF-8 时引用原文，包括折叠的行；否则按识别的字符集解码。
+/// ## 返回值
+/// - Option<Cow<str>>，有多个 Content-Type 时为第一个
+fn content_type(header: &[u8]) -> Option<Cow<'_, str>> {
+    let is_content_type = |(name, _): &(&str, &str)| name.eq_ignore_ascii_case("Content-Type");
+    match charset::header(header) {
+        Cow::Borrowed(text) => header::folded_fields(text).into_iter().find(is_content_type).map(|(_, value)| Cow::Borrowed(value)),
+        Cow::Owned(text) => header::folded_fields(&text).into_iter().find(is_content_type).map(|(_, value)| Cow::Owned(value.to_string())),
+    }
+}
+
+/// # 解析 Content-Type 的值
+/// ## 返回值
+/// - Option<(类型, 子类型, 参数)>，类型与子类型为小写；格式错误时为 None
+fn media(value: &str) -> Option<(Cow<'_, str>, Cow<'_, str>, Parameters<'_>)> {
+    let (media, parameters) = parameters(value);
+    let (media_type, subtype) = media.split_once('/')?;
+    let media_type = header::lowercase(header::unfold(media_type));
+    let subtype = header::lowercase(header::unfold(subtype));
+    (!media_type.is_empty() && !subtype.is_empty()).then_some((media_type, subtype, parameters))
+}
+
+/// # 复制引用的内容
+fn owned<'b>(value: Cow<'_, str>) -> Cow<'b, str> {
+    Cow::Owned(value.into_owned())
+}
+
+/// # 检查邮件头的每一行
+/// 每行应当是字段（名称: 值）或以空白开头的续行（RFC 5322 2.2）。
+fn check_header(header: &[u8]) -> Vec<MimeError> {
+    let mut defects = Vec::new();
+    for (index, line) in header.split(|&byte| byte == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        let continuation = index > 0 && (line.starts_with(b" ") || line.starts_with(b"\t"));
+        let name = line.iter().position(|&byte| byte == b':').map(|colon| &line[..colon]);
+        let field = name.is_some_and(|name| !name.is_empty() && name.iter().all(|&byte| byte.is_ascii_graphic()));
+        if !continuation && !field {
+            defects.push(MimeError::InvalidHeader(index + 1));
+        }
+    }
+    defects
+}
+
+/// # 拆分邮件头与正文
+/// 邮件头包括结尾的空行，以空行开头时邮件头只有这一行，没有空行时全部为邮件头。
+fn split(raw: &[u8]) -> (&[u8], &[u8]) {
+    let end = if raw.starts_with(b"\r\n") {
+        2
+    } else if raw.starts_with(b"\n") {
+        1
+    } else if let Some(position) = raw.windows(4).position(|window| window == b"\r\n\r\n") {
+        position + 4
+    } else if let Some(position) = raw.windows(2).position(|window| window == b"\n\n") {
+        position + 2
+    } else {
+        raw.len()
+    };
+    raw.split_at(end)
+}
+
+/// # 按分隔线拆分 multipart 的各部分（RFC 2046 5.1.1）
+/// 分隔线之前的 CRLF 属于分隔线，不计入上一部分。
+/// ## 返回值
+/// - (Vec<&[u8]>, bool)，各部分与是否遇到结束分隔线
+fn parts<'a>(body: &'a [u8], boundary: &str) -> (Vec<&'a [u8]>, bool) {
+    let delimiter = format!("--{}", boundary);
+    let mut parts = Vec::new();
+    let mut start = None;
+    let mut offset = 0;
+    for line in body.split_inclusive(|&byte| byte == b'\n') {
+        if let Some(rest) = line.strip_prefix(delimiter.as_bytes()) {
+            let close = rest.starts_with(b"--");
+            if close || rest.trim_ascii().is_empty() {
+                if let Some(start) = start {
+                    let end = body[..offset].strip_suffix(b"\n").map_or(offset, |before| before.strip_suffix(b"\r").unwrap_or(before).len());
+                    parts.push(&body[start..end.max(start)]);
+                }
+                if close {
+                    return (parts, true);
+                }
+                start = Some(offset + line.len());
+            }
+        }
+        offset += line.len();
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    (parts, false)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 语料库所在的目录，cargo fuzz 也以其中的邮件为种子
+    const CORPUS: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus");
+
+    /// 一封邮件预期的结果：(文件名, 解码后的 Subject, 各叶子部分的类型与文本的开头, 发现的问题)
+    type Expected = (&'static str, &'static str, &'static [(&'static str, &'static str)], &'static [&'static str]);
+
+    /// 语料库中每封邮件预期的结果
+    const EXPECTED: [Expected; 10] = [
+        ("apple-related.eml", "Logo", &[("text/html", "<html><body><p>New logo:"), ("image/png", "\u{2030}PNG")], &[]),
+        (
+            "broken.eml",
+            "🎉 You have won! =?utf-8?Q?broken=ZZ?=",
+            &[("text/plain", "Claim your prize now!"), ("application/octet-stream", "begin 644 prize.exe"), ("text/html", "<p>Click =here</p>")],
+            &[
+                "邮件头第 5 行格式错误",
+                "multipart 缺少结束分隔线",
+                "无法解码的 encoded-word =?utf-8?Q?broken=ZZ?=",
+                "base64 在第 30 字节处无效",
+                "不支持的 Content-Transfer-Encoding x-uuencode",
+                "quoted-printable 在第 9 字节处无效",
+            ],
+        ),
+        (
+            "delivery-report.eml",
+            "Undelivered Mail Returned to Sender",
+            &[("text/plain", "I'm sorry"), ("message/delivery-status", "Reporting-MTA: dns; mx.example.net"), ("text/rfc822-headers", "From: sender@example.org")],
+            &[],
+        ),
+        (
+            "digest.eml",
+            "Example-list Digest, Vol 12, Issue 3",
+            &[("text/plain", "Today's Topics:"), ("text/plain", "Friday works for me."), ("text/plain", "The linker fails")],
+            &[],
+        ),
+        (
+            "forwarded.eml",
+            "Fwd: Invoice 2025-10",
+            &[("text/plain", "See below."), ("text/plain", "Amount due: €120.00"), ("text/csv", "item,amount")],
+            &[],
+        ),
+        ("gbk-raw-header.eml", "无法登录邮箱", &[("text/plain", "您好，从昨天开始就无法登录邮箱")], &["内容不符合字符集 us-ascii"]),
+        (
+            "gmail-attachment.eml",
+            "合同",
+            &[("text/plain", "合同见附件。"), ("text/html", "<div dir=\"ltr\">合同见附件。"), ("application/pdf", "%PDF-1.4")],
+            &[],
+        ),
+        ("iso-2022-jp.eml", "会議", &[("text/plain", "会議は明日の十時からです。")], &[]),
+        ("outlook-alternative.eml", "报价单", &[("text/plain", "王经理：\r\n\r\n报价单已更新"), ("text/html", "<html><head>")], &[]),
+        ("thunderbird-qp.eml", "下周会议的议程", &[("text/plain", "你好，\r\n\r\n附件是下周会议的议程")], &[]),
+    ];
+
+    /// # 读取语料库中的邮件
+    fn read(name: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/{}", CORPUS, name)).unwrap()
+    }
+
+    #[test]
+    fn corpus() {
+        let mut names: Vec<String> = std::fs::read_dir(CORPUS).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, EXPECTED.map(|(name, ..)| name), "语料库中的每封邮件都应有预期的结果");
+
+        for (name, subject, leaves, errors) in EXPECTED {
+            let raw = read(name);
+            let message = parse(&raw);
+            assert_eq!(message.field("Subject").map(|value| header::decode_words(&value)).as_deref(), Some(subject), "{}", name);
+            let actual: Vec<(String, String)> = message
+                .leaves()
+                .into_iter()
+                .map(|leaf| (format!("{}/{}", leaf.media_type, leaf.subtype), leaf.text().0))
+                .collect();
+            assert_eq!(actual.len(), leaves.len(), "{}：{:?}", name, actual);
+            for ((kind, text), (expected_kind, prefix)) in actual.iter().zip(leaves) {
+                assert_eq!(kind, expected_kind, "{}", name);
+                assert!(text.starts_with(prefix), "{}：{:?}", name, text);
+            }
+            assert_eq!(message.errors().iter().map(ToString::to_string).collect::<Vec<_>>(), *errors, "{}", name);
+        }
+    }
+
+    #[test]
+    fn attachment_names() {
+        let raw = read("gmail-attachment.eml");
+        let message = parse(&raw);
+        let attachment = message.find(&[2]).unwrap();
+        assert_eq!(header::parameter(&attachment.parameters, "name").as_deref(), Some("合同.pdf"));
+        let disposition = attachment.field("Content-Disposition").unwrap();
+        let (value, parameters) = header::parameters(&disposition);
+        assert_eq!(value, "attachment");
+        assert_eq!(header::parameter(&parameters, "filename").as_deref(), Some("合同（签字版）.pdf"));
+
+        let raw = read("apple-related.eml");
+        let message = parse(&raw);
+        assert_eq!(message.parameter("type"), Some("text/html"));
+        assert_eq!(message.parameter("boundary"), Some("Apple-Mail=_6D1E2F4A-8B3C-4E5D-9F70-1A2B3C4D5E6F"));
+        assert_eq!(message.find(&[2]).unwrap().parameter("name"), Some("logo \"final\".png"));
+    }
+
+    #[test]
+    fn borrows_header() {
+        let borrowed = |value: &Cow<str>| matches!(value, Cow::Borrowed(_));
+        let raw = read("thunderbird-qp.eml");
+        let message = parse(&raw);
+        assert!(borrowed(&message.media_type) && borrowed(&message.subtype));
+        // 没有转义、折叠的行与大写的参数名时全部引用原文
+        assert!(message.parameters.iter().all(|(name, value)| borrowed(name) && borrowed(value)));
+
+        // 参数之间折叠的行不需要复制，含有转义的参数值需要
+        let raw = read("apple-related.eml");
+        let message = parse(&raw);
+        assert!(message.parameters.iter().all(|(name, value)| borrowed(name) && borrowed(value)));
+        let image = message.find(&[2]).unwrap();
+        assert!(!borrowed(&image.parameters[1].1) && borrowed(&image.parameters[0].1));
+
+        let raw = b"Content-Type: Text/HTML; Charset=\"utf-8\"\r\n\r\n<p>\r\n";
+        let part = parse(raw);
+        assert_eq!((part.media_type.as_ref(), part.subtype.as_ref(), part.parameter("charset")), ("text", "html", Some("utf-8")));
+        assert!(!borrowed(&part.media_type) && !borrowed(&part.parameters[0].0) && borrowed(&part.parameters[0].1));
+
+        // 不是有效 UTF-8 的邮件头按识别的字符集解码后复制
+        let raw = read("gbk-raw-header.eml");
+        let message = parse(&raw);
+        assert_eq!((message.media_type.as_ref(), message.parameter("charset")), ("text", Some("us-ascii")));
+        assert!(!borrowed(&message.media_type));
+    }
+
+    #[test]
+    fn header_fields() {
+        let header = b"Subject: a\r\n  b\r\nX-Empty:\r\nnot a field\r\n continued\r\nTo: c\r\n\r\nBody: d\r\n";
+        let expected = [("Subject", "a b"), ("X-Empty", ""), ("To", "c")];
+        assert_eq!(header::fields(header), expected.map(|(name, value)| (name.to_string(), value.to_string())));
+        assert_eq!(header::folded_fields(std::str::from_utf8(header).unwrap())[0], ("Subject", " a\r\n  b"));
+    }
+}
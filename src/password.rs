@@ -0,0 +1,205 @@
+/* 主账号密码（Argon2id） */
+/*
+# 密码模块
+## 用法
+let phc = password::hash("admin", &Params::default())?;   <-- 返回 PHC 字符串，例如 $argon2id$v=19$m=19456,t=2,p=1$...
+password::verify("admin", &phc)
+let scram = password::scram("admin");                      <-- 邮件账号额外保存 SCRAM-SHA-256 凭据，用于 SASL 登录
+ */
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Version};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand_core::RngCore;
+use ring::{digest, hmac, pbkdf2};
+use std::fmt;
+use std::num::NonZeroU32;
+use std::path::Path;
+
+use crate::config::{self, ConfigError};
+
+/// Argon2 参数
+#[derive(Debug, Clone, Copy)]
+pub struct Params {
+    /// 内存（KiB）
+    pub memory: u32,
+    /// 迭代次数
+    pub time: u32,
+    /// 并行度
+    pub parallelism: u32,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            memory: argon2::Params::DEFAULT_M_COST,
+            time: argon2::Params::DEFAULT_T_COST,
+            parallelism: argon2::Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PasswordError {
+    /// Argon2 参数或计算出错
+    Hash(String),
+    /// 读取终端输入失败
+    Io(std::io::Error),
+    /// 两次输入不一致或为空
+    Mismatch(&'static str),
+    Config(ConfigError),
+}
+
+impl fmt::Display for PasswordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PasswordError::Hash(e) => write!(f, "无法生成密码哈希：{}", e),
+            PasswordError::Io(e) => write!(f, "无法读取密码：{}", e),
+            PasswordError::Mismatch(message) => write!(f, "{}", message),
+            PasswordError::Config(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// # 生成 Argon2id 哈希
+/// ## 参数
+/// - password: 明文密码
+/// - params: Argon2 参数
+/// ## 返回值
+/// - Result<String, PasswordError>，PHC 字符串
+pub fn hash(password: &str, params: &Params) -> Result<String, PasswordError> {
+    let params = argon2::Params::new(params.memory, params.time, params.parallelism, None)
+        .map_err(|e| PasswordError::Hash(e.to_string()))?;
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| PasswordError::Hash(e.to_string()))
+}
+
+/// # 校验密码
+/// 参数从 PHC 字符串中读取。
+/// ## 参数
+/// - password: 明文密码
+/// - phc: PHC 字符串
+/// ## 返回值
+/// - bool
+pub fn verify(password: &str, phc: &str) -> bool {
+    match PasswordHash::new(phc) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// # 生成 SCRAM-SHA-256 凭据（RFC 7677）
+/// 格式与 PostgreSQL 相同：SCRAM-SHA-256$<迭代次数>:<salt>$<StoredKey>:<ServerKey>，均为 Base64。
+/// ## 参数
+/// - password: 明文密码
+/// ## 返回值
+/// - String
+pub fn scram(password: &str) -> String {
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let keys = ScramKeys::derive(password, &salt, SCRAM_ITERATIONS);
+    format!(
+        "SCRAM-SHA-256${}:{}${}:{}",
+        SCRAM_ITERATIONS,
+        BASE64.encode(salt),
+        BASE64.encode(keys.stored_key),
+        BASE64.encode(keys.server_key)
+    )
+}
+
+/// SCRAM 迭代次数（RFC 7677 建议至少 4096）
+pub const SCRAM_ITERATIONS: u32 = 4096;
+
+/// SCRAM 凭据
+#[derive(Debug, Clone)]
+pub struct ScramKeys {
+    pub salt: Vec<u8>,
+    pub iterations: u32,
+    pub stored_key: Vec<u8>,
+    pub server_key: Vec<u8>,
+}
+
+impl ScramKeys {
+    /// # 由明文密码计算
+    pub fn derive(password: &str, salt: &[u8], iterations: u32) -> ScramKeys {
+        let mut salted = [0u8; 32];
+        let rounds = NonZeroU32::new(iterations).unwrap_or(NonZeroU32::MIN);
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, rounds, salt, password.as_bytes(), &mut salted);
+        let client_key = hmac_sha256(&salted, b"Client Key");
+        ScramKeys {
+            salt: salt.to_vec(),
+            iterations,
+            stored_key: digest::digest(&digest::SHA256, &client_key).as_ref().to_vec(),
+            server_key: hmac_sha256(&salted, b"Server Key"),
+        }
+    }
+
+    /// # 解析 scram() 生成的字符串
+    /// ## 参数
+    /// - text: SCRAM-SHA-256$<迭代次数>:<salt>$<StoredKey>:<ServerKey>
+    /// ## 返回值
+    /// - Option<ScramKeys>
+    pub fn parse(text: &str) -> Option<ScramKeys> {
+        let rest = text.strip_prefix("SCRAM-SHA-256$")?;
+        let (params, keys) = rest.split_once('$')?;
+        let (iterations, salt) = params.split_once(':')?;
+        let (stored_key, server_key) = keys.split_once(':')?;
+        Some(ScramKeys {
+            salt: BASE64.decode(salt).ok()?,
+            iterations: iterations.parse().ok()?,
+            stored_key: BASE64.decode(stored_key).ok()?,
+            server_key: BASE64.decode(server_key).ok()?,
+        })
+    }
+}
+
+/// # HMAC-SHA-256
+pub fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), message).as_ref().to_vec()
+}
+
+/// # 是否为有效的 Argon2 PHC 字符串
+/// ## 参数
+/// - phc: &str
+/// ## 返回值
+/// - bool
+pub fn is_valid_hash(phc: &str) -> bool {
+    PasswordHash::new(phc).is_ok_and(|hash| hash.algorithm.as_str().starts_with("argon2"))
+}
+
+/// # 命令 zmou password
+/// 交互式读取并确认新密码，写入配置文件 [MainAccount] Password。
+/// ## 参数
+/// - path: 配置文件路径
+/// - params: Argon2 参数
+/// ## 返回值
+/// - Result<(), PasswordError>
+pub fn reset(path: &Path, params: &Params) -> Result<(), PasswordError> {
+    let phc = hash(&prompt()?, params)?;
+    config::update(path, |document| {
+        document["MainAccount"]["Password"] = toml_edit::value(phc);
+    })
+    .map_err(PasswordError::Config)?;
+    info!("主账号密码已重置");
+    Ok(())
+}
+
+/// # 交互式读取新密码
+/// 需要输入两次，不允许为空。
+/// ## 返回值
+/// - Result<String, PasswordError>，明文密码
+pub fn prompt() -> Result<String, PasswordError> {
+    let password = rpassword::prompt_password("新密码：").map_err(PasswordError::Io)?;
+    if password.is_empty() {
+        return Err(PasswordError::Mismatch("密码不能为空"));
+    }
+    let confirm = rpassword::prompt_password("确认密码：").map_err(PasswordError::Io)?;
+    if password != confirm {
+        return Err(PasswordError::Mismatch("两次输入的密码不一致"));
+    }
+    Ok(password)
+}
@@ -0,0 +1,28 @@
+/* POP3 服务 */
+/*
+# POP3 模块
+## 结构
+session.rs  协议状态机（RFC 1939），不涉及网络读写，逐条处理命令并返回响应
+server.rs   会话循环：按 listener.rs 接受的连接读写命令，处理超时
+## 用法
+for listener in pop3::server::listeners(&config.pop3) {
+    let handle = pop3::server::start(&config.pop3, listener, tls.clone(), store.clone())?;   <-- 在后台线程中监听
+}
+## 扩展
+CAPA（RFC 2449）、STLS（RFC 2595）、SASL（RFC 5034）、RESP-CODES、AUTH-RESP-CODE（RFC 3206）、TOP、UIDL、
+USER、PIPELINING。
+## 说明
+只提供 INBOX 中的邮件，登录时的邮件列表在会话期间不变，之后投递的邮件在下次登录时出现。
+UIDL 为 INBOX 的 UIDVALIDITY 与邮件的 UID，与 IMAP 一致，跨会话不变。
+QUIT 时删除 DELE 标记的邮件；账号设置为取回后删除（zmou account pop3）时，RETR 过的邮件一并删除。
+同一账号同时只允许一个 POP3 会话（RFC 1939 8），IMAP 不受影响。
+每个连接分配一个递增的编号，日志以「POP3 #编号」开头。
+ */
+pub mod server;
+pub mod session;
+
+/// 命令行长度上限（含 CRLF），RFC 2449 4 规定为 255
+pub const MAX_LINE: usize = 255;
+
+/// SASL 响应的长度上限（含 CRLF）
+pub const MAX_AUTH_LINE: usize = 12288;
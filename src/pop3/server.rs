@@ -0,0 +1,133 @@
+/* POP3 监听 */
+use rustls::ServerConfig;
+use std::io;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::session::{Next, Session};
+use super::{MAX_AUTH_LINE, MAX_LINE};
+use crate::config::Pop3;
+use crate::listener::{self, Client, Counter, Line, Listener, Service, read_line};
+use crate::secret::MASK;
+use crate::storage::MailStore;
+use crate::tls::Connection;
+
+/// 空闲连接的超时（RFC 1939 3 要求至少 10 分钟）
+const TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// 连接编号与连接数，所有端口共用
+static COUNTER: Counter = Counter::new();
+
+/// # 根据配置列出需要监听的端口
+/// ## 参数
+/// - config: [POP3]
+/// ## 返回值
+/// - Vec<Listener>
+pub fn listeners(config: &Pop3) -> Vec<Listener> {
+    let mut listeners = vec![Listener { name: "POP3", port: config.port, implicit_tls: false }];
+    if config.tls {
+        listeners.push(Listener { name: "POP3S", port: config.secure_port, implicit_tls: true });
+    }
+    listeners
+}
+
+/// # 启动 POP3 服务
+/// ## 参数
+/// - config: [POP3]
+/// - listener: 监听的端口
+/// - tls: TLS 配置，未启用 TLS 时为 None
+/// - store: 邮件存储
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start(config: &Pop3, listener: Listener, tls: Option<Arc<ServerConfig>>, store: Arc<dyn MailStore>) -> io::Result<JoinHandle<()>> {
+    listener::start(&config.address, listener, tls, Pop3Service { store })
+}
+
+/// POP3 的会话循环
+struct Pop3Service {
+    store: Arc<dyn MailStore>,
+}
+
+impl Service for Pop3Service {
+    const PROTOCOL: &'static str = "POP3";
+    const TIMEOUT: Duration = TIMEOUT;
+
+    fn counter(&self) -> &'static Counter {
+        &COUNTER
+    }
+
+    fn busy(&self) -> Vec<u8> {
+        b"-ERR [SYS/TEMP] Too many connections, try again later\r\n".to_vec()
+    }
+
+    fn serve(&self, connection: Connection, client: Client) -> io::Result<()> {
+        let session = Session::new(client.id, client.peer, Arc::clone(&self.store), client.state);
+        handle(connection, session, client.id, client.tls)
+    }
+}
+
+/// # 处理一个连接
+fn handle(mut connection: Connection, mut session: Session, id: u64, tls: Option<Arc<ServerConfig>>) -> io::Result<()> {
+    send(&mut connection, &session.greeting(), id)?;
+
+    loop {
+        let command = match read_line(&mut connection, MAX_LINE) {
+            Ok(Line::Text(command)) => command,
+            Ok(Line::TooLong) => {
+                send(&mut connection, b"-ERR Command line too long\r\n", id)?;
+                continue;
+            }
+            Ok(Line::Closed) => return Ok(()),
+            Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => {
+                let _ = send(&mut connection, &session.timeout(), id);
+                return Err(e);
+            }
+            Err(e) => return Err(e),
+        };
+        debug!("POP3 #{} C: {}", id, redact(&command));
+
+        let mut response = session.command(&command);
+        loop {
+            send(&mut connection, &response.data, id)?;
+            match response.next {
+                Next::Continue => break,
+                Next::Close => return Ok(()),
+                Next::StartTls => {
+                    let Some(config) = &tls else { break };
+                    connection = connection.starttls(config)?;
+                    session.secured();
+                    break;
+                }
+                // SASL 响应包含密码或其摘要，不记录内容
+                Next::Auth => {
+                    response = match read_line(&mut connection, MAX_AUTH_LINE)? {
+                        Line::Text(line) => {
+                            debug!("POP3 #{} C: {}", id, MASK);
+                            session.auth(&String::from_utf8_lossy(&line))
+                        }
+                        // 过长的响应按客户端取消处理
+                        Line::TooLong => session.auth("*"),
+                        Line::Closed => return Ok(()),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn send(connection: &mut Connection, data: &[u8], id: u64) -> io::Result<()> {
+    listener::send(connection, data, "POP3", id)
+}
+
+/// # 调试日志中的命令
+/// 隐藏 PASS 的密码与 AUTH 的初始响应。
+fn redact(command: &[u8]) -> String {
+    let line = String::from_utf8_lossy(command);
+    let words: Vec<&str> = line.splitn(3, ' ').collect();
+    match words.as_slice() {
+        [verb, _, ..] if verb.eq_ignore_ascii_case("PASS") => format!("{} {}", verb, MASK),
+        [verb, mechanism, _] if verb.eq_ignore_ascii_case("AUTH") => format!("{} {} {}", verb, mechanism, MASK),
+        _ => line.into_owned(),
+    }
+}
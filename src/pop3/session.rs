@@ -0,0 +1,736 @@
+/* POP3 会话 */
+/*
+# 会话状态机
+## 用法
+let mut session = Session::new(id, peer, store, Tls::Available);
+write(session.greeting());
+for line in lines {
+    let response = session.command(&line);
+    write(response.data);
+    match response.next {
+        Next::Continue => {}
+        Next::StartTls => { 升级连接; session.secured(); }
+        Next::Auth => write(session.auth(读取到的一行).data),   <-- 直到 next 不再是 Auth
+        Next::Close => break,
+    }
+}
+## 说明
+状态：AUTHORIZATION → TRANSACTION → UPDATE（RFC 1939 3），QUIT 进入 UPDATE 并删除邮件后关闭连接。
+未发送 QUIT 而断开连接时不删除任何邮件。
+端口 110 在 STLS 之前不允许登录，CAPA 中不列出 USER 与 SASL。
+ */
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use crate::event::{self, Event, Kind};
+use crate::sasl::{Authenticator, Mechanism, SaslError, Step};
+use crate::storage::{Account, MailStore, Mailbox, MessageInfo, StorageError};
+use crate::tls::Tls;
+
+/// 连续错误命令的上限，超过后断开连接
+const MAX_ERRORS: u32 = 10;
+
+/// 已有 POP3 会话的账号 id
+static LOCKED: Mutex<Vec<i64>> = Mutex::new(Vec::new());
+
+/// 处理命令后的下一步
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Next {
+    /// 继续读取命令
+    Continue,
+    /// 升级为 TLS，然后调用 Session::secured
+    StartTls,
+    /// 读取一行 SASL 响应，然后调用 Session::auth
+    Auth,
+    /// 关闭连接
+    Close,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    pub data: Vec<u8>,
+    pub next: Next,
+}
+
+/// 命令失败的原因
+enum Failure {
+    /// -ERR，文本可以带响应码
+    Err(String),
+    Storage(StorageError),
+}
+
+impl From<StorageError> for Failure {
+    fn from(e: StorageError) -> Self {
+        Failure::Storage(e)
+    }
+}
+
+/// 命令的结果，成功时为 +OK 之后的内容，多行响应包括结尾的 .
+type Outcome = Result<Vec<u8>, Failure>;
+
+/// 登录时 INBOX 中的一封邮件
+struct Message {
+    info: MessageInfo,
+    /// 已使用 DELE 标记删除
+    deleted: bool,
+    /// 已使用 RETR 取回
+    retrieved: bool,
+}
+
+/// 已登录账号的邮件，持有期间账号被锁定
+struct Maildrop {
+    account: Account,
+    inbox: Mailbox,
+    messages: Vec<Message>,
+}
+
+impl Drop for Maildrop {
+    fn drop(&mut self) {
+        locked().retain(|&id| id != self.account.id);
+    }
+}
+
+pub struct Session {
+    id: u64,
+    peer: IpAddr,
+    store: Arc<dyn MailStore>,
+    tls: Tls,
+    /// USER 命令提供的用户名
+    username: Option<String>,
+    /// 正在进行的 SASL 认证
+    authenticator: Option<Authenticator>,
+    maildrop: Option<Maildrop>,
+    errors: u32,
+}
+
+impl Session {
+    /// # 创建会话
+    /// ## 参数
+    /// - id: 连接编号
+    /// - peer: 客户端地址
+    /// - store: 邮件存储
+    /// - tls: 连接的加密状态
+    /// ## 返回值
+    /// - Session
+    pub fn new(id: u64, peer: IpAddr, store: Arc<dyn MailStore>, tls: Tls) -> Session {
+        Session { id, peer, store, tls, username: None, authenticator: None, maildrop: None, errors: 0 }
+    }
+
+    /// # 问候语
+    pub fn greeting(&self) -> Vec<u8> {
+        b"+OK ZitMail POP3 ready\r\n".to_vec()
+    }
+
+    /// # 处理一条命令
+    /// ## 参数
+    /// - line: 不含结尾 CRLF 的命令
+    /// ## 返回值
+    /// - Response
+    pub fn command(&mut self, line: &[u8]) -> Response {
+        let line = String::from_utf8_lossy(line);
+        let mut words = line.split(' ');
+        let name = words.next().unwrap_or_default().to_ascii_uppercase();
+        let arguments: Vec<&str> = words.collect();
+        if name == "AUTH" && !arguments.is_empty() {
+            return self.authenticate(&arguments);
+        }
+        let outcome = self.dispatch(&name, &arguments);
+        let next = match (&outcome, name.as_str()) {
+            (Ok(_), "QUIT") => Next::Close,
+            (Ok(_), "STLS") => Next::StartTls,
+            _ => Next::Continue,
+        };
+        self.finish(outcome, next)
+    }
+
+    /// # 连接已升级为 TLS
+    pub fn secured(&mut self) {
+        self.tls = Tls::Active;
+        debug!("POP3 #{} 已启用 TLS", self.id);
+    }
+
+    /// # 处理一行 SASL 响应
+    /// ## 参数
+    /// - line: 不含 CRLF 的 Base64 文本
+    /// ## 返回值
+    /// - Response
+    pub fn auth(&mut self, line: &str) -> Response {
+        let Some(mut authenticator) = self.authenticator.take() else {
+            return self.finish(Err(Failure::Err(String::from("No authentication in progress"))), Next::Continue);
+        };
+        let step = authenticator.step(line);
+        self.auth_step(authenticator, step)
+    }
+
+    /// # 以空闲超时结束会话
+    /// 不进入 UPDATE 状态，标记删除的邮件保留。
+    pub fn timeout(&self) -> Vec<u8> {
+        b"-ERR Autologout; idle for too long\r\n".to_vec()
+    }
+
+    /// 生成响应，统计连续的错误，超过上限后断开连接
+    fn finish(&mut self, outcome: Outcome, next: Next) -> Response {
+        let data = match outcome {
+            Ok(text) => {
+                self.errors = 0;
+                return Response { data: [b"+OK ".as_slice(), &text].concat(), next };
+            }
+            Err(Failure::Err(text)) => format!("-ERR {}\r\n", text).into_bytes(),
+            Err(Failure::Storage(e)) => {
+                error!("POP3 #{} 存储操作失败：{}", self.id, e);
+                b"-ERR [SYS/TEMP] Temporary failure, try again later\r\n".to_vec()
+            }
+        };
+        self.errors += 1;
+        let next = if self.errors >= MAX_ERRORS { Next::Close } else { next };
+        Response { data, next }
+    }
+
+    fn dispatch(&mut self, name: &str, arguments: &[&str]) -> Outcome {
+        let logged_in = self.maildrop.is_some();
+        match (name, logged_in) {
+            ("CAPA", _) => {
+                expect(arguments, 0)?;
+                Ok(multiline("Capability list follows", self.capabilities()))
+            }
+            ("NOOP", true) => {
+                expect(arguments, 0)?;
+                Ok(line("No operation"))
+            }
+            ("QUIT", _) => {
+                expect(arguments, 0)?;
+                self.quit()
+            }
+            ("STLS", false) => {
+                expect(arguments, 0)?;
+                match self.tls {
+                    Tls::Available => Ok(line("Begin TLS negotiation")),
+                    Tls::Active => Err(Failure::Err(String::from("TLS already active"))),
+                    Tls::Unavailable => Err(Failure::Err(String::from("STLS is not available"))),
+                }
+            }
+            ("USER", false) => {
+                let [username] = arguments else {
+                    return Err(Failure::Err(String::from("Usage: USER name")));
+                };
+                self.secure()?;
+                self.username = Some(username.to_string());
+                Ok(line("Send PASS"))
+            }
+            // 密码可以包含空格（RFC 1939 7）
+            ("PASS", false) => {
+                let Some(username) = self.username.take() else {
+                    return Err(Failure::Err(String::from("Send USER first")));
+                };
+                self.secure()?;
+                let password = arguments.join(" ");
+                let step = Authenticator::login(Arc::clone(&self.store), &username, &password);
+                self.logged_in(step)
+            }
+            ("AUTH", false) => Ok(multiline("Supported mechanisms follow", Mechanism::ALL.iter().map(|name| name.to_string()).collect())),
+            ("STAT", true) => {
+                expect(arguments, 0)?;
+                let maildrop = self.maildrop()?;
+                let remaining = maildrop.messages.iter().filter(|message| !message.deleted);
+                let (count, size) = remaining.fold((0, 0), |(count, size), message| (count + 1, size + message.info.size));
+                Ok(line(&format!("{} {}", count, size)))
+            }
+            ("LIST" | "UIDL", true) => {
+                let maildrop = self.maildrop()?;
+                let entry = |number: usize, message: &Message| match name {
+                    "LIST" => format!("{} {}", number, message.info.size),
+                    _ => format!("{} {}.{}", number, maildrop.inbox.uid_validity, message.info.uid),
+                };
+                match arguments {
+                    [] => {
+                        let entries = (1..)
+                            .zip(&maildrop.messages)
+                            .filter(|(_, message)| !message.deleted)
+                            .map(|(number, message)| entry(number, message))
+                            .collect();
+                        Ok(multiline(if name == "LIST" { "Scan listing follows" } else { "Unique-id listing follows" }, entries))
+                    }
+                    [number] => {
+                        let (number, message) = find(maildrop, number)?;
+                        Ok(line(&entry(number, message)))
+                    }
+                    _ => Err(Failure::Err(format!("Usage: {} [msg]", name))),
+                }
+            }
+            ("RETR", true) => {
+                let [number] = arguments else {
+                    return Err(Failure::Err(String::from("Usage: RETR msg")));
+                };
+                self.retrieve(number, None)
+            }
+            ("TOP", true) => {
+                let [number, lines] = arguments else {
+                    return Err(Failure::Err(String::from("Usage: TOP msg n")));
+                };
+                let lines = lines.parse().map_err(|_| Failure::Err(String::from("Invalid number of lines")))?;
+                self.retrieve(number, Some(lines))
+            }
+            ("DELE", true) => {
+                let [number] = arguments else {
+                    return Err(Failure::Err(String::from("Usage: DELE msg")));
+                };
+                let (number, _) = find(self.maildrop()?, number)?;
+                if let Some(maildrop) = self.maildrop.as_mut() {
+                    maildrop.messages[number - 1].deleted = true;
+                }
+                Ok(line(&format!("Message {} deleted", number)))
+            }
+            ("RSET", true) => {
+                expect(arguments, 0)?;
+                let maildrop = self.maildrop.as_mut().ok_or_else(|| Failure::Err(String::from("Not authenticated")))?;
+                for message in &mut maildrop.messages {
+                    message.deleted = false;
+                }
+                Ok(line(&format!("{} messages", maildrop.messages.len())))
+            }
+            ("NOOP" | "STAT" | "LIST" | "UIDL" | "RETR" | "TOP" | "DELE" | "RSET", false) => Err(Failure::Err(String::from("Not authenticated"))),
+            ("STLS" | "USER" | "PASS" | "AUTH", true) => Err(Failure::Err(String::from("Already authenticated"))),
+            _ => Err(Failure::Err(String::from("Unknown command"))),
+        }
+    }
+
+    /// # 当前状态下的能力（RFC 2449 6）
+    fn capabilities(&self) -> Vec<String> {
+        let mut capabilities: Vec<String> =
+            ["TOP", "UIDL", "RESP-CODES", "AUTH-RESP-CODE", "PIPELINING"].map(String::from).to_vec();
+        if self.maildrop.is_none() {
+            match self.tls {
+                Tls::Available => capabilities.push(String::from("STLS")),
+                _ => {
+                    capabilities.push(String::from("USER"));
+                    capabilities.push(format!("SASL {}", Mechanism::ALL.join(" ")));
+                }
+            }
+        }
+        capabilities.push(String::from("IMPLEMENTATION ZitMail"));
+        capabilities
+    }
+
+    /// 端口 110 在 STLS 之前不允许登录（RFC 2595 4）
+    fn secure(&self) -> Result<(), Failure> {
+        match self.tls {
+            Tls::Available => Err(Failure::Err(String::from("[AUTH] Use STLS first"))),
+            _ => Ok(()),
+        }
+    }
+
+    fn maildrop(&self) -> Result<&Maildrop, Failure> {
+        self.maildrop.as_ref().ok_or_else(|| Failure::Err(String::from("Not authenticated")))
+    }
+
+    /// AUTH 机制 [初始响应]（RFC 5034）
+    fn authenticate(&mut self, arguments: &[&str]) -> Response {
+        let started = (|| {
+            let (name, initial) = match arguments {
+                [name] => (name, None),
+                [name, initial] => (name, Some(*initial)),
+                _ => return Err(Failure::Err(String::from("Usage: AUTH mechanism [initial-response]"))),
+            };
+            if self.maildrop.is_some() {
+                return Err(Failure::Err(String::from("Already authenticated")));
+            }
+            self.secure()?;
+            let Some(mechanism) = Mechanism::parse(name) else {
+                return Err(Failure::Err(String::from("Unsupported authentication mechanism")));
+            };
+            let mut authenticator = Authenticator::new(mechanism, Arc::clone(&self.store));
+            let step = authenticator.start(initial);
+            Ok((authenticator, step))
+        })();
+        match started {
+            Ok((authenticator, step)) => self.auth_step(authenticator, step),
+            Err(failure) => self.finish(Err(failure), Next::Continue),
+        }
+    }
+
+    fn auth_step(&mut self, authenticator: Authenticator, step: Result<Step, SaslError>) -> Response {
+        if let Ok(Step::Challenge(challenge)) = &step {
+            let data = format!("+ {}\r\n", challenge).into_bytes();
+            self.authenticator = Some(authenticator);
+            return Response { data, next: Next::Auth };
+        }
+        let outcome = self.logged_in(step);
+        self.finish(outcome, Next::Continue)
+    }
+
+    /// 认证结束，锁定账号并读取 INBOX 中的邮件
+    fn logged_in(&mut self, step: Result<Step, SaslError>) -> Outcome {
+        let account = match step {
+            Ok(Step::Success(account)) => account,
+            Ok(Step::Failure(username)) => {
+                warning!("POP3 #{} 来自 {} 的登录失败，用户名 {}", self.id, self.peer, username);
+                return Err(Failure::Err(String::from("[AUTH] Authentication failed")));
+            }
+            Ok(Step::Challenge(_)) | Err(SaslError::Malformed) => return Err(Failure::Err(String::from("Malformed authentication data"))),
+            Err(SaslError::Cancelled) => return Err(Failure::Err(String::from("Authentication cancelled"))),
+            Err(SaslError::Storage(e)) => return Err(Failure::Storage(e)),
+        };
+        {
+            let mut locked = locked();
+            if locked.contains(&account.id) {
+                return Err(Failure::Err(String::from("[IN-USE] Mailbox is locked by another POP3 session")));
+            }
+            locked.push(account.id);
+        }
+        // 先持有锁，读取失败时由 Drop 释放
+        let mut maildrop = Maildrop { inbox: placeholder(account.id), account, messages: Vec::new() };
+        maildrop.inbox = match self.store.mailbox(maildrop.account.id, "INBOX")? {
+            Some(inbox) => inbox,
+            // 与投递时相同，INBOX 不存在时重新创建
+            None => self.store.create_mailbox(maildrop.account.id, "INBOX")?,
+        };
+        maildrop.messages = self
+            .store
+            .messages(maildrop.inbox.id)?
+            .into_iter()
+            .map(|info| Message { info, deleted: false, retrieved: false })
+            .collect();
+        info!("POP3 #{} 账号 {} 已登录，共 {} 封邮件", self.id, maildrop.account.address(), maildrop.messages.len());
+        let text = format!("Logged in, {} messages", maildrop.messages.len());
+        self.maildrop = Some(maildrop);
+        Ok(line(&text))
+    }
+
+    /// RETR 与 TOP
+    /// ## 参数
+    /// - number: 邮件编号
+    /// - lines: TOP 的正文行数，RETR 为 None
+    fn retrieve(&mut self, number: &str, lines: Option<usize>) -> Outcome {
+        let maildrop = self.maildrop()?;
+        let (number, message) = find(maildrop, number)?;
+        let Some(raw) = self.store.raw(message.info.id)? else {
+            return Err(Failure::Err(String::from("Message was removed by another session")));
+        };
+        let content = match lines {
+            Some(lines) => top(&raw, lines),
+            None => &raw[..],
+        };
+        let mut data = format!("{} octets\r\n", raw.len()).into_bytes();
+        stuff(&mut data, content);
+        if lines.is_none()
+            && let Some(maildrop) = self.maildrop.as_mut()
+        {
+            maildrop.messages[number - 1].retrieved = true;
+        }
+        Ok(data)
+    }
+
+    /// QUIT，已登录时进入 UPDATE 状态删除邮件
+    fn quit(&mut self) -> Outcome {
+        let Some(maildrop) = self.maildrop.take() else {
+            return Ok(line("ZitMail POP3 server signing off"));
+        };
+        let delete_retrieved = maildrop.account.pop3_delete;
+        let removed: Vec<i64> = maildrop
+            .messages
+            .iter()
+            .filter(|message| message.deleted || (delete_retrieved && message.retrieved))
+            .map(|message| message.info.id)
+            .collect();
+        if !removed.is_empty() {
+            if let Err(e) = self.store.expunge(&removed) {
+                error!("POP3 #{} 无法删除邮件：{}", self.id, e);
+                return Err(Failure::Err(String::from("[SYS/TEMP] Some deleted messages not removed")));
+            }
+            let inbox = &maildrop.inbox;
+            event::publish(Event {
+                account_id: inbox.account_id,
+                mailbox_id: inbox.id,
+                mailbox: inbox.name.clone(),
+                kind: Kind::MessageExpunge,
+                origin: 0,
+            });
+            info!("POP3 #{} 已删除账号 {} 的 {} 封邮件", self.id, maildrop.account.address(), removed.len());
+        }
+        Ok(line(&format!("ZitMail POP3 server signing off ({} messages deleted)", removed.len())))
+    }
+}
+
+/// # 检查参数个数
+fn expect(arguments: &[&str], count: usize) -> Result<(), Failure> {
+    match arguments.len() == count {
+        true => Ok(()),
+        false => Err(Failure::Err(String::from("Unexpected arguments"))),
+    }
+}
+
+/// # 查找未删除的邮件
+/// ## 返回值
+/// - (编号, 邮件)
+fn find<'a>(maildrop: &'a Maildrop, number: &str) -> Result<(usize, &'a Message), Failure> {
+    let index = number.parse::<usize>().ok().filter(|&number| number >= 1).map(|number| number - 1);
+    match index.and_then(|index| maildrop.messages.get(index)) {
+        Some(message) if message.deleted => Err(Failure::Err(format!("Message {} already deleted", number))),
+        Some(message) => Ok((index.unwrap_or_default() + 1, message)),
+        None => Err(Failure::Err(String::from("No such message"))),
+    }
+}
+
+/// # 单行响应
+fn line(text: &str) -> Vec<u8> {
+    format!("{}\r\n", text).into_bytes()
+}
+
+/// # 多行响应
+fn multiline(text: &str, lines: Vec<String>) -> Vec<u8> {
+    let mut data = line(text);
+    for entry in lines {
+        data.extend(line(&entry));
+    }
+    data.extend_from_slice(b".\r\n");
+    data
+}
+
+/// # 添加多行响应的内容
+/// 行尾统一为 CRLF，以 . 开头的行前面再加一个 .，最后以 . 结束（RFC 1939 3）。
+fn stuff(data: &mut Vec<u8>, content: &[u8]) {
+    for line in content.split_inclusive(|&byte| byte == b'\n') {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(line);
+        data.extend_from_slice(b"\r\n");
+    }
+    data.extend_from_slice(b".\r\n");
+}
+
+/// # TOP 的内容：邮件头、空行与正文的前几行
+fn top(raw: &[u8], lines: usize) -> &[u8] {
+    let mut end = 0;
+    let mut in_body = false;
+    let mut remaining = lines;
+    for line in raw.split_inclusive(|&byte| byte == b'\n') {
+        if in_body {
+            if remaining == 0 {
+                break;
+            }
+            remaining -= 1;
+        } else if line == b"\r\n" || line == b"\n" {
+            in_body = true;
+        }
+        end += line.len();
+    }
+    &raw[..end]
+}
+
+/// # 读取 INBOX 之前的占位邮箱
+fn placeholder(account_id: i64) -> Mailbox {
+    Mailbox { id: 0, account_id, name: String::from("INBOX"), uid_validity: 0, uid_next: 0, subscribed: false, highest_modseq: 0 }
+}
+
+fn locked() -> std::sync::MutexGuard<'static, Vec<i64>> {
+    LOCKED.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::password::{self, Params};
+    use crate::storage::memory::MemoryStore;
+    use chrono::Utc;
+
+    const MESSAGES: [&str; 3] = [
+        "From: alice@remote.test\r\nSubject: one\r\n\r\nline 1\r\n.dot line\r\nline 3\r\n",
+        "From: carol@remote.test\r\nSubject: two\r\n\r\nbody\r\n",
+        "From: alice@remote.test\nSubject: three\n\nbare\n.\n",
+    ];
+
+    /// # 测试账号 bob@example.com，密码 secret，INBOX 中有三封邮件
+    /// 锁定按账号 id 记录，每个测试先添加不同数量的域名，使账号 id 与并行的其他测试不同。
+    fn prepare(skip: usize) -> (Arc<dyn MailStore>, Account) {
+        let store: Arc<dyn MailStore> = Arc::new(MemoryStore::new());
+        for index in 0..skip {
+            store.add_domain(&format!("skip{}.test", index)).unwrap();
+        }
+        store.add_domain("example.com").unwrap();
+        let params = Params { memory: 8, time: 1, parallelism: 1 };
+        let account = store.add_account("bob", "example.com", &password::hash("secret", &params).unwrap(), &password::scram("secret")).unwrap();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        for raw in MESSAGES {
+            store.append(inbox.id, raw.as_bytes(), &[], Utc::now()).unwrap();
+        }
+        (store, account)
+    }
+
+    /// 已登录的会话
+    fn signed_in(store: &Arc<dyn MailStore>) -> Session {
+        let mut session = Session::new(1, "192.0.2.1".parse().unwrap(), Arc::clone(store), Tls::Active);
+        send(&mut session, "USER bob@example.com");
+        assert_eq!(send(&mut session, "PASS secret"), "+OK Logged in, 3 messages\r\n");
+        session
+    }
+
+    fn send(session: &mut Session, line: &str) -> String {
+        String::from_utf8(session.command(line.as_bytes()).data).unwrap()
+    }
+
+    /// # 按顺序发送命令，检查完整的响应
+    fn script(session: &mut Session, steps: &[(&str, &str)]) {
+        for (line, expected) in steps {
+            assert_eq!(send(session, line), *expected, "C: {}", line);
+        }
+    }
+
+    /// # INBOX 中邮件的 UID
+    fn uids(store: &Arc<dyn MailStore>, account: &Account) -> Vec<u32> {
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        store.messages(inbox.id).unwrap().iter().map(|message| message.uid).collect()
+    }
+
+    #[test]
+    fn stls_before_login() {
+        let (store, _) = prepare(1000);
+        let mut session = Session::new(1, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Available);
+        assert_eq!(session.greeting(), b"+OK ZitMail POP3 ready\r\n");
+        let capabilities = send(&mut session, "CAPA");
+        assert!(capabilities.contains("\r\nSTLS\r\n") && !capabilities.contains("USER") && !capabilities.contains("SASL"), "{}", capabilities);
+        script(
+            &mut session,
+            &[
+                ("USER bob@example.com", "-ERR [AUTH] Use STLS first\r\n"),
+                ("PASS secret", "-ERR Send USER first\r\n"),
+                ("AUTH PLAIN AGJvYkBleGFtcGxlLmNvbQBzZWNyZXQ=", "-ERR [AUTH] Use STLS first\r\n"),
+                ("STAT", "-ERR Not authenticated\r\n"),
+            ],
+        );
+        let response = session.command(b"STLS");
+        assert_eq!((response.data.as_slice(), response.next), (&b"+OK Begin TLS negotiation\r\n"[..], Next::StartTls));
+
+        session.secured();
+        let capabilities = send(&mut session, "CAPA");
+        assert!(!capabilities.contains("STLS") && capabilities.contains("\r\nUSER\r\n") && capabilities.contains("\r\nSASL PLAIN"), "{}", capabilities);
+        script(
+            &mut session,
+            &[
+                ("STLS", "-ERR TLS already active\r\n"),
+                ("USER bob@example.com", "+OK Send PASS\r\n"),
+                ("PASS wrong", "-ERR [AUTH] Authentication failed\r\n"),
+                ("AUTH PLAIN AGJvYkBleGFtcGxlLmNvbQBzZWNyZXQ=", "+OK Logged in, 3 messages\r\n"),
+                ("USER bob@example.com", "-ERR Already authenticated\r\n"),
+            ],
+        );
+
+        let mut plain = Session::new(2, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Unavailable);
+        assert_eq!(send(&mut plain, "STLS"), "-ERR STLS is not available\r\n");
+    }
+
+    #[test]
+    fn uidl_is_stable() {
+        let (store, account) = prepare(1010);
+        let validity = store.mailbox(account.id, "INBOX").unwrap().unwrap().uid_validity;
+        let mut session = signed_in(&store);
+        let listing = send(&mut session, "UIDL");
+        assert_eq!(listing, format!("+OK Unique-id listing follows\r\n1 {v}.1\r\n2 {v}.2\r\n3 {v}.3\r\n.\r\n", v = validity));
+        assert_eq!(send(&mut session, "UIDL 2"), format!("+OK 2 {}.2\r\n", validity));
+        assert_eq!(send(&mut session, "UIDL 4"), "-ERR No such message\r\n");
+        assert_eq!(send(&mut session, "QUIT"), "+OK ZitMail POP3 server signing off (0 messages deleted)\r\n");
+
+        // 之后的会话中同一封邮件的 UIDL 不变，编号按剩余的邮件重新排列
+        let mut session = signed_in(&store);
+        assert_eq!(send(&mut session, "UIDL"), listing);
+        send(&mut session, "DELE 1");
+        send(&mut session, "QUIT");
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        store.append(inbox.id, b"Subject: four\r\n\r\nnew\r\n", &[], Utc::now()).unwrap();
+        let mut session = signed_in(&store);
+        assert_eq!(send(&mut session, "UIDL"), format!("+OK Unique-id listing follows\r\n1 {v}.2\r\n2 {v}.3\r\n3 {v}.4\r\n.\r\n", v = validity));
+    }
+
+    #[test]
+    fn top_and_dot_stuffing() {
+        let (store, _) = prepare(1020);
+        let mut session = signed_in(&store);
+        let size = |index: usize| MESSAGES[index].len();
+        script(
+            &mut session,
+            &[
+                ("TOP 1 0", &format!("+OK {} octets\r\nFrom: alice@remote.test\r\nSubject: one\r\n\r\n.\r\n", size(0))),
+                ("TOP 1 2", &format!("+OK {} octets\r\nFrom: alice@remote.test\r\nSubject: one\r\n\r\nline 1\r\n..dot line\r\n.\r\n", size(0))),
+                ("TOP 2 10", &format!("+OK {} octets\r\nFrom: carol@remote.test\r\nSubject: two\r\n\r\nbody\r\n.\r\n", size(1))),
+                // 行尾统一为 CRLF，单独的 . 也要加倍
+                ("RETR 3", &format!("+OK {} octets\r\nFrom: alice@remote.test\r\nSubject: three\r\n\r\nbare\r\n..\r\n.\r\n", size(2))),
+                ("TOP 1 x", "-ERR Invalid number of lines\r\n"),
+                ("TOP 1", "-ERR Usage: TOP msg n\r\n"),
+                ("RETR 0", "-ERR No such message\r\n"),
+            ],
+        );
+    }
+
+    #[test]
+    fn dele_rset_and_quit() {
+        let (store, account) = prepare(1030);
+        let mut session = signed_in(&store);
+        let total: usize = MESSAGES.iter().map(|raw| raw.len()).sum();
+        script(
+            &mut session,
+            &[
+                ("STAT", &format!("+OK 3 {}\r\n", total)),
+                ("DELE 1", "+OK Message 1 deleted\r\n"),
+                ("DELE 1", "-ERR Message 1 already deleted\r\n"),
+                ("RETR 1", "-ERR Message 1 already deleted\r\n"),
+                ("STAT", &format!("+OK 2 {}\r\n", total - MESSAGES[0].len())),
+                ("LIST", &format!("+OK Scan listing follows\r\n2 {}\r\n3 {}\r\n.\r\n", MESSAGES[1].len(), MESSAGES[2].len())),
+                ("RSET", "+OK 3 messages\r\n"),
+                ("LIST 1", &format!("+OK 1 {}\r\n", MESSAGES[0].len())),
+                ("DELE 2", "+OK Message 2 deleted\r\n"),
+            ],
+        );
+        // 未发送 QUIT 而断开连接时不删除
+        drop(session);
+        assert_eq!(uids(&store, &account), [1, 2, 3]);
+
+        let mut session = signed_in(&store);
+        send(&mut session, "DELE 2");
+        let response = session.command(b"QUIT");
+        assert_eq!((response.data.as_slice(), response.next), (&b"+OK ZitMail POP3 server signing off (1 messages deleted)\r\n"[..], Next::Close));
+        assert_eq!(uids(&store, &account), [1, 3]);
+    }
+
+    #[test]
+    fn pop3_delete_removes_retrieved() {
+        let (store, account) = prepare(1040);
+        store.set_pop3_delete(account.id, true).unwrap();
+        let mut session = signed_in(&store);
+        // TOP 不算取回
+        send(&mut session, "RETR 1");
+        send(&mut session, "TOP 2 0");
+        send(&mut session, "RETR 3");
+        send(&mut session, "DELE 3");
+        assert_eq!(send(&mut session, "QUIT"), "+OK ZitMail POP3 server signing off (2 messages deleted)\r\n");
+        assert_eq!(uids(&store, &account), [2]);
+
+        // 未设置时 RETR 不删除
+        store.set_pop3_delete(account.id, false).unwrap();
+        let mut session = Session::new(1, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Active);
+        send(&mut session, "USER bob@example.com");
+        send(&mut session, "PASS secret");
+        send(&mut session, "RETR 1");
+        assert_eq!(send(&mut session, "QUIT"), "+OK ZitMail POP3 server signing off (0 messages deleted)\r\n");
+        assert_eq!(uids(&store, &account), [2]);
+    }
+
+    #[test]
+    fn mailbox_lock() {
+        let (store, _) = prepare(1050);
+        let first = signed_in(&store);
+        let mut second = Session::new(2, "192.0.2.1".parse().unwrap(), Arc::clone(&store), Tls::Active);
+        send(&mut second, "USER bob@example.com");
+        assert_eq!(send(&mut second, "PASS secret"), "-ERR [IN-USE] Mailbox is locked by another POP3 session\r\n");
+        assert_eq!(send(&mut second, "STAT"), "-ERR Not authenticated\r\n");
+
+        // 连接断开后释放锁
+        drop(first);
+        send(&mut second, "USER bob@example.com");
+        assert_eq!(send(&mut second, "PASS secret"), "+OK Logged in, 3 messages\r\n");
+        assert_eq!(send(&mut second, "QUIT"), "+OK ZitMail POP3 server signing off (0 messages deleted)\r\n");
+        let mut third = signed_in(&store);
+        assert_eq!(send(&mut third, "NOOP"), "+OK No operation\r\n");
+    }
+}
@@ -0,0 +1,283 @@
+/* SASL 认证 */
+/*
+# SASL 模块
+支持 PLAIN、LOGIN（RFC 4616、draft-murchison-sasl-login）与 SCRAM-SHA-256（RFC 5802、RFC 7677），
+供 SMTP、IMAP、POP3 共用。用户名为完整的邮件地址，密码与账号存储中的 Argon2 哈希比对。
+## 用法
+let mut authenticator = Authenticator::new(Mechanism::parse("PLAIN")?, store);
+let mut step = authenticator.start(initial)?;             <-- initial 为初始响应（Base64），可以为空
+while let Step::Challenge(challenge) = step {
+    step = authenticator.step(&read_line())?;             <-- 质询与响应均为 Base64
+}
+## 说明
+SCRAM-SHA-256 需要账号保存的 SCRAM 凭据。旧账号没有凭据时，在第一次使用 PLAIN 或 LOGIN 登录成功后自动生成。
+ */
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use lazy_static::lazy_static;
+use rand_core::{OsRng, RngCore};
+use ring::digest;
+use std::fmt;
+use std::sync::Arc;
+use subtle::ConstantTimeEq;
+
+use crate::password::{self, ScramKeys, hmac_sha256};
+use crate::storage::{Account, MailStore, StorageError};
+
+lazy_static! {
+    /// 为不存在的用户生成固定的假 salt，避免通过 SCRAM 探测账号是否存在
+    static ref FAKE_SALT_KEY: [u8; 32] = {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        key
+    };
+}
+
+/// 服务端 nonce 的字节数
+const NONCE_SIZE: usize = 18;
+
+/// 认证机制
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mechanism {
+    Plain,
+    Login,
+    ScramSha256,
+}
+
+impl Mechanism {
+    /// 所有支持的机制，用于 EHLO、CAPABILITY、CAPA
+    pub const ALL: &'static [&'static str] = &["PLAIN", "LOGIN", "SCRAM-SHA-256"];
+
+    /// # 解析机制名称
+    /// 不区分大小写。
+    /// ## 参数
+    /// - name: 例如 PLAIN
+    /// ## 返回值
+    /// - Option<Mechanism>
+    pub fn parse(name: &str) -> Option<Mechanism> {
+        match name.to_ascii_uppercase().as_str() {
+            "PLAIN" => Some(Mechanism::Plain),
+            "LOGIN" => Some(Mechanism::Login),
+            "SCRAM-SHA-256" => Some(Mechanism::ScramSha256),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SaslError {
+    /// 响应不是有效的 Base64 或格式不符合机制要求
+    Malformed,
+    /// 客户端发送 * 取消认证
+    Cancelled,
+    Storage(StorageError),
+}
+
+impl fmt::Display for SaslError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaslError::Malformed => write!(f, "认证数据格式错误"),
+            SaslError::Cancelled => write!(f, "客户端取消认证"),
+            SaslError::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<StorageError> for SaslError {
+    fn from(e: StorageError) -> Self {
+        SaslError::Storage(e)
+    }
+}
+
+/// 认证的下一步
+#[derive(Debug)]
+pub enum Step {
+    /// 向客户端发送质询（已编码为 Base64）并等待响应
+    Challenge(String),
+    Success(Account),
+    /// 认证失败，附带客户端提供的用户名，用于日志
+    Failure(String),
+}
+
+enum State {
+    Start,
+    LoginUser,
+    LoginPassword(String),
+    ScramFinal { username: String, account: Option<Account>, keys: ScramKeys, nonce: String, first: String },
+    ScramDone(Account),
+    Done,
+}
+
+pub struct Authenticator {
+    mechanism: Mechanism,
+    store: Arc<dyn MailStore>,
+    state: State,
+}
+
+impl Authenticator {
+    pub fn new(mechanism: Mechanism, store: Arc<dyn MailStore>) -> Authenticator {
+        Authenticator { mechanism, store, state: State::Start }
+    }
+
+    /// # 使用用户名与密码登录
+    /// 用于 IMAP LOGIN 与 POP3 USER/PASS 等不经过 SASL 的命令，与 PLAIN 相同，账号没有 SCRAM 凭据时顺便生成。
+    /// ## 参数
+    /// - store: 邮件存储
+    /// - username: 完整的邮件地址
+    /// - password: 密码
+    /// ## 返回值
+    /// - Result<Step, SaslError>，不会返回 Step::Challenge
+    pub fn login(store: Arc<dyn MailStore>, username: &str, password: &str) -> Result<Step, SaslError> {
+        Authenticator::new(Mechanism::Plain, store).check(username, password)
+    }
+
+    /// # 开始认证
+    /// ## 参数
+    /// - initial: 初始响应（Base64），= 表示空响应
+    /// ## 返回值
+    /// - Result<Step, SaslError>
+    pub fn start(&mut self, initial: Option<&str>) -> Result<Step, SaslError> {
+        match (initial, self.mechanism) {
+            (Some(initial), _) => self.step(initial),
+            (None, Mechanism::Login) => {
+                self.state = State::LoginUser;
+                Ok(Step::Challenge(BASE64.encode("Username:")))
+            }
+            (None, _) => Ok(Step::Challenge(String::new())),
+        }
+    }
+
+    /// # 处理客户端的响应
+    /// ## 参数
+    /// - response: 一行 Base64 文本，* 表示取消
+    /// ## 返回值
+    /// - Result<Step, SaslError>
+    pub fn step(&mut self, response: &str) -> Result<Step, SaslError> {
+        let response = decode(response)?;
+        match (std::mem::replace(&mut self.state, State::Done), self.mechanism) {
+            (State::Start, Mechanism::Plain) => self.plain(&response),
+            (State::Start, Mechanism::Login) | (State::LoginUser, _) => {
+                self.state = State::LoginPassword(text(response)?);
+                Ok(Step::Challenge(BASE64.encode("Password:")))
+            }
+            (State::LoginPassword(username), _) => self.check(&username, &text(response)?),
+            (State::Start, Mechanism::ScramSha256) => self.scram_first(&text(response)?),
+            (State::ScramFinal { username, account, keys, nonce, first }, _) => {
+                self.scram_final(&text(response)?, username, account, keys, &nonce, &first)
+            }
+            // RFC 5802 5：客户端收到服务端签名后以空响应结束
+            (State::ScramDone(account), _) if response.is_empty() => Ok(Step::Success(account)),
+            (State::ScramDone(_) | State::Done, _) => Err(SaslError::Malformed),
+        }
+    }
+
+    /// PLAIN：[authzid] NUL authcid NUL passwd
+    fn plain(&mut self, response: &[u8]) -> Result<Step, SaslError> {
+        let mut parts = response.split(|&byte| byte == 0);
+        let (Some(authzid), Some(authcid), Some(password), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(SaslError::Malformed);
+        };
+        let (authzid, authcid) = (text(authzid.to_vec())?, text(authcid.to_vec())?);
+        // 不支持代理其他账号
+        if !authzid.is_empty() && !authzid.eq_ignore_ascii_case(&authcid) {
+            return Ok(Step::Failure(authcid));
+        }
+        self.check(&authcid, &text(password.to_vec())?)
+    }
+
+    /// 比对密码，账号没有 SCRAM 凭据时顺便生成
+    fn check(&self, username: &str, password: &str) -> Result<Step, SaslError> {
+        let Some(account) = self.store.account(username)? else {
+            return Ok(Step::Failure(username.to_string()));
+        };
+        if !password::verify(password, &account.password) {
+            return Ok(Step::Failure(username.to_string()));
+        }
+        if account.scram.is_empty() {
+            let scram = password::scram(password);
+            self.store.set_password(account.id, &account.password, &scram)?;
+            debug!("已为账号 {} 生成 SCRAM 凭据", account.address());
+            return Ok(Step::Success(Account { scram, ..account }));
+        }
+        Ok(Step::Success(account))
+    }
+
+    /// SCRAM client-first-message：gs2-header client-first-message-bare
+    fn scram_first(&mut self, message: &str) -> Result<Step, SaslError> {
+        // 不支持通道绑定（p=）与授权身份
+        let bare = message.strip_prefix("n,,").or_else(|| message.strip_prefix("y,,")).ok_or(SaslError::Malformed)?;
+        let mut attributes = bare.split(',');
+        let username = attributes.next().and_then(|a| a.strip_prefix("n=")).ok_or(SaslError::Malformed)?;
+        let client_nonce = attributes.next().and_then(|a| a.strip_prefix("r=")).ok_or(SaslError::Malformed)?;
+        if client_nonce.is_empty() {
+            return Err(SaslError::Malformed);
+        }
+        let username = username.replace("=2C", ",").replace("=3D", "=");
+
+        let account = self.store.account(&username)?;
+        let keys = match account.as_ref().and_then(|account| ScramKeys::parse(&account.scram)) {
+            Some(keys) => keys,
+            None => {
+                let salt = hmac_sha256(FAKE_SALT_KEY.as_slice(), username.as_bytes());
+                ScramKeys { salt: salt[..16].to_vec(), iterations: password::SCRAM_ITERATIONS, stored_key: vec![0; 32], server_key: vec![0; 32] }
+            }
+        };
+        let mut random = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut random);
+        let nonce = format!("{}{}", client_nonce, BASE64.encode(random));
+        let server_first = format!("r={},s={},i={}", nonce, BASE64.encode(&keys.salt), keys.iterations);
+
+        let first = format!("{},{}", bare, server_first);
+        let challenge = BASE64.encode(&server_first);
+        // 没有 SCRAM 凭据的账号无法完成认证
+        let account = account.filter(|account| !account.scram.is_empty());
+        self.state = State::ScramFinal { username, account, keys, nonce, first };
+        Ok(Step::Challenge(challenge))
+    }
+
+    /// SCRAM client-final-message：c=...,r=...,p=...
+    fn scram_final(
+        &mut self,
+        message: &str,
+        username: String,
+        account: Option<Account>,
+        keys: ScramKeys,
+        nonce: &str,
+        first: &str,
+    ) -> Result<Step, SaslError> {
+        let (without_proof, proof) = message.rsplit_once(",p=").ok_or(SaslError::Malformed)?;
+        let mut attributes = without_proof.split(',');
+        let binding = attributes.next().and_then(|a| a.strip_prefix("c=")).ok_or(SaslError::Malformed)?;
+        let received = attributes.next().and_then(|a| a.strip_prefix("r=")).ok_or(SaslError::Malformed)?;
+        let proof = BASE64.decode(proof).map_err(|_| SaslError::Malformed)?;
+        if !matches!(binding, "biws" | "eSws") || received != nonce || proof.len() != keys.stored_key.len() {
+            return Ok(Step::Failure(username));
+        }
+
+        let auth_message = format!("{},{}", first, without_proof);
+        let signature = hmac_sha256(&keys.stored_key, auth_message.as_bytes());
+        let client_key: Vec<u8> = proof.iter().zip(&signature).map(|(a, b)| a ^ b).collect();
+        let stored_key = digest::digest(&digest::SHA256, &client_key);
+        let Some(account) = account.filter(|_| bool::from(stored_key.as_ref().ct_eq(&keys.stored_key))) else {
+            return Ok(Step::Failure(username));
+        };
+
+        let server_signature = hmac_sha256(&keys.server_key, auth_message.as_bytes());
+        self.state = State::ScramDone(account);
+        Ok(Step::Challenge(BASE64.encode(format!("v={}", BASE64.encode(server_signature)))))
+    }
+}
+
+/// # 解码客户端响应
+fn decode(response: &str) -> Result<Vec<u8>, SaslError> {
+    match response.trim() {
+        "*" => Err(SaslError::Cancelled),
+        "=" => Ok(Vec::new()),
+        response => BASE64.decode(response).map_err(|_| SaslError::Malformed),
+    }
+}
+
+fn text(bytes: Vec<u8>) -> Result<String, SaslError> {
+    String::from_utf8(bytes).map_err(|_| SaslError::Malformed)
+}
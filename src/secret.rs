@@ -0,0 +1,137 @@
+/* 敏感配置项 */
+/*
+# 敏感配置项的取值方式
+EnvMode        从环境变量读取，变量名见 ENV_MODE，例如 Password=admin zmou
+env:NAME       从环境变量 NAME 读取
+file:/path     从文件读取（去除末尾换行），适用于 Docker secrets 与 systemd credentials
+其他           直接使用填写的值
+解析后的值会注册到日志模块，日志中出现时显示为 ******。
+ */
+use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::log::redact;
+
+/// EnvMode 对应的环境变量
+pub const ENV_MODE: &[(&str, &str, &str)] = &[
+    ("Database", "Password", "Password"),
+    ("WebServer", "Key", "WebServerKey"),
+    ("API", "Key", "APIKey"),
+    ("SMTP", "Key", "SMTPKey"),
+    ("IMAP", "Key", "IMAPKey"),
+    ("POP3", "Key", "POP3Key"),
+];
+
+/// 打码后显示的内容
+pub const MASK: &str = "******";
+
+/// 敏感值，Debug 输出时打码
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// # 读取明文
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", MASK)
+    }
+}
+
+/// 取值方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    /// 值本身是敏感信息，例如密码
+    Value,
+    /// 值是敏感文件的路径，例如 TLS 私钥；file:/path 直接解析为该路径
+    Path,
+}
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// 环境变量不存在或为空
+    Env(String),
+    /// 文件无法读取
+    File(PathBuf, std::io::Error),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::Env(name) => write!(f, "环境变量 {} 不存在或为空", name),
+            SecretError::File(path, e) => write!(f, "无法读取文件 {}：{}", path.display(), e),
+        }
+    }
+}
+
+/// # 是否为引用形式（EnvMode、env:、file:）
+/// 引用本身不是敏感信息，可以原样显示。
+/// ## 参数
+/// - raw: 配置文件中填写的值
+/// ## 返回值
+/// - bool
+pub fn is_reference(raw: &str) -> bool {
+    raw == "EnvMode" || raw.starts_with("env:") || raw.starts_with("file:")
+}
+
+/// # 解析敏感配置项
+/// ## 参数
+/// - raw: 配置文件中填写的值
+/// - env_mode: EnvMode 对应的环境变量名
+/// - kind: 取值方式
+/// ## 返回值
+/// - Result<String, SecretError>
+pub fn resolve(raw: &str, env_mode: &str, kind: Kind) -> Result<String, SecretError> {
+    let value = if raw == "EnvMode" {
+        read_env(env_mode)?
+    } else if let Some(name) = raw.strip_prefix("env:") {
+        read_env(name)?
+    } else if let Some(path) = raw.strip_prefix("file:") {
+        match kind {
+            Kind::Path => path.to_string(),
+            Kind::Value => std::fs::read_to_string(path)
+                .map_err(|e| SecretError::File(PathBuf::from(path), e))?
+                .trim_end_matches(['\r', '\n'])
+                .to_string(),
+        }
+    } else {
+        raw.to_string()
+    };
+    if kind == Kind::Value {
+        redact(&value);
+    }
+    Ok(value)
+}
+
+/// # EnvMode 对应的环境变量名
+/// ## 参数
+/// - section: 节
+/// - key: 键
+/// ## 返回值
+/// - &str
+pub fn env_mode(section: &str, key: &str) -> &'static str {
+    ENV_MODE
+        .iter()
+        .find(|(s, k, _)| *s == section && *k == key)
+        .map(|(_, _, env)| *env)
+        .unwrap_or("Password")
+}
+
+fn read_env(name: &str) -> Result<String, SecretError> {
+    match std::env::var(name) {
+        Ok(value) if !value.is_empty() => Ok(value),
+        _ => Err(SecretError::Env(name.to_string())),
+    }
+}
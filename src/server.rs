@@ -0,0 +1,156 @@
+/* 启动 ZitMail */
+/*
+# 服务
+1. 打开存储并升级数据库结构
+2. 启动已启用的服务，每个服务在单独的线程中监听
+3. 启动发送队列
+4. 收到 Ctrl+C 或 SIGTERM 后停止发送队列并返回，随后由 quit! 关闭日志模块
+ */
+use crossbeam_channel::bounded;
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+use crate::api;
+use crate::config::{Backend, Config};
+use crate::imap;
+use crate::jmap;
+use crate::pop3;
+use crate::smtp;
+use crate::smtp::client::SmtpTransport;
+use crate::smtp::resolver::DnsResolver;
+use crate::storage::{self, StorageError};
+use crate::tls::{self, TlsError};
+
+#[derive(Debug)]
+pub enum ServerError {
+    Storage(StorageError),
+    Tls(TlsError),
+    /// 无法监听端口 (服务, 地址, 错误)
+    Bind(&'static str, String, io::Error),
+    /// 无法创建线程
+    Thread(io::Error),
+    /// 无法注册退出信号
+    Signal(ctrlc::Error),
+    /// 无法初始化 DNS 解析器
+    Resolver(io::Error),
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Storage(e) => write!(f, "{}", e),
+            ServerError::Tls(e) => write!(f, "{}", e),
+            ServerError::Bind(service, address, e) => write!(f, "{} 服务无法监听 {}：{}", service, address, e),
+            ServerError::Thread(e) => write!(f, "无法创建线程：{}", e),
+            ServerError::Signal(e) => write!(f, "无法注册退出信号：{}", e),
+            ServerError::Resolver(e) => write!(f, "无法初始化 DNS 解析器：{}", e),
+        }
+    }
+}
+
+impl From<StorageError> for ServerError {
+    fn from(e: StorageError) -> Self {
+        ServerError::Storage(e)
+    }
+}
+
+impl From<TlsError> for ServerError {
+    fn from(e: TlsError) -> Self {
+        ServerError::Tls(e)
+    }
+}
+
+/// # 启动所有已启用的服务
+/// 阻塞直到收到退出信号，返回前等待发送队列中正在进行的投递完成。
+/// ## 参数
+/// - config: 已校验并解析敏感配置项的配置
+/// ## 返回值
+/// - Result<(), ServerError>
+pub fn run(config: &Config) -> Result<(), ServerError> {
+    let store = storage::open(&config.database)?;
+    if config.database.backend == Backend::Memory {
+        warning!("正在使用内存存储，重启后数据将丢失");
+    }
+    store.migrate()?;
+
+    let (shutdown, signal) = bounded(1);
+    ctrlc::set_handler(move || {
+        let _ = shutdown.try_send(());
+    })
+    .map_err(ServerError::Signal)?;
+
+    // 监听线程在进程退出时结束
+    let mut handles = Vec::new();
+    let mut queue = None;
+    if config.smtp.enable {
+        let section = &config.smtp;
+        let tls = if section.tls { Some(tls::server_config(&section.cert, &section.key)?) } else { None };
+        if section.submission && tls.is_none() {
+            warning!("[SMTP] 未启用 TLS，邮件提交服务将以明文传输密码");
+        }
+        for (listener, mode) in smtp::server::listeners(section) {
+            let address = format!("{}:{}", section.address, listener.port);
+            let handle = smtp::server::start(section, listener, mode, tls.clone(), store.clone())
+                .map_err(|e| ServerError::Bind(listener.name, address, e))?;
+            handles.push(handle);
+        }
+        let resolver = Arc::new(DnsResolver::new(&config.queue.nameserver).map_err(ServerError::Resolver)?);
+        let transport = Arc::new(SmtpTransport::new(&section.hostname, resolver));
+        queue = Some(smtp::queue::start(&config.queue, &section.hostname, store.clone(), transport).map_err(ServerError::Thread)?);
+    }
+    if config.imap.enable {
+        let section = &config.imap;
+        let tls = if section.tls { Some(tls::server_config(&section.cert, &section.key)?) } else { None };
+        if tls.is_none() {
+            warning!("[IMAP] 未启用 TLS，将以明文传输密码");
+        }
+        for listener in imap::server::listeners(section) {
+            let address = format!("{}:{}", section.address, listener.port);
+            let handle = imap::server::start(section, listener, tls.clone(), store.clone())
+                .map_err(|e| ServerError::Bind(listener.name, address, e))?;
+            handles.push(handle);
+        }
+    }
+    if config.pop3.enable {
+        let section = &config.pop3;
+        let tls = if section.tls { Some(tls::server_config(&section.cert, &section.key)?) } else { None };
+        if tls.is_none() {
+            warning!("[POP3] 未启用 TLS，将以明文传输密码");
+        }
+        for listener in pop3::server::listeners(section) {
+            let address = format!("{}:{}", section.address, listener.port);
+            let handle = pop3::server::start(section, listener, tls.clone(), store.clone())
+                .map_err(|e| ServerError::Bind(listener.name, address, e))?;
+            handles.push(handle);
+        }
+    }
+    if config.api.enable {
+        let section = &config.api;
+        let tls = if section.tls { Some(tls::server_config(&section.cert, &section.key)?) } else { None };
+        let address = format!("{}:{}", section.address, section.port);
+        let handle = api::server::start(config, tls, store.clone()).map_err(|e| ServerError::Bind("API", address, e))?;
+        handles.push(handle);
+    }
+    if config.web_server.enable {
+        let section = &config.web_server;
+        let tls = if section.tls { Some(tls::server_config(&section.cert, &section.key)?) } else { None };
+        if tls.is_none() && section.address.parse::<std::net::IpAddr>().is_ok_and(|address| !address.is_loopback()) {
+            warning!("[WebServer] 未启用 TLS，JMAP 将以明文传输密码");
+        }
+        let address = format!("{}:{}", section.address, section.port);
+        let handle = jmap::server::start(config, tls, store.clone()).map_err(|e| ServerError::Bind("JMAP", address, e))?;
+        handles.push(handle);
+    }
+    if handles.is_empty() {
+        warning!("没有启用任何服务");
+        return Ok(());
+    }
+
+    let _ = signal.recv();
+    info!("正在停止 ZitMail");
+    if let Some(queue) = queue {
+        queue.stop();
+    }
+    Ok(())
+}
@@ -0,0 +1,370 @@
+/* 投递状态通知 */
+/*
+# 投递状态通知（DSN）
+## 用法
+let message = bounce::failure(hostname, &entry, &[(recipient, 原因)], &raw);
+let message = bounce::delay(hostname, &entry, &[(recipient, 原因)], expires, &raw);
+let message = bounce::success(hostname, &entry, Action::Relayed, &[(recipient, 原因)], &raw);
+## 说明
+生成的邮件为 multipart/report（RFC 6522），由发送队列以空发件人 <> 发往原邮件的发件人，包括三部分：
+text/plain                 可读的说明，列出每个收件人与原因
+message/delivery-status    机器可读的投递状态（RFC 3464），地址含非 ASCII 字符时为 message/global-delivery-status（RFC 6533）
+text/rfc822-headers        原邮件的邮件头；退信且发件人指定 RET=FULL 时为 message/rfc822，附带整封原邮件
+状态码优先使用远程服务器应答中的增强状态码（RFC 3463），否则由应答码或投递结果推断。
+ */
+use chrono::{DateTime, Utc};
+
+use super::encode_xtext;
+use crate::storage::{QueueEntry, QueueRecipient};
+
+/// 超过最长保留时间时失败原因的前缀
+pub const EXPIRED: &str = "Delivery time expired";
+
+/// 收件人的投递结果（RFC 3464 2.3.3）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Failed,
+    Delayed,
+    /// 已保存到本机账号
+    Delivered,
+    /// 已投递到不支持 DSN 的服务器，之后不会再有通知（RFC 3461 5.2.2）
+    Relayed,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Failed => "failed",
+            Action::Delayed => "delayed",
+            Action::Delivered => "delivered",
+            Action::Relayed => "relayed",
+        }
+    }
+}
+
+/// # 生成退信
+/// ## 参数
+/// - hostname: 本机主机名
+/// - entry: 队列中的原邮件
+/// - recipients: 失败的收件人与原因
+/// - raw: 原邮件原文
+/// ## 返回值
+/// - Vec<u8>
+pub fn failure(hostname: &str, entry: &QueueEntry, recipients: &[(&QueueRecipient, String)], raw: &[u8]) -> Vec<u8> {
+    let text = "Your message could not be delivered to one or more recipients.\r\n\
+                This is a permanent error. The following address(es) failed:";
+    build(hostname, entry, Action::Failed, "Undelivered Mail Returned to Sender", text, recipients, None, raw)
+}
+
+/// # 生成延迟通知
+/// ## 参数
+/// - hostname: 本机主机名
+/// - entry: 队列中的原邮件
+/// - recipients: 尚未投递的收件人与最近一次失败的原因
+/// - expires: 放弃投递的时间
+/// - raw: 原邮件原文
+/// ## 返回值
+/// - Vec<u8>
+pub fn delay(hostname: &str, entry: &QueueEntry, recipients: &[(&QueueRecipient, String)], expires: DateTime<Utc>, raw: &[u8]) -> Vec<u8> {
+    let text = format!(
+        "Delivery to the following recipient(s) has been delayed.\r\n\
+         The mail system will keep trying until {}.\r\n\
+         This is a warning only. You do not need to resend your message.",
+        expires.to_rfc2822()
+    );
+    build(hostname, entry, Action::Delayed, "Delayed Mail (still being retried)", &text, recipients, Some(expires), raw)
+}
+
+/// # 生成投递成功的通知
+/// 收件人的 NOTIFY 参数包含 SUCCESS 时发送。
+/// ## 参数
+/// - hostname: 本机主机名
+/// - entry: 队列中的原邮件
+/// - action: Action::Delivered 或 Action::Relayed
+/// - recipients: 收件人与服务器的应答
+/// - raw: 原邮件原文
+/// ## 返回值
+/// - Vec<u8>
+pub fn success(hostname: &str, entry: &QueueEntry, action: Action, recipients: &[(&QueueRecipient, String)], raw: &[u8]) -> Vec<u8> {
+    let text = match action {
+        Action::Relayed => {
+            "Your message was relayed to the following recipient(s).\r\n\
+             The destination does not support delivery notifications, you will not receive further notice."
+        }
+        _ => "Your message was successfully delivered to the following recipient(s):",
+    };
+    build(hostname, entry, action, "Successful Mail Delivery Report", text, recipients, None, raw)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn build(
+    hostname: &str,
+    entry: &QueueEntry,
+    action: Action,
+    subject: &str,
+    text: &str,
+    recipients: &[(&QueueRecipient, String)],
+    expires: Option<DateTime<Utc>>,
+    raw: &[u8],
+) -> Vec<u8> {
+    let now = Utc::now();
+    let boundary = format!("{}.{}/{}", now.timestamp_millis(), entry.id, hostname);
+    let mut message = format!(
+        "From: Mail Delivery System <MAILER-DAEMON@{hostname}>\r\n\
+         To: <{sender}>\r\n\
+         Subject: {subject}\r\n\
+         Date: {date}\r\n\
+         Message-ID: <{time}.{id}@{hostname}>\r\n\
+         Auto-Submitted: auto-replied\r\n\
+         MIME-Version: 1.0\r\n\
+         Content-Type: multipart/report; report-type=delivery-status;\r\n\
+         \tboundary=\"{boundary}\"\r\n\
+         \r\n\
+         This is a MIME-encapsulated message.\r\n\
+         \r\n\
+         --{boundary}\r\n\
+         Content-Description: Notification\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\
+         Content-Transfer-Encoding: 8bit\r\n\
+         \r\n\
+         This is the mail system at host {hostname}.\r\n\
+         \r\n\
+         {text}\r\n\
+         \r\n",
+        sender = entry.sender,
+        date = now.to_rfc2822(),
+        time = now.timestamp_millis(),
+        id = entry.id,
+    );
+    for (recipient, reason) in recipients {
+        message.push_str(&format!("<{}>: {}\r\n", recipient.recipient, reason));
+    }
+
+    let report = report(hostname, entry, action, recipients, expires);
+    let global = if report.is_ascii() { "" } else { "global-" };
+    message.push_str(&format!(
+        "\r\n--{boundary}\r\n\
+         Content-Description: Delivery report\r\n\
+         Content-Type: message/{global}delivery-status\r\n\
+         \r\n\
+         {report}"
+    ));
+
+    let full = action == Action::Failed && entry.dsn.ret.as_deref() == Some("FULL");
+    let (description, content_type, content) = match full {
+        true => ("Undelivered Message", "message/rfc822", raw),
+        false => ("Message Headers", "text/rfc822-headers", headers(raw)),
+    };
+    message.push_str(&format!("\r\n--{boundary}\r\nContent-Description: {description}\r\nContent-Type: {content_type}\r\n"));
+    if !content.is_ascii() {
+        message.push_str("Content-Transfer-Encoding: 8bit\r\n");
+    }
+    message.push_str("\r\n");
+    let mut content = content.to_vec();
+    if !content.ends_with(b"\r\n") {
+        content.extend_from_slice(b"\r\n");
+    }
+    [message.as_bytes(), &content, format!("--{}--\r\n", boundary).as_bytes()].concat()
+}
+
+/// # message/delivery-status 部分的正文
+/// 一个邮件字段组，之后每个收件人一个字段组，以空行分隔（RFC 3464 2.1）。
+fn report(hostname: &str, entry: &QueueEntry, action: Action, recipients: &[(&QueueRecipient, String)], expires: Option<DateTime<Utc>>) -> String {
+    let mut report = format!("Reporting-MTA: dns; {}\r\n", hostname);
+    if let Some(envid) = &entry.dsn.envid {
+        report.push_str(&format!("Original-Envelope-Id: {}\r\n", encode_xtext(envid)));
+    }
+    report.push_str(&format!("Arrival-Date: {}\r\n", entry.created_at.to_rfc2822()));
+
+    for (recipient, reason) in recipients {
+        report.push_str("\r\n");
+        if let Some((kind, address)) = recipient.dsn.orcpt.as_deref().and_then(|orcpt| orcpt.split_once(';')) {
+            report.push_str(&format!("Original-Recipient: {}; {}\r\n", kind, address));
+        }
+        let kind = if recipient.recipient.is_ascii() { "rfc822" } else { "utf-8" };
+        report.push_str(&format!("Final-Recipient: {}; {}\r\n", kind, recipient.recipient));
+        report.push_str(&format!("Action: {}\r\n", action.as_str()));
+        report.push_str(&format!("Status: {}\r\n", status(action, reason)));
+        if let Some(remote) = remote_mta(reason) {
+            report.push_str(&format!("Remote-MTA: dns; {}\r\n", remote));
+        }
+        if let Some(reply) = diagnostic(reason) {
+            report.push_str(&format!("Diagnostic-Code: smtp; {}\r\n", reply));
+        }
+        match expires {
+            Some(expires) => report.push_str(&format!("Will-Retry-Until: {}\r\n", expires.to_rfc2822())),
+            None => report.push_str(&format!("Last-Attempt-Date: {}\r\n", Utc::now().to_rfc2822())),
+        }
+    }
+    report
+}
+
+/// # 投递结果的增强状态码
+/// ## 参数
+/// - action: 投递结果
+/// - reason: 失败原因或服务器的应答，例如 mx.example.org[192.0.2.1] said: 550 5.1.1 No such user
+/// ## 返回值
+/// - String，例如 5.1.1
+pub fn status(action: Action, reason: &str) -> String {
+    if reason.starts_with(EXPIRED) {
+        return String::from("4.4.7");
+    }
+    // 本机产生的原因以增强状态码开头，例如 5.6.7 mx.example.org[192.0.2.1] does not support SMTPUTF8
+    let class = match action {
+        Action::Failed => "5",
+        Action::Delayed => "4",
+        Action::Delivered | Action::Relayed => "2",
+    };
+    if let Some(status) = reason.split(' ').next().filter(|status| is_status(status) && status.starts_with(class)) {
+        return status.to_string();
+    }
+    if let Some(reply) = diagnostic(reason) {
+        let mut words = reply.split(' ');
+        let class = &reply[..1];
+        return match words.nth(1) {
+            Some(status) if is_status(status) && status.starts_with(class) => status.to_string(),
+            _ => format!("{}.0.0", class),
+        };
+    }
+    String::from(match action {
+        Action::Failed => "5.0.0",
+        Action::Delayed => "4.0.0",
+        Action::Delivered | Action::Relayed => "2.0.0",
+    })
+}
+
+/// # 原因中的 SMTP 应答
+/// 例如 550 5.1.1 No such user，原因不是来自 SMTP 应答时为 None。
+pub fn diagnostic(reason: &str) -> Option<&str> {
+    let reply = reason.split_once(" said: ").map_or(reason, |(_, reply)| reply);
+    let valid = reply.len() > 4 && reply.as_bytes()[..3].iter().all(u8::is_ascii_digit) && reply.as_bytes()[3] == b' ';
+    (valid && matches!(reply.as_bytes()[0], b'2' | b'4' | b'5')).then_some(reply)
+}
+
+/// # 原因中的远程服务器主机名
+/// 由 client.rs 的 主机名[IP] said: 格式得到。
+pub fn remote_mta(reason: &str) -> Option<&str> {
+    let (prefix, _) = reason.split_once(" said: ")?;
+    let peer = prefix.rsplit(' ').next()?;
+    Some(peer.split_once('[').map_or(peer, |(host, _)| host))
+}
+
+/// # 是否为增强状态码
+/// 格式为 class.subject.detail，例如 5.1.1（RFC 3463 2）
+pub fn is_status(text: &str) -> bool {
+    let parts: Vec<&str> = text.split('.').collect();
+    parts.len() == 3
+        && matches!(parts[0], "2" | "4" | "5")
+        && parts[1..].iter().all(|part| (1..=3).contains(&part.len()) && part.bytes().all(|byte| byte.is_ascii_digit()))
+}
+
+/// # 邮件头部分
+/// 包括结尾的空行，没有正文时为整封邮件。
+pub fn headers(raw: &[u8]) -> &[u8] {
+    match raw.windows(4).position(|window| window == b"\r\n\r\n") {
+        Some(position) => &raw[..position + 4],
+        None => raw,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mime;
+    use crate::smtp::report;
+    use crate::storage::{Dsn, RecipientDsn};
+
+    const RAW: &[u8] = b"From: alice@example.com\r\nTo: bob@remote.test\r\nSubject: hi\r\nMessage-ID: <hi-1@example.com>\r\n\r\nbody\r\n";
+
+    /// # 队列中的原邮件
+    fn queued(ret: &str, recipients: &[&str]) -> QueueEntry {
+        let now = Utc::now();
+        QueueEntry {
+            id: 7,
+            sender: String::from("alice@example.com"),
+            dsn: Dsn { ret: Some(ret.to_string()), envid: Some(String::from("e+1 x")) },
+            created_at: now,
+            warned: false,
+            held: false,
+            recipients: recipients
+                .iter()
+                .enumerate()
+                .map(|(index, recipient)| QueueRecipient {
+                    id: index as i64 + 1,
+                    recipient: recipient.to_string(),
+                    domain: String::from("remote.test"),
+                    attempts: 1,
+                    next_attempt: now,
+                    last_error: None,
+                    dsn: RecipientDsn { notify: None, orcpt: Some(String::from("rfc822;Bob@remote.test")) },
+                })
+                .collect(),
+        }
+    }
+
+    /// # 各部分的类型
+    fn layout(message: &[u8]) -> Vec<String> {
+        let part = mime::parse(message);
+        part.children.iter().map(|child| format!("{}/{}", child.media_type, child.subtype)).collect()
+    }
+
+    #[test]
+    fn failure_layout() {
+        let entry = queued("HDRS", &["bob@remote.test"]);
+        let reason = String::from("mx.remote.test[192.0.2.1] said: 550 5.1.1 no such user");
+        let message = failure("mx.example.com", &entry, &[(&entry.recipients[0], reason)], RAW);
+
+        let part = mime::parse(&message);
+        assert!(part.errors().is_empty(), "{:?}", part.errors());
+        assert_eq!((part.media_type.as_ref(), part.subtype.as_ref()), ("multipart", "report"));
+        assert_eq!(part.parameter("report-type"), Some("delivery-status"));
+        assert_eq!(part.field("To").as_deref(), Some("<alice@example.com>"));
+        assert_eq!(layout(&message), ["text/plain", "message/delivery-status", "text/rfc822-headers"]);
+        assert_eq!(&*part.children[2].decode(), &RAW[..RAW.len() - 8]);
+
+        let parsed = report::parse(&message).unwrap();
+        assert_eq!(parsed.reporting_mta.as_deref(), Some("mx.example.com"));
+        assert_eq!(parsed.envid.as_deref(), Some("e+1 x"));
+        assert_eq!(parsed.message_id.as_deref(), Some("hi-1@example.com"));
+        let recipient = &parsed.recipients[0];
+        assert_eq!(recipient.final_recipient.as_deref(), Some("bob@remote.test"));
+        assert_eq!(recipient.original_recipient.as_deref(), Some("Bob@remote.test"));
+        assert_eq!((recipient.action.as_str(), recipient.status.as_deref()), ("failed", Some("5.1.1")));
+        assert_eq!(recipient.remote_mta.as_deref(), Some("mx.remote.test"));
+        assert_eq!(recipient.diagnostic.as_deref(), Some("550 5.1.1 no such user"));
+
+        // RET=FULL 附带整封原邮件
+        let entry = queued("FULL", &["bob@remote.test"]);
+        let message = failure("mx.example.com", &entry, &[(&entry.recipients[0], String::from("5.6.3 no 8BITMIME"))], RAW);
+        assert_eq!(layout(&message), ["text/plain", "message/delivery-status", "message/rfc822"]);
+        let part = mime::parse(&message);
+        assert_eq!(part.children[2].message.as_ref().unwrap().body, b"body");
+        assert_eq!(report::parse(&message).unwrap().recipients[0].status.as_deref(), Some("5.6.3"));
+    }
+
+    #[test]
+    fn delay_and_success_layout() {
+        let entry = queued("FULL", &["bob@remote.test", "用户@remote.test"]);
+        let reasons: Vec<_> = entry.recipients.iter().map(|recipient| (recipient, String::from("connection timed out"))).collect();
+        let expires = Utc::now() + chrono::Duration::days(4);
+        let message = delay("mx.example.com", &entry, &reasons, expires, RAW);
+        // 延迟通知不附带原邮件；地址含非 ASCII 字符时为 global-delivery-status
+        assert_eq!(layout(&message), ["text/plain", "message/global-delivery-status", "text/rfc822-headers"]);
+        let text = String::from_utf8_lossy(&message).into_owned();
+        assert!(text.contains(&format!("Will-Retry-Until: {}\r\n", expires.to_rfc2822())));
+        assert!(text.contains("Final-Recipient: utf-8; 用户@remote.test\r\n"));
+        let parsed = report::parse(&message).unwrap();
+        assert_eq!(parsed.recipients.iter().map(|recipient| (recipient.action.as_str(), recipient.status.as_deref())).collect::<Vec<_>>(), [
+            ("delayed", Some("4.0.0")),
+            ("delayed", Some("4.0.0"))
+        ]);
+
+        let entry = queued("HDRS", &["bob@remote.test"]);
+        let reason = String::from("mx.remote.test[192.0.2.1] said: 250 2.0.0 queued as 1234");
+        let message = success("mx.example.com", &entry, Action::Relayed, &[(&entry.recipients[0], reason)], RAW);
+        assert_eq!(layout(&message), ["text/plain", "message/delivery-status", "text/rfc822-headers"]);
+        assert!(mime::parse(&message).field("Subject").is_some_and(|subject| subject == "Successful Mail Delivery Report"));
+        let recipient = &report::parse(&message).unwrap().recipients[0];
+        assert_eq!((recipient.action.as_str(), recipient.status.as_deref()), ("relayed", Some("2.0.0")));
+    }
+}
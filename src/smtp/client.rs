@@ -0,0 +1,730 @@
+/* SMTP 客户端 */
+/*
+# 投递到其他邮件服务器
+## 用法
+let resolver = Arc::new(DnsResolver::new(&config.queue.nameserver)?);
+let transport = Arc::new(SmtpTransport::new(&config.smtp.hostname, resolver));
+let outcomes = transport.deliver("example.org", &entry, &entry.recipients, &raw);
+## 说明
+按 MX 优先级依次尝试每台邮件服务器的每个地址（A/AAAA），直到有一个完成会话；问候语不是 220（例如 421、554）时同样尝试下一个，
+全部拒绝时以最后一个问候语为结果。没有 MX 记录时使用域名本身，[192.0.2.1] 形式的地址字面量直接连接。
+对方支持 STARTTLS 时加密传输但不校验证书（RFC 7435），握手失败时改用明文重新连接。
+应答 2xx 为成功，4xx 与网络错误为临时失败，5xx 为永久失败（RFC 5321 4.2.1）。
+对方不支持 SMTPUTF8 时，非 ASCII 的发件人或收件人永久失败（5.6.7，RFC 6531 3.2）；
+不支持 8BITMIME 时，含有 8 位内容的邮件不做转换，永久失败（5.6.3）。本机产生的原因以增强状态码开头。
+ */
+use std::io::{self, BufRead, Read};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::ClientConfig;
+
+use super::encode_xtext;
+use super::queue::{Outcome, Transport};
+use super::resolver::{self, DnsError, Resolver};
+use crate::storage::{QueueEntry, QueueRecipient};
+use crate::tls::{self, Connection, Stream};
+
+/// SMTP 端口
+const PORT: u16 = 25;
+
+/// 建立连接的超时
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 等待应答的超时（RFC 5321 4.5.3.2）
+const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// 等待 DATA 结束后应答的超时（RFC 5321 4.5.3.2.6）
+const DATA_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// 应答行的长度上限
+const MAX_REPLY_LINE: u64 = 4096;
+
+/// 直接连接收件人域名的邮件服务器投递
+pub struct SmtpTransport {
+    /// 本机主机名，用于 EHLO 与 MX 回环检查
+    hostname: String,
+    resolver: Arc<dyn Resolver>,
+    tls: Arc<ClientConfig>,
+    port: u16,
+}
+
+impl Transport for SmtpTransport {
+    fn deliver(&self, domain: &str, entry: &QueueEntry, recipients: &[QueueRecipient], raw: &[u8]) -> Vec<Outcome> {
+        let hosts = match resolver::literal(domain) {
+            Some(ip) => vec![ip.to_string()],
+            None => match resolver::mail_hosts(self.resolver.as_ref(), domain, &self.hostname) {
+                Ok(hosts) => hosts,
+                Err(e) => {
+                    debug!("队列 #{} 无法投递到 {}：{}", entry.id, domain, e);
+                    let outcome = match e {
+                        DnsError::NotFound(_) => Outcome::Failed(format!("domain {} does not exist", domain)),
+                        DnsError::NullMx(_) => Outcome::Failed(format!("domain {} does not accept mail (null MX)", domain)),
+                        DnsError::Loop(_) => Outcome::Failed(format!("mail for {} loops back to myself", domain)),
+                        DnsError::Temporary(e) => Outcome::Deferred(format!("DNS lookup for {} failed: {}", domain, e)),
+                    };
+                    return vec![outcome; recipients.len()];
+                }
+            },
+        };
+
+        let mut last_error = None;
+        for host in &hosts {
+            let addresses = match resolver::literal(host) {
+                Some(ip) => vec![ip],
+                None => match self.resolver.ip(host) {
+                    Ok(addresses) => addresses,
+                    Err(e) => {
+                        debug!("队列 #{} 无法解析 {}：{}", entry.id, host, e);
+                        last_error = Some(Outcome::Deferred(format!("cannot resolve {}: {}", host, e)));
+                        continue;
+                    }
+                },
+            };
+            for ip in addresses {
+                match self.attempt(host, ip, entry, recipients, raw) {
+                    Ok(outcomes) => return outcomes,
+                    Err(Failure::Refused(reply)) => {
+                        debug!("队列 #{} {}（{}）拒绝服务：{} {}", entry.id, host, ip, reply.code, reply.lines.join(" "));
+                        last_error = Some(reply.refusal(&format!("{}[{}]", host, ip)));
+                    }
+                    Err(Failure::Connect(e) | Failure::Tls(e)) => {
+                        debug!("队列 #{} 无法连接 {}（{}）：{}", entry.id, host, ip, e);
+                        last_error = Some(Outcome::Deferred(format!("connection to {}[{}] failed: {}", host, ip, e)));
+                    }
+                }
+            }
+        }
+        let outcome = match last_error {
+            Some(outcome) => outcome,
+            // 没有 MX 记录，域名本身也没有地址（RFC 5321 5.1）
+            None if hosts.len() == 1 && hosts[0].eq_ignore_ascii_case(domain) => {
+                Outcome::Failed(format!("domain {} has no MX or address records", domain))
+            }
+            None => Outcome::Deferred(format!("no address found for the mail servers of {}", domain)),
+        };
+        vec![outcome; recipients.len()]
+    }
+}
+
+/// 会话在邮件事务开始前失败
+enum Failure {
+    /// 无法连接或没有问候语，尝试下一个地址
+    Connect(io::Error),
+    /// TLS 握手失败，以明文重新连接同一地址
+    Tls(io::Error),
+    /// 问候语不是 220，例如 421 或 554，尝试下一个地址（RFC 5321 3.1）
+    Refused(Reply),
+}
+
+/// 服务器的应答
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    /// 以应答码判断收件人的投递结果
+    fn outcome(&self, peer: &str) -> Outcome {
+        let reason = format!("{} said: {} {}", peer, self.code, self.lines.join(" "));
+        match self.code {
+            200..=399 => Outcome::Delivered(reason),
+            400..=499 => Outcome::Deferred(reason),
+            _ => Outcome::Failed(reason),
+        }
+    }
+
+    /// 拒绝服务的问候语，所有地址都拒绝时作为投递结果：5xx 为永久失败，其他为临时失败
+    fn refusal(&self, peer: &str) -> Outcome {
+        let reason = format!("{} said: {} {}", peer, self.code, self.lines.join(" "));
+        match self.code {
+            500..=599 => Outcome::Failed(reason),
+            _ => Outcome::Deferred(reason),
+        }
+    }
+
+    fn is_positive(&self) -> bool {
+        self.code < 400
+    }
+}
+
+/// EHLO 应答中的扩展
+struct Extensions(Vec<String>);
+
+impl Extensions {
+    /// # 是否支持扩展
+    /// ## 参数
+    /// - keyword: 扩展名，例如 STARTTLS
+    fn has(&self, keyword: &str) -> bool {
+        self.0.iter().any(|line| line.split(' ').next().is_some_and(|name| name.eq_ignore_ascii_case(keyword)))
+    }
+}
+
+impl SmtpTransport {
+    /// # 创建
+    /// ## 参数
+    /// - hostname: 本机主机名
+    /// - resolver: DNS 查询
+    /// ## 返回值
+    /// - SmtpTransport
+    pub fn new(hostname: &str, resolver: Arc<dyn Resolver>) -> SmtpTransport {
+        SmtpTransport { hostname: hostname.to_string(), resolver, tls: tls::client_config(), port: PORT }
+    }
+
+    /// # 设置对方的端口
+    /// 默认为 25，测试时连接本地的 SMTP 服务器。
+    #[cfg(test)]
+    pub fn port(mut self, port: u16) -> SmtpTransport {
+        self.port = port;
+        self
+    }
+
+    /// # 连接一个地址
+    /// 先尝试 STARTTLS，握手失败时以明文重试一次。
+    fn attempt(&self, host: &str, ip: IpAddr, entry: &QueueEntry, recipients: &[QueueRecipient], raw: &[u8]) -> Result<Vec<Outcome>, Failure> {
+        match self.session(host, ip, entry, recipients, raw, true) {
+            Err(Failure::Tls(e)) => {
+                debug!("队列 #{} 与 {}（{}）的 TLS 握手失败，改用明文：{}", entry.id, host, ip, e);
+                self.session(host, ip, entry, recipients, raw, false)
+            }
+            result => result,
+        }
+    }
+
+    /// # 与一台服务器完成一次会话
+    /// 连接建立前、问候语之前、拒绝服务的问候语或 TLS 握手的错误返回 Err，由调用者决定如何重试；
+    /// 此后的错误作为所有未决收件人的临时失败。
+    fn session(
+        &self,
+        host: &str,
+        ip: IpAddr,
+        entry: &QueueEntry,
+        recipients: &[QueueRecipient],
+        raw: &[u8],
+        starttls: bool,
+    ) -> Result<Vec<Outcome>, Failure> {
+        let address = SocketAddr::new(ip, self.port);
+        let peer = format!("{}[{}]", host, ip);
+        let tcp = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(Failure::Connect)?;
+        tcp.set_read_timeout(Some(TIMEOUT)).map_err(Failure::Connect)?;
+        tcp.set_write_timeout(Some(TIMEOUT)).map_err(Failure::Connect)?;
+        let mut connection = Connection::new(Stream::Plain(tcp));
+        let greeting = read_reply(&mut connection).map_err(Failure::Connect)?;
+        if greeting.code != 220 {
+            let _ = command(&mut connection, "QUIT");
+            return Err(Failure::Refused(greeting));
+        }
+
+        let lost = |e: io::Error| vec![Outcome::Deferred(format!("connection to {} lost: {}", peer, e)); recipients.len()];
+        let mut extensions = match self.hello(&mut connection) {
+            Ok(Ok(extensions)) => extensions,
+            Ok(Err(reply)) => return Ok(vec![reply.outcome(&peer); recipients.len()]),
+            Err(e) => return Ok(lost(e)),
+        };
+        if starttls && extensions.has("STARTTLS") {
+            match command(&mut connection, "STARTTLS") {
+                Ok(reply) if reply.code == 220 => {
+                    connection = connection.connect(&self.tls, host).map_err(Failure::Tls)?;
+                    debug!("队列 #{} 与 {} 的连接已加密", entry.id, peer);
+                    extensions = match self.hello(&mut connection) {
+                        Ok(Ok(extensions)) => extensions,
+                        Ok(Err(reply)) => return Ok(vec![reply.outcome(&peer); recipients.len()]),
+                        Err(e) => return Ok(lost(e)),
+                    };
+                }
+                Ok(reply) => debug!("队列 #{} {} 拒绝了 STARTTLS：{} {}", entry.id, peer, reply.code, reply.lines.join(" ")),
+                Err(e) => return Ok(lost(e)),
+            }
+        }
+
+        // 对方不支持 DSN 时不能转交 NOTIFY 参数，需要由本机发送 relayed 通知（RFC 3461 5.2.2）
+        let relayed = !extensions.has("DSN");
+        let mut outcomes = vec![None; recipients.len()];
+        let result = transaction(&mut connection, &extensions, &peer, entry, recipients, raw, &mut outcomes);
+        let _ = command(&mut connection, "QUIT");
+        let fallback = match result {
+            Ok(reply) => reply.map(|reply| reply.outcome(&peer)),
+            Err(e) => Some(Outcome::Deferred(format!("connection to {} lost: {}", peer, e))),
+        };
+        Ok(outcomes
+            .into_iter()
+            .map(|outcome| match outcome.or_else(|| fallback.clone()) {
+                Some(Outcome::Delivered(reply)) if relayed => Outcome::Relayed(reply),
+                Some(outcome) => outcome,
+                None => Outcome::Deferred(String::from("no response")),
+            })
+            .collect())
+    }
+
+    /// # EHLO，不支持时改用 HELO
+    /// 两者都被拒绝时返回 HELO 的应答。
+    fn hello(&self, connection: &mut Connection) -> io::Result<Result<Extensions, Reply>> {
+        let ehlo = command(connection, &format!("EHLO {}", self.hostname))?;
+        if ehlo.is_positive() {
+            return Ok(Ok(Extensions(ehlo.lines.into_iter().skip(1).collect())));
+        }
+        let helo = command(connection, &format!("HELO {}", self.hostname))?;
+        Ok(if helo.is_positive() { Ok(Extensions(Vec::new())) } else { Err(helo) })
+    }
+}
+
+/// # 邮件事务
+/// 拒绝的收件人与对方不支持所需扩展的收件人写入 outcomes，返回 DATA 的应答（其余收件人的结果），
+/// 所有收件人都被拒绝时返回 None。
+fn transaction(
+    connection: &mut Connection,
+    extensions: &Extensions,
+    peer: &str,
+    entry: &QueueEntry,
+    recipients: &[QueueRecipient],
+    raw: &[u8],
+    outcomes: &mut [Option<Outcome>],
+) -> io::Result<Option<Reply>> {
+    if !raw.is_ascii() && !extensions.has("8BITMIME") {
+        outcomes.fill(Some(Outcome::Failed(format!("5.6.3 {} does not support 8BITMIME, required for 8-bit content", peer))));
+        return Ok(None);
+    }
+    let smtputf8 = extensions.has("SMTPUTF8");
+    let unsupported = |address: &str| (!address.is_ascii() && !smtputf8).then(|| Outcome::Failed(format!("5.6.7 {} does not support SMTPUTF8, required for {}", peer, address)));
+    if let Some(outcome) = unsupported(&entry.sender) {
+        outcomes.fill(Some(outcome));
+        return Ok(None);
+    }
+    for (recipient, outcome) in recipients.iter().zip(outcomes.iter_mut()) {
+        *outcome = unsupported(&recipient.recipient);
+    }
+    if outcomes.iter().all(Option::is_some) {
+        return Ok(None);
+    }
+
+    let dsn = extensions.has("DSN");
+    let mut mail = format!("MAIL FROM:<{}>", entry.sender);
+    if extensions.has("SIZE") {
+        mail.push_str(&format!(" SIZE={}", raw.len()));
+    }
+    if !raw.is_ascii() {
+        mail.push_str(" BODY=8BITMIME");
+    }
+    if smtputf8 && (!entry.sender.is_ascii() || recipients.iter().any(|recipient| !recipient.recipient.is_ascii())) {
+        mail.push_str(" SMTPUTF8");
+    }
+    if dsn {
+        if let Some(ret) = &entry.dsn.ret {
+            mail.push_str(&format!(" RET={}", ret));
+        }
+        if let Some(envid) = &entry.dsn.envid {
+            mail.push_str(&format!(" ENVID={}", encode_xtext(envid)));
+        }
+    }
+    let reply = command(connection, &mail)?;
+    if !reply.is_positive() {
+        return Ok(Some(reply));
+    }
+
+    let mut accepted = 0;
+    for (recipient, outcome) in recipients.iter().zip(outcomes.iter_mut()) {
+        if outcome.is_some() {
+            continue;
+        }
+        let mut rcpt = format!("RCPT TO:<{}>", recipient.recipient);
+        if dsn {
+            if let Some(notify) = &recipient.dsn.notify {
+                rcpt.push_str(&format!(" NOTIFY={}", notify));
+            }
+            if let Some((kind, address)) = recipient.dsn.orcpt.as_deref().and_then(|orcpt| orcpt.split_once(';')) {
+                rcpt.push_str(&format!(" ORCPT={};{}", kind, encode_xtext(address)));
+            }
+        }
+        let reply = command(connection, &rcpt)?;
+        if reply.is_positive() {
+            accepted += 1;
+        } else {
+            *outcome = Some(reply.outcome(peer));
+        }
+    }
+    if accepted == 0 {
+        let _ = command(connection, "RSET");
+        return Ok(None);
+    }
+
+    let reply = command(connection, "DATA")?;
+    if reply.code != 354 {
+        return Ok(Some(reply));
+    }
+    connection.send(&[dot_stuff(raw).as_slice(), b".\r\n"].concat())?;
+    connection.set_read_timeout(DATA_TIMEOUT)?;
+    let reply = read_reply(connection)?;
+    connection.set_read_timeout(TIMEOUT)?;
+    Ok(Some(reply))
+}
+
+/// # 发送一行命令并读取应答
+fn command(connection: &mut Connection, line: &str) -> io::Result<Reply> {
+    connection.send(format!("{}\r\n", line).as_bytes())?;
+    read_reply(connection)
+}
+
+/// # 读取应答
+/// 多行应答以 代码- 开头，最后一行以 代码空格 开头。
+fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = Vec::new();
+        if reader.take(MAX_REPLY_LINE).read_until(b'\n', &mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "连接已关闭"));
+        }
+        let line = String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string();
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok());
+        let (Some(code), separator) = (code, line.as_bytes().get(3).copied()) else {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("无效的应答 {}", line)));
+        };
+        lines.push(line.get(4..).unwrap_or_default().to_string());
+        if separator != Some(b'-') {
+            return Ok(Reply { code, lines });
+        }
+    }
+}
+
+/// # 转换为 DATA 格式
+/// 行尾统一为 CRLF，行首的 . 前再加一个 .（RFC 5321 4.5.2）。
+fn dot_stuff(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len() + raw.len() / 64);
+    for line in raw.split_inclusive(|&byte| byte == b'\n') {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content.starts_with(b".") {
+            data.push(b'.');
+        }
+        data.extend_from_slice(content);
+        data.extend_from_slice(b"\r\n");
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::HashMap;
+    use std::io::{BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+
+    use crate::smtp::bounce;
+    use crate::storage::{Dsn, RecipientDsn};
+
+    /// 固定的查询结果，没有列出的域名不存在
+    #[derive(Default)]
+    struct Stub {
+        mx: HashMap<&'static str, Vec<(u16, String)>>,
+        ip: HashMap<&'static str, Vec<IpAddr>>,
+        /// 查询 MX 记录时返回的错误
+        error: Option<DnsError>,
+    }
+
+    impl Resolver for Stub {
+        fn mx(&self, domain: &str) -> Result<Vec<(u16, String)>, DnsError> {
+            if let Some(e) = &self.error {
+                return Err(e.clone());
+            }
+            self.mx.get(domain).cloned().ok_or_else(|| DnsError::NotFound(domain.to_string()))
+        }
+
+        fn ip(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+            Ok(self.ip.get(host).cloned().unwrap_or_default())
+        }
+    }
+
+    impl Stub {
+        fn mx(mut self, domain: &'static str, records: &[(u16, &str)]) -> Stub {
+            self.mx.insert(domain, records.iter().map(|(preference, host)| (*preference, host.to_string())).collect());
+            self
+        }
+
+        fn ip(mut self, host: &'static str, ip: &str) -> Stub {
+            self.ip.entry(host).or_default().push(ip.parse().unwrap());
+            self
+        }
+    }
+
+    /// # 本机回环地址上的假邮件服务器
+    /// 所有地址使用同一个端口，返回端口与每个地址的监听器。
+    fn listen(ips: &[&str]) -> (u16, Vec<TcpListener>) {
+        let first = TcpListener::bind((ips[0], 0)).unwrap();
+        let port = first.local_addr().unwrap().port();
+        let mut listeners = vec![first];
+        listeners.extend(ips[1..].iter().map(|ip| TcpListener::bind((*ip, port)).unwrap()));
+        (port, listeners)
+    }
+
+    /// # 接受 connections 个连接并按脚本应答
+    /// extensions 为 EHLO 应答中的扩展；STARTTLS 应答后发送非 TLS 数据，使握手失败。
+    /// 地址含有 reject 的收件人被拒绝。返回每个连接收到的命令。
+    fn peer(listener: TcpListener, connections: usize, extensions: &'static [&'static str]) -> JoinHandle<Vec<Vec<String>>> {
+        thread::spawn(move || {
+            (0..connections)
+                .map(|_| {
+                    let (stream, _) = listener.accept().unwrap();
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    let mut writer = stream;
+                    let mut commands = Vec::new();
+                    writer.write_all(b"220 peer.test ESMTP\r\n").unwrap();
+                    loop {
+                        let mut line = String::new();
+                        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                            break;
+                        }
+                        let line = line.trim_end().to_string();
+                        let verb = line.split([' ', ':']).next().unwrap_or_default().to_ascii_uppercase();
+                        commands.push(line.clone());
+                        let reply = match verb.as_str() {
+                            "EHLO" => {
+                                let lines: Vec<&str> = ["peer.test"].iter().chain(extensions).copied().collect();
+                                let last = lines.len() - 1;
+                                let separator = |index| if index == last { ' ' } else { '-' };
+                                lines.iter().enumerate().map(|(index, line)| format!("250{}{}\r\n", separator(index), line)).collect()
+                            }
+                            "STARTTLS" => {
+                                writer.write_all(b"220 2.0.0 go ahead\r\nnot a TLS record\r\n").unwrap();
+                                break;
+                            }
+                            "RCPT" if line.contains("reject") => String::from("550 5.1.1 no such user\r\n"),
+                            "DATA" => {
+                                writer.write_all(b"354 go ahead\r\n").unwrap();
+                                let mut body = String::new();
+                                while body != ".\r\n" {
+                                    body.clear();
+                                    reader.read_line(&mut body).unwrap();
+                                }
+                                String::from("250 2.0.0 queued\r\n")
+                            }
+                            "QUIT" => {
+                                writer.write_all(b"221 2.0.0 bye\r\n").unwrap();
+                                break;
+                            }
+                            _ => String::from("250 2.0.0 OK\r\n"),
+                        };
+                        writer.write_all(reply.as_bytes()).unwrap();
+                    }
+                    commands
+                })
+                .collect()
+        })
+    }
+
+    /// # 以 greeting 问候、对任何命令都应答 221 的假邮件服务器
+    /// 返回收到的命令。
+    fn busy(listener: TcpListener, greeting: &'static str) -> JoinHandle<Vec<String>> {
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut writer = stream.try_clone().unwrap();
+            writer.write_all(greeting.as_bytes()).unwrap();
+            let mut commands = Vec::new();
+            for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                commands.push(line);
+                writer.write_all(b"221 2.0.0 bye\r\n").unwrap();
+            }
+            commands
+        })
+    }
+
+    fn entry(recipients: &[&str]) -> QueueEntry {
+        let now = Utc::now();
+        QueueEntry {
+            id: 1,
+            sender: String::from("alice@example.com"),
+            dsn: Dsn { ret: Some(String::from("HDRS")), envid: Some(String::from("e 1")) },
+            created_at: now,
+            warned: false,
+            held: false,
+            recipients: recipients
+                .iter()
+                .enumerate()
+                .map(|(index, recipient)| QueueRecipient {
+                    id: index as i64 + 1,
+                    recipient: recipient.to_string(),
+                    domain: String::from("remote.test"),
+                    attempts: 0,
+                    next_attempt: now,
+                    last_error: None,
+                    dsn: RecipientDsn { notify: Some(String::from("SUCCESS")), orcpt: None },
+                })
+                .collect(),
+        }
+    }
+
+    fn deliver(stub: Stub, port: u16, recipients: &[&str]) -> Vec<Outcome> {
+        let transport = SmtpTransport::new("mx.example.com", Arc::new(stub)).port(port);
+        let entry = entry(recipients);
+        transport.deliver("remote.test", &entry, &entry.recipients, b"Subject: hi\r\n\r\n.dot\r\n")
+    }
+
+    #[test]
+    fn mx_preference() {
+        // 优先级最高的服务器问候 421、第二台拒绝连接时尝试下一台
+        let (port, mut listeners) = listen(&["127.0.0.1", "127.0.0.2", "127.0.0.3"]);
+        drop(listeners.remove(2));
+        let primary = busy(listeners.remove(1), "421 4.3.2 busy, try again later\r\n");
+        let backup = peer(listeners.remove(0), 1, &["SIZE", "DSN"]);
+        let stub = Stub::default()
+            .mx("remote.test", &[(20, "backup.remote.test"), (10, "primary.remote.test"), (15, "secondary.remote.test"), (30, "backup.remote.test")])
+            .ip("primary.remote.test", "127.0.0.2")
+            .ip("secondary.remote.test", "127.0.0.3")
+            .ip("backup.remote.test", "127.0.0.1");
+        let outcomes = deliver(stub, port, &["bob@remote.test", "reject@remote.test"]);
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Delivered(String::from("backup.remote.test[127.0.0.1] said: 250 2.0.0 queued")),
+                Outcome::Failed(String::from("backup.remote.test[127.0.0.1] said: 550 5.1.1 no such user")),
+            ]
+        );
+        assert_eq!(primary.join().unwrap(), ["QUIT"]);
+        let commands = backup.join().unwrap();
+        assert_eq!(
+            commands[0],
+            [
+                "EHLO mx.example.com",
+                "MAIL FROM:<alice@example.com> SIZE=21 RET=HDRS ENVID=e+201",
+                "RCPT TO:<bob@remote.test> NOTIFY=SUCCESS",
+                "RCPT TO:<reject@remote.test> NOTIFY=SUCCESS",
+                "DATA",
+                "QUIT",
+            ]
+        );
+    }
+
+    #[test]
+    fn refused_greeting() {
+        // 所有服务器都拒绝服务时以最后一个问候语为结果
+        let (port, mut listeners) = listen(&["127.0.0.1", "127.0.0.2"]);
+        let primary = busy(listeners.remove(1), "421 4.3.2 busy\r\n");
+        let backup = busy(listeners.remove(0), "554 5.7.1 no service\r\n");
+        let stub = Stub::default()
+            .mx("remote.test", &[(10, "primary.remote.test"), (20, "backup.remote.test")])
+            .ip("primary.remote.test", "127.0.0.2")
+            .ip("backup.remote.test", "127.0.0.1");
+        let outcomes = deliver(stub, port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Failed(String::from("backup.remote.test[127.0.0.1] said: 554 5.7.1 no service"))]);
+        primary.join().unwrap();
+        backup.join().unwrap();
+    }
+
+    #[test]
+    fn eight_bit_and_smtputf8() {
+        let stub = || Stub::default().mx("remote.test", &[(10, "mx.remote.test")]).ip("mx.remote.test", "127.0.0.1");
+        let (port, mut listeners) = listen(&["127.0.0.1"]);
+        let server = peer(listeners.remove(0), 4, &["DSN"]);
+        let transport = SmtpTransport::new("mx.example.com", Arc::new(stub())).port(port);
+
+        // 不支持 8BITMIME 时 8 位内容永久失败，不发送 MAIL
+        let queued = entry(&["bob@remote.test"]);
+        let outcomes = transport.deliver("remote.test", &queued, &queued.recipients, "Subject: 你好\r\n\r\n正文\r\n".as_bytes());
+        assert_eq!(outcomes, [Outcome::Failed(String::from("5.6.3 mx.remote.test[127.0.0.1] does not support 8BITMIME, required for 8-bit content"))]);
+        assert_eq!(bounce::status(bounce::Action::Failed, "5.6.3 mx.remote.test[127.0.0.1] does not support 8BITMIME"), "5.6.3");
+
+        // 不支持 SMTPUTF8 时只有非 ASCII 的收件人失败
+        let queued = entry(&["用户@remote.test", "bob@remote.test"]);
+        let outcomes = transport.deliver("remote.test", &queued, &queued.recipients, b"Subject: hi\r\n\r\nbody\r\n");
+        assert_eq!(
+            outcomes,
+            [
+                Outcome::Failed(String::from("5.6.7 mx.remote.test[127.0.0.1] does not support SMTPUTF8, required for 用户@remote.test")),
+                Outcome::Delivered(String::from("mx.remote.test[127.0.0.1] said: 250 2.0.0 queued")),
+            ]
+        );
+
+        // 非 ASCII 的发件人使所有收件人失败
+        let mut queued = entry(&["bob@remote.test"]);
+        queued.sender = String::from("爱丽丝@example.com");
+        let outcomes = transport.deliver("remote.test", &queued, &queued.recipients, b"Subject: hi\r\n\r\nbody\r\n");
+        assert!(matches!(&outcomes[..], [Outcome::Failed(reason)] if reason.starts_with("5.6.7 ") && reason.ends_with("爱丽丝@example.com")));
+
+        // 全部收件人都无法投递时不开始邮件事务
+        let queued = entry(&["用户@remote.test"]);
+        let outcomes = transport.deliver("remote.test", &queued, &queued.recipients, b"Subject: hi\r\n\r\nbody\r\n");
+        assert!(matches!(&outcomes[..], [Outcome::Failed(reason)] if reason.starts_with("5.6.7 ")));
+
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0], ["EHLO mx.example.com", "QUIT"]);
+        assert_eq!(commands[1][1..3], ["MAIL FROM:<alice@example.com> RET=HDRS ENVID=e+201", "RCPT TO:<bob@remote.test> NOTIFY=SUCCESS"]);
+        assert_eq!(commands[1][3], "DATA");
+        assert_eq!(commands[2], ["EHLO mx.example.com", "QUIT"]);
+        assert_eq!(commands[3], ["EHLO mx.example.com", "QUIT"]);
+
+        // 支持时声明 BODY=8BITMIME 与 SMTPUTF8
+        let (port, mut listeners) = listen(&["127.0.0.1"]);
+        let server = peer(listeners.remove(0), 1, &["8BITMIME", "SMTPUTF8"]);
+        let transport = SmtpTransport::new("mx.example.com", Arc::new(stub())).port(port);
+        let queued = entry(&["用户@remote.test"]);
+        let outcomes = transport.deliver("remote.test", &queued, &queued.recipients, "Subject: 你好\r\n\r\n正文\r\n".as_bytes());
+        assert!(matches!(&outcomes[..], [Outcome::Relayed(_)]));
+        assert_eq!(server.join().unwrap()[0][1], "MAIL FROM:<alice@example.com> BODY=8BITMIME SMTPUTF8");
+    }
+
+    #[test]
+    fn implicit_mx() {
+        // 没有 MX 记录时连接域名本身的地址，对方不支持 DSN 时为 relayed
+        let (port, mut listeners) = listen(&["127.0.0.1"]);
+        let server = peer(listeners.remove(0), 1, &[]);
+        let stub = Stub::default().mx("remote.test", &[]).ip("remote.test", "127.0.0.1");
+        let outcomes = deliver(stub, port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Relayed(String::from("remote.test[127.0.0.1] said: 250 2.0.0 queued"))]);
+        assert_eq!(server.join().unwrap()[0][1], "MAIL FROM:<alice@example.com>");
+
+        // 域名本身也没有地址时永久失败
+        let outcomes = deliver(Stub::default().mx("remote.test", &[]), port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Failed(String::from("domain remote.test has no MX or address records"))]);
+        // MX 主机没有地址时稍后重试
+        let outcomes = deliver(Stub::default().mx("remote.test", &[(10, "mx.remote.test")]), port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Deferred(String::from("no address found for the mail servers of remote.test"))]);
+    }
+
+    #[test]
+    fn dns_failures() {
+        let outcomes = deliver(Stub::default().mx("remote.test", &[(0, "")]), 1, &["bob@remote.test", "carol@remote.test"]);
+        assert_eq!(outcomes, vec![Outcome::Failed(String::from("domain remote.test does not accept mail (null MX)")); 2]);
+
+        let outcomes = deliver(Stub::default(), 1, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Failed(String::from("domain remote.test does not exist"))]);
+
+        let stub = Stub { error: Some(DnsError::Temporary(String::from("timeout"))), ..Stub::default() };
+        let outcomes = deliver(stub, 1, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Deferred(String::from("DNS lookup for remote.test failed: timeout"))]);
+    }
+
+    #[test]
+    fn mail_loop() {
+        // 本机是最优先的 MX
+        let stub = Stub::default().mx("remote.test", &[(10, "MX.example.com"), (20, "backup.remote.test")]);
+        let outcomes = deliver(stub, 1, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Failed(String::from("mail for remote.test loops back to myself"))]);
+
+        // 只尝试优先级高于本机的 MX，不会投递给本机或优先级更低的备份服务器
+        let (port, mut listeners) = listen(&["127.0.0.1"]);
+        let server = peer(listeners.remove(0), 1, &["DSN"]);
+        let stub = Stub::default()
+            .mx("remote.test", &[(30, "backup.remote.test"), (20, "mx.example.com"), (10, "primary.remote.test")])
+            .ip("primary.remote.test", "127.0.0.1")
+            .ip("mx.example.com", "127.0.0.2")
+            .ip("backup.remote.test", "127.0.0.3");
+        let outcomes = deliver(stub, port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Delivered(String::from("primary.remote.test[127.0.0.1] said: 250 2.0.0 queued"))]);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn starttls_fallback() {
+        // 握手失败后以明文重新连接同一地址
+        let (port, mut listeners) = listen(&["127.0.0.1"]);
+        let server = peer(listeners.remove(0), 2, &["STARTTLS", "DSN"]);
+        let stub = Stub::default().mx("remote.test", &[(10, "mx.remote.test")]).ip("mx.remote.test", "127.0.0.1");
+        let outcomes = deliver(stub, port, &["bob@remote.test"]);
+        assert_eq!(outcomes, [Outcome::Delivered(String::from("mx.remote.test[127.0.0.1] said: 250 2.0.0 queued"))]);
+        let commands = server.join().unwrap();
+        assert_eq!(commands[0], ["EHLO mx.example.com", "STARTTLS"]);
+        assert_eq!(commands[1][..2], ["EHLO mx.example.com", "MAIL FROM:<alice@example.com> RET=HDRS ENVID=e+201"]);
+        assert_eq!(commands[1].last().map(String::as_str), Some("QUIT"));
+    }
+}
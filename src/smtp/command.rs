@@ -0,0 +1,165 @@
+/* 命令 zmou queue */
+/*
+# 发送队列管理
+## 用法
+zmou queue [list] [--sender <地址>] [--domain <域名>]     <-- 列出队列中的邮件
+zmou queue show 12                                    <-- 查看收件人与原邮件的邮件头
+zmou queue retry 12 / zmou queue flush                <-- 立即重试一封 / 所有邮件
+zmou queue hold 12 / zmou queue release 12            <-- 暂停 / 恢复投递
+zmou queue delete 12                                  <-- 删除一封邮件，不发送退信
+zmou queue delete --domain example.org                <-- 删除发往该域名的所有收件人
+## 说明
+修改在 ZitMail 的下一次队列扫描时生效（最多 30 秒）。
+ */
+use chrono::{DateTime, Utc};
+
+use super::bounce;
+use super::queue::{self, Filter};
+use crate::account::{AccountError, open};
+use crate::config::Config;
+use crate::storage::{QueueEntry, StorageError};
+use crate::utils::format_time;
+
+/// # 命令 zmou queue
+/// 列出队列中的邮件
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - filter: 筛选条件
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn list(config: &Config, filter: &Filter) -> Result<(), AccountError> {
+    let entries = queue::select(open(config)?.as_ref(), filter)?;
+    if entries.is_empty() {
+        println!("{}", if filter.is_empty() { "发送队列为空" } else { "没有符合条件的邮件" });
+        return Ok(());
+    }
+    let time_zone = &config.general.time_zone;
+    let mut recipients = 0;
+    for entry in &entries {
+        print_entry(entry, time_zone);
+        recipients += entry.recipients.len();
+    }
+    println!("共 {} 封邮件，{} 个收件人", entries.len(), recipients);
+    Ok(())
+}
+
+/// # 命令 zmou queue show
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - id: 队列编号
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn show(config: &Config, id: i64) -> Result<(), AccountError> {
+    let store = open(config)?;
+    let entry = queue::entry(store.as_ref(), id)?;
+    let raw = store.queue_raw(id)?.ok_or(StorageError::NotFound(format!("队列 #{}", id)))?;
+    print_entry(&entry, &config.general.time_zone);
+    if let Some(envid) = &entry.dsn.envid {
+        println!("  ENVID：{}", envid);
+    }
+    println!("  大小：{} 字节", raw.len());
+    println!();
+    print!("{}", String::from_utf8_lossy(bounce::headers(&raw)));
+    Ok(())
+}
+
+/// # 命令 zmou queue retry
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - id: 队列编号
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn retry(config: &Config, id: i64) -> Result<(), AccountError> {
+    let store = open(config)?;
+    let entry = queue::entry(store.as_ref(), id)?;
+    queue::retry(store.as_ref(), id)?;
+    info!("队列 #{} 将在下一次扫描时重试", id);
+    if entry.held {
+        warning!("队列 #{} 已暂停，使用 zmou queue release {} 恢复投递", id, id);
+    }
+    Ok(())
+}
+
+/// # 命令 zmou queue flush
+/// 立即重试所有未暂停的邮件
+pub fn flush(config: &Config) -> Result<(), AccountError> {
+    let count = queue::flush(open(config)?.as_ref())?;
+    info!("{} 封邮件将在下一次扫描时重试", count);
+    Ok(())
+}
+
+/// # 命令 zmou queue hold 与 zmou queue release
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - id: 队列编号
+/// - held: true 为暂停，false 为恢复
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn hold(config: &Config, id: i64, held: bool) -> Result<(), AccountError> {
+    queue::hold(open(config)?.as_ref(), id, held)?;
+    if held {
+        info!("已暂停投递队列 #{}", id);
+    } else {
+        info!("已恢复投递队列 #{}", id);
+    }
+    Ok(())
+}
+
+/// # 命令 zmou queue delete
+/// 不发送退信。
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// - id: 队列编号，为空时按 filter 删除
+/// - filter: 筛选条件
+/// ## 返回值
+/// - Result<(), AccountError>
+pub fn delete(config: &Config, id: Option<i64>, filter: &Filter) -> Result<(), AccountError> {
+    let store = open(config)?;
+    match id {
+        Some(id) => {
+            store.dequeue(id)?;
+            warning!("已从发送队列删除 #{}", id);
+        }
+        None if filter.is_empty() => {
+            return Err(AccountError::Invalid(String::from("请指定队列编号，或使用 --sender、--domain 指定删除条件")));
+        }
+        None => {
+            let count = queue::purge(store.as_ref(), filter)?;
+            warning!("已从发送队列删除 {} 个收件人", count);
+        }
+    }
+    Ok(())
+}
+
+/// # 输出一封邮件及其收件人
+fn print_entry(entry: &QueueEntry, time_zone: &str) {
+    let sender = if entry.sender.is_empty() { "<>" } else { entry.sender.as_str() };
+    let held = if entry.held { "  已暂停" } else { "" };
+    println!("#{}  {}  {}（{}前）{}", entry.id, sender, format_time(entry.created_at, time_zone), age(entry.created_at), held);
+    for recipient in &entry.recipients {
+        if recipient.attempts == 0 {
+            println!("  {}  等待投递", recipient.recipient);
+        } else {
+            println!(
+                "  {}  已尝试 {} 次，下次 {}",
+                recipient.recipient,
+                recipient.attempts,
+                format_time(recipient.next_attempt, time_zone)
+            );
+        }
+        if let Some(error) = &recipient.last_error {
+            println!("    {}", error);
+        }
+    }
+}
+
+/// # 已排队的时长
+/// 例如 5 分钟、3 小时 20 分钟、2 天 4 小时
+fn age(since: DateTime<Utc>) -> String {
+    let minutes = (Utc::now() - since).num_minutes().max(0);
+    match (minutes / 1440, minutes / 60 % 24, minutes % 60) {
+        (0, 0, minutes) => format!("{} 分钟", minutes),
+        (0, hours, minutes) => format!("{} 小时 {} 分钟", hours, minutes),
+        (days, hours, _) => format!("{} 天 {} 小时", days, hours),
+    }
+}
@@ -0,0 +1,210 @@
+/* SMTP 服务 */
+/*
+# SMTP 模块
+## 结构
+session.rs  协议状态机（RFC 5321），不涉及网络读写，逐行处理命令并返回应答
+server.rs   会话循环：按 listener.rs 接受的连接读写命令，处理超时
+queue.rs    发送队列，按收件人域名分组投递，失败后重试、发送延迟通知与退信
+client.rs   投递到其他邮件服务器的 SMTP 客户端
+bounce.rs   生成投递状态通知：退信、延迟通知与投递成功的通知
+report.rs   解析收到的投递状态通知，更新已发送邮件的投递状态
+command.rs  命令 zmou queue
+resolver.rs DNS 查询（MX、A、AAAA），可以替换为测试用的实现
+## 用法
+for (listener, mode) in smtp::server::listeners(&config.smtp) {
+    let handle = smtp::server::start(&config.smtp, listener, mode, tls.clone(), store.clone())?;   <-- 在后台线程中监听
+}
+let queue = smtp::queue::start(&config.queue, &config.smtp.hostname, store, transport)?;    <-- 退出前调用 queue.stop()
+## 扩展
+PIPELINING、SIZE、8BITMIME、SMTPUTF8、CHUNKING（BDAT）、ENHANCEDSTATUSCODES、DSN，
+提交端口另有 STARTTLS 与 AUTH。
+## 说明
+应答文本使用 US-ASCII（RFC 5321 4.2），日志使用中文。
+每个连接分配一个递增的编号，日志以「SMTP #编号」开头。
+ */
+use chrono::Utc;
+use std::fmt;
+
+use crate::event::{self, Event, Kind};
+use crate::mime::{self, MimeError, fields};
+use crate::storage::{Account, MailStore, StorageError};
+
+pub mod bounce;
+pub mod client;
+pub mod command;
+pub mod queue;
+pub mod report;
+pub mod resolver;
+pub mod server;
+pub mod session;
+
+/// 命令行长度上限（含 CRLF）
+/// RFC 5321 4.5.3.1.4 规定为 512，SIZE、DSN、SMTPUTF8 等扩展的参数需要额外的长度。
+pub const MAX_LINE: usize = 2048;
+
+/// SASL 响应的长度上限（含 CRLF，RFC 4954 4）
+pub const MAX_AUTH_LINE: usize = 12288;
+
+/// 单个事务的收件人上限（RFC 5321 4.5.3.1.8 要求至少 100）
+pub const MAX_RECIPIENTS: usize = 100;
+
+/// SMTP 应答
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub code: u16,
+    /// 增强状态码（RFC 3463），例如 2.1.5
+    pub status: Option<&'static str>,
+    pub lines: Vec<String>,
+}
+
+impl Reply {
+    /// # 单行应答
+    /// 不带增强状态码，用于问候语、EHLO、354 与 334。
+    /// ## 参数
+    /// - code: 应答码，例如 250
+    /// - text: 应答文本
+    /// ## 返回值
+    /// - Reply
+    pub fn new(code: u16, text: impl Into<String>) -> Reply {
+        Reply { code, status: None, lines: vec![text.into()] }
+    }
+
+    /// # 带增强状态码的单行应答
+    /// ## 参数
+    /// - code: 应答码，例如 550
+    /// - status: 增强状态码，例如 5.1.1
+    /// - text: 应答文本
+    /// ## 返回值
+    /// - Reply
+    pub fn enhanced(code: u16, status: &'static str, text: impl Into<String>) -> Reply {
+        Reply { code, status: Some(status), lines: vec![text.into()] }
+    }
+
+    /// # 多行应答
+    /// ## 参数
+    /// - code: 应答码
+    /// - lines: 每行的文本，至少一行
+    /// ## 返回值
+    /// - Reply
+    pub fn multiline(code: u16, lines: Vec<String>) -> Reply {
+        Reply { code, status: None, lines }
+    }
+
+    /// # 是否为肯定应答（2xx 或 3xx）
+    pub fn is_positive(&self) -> bool {
+        self.code < 400
+    }
+}
+
+/// 按照 SMTP 格式输出，每行以 CRLF 结尾
+impl fmt::Display for Reply {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let last = self.lines.len().saturating_sub(1);
+        for (index, line) in self.lines.iter().enumerate() {
+            let separator = if index == last { ' ' } else { '-' };
+            match self.status {
+                Some(status) => write!(f, "{}{}{} {}\r\n", self.code, separator, status, line)?,
+                None => write!(f, "{}{}{}\r\n", self.code, separator, line)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// # 解码 xtext（RFC 3461 4）
+/// 用于 DSN 参数 ENVID 与 ORCPT，+XX 表示 16 进制编码的字节。
+/// ## 参数
+/// - text: xtext
+/// ## 返回值
+/// - Option<String>，格式错误时为 None
+pub fn decode_xtext(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => {
+                let hex = text.get(index + 1..index + 3)?;
+                if !hex.bytes().all(|byte| byte.is_ascii_digit() || (b'A'..=b'F').contains(&byte)) {
+                    return None;
+                }
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+                index += 3;
+            }
+            b'!'..=b'~' if bytes[index] != b'=' => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+            _ => return None,
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// # 编码 xtext（RFC 3461 4）
+/// + 与 = 及可打印 ASCII 以外的字节编码为 +XX。
+/// ## 参数
+/// - text: 原文
+/// ## 返回值
+/// - String
+pub fn encode_xtext(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        match byte {
+            b'!'..=b'~' if byte != b'+' && byte != b'=' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("+{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// # 查找邮件头
+/// ## 参数
+/// - message: 邮件原文
+/// - name: 字段名，不区分大小写
+/// ## 返回值
+/// - Option<String>，有多个同名字段时为第一个
+pub fn header(message: &[u8], name: &str) -> Option<String> {
+    fields(bounce::headers(message)).into_iter().find(|(key, _)| key.eq_ignore_ascii_case(name)).map(|(_, value)| value)
+}
+
+/// # 邮件的 Message-ID
+/// ## 参数
+/// - message: 邮件原文，也可以只有邮件头
+/// ## 返回值
+/// - Option<String>，不含尖括号
+pub fn message_id(message: &[u8]) -> Option<String> {
+    let value = header(message, "Message-ID")?;
+    let id = value.trim().trim_start_matches('<').trim_end_matches('>').trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+/// # 保存到本机账号的 INBOX
+/// INBOX 被删除时重新创建。保存后发布新邮件事件，通知 IMAP IDLE 等待中的客户端。
+/// ## 参数
+/// - store: 邮件存储
+/// - account: 收件人账号
+/// - raw: 邮件原文，已包含追踪信息
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn deliver(store: &dyn MailStore, account: &Account, raw: &[u8]) -> Result<(), StorageError> {
+    let mut kinds = Vec::new();
+    let inbox = match store.mailbox(account.id, "INBOX")? {
+        Some(inbox) => inbox,
+        None => {
+            kinds.push(Kind::MailboxName(None));
+            store.create_mailbox(account.id, "INBOX")?
+        }
+    };
+    let errors = mime::parse(raw).errors();
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(MimeError::to_string).collect();
+        debug!("投递到 {} 的邮件格式有问题：{}", account.address(), errors.join("；"));
+    }
+    store.append(inbox.id, raw, &[], Utc::now())?;
+    kinds.push(Kind::MessageNew);
+    for kind in kinds {
+        event::publish(Event { account_id: account.id, mailbox_id: inbox.id, mailbox: inbox.name.clone(), kind, origin: 0 });
+    }
+    Ok(())
+}
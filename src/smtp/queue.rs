@@ -0,0 +1,577 @@
+/* 发送队列 */
+/*
+# 发送队列
+## 用法
+let queue = smtp::queue::start(&config.queue, &config.smtp.hostname, store, transport)?;
+smtp::queue::wake();   <-- 有新邮件加入队列时立即扫描，否则每 POLL_INTERVAL 扫描一次
+queue.stop();          <-- 等待正在进行的投递完成后返回
+smtp::queue::enqueue(store, sender, &dsn, &recipients, &raw)?;   <-- 加入队列并记录投递状态
+smtp::queue::retry(store, id)?;   <-- 管理操作，供 zmou queue 与 API 使用
+## 说明
+队列保存在数据库中，重启后继续投递。扫描线程将到期的收件人按 (邮件, 域名) 分组交给投递线程，
+本机域名直接保存到账号的 INBOX，其他域名通过 Transport 投递。
+临时失败按 RetryInterval × 2^attempts 重试，不超过 MaxRetryInterval；永久失败或超过 Lifetime 时退信。
+投递延迟超过 DelayWarning 时向发件人发送一次延迟通知，NOTIFY 包含 SUCCESS 时发送投递成功的通知。
+空发件人 <> 的邮件（退信本身）不会再产生退信，也不记录投递状态。
+每个收件人的投递状态记录在 deliveries 表中，保留 StatusRetention 天，见 API /delivery。
+暂停（hold）的邮件不会投递，也不会过期，直到恢复（release）。
+其他进程（例如 zmou queue retry）的修改在下一次扫描时生效。
+ */
+use chrono::{DateTime, Duration as TimeDelta, Utc};
+use crossbeam_channel::{Receiver, Sender, bounded, select, unbounded};
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use super::bounce::{self, Action};
+use super::{deliver, message_id};
+use crate::config;
+use crate::storage::{Delivery, Dsn, MailStore, QueueEntry, QueueRecipient, RecipientDsn, StorageError};
+
+/// 没有新邮件时扫描队列的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 清理过期投递状态的间隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+lazy_static! {
+    /// 唤醒扫描线程，容量为 1，多次唤醒合并为一次
+    static ref WAKE: (Sender<()>, Receiver<()>) = bounded(1);
+}
+
+/// 单个收件人的投递结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    /// 已投递，附带服务器的应答
+    Delivered(String),
+    /// 已投递到不支持 DSN 的服务器，附带服务器的应答
+    Relayed(String),
+    /// 临时失败，稍后重试，附带远程服务器的应答或错误
+    Deferred(String),
+    /// 永久失败，退信
+    Failed(String),
+}
+
+/// 投递到其他邮件服务器的方式
+pub trait Transport: Send + Sync {
+    /// # 投递到一个域名
+    /// ## 参数
+    /// - domain: 收件人域名
+    /// - entry: 队列中的邮件，提供发件人与 DSN 参数
+    /// - recipients: 该域名下需要投递的收件人
+    /// - raw: 邮件原文
+    /// ## 返回值
+    /// - Vec<Outcome>，与 recipients 一一对应
+    fn deliver(&self, domain: &str, entry: &QueueEntry, recipients: &[QueueRecipient], raw: &[u8]) -> Vec<Outcome>;
+}
+
+/// 一次投递任务：同一封邮件中同一域名的到期收件人
+struct Job {
+    entry: QueueEntry,
+    domain: String,
+}
+
+/// 扫描线程与投递线程共用的状态
+struct Shared {
+    config: config::Queue,
+    hostname: String,
+    store: Arc<dyn MailStore>,
+    transport: Arc<dyn Transport>,
+    /// 已交给投递线程、尚未完成的 (邮件, 域名)
+    running: Mutex<HashSet<(i64, String)>>,
+    stopping: AtomicBool,
+}
+
+/// 正在运行的发送队列
+pub struct Queue {
+    shared: Arc<Shared>,
+    stop: Sender<()>,
+    scheduler: JoinHandle<()>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+/// # 唤醒发送队列
+/// 邮件加入队列后调用，发送队列未启动时不执行任何操作。
+pub fn wake() {
+    let _ = WAKE.0.try_send(());
+}
+
+/// # 启动发送队列
+/// 创建一个扫描线程与 [Queue] Workers 个投递线程。
+/// ## 参数
+/// - config: [Queue]
+/// - hostname: 本机主机名，用于退信的发件人
+/// - store: 邮件存储
+/// - transport: 投递到其他邮件服务器的方式
+/// ## 返回值
+/// - io::Result<Queue>，无法创建线程时返回错误
+pub fn start(config: &config::Queue, hostname: &str, store: Arc<dyn MailStore>, transport: Arc<dyn Transport>) -> io::Result<Queue> {
+    let shared = Arc::new(Shared {
+        config: config.clone(),
+        hostname: hostname.to_string(),
+        store,
+        transport,
+        running: Mutex::new(HashSet::new()),
+        stopping: AtomicBool::new(false),
+    });
+    let (jobs, receiver) = unbounded::<Job>();
+    let mut workers = Vec::new();
+    for index in 0..config.workers {
+        let shared = Arc::clone(&shared);
+        let receiver = receiver.clone();
+        workers.push(thread::Builder::new().name(format!("queue-{}", index)).spawn(move || {
+            for job in receiver.iter() {
+                // 未执行的任务保留在数据库中，下次启动后继续
+                if shared.stopping.load(Ordering::SeqCst) {
+                    break;
+                }
+                let key = (job.entry.id, job.domain.clone());
+                process(&shared, job);
+                shared.running.lock().unwrap_or_else(|e| e.into_inner()).remove(&key);
+            }
+        })?);
+    }
+
+    let (stop, stopped) = bounded::<()>(1);
+    let scheduler = {
+        let shared = Arc::clone(&shared);
+        thread::Builder::new().name(String::from("queue")).spawn(move || {
+            let mut pruned: Option<Instant> = None;
+            loop {
+                if let Err(e) = scan(&shared, &jobs) {
+                    error!("无法读取发送队列：{}", e);
+                }
+                if pruned.is_none_or(|time| time.elapsed() >= PRUNE_INTERVAL) {
+                    prune(&shared);
+                    pruned = Some(Instant::now());
+                }
+                select! {
+                    recv(stopped) -> _ => break,
+                    recv(WAKE.1) -> _ => {}
+                    default(POLL_INTERVAL) => {}
+                }
+            }
+        })?
+    };
+    info!("发送队列已启动，{} 个投递线程", config.workers);
+    Ok(Queue { shared, stop, scheduler, workers })
+}
+
+impl Queue {
+    /// # 停止发送队列
+    /// 不再开始新的投递，等待正在进行的投递完成。
+    pub fn stop(self) {
+        self.shared.stopping.store(true, Ordering::SeqCst);
+        let _ = self.stop.send(());
+        let _ = self.scheduler.join();
+        let running = self.shared.running.lock().unwrap_or_else(|e| e.into_inner()).len();
+        if running > 0 {
+            info!("等待 {} 个投递任务完成", running);
+        }
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+        info!("发送队列已停止");
+    }
+}
+
+/// # 扫描队列
+/// 发送到期的延迟通知，并将到期的收件人交给投递线程。
+fn scan(shared: &Shared, jobs: &Sender<Job>) -> Result<(), StorageError> {
+    let now = Utc::now();
+    for entry in shared.store.queue()? {
+        if entry.held {
+            continue;
+        }
+        warn_delay(shared, &entry, now);
+
+        let due = |recipient: &&QueueRecipient| recipient.next_attempt <= now;
+        let mut domains: Vec<&str> = entry.recipients.iter().filter(due).map(|recipient| recipient.domain.as_str()).collect();
+        domains.sort_unstable();
+        domains.dedup();
+        for domain in domains {
+            let key = (entry.id, domain.to_string());
+            if !shared.running.lock().unwrap_or_else(|e| e.into_inner()).insert(key) {
+                continue;
+            }
+            let recipients = entry.recipients.iter().filter(due).filter(|recipient| recipient.domain == domain).cloned().collect();
+            let job = Job { entry: QueueEntry { recipients, ..entry.clone() }, domain: domain.to_string() };
+            if jobs.send(job).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// # 删除超过 StatusRetention 天的投递状态
+fn prune(shared: &Shared) {
+    let before = Utc::now() - TimeDelta::days(shared.config.status_retention as i64);
+    match shared.store.prune_deliveries(before) {
+        Ok(0) => {}
+        Ok(count) => debug!("已删除 {} 条过期的投递状态", count),
+        Err(e) => error!("无法删除过期的投递状态：{}", e),
+    }
+}
+
+/// # 投递一个任务并记录结果
+fn process(shared: &Shared, job: Job) {
+    let Job { entry, domain } = job;
+    let raw = match shared.store.queue_raw(entry.id) {
+        Ok(Some(raw)) => raw,
+        // 已被删除
+        Ok(None) => return,
+        Err(e) => {
+            error!("队列 #{} 无法读取邮件：{}", entry.id, e);
+            return;
+        }
+    };
+    let local = match shared.store.has_domain(&domain) {
+        Ok(local) => local,
+        Err(e) => {
+            error!("队列 #{} 无法查询域名 {}：{}", entry.id, domain, e);
+            return;
+        }
+    };
+    let outcomes = match local {
+        true => deliver_local(shared, &entry, &raw),
+        false => shared.transport.deliver(&domain, &entry, &entry.recipients, &raw),
+    };
+
+    let now = Utc::now();
+    let expired = now - entry.created_at >= TimeDelta::hours(shared.config.lifetime as i64);
+    let mut failed = Vec::new();
+    let mut succeeded = Vec::new();
+    for (recipient, outcome) in entry.recipients.iter().zip(outcomes) {
+        let result = match outcome {
+            Outcome::Delivered(reply) if local => {
+                info!("队列 #{} 已投递到 {}", entry.id, recipient.recipient);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, Action::Delivered.as_str(), Some(&reply));
+                succeeded.push((recipient, reply));
+                shared.store.complete(recipient.id)
+            }
+            Outcome::Delivered(reply) => {
+                info!("队列 #{} 已投递到 {}", entry.id, recipient.recipient);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, "sent", Some(&reply));
+                shared.store.complete(recipient.id)
+            }
+            Outcome::Relayed(reply) => {
+                info!("队列 #{} 已投递到 {}，对方不支持 DSN", entry.id, recipient.recipient);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, Action::Relayed.as_str(), Some(&reply));
+                succeeded.push((recipient, reply));
+                shared.store.complete(recipient.id)
+            }
+            Outcome::Deferred(reason) if expired => {
+                warning!("队列 #{} 超过最长保留时间，放弃投递到 {}：{}", entry.id, recipient.recipient, reason);
+                let reason = format!("{}, last error: {}", bounce::EXPIRED, reason);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, Action::Failed.as_str(), Some(&reason));
+                failed.push((recipient, reason));
+                shared.store.complete(recipient.id)
+            }
+            Outcome::Deferred(reason) => {
+                let next_attempt = now + backoff(&shared.config, recipient.attempts);
+                info!("队列 #{} 暂时无法投递到 {}，第 {} 次重试：{}", entry.id, recipient.recipient, recipient.attempts + 1, reason);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, Action::Delayed.as_str(), Some(&reason));
+                shared.store.defer(recipient.id, next_attempt, &reason)
+            }
+            Outcome::Failed(reason) => {
+                warning!("队列 #{} 无法投递到 {}：{}", entry.id, recipient.recipient, reason);
+                track(shared.store.as_ref(), &entry, &raw, &recipient.recipient, Action::Failed.as_str(), Some(&reason));
+                failed.push((recipient, reason));
+                shared.store.complete(recipient.id)
+            }
+        };
+        if let Err(e) = result {
+            error!("队列 #{} 无法更新收件人 {}：{}", entry.id, recipient.recipient, e);
+        }
+    }
+
+    let failed: Vec<(&QueueRecipient, String)> = failed.into_iter().filter(|(recipient, _)| notifies(recipient, "FAILURE")).collect();
+    if !failed.is_empty() {
+        notify(shared, &entry, bounce::failure(&shared.hostname, &entry, &failed, &raw), "退信");
+    }
+    let succeeded: Vec<(&QueueRecipient, String)> =
+        succeeded.into_iter().filter(|(recipient, _)| notifies(recipient, "SUCCESS")).collect();
+    if !succeeded.is_empty() {
+        let action = if local { Action::Delivered } else { Action::Relayed };
+        notify(shared, &entry, bounce::success(&shared.hostname, &entry, action, &succeeded, &raw), "投递成功通知");
+    }
+}
+
+/// # 投递到本机账号
+/// 添加 Return-Path，别名解析为对应的账号。
+fn deliver_local(shared: &Shared, entry: &QueueEntry, raw: &[u8]) -> Vec<Outcome> {
+    let raw = [format!("Return-Path: <{}>\r\n", entry.sender).as_bytes(), raw].concat();
+    entry
+        .recipients
+        .iter()
+        .map(|recipient| {
+            let result = shared.store.resolve(&recipient.recipient).and_then(|account| match account {
+                Some(account) => deliver(shared.store.as_ref(), &account, &raw).map(|_| true),
+                None => Ok(false),
+            });
+            match result {
+                Ok(true) => Outcome::Delivered(String::from("delivered to mailbox")),
+                Ok(false) => Outcome::Failed(format!("550 5.1.1 <{}>: no such user", recipient.recipient)),
+                Err(e) => {
+                    error!("队列 #{} 无法保存发往 {} 的邮件：{}", entry.id, recipient.recipient, e);
+                    Outcome::Deferred(String::from("451 4.3.0 local error in processing"))
+                }
+            }
+        })
+        .collect()
+}
+
+/// # 发送延迟通知
+/// 每封邮件只发送一次，仅通知已经尝试过投递的收件人。
+fn warn_delay(shared: &Shared, entry: &QueueEntry, now: DateTime<Utc>) {
+    let delay = shared.config.delay_warning;
+    if delay == 0 || entry.warned || entry.sender.is_empty() || now - entry.created_at < TimeDelta::hours(delay as i64) {
+        return;
+    }
+    let pending: Vec<&QueueRecipient> = entry.recipients.iter().filter(|recipient| recipient.attempts > 0).collect();
+    if pending.is_empty() {
+        return;
+    }
+    let delayed: Vec<(&QueueRecipient, String)> = pending
+        .into_iter()
+        .filter(|recipient| notifies(recipient, "DELAY"))
+        .map(|recipient| (recipient, recipient.last_error.clone().unwrap_or_default()))
+        .collect();
+    if !delayed.is_empty() {
+        let expires = entry.created_at + TimeDelta::hours(shared.config.lifetime as i64);
+        let raw = match shared.store.queue_raw(entry.id) {
+            Ok(Some(raw)) => raw,
+            Ok(None) => return,
+            Err(e) => {
+                error!("队列 #{} 无法读取邮件：{}", entry.id, e);
+                return;
+            }
+        };
+        notify(shared, entry, bounce::delay(&shared.hostname, entry, &delayed, expires, &raw), "延迟通知");
+    }
+    if let Err(e) = shared.store.set_warned(entry.id) {
+        error!("队列 #{} 无法记录延迟通知：{}", entry.id, e);
+    }
+}
+
+/// # 将退信或延迟通知加入队列
+/// 使用空发件人，避免退信循环（RFC 5321 4.5.5）。
+fn notify(shared: &Shared, entry: &QueueEntry, message: Vec<u8>, kind: &str) {
+    if entry.sender.is_empty() {
+        debug!("队列 #{} 发件人为空，不发送{}", entry.id, kind);
+        return;
+    }
+    match enqueue(shared.store.as_ref(), "", &Dsn::default(), &[(entry.sender.clone(), RecipientDsn::default())], &message) {
+        Ok(id) => info!("队列 #{} 已向 {} 发送{}（队列 #{}）", entry.id, entry.sender, kind, id),
+        Err(e) => error!("队列 #{} 无法发送{}：{}", entry.id, kind, e),
+    }
+}
+
+/// # 加入发送队列
+/// 记录每个收件人的投递状态为 queued，然后唤醒发送队列。
+/// ## 参数
+/// - store: 邮件存储
+/// - sender: 发件人，空字符串表示空发件人 <>
+/// - dsn: 信封的 DSN 参数
+/// - recipients: 收件人与 DSN 参数
+/// - raw: 邮件原文
+/// ## 返回值
+/// - Result<i64, StorageError>，队列编号
+pub fn enqueue(store: &dyn MailStore, sender: &str, dsn: &Dsn, recipients: &[(String, RecipientDsn)], raw: &[u8]) -> Result<i64, StorageError> {
+    let id = store.enqueue(sender, dsn, recipients, raw)?;
+    let entry = QueueEntry {
+        id,
+        sender: sender.to_string(),
+        dsn: dsn.clone(),
+        created_at: Utc::now(),
+        warned: false,
+        held: false,
+        recipients: Vec::new(),
+    };
+    for (recipient, _) in recipients {
+        track(store, &entry, raw, recipient, "queued", None);
+    }
+    wake();
+    Ok(id)
+}
+
+/// # 记录收件人的投递状态
+/// 空发件人的邮件不记录。失败时只写日志，不影响投递。
+/// ## 参数
+/// - store: 邮件存储
+/// - entry: 队列中的邮件
+/// - raw: 邮件原文，用于读取 Message-ID
+/// - recipient: 收件人地址
+/// - action: 见 Delivery.action
+/// - reason: 失败原因或服务器的应答
+fn track(store: &dyn MailStore, entry: &QueueEntry, raw: &[u8], recipient: &str, action: &str, reason: Option<&str>) {
+    if entry.sender.is_empty() {
+        return;
+    }
+    let kind = match action {
+        "failed" => Action::Failed,
+        "delayed" => Action::Delayed,
+        _ => Action::Delivered,
+    };
+    let delivery = Delivery {
+        queue_id: entry.id,
+        envid: entry.dsn.envid.clone(),
+        message_id: message_id(raw),
+        sender: entry.sender.clone(),
+        recipient: recipient.to_string(),
+        action: action.to_string(),
+        status: reason.map(|reason| bounce::status(kind, reason)),
+        diagnostic: reason.map(str::to_string),
+        remote_mta: reason.and_then(bounce::remote_mta).map(str::to_string),
+        updated_at: Utc::now(),
+    };
+    if let Err(e) = store.set_delivery(&delivery) {
+        error!("队列 #{} 无法记录 {} 的投递状态：{}", entry.id, recipient, e);
+    }
+}
+
+/// # 收件人的 NOTIFY 参数是否包含指定条件
+/// 没有 NOTIFY 参数时通知失败与延迟（RFC 3461 4.1）。
+/// ## 参数
+/// - recipient: 收件人
+/// - condition: SUCCESS、FAILURE 或 DELAY
+/// ## 返回值
+/// - bool
+pub fn notifies(recipient: &QueueRecipient, condition: &str) -> bool {
+    match &recipient.dsn.notify {
+        Some(notify) => notify.split(',').any(|item| item == condition),
+        None => condition != "SUCCESS",
+    }
+}
+
+/// # 下次重试的间隔
+/// ## 参数
+/// - config: [Queue]
+/// - attempts: 已经失败的次数
+/// ## 返回值
+/// - TimeDelta
+fn backoff(config: &config::Queue, attempts: i32) -> TimeDelta {
+    let factor = 1u64 << attempts.clamp(0, 20);
+    let minutes = config.retry_interval.saturating_mul(factor).min(config.max_retry_interval);
+    TimeDelta::minutes(minutes as i64)
+}
+
+/// 按发件人或收件人域名筛选队列，均为空时匹配所有邮件
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub sender: Option<String>,
+    pub domain: Option<String>,
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.sender.is_none() && self.domain.is_none()
+    }
+
+    /// # 邮件是否匹配
+    /// 地址与域名不区分大小写，空发件人 <> 以空字符串表示。
+    pub fn matches(&self, entry: &QueueEntry) -> bool {
+        let sender = self.sender.as_ref().is_none_or(|sender| sender.trim_matches(['<', '>']).eq_ignore_ascii_case(&entry.sender));
+        sender && entry.recipients.iter().any(|recipient| self.matches_domain(recipient))
+    }
+
+    fn matches_domain(&self, recipient: &QueueRecipient) -> bool {
+        self.domain.as_ref().is_none_or(|domain| domain.eq_ignore_ascii_case(&recipient.domain))
+    }
+}
+
+/// # 列出队列中的邮件
+/// ## 参数
+/// - store: 邮件存储
+/// - filter: 筛选条件
+/// ## 返回值
+/// - Result<Vec<QueueEntry>, StorageError>
+pub fn select(store: &dyn MailStore, filter: &Filter) -> Result<Vec<QueueEntry>, StorageError> {
+    Ok(store.queue()?.into_iter().filter(|entry| filter.matches(entry)).collect())
+}
+
+/// # 查询队列中的一封邮件
+/// ## 参数
+/// - store: 邮件存储
+/// - id: 队列编号
+/// ## 返回值
+/// - Result<QueueEntry, StorageError>
+pub fn entry(store: &dyn MailStore, id: i64) -> Result<QueueEntry, StorageError> {
+    store.queue()?.into_iter().find(|entry| entry.id == id).ok_or(StorageError::NotFound(format!("队列 #{}", id)))
+}
+
+/// # 立即重试一封邮件
+/// 所有收件人的下次投递时间设为现在，暂停的邮件仍需恢复后才会投递。
+/// ## 参数
+/// - store: 邮件存储
+/// - id: 队列编号
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn retry(store: &dyn MailStore, id: i64) -> Result<(), StorageError> {
+    store.reschedule(id, Utc::now())?;
+    wake();
+    Ok(())
+}
+
+/// # 立即重试所有未暂停的邮件
+/// ## 参数
+/// - store: 邮件存储
+/// ## 返回值
+/// - Result<usize, StorageError>，重试的邮件数
+pub fn flush(store: &dyn MailStore) -> Result<usize, StorageError> {
+    let now = Utc::now();
+    let mut count = 0;
+    for entry in store.queue()?.into_iter().filter(|entry| !entry.held) {
+        match store.reschedule(entry.id, now) {
+            Ok(()) => count += 1,
+            // 已投递完成
+            Err(StorageError::NotFound(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    wake();
+    Ok(count)
+}
+
+/// # 暂停或恢复投递
+/// ## 参数
+/// - store: 邮件存储
+/// - id: 队列编号
+/// - held: true 为暂停，false 为恢复
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn hold(store: &dyn MailStore, id: i64, held: bool) -> Result<(), StorageError> {
+    store.set_held(id, held)?;
+    if !held {
+        wake();
+    }
+    Ok(())
+}
+
+/// # 按条件删除
+/// 不发送退信。指定域名时只删除该域名的收件人，邮件没有其他收件人时一并删除。
+/// ## 参数
+/// - store: 邮件存储
+/// - filter: 筛选条件，不能为空
+/// ## 返回值
+/// - Result<usize, StorageError>，删除的收件人数
+pub fn purge(store: &dyn MailStore, filter: &Filter) -> Result<usize, StorageError> {
+    let mut count = 0;
+    for entry in select(store, filter)? {
+        if filter.domain.is_none() {
+            count += entry.recipients.len();
+            store.dequeue(entry.id)?;
+            continue;
+        }
+        for recipient in entry.recipients.iter().filter(|recipient| filter.matches_domain(recipient)) {
+            store.complete(recipient.id)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
@@ -0,0 +1,239 @@
+/* 收到的投递状态通知 */
+/*
+# 关联投递状态通知
+## 用法
+if let Some(report) = report::parse(&raw) {
+    let updated = report::correlate(store, hostname, &recipients, &report)?;
+}
+## 说明
+其他邮件服务器发回的 multipart/report; report-type=delivery-status（RFC 3464、RFC 6522）
+按 Original-Envelope-Id（ENVID），或者所附原邮件头中的 Message-ID 找到已发送的邮件，
+再按 Final-Recipient 或 Original-Recipient 更新对应收件人的投递状态。
+通知必须发给原邮件的发件人，本机生成的通知（Reporting-MTA 为本机主机名）会被忽略。
+ */
+use chrono::Utc;
+
+use super::bounce::is_status;
+use super::message_id;
+use crate::mime::{self, fields};
+use crate::storage::{Delivery, DeliveryKey, MailStore, StorageError};
+
+/// 投递状态通知中的一个收件人
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recipient {
+    /// 不含地址类型，例如 manser@example.org
+    pub final_recipient: Option<String>,
+    pub original_recipient: Option<String>,
+    /// failed、delayed、delivered、relayed 或 expanded
+    pub action: String,
+    pub status: Option<String>,
+    pub diagnostic: Option<String>,
+    pub remote_mta: Option<String>,
+}
+
+/// 一封投递状态通知
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub reporting_mta: Option<String>,
+    pub envid: Option<String>,
+    /// 所附原邮件头中的 Message-ID，不含尖括号
+    pub message_id: Option<String>,
+    pub recipients: Vec<Recipient>,
+}
+
+/// # 解析投递状态通知
+/// ## 参数
+/// - raw: 收到的邮件原文
+/// ## 返回值
+/// - Option<Report>，不是投递状态通知或没有有效的收件人时为 None
+pub fn parse(raw: &[u8]) -> Option<Report> {
+    let message = mime::parse(raw);
+    let report_type = message.parameter("report-type")?;
+    if message.media_type != "multipart" || message.subtype != "report" || !report_type.eq_ignore_ascii_case("delivery-status") {
+        return None;
+    }
+
+    let mut report = Report::default();
+    for part in &message.children {
+        let media = format!("{}/{}", part.media_type, part.subtype);
+        match media.as_str() {
+            "message/delivery-status" | "message/global-delivery-status" => status_fields(&part.decode(), &mut report),
+            "text/rfc822-headers" | "message/rfc822" | "message/global-headers" | "message/global" => {
+                report.message_id = message_id(&part.decode());
+            }
+            _ => {}
+        }
+    }
+    (!report.recipients.is_empty()).then_some(report)
+}
+
+/// # 更新对应收件人的投递状态
+/// ## 参数
+/// - store: 邮件存储
+/// - hostname: 本机主机名
+/// - recipients: 通知的收件人，即原邮件的发件人
+/// - report: 投递状态通知
+/// ## 返回值
+/// - Result<usize, StorageError>，更新的收件人数
+pub fn correlate(store: &dyn MailStore, hostname: &str, recipients: &[&str], report: &Report) -> Result<usize, StorageError> {
+    if report.reporting_mta.as_deref().is_some_and(|mta| mta.eq_ignore_ascii_case(hostname)) {
+        return Ok(0);
+    }
+    let key = match (&report.envid, &report.message_id) {
+        (Some(envid), _) => DeliveryKey::Envid(envid.clone()),
+        (None, Some(id)) => DeliveryKey::MessageId(id.clone()),
+        (None, None) => return Ok(0),
+    };
+    let deliveries: Vec<Delivery> = store
+        .deliveries(&key)?
+        .into_iter()
+        .filter(|delivery| recipients.iter().any(|recipient| recipient.eq_ignore_ascii_case(&delivery.sender)))
+        .collect();
+
+    let mut updated = 0;
+    for item in &report.recipients {
+        let matches = |address: &Option<String>, delivery: &Delivery| {
+            address.as_ref().is_some_and(|address| address.eq_ignore_ascii_case(&delivery.recipient))
+        };
+        let found = deliveries.iter().find(|delivery| matches(&item.final_recipient, delivery) || matches(&item.original_recipient, delivery));
+        let Some(delivery) = found else {
+            continue;
+        };
+        store.set_delivery(&Delivery {
+            action: item.action.clone(),
+            status: item.status.clone(),
+            diagnostic: item.diagnostic.clone(),
+            remote_mta: item.remote_mta.clone().or_else(|| report.reporting_mta.clone()),
+            updated_at: Utc::now(),
+            ..delivery.clone()
+        })?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+/// # 解析 message/delivery-status 的正文
+/// 第一个字段组描述邮件，之后每组描述一个收件人（RFC 3464 2.1）。
+fn status_fields(body: &[u8], report: &mut Report) {
+    let text = String::from_utf8_lossy(body).replace("\r\n", "\n");
+    let mut groups = text.split("\n\n").map(|group| fields(group.trim_start_matches('\n').as_bytes())).filter(|group| !group.is_empty());
+    let Some(message) = groups.next() else {
+        return;
+    };
+    for (name, value) in message {
+        match name.to_ascii_lowercase().as_str() {
+            "reporting-mta" => report.reporting_mta = Some(typed(&value).to_string()),
+            "original-envelope-id" => report.envid = super::decode_xtext(&value).or(Some(value)),
+            _ => {}
+        }
+    }
+    for group in groups {
+        let mut recipient = Recipient::default();
+        for (name, value) in group {
+            match name.to_ascii_lowercase().as_str() {
+                "final-recipient" => recipient.final_recipient = Some(address(&value)),
+                "original-recipient" => recipient.original_recipient = Some(address(&value)),
+                "action" => recipient.action = value.to_ascii_lowercase(),
+                "status" => recipient.status = value.split_whitespace().next().filter(|status| is_status(status)).map(str::to_string),
+                "diagnostic-code" => recipient.diagnostic = Some(typed(&value).to_string()),
+                "remote-mta" => recipient.remote_mta = Some(typed(&value).to_string()),
+                _ => {}
+            }
+        }
+        let valid = matches!(recipient.action.as_str(), "failed" | "delayed" | "delivered" | "relayed" | "expanded");
+        if valid && recipient.final_recipient.is_some() {
+            report.recipients.push(recipient);
+        }
+    }
+}
+
+/// # 去掉字段值的类型
+/// 例如 dns; mx.example.org 为 mx.example.org
+fn typed(value: &str) -> &str {
+    value.split_once(';').map_or(value, |(_, value)| value).trim()
+}
+
+/// # 收件人字段中的地址
+/// 例如 rfc822; <Manser@Example.org> 为 Manser@Example.org
+fn address(value: &str) -> String {
+    typed(value).trim_matches(['<', '>']).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::memory::MemoryStore;
+
+    const REPORT: &str = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/corpus/delivery-report.eml"));
+
+    /// # 已发送邮件的投递状态
+    fn sent(queue_id: i64, envid: Option<&str>, recipient: &str) -> Delivery {
+        Delivery {
+            queue_id,
+            envid: envid.map(str::to_string),
+            message_id: Some(String::from("hello-1@example.org")),
+            sender: String::from("sender@example.org"),
+            recipient: recipient.to_string(),
+            action: String::from("sent"),
+            status: None,
+            diagnostic: None,
+            remote_mta: Some(String::from("mx.example.net")),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn corpus_report() {
+        let report = parse(REPORT.as_bytes()).unwrap();
+        assert_eq!(report.reporting_mta.as_deref(), Some("mx.example.net"));
+        assert_eq!((report.envid, report.message_id.as_deref()), (None, Some("hello-1@example.org")));
+        assert_eq!(report.recipients, [Recipient {
+            final_recipient: Some(String::from("nobody@example.net")),
+            original_recipient: None,
+            action: String::from("failed"),
+            status: Some(String::from("5.1.1")),
+            diagnostic: Some(String::from("550 5.1.1 User unknown")),
+            remote_mta: None,
+        }]);
+
+        // 不是投递状态通知
+        assert!(parse(REPORT.replace("report-type=delivery-status", "report-type=disposition-notification").as_bytes()).is_none());
+        assert!(parse(b"Subject: hi\r\n\r\nhello\r\n").is_none());
+    }
+
+    #[test]
+    fn correlate_by_message_id() {
+        let store = MemoryStore::new();
+        store.set_delivery(&sent(1, None, "nobody@example.net")).unwrap();
+        store.set_delivery(&sent(1, None, "somebody@example.net")).unwrap();
+        let report = parse(REPORT.as_bytes()).unwrap();
+
+        // 通知必须发给原邮件的发件人，本机生成的通知被忽略
+        assert_eq!(correlate(&store, "mx.example.com", &["other@example.org"], &report).unwrap(), 0);
+        assert_eq!(correlate(&store, "mx.example.net", &["sender@example.org"], &report).unwrap(), 0);
+        assert_eq!(correlate(&store, "mx.example.com", &["Sender@Example.org"], &report).unwrap(), 1);
+
+        let deliveries = store.deliveries(&DeliveryKey::MessageId(String::from("hello-1@example.org"))).unwrap();
+        let failed = deliveries.iter().find(|delivery| delivery.recipient == "nobody@example.net").unwrap();
+        assert_eq!((failed.action.as_str(), failed.status.as_deref()), ("failed", Some("5.1.1")));
+        assert_eq!(failed.diagnostic.as_deref(), Some("550 5.1.1 User unknown"));
+        assert_eq!(failed.remote_mta.as_deref(), Some("mx.example.net"));
+        let other = deliveries.iter().find(|delivery| delivery.recipient == "somebody@example.net").unwrap();
+        assert_eq!((other.action.as_str(), other.status.as_deref()), ("sent", None));
+    }
+
+    #[test]
+    fn correlate_by_envid() {
+        let store = MemoryStore::new();
+        store.set_delivery(&sent(1, Some("envelope 1"), "nobody@example.net")).unwrap();
+        store.set_delivery(&sent(2, Some("envelope 2"), "nobody@example.net")).unwrap();
+        // ENVID 为 xtext，优先于 Message-ID
+        let raw = REPORT.replace("Reporting-MTA: dns; mx.example.net\r\n", "Reporting-MTA: dns; mx.example.net\r\nOriginal-Envelope-Id: envelope+202\r\n");
+        let report = parse(raw.as_bytes()).unwrap();
+        assert_eq!(report.envid.as_deref(), Some("envelope 2"));
+        assert_eq!(correlate(&store, "mx.example.com", &["sender@example.org"], &report).unwrap(), 1);
+
+        let action = |envid: &str| store.deliveries(&DeliveryKey::Envid(envid.to_string())).unwrap()[0].action.clone();
+        assert_eq!((action("envelope 1").as_str(), action("envelope 2").as_str()), ("sent", "failed"));
+    }
+}
@@ -0,0 +1,187 @@
+/* DNS 解析 */
+/*
+# 解析器
+## 用法
+let resolver = DnsResolver::new(&config.queue.nameserver)?;   <-- 留空时使用系统配置（/etc/resolv.conf）
+let hosts = resolver::mail_hosts(&resolver, "example.org", "mail.example.com")?;
+let addresses = resolver.ip("mx.example.org")?;
+## 说明
+投递时通过 Resolver 查询，测试时可以替换为本地的 DNS 服务器或固定的查询结果。
+ */
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::proto::op::ResponseCode;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use crate::utils::{is_valid_ipv4, is_valid_ipv6};
+
+/// DNS 端口
+const PORT: u16 = 53;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DnsError {
+    /// 域名不存在（NXDOMAIN）
+    NotFound(String),
+    /// 域名声明不接收邮件（RFC 7505 Null MX）
+    NullMx(String),
+    /// 最优先的 MX 记录指向本机
+    Loop(String),
+    /// 超时、服务器错误等，可以稍后重试
+    Temporary(String),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::NotFound(domain) => write!(f, "域名 {} 不存在", domain),
+            DnsError::NullMx(domain) => write!(f, "域名 {} 不接收邮件", domain),
+            DnsError::Loop(domain) => write!(f, "域名 {} 的 MX 记录指向本机", domain),
+            DnsError::Temporary(e) => write!(f, "DNS 查询失败：{}", e),
+        }
+    }
+}
+
+/// DNS 查询
+pub trait Resolver: Send + Sync {
+    /// # 查询 MX 记录
+    /// ## 参数
+    /// - domain: 域名
+    /// ## 返回值
+    /// - Result<Vec<(优先级, 主机)>, DnsError>，域名存在但没有 MX 记录时为空列表
+    fn mx(&self, domain: &str) -> Result<Vec<(u16, String)>, DnsError>;
+
+    /// # 查询 A 与 AAAA 记录
+    /// ## 参数
+    /// - host: 主机名
+    /// ## 返回值
+    /// - Result<Vec<IpAddr>, DnsError>，没有记录时为空列表
+    fn ip(&self, host: &str) -> Result<Vec<IpAddr>, DnsError>;
+}
+
+/// 通过 DNS 服务器查询
+pub struct DnsResolver {
+    resolver: hickory_resolver::Resolver,
+}
+
+impl DnsResolver {
+    /// # 创建解析器
+    /// ## 参数
+    /// - nameserver: [Queue] Nameserver，例如 127.0.0.1 或 127.0.0.1:5353，留空时使用系统配置
+    /// ## 返回值
+    /// - io::Result<DnsResolver>
+    pub fn new(nameserver: &str) -> io::Result<DnsResolver> {
+        let resolver = match parse_nameserver(nameserver) {
+            Some(address) => {
+                let servers = NameServerConfigGroup::from_ips_clear(&[address.ip()], address.port(), true);
+                let mut options = ResolverOpts::default();
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                hickory_resolver::Resolver::new(ResolverConfig::from_parts(None, Vec::new(), servers), options)?
+            }
+            None => {
+                let (config, mut options) = hickory_resolver::system_conf::read_system_conf()?;
+                options.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+                hickory_resolver::Resolver::new(config, options)?
+            }
+        };
+        Ok(DnsResolver { resolver })
+    }
+}
+
+impl Resolver for DnsResolver {
+    fn mx(&self, domain: &str) -> Result<Vec<(u16, String)>, DnsError> {
+        match self.resolver.mx_lookup(absolute(domain)) {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .map(|mx| (mx.preference(), mx.exchange().to_utf8().trim_end_matches('.').to_string()))
+                .collect()),
+            Err(e) => empty(e, domain),
+        }
+    }
+
+    fn ip(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        match self.resolver.lookup_ip(absolute(host)) {
+            Ok(lookup) => Ok(lookup.iter().collect()),
+            Err(e) => empty(e, host),
+        }
+    }
+}
+
+/// 使用完整域名查询，不附加搜索域
+fn absolute(name: &str) -> String {
+    format!("{}.", name.trim_end_matches('.'))
+}
+
+/// 区分「没有记录」、「域名不存在」与临时错误
+fn empty<T>(e: ResolveError, name: &str) -> Result<Vec<T>, DnsError> {
+    match e.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NXDomain, .. } => Err(DnsError::NotFound(name.to_string())),
+        ResolveErrorKind::NoRecordsFound { .. } => Ok(Vec::new()),
+        _ => Err(DnsError::Temporary(e.to_string())),
+    }
+}
+
+/// # 解析 [Queue] Nameserver
+/// ## 参数
+/// - nameserver: 例如 127.0.0.1、::1 或 [::1]:5353
+/// ## 返回值
+/// - Option<SocketAddr>，留空或格式错误时为 None
+pub fn parse_nameserver(nameserver: &str) -> Option<SocketAddr> {
+    let nameserver = nameserver.trim();
+    if is_valid_ipv4(nameserver) || is_valid_ipv6(nameserver) {
+        return nameserver.parse().ok().map(|ip| SocketAddr::new(ip, PORT));
+    }
+    nameserver.parse().ok()
+}
+
+/// # 地址字面量
+/// 例如 [192.0.2.1] 或 [IPv6:2001:db8::1]（RFC 5321 4.1.3），也接受不带方括号的 IP 地址。
+/// ## 参数
+/// - host: 域名或主机名
+/// ## 返回值
+/// - Option<IpAddr>
+pub fn literal(host: &str) -> Option<IpAddr> {
+    let host = host.strip_prefix('[').and_then(|host| host.strip_suffix(']')).unwrap_or(host);
+    if is_valid_ipv4(host) {
+        return host.parse().ok();
+    }
+    let host = host.strip_prefix("IPv6:").or_else(|| host.strip_prefix("ipv6:")).unwrap_or(host);
+    if is_valid_ipv6(host) {
+        return host.parse().ok();
+    }
+    None
+}
+
+/// # 收件人域名的邮件服务器
+/// 按 MX 优先级排序；没有 MX 记录时使用域名本身（RFC 5321 5.1 隐式 MX）。
+/// 优先级不高于本机的 MX 记录会被丢弃，避免投递回本机。
+/// ## 参数
+/// - resolver: DNS 查询
+/// - domain: 收件人域名
+/// - hostname: 本机主机名
+/// ## 返回值
+/// - Result<Vec<String>, DnsError>
+pub fn mail_hosts(resolver: &dyn Resolver, domain: &str, hostname: &str) -> Result<Vec<String>, DnsError> {
+    let mut records = resolver.mx(domain)?;
+    if records.is_empty() {
+        return Ok(vec![domain.to_string()]);
+    }
+    if records.iter().any(|(_, host)| host.is_empty() || host == ".") {
+        return Err(DnsError::NullMx(domain.to_string()));
+    }
+    records.sort_by_key(|(preference, _)| *preference);
+    if let Some(own) = records.iter().find(|(_, host)| host.eq_ignore_ascii_case(hostname)).map(|(preference, _)| *preference) {
+        records.retain(|(preference, _)| *preference < own);
+        if records.is_empty() {
+            return Err(DnsError::Loop(domain.to_string()));
+        }
+    }
+    let mut hosts: Vec<String> = Vec::new();
+    for (_, host) in records {
+        if !hosts.contains(&host) {
+            hosts.push(host);
+        }
+    }
+    Ok(hosts)
+}
@@ -0,0 +1,281 @@
+/* SMTP 监听 */
+use rustls::ServerConfig;
+use std::io::{self, BufRead, Read};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use super::session::{Mode, Next, Response, Session};
+use super::{MAX_AUTH_LINE, MAX_LINE, Reply};
+use crate::config::Smtp;
+use crate::listener::{self, Client, Counter, Line, Listener, Service, read_line};
+use crate::secret::MASK;
+use crate::storage::MailStore;
+use crate::tls::Connection;
+
+/// 等待客户端命令与数据的超时（RFC 5321 4.5.3.2 建议至少 5 分钟）
+const TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// 连接编号与连接数，所有端口共用
+static COUNTER: Counter = Counter::new();
+
+/// # 根据配置列出需要监听的端口
+/// ## 参数
+/// - config: [SMTP]
+/// ## 返回值
+/// - Vec<(Listener, Mode)>，端口与其接受的邮件
+pub fn listeners(config: &Smtp) -> Vec<(Listener, Mode)> {
+    let mut listeners = vec![(Listener { name: "SMTP", port: config.port, implicit_tls: false }, Mode::Relay)];
+    if config.submission {
+        listeners.push((Listener { name: "Submission", port: config.submission_port, implicit_tls: false }, Mode::Submission));
+    }
+    if config.submission && config.tls {
+        listeners.push((Listener { name: "Submissions", port: config.submissions_port, implicit_tls: true }, Mode::Submission));
+    }
+    listeners
+}
+
+/// # 启动 SMTP 服务
+/// ## 参数
+/// - config: [SMTP]
+/// - listener: 监听的端口
+/// - mode: 端口接受的邮件
+/// - tls: TLS 配置，未启用 TLS 时为 None
+/// - store: 邮件存储
+/// ## 返回值
+/// - io::Result<JoinHandle<()>>，端口无法绑定时返回错误
+pub fn start(
+    config: &Smtp,
+    listener: Listener,
+    mode: Mode,
+    tls: Option<Arc<ServerConfig>>,
+    store: Arc<dyn MailStore>,
+) -> io::Result<JoinHandle<()>> {
+    listener::start(&config.address, listener, tls, SmtpService { config: config.clone(), mode, store })
+}
+
+/// SMTP 的会话循环
+struct SmtpService {
+    config: Smtp,
+    mode: Mode,
+    store: Arc<dyn MailStore>,
+}
+
+impl Service for SmtpService {
+    const PROTOCOL: &'static str = "SMTP";
+    const TIMEOUT: Duration = TIMEOUT;
+
+    fn counter(&self) -> &'static Counter {
+        &COUNTER
+    }
+
+    fn busy(&self) -> Vec<u8> {
+        let reply = Reply::enhanced(421, "4.3.2", format!("{} too many connections, try again later", self.config.hostname));
+        reply.to_string().into_bytes()
+    }
+
+    fn serve(&self, connection: Connection, client: Client) -> io::Result<()> {
+        let session = Session::new(client.id, &self.config, client.peer, Arc::clone(&self.store), self.mode, client.state);
+        handle(connection, session, client.id, client.tls)
+    }
+}
+
+/// # 处理一个连接
+fn handle(mut connection: Connection, mut session: Session, id: u64, tls: Option<Arc<ServerConfig>>) -> io::Result<()> {
+    send(&mut connection, &session.greeting(), id)?;
+
+    loop {
+        let line = match read_line(&mut connection, MAX_LINE)? {
+            Line::Text(line) => text(&line),
+            Line::TooLong => {
+                send(&mut connection, &Reply::enhanced(500, "5.5.2", "Line too long"), id)?;
+                continue;
+            }
+            Line::Closed => return Ok(()),
+        };
+        debug!("SMTP #{} C: {}", id, redact(&line));
+
+        let mut response = session.command(&line);
+        loop {
+            if let Some(reply) = &response.reply {
+                send(&mut connection, reply, id)?;
+            }
+            match response.next {
+                Next::Continue => break,
+                Next::Close => return Ok(()),
+                Next::Data => {
+                    let reply = match read_data(&mut connection, session.max_size())? {
+                        Some(message) => session.data(message),
+                        None => session.abort(Reply::enhanced(552, "5.3.4", "Message size exceeds fixed maximum message size")),
+                    };
+                    send(&mut connection, &reply, id)?;
+                    break;
+                }
+                Next::Chunk { size, last } => {
+                    let chunk = read_chunk(&mut connection, size, session.chunk_limit())?;
+                    response = session.chunk(chunk, last);
+                }
+                Next::StartTls => {
+                    let Some(config) = &tls else { break };
+                    connection = connection.starttls(config)?;
+                    session.secured();
+                    break;
+                }
+                // SASL 响应包含密码或其摘要，不记录内容
+                Next::Auth => {
+                    response = match read_line(&mut connection, MAX_AUTH_LINE)? {
+                        Line::Text(line) => {
+                            debug!("SMTP #{} C: {}", id, MASK);
+                            session.auth(&text(&line))
+                        }
+                        Line::TooLong => Response {
+                            reply: Some(session.abort(Reply::enhanced(500, "5.5.6", "Authentication exchange line is too long"))),
+                            next: Next::Continue,
+                        },
+                        Line::Closed => return Ok(()),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn send(connection: &mut Connection, reply: &Reply, id: u64) -> io::Result<()> {
+    listener::send(connection, reply.to_string().as_bytes(), "SMTP", id)
+}
+
+/// # 命令行的文本
+/// 无效的 UTF-8 替换为 U+FFFD，去掉行尾多余的 CR。
+fn text(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).trim_end_matches('\r').to_string()
+}
+
+/// # 隐藏 AUTH 命令的初始响应
+fn redact(line: &str) -> String {
+    let mut words = line.splitn(3, ' ');
+    match (words.next(), words.next(), words.next()) {
+        (Some(verb), Some(mechanism), Some(_)) if verb.eq_ignore_ascii_case("AUTH") => format!("{} {} {}", verb, mechanism, MASK),
+        _ => line.to_string(),
+    }
+}
+
+/// # 读取 DATA 内容
+/// 以单独一行的 . 结束，去除行首多余的 .（RFC 5321 4.5.2），行尾统一为 CRLF。
+/// 超出上限时继续读取到结束标记，以便会话可以继续。
+/// ## 参数
+/// - reader: 输入
+/// - limit: 邮件大小上限
+/// ## 返回值
+/// - io::Result<Option<Vec<u8>>>，超出上限时为 None
+fn read_data(reader: &mut impl BufRead, limit: usize) -> io::Result<Option<Vec<u8>>> {
+    let mut message = Vec::new();
+    let mut exceeded = false;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.take(limit as u64 + 3).read_until(b'\n', &mut line)? == 0 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "DATA 未结束"));
+        }
+        let content = line.strip_suffix(b"\n").unwrap_or(&line);
+        let content = content.strip_suffix(b"\r").unwrap_or(content);
+        if content == b"." {
+            break;
+        }
+        if exceeded {
+            continue;
+        }
+        let content = content.strip_prefix(b".").unwrap_or(content);
+        message.extend_from_slice(content);
+        message.extend_from_slice(b"\r\n");
+        exceeded = message.len() > limit;
+    }
+    Ok(if exceeded { None } else { Some(message) })
+}
+
+/// # 读取 BDAT 数据块
+/// 数据块原样保存，不做任何转换（RFC 3030 2）。超出上限时读取并丢弃，以便会话可以继续。
+/// ## 参数
+/// - reader: 输入
+/// - size: BDAT 命令给出的字节数
+/// - limit: 本次最多可以接收的字节数
+/// ## 返回值
+/// - io::Result<Option<Vec<u8>>>，超出上限时为 None
+fn read_chunk(reader: &mut impl BufRead, size: usize, limit: usize) -> io::Result<Option<Vec<u8>>> {
+    let incomplete = || io::Error::new(io::ErrorKind::UnexpectedEof, "BDAT 数据不完整");
+    if size > limit {
+        if io::copy(&mut reader.take(size as u64), &mut io::sink())? < size as u64 {
+            return Err(incomplete());
+        }
+        return Ok(None);
+    }
+    let mut chunk = Vec::with_capacity(size);
+    if reader.take(size as u64).read_to_end(&mut chunk)? < size {
+        return Err(incomplete());
+    }
+    Ok(Some(chunk))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::default::CONFIG;
+    use crate::storage::memory::MemoryStore;
+    use crate::tls::{Stream, Tls};
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// # 一次写入全部命令（PIPELINING），返回服务器的全部输出
+    fn transcript(store: &Arc<dyn MailStore>, script: &[u8]) -> String {
+        let mut config = Config::parse(CONFIG).unwrap().smtp;
+        config.hostname = String::from("mx.example.com");
+        config.max_message_size = 1;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, peer) = listener.accept().unwrap();
+        let session = Session::new(1, &config, peer.ip(), Arc::clone(store), Mode::Relay, Tls::Unavailable);
+        let server = thread::spawn(move || handle(Connection::new(Stream::Plain(stream)), session, 1, None));
+
+        client.write_all(script).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut output = String::new();
+        client.read_to_string(&mut output).unwrap();
+        server.join().unwrap().unwrap();
+        output
+    }
+
+    #[test]
+    fn pipelined_transcript() {
+        let store: Arc<dyn MailStore> = Arc::new(MemoryStore::new());
+        store.add_domain("example.com").unwrap();
+        let bob = store.add_account("bob", "example.com", "", "").unwrap();
+
+        let script = b"EHLO client.test\r\n\
+                       MAIL FROM:<alice@remote.test>\r\n\
+                       RCPT TO:<nobody@example.com>\r\n\
+                       RCPT TO:<bob@example.com>\r\n\
+                       DATA\r\n\
+                       Subject: one\r\n\
+                       \r\n\
+                       ..dot\r\n\
+                       .\r\n\
+                       MAIL FROM:<alice@remote.test>\r\n\
+                       RCPT TO:<bob@example.com>\r\n\
+                       BDAT 5\r\n\
+                       line\nBDAT 3 LAST\r\n\
+                       .\r\n\
+                       NOOP\r\n\
+                       QUIT\r\n";
+        let output = transcript(&store, script);
+        let codes: Vec<&str> = output.lines().filter(|line| !line.starts_with("250-")).map(|line| &line[..3]).collect();
+        assert_eq!(codes, ["220", "250", "250", "550", "250", "354", "250", "250", "250", "250", "250", "250", "221"], "{}", output);
+
+        // DATA 去除行首多余的 .，BDAT 原样保存
+        let inbox = store.mailbox(bob.id, "INBOX").unwrap().unwrap();
+        let messages = store.messages(inbox.id).unwrap();
+        let raws: Vec<Vec<u8>> = messages.iter().map(|message| store.raw(message.id).unwrap().unwrap()).collect();
+        assert!(raws[0].ends_with(b"Subject: one\r\n\r\n.dot\r\n"));
+        assert!(raws[1].ends_with(b"line\n.\r\n"));
+    }
+}
@@ -0,0 +1,1183 @@
+/* SMTP 会话 */
+/*
+# 会话状态机
+## 用法
+let mut session = Session::new(id, &config.smtp, peer, store, Mode::Relay, Tls::Available);
+write(session.greeting());
+for line in lines {
+    let response = session.command(&line);
+    write(response.reply);                                  <-- BDAT 在读取数据后才有应答
+    match response.next {
+        Next::Continue => {}
+        Next::Data => write(session.data(读取到的邮件)),   <-- 读取 DATA 内容，去除行首的 .
+        Next::Chunk { size, last } => write(session.chunk(读取 size 字节, last).reply),   <-- 超出 session.chunk_limit() 时丢弃并传入 None
+        Next::StartTls => { 升级连接; session.secured(); }
+        Next::Auth => write(session.auth(读取到的一行).reply),   <-- 直到 next 不再是 Auth
+        Next::Close => break,
+    }
+}
+## 说明
+Mode::Relay      端口 25，只接收发往本机域名（zmou domain）且账号或别名存在的邮件，不提供转发；
+                 空发件人的投递状态通知用于更新已发送邮件的投递状态；
+                 收件人的 NOTIFY 包含 SUCCESS 时，保存后向发件人发送投递成功的通知；无法保存的收件人交给发送队列重试。
+Mode::Submission 提交端口，登录后可以发往任意地址，邮件加入发送队列。
+除问候语、EHLO、354 与 334 外，所有应答都带有增强状态码（RFC 2034）。
+ */
+use chrono::Utc;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use super::bounce::{self, Action};
+use super::{MAX_RECIPIENTS, Reply, decode_xtext, deliver, header, queue, report};
+use crate::config::Smtp;
+use crate::sasl::{Authenticator, Mechanism, SaslError, Step};
+use crate::storage::{Account, Dsn, MailStore, QueueEntry, QueueRecipient, RecipientDsn, StorageError, split_address};
+use crate::tls::Tls;
+use crate::utils::is_valid_address;
+
+/// 连续错误命令的上限，超过后断开连接
+const MAX_ERRORS: u32 = 10;
+
+/// ENVID 的长度上限（RFC 3461 4.4）
+const MAX_ENVID: usize = 100;
+
+/// 处理命令后的下一步
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Next {
+    /// 继续读取命令
+    Continue,
+    /// 读取邮件内容，然后调用 Session::data
+    Data,
+    /// 读取 size 字节的 BDAT 数据，然后调用 Session::chunk
+    Chunk { size: usize, last: bool },
+    /// 升级为 TLS，然后调用 Session::secured
+    StartTls,
+    /// 读取一行 SASL 响应，然后调用 Session::auth
+    Auth,
+    /// 关闭连接
+    Close,
+}
+
+#[derive(Debug)]
+pub struct Response {
+    /// 需要立即发送的应答，BDAT 为 None
+    pub reply: Option<Reply>,
+    pub next: Next,
+}
+
+impl Response {
+    fn reply(reply: Reply) -> Response {
+        Response { reply: Some(reply), next: Next::Continue }
+    }
+}
+
+/// 服务模式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// 接收其他邮件服务器投递的邮件
+    Relay,
+    /// 邮件提交（RFC 6409），需要登录
+    Submission,
+}
+
+/// 邮件事务，从 MAIL 开始，到 DATA 或 BDAT LAST 结束，或 RSET 为止
+#[derive(Debug)]
+struct Transaction {
+    /// 发件人，空字符串表示空发件人 <>（退信）
+    sender: String,
+    dsn: Dsn,
+    /// MAIL FROM 带有 SMTPUTF8 参数，允许非 ASCII 地址（RFC 6531）
+    utf8: bool,
+    recipients: Vec<Recipient>,
+    /// BDAT 已接收的内容，未使用 BDAT 时为 None
+    chunks: Option<Vec<u8>>,
+}
+
+#[derive(Debug)]
+struct Recipient {
+    address: String,
+    /// 本机账号，Mode::Submission 下不查找
+    account: Option<Account>,
+    dsn: RecipientDsn,
+}
+
+/// 收件人查找结果
+enum Lookup {
+    /// 本机账号
+    Local(Account),
+    /// 本机域名，但账号不存在
+    Unknown,
+    /// 不是本机域名
+    Remote,
+}
+
+pub struct Session {
+    id: u64,
+    hostname: String,
+    /// 邮件大小上限（字节）
+    max_size: usize,
+    peer: IpAddr,
+    store: Arc<dyn MailStore>,
+    mode: Mode,
+    tls: Tls,
+    /// HELO/EHLO 提供的客户端名称
+    helo: Option<String>,
+    extended: bool,
+    /// 已登录的账号
+    user: Option<Account>,
+    /// 正在进行的 SASL 认证
+    authenticator: Option<Authenticator>,
+    transaction: Option<Transaction>,
+    errors: u32,
+    /// 本连接已接收的邮件数
+    received: u32,
+}
+
+impl Session {
+    /// # 创建会话
+    /// ## 参数
+    /// - id: 连接编号
+    /// - config: [SMTP]
+    /// - peer: 客户端地址
+    /// - store: 邮件存储
+    /// - mode: 服务模式
+    /// - tls: 连接的加密状态
+    /// ## 返回值
+    /// - Session
+    pub fn new(id: u64, config: &Smtp, peer: IpAddr, store: Arc<dyn MailStore>, mode: Mode, tls: Tls) -> Session {
+        Session {
+            id,
+            hostname: config.hostname.clone(),
+            max_size: (config.max_message_size * 1024 * 1024) as usize,
+            peer,
+            store,
+            mode,
+            tls,
+            helo: None,
+            extended: false,
+            user: None,
+            authenticator: None,
+            transaction: None,
+            errors: 0,
+            received: 0,
+        }
+    }
+
+    /// # 问候语
+    pub fn greeting(&self) -> Reply {
+        Reply::new(220, format!("{} ESMTP ZitMail", self.hostname))
+    }
+
+    /// # 邮件大小上限（字节）
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// # 下一个 BDAT 数据块最多可以接收的字节数
+    /// 没有进行中的事务时为 0，数据块会被丢弃。
+    pub fn chunk_limit(&self) -> usize {
+        match &self.transaction {
+            Some(transaction) => self.max_size.saturating_sub(transaction.chunks.as_ref().map_or(0, Vec::len)),
+            None => 0,
+        }
+    }
+
+    /// # 处理一行命令
+    /// ## 参数
+    /// - line: 不含 CRLF 的命令行
+    /// ## 返回值
+    /// - Response
+    pub fn command(&mut self, line: &str) -> Response {
+        let (verb, argument) = match line.split_once(' ') {
+            Some((verb, argument)) => (verb, argument.trim()),
+            None => (line, ""),
+        };
+        let response = match verb.to_ascii_uppercase().as_str() {
+            "HELO" => self.helo(argument, false),
+            "EHLO" => self.helo(argument, true),
+            "MAIL" => self.mail(argument),
+            "RCPT" => self.rcpt(argument),
+            "DATA" => self.data_command(argument),
+            "BDAT" => self.bdat(argument),
+            "STARTTLS" => self.starttls(argument),
+            "AUTH" => self.auth_command(argument),
+            "RSET" if argument.is_empty() => {
+                self.transaction = None;
+                Response::reply(Reply::enhanced(250, "2.0.0", "OK"))
+            }
+            "NOOP" => Response::reply(Reply::enhanced(250, "2.0.0", "OK")),
+            "QUIT" => Response {
+                reply: Some(Reply::enhanced(221, "2.0.0", format!("{} closing connection", self.hostname))),
+                next: Next::Close,
+            },
+            "VRFY" if argument.is_empty() => Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: VRFY <address>")),
+            // 不透露账号是否存在，防止枚举
+            "VRFY" => Response::reply(Reply::enhanced(252, "2.0.0", "Cannot VRFY user, but will accept message and attempt delivery")),
+            "RSET" => Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: RSET")),
+            "EXPN" | "HELP" | "TURN" => Response::reply(Reply::enhanced(502, "5.5.1", "Command not implemented")),
+            _ => Response::reply(Reply::enhanced(500, "5.5.2", "Command unrecognized")),
+        };
+        self.tally(response)
+    }
+
+    /// # 连接已升级为 TLS
+    /// 丢弃升级前的所有状态，客户端需要重新发送 EHLO（RFC 3207 4.2）。
+    pub fn secured(&mut self) {
+        self.tls = Tls::Active;
+        self.helo = None;
+        self.extended = false;
+        self.user = None;
+        self.authenticator = None;
+        self.transaction = None;
+        debug!("SMTP #{} 已启用 TLS", self.id);
+    }
+
+    /// # 处理一行 SASL 响应
+    /// ## 参数
+    /// - line: 不含 CRLF 的 Base64 文本
+    /// ## 返回值
+    /// - Response
+    pub fn auth(&mut self, line: &str) -> Response {
+        let Some(mut authenticator) = self.authenticator.take() else {
+            return self.tally(Response::reply(Reply::enhanced(503, "5.5.1", "No authentication in progress")));
+        };
+        let step = authenticator.step(line);
+        let response = self.auth_step(authenticator, step);
+        self.tally(response)
+    }
+
+    /// 统计连续错误，超过上限后断开连接
+    fn tally(&mut self, response: Response) -> Response {
+        if response.reply.as_ref().is_none_or(Reply::is_positive) {
+            self.errors = 0;
+            return response;
+        }
+        self.errors += 1;
+        if self.errors >= MAX_ERRORS {
+            let reply = Reply::enhanced(421, "4.7.0", format!("{} too many errors, closing connection", self.hostname));
+            return Response { reply: Some(reply), next: Next::Close };
+        }
+        response
+    }
+
+    /// # 接收邮件内容
+    /// ## 参数
+    /// - message: DATA 读取到的邮件，已去除行首的 . 与结尾的 .CRLF
+    /// ## 返回值
+    /// - Reply
+    pub fn data(&mut self, message: Vec<u8>) -> Reply {
+        let Some(transaction) = self.transaction.take() else {
+            return Reply::enhanced(503, "5.5.1", "Bad sequence of commands");
+        };
+        self.finish(transaction, message)
+    }
+
+    /// # 接收 BDAT 数据块（RFC 3030）
+    /// ## 参数
+    /// - chunk: 读取到的数据，超出 chunk_limit() 而被丢弃时为 None
+    /// - last: 是否为最后一块
+    /// ## 返回值
+    /// - Response
+    pub fn chunk(&mut self, chunk: Option<Vec<u8>>, last: bool) -> Response {
+        let reply = match (&mut self.transaction, chunk) {
+            (None, _) => Reply::enhanced(503, "5.5.1", "Need MAIL command"),
+            (Some(transaction), _) if transaction.recipients.is_empty() => Reply::enhanced(503, "5.5.1", "Need RCPT command"),
+            (Some(_), None) => self.abort(Reply::enhanced(552, "5.3.4", "Message size exceeds fixed maximum message size")),
+            (Some(transaction), Some(chunk)) => {
+                let chunks = transaction.chunks.get_or_insert_with(Vec::new);
+                chunks.extend_from_slice(&chunk);
+                let received = chunks.len();
+                match self.transaction.take() {
+                    Some(mut transaction) if last => {
+                        let message = transaction.chunks.take().unwrap_or_default();
+                        self.finish(transaction, message)
+                    }
+                    transaction => {
+                        self.transaction = transaction;
+                        Reply::enhanced(250, "2.0.0", format!("{} octets received", received))
+                    }
+                }
+            }
+        };
+        self.tally(Response::reply(reply))
+    }
+
+    /// # 放弃当前事务
+    /// 用于 DATA 内容超出大小上限等情况。
+    /// ## 参数
+    /// - reply: 返回给客户端的应答
+    /// ## 返回值
+    /// - Reply
+    pub fn abort(&mut self, reply: Reply) -> Reply {
+        self.transaction = None;
+        self.authenticator = None;
+        reply
+    }
+
+    /// # 完成事务
+    /// 保存到本机账号，或者加入发送队列。
+    /// 已保存的收件人无法撤回，此时若客户端重试会重复投递，因此保存失败的收件人交给发送队列重试，
+    /// 只有全部收件人都无法保存、也无法加入队列时才返回临时错误。
+    fn finish(&mut self, transaction: Transaction, message: Vec<u8>) -> Reply {
+        self.received += 1;
+        if self.mode == Mode::Submission {
+            return self.submit(transaction, message);
+        }
+        let received = [self.trace(&transaction).as_bytes(), &message].concat();
+        let raw = [format!("Return-Path: <{}>\r\n", transaction.sender).as_bytes(), &received].concat();
+
+        let mut delivered = Vec::new();
+        let mut failed = Vec::new();
+        for recipient in &transaction.recipients {
+            let Some(account) = &recipient.account else { continue };
+            match deliver(self.store.as_ref(), account, &raw) {
+                Ok(()) => delivered.push(recipient),
+                Err(e) => {
+                    error!("SMTP #{} 无法保存发往 {} 的邮件：{}", self.id, account.address(), e);
+                    failed.push((recipient.address.clone(), recipient.dsn.clone()));
+                }
+            }
+        }
+        if !failed.is_empty() {
+            // 发送队列投递到本机账号时会添加 Return-Path
+            match queue::enqueue(self.store.as_ref(), &transaction.sender, &transaction.dsn, &failed, &received) {
+                Ok(queue_id) => info!("SMTP #{} 已将无法保存的 {} 个收件人加入发送队列 #{}", self.id, failed.len(), queue_id),
+                Err(e) if delivered.is_empty() => {
+                    error!("SMTP #{} 无法将邮件加入发送队列：{}", self.id, e);
+                    return Reply::enhanced(451, "4.3.0", "Requested action aborted: local error in processing");
+                }
+                Err(e) => {
+                    let addresses: Vec<&str> = failed.iter().map(|(address, _)| address.as_str()).collect();
+                    error!("SMTP #{} 无法将邮件加入发送队列，{} 未能收到邮件：{}", self.id, addresses.join("、"), e);
+                }
+            }
+        }
+
+        let recipients: Vec<&str> = transaction.recipients.iter().map(|recipient| recipient.address.as_str()).collect();
+        info!(
+            "SMTP #{} 已接收 <{}> 发往 {} 的邮件（{} 字节）",
+            self.id,
+            transaction.sender,
+            recipients.join("、"),
+            raw.len()
+        );
+        self.notify_delivered(&transaction, &delivered, &raw);
+        if transaction.sender.is_empty() {
+            self.correlate(&recipients, &message);
+        }
+        Reply::enhanced(250, "2.0.0", "OK message accepted for delivery")
+    }
+
+    /// # 发送投递成功的通知
+    /// 已保存到本机账号即为最终投递，NOTIFY 包含 SUCCESS 的收件人由本机发送通知（RFC 3461 5.2.2），与发送队列的本机投递相同。
+    fn notify_delivered(&self, transaction: &Transaction, delivered: &[&Recipient], raw: &[u8]) {
+        if transaction.sender.is_empty() {
+            return;
+        }
+        let now = Utc::now();
+        let recipients: Vec<QueueRecipient> = delivered
+            .iter()
+            .map(|recipient| QueueRecipient {
+                id: 0,
+                recipient: recipient.address.clone(),
+                domain: split_address(&recipient.address).unwrap_or_default().1,
+                attempts: 0,
+                next_attempt: now,
+                last_error: None,
+                dsn: recipient.dsn.clone(),
+            })
+            .filter(|recipient| queue::notifies(recipient, "SUCCESS"))
+            .collect();
+        if recipients.is_empty() {
+            return;
+        }
+        // 没有进入发送队列，编号使用连接编号，只用于生成 Message-ID
+        let entry = QueueEntry {
+            id: self.id as i64,
+            sender: transaction.sender.clone(),
+            dsn: transaction.dsn.clone(),
+            created_at: now,
+            warned: false,
+            held: false,
+            recipients: Vec::new(),
+        };
+        let reasons: Vec<(&QueueRecipient, String)> =
+            recipients.iter().map(|recipient| (recipient, String::from("delivered to mailbox"))).collect();
+        let report = bounce::success(&self.hostname, &entry, Action::Delivered, &reasons, raw);
+        match queue::enqueue(self.store.as_ref(), "", &Dsn::default(), &[(transaction.sender.clone(), RecipientDsn::default())], &report) {
+            Ok(id) => info!("SMTP #{} 已向 {} 发送投递成功通知（队列 #{}）", self.id, transaction.sender, id),
+            Err(e) => error!("SMTP #{} 无法发送投递成功通知：{}", self.id, e),
+        }
+    }
+
+    /// # 关联收到的投递状态通知
+    /// 更新已发送邮件的投递状态，见 report.rs。
+    fn correlate(&self, recipients: &[&str], message: &[u8]) {
+        let Some(report) = report::parse(message) else {
+            return;
+        };
+        match report::correlate(self.store.as_ref(), &self.hostname, recipients, &report) {
+            Ok(0) => debug!("SMTP #{} 收到的投递状态通知没有对应的已发送邮件", self.id),
+            Ok(count) => info!("SMTP #{} 收到投递状态通知，已更新 {} 个收件人的投递状态", self.id, count),
+            Err(e) => error!("SMTP #{} 无法更新投递状态：{}", self.id, e),
+        }
+    }
+
+    /// # 提交的邮件加入发送队列
+    /// 补充缺少的 Date 与 Message-ID 邮件头（RFC 6409 8）。
+    fn submit(&mut self, transaction: Transaction, message: Vec<u8>) -> Reply {
+        let mut headers = String::new();
+        if header(&message, "Date").is_none() {
+            headers.push_str(&format!("Date: {}\r\n", Utc::now().to_rfc2822()));
+        }
+        if header(&message, "Message-ID").is_none() {
+            let (_, domain) = split_address(&transaction.sender).unwrap_or_default();
+            headers.push_str(&format!("Message-ID: <{}.{}.{}@{}>\r\n", Utc::now().timestamp_millis(), self.id, self.received, domain));
+        }
+        let raw = [self.trace(&transaction).as_bytes(), headers.as_bytes(), &message].concat();
+
+        let recipients: Vec<(String, RecipientDsn)> =
+            transaction.recipients.into_iter().map(|recipient| (recipient.address, recipient.dsn)).collect();
+        match queue::enqueue(self.store.as_ref(), &transaction.sender, &transaction.dsn, &recipients, &raw) {
+            Ok(queue_id) => {
+                let addresses: Vec<&str> = recipients.iter().map(|(address, _)| address.as_str()).collect();
+                info!(
+                    "SMTP #{} 已将 <{}> 发往 {} 的邮件加入发送队列 #{}（{} 字节）",
+                    self.id,
+                    transaction.sender,
+                    addresses.join("、"),
+                    queue_id,
+                    raw.len()
+                );
+                Reply::enhanced(250, "2.0.0", format!("OK queued as {}", queue_id))
+            }
+            Err(e) => {
+                error!("SMTP #{} 无法将邮件加入发送队列：{}", self.id, e);
+                Reply::enhanced(451, "4.3.0", "Requested action aborted: local error in processing")
+            }
+        }
+    }
+
+    fn helo(&mut self, argument: &str, extended: bool) -> Response {
+        if argument.is_empty() {
+            let verb = if extended { "EHLO" } else { "HELO" };
+            return Response::reply(Reply::enhanced(501, "5.5.4", format!("Syntax: {} <hostname>", verb)));
+        }
+        self.helo = Some(argument.to_string());
+        self.extended = extended;
+        self.transaction = None;
+        debug!("SMTP #{} 客户端自称 {}", self.id, argument);
+        if !extended {
+            return Response::reply(Reply::new(250, self.hostname.clone()));
+        }
+
+        let mut lines = vec![format!("{} greets {}", self.hostname, argument)];
+        for extension in ["PIPELINING", "8BITMIME", "SMTPUTF8", "CHUNKING", "ENHANCEDSTATUSCODES", "DSN"] {
+            lines.push(extension.to_string());
+        }
+        lines.insert(2, format!("SIZE {}", self.max_size));
+        if self.tls == Tls::Available {
+            lines.push(String::from("STARTTLS"));
+        }
+        if self.mode == Mode::Submission && self.tls != Tls::Available {
+            lines.push(format!("AUTH {}", Mechanism::ALL.join(" ")));
+        }
+        Response::reply(Reply::multiline(250, lines))
+    }
+
+    fn mail(&mut self, argument: &str) -> Response {
+        if self.helo.is_none() {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Send HELO/EHLO first"));
+        }
+        if self.transaction.is_some() {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Nested MAIL command"));
+        }
+        let Some((sender, parameters)) = parse_path(argument, "FROM:") else {
+            return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: MAIL FROM:<address>"));
+        };
+        let parameters = match self.parameters(parameters, "MAIL FROM") {
+            Ok(parameters) => parameters,
+            Err(reply) => return Response::reply(reply),
+        };
+
+        let mut dsn = Dsn::default();
+        let mut utf8 = false;
+        for (keyword, value) in parameters {
+            match (keyword.as_str(), value) {
+                ("SIZE", Some(value)) => match value.parse::<u64>() {
+                    Ok(size) if size > self.max_size as u64 => {
+                        return Response::reply(Reply::enhanced(552, "5.3.4", "Message size exceeds fixed maximum message size"));
+                    }
+                    Ok(_) => {}
+                    Err(_) => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: SIZE=<size>")),
+                },
+                // RFC 6152，不支持 BINARYMIME
+                ("BODY", Some(value)) if value.eq_ignore_ascii_case("7BIT") || value.eq_ignore_ascii_case("8BITMIME") => {}
+                ("BODY", _) => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: BODY=7BIT|8BITMIME")),
+                ("SMTPUTF8", None) => utf8 = true,
+                ("RET", Some(value)) if value.eq_ignore_ascii_case("FULL") || value.eq_ignore_ascii_case("HDRS") => {
+                    dsn.ret = Some(value.to_ascii_uppercase());
+                }
+                ("RET", _) => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: RET=FULL|HDRS")),
+                ("ENVID", Some(value)) => match decode_xtext(&value) {
+                    Some(envid) if !envid.is_empty() && envid.len() <= MAX_ENVID => dsn.envid = Some(envid),
+                    _ => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: ENVID=<xtext>")),
+                },
+                // RFC 4954 5，不信任客户端提供的原始身份，忽略
+                ("AUTH", Some(_)) if self.mode == Mode::Submission => {}
+                (keyword, _) => {
+                    return Response::reply(Reply::enhanced(555, "5.5.4", format!("MAIL FROM parameter {} not recognized", keyword)));
+                }
+            }
+        }
+
+        if !sender.is_empty() && !is_valid_address(&sender) {
+            return Response::reply(Reply::enhanced(553, "5.1.7", "Invalid sender address"));
+        }
+        if !utf8 && !sender.is_ascii() {
+            return Response::reply(Reply::enhanced(553, "5.6.7", "Non-ASCII address requires SMTPUTF8"));
+        }
+        if self.mode == Mode::Submission {
+            let Some(user) = &self.user else {
+                return Response::reply(Reply::enhanced(530, "5.7.0", "Authentication required"));
+            };
+            // 只能使用自己的地址或别名
+            match self.store.resolve(&sender) {
+                Ok(Some(account)) if account.id == user.id => {}
+                Ok(_) => {
+                    warning!("SMTP #{} 账号 {} 尝试使用发件人 <{}>，已拒绝", self.id, user.address(), sender);
+                    return Response::reply(Reply::enhanced(553, "5.7.1", "Sender address not owned by authenticated user"));
+                }
+                Err(e) => {
+                    error!("SMTP #{} 无法查询发件人 {}：{}", self.id, sender, e);
+                    return Response::reply(Reply::enhanced(451, "4.3.0", "Requested action aborted: local error in processing"));
+                }
+            }
+        }
+        self.transaction = Some(Transaction { sender, dsn, utf8, recipients: Vec::new(), chunks: None });
+        Response::reply(Reply::enhanced(250, "2.1.0", "OK"))
+    }
+
+    fn rcpt(&mut self, argument: &str) -> Response {
+        let Some(utf8) = self.transaction.as_ref().map(|transaction| transaction.utf8) else {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Need MAIL command"));
+        };
+        let Some((recipient, parameters)) = parse_path(argument, "TO:") else {
+            return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: RCPT TO:<address>"));
+        };
+        let parameters = match self.parameters(parameters, "RCPT TO") {
+            Ok(parameters) => parameters,
+            Err(reply) => return Response::reply(reply),
+        };
+
+        let mut dsn = RecipientDsn::default();
+        for (keyword, value) in parameters {
+            match (keyword.as_str(), value) {
+                ("NOTIFY", Some(value)) => match parse_notify(&value) {
+                    Some(notify) => dsn.notify = Some(notify),
+                    None => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: NOTIFY=NEVER|SUCCESS,FAILURE,DELAY")),
+                },
+                ("ORCPT", Some(value)) => match parse_orcpt(&value) {
+                    Some(orcpt) => dsn.orcpt = Some(orcpt),
+                    None => return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: ORCPT=<addr-type>;<xtext>")),
+                },
+                (keyword, _) => {
+                    return Response::reply(Reply::enhanced(555, "5.5.4", format!("RCPT TO parameter {} not recognized", keyword)));
+                }
+            }
+        }
+
+        if !is_valid_address(&recipient) {
+            return Response::reply(Reply::enhanced(553, "5.1.3", "Invalid recipient address"));
+        }
+        if !utf8 && !recipient.is_ascii() {
+            return Response::reply(Reply::enhanced(553, "5.6.7", "Non-ASCII address requires SMTPUTF8"));
+        }
+        let lookup = match self.mode {
+            Mode::Relay => self.lookup(&recipient),
+            Mode::Submission => Ok(Lookup::Remote),
+        };
+        let mode = self.mode;
+        let Some(transaction) = self.transaction.as_mut() else {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Need MAIL command"));
+        };
+        if transaction.recipients.len() >= MAX_RECIPIENTS {
+            return Response::reply(Reply::enhanced(452, "4.5.3", "Too many recipients"));
+        }
+        match lookup {
+            Ok(Lookup::Local(account)) => {
+                let duplicate = transaction.recipients.iter().any(|other| other.account.as_ref().is_some_and(|a| a.id == account.id));
+                if !duplicate {
+                    transaction.recipients.push(Recipient { address: account.address(), account: Some(account), dsn });
+                }
+                Response::reply(Reply::enhanced(250, "2.1.5", "OK"))
+            }
+            Ok(Lookup::Remote) if mode == Mode::Submission => {
+                let address = recipient.to_lowercase();
+                if transaction.recipients.iter().all(|other| other.address != address) {
+                    transaction.recipients.push(Recipient { address, account: None, dsn });
+                }
+                Response::reply(Reply::enhanced(250, "2.1.5", "OK"))
+            }
+            Ok(Lookup::Unknown) => {
+                debug!("SMTP #{} 拒绝收件人 {}：账号不存在", self.id, recipient);
+                Response::reply(Reply::enhanced(550, "5.1.1", "No such user here"))
+            }
+            Ok(Lookup::Remote) => {
+                debug!("SMTP #{} 拒绝收件人 {}：不是本机域名", self.id, recipient);
+                Response::reply(Reply::enhanced(550, "5.7.1", "Relaying denied"))
+            }
+            Err(e) => {
+                error!("SMTP #{} 无法查询收件人 {}：{}", self.id, recipient, e);
+                Response::reply(Reply::enhanced(451, "4.3.0", "Requested action aborted: local error in processing"))
+            }
+        }
+    }
+
+    /// # 解析 MAIL FROM 与 RCPT TO 的参数
+    /// HELO 会话不允许带参数，关键字重复时报错。
+    fn parameters(&self, text: &str, command: &str) -> Result<Vec<(String, Option<String>)>, Reply> {
+        if text.is_empty() {
+            return Ok(Vec::new());
+        }
+        if !self.extended {
+            return Err(Reply::enhanced(555, "5.5.4", format!("{} parameters not recognized", command)));
+        }
+        let mut parameters: Vec<(String, Option<String>)> = Vec::new();
+        for parameter in text.split_ascii_whitespace() {
+            let (keyword, value) = match parameter.split_once('=') {
+                Some((keyword, value)) => (keyword, Some(value.to_string())),
+                None => (parameter, None),
+            };
+            let keyword = keyword.to_ascii_uppercase();
+            if keyword.is_empty() || value.as_ref().is_some_and(String::is_empty) {
+                return Err(Reply::enhanced(501, "5.5.4", format!("Syntax error in {} parameters", command)));
+            }
+            if parameters.iter().any(|(other, _)| *other == keyword) {
+                return Err(Reply::enhanced(501, "5.5.4", format!("Duplicate parameter {}", keyword)));
+            }
+            parameters.push((keyword, value));
+        }
+        Ok(parameters)
+    }
+
+    fn data_command(&mut self, argument: &str) -> Response {
+        if !argument.is_empty() {
+            return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: DATA"));
+        }
+        match &self.transaction {
+            None => Response::reply(Reply::enhanced(503, "5.5.1", "Need MAIL command")),
+            Some(transaction) if transaction.recipients.is_empty() => Response::reply(Reply::enhanced(503, "5.5.1", "Need RCPT command")),
+            Some(transaction) if transaction.chunks.is_some() => {
+                Response::reply(Reply::enhanced(503, "5.5.1", "DATA not permitted after BDAT"))
+            }
+            Some(_) => Response { reply: Some(Reply::new(354, "End data with <CR><LF>.<CR><LF>")), next: Next::Data },
+        }
+    }
+
+    /// BDAT <大小> [LAST]（RFC 3030）
+    /// 无论事务状态如何都需要读取数据块，应答在读取后由 Session::chunk 给出。
+    fn bdat(&mut self, argument: &str) -> Response {
+        let mut words = argument.split_ascii_whitespace();
+        let size = words.next().filter(|size| size.bytes().all(|byte| byte.is_ascii_digit())).and_then(|size| size.parse().ok());
+        let last = match words.next() {
+            None => Some(false),
+            Some(word) if word.eq_ignore_ascii_case("LAST") => Some(true),
+            Some(_) => None,
+        };
+        match (size, last, words.next()) {
+            (Some(size), Some(last), None) => Response { reply: None, next: Next::Chunk { size, last } },
+            // 无法确定数据块的长度，不能继续读取命令
+            _ => Response { reply: Some(Reply::enhanced(501, "5.5.4", "Syntax: BDAT <size> [LAST]")), next: Next::Close },
+        }
+    }
+
+    fn starttls(&mut self, argument: &str) -> Response {
+        if !argument.is_empty() {
+            return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: STARTTLS"));
+        }
+        match self.tls {
+            Tls::Unavailable => Response::reply(Reply::enhanced(502, "5.5.1", "Command not implemented")),
+            Tls::Active => Response::reply(Reply::enhanced(503, "5.5.1", "TLS already active")),
+            Tls::Available => Response { reply: Some(Reply::enhanced(220, "2.0.0", "Ready to start TLS")), next: Next::StartTls },
+        }
+    }
+
+    /// AUTH <机制> [初始响应]（RFC 4954）
+    fn auth_command(&mut self, argument: &str) -> Response {
+        if self.mode != Mode::Submission {
+            return Response::reply(Reply::enhanced(502, "5.5.1", "Command not implemented"));
+        }
+        if !self.extended {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Send EHLO first"));
+        }
+        if self.user.is_some() {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "Already authenticated"));
+        }
+        if self.transaction.is_some() {
+            return Response::reply(Reply::enhanced(503, "5.5.1", "AUTH not permitted during a mail transaction"));
+        }
+        if self.tls == Tls::Available {
+            return Response::reply(Reply::enhanced(530, "5.7.0", "Must issue a STARTTLS command first"));
+        }
+        let (name, initial) = match argument.split_once(' ') {
+            Some((name, initial)) => (name, Some(initial.trim())),
+            None => (argument, None),
+        };
+        if name.is_empty() {
+            return Response::reply(Reply::enhanced(501, "5.5.4", "Syntax: AUTH <mechanism> [initial-response]"));
+        }
+        let Some(mechanism) = Mechanism::parse(name) else {
+            return Response::reply(Reply::enhanced(504, "5.5.4", "Unrecognized authentication type"));
+        };
+        let mut authenticator = Authenticator::new(mechanism, Arc::clone(&self.store));
+        let step = authenticator.start(initial);
+        self.auth_step(authenticator, step)
+    }
+
+    fn auth_step(&mut self, authenticator: Authenticator, step: Result<Step, SaslError>) -> Response {
+        match step {
+            Ok(Step::Challenge(challenge)) => {
+                self.authenticator = Some(authenticator);
+                Response { reply: Some(Reply::new(334, challenge)), next: Next::Auth }
+            }
+            Ok(Step::Success(account)) => {
+                info!("SMTP #{} 账号 {} 已登录", self.id, account.address());
+                self.user = Some(account);
+                Response::reply(Reply::enhanced(235, "2.7.0", "Authentication successful"))
+            }
+            Ok(Step::Failure(username)) => {
+                warning!("SMTP #{} 来自 {} 的登录失败，用户名 {}", self.id, self.peer, username);
+                Response::reply(Reply::enhanced(535, "5.7.8", "Authentication credentials invalid"))
+            }
+            Err(SaslError::Cancelled) => Response::reply(Reply::enhanced(501, "5.7.0", "Authentication cancelled")),
+            Err(SaslError::Malformed) => Response::reply(Reply::enhanced(501, "5.5.2", "Malformed authentication data")),
+            Err(SaslError::Storage(e)) => {
+                error!("SMTP #{} 无法查询账号：{}", self.id, e);
+                Response::reply(Reply::enhanced(454, "4.7.0", "Temporary authentication failure"))
+            }
+        }
+    }
+
+    /// # 查找收件人
+    /// 别名解析为对应的账号。
+    fn lookup(&self, address: &str) -> Result<Lookup, StorageError> {
+        let (_, domain) = split_address(address).unwrap_or_default();
+        if !self.store.has_domain(&domain)? {
+            return Ok(Lookup::Remote);
+        }
+        Ok(match self.store.resolve(address)? {
+            Some(account) => Lookup::Local(account),
+            None => Lookup::Unknown,
+        })
+    }
+
+    /// # 追踪信息
+    /// Received 邮件头（RFC 5321 4.4），协议名称见 RFC 3848 与 RFC 6531 3.7.3。
+    /// Return-Path 由最终投递时添加，见 finish() 与发送队列。
+    fn trace(&self, transaction: &Transaction) -> String {
+        let helo = self.helo.as_deref().unwrap_or("unknown");
+        let mut protocol = String::from(match (self.extended, transaction.utf8) {
+            (false, _) => "SMTP",
+            (true, false) => "ESMTP",
+            (true, true) => "UTF8SMTP",
+        });
+        if self.extended && self.tls == Tls::Active {
+            protocol.push('S');
+        }
+        if self.extended && self.user.is_some() {
+            protocol.push('A');
+        }
+        let recipient = match transaction.recipients.as_slice() {
+            [only] => format!("\r\n\tfor <{}>", only.address),
+            _ => String::new(),
+        };
+        format!(
+            "Received: from {} ({})\r\n\tby {} (ZitMail) with {} id {}.{}{};\r\n\t{}\r\n",
+            helo,
+            self.peer,
+            self.hostname,
+            protocol,
+            self.id,
+            self.received,
+            recipient,
+            Utc::now().to_rfc2822()
+        )
+    }
+}
+
+/// # 解析 NOTIFY 参数（RFC 3461 4.1）
+/// ## 参数
+/// - value: 例如 SUCCESS,FAILURE
+/// ## 返回值
+/// - Option<String>，统一为大写
+fn parse_notify(value: &str) -> Option<String> {
+    let value = value.to_ascii_uppercase();
+    let items: Vec<&str> = value.split(',').collect();
+    let valid = match items.as_slice() {
+        ["NEVER"] => true,
+        items => items.iter().enumerate().all(|(index, item)| {
+            matches!(*item, "SUCCESS" | "FAILURE" | "DELAY") && !items[..index].contains(item)
+        }),
+    };
+    valid.then_some(value)
+}
+
+/// # 解析 ORCPT 参数（RFC 3461 4.2）
+/// ## 参数
+/// - value: 例如 rfc822;manser+2B1@example.com
+/// ## 返回值
+/// - Option<String>，地址部分已解码，例如 rfc822;manser+1@example.com
+fn parse_orcpt(value: &str) -> Option<String> {
+    let (kind, address) = value.split_once(';')?;
+    if kind.is_empty() || !kind.bytes().all(|byte| byte.is_ascii_alphanumeric() || byte == b'-') {
+        return None;
+    }
+    let address = decode_xtext(address).filter(|address| !address.is_empty())?;
+    Some(format!("{};{}", kind, address))
+}
+
+/// # 解析 MAIL FROM 与 RCPT TO 的路径
+/// 忽略源路由（@a,@b:user@domain），地址两侧的尖括号不可省略。
+/// ## 参数
+/// - argument: 命令参数，例如 FROM:<manser@example.com> SIZE=1024
+/// - keyword: FROM: 或 TO:
+/// ## 返回值
+/// - Option<(地址, 其余参数)>
+fn parse_path<'a>(argument: &'a str, keyword: &str) -> Option<(String, &'a str)> {
+    let prefix = argument.get(..keyword.len())?;
+    if !prefix.eq_ignore_ascii_case(keyword) {
+        return None;
+    }
+    let rest = argument[keyword.len()..].trim_start().strip_prefix('<')?;
+    let (path, parameters) = rest.split_once('>')?;
+    let mailbox = match path.split_once(':') {
+        Some((route, mailbox)) if route.starts_with('@') => mailbox,
+        _ => path,
+    };
+    Some((mailbox.to_string(), parameters.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::default::CONFIG;
+    use crate::storage::memory::MemoryStore;
+    use crate::storage::sqlite::SqliteStore;
+
+    /// 准备测试账号：example.com 下的 bob 与 carol，以及 bob 的别名 info
+    fn prepare(store: &dyn MailStore) {
+        store.add_domain("example.com").unwrap();
+        let bob = store.add_account("bob", "example.com", "", "").unwrap();
+        store.add_account("carol", "example.com", "", "").unwrap();
+        store.add_alias("info@example.com", bob.id).unwrap();
+    }
+
+    fn memory() -> Arc<dyn MailStore> {
+        let store = MemoryStore::new();
+        prepare(&store);
+        Arc::new(store)
+    }
+
+    /// 邮件大小上限为 1 MB
+    fn session(store: &Arc<dyn MailStore>) -> Session {
+        let mut config = Config::parse(CONFIG).unwrap().smtp;
+        config.hostname = String::from("mx.example.com");
+        config.max_message_size = 1;
+        Session::new(1, &config, "192.0.2.1".parse().unwrap(), Arc::clone(store), Mode::Relay, Tls::Unavailable)
+    }
+
+    /// # 按顺序发送命令，检查每个应答的开头
+    fn script(session: &mut Session, steps: &[(&str, &str)]) {
+        for (line, expected) in steps {
+            let reply = session.command(line).reply.map(|reply| reply.to_string()).unwrap_or_default();
+            assert!(reply.starts_with(expected), "C: {}\nS: {}期望：{}", line, reply, expected);
+        }
+    }
+
+    /// # 发送 DATA 与邮件内容
+    fn data(session: &mut Session, message: &[u8]) -> String {
+        let response = session.command("DATA");
+        assert_eq!(response.next, Next::Data);
+        session.data(message.to_vec()).to_string()
+    }
+
+    /// # 发送 BDAT 命令与数据块
+    /// 与 server.rs 相同，超出 chunk_limit() 的数据块被丢弃。
+    fn chunk(session: &mut Session, chunk: &[u8], last: bool) -> String {
+        let line = format!("BDAT {}{}", chunk.len(), if last { " LAST" } else { "" });
+        let response = session.command(&line);
+        assert!(response.reply.is_none());
+        assert_eq!(response.next, Next::Chunk { size: chunk.len(), last });
+        let chunk = (chunk.len() <= session.chunk_limit()).then(|| chunk.to_vec());
+        session.chunk(chunk, last).reply.unwrap().to_string()
+    }
+
+    /// # 账号 INBOX 中所有邮件的原文
+    fn inbox(store: &Arc<dyn MailStore>, address: &str) -> Vec<Vec<u8>> {
+        let account = store.account(address).unwrap().unwrap();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        store.messages(inbox.id).unwrap().iter().map(|message| store.raw(message.id).unwrap().unwrap()).collect()
+    }
+
+    fn contains(haystack: &[u8], needle: &str) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle.as_bytes())
+    }
+
+    #[test]
+    fn pipelining() {
+        let store = memory();
+        let mut session = session(&store);
+        let ehlo = session.command("EHLO client.test").reply.unwrap().to_string();
+        assert!(ehlo.contains("250-PIPELINING\r\n"));
+
+        // 一批命令的应答按顺序返回，中间被拒绝的收件人不影响后续命令
+        script(
+            &mut session,
+            &[
+                ("MAIL FROM:<alice@remote.test>", "250 2.1.0"),
+                ("RCPT TO:<bob@example.com>", "250 2.1.5"),
+                ("RCPT TO:<nobody@example.com>", "550 5.1.1"),
+                ("RCPT TO:<someone@remote.test>", "550 5.7.1"),
+                ("RCPT TO:<INFO@example.com>", "250 2.1.5"),
+                ("RCPT TO:<carol@example.com>", "250 2.1.5"),
+            ],
+        );
+        assert!(data(&mut session, b"Subject: hi\r\n\r\nhello\r\n").starts_with("250 2.0.0"));
+        // 别名与账号重复时只保存一份
+        assert_eq!(inbox(&store, "bob@example.com").len(), 1);
+        assert_eq!(inbox(&store, "carol@example.com").len(), 1);
+
+        // 全部收件人被拒绝时，随后的 DATA 也被拒绝
+        script(
+            &mut session,
+            &[("MAIL FROM:<alice@remote.test>", "250 2.1.0"), ("RCPT TO:<nobody@example.com>", "550 5.1.1"), ("DATA", "503 5.5.1")],
+        );
+        script(&mut session, &[("RSET", "250 2.0.0"), ("QUIT", "221 2.0.0")]);
+    }
+
+    #[test]
+    fn size() {
+        let store = memory();
+        let mut session = session(&store);
+        let ehlo = session.command("EHLO client.test").reply.unwrap().to_string();
+        assert!(ehlo.contains("250-SIZE 1048576\r\n"));
+        script(
+            &mut session,
+            &[
+                ("MAIL FROM:<alice@remote.test> SIZE=1048577", "552 5.3.4"),
+                ("MAIL FROM:<alice@remote.test> SIZE=many", "501 5.5.4"),
+                ("MAIL FROM:<alice@remote.test> SIZE=1048576", "250 2.1.0"),
+                ("RCPT TO:<bob@example.com>", "250 2.1.5"),
+            ],
+        );
+        // 服务器读取 DATA 时发现超出上限，放弃事务
+        assert!(session.abort(Reply::enhanced(552, "5.3.4", "Message size exceeds fixed maximum message size")).to_string().starts_with("552"));
+        script(&mut session, &[("DATA", "503 5.5.1")]);
+
+        // HELO 会话不支持扩展参数
+        script(&mut session, &[("HELO client.test", "250 mx.example.com"), ("MAIL FROM:<alice@remote.test> SIZE=10", "555 5.5.4")]);
+    }
+
+    #[test]
+    fn eight_bit_and_smtputf8() {
+        let store = memory();
+        let mut session = session(&store);
+        let ehlo = session.command("EHLO client.test").reply.unwrap().to_string();
+        assert!(ehlo.contains("250-8BITMIME\r\n") && ehlo.contains("250-SMTPUTF8\r\n"));
+        script(
+            &mut session,
+            &[
+                ("MAIL FROM:<alice@remote.test> BODY=BINARYMIME", "501 5.5.4"),
+                ("MAIL FROM:<用户@remote.test>", "553 5.6.7"),
+                ("MAIL FROM:<alice@remote.test> BODY=8BITMIME", "250 2.1.0"),
+                ("RCPT TO:<用户@example.com>", "553 5.6.7"),
+                ("RCPT TO:<bob@example.com>", "250 2.1.5"),
+            ],
+        );
+        let message = "Subject: 你好\r\n\r\n八位内容\r\n".as_bytes();
+        assert!(data(&mut session, message).starts_with("250 2.0.0"));
+        let raw = &inbox(&store, "bob@example.com")[0];
+        assert!(raw.ends_with(message));
+        assert!(contains(raw, "with ESMTP id"));
+
+        script(
+            &mut session,
+            &[
+                ("MAIL FROM:<用户@remote.test> SMTPUTF8 BODY=8BITMIME", "250 2.1.0"),
+                ("RCPT TO:<用户@example.com>", "550 5.1.1"),
+                ("RCPT TO:<carol@example.com>", "250 2.1.5"),
+            ],
+        );
+        assert!(data(&mut session, message).starts_with("250 2.0.0"));
+        let raw = &inbox(&store, "carol@example.com")[0];
+        assert!(raw.starts_with("Return-Path: <用户@remote.test>\r\n".as_bytes()));
+        assert!(contains(raw, "with UTF8SMTP id"));
+    }
+
+    #[test]
+    fn bdat() {
+        let store = memory();
+        let mut session = session(&store);
+        script(&mut session, &[("EHLO client.test", "250-mx.example.com")]);
+        // 没有事务时数据块被丢弃
+        assert!(chunk(&mut session, b"lost", false).starts_with("503 5.5.1 Need MAIL"));
+
+        script(&mut session, &[("MAIL FROM:<alice@remote.test>", "250 2.1.0")]);
+        assert!(chunk(&mut session, b"early", false).starts_with("503 5.5.1 Need RCPT"));
+        script(&mut session, &[("RCPT TO:<bob@example.com>", "250 2.1.5")]);
+        assert_eq!(chunk(&mut session, b"Subject: chunks\r\n", false), "250 2.0.0 17 octets received\r\n");
+        script(&mut session, &[("DATA", "503 5.5.1")]);
+        // 数据块原样保存，不处理行首的 .
+        assert_eq!(chunk(&mut session, b"\r\n.body\r\n", false), "250 2.0.0 26 octets received\r\n");
+        assert!(chunk(&mut session, b"", true).starts_with("250 2.0.0 OK"));
+        assert!(inbox(&store, "bob@example.com")[0].ends_with(b"Subject: chunks\r\n\r\n.body\r\n"));
+
+        // 超出上限的数据块放弃整个事务
+        script(&mut session, &[("MAIL FROM:<alice@remote.test>", "250 2.1.0"), ("RCPT TO:<bob@example.com>", "250 2.1.5")]);
+        assert!(chunk(&mut session, &[b'x'; 1024 * 1024], false).starts_with("250"));
+        assert!(chunk(&mut session, b"x", true).starts_with("552 5.3.4"));
+        assert!(chunk(&mut session, b"x", true).starts_with("503 5.5.1 Need MAIL"));
+        assert_eq!(inbox(&store, "bob@example.com").len(), 1);
+
+        // 长度无法解析时无法继续读取，关闭连接
+        let response = session.command("BDAT ten LAST");
+        assert!(response.reply.unwrap().to_string().starts_with("501 5.5.4"));
+        assert_eq!(response.next, Next::Close);
+    }
+
+    #[test]
+    fn enhanced_status_codes() {
+        let store = memory();
+        let mut session = session(&store);
+        assert_eq!(session.greeting().status, None);
+        script(&mut session, &[("MAIL FROM:<alice@remote.test>", "503 5.5.1")]);
+        let ehlo = session.command("EHLO client.test").reply.unwrap();
+        assert_eq!(ehlo.status, None);
+        assert!(ehlo.lines.iter().any(|line| line == "ENHANCEDSTATUSCODES"));
+        for (line, status) in [
+            ("NOOP", "2.0.0"),
+            ("VRFY bob@example.com", "2.0.0"),
+            ("EXPN staff", "5.5.1"),
+            ("FOO", "5.5.2"),
+            ("RSET now", "5.5.4"),
+            ("STARTTLS", "5.5.1"),
+            ("AUTH PLAIN", "5.5.1"),
+            ("MAIL FROM:alice@remote.test", "5.5.4"),
+            ("MAIL FROM:<alice>", "5.1.7"),
+            ("MAIL FROM:<alice@remote.test> FOO=1", "5.5.4"),
+            ("MAIL FROM:<alice@remote.test>", "2.1.0"),
+            ("MAIL FROM:<alice@remote.test>", "5.5.1"),
+            ("RCPT TO:<bob>", "5.1.3"),
+            ("RCPT TO:<bob@example.com>", "2.1.5"),
+            ("QUIT", "2.0.0"),
+        ] {
+            assert_eq!(session.command(line).reply.unwrap().status, Some(status), "{}", line);
+        }
+        let response = session.command("DATA");
+        assert_eq!((response.reply.unwrap().status, response.next), (None, Next::Data));
+    }
+
+    #[test]
+    fn dsn() {
+        let store = memory();
+        let mut session = session(&store);
+        let ehlo = session.command("EHLO client.test").reply.unwrap().to_string();
+        assert!(ehlo.ends_with("250 DSN\r\n"));
+        script(
+            &mut session,
+            &[
+                ("MAIL FROM:<alice@remote.test> RET=BODY", "501 5.5.4"),
+                ("MAIL FROM:<alice@remote.test> ENVID=a+2", "501 5.5.4"),
+                ("MAIL FROM:<alice@remote.test> RET=HDRS RET=FULL", "501 5.5.4"),
+                ("MAIL FROM:<alice@remote.test> RET=hdrs ENVID=abc+2Bd", "250 2.1.0"),
+                ("RCPT TO:<bob@example.com> NOTIFY=NEVER,SUCCESS", "501 5.5.4"),
+                ("RCPT TO:<bob@example.com> ORCPT=rfc822", "501 5.5.4"),
+                ("RCPT TO:<bob@example.com> NOTIFY=success,FAILURE ORCPT=rfc822;bob+2B1@example.com", "250 2.1.5"),
+                ("RCPT TO:<carol@example.com> NOTIFY=FAILURE", "250 2.1.5"),
+            ],
+        );
+        assert!(data(&mut session, b"Subject: dsn\r\nMessage-ID: <1@remote.test>\r\n\r\nbody\r\n").starts_with("250 2.0.0"));
+
+        // 只有 NOTIFY 包含 SUCCESS 的收件人产生投递成功的通知，由空发件人发往原发件人
+        let queue = store.queue().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue[0].sender, "");
+        assert_eq!(queue[0].recipients[0].recipient, "alice@remote.test");
+        let report = store.queue_raw(queue[0].id).unwrap().unwrap();
+        for field in [
+            "To: <alice@remote.test>\r\n",
+            "Content-Type: message/delivery-status\r\n",
+            "Original-Envelope-Id: abc+2Bd\r\n",
+            "Original-Recipient: rfc822; bob+1@example.com\r\n",
+            "Final-Recipient: rfc822; bob@example.com\r\n",
+            "Action: delivered\r\n",
+            "Status: 2.0.0\r\n",
+            "Message-ID: <1@remote.test>\r\n",
+        ] {
+            assert!(contains(&report, field), "{}", field);
+        }
+        assert!(!contains(&report, "carol@example.com"));
+
+        // 空发件人的邮件不产生通知
+        script(
+            &mut session,
+            &[("MAIL FROM:<>", "250 2.1.0"), ("RCPT TO:<bob@example.com> NOTIFY=SUCCESS", "250 2.1.5")],
+        );
+        assert!(data(&mut session, b"Subject: bounce\r\n\r\nbody\r\n").starts_with("250 2.0.0"));
+        assert_eq!(store.queue().unwrap().len(), 1);
+    }
+
+    /// # 无法保存的收件人交给发送队列
+    /// SQLite 触发器模拟保存失败。
+    #[test]
+    fn partial_failure() {
+        let path = std::env::temp_dir().join(format!("zitmail-session-{}.db", std::process::id()));
+        let remove = || ["", "-wal", "-shm"].iter().for_each(|suffix| drop(std::fs::remove_file(format!("{}{}", path.display(), suffix))));
+        remove();
+        let sqlite = SqliteStore::open(&path.to_string_lossy()).unwrap();
+        sqlite.migrate().unwrap();
+        prepare(&sqlite);
+        let store: Arc<dyn MailStore> = Arc::new(sqlite);
+        let carol = store.account("carol@example.com").unwrap().unwrap();
+        let mailbox = store.mailbox(carol.id, "INBOX").unwrap().unwrap();
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(&format!(
+                "CREATE TRIGGER fail BEFORE INSERT ON messages WHEN NEW.mailbox_id = {} BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+                mailbox.id
+            ))
+            .unwrap();
+
+        let mut session = session(&store);
+        script(
+            &mut session,
+            &[
+                ("EHLO client.test", "250"),
+                ("MAIL FROM:<alice@remote.test> RET=FULL ENVID=e1", "250 2.1.0"),
+                ("RCPT TO:<bob@example.com>", "250 2.1.5"),
+                ("RCPT TO:<carol@example.com> NOTIFY=SUCCESS", "250 2.1.5"),
+            ],
+        );
+        // 已保存到 bob，不能再让客户端重试
+        assert!(data(&mut session, b"Subject: partial\r\n\r\nbody\r\n").starts_with("250 2.0.0"));
+        assert_eq!(inbox(&store, "bob@example.com").len(), 1);
+        let queue = store.queue().unwrap();
+        assert_eq!(queue.len(), 1);
+        assert_eq!((queue[0].sender.as_str(), queue[0].dsn.ret.as_deref(), queue[0].dsn.envid.as_deref()), ("alice@remote.test", Some("FULL"), Some("e1")));
+        assert_eq!(queue[0].recipients.len(), 1);
+        assert_eq!((queue[0].recipients[0].recipient.as_str(), queue[0].recipients[0].dsn.notify.as_deref()), ("carol@example.com", Some("SUCCESS")));
+        let raw = store.queue_raw(queue[0].id).unwrap().unwrap();
+        assert!(raw.starts_with(b"Received: from client.test (192.0.2.1)"));
+
+        // 没有收件人保存成功、也无法加入队列时返回临时错误，客户端重试不会重复
+        connection.execute_batch("CREATE TRIGGER full BEFORE INSERT ON queue BEGIN SELECT RAISE(ABORT, 'disk full'); END;").unwrap();
+        script(&mut session, &[("MAIL FROM:<alice@remote.test>", "250 2.1.0"), ("RCPT TO:<carol@example.com>", "250 2.1.5")]);
+        assert!(data(&mut session, b"Subject: failed\r\n\r\nbody\r\n").starts_with("451 4.3.0"));
+        assert_eq!(store.queue().unwrap().len(), 1);
+
+        drop((session, store, connection));
+        remove();
+    }
+}
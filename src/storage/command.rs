@@ -0,0 +1,60 @@
+/* 命令 zmou db */
+use super::{StorageError, open};
+use crate::config::Config;
+use crate::utils::format_time;
+
+/// # 命令 zmou db migrate
+/// 升级数据库结构到最新版本
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn migrate(config: &Config) -> Result<(), StorageError> {
+    let store = open(&config.database)?;
+    if store.migrate()?.is_empty() {
+        info!("数据库已是最新版本");
+    }
+    Ok(())
+}
+
+/// # 命令 zmou db status
+/// 列出所有版本及其应用时间
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn status(config: &Config) -> Result<(), StorageError> {
+    let store = open(&config.database)?;
+    if store.migrations().is_empty() {
+        info!("{} 后端没有数据库结构版本", store.backend());
+        return Ok(());
+    }
+    let applied = store.applied()?;
+    let migrations = store.migrations();
+    for migration in migrations {
+        let state = match applied.iter().find(|(version, _)| *version == migration.version) {
+            Some((_, time)) => format!("已应用于 {}", format_time(*time, &config.general.time_zone)),
+            None => String::from("待应用"),
+        };
+        println!("{:>4}  {:<16} {}", migration.version, migration.name, state);
+    }
+    for (version, _) in applied.iter().filter(|(version, _)| migrations.iter().all(|m| m.version != *version)) {
+        println!("{:>4}  {:<16} 程序中不存在此版本，请更新 ZitMail", version, "未知");
+    }
+    Ok(())
+}
+
+/// # 命令 zmou db rollback
+/// 回滚最近的一个版本
+/// ## 参数
+/// - config: 已解析敏感配置项的配置
+/// ## 返回值
+/// - Result<(), StorageError>
+pub fn rollback(config: &Config) -> Result<(), StorageError> {
+    let store = open(&config.database)?;
+    match store.rollback()? {
+        Some(migration) => warning!("已回滚数据库版本 {} {}", migration.version, migration.name),
+        None => info!("没有可回滚的版本"),
+    }
+    Ok(())
+}
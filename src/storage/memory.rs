@@ -0,0 +1,560 @@
+/* 内存存储 */
+/*
+数据只保存在内存中，重启后丢失，用于测试与临时运行。
+没有数据库结构版本，migrate 与 rollback 不执行任何操作。
+ */
+use chrono::{DateTime, Utc};
+use std::sync::{Mutex, MutexGuard};
+
+use super::migration::Migration;
+use super::{Account, Delivery, DeliveryKey, Dsn, MailStore, Mailbox, MessageInfo, QueueEntry, QueueRecipient, RecipientDsn, StorageError};
+use super::{new_uid_validity, split_address};
+
+#[derive(Default)]
+struct Data {
+    /// 自增 id，所有对象共用
+    sequence: i64,
+    domains: Vec<(i64, String)>,
+    /// (账号, 域名 id)
+    accounts: Vec<(Account, i64)>,
+    /// (域名 id, 本地部分, 账号 id)
+    aliases: Vec<(i64, String, i64)>,
+    mailboxes: Vec<Mailbox>,
+    messages: Vec<(MessageInfo, Vec<u8>)>,
+    /// (邮箱 id, UID, 修改序列, 邮件 id)
+    vanished: Vec<(i64, u32, u64, i64)>,
+    queue: Vec<(QueueEntry, Vec<u8>)>,
+    deliveries: Vec<Delivery>,
+}
+
+impl Data {
+    fn next_id(&mut self) -> i64 {
+        self.sequence += 1;
+        self.sequence
+    }
+
+    /// 分配下一个 UID 与修改序列
+    fn next_uid(&mut self, mailbox_id: i64) -> Result<(u32, u64), StorageError> {
+        let mailbox = self.find_mailbox(mailbox_id)?;
+        mailbox.uid_next += 1;
+        mailbox.highest_modseq += 1;
+        Ok((mailbox.uid_next - 1, mailbox.highest_modseq))
+    }
+
+    fn next_modseq(&mut self, mailbox_id: i64) -> Result<u64, StorageError> {
+        let mailbox = self.find_mailbox(mailbox_id)?;
+        mailbox.highest_modseq += 1;
+        Ok(mailbox.highest_modseq)
+    }
+
+    fn find_mailbox(&mut self, mailbox_id: i64) -> Result<&mut Mailbox, StorageError> {
+        self.mailboxes
+            .iter_mut()
+            .find(|mailbox| mailbox.id == mailbox_id)
+            .ok_or_else(|| StorageError::NotFound(format!("邮箱 #{}", mailbox_id)))
+    }
+
+    fn new_mailbox(&mut self, account_id: i64, name: &str) -> Mailbox {
+        let mailbox = Mailbox {
+            id: self.next_id(),
+            account_id,
+            name: name.to_string(),
+            uid_validity: new_uid_validity(),
+            uid_next: 1,
+            subscribed: true,
+            highest_modseq: 1,
+        };
+        self.mailboxes.push(mailbox.clone());
+        mailbox
+    }
+
+    /// 删除邮箱及其中的邮件
+    fn remove_mailboxes(&mut self, removed: impl Fn(&Mailbox) -> bool) {
+        let ids: Vec<i64> = self.mailboxes.iter().filter(|m| removed(m)).map(|m| m.id).collect();
+        self.mailboxes.retain(|mailbox| !ids.contains(&mailbox.id));
+        self.messages.retain(|(message, _)| !ids.contains(&message.mailbox_id));
+        self.vanished.retain(|(mailbox_id, _, _, _)| !ids.contains(mailbox_id));
+    }
+}
+
+#[derive(Default)]
+pub struct MemoryStore {
+    data: Mutex<Data>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+
+    fn data(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl MailStore for MemoryStore {
+    fn backend(&self) -> &'static str {
+        "Memory"
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        &[]
+    }
+
+    fn applied(&self) -> Result<Vec<(i64, DateTime<Utc>)>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    fn migrate(&self) -> Result<Vec<&'static Migration>, StorageError> {
+        Ok(Vec::new())
+    }
+
+    fn rollback(&self) -> Result<Option<&'static Migration>, StorageError> {
+        Ok(None)
+    }
+
+    fn add_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        let mut data = self.data();
+        if data.domains.iter().any(|(_, domain)| *domain == name) {
+            return Err(StorageError::Conflict(format!("域名 {}", name)));
+        }
+        let id = data.next_id();
+        data.domains.push((id, name));
+        Ok(())
+    }
+
+    fn delete_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        let mut data = self.data();
+        let Some(position) = data.domains.iter().position(|(_, domain)| *domain == name) else {
+            return Err(StorageError::NotFound(format!("域名 {}", name)));
+        };
+        let (domain_id, _) = data.domains.remove(position);
+        let accounts: Vec<i64> = data.accounts.iter().filter(|(_, d)| *d == domain_id).map(|(a, _)| a.id).collect();
+        data.accounts.retain(|(_, d)| *d != domain_id);
+        data.aliases.retain(|(d, _, account)| *d != domain_id && !accounts.contains(account));
+        data.remove_mailboxes(|mailbox| accounts.contains(&mailbox.account_id));
+        Ok(())
+    }
+
+    fn domains(&self) -> Result<Vec<String>, StorageError> {
+        let mut domains: Vec<String> = self.data().domains.iter().map(|(_, name)| name.clone()).collect();
+        domains.sort();
+        Ok(domains)
+    }
+
+    fn has_domain(&self, name: &str) -> Result<bool, StorageError> {
+        let name = name.to_lowercase();
+        Ok(self.data().domains.iter().any(|(_, domain)| *domain == name))
+    }
+
+    fn add_account(&self, local_part: &str, domain: &str, password: &str, scram: &str) -> Result<Account, StorageError> {
+        let (local_part, domain) = (local_part.to_lowercase(), domain.to_lowercase());
+        let mut data = self.data();
+        let Some(&(domain_id, _)) = data.domains.iter().find(|(_, name)| *name == domain) else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        if data.accounts.iter().any(|(a, d)| *d == domain_id && a.local_part == local_part) {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        }
+        if data.aliases.iter().any(|(d, alias, _)| *d == domain_id && *alias == local_part) {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        let account =
+            Account { id: data.next_id(), local_part, domain, password: password.to_string(), scram: scram.to_string(), pop3_delete: false };
+        data.accounts.push((account.clone(), domain_id));
+        data.new_mailbox(account.id, "INBOX");
+        Ok(account)
+    }
+
+    fn delete_account(&self, id: i64) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some(position) = data.accounts.iter().position(|(account, _)| account.id == id) else {
+            return Err(StorageError::NotFound(format!("账号 #{}", id)));
+        };
+        data.accounts.remove(position);
+        data.aliases.retain(|(_, _, account)| *account != id);
+        data.remove_mailboxes(|mailbox| mailbox.account_id == id);
+        Ok(())
+    }
+
+    fn account(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let data = self.data();
+        let account = data.accounts.iter().map(|(account, _)| account).find(|a| a.local_part == local_part && a.domain == domain);
+        Ok(account.cloned())
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, StorageError> {
+        let mut accounts: Vec<Account> = self.data().accounts.iter().map(|(account, _)| account.clone()).collect();
+        accounts.sort_by(|a, b| (&a.domain, &a.local_part).cmp(&(&b.domain, &b.local_part)));
+        Ok(accounts)
+    }
+
+    fn set_password(&self, id: i64, password: &str, scram: &str) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((account, _)) = data.accounts.iter_mut().find(|(account, _)| account.id == id) else {
+            return Err(StorageError::NotFound(format!("账号 #{}", id)));
+        };
+        account.password = password.to_string();
+        account.scram = scram.to_string();
+        Ok(())
+    }
+
+    fn set_pop3_delete(&self, id: i64, delete: bool) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((account, _)) = data.accounts.iter_mut().find(|(account, _)| account.id == id) else {
+            return Err(StorageError::NotFound(format!("账号 #{}", id)));
+        };
+        account.pop3_delete = delete;
+        Ok(())
+    }
+
+    fn add_alias(&self, address: &str, account_id: i64) -> Result<(), StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Err(StorageError::NotFound(format!("域名 {}", address)));
+        };
+        let mut data = self.data();
+        let Some(&(domain_id, _)) = data.domains.iter().find(|(_, name)| *name == domain) else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        if data.accounts.iter().any(|(a, d)| *d == domain_id && a.local_part == local_part) {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        }
+        if data.aliases.iter().any(|(d, alias, _)| *d == domain_id && *alias == local_part) {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        if data.accounts.iter().all(|(account, _)| account.id != account_id) {
+            return Err(StorageError::NotFound(format!("账号 #{}", account_id)));
+        }
+        data.aliases.push((domain_id, local_part, account_id));
+        Ok(())
+    }
+
+    fn delete_alias(&self, address: &str) -> Result<(), StorageError> {
+        let (local_part, domain) = split_address(address).unwrap_or_default();
+        let mut data = self.data();
+        let domain_id = data.domains.iter().find(|(_, name)| *name == domain).map(|(id, _)| *id);
+        let Some(position) = data.aliases.iter().position(|(d, alias, _)| Some(*d) == domain_id && *alias == local_part) else {
+            return Err(StorageError::NotFound(format!("别名 {}", address)));
+        };
+        data.aliases.remove(position);
+        Ok(())
+    }
+
+    fn aliases(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let data = self.data();
+        let mut aliases: Vec<(String, String)> = data
+            .aliases
+            .iter()
+            .filter_map(|(domain_id, local_part, account_id)| {
+                let (_, domain) = data.domains.iter().find(|(id, _)| id == domain_id)?;
+                let (account, _) = data.accounts.iter().find(|(account, _)| account.id == *account_id)?;
+                Some((format!("{}@{}", local_part, domain), account.address()))
+            })
+            .collect();
+        aliases.sort();
+        Ok(aliases)
+    }
+
+    fn resolve(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        if let Some(account) = self.account(address)? {
+            return Ok(Some(account));
+        }
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let data = self.data();
+        let Some(&(domain_id, _)) = data.domains.iter().find(|(_, name)| *name == domain) else {
+            return Ok(None);
+        };
+        let account_id = data.aliases.iter().find(|(d, alias, _)| *d == domain_id && *alias == local_part).map(|(_, _, id)| *id);
+        Ok(data.accounts.iter().map(|(account, _)| account).find(|a| Some(a.id) == account_id).cloned())
+    }
+
+    fn create_mailbox(&self, account_id: i64, name: &str) -> Result<Mailbox, StorageError> {
+        let mut data = self.data();
+        if data.mailboxes.iter().any(|m| m.account_id == account_id && m.name == name) {
+            return Err(StorageError::Conflict(format!("邮箱 {}", name)));
+        }
+        Ok(data.new_mailbox(account_id, name))
+    }
+
+    fn mailbox(&self, account_id: i64, name: &str) -> Result<Option<Mailbox>, StorageError> {
+        let data = self.data();
+        Ok(data.mailboxes.iter().find(|m| m.account_id == account_id && m.name == name).cloned())
+    }
+
+    fn mailboxes(&self, account_id: i64) -> Result<Vec<Mailbox>, StorageError> {
+        let mut mailboxes: Vec<Mailbox> = self.data().mailboxes.iter().filter(|m| m.account_id == account_id).cloned().collect();
+        mailboxes.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(mailboxes)
+    }
+
+    fn rename_mailbox(&self, id: i64, name: &str) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some(account_id) = data.mailboxes.iter().find(|m| m.id == id).map(|m| m.account_id) else {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", id)));
+        };
+        if data.mailboxes.iter().any(|m| m.account_id == account_id && m.name == name && m.id != id) {
+            return Err(StorageError::Conflict(format!("邮箱 {}", name)));
+        }
+        if let Some(mailbox) = data.mailboxes.iter_mut().find(|m| m.id == id) {
+            mailbox.name = name.to_string();
+        }
+        Ok(())
+    }
+
+    fn delete_mailbox(&self, id: i64) -> Result<(), StorageError> {
+        let mut data = self.data();
+        if data.mailboxes.iter().all(|mailbox| mailbox.id != id) {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", id)));
+        }
+        data.remove_mailboxes(|mailbox| mailbox.id == id);
+        Ok(())
+    }
+
+    fn set_subscribed(&self, id: i64, subscribed: bool) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some(mailbox) = data.mailboxes.iter_mut().find(|mailbox| mailbox.id == id) else {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", id)));
+        };
+        mailbox.subscribed = subscribed;
+        Ok(())
+    }
+
+    fn append(&self, mailbox_id: i64, raw: &[u8], flags: &[String], internal_date: DateTime<Utc>) -> Result<MessageInfo, StorageError> {
+        let mut data = self.data();
+        let (uid, modseq) = data.next_uid(mailbox_id)?;
+        let message = MessageInfo {
+            id: data.next_id(),
+            mailbox_id,
+            uid,
+            flags: flags.to_vec(),
+            internal_date: DateTime::from_timestamp(internal_date.timestamp(), 0).unwrap_or_default(),
+            size: raw.len() as u64,
+            modseq,
+        };
+        data.messages.push((message.clone(), raw.to_vec()));
+        Ok(message)
+    }
+
+    fn messages(&self, mailbox_id: i64) -> Result<Vec<MessageInfo>, StorageError> {
+        let data = self.data();
+        Ok(data.messages.iter().map(|(message, _)| message).filter(|m| m.mailbox_id == mailbox_id).cloned().collect())
+    }
+
+    fn raw(&self, message_id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let data = self.data();
+        Ok(data.messages.iter().find(|(message, _)| message.id == message_id).map(|(_, raw)| raw.clone()))
+    }
+
+    fn set_flags(&self, message_id: i64, flags: &[String], unchanged_since: Option<u64>) -> Result<Option<u64>, StorageError> {
+        let mut data = self.data();
+        let Some(index) = data.messages.iter().position(|(message, _)| message.id == message_id) else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        let (mailbox_id, current) = (data.messages[index].0.mailbox_id, data.messages[index].0.modseq);
+        if unchanged_since.is_some_and(|modseq| current > modseq) {
+            return Ok(None);
+        }
+        let modseq = data.next_modseq(mailbox_id)?;
+        let message = &mut data.messages[index].0;
+        message.flags = flags.to_vec();
+        message.modseq = modseq;
+        Ok(Some(modseq))
+    }
+
+    fn expunge(&self, message_ids: &[i64]) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let removed: Vec<(i64, u32, i64)> = data
+            .messages
+            .iter()
+            .filter(|(message, _)| message_ids.contains(&message.id))
+            .map(|(message, _)| (message.mailbox_id, message.uid, message.id))
+            .collect();
+        data.messages.retain(|(message, _)| !message_ids.contains(&message.id));
+        let mut mailboxes: Vec<i64> = removed.iter().map(|(mailbox_id, _, _)| *mailbox_id).collect();
+        mailboxes.sort();
+        mailboxes.dedup();
+        for mailbox_id in mailboxes {
+            let modseq = data.next_modseq(mailbox_id)?;
+            let uids: Vec<(u32, i64)> = removed.iter().filter(|(id, _, _)| *id == mailbox_id).map(|(_, uid, id)| (*uid, *id)).collect();
+            data.vanished.extend(uids.into_iter().map(|(uid, id)| (mailbox_id, uid, modseq, id)));
+        }
+        Ok(())
+    }
+
+    fn copy(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut data = self.data();
+        let Some((source, raw)) = data.messages.iter().find(|(message, _)| message.id == message_id).cloned() else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        let (uid, modseq) = data.next_uid(mailbox_id)?;
+        let message = MessageInfo { id: data.next_id(), mailbox_id, uid, modseq, ..source };
+        data.messages.push((message.clone(), raw));
+        Ok(message)
+    }
+
+    fn move_message(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut data = self.data();
+        let Some(source) = data.messages.iter().map(|(message, _)| message).find(|message| message.id == message_id).cloned() else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        if source.mailbox_id == mailbox_id {
+            return Ok(source);
+        }
+        let (uid, modseq) = data.next_uid(mailbox_id)?;
+        let removed = data.next_modseq(source.mailbox_id)?;
+        data.vanished.push((source.mailbox_id, source.uid, removed, message_id));
+        let message = MessageInfo { mailbox_id, uid, modseq, ..source };
+        if let Some((entry, _)) = data.messages.iter_mut().find(|(entry, _)| entry.id == message_id) {
+            *entry = message.clone();
+        }
+        Ok(message)
+    }
+
+    fn vanished(&self, mailbox_id: i64, since: u64) -> Result<Vec<u32>, StorageError> {
+        let data = self.data();
+        let mut uids: Vec<u32> =
+            data.vanished.iter().filter(|(id, _, modseq, _)| *id == mailbox_id && *modseq > since).map(|(_, uid, _, _)| *uid).collect();
+        uids.sort();
+        Ok(uids)
+    }
+
+    fn removed(&self, mailbox_id: i64, since: u64) -> Result<Vec<i64>, StorageError> {
+        let data = self.data();
+        let removed = data.vanished.iter().filter(|(id, _, modseq, _)| *id == mailbox_id && *modseq > since);
+        Ok(removed.map(|(_, _, _, message_id)| *message_id).collect())
+    }
+
+    fn enqueue(&self, sender: &str, dsn: &Dsn, recipients: &[(String, RecipientDsn)], raw: &[u8]) -> Result<i64, StorageError> {
+        let mut data = self.data();
+        let id = data.next_id();
+        let now = Utc::now();
+        let recipients = recipients
+            .iter()
+            .map(|(recipient, dsn)| QueueRecipient {
+                id: data.next_id(),
+                recipient: recipient.clone(),
+                domain: split_address(recipient).map(|(_, domain)| domain).unwrap_or_default(),
+                attempts: 0,
+                next_attempt: now,
+                last_error: None,
+                dsn: dsn.clone(),
+            })
+            .collect();
+        let entry = QueueEntry { id, sender: sender.to_string(), dsn: dsn.clone(), created_at: now, warned: false, held: false, recipients };
+        data.queue.push((entry, raw.to_vec()));
+        Ok(id)
+    }
+
+    fn queue(&self) -> Result<Vec<QueueEntry>, StorageError> {
+        Ok(self.data().queue.iter().map(|(entry, _)| entry.clone()).collect())
+    }
+
+    fn queue_raw(&self, id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let data = self.data();
+        Ok(data.queue.iter().find(|(entry, _)| entry.id == id).map(|(_, raw)| raw.clone()))
+    }
+
+    fn dequeue(&self, id: i64) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some(position) = data.queue.iter().position(|(entry, _)| entry.id == id) else {
+            return Err(StorageError::NotFound(format!("队列 #{}", id)));
+        };
+        data.queue.remove(position);
+        Ok(())
+    }
+
+    fn defer(&self, recipient_id: i64, next_attempt: DateTime<Utc>, error: &str) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some(recipient) =
+            data.queue.iter_mut().flat_map(|(entry, _)| entry.recipients.iter_mut()).find(|recipient| recipient.id == recipient_id)
+        else {
+            return Err(StorageError::NotFound(format!("收件人 #{}", recipient_id)));
+        };
+        recipient.attempts += 1;
+        recipient.next_attempt = next_attempt;
+        recipient.last_error = Some(error.to_string());
+        Ok(())
+    }
+
+    fn complete(&self, recipient_id: i64) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((entry, _)) =
+            data.queue.iter_mut().find(|(entry, _)| entry.recipients.iter().any(|recipient| recipient.id == recipient_id))
+        else {
+            return Err(StorageError::NotFound(format!("收件人 #{}", recipient_id)));
+        };
+        entry.recipients.retain(|recipient| recipient.id != recipient_id);
+        data.queue.retain(|(entry, _)| !entry.recipients.is_empty());
+        Ok(())
+    }
+
+    fn set_warned(&self, id: i64) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((entry, _)) = data.queue.iter_mut().find(|(entry, _)| entry.id == id) else {
+            return Err(StorageError::NotFound(format!("队列 #{}", id)));
+        };
+        entry.warned = true;
+        Ok(())
+    }
+
+    fn set_held(&self, id: i64, held: bool) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((entry, _)) = data.queue.iter_mut().find(|(entry, _)| entry.id == id) else {
+            return Err(StorageError::NotFound(format!("队列 #{}", id)));
+        };
+        entry.held = held;
+        Ok(())
+    }
+
+    fn reschedule(&self, id: i64, next_attempt: DateTime<Utc>) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let Some((entry, _)) = data.queue.iter_mut().find(|(entry, _)| entry.id == id) else {
+            return Err(StorageError::NotFound(format!("队列 #{}", id)));
+        };
+        for recipient in &mut entry.recipients {
+            recipient.next_attempt = next_attempt;
+        }
+        Ok(())
+    }
+
+    fn set_delivery(&self, delivery: &Delivery) -> Result<(), StorageError> {
+        let mut data = self.data();
+        let existing = data.deliveries.iter_mut().find(|existing| {
+            existing.queue_id == delivery.queue_id && existing.recipient == delivery.recipient
+        });
+        match existing {
+            Some(existing) => {
+                existing.action = delivery.action.clone();
+                existing.status = delivery.status.clone();
+                existing.diagnostic = delivery.diagnostic.clone();
+                existing.remote_mta = delivery.remote_mta.clone();
+                existing.updated_at = delivery.updated_at;
+            }
+            None => data.deliveries.push(delivery.clone()),
+        }
+        Ok(())
+    }
+
+    fn deliveries(&self, key: &DeliveryKey) -> Result<Vec<Delivery>, StorageError> {
+        let matches = |delivery: &&Delivery| match key {
+            DeliveryKey::Queue(id) => delivery.queue_id == *id,
+            DeliveryKey::Envid(envid) => delivery.envid.as_ref() == Some(envid),
+            DeliveryKey::MessageId(id) => delivery.message_id.as_ref() == Some(id),
+        };
+        Ok(self.data().deliveries.iter().filter(matches).cloned().collect())
+    }
+
+    fn prune_deliveries(&self, before: DateTime<Utc>) -> Result<usize, StorageError> {
+        let mut data = self.data();
+        let count = data.deliveries.len();
+        data.deliveries.retain(|delivery| delivery.updated_at >= before);
+        Ok(count - data.deliveries.len())
+    }
+}
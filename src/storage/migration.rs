@@ -0,0 +1,407 @@
+/* 数据库结构版本 */
+/*
+每个版本包含升级（up）与回滚（down）两段 SQL，按 version 顺序执行。
+已经发布的版本不可修改，结构变更请追加新版本。
+ */
+
+/// 数据库结构的一个版本
+#[derive(Debug)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+/// PostgreSQL 数据库结构
+pub const POSTGRES: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "初始结构",
+        up: r#"
+CREATE TABLE domains (
+    id BIGSERIAL PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE accounts (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id BIGINT NOT NULL REFERENCES domains (id) ON DELETE CASCADE,
+    local_part TEXT NOT NULL,
+    password TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (domain_id, local_part)
+);
+
+CREATE TABLE mailboxes (
+    id BIGSERIAL PRIMARY KEY,
+    account_id BIGINT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    uid_validity BIGINT NOT NULL,
+    uid_next BIGINT NOT NULL DEFAULT 1,
+    subscribed BOOLEAN NOT NULL DEFAULT TRUE,
+    UNIQUE (account_id, name)
+);
+
+CREATE TABLE messages (
+    id BIGSERIAL PRIMARY KEY,
+    mailbox_id BIGINT NOT NULL REFERENCES mailboxes (id) ON DELETE CASCADE,
+    uid BIGINT NOT NULL,
+    flags TEXT NOT NULL DEFAULT '',
+    internal_date TIMESTAMPTZ NOT NULL DEFAULT now(),
+    size BIGINT NOT NULL,
+    raw BYTEA NOT NULL,
+    UNIQUE (mailbox_id, uid)
+);
+
+CREATE TABLE queue (
+    id BIGSERIAL PRIMARY KEY,
+    sender TEXT NOT NULL,
+    raw BYTEA NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE TABLE queue_recipients (
+    id BIGSERIAL PRIMARY KEY,
+    queue_id BIGINT NOT NULL REFERENCES queue (id) ON DELETE CASCADE,
+    recipient TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt TIMESTAMPTZ NOT NULL DEFAULT now(),
+    last_error TEXT
+);
+
+CREATE INDEX queue_recipients_next_attempt ON queue_recipients (next_attempt);
+"#,
+        down: r#"
+DROP TABLE queue_recipients;
+DROP TABLE queue;
+DROP TABLE messages;
+DROP TABLE mailboxes;
+DROP TABLE accounts;
+DROP TABLE domains;
+"#,
+    },
+    Migration {
+        version: 2,
+        name: "别名与 SCRAM",
+        up: r#"
+ALTER TABLE accounts ADD COLUMN scram TEXT NOT NULL DEFAULT '';
+
+CREATE TABLE aliases (
+    id BIGSERIAL PRIMARY KEY,
+    domain_id BIGINT NOT NULL REFERENCES domains (id) ON DELETE CASCADE,
+    local_part TEXT NOT NULL,
+    account_id BIGINT NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    UNIQUE (domain_id, local_part)
+);
+"#,
+        down: r#"
+DROP TABLE aliases;
+ALTER TABLE accounts DROP COLUMN scram;
+"#,
+    },
+    Migration {
+        version: 3,
+        name: "DSN 参数",
+        up: r#"
+ALTER TABLE queue ADD COLUMN ret TEXT, ADD COLUMN envid TEXT;
+ALTER TABLE queue_recipients ADD COLUMN notify TEXT, ADD COLUMN orcpt TEXT;
+"#,
+        down: r#"
+ALTER TABLE queue_recipients DROP COLUMN orcpt, DROP COLUMN notify;
+ALTER TABLE queue DROP COLUMN envid, DROP COLUMN ret;
+"#,
+    },
+    Migration {
+        version: 4,
+        name: "延迟通知",
+        up: r#"
+ALTER TABLE queue ADD COLUMN warned BOOLEAN NOT NULL DEFAULT FALSE;
+"#,
+        down: r#"
+ALTER TABLE queue DROP COLUMN warned;
+"#,
+    },
+    Migration {
+        version: 5,
+        name: "暂停投递",
+        up: r#"
+ALTER TABLE queue ADD COLUMN held BOOLEAN NOT NULL DEFAULT FALSE;
+"#,
+        down: r#"
+ALTER TABLE queue DROP COLUMN held;
+"#,
+    },
+    Migration {
+        version: 6,
+        name: "投递状态",
+        up: r#"
+CREATE TABLE deliveries (
+    id BIGSERIAL PRIMARY KEY,
+    queue_id BIGINT NOT NULL,
+    envid TEXT,
+    message_id TEXT,
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    action TEXT NOT NULL,
+    status TEXT,
+    diagnostic TEXT,
+    remote_mta TEXT,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    UNIQUE (queue_id, recipient)
+);
+
+CREATE INDEX deliveries_envid ON deliveries (envid);
+CREATE INDEX deliveries_message_id ON deliveries (message_id);
+CREATE INDEX deliveries_updated_at ON deliveries (updated_at);
+"#,
+        down: r#"
+DROP TABLE deliveries;
+"#,
+    },
+    Migration {
+        version: 7,
+        name: "修改序列",
+        up: r#"
+ALTER TABLE mailboxes ADD COLUMN highest_modseq BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE messages ADD COLUMN modseq BIGINT NOT NULL DEFAULT 1;
+
+CREATE TABLE vanished (
+    mailbox_id BIGINT NOT NULL REFERENCES mailboxes (id) ON DELETE CASCADE,
+    uid BIGINT NOT NULL,
+    modseq BIGINT NOT NULL,
+    PRIMARY KEY (mailbox_id, uid)
+);
+
+CREATE INDEX messages_modseq ON messages (mailbox_id, modseq);
+CREATE INDEX vanished_modseq ON vanished (mailbox_id, modseq);
+"#,
+        down: r#"
+DROP TABLE vanished;
+DROP INDEX messages_modseq;
+ALTER TABLE messages DROP COLUMN modseq;
+ALTER TABLE mailboxes DROP COLUMN highest_modseq;
+"#,
+    },
+    Migration {
+        version: 8,
+        name: "POP3 设置",
+        up: r#"
+ALTER TABLE accounts ADD COLUMN pop3_delete BOOLEAN NOT NULL DEFAULT FALSE;
+"#,
+        down: r#"
+ALTER TABLE accounts DROP COLUMN pop3_delete;
+"#,
+    },
+    Migration {
+        version: 9,
+        name: "移除邮件的 id",
+        up: r#"
+ALTER TABLE vanished ADD COLUMN message_id BIGINT;
+"#,
+        down: r#"
+ALTER TABLE vanished DROP COLUMN message_id;
+"#,
+    },
+];
+
+/// SQLite 数据库结构
+/// 与 POSTGRES 保持相同的版本号，时间以 Unix 时间戳（秒）保存
+pub const SQLITE: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "初始结构",
+        up: r#"
+CREATE TABLE domains (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL UNIQUE,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE TABLE accounts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    domain_id INTEGER NOT NULL REFERENCES domains (id) ON DELETE CASCADE,
+    local_part TEXT NOT NULL,
+    password TEXT NOT NULL DEFAULT '',
+    created_at INTEGER NOT NULL DEFAULT (unixepoch()),
+    UNIQUE (domain_id, local_part)
+);
+
+CREATE TABLE mailboxes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    account_id INTEGER NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    uid_validity INTEGER NOT NULL,
+    uid_next INTEGER NOT NULL DEFAULT 1,
+    subscribed INTEGER NOT NULL DEFAULT 1,
+    UNIQUE (account_id, name)
+);
+
+CREATE TABLE messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    mailbox_id INTEGER NOT NULL REFERENCES mailboxes (id) ON DELETE CASCADE,
+    uid INTEGER NOT NULL,
+    flags TEXT NOT NULL DEFAULT '',
+    internal_date INTEGER NOT NULL DEFAULT (unixepoch()),
+    size INTEGER NOT NULL,
+    raw BLOB NOT NULL,
+    UNIQUE (mailbox_id, uid)
+);
+
+CREATE TABLE queue (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender TEXT NOT NULL,
+    raw BLOB NOT NULL,
+    created_at INTEGER NOT NULL DEFAULT (unixepoch())
+);
+
+CREATE TABLE queue_recipients (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL REFERENCES queue (id) ON DELETE CASCADE,
+    recipient TEXT NOT NULL,
+    domain TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt INTEGER NOT NULL DEFAULT (unixepoch()),
+    last_error TEXT
+);
+
+CREATE INDEX queue_recipients_next_attempt ON queue_recipients (next_attempt);
+"#,
+        down: r#"
+DROP TABLE queue_recipients;
+DROP TABLE queue;
+DROP TABLE messages;
+DROP TABLE mailboxes;
+DROP TABLE accounts;
+DROP TABLE domains;
+"#,
+    },
+    Migration {
+        version: 2,
+        name: "别名与 SCRAM",
+        up: r#"
+ALTER TABLE accounts ADD COLUMN scram TEXT NOT NULL DEFAULT '';
+
+CREATE TABLE aliases (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    domain_id INTEGER NOT NULL REFERENCES domains (id) ON DELETE CASCADE,
+    local_part TEXT NOT NULL,
+    account_id INTEGER NOT NULL REFERENCES accounts (id) ON DELETE CASCADE,
+    UNIQUE (domain_id, local_part)
+);
+"#,
+        down: r#"
+DROP TABLE aliases;
+ALTER TABLE accounts DROP COLUMN scram;
+"#,
+    },
+    Migration {
+        version: 3,
+        name: "DSN 参数",
+        up: r#"
+ALTER TABLE queue ADD COLUMN ret TEXT;
+ALTER TABLE queue ADD COLUMN envid TEXT;
+ALTER TABLE queue_recipients ADD COLUMN notify TEXT;
+ALTER TABLE queue_recipients ADD COLUMN orcpt TEXT;
+"#,
+        down: r#"
+ALTER TABLE queue_recipients DROP COLUMN orcpt;
+ALTER TABLE queue_recipients DROP COLUMN notify;
+ALTER TABLE queue DROP COLUMN envid;
+ALTER TABLE queue DROP COLUMN ret;
+"#,
+    },
+    Migration {
+        version: 4,
+        name: "延迟通知",
+        up: r#"
+ALTER TABLE queue ADD COLUMN warned INTEGER NOT NULL DEFAULT 0;
+"#,
+        down: r#"
+ALTER TABLE queue DROP COLUMN warned;
+"#,
+    },
+    Migration {
+        version: 5,
+        name: "暂停投递",
+        up: r#"
+ALTER TABLE queue ADD COLUMN held INTEGER NOT NULL DEFAULT 0;
+"#,
+        down: r#"
+ALTER TABLE queue DROP COLUMN held;
+"#,
+    },
+    Migration {
+        version: 6,
+        name: "投递状态",
+        up: r#"
+CREATE TABLE deliveries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    queue_id INTEGER NOT NULL,
+    envid TEXT,
+    message_id TEXT,
+    sender TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    action TEXT NOT NULL,
+    status TEXT,
+    diagnostic TEXT,
+    remote_mta TEXT,
+    updated_at INTEGER NOT NULL DEFAULT (unixepoch()),
+    UNIQUE (queue_id, recipient)
+);
+
+CREATE INDEX deliveries_envid ON deliveries (envid);
+CREATE INDEX deliveries_message_id ON deliveries (message_id);
+CREATE INDEX deliveries_updated_at ON deliveries (updated_at);
+"#,
+        down: r#"
+DROP TABLE deliveries;
+"#,
+    },
+    Migration {
+        version: 7,
+        name: "修改序列",
+        up: r#"
+ALTER TABLE mailboxes ADD COLUMN highest_modseq INTEGER NOT NULL DEFAULT 1;
+ALTER TABLE messages ADD COLUMN modseq INTEGER NOT NULL DEFAULT 1;
+
+CREATE TABLE vanished (
+    mailbox_id INTEGER NOT NULL REFERENCES mailboxes (id) ON DELETE CASCADE,
+    uid INTEGER NOT NULL,
+    modseq INTEGER NOT NULL,
+    PRIMARY KEY (mailbox_id, uid)
+);
+
+CREATE INDEX messages_modseq ON messages (mailbox_id, modseq);
+CREATE INDEX vanished_modseq ON vanished (mailbox_id, modseq);
+"#,
+        down: r#"
+DROP TABLE vanished;
+DROP INDEX messages_modseq;
+ALTER TABLE messages DROP COLUMN modseq;
+ALTER TABLE mailboxes DROP COLUMN highest_modseq;
+"#,
+    },
+    Migration {
+        version: 8,
+        name: "POP3 设置",
+        up: r#"
+ALTER TABLE accounts ADD COLUMN pop3_delete INTEGER NOT NULL DEFAULT 0;
+"#,
+        down: r#"
+ALTER TABLE accounts DROP COLUMN pop3_delete;
+"#,
+    },
+    Migration {
+        version: 9,
+        name: "移除邮件的 id",
+        up: r#"
+ALTER TABLE vanished ADD COLUMN message_id INTEGER;
+"#,
+        down: r#"
+ALTER TABLE vanished DROP COLUMN message_id;
+"#,
+    },
+];
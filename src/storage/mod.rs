@@ -0,0 +1,526 @@
+/* 数据存储 */
+/*
+# 存储模块
+## 用法
+let store = storage::open(&config.database)?;   <-- 按 [Database] Backend 选择后端
+store.migrate()?;                               <-- 启动时自动升级数据库结构
+命令 zmou db migrate|status|rollback 手动管理数据库结构
+## 后端
+PostgreSQL  生产环境
+SQLite      单文件，适合小型部署
+Memory      内存，仅用于测试，重启后数据丢失
+ */
+use chrono::{DateTime, Utc};
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::config::{Backend, Database};
+use migration::Migration;
+
+pub mod command;
+pub mod memory;
+pub mod migration;
+pub mod postgres;
+pub mod sqlite;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// 无法连接数据库
+    Connect(String),
+    /// 查询失败
+    Query(String),
+    /// 数据库结构版本错误
+    Migration(String),
+    /// 对象已存在
+    Conflict(String),
+    /// 对象不存在
+    NotFound(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Connect(e) => write!(f, "无法连接数据库：{}", e),
+            StorageError::Query(e) => write!(f, "数据库查询失败：{}", e),
+            StorageError::Migration(e) => write!(f, "数据库结构版本错误：{}", e),
+            StorageError::Conflict(e) => write!(f, "{} 已存在", e),
+            StorageError::NotFound(e) => write!(f, "{} 不存在", e),
+        }
+    }
+}
+
+/// 邮件账号
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub id: i64,
+    pub local_part: String,
+    pub domain: String,
+    /// Argon2 PHC 字符串
+    pub password: String,
+    /// SCRAM-SHA-256 凭据，见 password::scram
+    pub scram: String,
+    /// POP3 取回邮件后从服务器删除，默认保留
+    pub pop3_delete: bool,
+}
+
+impl Account {
+    /// # 邮件地址
+    pub fn address(&self) -> String {
+        format!("{}@{}", self.local_part, self.domain)
+    }
+}
+
+/// 邮箱（文件夹）
+#[derive(Debug, Clone)]
+pub struct Mailbox {
+    pub id: i64,
+    pub account_id: i64,
+    pub name: String,
+    pub uid_validity: u32,
+    pub uid_next: u32,
+    pub subscribed: bool,
+    /// 邮箱中最后一次修改的修改序列（RFC 7162）
+    pub highest_modseq: u64,
+}
+
+/// 邮件（不含原文）
+#[derive(Debug, Clone)]
+pub struct MessageInfo {
+    pub id: i64,
+    pub mailbox_id: i64,
+    pub uid: u32,
+    pub flags: Vec<String>,
+    pub internal_date: DateTime<Utc>,
+    pub size: u64,
+    /// 最后一次修改（添加或修改标记）的修改序列
+    pub modseq: u64,
+}
+
+/// 投递队列中的一封邮件
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub id: i64,
+    pub sender: String,
+    pub dsn: Dsn,
+    pub created_at: DateTime<Utc>,
+    /// 已发送延迟通知
+    pub warned: bool,
+    /// 已暂停投递，见 zmou queue hold
+    pub held: bool,
+    pub recipients: Vec<QueueRecipient>,
+}
+
+/// 投递队列中的一个收件人
+#[derive(Debug, Clone)]
+pub struct QueueRecipient {
+    pub id: i64,
+    pub recipient: String,
+    pub domain: String,
+    pub attempts: i32,
+    pub next_attempt: DateTime<Utc>,
+    pub last_error: Option<String>,
+    pub dsn: RecipientDsn,
+}
+
+/// 信封的 DSN 参数（RFC 3461 4.3、4.4）
+/// ENVID 保存 xtext 解码后的值。
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Dsn {
+    /// FULL 或 HDRS
+    pub ret: Option<String>,
+    pub envid: Option<String>,
+}
+
+/// 收件人的 DSN 参数（RFC 3461 4.1、4.2）
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RecipientDsn {
+    /// NEVER，或 SUCCESS、FAILURE、DELAY 以逗号连接
+    pub notify: Option<String>,
+    /// 原始收件人，例如 rfc822;manser@example.com，保存 xtext 解码后的值
+    pub orcpt: Option<String>,
+}
+
+/// 收件人的投递状态，邮件离开队列后仍然保留，见 API /delivery
+#[derive(Debug, Clone, PartialEq)]
+pub struct Delivery {
+    pub queue_id: i64,
+    pub envid: Option<String>,
+    /// 原邮件的 Message-ID，不含尖括号
+    pub message_id: Option<String>,
+    pub sender: String,
+    pub recipient: String,
+    /// queued、sent，或 DSN 的 Action（RFC 3464 2.3.3）：delayed、delivered、relayed、expanded、failed
+    pub action: String,
+    /// 增强状态码，例如 5.1.1
+    pub status: Option<String>,
+    /// 远程服务器的应答或失败原因
+    pub diagnostic: Option<String>,
+    pub remote_mta: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// 查询投递状态的条件
+#[derive(Debug, Clone)]
+pub enum DeliveryKey {
+    Queue(i64),
+    Envid(String),
+    MessageId(String),
+}
+
+/// 邮件存储
+/// 所有后端行为一致：地址不区分大小写，新账号自带 INBOX，UID 在邮箱内递增且不复用。
+/// 添加、删除、移动邮件与修改标记都会使所在邮箱的修改序列加 1，删除的 UID 与当时的修改序列一并记录。
+pub trait MailStore: Send + Sync {
+    /// 后端名称
+    fn backend(&self) -> &'static str;
+
+    // 数据库结构
+    fn migrations(&self) -> &'static [Migration];
+    fn applied(&self) -> Result<Vec<(i64, DateTime<Utc>)>, StorageError>;
+    fn migrate(&self) -> Result<Vec<&'static Migration>, StorageError>;
+    fn rollback(&self) -> Result<Option<&'static Migration>, StorageError>;
+
+    // 域名
+    fn add_domain(&self, name: &str) -> Result<(), StorageError>;
+    fn delete_domain(&self, name: &str) -> Result<(), StorageError>;
+    fn domains(&self) -> Result<Vec<String>, StorageError>;
+    fn has_domain(&self, name: &str) -> Result<bool, StorageError>;
+
+    // 账号
+    fn add_account(&self, local_part: &str, domain: &str, password: &str, scram: &str) -> Result<Account, StorageError>;
+    fn delete_account(&self, id: i64) -> Result<(), StorageError>;
+    fn account(&self, address: &str) -> Result<Option<Account>, StorageError>;
+    fn accounts(&self) -> Result<Vec<Account>, StorageError>;
+    fn set_password(&self, id: i64, password: &str, scram: &str) -> Result<(), StorageError>;
+    fn set_pop3_delete(&self, id: i64, delete: bool) -> Result<(), StorageError>;
+
+    // 别名，地址不能与账号重复
+    fn add_alias(&self, address: &str, account_id: i64) -> Result<(), StorageError>;
+    fn delete_alias(&self, address: &str) -> Result<(), StorageError>;
+    /// (别名, 所属账号的地址)
+    fn aliases(&self) -> Result<Vec<(String, String)>, StorageError>;
+    /// 按地址查找账号，地址可以是别名
+    fn resolve(&self, address: &str) -> Result<Option<Account>, StorageError>;
+
+    // 邮箱
+    fn create_mailbox(&self, account_id: i64, name: &str) -> Result<Mailbox, StorageError>;
+    fn mailbox(&self, account_id: i64, name: &str) -> Result<Option<Mailbox>, StorageError>;
+    fn mailboxes(&self, account_id: i64) -> Result<Vec<Mailbox>, StorageError>;
+    fn rename_mailbox(&self, id: i64, name: &str) -> Result<(), StorageError>;
+    fn delete_mailbox(&self, id: i64) -> Result<(), StorageError>;
+    fn set_subscribed(&self, id: i64, subscribed: bool) -> Result<(), StorageError>;
+
+    // 邮件
+    fn append(&self, mailbox_id: i64, raw: &[u8], flags: &[String], internal_date: DateTime<Utc>) -> Result<MessageInfo, StorageError>;
+    fn messages(&self, mailbox_id: i64) -> Result<Vec<MessageInfo>, StorageError>;
+    fn raw(&self, message_id: i64) -> Result<Option<Vec<u8>>, StorageError>;
+    /// 返回新的修改序列；unchanged_since 不为空且邮件的修改序列更大时不修改，返回 None
+    fn set_flags(&self, message_id: i64, flags: &[String], unchanged_since: Option<u64>) -> Result<Option<u64>, StorageError>;
+    fn expunge(&self, message_ids: &[i64]) -> Result<(), StorageError>;
+    fn copy(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError>;
+    /// 移动到另一个邮箱，保留邮件 id，在目标邮箱中分配新的 UID，原邮箱记录删除
+    fn move_message(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError>;
+    /// 修改序列大于 since 之后删除的 UID，按 UID 递增
+    fn vanished(&self, mailbox_id: i64, since: u64) -> Result<Vec<u32>, StorageError>;
+    /// 修改序列大于 since 之后删除或移出的邮件 id，不包括版本 9 之前删除的邮件
+    fn removed(&self, mailbox_id: i64, since: u64) -> Result<Vec<i64>, StorageError>;
+
+    // 投递队列
+    fn enqueue(&self, sender: &str, dsn: &Dsn, recipients: &[(String, RecipientDsn)], raw: &[u8]) -> Result<i64, StorageError>;
+    fn queue(&self) -> Result<Vec<QueueEntry>, StorageError>;
+    fn queue_raw(&self, id: i64) -> Result<Option<Vec<u8>>, StorageError>;
+    fn dequeue(&self, id: i64) -> Result<(), StorageError>;
+    /// 投递失败，attempts 加 1 并记录错误，在 next_attempt 之后重试
+    fn defer(&self, recipient_id: i64, next_attempt: DateTime<Utc>, error: &str) -> Result<(), StorageError>;
+    /// 收件人已投递或已退信，邮件没有其他收件人时一并删除
+    fn complete(&self, recipient_id: i64) -> Result<(), StorageError>;
+    fn set_warned(&self, id: i64) -> Result<(), StorageError>;
+    fn set_held(&self, id: i64, held: bool) -> Result<(), StorageError>;
+    /// 将邮件所有收件人的下次投递时间设为 next_attempt
+    fn reschedule(&self, id: i64, next_attempt: DateTime<Utc>) -> Result<(), StorageError>;
+
+    // 投递状态
+    /// 同一邮件的同一收件人只保留最新的状态
+    fn set_delivery(&self, delivery: &Delivery) -> Result<(), StorageError>;
+    fn deliveries(&self, key: &DeliveryKey) -> Result<Vec<Delivery>, StorageError>;
+    /// 删除 before 之前更新的状态，返回删除的条数
+    fn prune_deliveries(&self, before: DateTime<Utc>) -> Result<usize, StorageError>;
+}
+
+/// # 打开存储
+/// ## 参数
+/// - database: [Database]，Password 需要先通过 Config::resolve_secrets 解析
+/// ## 返回值
+/// - Result<Arc<dyn MailStore>, StorageError>
+pub fn open(database: &Database) -> Result<Arc<dyn MailStore>, StorageError> {
+    Ok(match database.backend {
+        Backend::PostgreSQL => Arc::new(postgres::PostgresStore::connect(database)?),
+        Backend::SQLite => Arc::new(sqlite::SqliteStore::open(&database.path)?),
+        Backend::Memory => Arc::new(memory::MemoryStore::new()),
+    })
+}
+
+/// # 拆分邮件地址
+/// 统一转换为小写
+/// ## 参数
+/// - address: 例如 Manser@Example.com
+/// ## 返回值
+/// - Option<(本地部分, 域名)>
+pub fn split_address(address: &str) -> Option<(String, String)> {
+    let (local_part, domain) = address.trim().rsplit_once('@')?;
+    if local_part.is_empty() || domain.is_empty() {
+        return None;
+    }
+    Some((local_part.to_lowercase(), domain.to_lowercase()))
+}
+
+/// 上一次分配的 UIDVALIDITY
+static LAST_UID_VALIDITY: AtomicU32 = AtomicU32::new(0);
+
+/// # 新邮箱的 UIDVALIDITY
+/// 使用当前时间（秒），同一秒内分配多个时依次加 1，保证删除后立即重建的同名邮箱 UIDVALIDITY 不同（RFC 9051 2.3.1.1）
+pub fn new_uid_validity() -> u32 {
+    let now = Utc::now().timestamp() as u32;
+    let previous = LAST_UID_VALIDITY.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| Some(now.max(last.saturating_add(1)))).unwrap();
+    now.max(previous.saturating_add(1))
+}
+
+/// # 将标记列表转换为存储格式
+pub fn join_flags(flags: &[String]) -> String {
+    flags.join(" ")
+}
+
+/// # 将存储格式转换为标记列表
+pub fn split_flags(flags: &str) -> Vec<String> {
+    flags.split_whitespace().map(str::to_string).collect()
+}
+
+/// 所有后端共用的一致性测试，PostgreSQL 的测试需要设置 ZITMAIL_TEST_PG，见 postgres::throwaway
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 固定的时间，避免各后端时间精度不同
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(seconds, 0).unwrap()
+    }
+
+    fn conformance(store: &dyn MailStore) {
+        store.migrate().unwrap();
+        domains_accounts_aliases(store);
+        mailboxes(store);
+        uids_and_modseqs(store);
+        queue_and_deliveries(store);
+    }
+
+    fn domains_accounts_aliases(store: &dyn MailStore) {
+        store.add_domain("Example.COM").unwrap();
+        assert!(matches!(store.add_domain("example.com"), Err(StorageError::Conflict(_))));
+        assert!(store.domains().unwrap().contains(&String::from("example.com")));
+        assert!(store.has_domain("EXAMPLE.com").unwrap());
+        assert!(!store.has_domain("example.org").unwrap());
+
+        let bob = store.add_account("Bob", "example.com", "hash", "scram").unwrap();
+        assert_eq!(bob.address(), "bob@example.com");
+        assert!(matches!(store.add_account("bob", "EXAMPLE.com", "", ""), Err(StorageError::Conflict(_))));
+        assert!(matches!(store.add_account("bob", "example.org", "", ""), Err(StorageError::NotFound(_))));
+        assert_eq!(store.account("BOB@Example.com").unwrap().map(|account| account.id), Some(bob.id));
+        assert!(store.mailbox(bob.id, "INBOX").unwrap().is_some());
+
+        store.set_password(bob.id, "new hash", "new scram").unwrap();
+        store.set_pop3_delete(bob.id, true).unwrap();
+        let account = store.account("bob@example.com").unwrap().unwrap();
+        assert_eq!((account.password.as_str(), account.scram.as_str(), account.pop3_delete), ("new hash", "new scram", true));
+
+        store.add_alias("Info@example.com", bob.id).unwrap();
+        assert_eq!(store.aliases().unwrap(), [(String::from("info@example.com"), String::from("bob@example.com"))]);
+        assert_eq!(store.resolve("INFO@example.com").unwrap().map(|account| account.id), Some(bob.id));
+        assert_eq!(store.resolve("bob@example.com").unwrap().map(|account| account.id), Some(bob.id));
+        assert!(store.resolve("nobody@example.com").unwrap().is_none());
+        assert!(matches!(store.add_alias("bob@example.com", bob.id), Err(StorageError::Conflict(_))));
+        assert!(matches!(store.add_alias("info@example.com", bob.id), Err(StorageError::Conflict(_))));
+        assert!(matches!(store.add_account("info", "example.com", "", ""), Err(StorageError::Conflict(_))));
+        assert!(matches!(store.add_alias("sales@example.org", bob.id), Err(StorageError::NotFound(_))));
+
+        store.delete_alias("info@example.com").unwrap();
+        assert!(store.resolve("info@example.com").unwrap().is_none());
+        assert!(matches!(store.delete_alias("info@example.com"), Err(StorageError::NotFound(_))));
+
+        store.delete_account(bob.id).unwrap();
+        assert!(store.account("bob@example.com").unwrap().is_none());
+        assert!(store.mailboxes(bob.id).unwrap().is_empty());
+        assert!(matches!(store.delete_account(bob.id), Err(StorageError::NotFound(_))));
+
+        // 删除域名时一并删除其下的账号与别名
+        let alice = store.add_account("alice", "example.com", "", "").unwrap();
+        store.add_alias("admin@example.com", alice.id).unwrap();
+        store.delete_domain("example.com").unwrap();
+        assert!(!store.has_domain("example.com").unwrap());
+        assert!(store.account("alice@example.com").unwrap().is_none());
+        assert!(store.aliases().unwrap().is_empty());
+        assert!(matches!(store.delete_domain("example.com"), Err(StorageError::NotFound(_))));
+    }
+
+    fn mailboxes(store: &dyn MailStore) {
+        store.add_domain("mailboxes.test").unwrap();
+        let account = store.add_account("carol", "mailboxes.test", "", "").unwrap();
+
+        let archive = store.create_mailbox(account.id, "Archive").unwrap();
+        assert_eq!((archive.uid_next, archive.subscribed), (1, true));
+        assert!(matches!(store.create_mailbox(account.id, "Archive"), Err(StorageError::Conflict(_))));
+        let names = store.mailboxes(account.id).unwrap().into_iter().map(|mailbox| mailbox.name).collect::<Vec<_>>();
+        assert_eq!(names, ["Archive", "INBOX"]);
+
+        store.rename_mailbox(archive.id, "Old").unwrap();
+        assert!(store.mailbox(account.id, "Archive").unwrap().is_none());
+        assert_eq!(store.mailbox(account.id, "Old").unwrap().map(|mailbox| mailbox.id), Some(archive.id));
+        assert!(matches!(store.rename_mailbox(archive.id, "INBOX"), Err(StorageError::Conflict(_))));
+
+        store.set_subscribed(archive.id, false).unwrap();
+        assert!(!store.mailbox(account.id, "Old").unwrap().unwrap().subscribed);
+
+        store.append(archive.id, b"Subject: a\r\n\r\nbody\r\n", &[], at(1_700_000_000)).unwrap();
+        store.delete_mailbox(archive.id).unwrap();
+        assert!(store.mailbox(account.id, "Old").unwrap().is_none());
+        assert!(matches!(store.delete_mailbox(archive.id), Err(StorageError::NotFound(_))));
+        assert!(matches!(store.rename_mailbox(archive.id, "New"), Err(StorageError::NotFound(_))));
+
+        // 删除后立即重建的同名邮箱使用新的 UIDVALIDITY，UID 从 1 开始
+        let recreated = store.create_mailbox(account.id, "Old").unwrap();
+        assert!(recreated.uid_validity > archive.uid_validity);
+        assert_eq!(recreated.uid_next, 1);
+    }
+
+    fn uids_and_modseqs(store: &dyn MailStore) {
+        store.add_domain("uids.test").unwrap();
+        let account = store.add_account("dave", "uids.test", "", "").unwrap();
+        let inbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        let archive = store.create_mailbox(account.id, "Archive").unwrap();
+
+        let raw: &[u8] = b"Subject: test\r\n\r\nbody\r\n";
+        let seen = [String::from("\\Seen")];
+        let appended = (0..3).map(|_| store.append(inbox.id, raw, &seen, at(1_700_000_000)).unwrap()).collect::<Vec<_>>();
+        assert_eq!(appended.iter().map(|message| message.uid).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(appended.windows(2).all(|pair| pair[0].modseq < pair[1].modseq));
+        assert_eq!(appended[0].size, raw.len() as u64);
+        assert_eq!(appended[0].flags, seen);
+        assert_eq!(appended[0].internal_date, at(1_700_000_000));
+        assert_eq!(store.raw(appended[0].id).unwrap().as_deref(), Some(raw));
+        let mailbox = store.mailbox(account.id, "INBOX").unwrap().unwrap();
+        assert_eq!((mailbox.uid_next, mailbox.highest_modseq), (4, appended[2].modseq));
+
+        // 修改标记
+        let modseq = store.set_flags(appended[0].id, &[String::from("\\Flagged")], None).unwrap().unwrap();
+        assert!(modseq > appended[2].modseq);
+        assert!(store.set_flags(appended[0].id, &[], Some(appended[0].modseq)).unwrap().is_none());
+        assert_eq!(store.messages(inbox.id).unwrap()[0].flags, [String::from("\\Flagged")]);
+
+        // 删除的 UID 不再复用，并记录在删除时的修改序列之后
+        store.expunge(&[appended[1].id]).unwrap();
+        assert_eq!(store.messages(inbox.id).unwrap().iter().map(|message| message.uid).collect::<Vec<_>>(), [1, 3]);
+        assert_eq!(store.vanished(inbox.id, modseq).unwrap(), [2]);
+        assert_eq!(store.removed(inbox.id, modseq).unwrap(), [appended[1].id]);
+        let expunged = store.mailbox(account.id, "INBOX").unwrap().unwrap().highest_modseq;
+        assert!(expunged > modseq);
+        assert!(store.vanished(inbox.id, expunged).unwrap().is_empty());
+        assert_eq!(store.append(inbox.id, raw, &[], at(1_700_000_000)).unwrap().uid, 4);
+
+        // 复制分配新的 id 与 UID，移动保留 id
+        let copied = store.copy(appended[0].id, archive.id).unwrap();
+        assert_ne!(copied.id, appended[0].id);
+        assert_eq!((copied.mailbox_id, copied.uid), (archive.id, 1));
+        let before = store.mailbox(account.id, "INBOX").unwrap().unwrap().highest_modseq;
+        let moved = store.move_message(appended[2].id, archive.id).unwrap();
+        assert_eq!((moved.id, moved.mailbox_id, moved.uid), (appended[2].id, archive.id, 2));
+        assert_eq!(store.vanished(inbox.id, before).unwrap(), [3]);
+        assert_eq!(store.removed(inbox.id, before).unwrap(), [appended[2].id]);
+        assert_eq!(store.raw(moved.id).unwrap().as_deref(), Some(raw));
+        assert!(store.mailbox(account.id, "INBOX").unwrap().unwrap().highest_modseq > before);
+        assert!(matches!(store.set_flags(appended[1].id, &[], None), Err(StorageError::NotFound(_))));
+    }
+
+    fn queue_and_deliveries(store: &dyn MailStore) {
+        let dsn = Dsn { ret: Some(String::from("HDRS")), envid: Some(String::from("envelope-1")) };
+        let notify = RecipientDsn { notify: Some(String::from("SUCCESS,FAILURE")), orcpt: Some(String::from("rfc822;a@x.test")) };
+        let recipients = [(String::from("a@x.test"), notify.clone()), (String::from("b@Y.test"), RecipientDsn::default())];
+        let id = store.enqueue("sender@example.com", &dsn, &recipients, b"raw message").unwrap();
+
+        let entry = store.queue().unwrap().into_iter().find(|entry| entry.id == id).unwrap();
+        assert_eq!((entry.sender.as_str(), &entry.dsn, entry.warned, entry.held), ("sender@example.com", &dsn, false, false));
+        assert_eq!(entry.recipients.iter().map(|recipient| recipient.domain.as_str()).collect::<Vec<_>>(), ["x.test", "y.test"]);
+        assert_eq!(entry.recipients[0].dsn, notify);
+        assert_eq!(entry.recipients[0].attempts, 0);
+        assert_eq!(store.queue_raw(id).unwrap().as_deref(), Some(&b"raw message"[..]));
+
+        let (first, second) = (entry.recipients[0].id, entry.recipients[1].id);
+        store.defer(first, at(2_000_000_000), "451 try later").unwrap();
+        store.set_warned(id).unwrap();
+        store.set_held(id, true).unwrap();
+        let entry = store.queue().unwrap().into_iter().find(|entry| entry.id == id).unwrap();
+        let recipient = entry.recipients.iter().find(|recipient| recipient.id == first).unwrap();
+        assert_eq!((recipient.attempts, recipient.next_attempt, recipient.last_error.as_deref()), (1, at(2_000_000_000), Some("451 try later")));
+        assert!(entry.warned && entry.held);
+
+        store.reschedule(id, at(2_100_000_000)).unwrap();
+        let entry = store.queue().unwrap().into_iter().find(|entry| entry.id == id).unwrap();
+        assert!(entry.recipients.iter().all(|recipient| recipient.next_attempt == at(2_100_000_000)));
+
+        // 最后一个收件人完成后邮件离开队列
+        store.complete(first).unwrap();
+        assert_eq!(store.queue().unwrap().into_iter().find(|entry| entry.id == id).unwrap().recipients.len(), 1);
+        store.complete(second).unwrap();
+        assert!(store.queue().unwrap().iter().all(|entry| entry.id != id));
+        assert!(store.queue_raw(id).unwrap().is_none());
+        assert!(matches!(store.complete(second), Err(StorageError::NotFound(_))));
+
+        let other = store.enqueue("sender@example.com", &Dsn::default(), &recipients[..1], b"raw").unwrap();
+        store.dequeue(other).unwrap();
+        assert!(matches!(store.dequeue(other), Err(StorageError::NotFound(_))));
+
+        // 同一收件人只保留最新的状态
+        let delivery = |action: &str, updated_at: i64| Delivery {
+            queue_id: id,
+            envid: Some(String::from("envelope-1")),
+            message_id: Some(String::from("1@example.com")),
+            sender: String::from("sender@example.com"),
+            recipient: String::from("a@x.test"),
+            action: action.to_string(),
+            status: Some(String::from("2.0.0")),
+            diagnostic: None,
+            remote_mta: Some(String::from("mx.x.test")),
+            updated_at: at(updated_at),
+        };
+        store.set_delivery(&delivery("queued", 1_700_000_000)).unwrap();
+        store.set_delivery(&delivery("delivered", 1_700_000_100)).unwrap();
+        assert_eq!(store.deliveries(&DeliveryKey::Queue(id)).unwrap(), [delivery("delivered", 1_700_000_100)]);
+        assert_eq!(store.deliveries(&DeliveryKey::Envid(String::from("envelope-1"))).unwrap().len(), 1);
+        assert_eq!(store.deliveries(&DeliveryKey::MessageId(String::from("1@example.com"))).unwrap().len(), 1);
+        assert!(store.deliveries(&DeliveryKey::Envid(String::from("other"))).unwrap().is_empty());
+        assert_eq!(store.prune_deliveries(at(1_700_000_050)).unwrap(), 0);
+        assert_eq!(store.prune_deliveries(at(1_700_000_200)).unwrap(), 1);
+        assert!(store.deliveries(&DeliveryKey::Queue(id)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn memory() {
+        conformance(&memory::MemoryStore::new());
+    }
+
+    #[test]
+    fn sqlite() {
+        let path = std::env::temp_dir().join(format!("zitmail-conformance-{}.db", std::process::id()));
+        let remove = || ["", "-wal", "-shm"].iter().for_each(|suffix| drop(std::fs::remove_file(format!("{}{}", path.display(), suffix))));
+        remove();
+        conformance(&sqlite::SqliteStore::open(&path.to_string_lossy()).unwrap());
+        remove();
+    }
+
+    #[test]
+    fn postgres() {
+        let Some(throwaway) = postgres::throwaway::Throwaway::create("conformance") else {
+            return;
+        };
+        conformance(&postgres::PostgresStore::connect(&throwaway.database).unwrap());
+    }
+}
@@ -0,0 +1,801 @@
+/* PostgreSQL */
+use chrono::{DateTime, Utc};
+use postgres::NoTls;
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use std::time::Duration;
+
+use super::migration::{Migration, POSTGRES};
+use super::{Account, Delivery, DeliveryKey, Dsn, MailStore, Mailbox, MessageInfo, QueueEntry, QueueRecipient, RecipientDsn, StorageError};
+use super::{join_flags, new_uid_validity, split_address, split_flags};
+use crate::config::Database;
+
+type Manager = PostgresConnectionManager<NoTls>;
+
+pub struct PostgresStore {
+    pool: Pool<Manager>,
+}
+
+impl From<postgres::Error> for StorageError {
+    fn from(e: postgres::Error) -> Self {
+        StorageError::Query(e.to_string())
+    }
+}
+
+impl PostgresStore {
+    /// # 连接数据库
+    /// 使用 [Database] 中的配置建立连接池，Password 需要先通过 Config::resolve_secrets 解析。
+    /// ## 参数
+    /// - database: &Database
+    /// ## 返回值
+    /// - Result<PostgresStore, StorageError>
+    pub fn connect(database: &Database) -> Result<PostgresStore, StorageError> {
+        let mut config = postgres::Config::new();
+        config
+            .host(&database.host)
+            .port(database.port)
+            .dbname(&database.database)
+            .user(&database.user)
+            .application_name("ZitMail")
+            .connect_timeout(Duration::from_secs(10));
+        if !database.password.expose().is_empty() {
+            config.password(database.password.expose());
+        }
+
+        let pool = Pool::builder()
+            .max_size(database.pool)
+            .connection_timeout(Duration::from_secs(10))
+            .error_handler(Box::new(r2d2::NopErrorHandler))
+            .build(Manager::new(config, NoTls))
+            .map_err(|e| StorageError::Connect(e.to_string()))?;
+        debug!("已连接数据库 {}:{}/{}", database.host, database.port, database.database);
+        Ok(PostgresStore { pool })
+    }
+
+    /// # 从连接池取出一个连接
+    fn client(&self) -> Result<PooledConnection<Manager>, StorageError> {
+        self.pool.get().map_err(|e| StorageError::Connect(e.to_string()))
+    }
+}
+
+fn account_from(row: &postgres::Row) -> Account {
+    Account {
+        id: row.get(0),
+        local_part: row.get(1),
+        domain: row.get(2),
+        password: row.get(3),
+        scram: row.get(4),
+        pop3_delete: row.get(5),
+    }
+}
+
+fn mailbox_from(row: &postgres::Row) -> Mailbox {
+    Mailbox {
+        id: row.get(0),
+        account_id: row.get(1),
+        name: row.get(2),
+        uid_validity: row.get::<_, i64>(3) as u32,
+        uid_next: row.get::<_, i64>(4) as u32,
+        subscribed: row.get(5),
+        highest_modseq: row.get::<_, i64>(6) as u64,
+    }
+}
+
+fn message_from(row: &postgres::Row) -> MessageInfo {
+    MessageInfo {
+        id: row.get(0),
+        mailbox_id: row.get(1),
+        uid: row.get::<_, i64>(2) as u32,
+        flags: split_flags(row.get(3)),
+        internal_date: row.get(4),
+        size: row.get::<_, i64>(5) as u64,
+        modseq: row.get::<_, i64>(6) as u64,
+    }
+}
+
+const ACCOUNT: &str = "SELECT a.id, a.local_part, d.name, a.password, a.scram, a.pop3_delete FROM accounts a JOIN domains d ON d.id = a.domain_id";
+const MAILBOX: &str = "SELECT id, account_id, name, uid_validity, uid_next, subscribed, highest_modseq FROM mailboxes";
+const MESSAGE: &str = "SELECT id, mailbox_id, uid, flags, internal_date, size, modseq FROM messages";
+
+/// 分配下一个 UID 与修改序列
+const NEXT_UID: &str =
+    "UPDATE mailboxes SET uid_next = uid_next + 1, highest_modseq = highest_modseq + 1 WHERE id = $1 RETURNING uid_next - 1, highest_modseq";
+
+impl MailStore for PostgresStore {
+    fn backend(&self) -> &'static str {
+        "PostgreSQL"
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        POSTGRES
+    }
+
+    fn applied(&self) -> Result<Vec<(i64, DateTime<Utc>)>, StorageError> {
+        let mut client = self.client()?;
+        client.batch_execute(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version BIGINT PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )",
+        )?;
+        let rows = client.query("SELECT version, applied_at FROM schema_migrations ORDER BY version", &[])?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    /// 每个版本在单独的事务中执行。
+    fn migrate(&self) -> Result<Vec<&'static Migration>, StorageError> {
+        let applied = self.applied()?;
+        let latest = applied.last().map(|(version, _)| *version).unwrap_or(0);
+        if let Some(known) = POSTGRES.last()
+            && latest > known.version
+        {
+            return Err(StorageError::Migration(format!("数据库版本 {} 高于程序支持的版本 {}，请更新 ZitMail", latest, known.version)));
+        }
+
+        let mut client = self.client()?;
+        let mut done = Vec::new();
+        for migration in POSTGRES.iter().filter(|migration| migration.version > latest) {
+            let mut transaction = client.transaction()?;
+            transaction
+                .batch_execute(migration.up)
+                .map_err(|e| StorageError::Migration(format!("版本 {} {}：{}", migration.version, migration.name, e)))?;
+            transaction.execute(
+                "INSERT INTO schema_migrations (version, name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )?;
+            transaction.commit()?;
+            info!("数据库已升级到版本 {} {}", migration.version, migration.name);
+            done.push(migration);
+        }
+        Ok(done)
+    }
+
+    fn rollback(&self) -> Result<Option<&'static Migration>, StorageError> {
+        let Some((latest, _)) = self.applied()?.pop() else {
+            return Ok(None);
+        };
+        let Some(migration) = POSTGRES.iter().find(|migration| migration.version == latest) else {
+            return Err(StorageError::Migration(format!("程序中没有版本 {} 的回滚脚本", latest)));
+        };
+
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        transaction
+            .batch_execute(migration.down)
+            .map_err(|e| StorageError::Migration(format!("回滚版本 {} {}：{}", migration.version, migration.name, e)))?;
+        transaction.execute("DELETE FROM schema_migrations WHERE version = $1", &[&migration.version])?;
+        transaction.commit()?;
+        Ok(Some(migration))
+    }
+
+    fn add_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        let inserted = self
+            .client()?
+            .execute("INSERT INTO domains (name) VALUES ($1) ON CONFLICT DO NOTHING", &[&name])?;
+        match inserted {
+            0 => Err(StorageError::Conflict(format!("域名 {}", name))),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        match self.client()?.execute("DELETE FROM domains WHERE name = $1", &[&name])? {
+            0 => Err(StorageError::NotFound(format!("域名 {}", name))),
+            _ => Ok(()),
+        }
+    }
+
+    fn domains(&self) -> Result<Vec<String>, StorageError> {
+        let rows = self.client()?.query("SELECT name FROM domains ORDER BY name", &[])?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn has_domain(&self, name: &str) -> Result<bool, StorageError> {
+        let row = self
+            .client()?
+            .query_opt("SELECT 1 FROM domains WHERE name = $1", &[&name.to_lowercase()])?;
+        Ok(row.is_some())
+    }
+
+    fn add_account(&self, local_part: &str, domain: &str, password: &str, scram: &str) -> Result<Account, StorageError> {
+        let (local_part, domain) = (local_part.to_lowercase(), domain.to_lowercase());
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt("SELECT id FROM domains WHERE name = $1", &[&domain])? else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        let domain_id: i64 = row.get(0);
+        let alias = transaction.query_opt(
+            "SELECT 1 FROM aliases WHERE domain_id = $1 AND local_part = $2",
+            &[&domain_id, &local_part],
+        )?;
+        if alias.is_some() {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        let Some(row) = transaction.query_opt(
+            "INSERT INTO accounts (domain_id, local_part, password, scram) VALUES ($1, $2, $3, $4)
+             ON CONFLICT DO NOTHING RETURNING id",
+            &[&domain_id, &local_part, &password, &scram],
+        )?
+        else {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        };
+        let id: i64 = row.get(0);
+        transaction.execute(
+            "INSERT INTO mailboxes (account_id, name, uid_validity) VALUES ($1, 'INBOX', $2)",
+            &[&id, &(new_uid_validity() as i64)],
+        )?;
+        transaction.commit()?;
+        Ok(Account { id, local_part, domain, password: password.to_string(), scram: scram.to_string(), pop3_delete: false })
+    }
+
+    fn delete_account(&self, id: i64) -> Result<(), StorageError> {
+        match self.client()?.execute("DELETE FROM accounts WHERE id = $1", &[&id])? {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn account(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let row = self.client()?.query_opt(
+            &format!("{} WHERE a.local_part = $1 AND d.name = $2", ACCOUNT),
+            &[&local_part, &domain],
+        )?;
+        Ok(row.as_ref().map(account_from))
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, StorageError> {
+        let rows = self.client()?.query(&format!("{} ORDER BY d.name, a.local_part", ACCOUNT), &[])?;
+        Ok(rows.iter().map(account_from).collect())
+    }
+
+    fn set_password(&self, id: i64, password: &str, scram: &str) -> Result<(), StorageError> {
+        match self
+            .client()?
+            .execute("UPDATE accounts SET password = $2, scram = $3 WHERE id = $1", &[&id, &password, &scram])?
+        {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_pop3_delete(&self, id: i64, delete: bool) -> Result<(), StorageError> {
+        match self.client()?.execute("UPDATE accounts SET pop3_delete = $2 WHERE id = $1", &[&id, &delete])? {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_alias(&self, address: &str, account_id: i64) -> Result<(), StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Err(StorageError::NotFound(format!("域名 {}", address)));
+        };
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt("SELECT id FROM domains WHERE name = $1", &[&domain])? else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        let domain_id: i64 = row.get(0);
+        let account = transaction.query_opt(
+            "SELECT 1 FROM accounts WHERE domain_id = $1 AND local_part = $2",
+            &[&domain_id, &local_part],
+        )?;
+        if account.is_some() {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        }
+        let inserted = transaction.execute(
+            "INSERT INTO aliases (domain_id, local_part, account_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING",
+            &[&domain_id, &local_part, &account_id],
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_alias(&self, address: &str) -> Result<(), StorageError> {
+        let (local_part, domain) = split_address(address).unwrap_or_default();
+        let deleted = self.client()?.execute(
+            "DELETE FROM aliases USING domains d WHERE aliases.domain_id = d.id AND aliases.local_part = $1 AND d.name = $2",
+            &[&local_part, &domain],
+        )?;
+        match deleted {
+            0 => Err(StorageError::NotFound(format!("别名 {}", address))),
+            _ => Ok(()),
+        }
+    }
+
+    fn aliases(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let rows = self.client()?.query(
+            "SELECT al.local_part || '@' || d.name, a.local_part || '@' || ad.name FROM aliases al
+             JOIN domains d ON d.id = al.domain_id
+             JOIN accounts a ON a.id = al.account_id
+             JOIN domains ad ON ad.id = a.domain_id
+             ORDER BY d.name, al.local_part",
+            &[],
+        )?;
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
+
+    fn resolve(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        if let Some(account) = self.account(address)? {
+            return Ok(Some(account));
+        }
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let row = self.client()?.query_opt(
+            &format!(
+                "{} JOIN aliases al ON al.account_id = a.id JOIN domains ald ON ald.id = al.domain_id
+                 WHERE al.local_part = $1 AND ald.name = $2",
+                ACCOUNT
+            ),
+            &[&local_part, &domain],
+        )?;
+        Ok(row.as_ref().map(account_from))
+    }
+
+    fn create_mailbox(&self, account_id: i64, name: &str) -> Result<Mailbox, StorageError> {
+        let row = self.client()?.query_opt(
+            "INSERT INTO mailboxes (account_id, name, uid_validity) VALUES ($1, $2, $3)
+             ON CONFLICT DO NOTHING RETURNING id, account_id, name, uid_validity, uid_next, subscribed, highest_modseq",
+            &[&account_id, &name, &(new_uid_validity() as i64)],
+        )?;
+        row.as_ref().map(mailbox_from).ok_or_else(|| StorageError::Conflict(format!("邮箱 {}", name)))
+    }
+
+    fn mailbox(&self, account_id: i64, name: &str) -> Result<Option<Mailbox>, StorageError> {
+        let row = self
+            .client()?
+            .query_opt(&format!("{} WHERE account_id = $1 AND name = $2", MAILBOX), &[&account_id, &name])?;
+        Ok(row.as_ref().map(mailbox_from))
+    }
+
+    fn mailboxes(&self, account_id: i64) -> Result<Vec<Mailbox>, StorageError> {
+        let rows = self
+            .client()?
+            .query(&format!("{} WHERE account_id = $1 ORDER BY name", MAILBOX), &[&account_id])?;
+        Ok(rows.iter().map(mailbox_from).collect())
+    }
+
+    fn rename_mailbox(&self, id: i64, name: &str) -> Result<(), StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let taken = transaction.query_opt(
+            "SELECT 1 FROM mailboxes WHERE account_id = (SELECT account_id FROM mailboxes WHERE id = $1) AND name = $2",
+            &[&id, &name],
+        )?;
+        if taken.is_some() {
+            return Err(StorageError::Conflict(format!("邮箱 {}", name)));
+        }
+        if transaction.execute("UPDATE mailboxes SET name = $2 WHERE id = $1", &[&id, &name])? == 0 {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", id)));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_mailbox(&self, id: i64) -> Result<(), StorageError> {
+        match self.client()?.execute("DELETE FROM mailboxes WHERE id = $1", &[&id])? {
+            0 => Err(StorageError::NotFound(format!("邮箱 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_subscribed(&self, id: i64, subscribed: bool) -> Result<(), StorageError> {
+        match self
+            .client()?
+            .execute("UPDATE mailboxes SET subscribed = $2 WHERE id = $1", &[&id, &subscribed])?
+        {
+            0 => Err(StorageError::NotFound(format!("邮箱 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&self, mailbox_id: i64, raw: &[u8], flags: &[String], internal_date: DateTime<Utc>) -> Result<MessageInfo, StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt(NEXT_UID, &[&mailbox_id])? else {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", mailbox_id)));
+        };
+        let (uid, modseq): (i64, i64) = (row.get(0), row.get(1));
+        let row = transaction.query_one(
+            "INSERT INTO messages (mailbox_id, uid, flags, internal_date, size, raw, modseq) VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id, mailbox_id, uid, flags, internal_date, size, modseq",
+            &[&mailbox_id, &uid, &join_flags(flags), &internal_date, &(raw.len() as i64), &raw, &modseq],
+        )?;
+        transaction.commit()?;
+        Ok(message_from(&row))
+    }
+
+    fn messages(&self, mailbox_id: i64) -> Result<Vec<MessageInfo>, StorageError> {
+        let rows = self
+            .client()?
+            .query(&format!("{} WHERE mailbox_id = $1 ORDER BY uid", MESSAGE), &[&mailbox_id])?;
+        Ok(rows.iter().map(message_from).collect())
+    }
+
+    fn raw(&self, message_id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let row = self.client()?.query_opt("SELECT raw FROM messages WHERE id = $1", &[&message_id])?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn set_flags(&self, message_id: i64, flags: &[String], unchanged_since: Option<u64>) -> Result<Option<u64>, StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        // 锁定邮件所在的行，检查与修改之间不会被其他连接修改
+        let Some(row) = transaction.query_opt("SELECT mailbox_id, modseq FROM messages WHERE id = $1 FOR UPDATE", &[&message_id])? else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        let (mailbox_id, current): (i64, i64) = (row.get(0), row.get(1));
+        if unchanged_since.is_some_and(|modseq| current as u64 > modseq) {
+            return Ok(None);
+        }
+        let modseq: i64 = transaction
+            .query_one(
+                "UPDATE mailboxes SET highest_modseq = highest_modseq + 1 WHERE id = $1 RETURNING highest_modseq",
+                &[&mailbox_id],
+            )?
+            .get(0);
+        transaction.execute(
+            "UPDATE messages SET flags = $2, modseq = $3 WHERE id = $1",
+            &[&message_id, &join_flags(flags), &modseq],
+        )?;
+        transaction.commit()?;
+        Ok(Some(modseq as u64))
+    }
+
+    fn expunge(&self, message_ids: &[i64]) -> Result<(), StorageError> {
+        // 同一邮箱中一次删除的邮件使用相同的修改序列
+        self.client()?.execute(
+            "WITH removed AS (DELETE FROM messages WHERE id = ANY($1) RETURNING id, mailbox_id, uid),
+             bumped AS (
+                 UPDATE mailboxes SET highest_modseq = highest_modseq + 1
+                 WHERE id IN (SELECT mailbox_id FROM removed) RETURNING id, highest_modseq
+             )
+             INSERT INTO vanished (mailbox_id, uid, modseq, message_id)
+             SELECT r.mailbox_id, r.uid, b.highest_modseq, r.id FROM removed r JOIN bumped b ON b.id = r.mailbox_id
+             ON CONFLICT DO NOTHING",
+            &[&message_ids],
+        )?;
+        Ok(())
+    }
+
+    fn copy(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt(NEXT_UID, &[&mailbox_id])? else {
+            return Err(StorageError::NotFound(format!("邮箱 #{}", mailbox_id)));
+        };
+        let (uid, modseq): (i64, i64) = (row.get(0), row.get(1));
+        let Some(row) = transaction.query_opt(
+            "INSERT INTO messages (mailbox_id, uid, flags, internal_date, size, raw, modseq)
+             SELECT $2, $3, flags, internal_date, size, raw, $4 FROM messages WHERE id = $1
+             RETURNING id, mailbox_id, uid, flags, internal_date, size, modseq",
+            &[&message_id, &mailbox_id, &uid, &modseq],
+        )?
+        else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        transaction.commit()?;
+        Ok(message_from(&row))
+    }
+
+    fn move_message(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt("SELECT mailbox_id, uid FROM messages WHERE id = $1 FOR UPDATE", &[&message_id])? else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        let (source, old_uid): (i64, i64) = (row.get(0), row.get(1));
+        if source != mailbox_id {
+            let Some(row) = transaction.query_opt(NEXT_UID, &[&mailbox_id])? else {
+                return Err(StorageError::NotFound(format!("邮箱 #{}", mailbox_id)));
+            };
+            let (uid, modseq): (i64, i64) = (row.get(0), row.get(1));
+            transaction.execute(
+                "UPDATE messages SET mailbox_id = $2, uid = $3, modseq = $4 WHERE id = $1",
+                &[&message_id, &mailbox_id, &uid, &modseq],
+            )?;
+            transaction.execute(
+                "WITH bumped AS (UPDATE mailboxes SET highest_modseq = highest_modseq + 1 WHERE id = $1 RETURNING highest_modseq)
+                 INSERT INTO vanished (mailbox_id, uid, modseq, message_id) SELECT $1, $2, highest_modseq, $3 FROM bumped
+                 ON CONFLICT DO NOTHING",
+                &[&source, &old_uid, &message_id],
+            )?;
+        }
+        let row = transaction.query_one(&format!("{} WHERE id = $1", MESSAGE), &[&message_id])?;
+        transaction.commit()?;
+        Ok(message_from(&row))
+    }
+
+    fn vanished(&self, mailbox_id: i64, since: u64) -> Result<Vec<u32>, StorageError> {
+        let rows = self.client()?.query(
+            "SELECT uid FROM vanished WHERE mailbox_id = $1 AND modseq > $2 ORDER BY uid",
+            &[&mailbox_id, &(since as i64)],
+        )?;
+        Ok(rows.iter().map(|row| row.get::<_, i64>(0) as u32).collect())
+    }
+
+    fn removed(&self, mailbox_id: i64, since: u64) -> Result<Vec<i64>, StorageError> {
+        let rows = self.client()?.query(
+            "SELECT message_id FROM vanished WHERE mailbox_id = $1 AND modseq > $2 AND message_id IS NOT NULL ORDER BY uid",
+            &[&mailbox_id, &(since as i64)],
+        )?;
+        Ok(rows.iter().map(|row| row.get(0)).collect())
+    }
+
+    fn enqueue(&self, sender: &str, dsn: &Dsn, recipients: &[(String, RecipientDsn)], raw: &[u8]) -> Result<i64, StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let id: i64 = transaction
+            .query_one(
+                "INSERT INTO queue (sender, raw, ret, envid) VALUES ($1, $2, $3, $4) RETURNING id",
+                &[&sender, &raw, &dsn.ret, &dsn.envid],
+            )?
+            .get(0);
+        for (recipient, dsn) in recipients {
+            let domain = split_address(recipient).map(|(_, domain)| domain).unwrap_or_default();
+            transaction.execute(
+                "INSERT INTO queue_recipients (queue_id, recipient, domain, notify, orcpt) VALUES ($1, $2, $3, $4, $5)",
+                &[&id, recipient, &domain, &dsn.notify, &dsn.orcpt],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    fn queue(&self) -> Result<Vec<QueueEntry>, StorageError> {
+        let mut client = self.client()?;
+        let mut entries: Vec<QueueEntry> = client
+            .query("SELECT id, sender, ret, envid, created_at, warned, held FROM queue ORDER BY id", &[])?
+            .iter()
+            .map(|row| QueueEntry {
+                id: row.get(0),
+                sender: row.get(1),
+                dsn: Dsn { ret: row.get(2), envid: row.get(3) },
+                created_at: row.get(4),
+                warned: row.get(5),
+                held: row.get(6),
+                recipients: Vec::new(),
+            })
+            .collect();
+        let rows = client.query(
+            "SELECT queue_id, id, recipient, domain, attempts, next_attempt, last_error, notify, orcpt
+             FROM queue_recipients ORDER BY id",
+            &[],
+        )?;
+        for row in rows {
+            let queue_id: i64 = row.get(0);
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.id == queue_id) {
+                entry.recipients.push(QueueRecipient {
+                    id: row.get(1),
+                    recipient: row.get(2),
+                    domain: row.get(3),
+                    attempts: row.get(4),
+                    next_attempt: row.get(5),
+                    last_error: row.get(6),
+                    dsn: RecipientDsn { notify: row.get(7), orcpt: row.get(8) },
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    fn queue_raw(&self, id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let row = self.client()?.query_opt("SELECT raw FROM queue WHERE id = $1", &[&id])?;
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    fn dequeue(&self, id: i64) -> Result<(), StorageError> {
+        match self.client()?.execute("DELETE FROM queue WHERE id = $1", &[&id])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn defer(&self, recipient_id: i64, next_attempt: DateTime<Utc>, error: &str) -> Result<(), StorageError> {
+        match self.client()?.execute(
+            "UPDATE queue_recipients SET attempts = attempts + 1, next_attempt = $2, last_error = $3 WHERE id = $1",
+            &[&recipient_id, &next_attempt, &error],
+        )? {
+            0 => Err(StorageError::NotFound(format!("收件人 #{}", recipient_id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn complete(&self, recipient_id: i64) -> Result<(), StorageError> {
+        let mut client = self.client()?;
+        let mut transaction = client.transaction()?;
+        let Some(row) = transaction.query_opt("DELETE FROM queue_recipients WHERE id = $1 RETURNING queue_id", &[&recipient_id])? else {
+            return Err(StorageError::NotFound(format!("收件人 #{}", recipient_id)));
+        };
+        let queue_id: i64 = row.get(0);
+        transaction.execute(
+            "DELETE FROM queue WHERE id = $1 AND NOT EXISTS (SELECT 1 FROM queue_recipients WHERE queue_id = $1)",
+            &[&queue_id],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_warned(&self, id: i64) -> Result<(), StorageError> {
+        match self.client()?.execute("UPDATE queue SET warned = TRUE WHERE id = $1", &[&id])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_held(&self, id: i64, held: bool) -> Result<(), StorageError> {
+        match self.client()?.execute("UPDATE queue SET held = $2 WHERE id = $1", &[&id, &held])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn reschedule(&self, id: i64, next_attempt: DateTime<Utc>) -> Result<(), StorageError> {
+        match self.client()?.execute("UPDATE queue_recipients SET next_attempt = $2 WHERE queue_id = $1", &[&id, &next_attempt])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_delivery(&self, delivery: &Delivery) -> Result<(), StorageError> {
+        self.client()?.execute(
+            "INSERT INTO deliveries (queue_id, envid, message_id, sender, recipient, action, status, diagnostic, remote_mta, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+             ON CONFLICT (queue_id, recipient) DO UPDATE SET action = $6, status = $7, diagnostic = $8, remote_mta = $9, updated_at = $10",
+            &[
+                &delivery.queue_id,
+                &delivery.envid,
+                &delivery.message_id,
+                &delivery.sender,
+                &delivery.recipient,
+                &delivery.action,
+                &delivery.status,
+                &delivery.diagnostic,
+                &delivery.remote_mta,
+                &delivery.updated_at,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn deliveries(&self, key: &DeliveryKey) -> Result<Vec<Delivery>, StorageError> {
+        let (column, value): (&str, &(dyn postgres::types::ToSql + Sync)) = match key {
+            DeliveryKey::Queue(id) => ("queue_id", id),
+            DeliveryKey::Envid(envid) => ("envid", envid),
+            DeliveryKey::MessageId(id) => ("message_id", id),
+        };
+        let rows = self.client()?.query(
+            &format!(
+                "SELECT queue_id, envid, message_id, sender, recipient, action, status, diagnostic, remote_mta, updated_at
+                 FROM deliveries WHERE {} = $1 ORDER BY id",
+                column
+            ),
+            &[value],
+        )?;
+        Ok(rows
+            .iter()
+            .map(|row| Delivery {
+                queue_id: row.get(0),
+                envid: row.get(1),
+                message_id: row.get(2),
+                sender: row.get(3),
+                recipient: row.get(4),
+                action: row.get(5),
+                status: row.get(6),
+                diagnostic: row.get(7),
+                remote_mta: row.get(8),
+                updated_at: row.get(9),
+            })
+            .collect())
+    }
+
+    fn prune_deliveries(&self, before: DateTime<Utc>) -> Result<usize, StorageError> {
+        Ok(self.client()?.execute("DELETE FROM deliveries WHERE updated_at < $1", &[&before])? as usize)
+    }
+}
+
+/// 测试用的临时数据库
+/// 环境变量 ZITMAIL_TEST_PG 为可以创建数据库的连接地址，例如 postgres://postgres@127.0.0.1:5432/postgres；
+/// 每个测试在其中新建一个数据库，结束时删除。未设置时跳过 PostgreSQL 的测试。
+#[cfg(test)]
+pub mod throwaway {
+    use postgres::{Client, NoTls};
+
+    use crate::config::{Backend, Database};
+    use crate::secret::Secret;
+
+    pub struct Throwaway {
+        pub database: Database,
+        admin: String,
+    }
+
+    impl Throwaway {
+        /// # 新建临时数据库
+        /// ## 参数
+        /// - name: 测试名称，用于区分同时运行的测试
+        /// ## 返回值
+        /// - Option<Throwaway>，未设置 ZITMAIL_TEST_PG 时为 None
+        pub fn create(name: &str) -> Option<Throwaway> {
+            let admin = std::env::var("ZITMAIL_TEST_PG").ok()?;
+            let url = url::Url::parse(&admin).expect("ZITMAIL_TEST_PG 不是有效的地址");
+            let database = format!("zitmail_test_{}_{}", name, std::process::id());
+            let mut client = Client::connect(&admin, NoTls).expect("无法连接 ZITMAIL_TEST_PG");
+            client.batch_execute(&format!("DROP DATABASE IF EXISTS {}", database)).unwrap();
+            client.batch_execute(&format!("CREATE DATABASE {}", database)).unwrap();
+            let database = Database {
+                backend: Backend::PostgreSQL,
+                path: String::new(),
+                host: url.host_str().unwrap_or("127.0.0.1").to_string(),
+                port: url.port().unwrap_or(5432),
+                database,
+                user: url.username().to_string(),
+                password: Secret::from(url.password().unwrap_or_default().to_string()),
+                pool: 2,
+            };
+            Some(Throwaway { database, admin })
+        }
+    }
+
+    impl Drop for Throwaway {
+        fn drop(&mut self) {
+            if let Ok(mut client) = Client::connect(&self.admin, NoTls) {
+                let _ = client.batch_execute(&format!("DROP DATABASE IF EXISTS {} WITH (FORCE)", self.database.database));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::throwaway::Throwaway;
+    use super::*;
+
+    /// 依次升级全部版本、查看状态，再逐个回滚到空的数据库结构，之后仍能重新升级
+    #[test]
+    fn migrations_round_trip() {
+        let Some(throwaway) = Throwaway::create("migrations") else {
+            return;
+        };
+        let store = PostgresStore::connect(&throwaway.database).unwrap();
+        let versions = POSTGRES.iter().map(|migration| migration.version).collect::<Vec<_>>();
+
+        assert_eq!(store.migrate().unwrap().len(), POSTGRES.len());
+        let applied = store.applied().unwrap().into_iter().map(|(version, _)| version).collect::<Vec<_>>();
+        assert_eq!(applied, versions);
+        assert!(store.migrate().unwrap().is_empty());
+
+        for migration in POSTGRES.iter().rev() {
+            assert_eq!(store.rollback().unwrap().map(|migration| migration.version), Some(migration.version));
+        }
+        assert!(store.rollback().unwrap().is_none());
+        assert!(store.applied().unwrap().is_empty());
+
+        // 只剩下记录版本的 schema_migrations
+        let mut client = store.client().unwrap();
+        let relations = client
+            .query("SELECT c.relname FROM pg_class c JOIN pg_namespace n ON n.oid = c.relnamespace WHERE n.nspname = 'public' ORDER BY c.relname", &[])
+            .unwrap()
+            .iter()
+            .map(|row| row.get::<_, String>(0))
+            .collect::<Vec<_>>();
+        assert_eq!(relations, ["schema_migrations", "schema_migrations_pkey"]);
+        let functions: i64 = client
+            .query_one("SELECT count(*) FROM pg_proc p JOIN pg_namespace n ON n.oid = p.pronamespace WHERE n.nspname = 'public'", &[])
+            .unwrap()
+            .get(0);
+        assert_eq!(functions, 0);
+        drop(client);
+
+        assert_eq!(store.migrate().unwrap().len(), POSTGRES.len());
+    }
+}
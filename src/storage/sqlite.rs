@@ -0,0 +1,766 @@
+/* SQLite */
+/*
+单文件数据库，适合小型部署。所有访问通过一个连接串行执行。
+ */
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OptionalExtension, Row, params};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::migration::{Migration, SQLITE};
+use super::{Account, Delivery, DeliveryKey, Dsn, MailStore, Mailbox, MessageInfo, QueueEntry, QueueRecipient, RecipientDsn, StorageError};
+use super::{join_flags, new_uid_validity, split_address, split_flags};
+
+pub struct SqliteStore {
+    connection: Mutex<Connection>,
+}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Query(e.to_string())
+    }
+}
+
+impl SqliteStore {
+    /// # 打开数据库文件
+    /// 文件不存在时自动创建。
+    /// ## 参数
+    /// - path: Database.Path
+    /// ## 返回值
+    /// - Result<SqliteStore, StorageError>
+    pub fn open(path: &str) -> Result<SqliteStore, StorageError> {
+        let connection = Connection::open(Path::new(path)).map_err(|e| StorageError::Connect(format!("{}：{}", path, e)))?;
+        connection.busy_timeout(Duration::from_secs(10))?;
+        connection.execute_batch("PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL;")?;
+        debug!("已打开数据库 {}", path);
+        Ok(SqliteStore { connection: Mutex::new(connection) })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn time(seconds: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(seconds, 0).unwrap_or_default()
+}
+
+fn account_from(row: &Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        id: row.get(0)?,
+        local_part: row.get(1)?,
+        domain: row.get(2)?,
+        password: row.get(3)?,
+        scram: row.get(4)?,
+        pop3_delete: row.get(5)?,
+    })
+}
+
+fn mailbox_from(row: &Row) -> rusqlite::Result<Mailbox> {
+    Ok(Mailbox {
+        id: row.get(0)?,
+        account_id: row.get(1)?,
+        name: row.get(2)?,
+        uid_validity: row.get(3)?,
+        uid_next: row.get(4)?,
+        subscribed: row.get(5)?,
+        highest_modseq: row.get(6)?,
+    })
+}
+
+fn message_from(row: &Row) -> rusqlite::Result<MessageInfo> {
+    Ok(MessageInfo {
+        id: row.get(0)?,
+        mailbox_id: row.get(1)?,
+        uid: row.get(2)?,
+        flags: split_flags(&row.get::<_, String>(3)?),
+        internal_date: time(row.get(4)?),
+        size: row.get(5)?,
+        modseq: row.get(6)?,
+    })
+}
+
+fn delivery_from(row: &Row) -> rusqlite::Result<Delivery> {
+    Ok(Delivery {
+        queue_id: row.get(0)?,
+        envid: row.get(1)?,
+        message_id: row.get(2)?,
+        sender: row.get(3)?,
+        recipient: row.get(4)?,
+        action: row.get(5)?,
+        status: row.get(6)?,
+        diagnostic: row.get(7)?,
+        remote_mta: row.get(8)?,
+        updated_at: time(row.get(9)?),
+    })
+}
+
+const ACCOUNT: &str = "SELECT a.id, a.local_part, d.name, a.password, a.scram, a.pop3_delete FROM accounts a JOIN domains d ON d.id = a.domain_id";
+const MAILBOX: &str = "SELECT id, account_id, name, uid_validity, uid_next, subscribed, highest_modseq FROM mailboxes";
+const MESSAGE: &str = "SELECT id, mailbox_id, uid, flags, internal_date, size, modseq FROM messages";
+const DELIVERY: &str =
+    "SELECT queue_id, envid, message_id, sender, recipient, action, status, diagnostic, remote_mta, updated_at FROM deliveries";
+
+/// # 在事务中分配下一个 UID 与修改序列
+fn next_uid(transaction: &rusqlite::Transaction, mailbox_id: i64) -> Result<(u32, u64), StorageError> {
+    transaction
+        .query_row(
+            "UPDATE mailboxes SET uid_next = uid_next + 1, highest_modseq = highest_modseq + 1 WHERE id = ?1
+             RETURNING uid_next - 1, highest_modseq",
+            [mailbox_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?
+        .ok_or_else(|| StorageError::NotFound(format!("邮箱 #{}", mailbox_id)))
+}
+
+/// # 在事务中分配下一个修改序列
+fn next_modseq(transaction: &rusqlite::Transaction, mailbox_id: i64) -> Result<u64, StorageError> {
+    transaction
+        .query_row(
+            "UPDATE mailboxes SET highest_modseq = highest_modseq + 1 WHERE id = ?1 RETURNING highest_modseq",
+            [mailbox_id],
+            |row| row.get(0),
+        )
+        .optional()?
+        .ok_or_else(|| StorageError::NotFound(format!("邮箱 #{}", mailbox_id)))
+}
+
+impl MailStore for SqliteStore {
+    fn backend(&self) -> &'static str {
+        "SQLite"
+    }
+
+    fn migrations(&self) -> &'static [Migration] {
+        SQLITE
+    }
+
+    fn applied(&self) -> Result<Vec<(i64, DateTime<Utc>)>, StorageError> {
+        let connection = self.connection();
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+            )",
+        )?;
+        let mut statement = connection.prepare("SELECT version, applied_at FROM schema_migrations ORDER BY version")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, time(row.get(1)?))))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// 每个版本在单独的事务中执行。
+    fn migrate(&self) -> Result<Vec<&'static Migration>, StorageError> {
+        let applied = self.applied()?;
+        let latest = applied.last().map(|(version, _)| *version).unwrap_or(0);
+        if let Some(known) = SQLITE.last()
+            && latest > known.version
+        {
+            return Err(StorageError::Migration(format!("数据库版本 {} 高于程序支持的版本 {}，请更新 ZitMail", latest, known.version)));
+        }
+
+        let mut connection = self.connection();
+        let mut done = Vec::new();
+        for migration in SQLITE.iter().filter(|migration| migration.version > latest) {
+            let transaction = connection.transaction()?;
+            transaction
+                .execute_batch(migration.up)
+                .map_err(|e| StorageError::Migration(format!("版本 {} {}：{}", migration.version, migration.name, e)))?;
+            transaction.execute(
+                "INSERT INTO schema_migrations (version, name) VALUES (?1, ?2)",
+                params![migration.version, migration.name],
+            )?;
+            transaction.commit()?;
+            info!("数据库已升级到版本 {} {}", migration.version, migration.name);
+            done.push(migration);
+        }
+        Ok(done)
+    }
+
+    fn rollback(&self) -> Result<Option<&'static Migration>, StorageError> {
+        let Some((latest, _)) = self.applied()?.pop() else {
+            return Ok(None);
+        };
+        let Some(migration) = SQLITE.iter().find(|migration| migration.version == latest) else {
+            return Err(StorageError::Migration(format!("程序中没有版本 {} 的回滚脚本", latest)));
+        };
+
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction
+            .execute_batch(migration.down)
+            .map_err(|e| StorageError::Migration(format!("回滚版本 {} {}：{}", migration.version, migration.name, e)))?;
+        transaction.execute("DELETE FROM schema_migrations WHERE version = ?1", [migration.version])?;
+        transaction.commit()?;
+        Ok(Some(migration))
+    }
+
+    fn add_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        match self.connection().execute("INSERT OR IGNORE INTO domains (name) VALUES (?1)", [&name])? {
+            0 => Err(StorageError::Conflict(format!("域名 {}", name))),
+            _ => Ok(()),
+        }
+    }
+
+    fn delete_domain(&self, name: &str) -> Result<(), StorageError> {
+        let name = name.to_lowercase();
+        match self.connection().execute("DELETE FROM domains WHERE name = ?1", [&name])? {
+            0 => Err(StorageError::NotFound(format!("域名 {}", name))),
+            _ => Ok(()),
+        }
+    }
+
+    fn domains(&self) -> Result<Vec<String>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT name FROM domains ORDER BY name")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn has_domain(&self, name: &str) -> Result<bool, StorageError> {
+        let row = self
+            .connection()
+            .query_row("SELECT 1 FROM domains WHERE name = ?1", [name.to_lowercase()], |_| Ok(()))
+            .optional()?;
+        Ok(row.is_some())
+    }
+
+    fn add_account(&self, local_part: &str, domain: &str, password: &str, scram: &str) -> Result<Account, StorageError> {
+        let (local_part, domain) = (local_part.to_lowercase(), domain.to_lowercase());
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let Some(domain_id) = transaction
+            .query_row("SELECT id FROM domains WHERE name = ?1", [&domain], |row| row.get::<_, i64>(0))
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        let alias = transaction
+            .query_row(
+                "SELECT 1 FROM aliases WHERE domain_id = ?1 AND local_part = ?2",
+                params![domain_id, local_part],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if alias.is_some() {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO accounts (domain_id, local_part, password, scram) VALUES (?1, ?2, ?3, ?4)",
+            params![domain_id, local_part, password, scram],
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        }
+        let id = transaction.last_insert_rowid();
+        transaction.execute(
+            "INSERT INTO mailboxes (account_id, name, uid_validity) VALUES (?1, 'INBOX', ?2)",
+            params![id, new_uid_validity()],
+        )?;
+        transaction.commit()?;
+        Ok(Account { id, local_part, domain, password: password.to_string(), scram: scram.to_string(), pop3_delete: false })
+    }
+
+    fn delete_account(&self, id: i64) -> Result<(), StorageError> {
+        match self.connection().execute("DELETE FROM accounts WHERE id = ?1", [id])? {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn account(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let account = self
+            .connection()
+            .query_row(
+                &format!("{} WHERE a.local_part = ?1 AND d.name = ?2", ACCOUNT),
+                [&local_part, &domain],
+                account_from,
+            )
+            .optional()?;
+        Ok(account)
+    }
+
+    fn accounts(&self) -> Result<Vec<Account>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("{} ORDER BY d.name, a.local_part", ACCOUNT))?;
+        let rows = statement.query_map([], account_from)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn set_password(&self, id: i64, password: &str, scram: &str) -> Result<(), StorageError> {
+        match self
+            .connection()
+            .execute("UPDATE accounts SET password = ?2, scram = ?3 WHERE id = ?1", params![id, password, scram])?
+        {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_pop3_delete(&self, id: i64, delete: bool) -> Result<(), StorageError> {
+        match self.connection().execute("UPDATE accounts SET pop3_delete = ?2 WHERE id = ?1", params![id, delete])? {
+            0 => Err(StorageError::NotFound(format!("账号 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_alias(&self, address: &str, account_id: i64) -> Result<(), StorageError> {
+        let Some((local_part, domain)) = split_address(address) else {
+            return Err(StorageError::NotFound(format!("域名 {}", address)));
+        };
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let Some(domain_id) = transaction
+            .query_row("SELECT id FROM domains WHERE name = ?1", [&domain], |row| row.get::<_, i64>(0))
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("域名 {}", domain)));
+        };
+        let account = transaction
+            .query_row(
+                "SELECT 1 FROM accounts WHERE domain_id = ?1 AND local_part = ?2",
+                params![domain_id, local_part],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if account.is_some() {
+            return Err(StorageError::Conflict(format!("账号 {}@{}", local_part, domain)));
+        }
+        let inserted = transaction.execute(
+            "INSERT OR IGNORE INTO aliases (domain_id, local_part, account_id) VALUES (?1, ?2, ?3)",
+            params![domain_id, local_part, account_id],
+        )?;
+        if inserted == 0 {
+            return Err(StorageError::Conflict(format!("别名 {}@{}", local_part, domain)));
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn delete_alias(&self, address: &str) -> Result<(), StorageError> {
+        let (local_part, domain) = split_address(address).unwrap_or_default();
+        let deleted = self.connection().execute(
+            "DELETE FROM aliases WHERE local_part = ?1 AND domain_id = (SELECT id FROM domains WHERE name = ?2)",
+            [&local_part, &domain],
+        )?;
+        match deleted {
+            0 => Err(StorageError::NotFound(format!("别名 {}", address))),
+            _ => Ok(()),
+        }
+    }
+
+    fn aliases(&self) -> Result<Vec<(String, String)>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT al.local_part || '@' || d.name, a.local_part || '@' || ad.name FROM aliases al
+             JOIN domains d ON d.id = al.domain_id
+             JOIN accounts a ON a.id = al.account_id
+             JOIN domains ad ON ad.id = a.domain_id
+             ORDER BY d.name, al.local_part",
+        )?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn resolve(&self, address: &str) -> Result<Option<Account>, StorageError> {
+        if let Some(account) = self.account(address)? {
+            return Ok(Some(account));
+        }
+        let Some((local_part, domain)) = split_address(address) else {
+            return Ok(None);
+        };
+        let account = self
+            .connection()
+            .query_row(
+                &format!(
+                    "{} JOIN aliases al ON al.account_id = a.id JOIN domains ald ON ald.id = al.domain_id
+                     WHERE al.local_part = ?1 AND ald.name = ?2",
+                    ACCOUNT
+                ),
+                [&local_part, &domain],
+                account_from,
+            )
+            .optional()?;
+        Ok(account)
+    }
+
+    fn create_mailbox(&self, account_id: i64, name: &str) -> Result<Mailbox, StorageError> {
+        let mailbox = self
+            .connection()
+            .query_row(
+                "INSERT OR IGNORE INTO mailboxes (account_id, name, uid_validity) VALUES (?1, ?2, ?3)
+                 RETURNING id, account_id, name, uid_validity, uid_next, subscribed, highest_modseq",
+                params![account_id, name, new_uid_validity()],
+                mailbox_from,
+            )
+            .optional()?;
+        mailbox.ok_or_else(|| StorageError::Conflict(format!("邮箱 {}", name)))
+    }
+
+    fn mailbox(&self, account_id: i64, name: &str) -> Result<Option<Mailbox>, StorageError> {
+        let mailbox = self
+            .connection()
+            .query_row(
+                &format!("{} WHERE account_id = ?1 AND name = ?2", MAILBOX),
+                params![account_id, name],
+                mailbox_from,
+            )
+            .optional()?;
+        Ok(mailbox)
+    }
+
+    fn mailboxes(&self, account_id: i64) -> Result<Vec<Mailbox>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("{} WHERE account_id = ?1 ORDER BY name", MAILBOX))?;
+        let rows = statement.query_map([account_id], mailbox_from)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn rename_mailbox(&self, id: i64, name: &str) -> Result<(), StorageError> {
+        let result = self
+            .connection()
+            .execute("UPDATE mailboxes SET name = ?2 WHERE id = ?1", params![id, name]);
+        match result {
+            Ok(0) => Err(StorageError::NotFound(format!("邮箱 #{}", id))),
+            Ok(_) => Ok(()),
+            Err(rusqlite::Error::SqliteFailure(e, _)) if e.code == rusqlite::ErrorCode::ConstraintViolation => {
+                Err(StorageError::Conflict(format!("邮箱 {}", name)))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn delete_mailbox(&self, id: i64) -> Result<(), StorageError> {
+        match self.connection().execute("DELETE FROM mailboxes WHERE id = ?1", [id])? {
+            0 => Err(StorageError::NotFound(format!("邮箱 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_subscribed(&self, id: i64, subscribed: bool) -> Result<(), StorageError> {
+        match self
+            .connection()
+            .execute("UPDATE mailboxes SET subscribed = ?2 WHERE id = ?1", params![id, subscribed])?
+        {
+            0 => Err(StorageError::NotFound(format!("邮箱 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn append(&self, mailbox_id: i64, raw: &[u8], flags: &[String], internal_date: DateTime<Utc>) -> Result<MessageInfo, StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let (uid, modseq) = next_uid(&transaction, mailbox_id)?;
+        let message = transaction.query_row(
+            "INSERT INTO messages (mailbox_id, uid, flags, internal_date, size, raw, modseq) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+             RETURNING id, mailbox_id, uid, flags, internal_date, size, modseq",
+            params![mailbox_id, uid, join_flags(flags), internal_date.timestamp(), raw.len() as i64, raw, modseq],
+            message_from,
+        )?;
+        transaction.commit()?;
+        Ok(message)
+    }
+
+    fn messages(&self, mailbox_id: i64) -> Result<Vec<MessageInfo>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("{} WHERE mailbox_id = ?1 ORDER BY uid", MESSAGE))?;
+        let rows = statement.query_map([mailbox_id], message_from)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn raw(&self, message_id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let raw = self
+            .connection()
+            .query_row("SELECT raw FROM messages WHERE id = ?1", [message_id], |row| row.get(0))
+            .optional()?;
+        Ok(raw)
+    }
+
+    fn set_flags(&self, message_id: i64, flags: &[String], unchanged_since: Option<u64>) -> Result<Option<u64>, StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let Some((mailbox_id, current)) = transaction
+            .query_row("SELECT mailbox_id, modseq FROM messages WHERE id = ?1", [message_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, u64>(1)?))
+            })
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        if unchanged_since.is_some_and(|modseq| current > modseq) {
+            return Ok(None);
+        }
+        let modseq = next_modseq(&transaction, mailbox_id)?;
+        transaction.execute(
+            "UPDATE messages SET flags = ?2, modseq = ?3 WHERE id = ?1",
+            params![message_id, join_flags(flags), modseq],
+        )?;
+        transaction.commit()?;
+        Ok(Some(modseq))
+    }
+
+    fn expunge(&self, message_ids: &[i64]) -> Result<(), StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        // 同一邮箱中一次删除的邮件使用相同的修改序列
+        let mut modseqs: Vec<(i64, u64)> = Vec::new();
+        for id in message_ids {
+            let Some((mailbox_id, uid)) = transaction
+                .query_row("DELETE FROM messages WHERE id = ?1 RETURNING mailbox_id, uid", [id], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?))
+                })
+                .optional()?
+            else {
+                continue;
+            };
+            let modseq = match modseqs.iter().find(|(id, _)| *id == mailbox_id) {
+                Some(&(_, modseq)) => modseq,
+                None => {
+                    let modseq = next_modseq(&transaction, mailbox_id)?;
+                    modseqs.push((mailbox_id, modseq));
+                    modseq
+                }
+            };
+            transaction.execute(
+                "INSERT OR IGNORE INTO vanished (mailbox_id, uid, modseq, message_id) VALUES (?1, ?2, ?3, ?4)",
+                params![mailbox_id, uid, modseq, id],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn copy(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let (uid, modseq) = next_uid(&transaction, mailbox_id)?;
+        let Some(message) = transaction
+            .query_row(
+                "INSERT INTO messages (mailbox_id, uid, flags, internal_date, size, raw, modseq)
+                 SELECT ?2, ?3, flags, internal_date, size, raw, ?4 FROM messages WHERE id = ?1
+                 RETURNING id, mailbox_id, uid, flags, internal_date, size, modseq",
+                params![message_id, mailbox_id, uid, modseq],
+                message_from,
+            )
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        transaction.commit()?;
+        Ok(message)
+    }
+
+    fn move_message(&self, message_id: i64, mailbox_id: i64) -> Result<MessageInfo, StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let Some((source, old_uid)) = transaction
+            .query_row("SELECT mailbox_id, uid FROM messages WHERE id = ?1", [message_id], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, u32>(1)?))
+            })
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("邮件 #{}", message_id)));
+        };
+        if source != mailbox_id {
+            let (uid, modseq) = next_uid(&transaction, mailbox_id)?;
+            transaction.execute(
+                "UPDATE messages SET mailbox_id = ?2, uid = ?3, modseq = ?4 WHERE id = ?1",
+                params![message_id, mailbox_id, uid, modseq],
+            )?;
+            let modseq = next_modseq(&transaction, source)?;
+            transaction.execute(
+                "INSERT OR IGNORE INTO vanished (mailbox_id, uid, modseq, message_id) VALUES (?1, ?2, ?3, ?4)",
+                params![source, old_uid, modseq, message_id],
+            )?;
+        }
+        let message = transaction.query_row(&format!("{} WHERE id = ?1", MESSAGE), [message_id], message_from)?;
+        transaction.commit()?;
+        Ok(message)
+    }
+
+    fn vanished(&self, mailbox_id: i64, since: u64) -> Result<Vec<u32>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT uid FROM vanished WHERE mailbox_id = ?1 AND modseq > ?2 ORDER BY uid")?;
+        let rows = statement.query_map(params![mailbox_id, since], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn removed(&self, mailbox_id: i64, since: u64) -> Result<Vec<i64>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare(
+            "SELECT message_id FROM vanished WHERE mailbox_id = ?1 AND modseq > ?2 AND message_id IS NOT NULL ORDER BY uid",
+        )?;
+        let rows = statement.query_map(params![mailbox_id, since], |row| row.get(0))?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn enqueue(&self, sender: &str, dsn: &Dsn, recipients: &[(String, RecipientDsn)], raw: &[u8]) -> Result<i64, StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO queue (sender, raw, ret, envid) VALUES (?1, ?2, ?3, ?4)",
+            params![sender, raw, dsn.ret, dsn.envid],
+        )?;
+        let id = transaction.last_insert_rowid();
+        for (recipient, dsn) in recipients {
+            let domain = split_address(recipient).map(|(_, domain)| domain).unwrap_or_default();
+            transaction.execute(
+                "INSERT INTO queue_recipients (queue_id, recipient, domain, notify, orcpt) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, recipient, domain, dsn.notify, dsn.orcpt],
+            )?;
+        }
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    fn queue(&self) -> Result<Vec<QueueEntry>, StorageError> {
+        let connection = self.connection();
+        let mut statement = connection.prepare("SELECT id, sender, ret, envid, created_at, warned, held FROM queue ORDER BY id")?;
+        let mut entries = statement
+            .query_map([], |row| {
+                Ok(QueueEntry {
+                    id: row.get(0)?,
+                    sender: row.get(1)?,
+                    dsn: Dsn { ret: row.get(2)?, envid: row.get(3)? },
+                    created_at: time(row.get(4)?),
+                    warned: row.get(5)?,
+                    held: row.get(6)?,
+                    recipients: Vec::new(),
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut statement = connection.prepare(
+            "SELECT queue_id, id, recipient, domain, attempts, next_attempt, last_error, notify, orcpt
+             FROM queue_recipients ORDER BY id",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                QueueRecipient {
+                    id: row.get(1)?,
+                    recipient: row.get(2)?,
+                    domain: row.get(3)?,
+                    attempts: row.get(4)?,
+                    next_attempt: time(row.get(5)?),
+                    last_error: row.get(6)?,
+                    dsn: RecipientDsn { notify: row.get(7)?, orcpt: row.get(8)? },
+                },
+            ))
+        })?;
+        for row in rows {
+            let (queue_id, recipient) = row?;
+            if let Some(entry) = entries.iter_mut().find(|entry| entry.id == queue_id) {
+                entry.recipients.push(recipient);
+            }
+        }
+        Ok(entries)
+    }
+
+    fn queue_raw(&self, id: i64) -> Result<Option<Vec<u8>>, StorageError> {
+        let raw = self
+            .connection()
+            .query_row("SELECT raw FROM queue WHERE id = ?1", [id], |row| row.get(0))
+            .optional()?;
+        Ok(raw)
+    }
+
+    fn dequeue(&self, id: i64) -> Result<(), StorageError> {
+        match self.connection().execute("DELETE FROM queue WHERE id = ?1", [id])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn defer(&self, recipient_id: i64, next_attempt: DateTime<Utc>, error: &str) -> Result<(), StorageError> {
+        match self.connection().execute(
+            "UPDATE queue_recipients SET attempts = attempts + 1, next_attempt = ?2, last_error = ?3 WHERE id = ?1",
+            params![recipient_id, next_attempt.timestamp(), error],
+        )? {
+            0 => Err(StorageError::NotFound(format!("收件人 #{}", recipient_id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn complete(&self, recipient_id: i64) -> Result<(), StorageError> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let Some(queue_id) = transaction
+            .query_row("DELETE FROM queue_recipients WHERE id = ?1 RETURNING queue_id", [recipient_id], |row| row.get::<_, i64>(0))
+            .optional()?
+        else {
+            return Err(StorageError::NotFound(format!("收件人 #{}", recipient_id)));
+        };
+        transaction.execute(
+            "DELETE FROM queue WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM queue_recipients WHERE queue_id = ?1)",
+            [queue_id],
+        )?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn set_warned(&self, id: i64) -> Result<(), StorageError> {
+        match self.connection().execute("UPDATE queue SET warned = 1 WHERE id = ?1", [id])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_held(&self, id: i64, held: bool) -> Result<(), StorageError> {
+        match self.connection().execute("UPDATE queue SET held = ?2 WHERE id = ?1", params![id, held])? {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn reschedule(&self, id: i64, next_attempt: DateTime<Utc>) -> Result<(), StorageError> {
+        match self
+            .connection()
+            .execute("UPDATE queue_recipients SET next_attempt = ?2 WHERE queue_id = ?1", params![id, next_attempt.timestamp()])?
+        {
+            0 => Err(StorageError::NotFound(format!("队列 #{}", id))),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_delivery(&self, delivery: &Delivery) -> Result<(), StorageError> {
+        self.connection().execute(
+            "INSERT INTO deliveries (queue_id, envid, message_id, sender, recipient, action, status, diagnostic, remote_mta, updated_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+             ON CONFLICT (queue_id, recipient) DO UPDATE SET action = ?6, status = ?7, diagnostic = ?8, remote_mta = ?9, updated_at = ?10",
+            params![
+                delivery.queue_id,
+                delivery.envid,
+                delivery.message_id,
+                delivery.sender,
+                delivery.recipient,
+                delivery.action,
+                delivery.status,
+                delivery.diagnostic,
+                delivery.remote_mta,
+                delivery.updated_at.timestamp(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn deliveries(&self, key: &DeliveryKey) -> Result<Vec<Delivery>, StorageError> {
+        let (column, value): (&str, &dyn rusqlite::ToSql) = match key {
+            DeliveryKey::Queue(id) => ("queue_id", id),
+            DeliveryKey::Envid(envid) => ("envid", envid),
+            DeliveryKey::MessageId(id) => ("message_id", id),
+        };
+        let connection = self.connection();
+        let mut statement = connection.prepare(&format!("{} WHERE {} = ?1 ORDER BY id", DELIVERY, column))?;
+        let deliveries = statement.query_map([value], delivery_from)?.collect::<Result<Vec<_>, _>>()?;
+        Ok(deliveries)
+    }
+
+    fn prune_deliveries(&self, before: DateTime<Utc>) -> Result<usize, StorageError> {
+        Ok(self.connection().execute("DELETE FROM deliveries WHERE updated_at < ?1", params![before.timestamp()])?)
+    }
+}
@@ -0,0 +1,117 @@
+/* 命令 zmou help timezone */
+use chrono::{Offset, Utc};
+use chrono_tz::{OffsetComponents, Tz};
+use std::str::FromStr;
+
+use crate::default::TIMEZONES;
+use crate::utils::get_current_time;
+
+/// # 列出时区
+/// 按地区分组，显示 UTC 偏移、夏令时与当地时间。
+/// ## 参数
+/// - filter: 筛选关键字，支持子串与模糊匹配，例如 shang
+/// ## 返回值
+/// - 匹配到的时区数量
+pub fn list(filter: Option<&str>) -> usize {
+    let zones = search(filter.unwrap_or(""));
+    let mut region = "";
+    for zone in &zones {
+        let current = zone.split('/').next().unwrap_or(zone);
+        if current != region {
+            if !region.is_empty() {
+                println!();
+            }
+            println!("[{}]", current);
+            region = current;
+        }
+        let (offset, dst) = offset(zone);
+        let dst = if dst { "夏令时" } else { "      " };
+        println!("  {:<32} {}  {}  {}", zone, offset, dst, get_current_time(zone));
+    }
+
+    if zones.is_empty()
+        && let Some(filter) = filter
+    {
+        match suggest(filter) {
+            Some(name) => println!("没有匹配 {} 的时区，你是否在找 {}？", filter, name),
+            None => println!("没有匹配 {} 的时区", filter),
+        }
+    }
+    zones.len()
+}
+
+/// # 搜索时区
+/// 优先返回包含关键字的时区；没有时返回按顺序包含关键字全部字母的时区。
+/// ## 参数
+/// - filter: &str
+/// ## 返回值
+/// - Vec<&'static str>
+pub fn search(filter: &str) -> Vec<&'static str> {
+    let filter = filter.to_lowercase();
+    let contains: Vec<&str> = TIMEZONES.iter().copied().filter(|zone| zone.to_lowercase().contains(&filter)).collect();
+    if !contains.is_empty() {
+        return contains;
+    }
+    TIMEZONES.iter().copied().filter(|zone| is_subsequence(&filter, &zone.to_lowercase())).collect()
+}
+
+/// # 推荐最接近的有效时区
+/// 用于提示填写错误的 General.TimeZone。
+/// ## 参数
+/// - name: 错误的时区名称
+/// ## 返回值
+/// - Option<&'static str>
+pub fn suggest(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    if name.is_empty() {
+        return None;
+    }
+    let (zone, distance) = TIMEZONES
+        .iter()
+        .map(|zone| {
+            let lower = zone.to_lowercase();
+            let city = lower.rsplit('/').next().unwrap_or(&lower);
+            (*zone, levenshtein(&name, &lower).min(levenshtein(&name, city)))
+        })
+        .min_by_key(|(_, distance)| *distance)?;
+    (distance <= name.chars().count().div_ceil(2)).then_some(zone)
+}
+
+/// # 当前的 UTC 偏移
+/// ## 参数
+/// - zone: 时区名称
+/// ## 返回值
+/// - (偏移，例如 UTC+08:00, 是否处于夏令时)
+fn offset(zone: &str) -> (String, bool) {
+    let Ok(tz) = Tz::from_str(zone) else {
+        return (String::from("UTC?"), false);
+    };
+    let now = Utc::now().with_timezone(&tz);
+    let components = now.offset();
+    let seconds = components.fix().local_minus_utc();
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.abs() / 60;
+    let dst = !components.dst_offset().is_zero();
+    (format!("UTC{}{:02}:{:02}", sign, minutes / 60, minutes % 60), dst)
+}
+
+/// # needle 的字符是否按顺序出现在 haystack 中
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut chars = haystack.chars();
+    needle.chars().all(|c| chars.any(|h| h == c))
+}
+
+/// # 编辑距离
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
@@ -0,0 +1,265 @@
+/* TLS */
+/*
+# TLS 模块
+## 用法
+let config = tls::server_config(&cert, &key)?;          <-- 读取 PEM 格式的证书链与私钥
+let mut connection = Connection::new(Stream::Plain(tcp));
+connection = connection.starttls(&config)?;              <-- STARTTLS，丢弃升级前缓冲的明文
+let connection = Connection::new(Stream::accept(tcp, &config));   <-- 隐式 TLS
+let connection = connection.connect(&tls::client_config(), "mx.example.org")?;   <-- 作为客户端升级（投递时的 STARTTLS）
+ */
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, ServerConfig, ServerConnection, SignatureScheme, StreamOwned};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum TlsError {
+    /// 证书或私钥文件无法读取或解析
+    File(String, String),
+    /// 证书与私钥不匹配等
+    Config(String),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::File(path, e) => write!(f, "无法读取 {}：{}", path, e),
+            TlsError::Config(e) => write!(f, "TLS 配置错误：{}", e),
+        }
+    }
+}
+
+/// # 创建 TLS 服务端配置
+/// ## 参数
+/// - cert: PEM 格式的证书链路径
+/// - key: PEM 格式的私钥路径
+/// ## 返回值
+/// - Result<Arc<ServerConfig>, TlsError>
+pub fn server_config(cert: &str, key: &str) -> Result<Arc<ServerConfig>, TlsError> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| TlsError::File(cert.to_string(), e.to_string()))?;
+    if certs.is_empty() {
+        return Err(TlsError::File(cert.to_string(), String::from("文件中没有证书")));
+    }
+    let key = PrivateKeyDer::from_pem_file(key).map_err(|e| TlsError::File(key.to_string(), e.to_string()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| TlsError::Config(e.to_string()))?;
+    Ok(Arc::new(config))
+}
+
+/// # 创建 TLS 客户端配置
+/// 用于投递时的机会性 STARTTLS（RFC 7435）：加密传输，但不校验对方证书，
+/// 因为大多数邮件服务器的证书与 MX 主机名不匹配。仍然校验握手签名。
+/// ## 返回值
+/// - Arc<ClientConfig>
+pub fn client_config() -> Arc<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .expect("ring 支持默认的 TLS 版本")
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(Opportunistic(provider)))
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+/// 接受任何证书
+#[derive(Debug)]
+struct Opportunistic(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for Opportunistic {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        signature: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, signature, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+/// 连接的加密状态，SMTP、IMAP、POP3 的会话据此决定是否提供 STARTTLS
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tls {
+    /// 未配置证书
+    Unavailable,
+    /// 可以通过 STARTTLS 升级
+    Available,
+    /// 已加密
+    Active,
+}
+
+/// 明文或 TLS 连接
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+    /// 本机作为客户端的 TLS 连接
+    Client(Box<StreamOwned<ClientConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// # 以 TLS 方式接受连接
+    /// 握手在第一次读写时进行。
+    /// ## 参数
+    /// - tcp: TcpStream
+    /// - config: TLS 服务端配置
+    /// ## 返回值
+    /// - io::Result<Stream>
+    pub fn accept(tcp: TcpStream, config: &Arc<ServerConfig>) -> io::Result<Stream> {
+        let connection = ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, tcp))))
+    }
+
+    /// # 以客户端身份进行 TLS 握手
+    /// 立即完成握手，以便握手失败时可以改用明文重新连接。
+    /// ## 参数
+    /// - tcp: TcpStream
+    /// - config: TLS 客户端配置
+    /// - name: 对方的主机名，用于 SNI
+    /// ## 返回值
+    /// - io::Result<Stream>
+    pub fn connect(mut tcp: TcpStream, config: &Arc<ClientConfig>, name: &str) -> io::Result<Stream> {
+        let name = ServerName::try_from(name.to_string()).map_err(io::Error::other)?;
+        let mut connection = ClientConnection::new(Arc::clone(config), name).map_err(io::Error::other)?;
+        while connection.is_handshaking() {
+            connection.complete_io(&mut tcp)?;
+        }
+        Ok(Stream::Client(Box::new(StreamOwned::new(connection, tcp))))
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Stream::Plain(stream) => stream,
+            Stream::Tls(stream) => stream.get_ref(),
+            Stream::Client(stream) => stream.get_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.read(buffer),
+            Stream::Tls(stream) => stream.read(buffer),
+            Stream::Client(stream) => stream.read(buffer),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(stream) => stream.write(buffer),
+            Stream::Tls(stream) => stream.write(buffer),
+            Stream::Client(stream) => stream.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(stream) => stream.flush(),
+            Stream::Tls(stream) => stream.flush(),
+            Stream::Client(stream) => stream.flush(),
+        }
+    }
+}
+
+/// 带缓冲的连接，供 SMTP、IMAP、POP3 共用
+pub struct Connection {
+    reader: BufReader<Stream>,
+}
+
+impl Connection {
+    pub fn new(stream: Stream) -> Connection {
+        Connection { reader: BufReader::new(stream) }
+    }
+
+    /// # 发送并立即刷新
+    pub fn send(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let stream = self.reader.get_mut();
+        stream.write_all(bytes)?;
+        stream.flush()
+    }
+
+    /// # 升级为 TLS（STARTTLS）
+    /// 丢弃升级前已缓冲但未处理的明文，防止命令注入（RFC 3207 4.2）。
+    /// ## 参数
+    /// - config: TLS 服务端配置
+    /// ## 返回值
+    /// - io::Result<Connection>
+    pub fn starttls(self, config: &Arc<ServerConfig>) -> io::Result<Connection> {
+        match self.reader.into_inner() {
+            Stream::Plain(tcp) => Ok(Connection::new(Stream::accept(tcp, config)?)),
+            _ => Err(io::Error::other("连接已经加密")),
+        }
+    }
+
+    /// # 以客户端身份升级为 TLS
+    /// ## 参数
+    /// - config: TLS 客户端配置
+    /// - name: 对方的主机名
+    /// ## 返回值
+    /// - io::Result<Connection>
+    pub fn connect(self, config: &Arc<ClientConfig>, name: &str) -> io::Result<Connection> {
+        match self.reader.into_inner() {
+            Stream::Plain(tcp) => Ok(Connection::new(Stream::connect(tcp, config, name)?)),
+            _ => Err(io::Error::other("连接已经加密")),
+        }
+    }
+
+    /// # 设置读取超时
+    pub fn set_read_timeout(&self, timeout: Duration) -> io::Result<()> {
+        self.reader.get_ref().tcp().set_read_timeout(Some(timeout))
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buffer)
+    }
+}
+
+impl BufRead for Connection {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.reader.consume(amount)
+    }
+}
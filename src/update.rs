@@ -0,0 +1,342 @@
+/* 命令 zmou update */
+/*
+# 更新
+1. 将 General.Server 中的 %file% 替换为当前平台的文件名，例如 zmou-linux-x86_64
+2. 下载文件本体、校验值 <文件名>.sha256 与签名 <文件名>.sig
+3. 校验 SHA-256 与 Ed25519 签名（公钥 General.UpdateKey）
+4. 写入 zmou.new 后替换当前程序，旧程序保留为 zmou.old，可使用 zmou update --rollback 回滚
+## 测试
+install() 接受下载地址与目标文件，测试时由本地的 TcpListener 提供更新文件
+ */
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{Config, ConfigError};
+use crate::utils::{from_hex, to_hex};
+
+/// 更新文件的大小上限（200 MiB）
+const MAX_SIZE: u64 = 200 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum UpdateError {
+    Config(ConfigError),
+    /// 下载失败
+    Download(String, String),
+    /// 校验值或签名不匹配
+    Verify(String),
+    /// 读写程序文件失败
+    Io(io::Error),
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::Config(e) => write!(f, "{}", e),
+            UpdateError::Download(url, e) => write!(f, "无法下载 {}：{}", url, e),
+            UpdateError::Verify(message) => write!(f, "校验失败：{}", message),
+            UpdateError::Io(e) => write!(f, "无法替换程序文件：{}", e),
+        }
+    }
+}
+
+impl From<ConfigError> for UpdateError {
+    fn from(e: ConfigError) -> Self {
+        UpdateError::Config(e)
+    }
+}
+
+impl From<io::Error> for UpdateError {
+    fn from(e: io::Error) -> Self {
+        UpdateError::Io(e)
+    }
+}
+
+/// # 当前平台的更新文件名
+/// ## 返回值
+/// - String，例如 zmou-linux-x86_64、zmou-windows-x86_64.exe
+pub fn artifact() -> String {
+    let suffix = if cfg!(windows) { ".exe" } else { "" };
+    format!("zmou-{}-{}{}", std::env::consts::OS, std::env::consts::ARCH, suffix)
+}
+
+/// # 替换 %file%
+/// ## 参数
+/// - server: General.Server
+/// - file: 文件名
+/// ## 返回值
+/// - String
+pub fn url(server: &str, file: &str) -> String {
+    server.replace("%file%", file)
+}
+
+/// # 是否为有效的更新公钥
+/// ## 参数
+/// - key: 64 位 16 进制字符
+/// ## 返回值
+/// - bool
+pub fn is_valid_key(key: &str) -> bool {
+    parse_key(key).is_some()
+}
+
+/// # 命令 zmou update
+/// ## 参数
+/// - path: 配置文件路径
+/// ## 返回值
+/// - Result<(), UpdateError>
+pub fn update(path: &Path) -> Result<(), UpdateError> {
+    let config = Config::load(path)?;
+    let Some(key) = parse_key(&config.general.update_key) else {
+        return Err(UpdateError::Verify(String::from("未配置有效的 [General] UpdateKey，无法校验更新文件")));
+    };
+    install(&config.general.server, &key, &std::env::current_exe()?)
+}
+
+/// # 下载、校验并替换程序文件
+/// ## 参数
+/// - server: 下载地址，%file% 替换为文件名，即 General.Server
+/// - key: 更新公钥
+/// - target: 要替换的程序文件，通常为当前程序
+/// ## 返回值
+/// - Result<(), UpdateError>
+fn install(server: &str, key: &VerifyingKey, target: &Path) -> Result<(), UpdateError> {
+    let file = artifact();
+    let binary = download(&url(server, &file))?;
+    let checksum = download(&url(server, &format!("{}.sha256", file)))?;
+    let signature = download(&url(server, &format!("{}.sig", file)))?;
+    verify(&binary, &checksum, &signature, key)?;
+    info!("更新文件 {} 校验通过", file);
+
+    if to_hex(&Sha256::digest(fs::read(target)?)) == to_hex(&Sha256::digest(&binary)) {
+        info!("已是最新版本");
+        return Ok(());
+    }
+    replace(target, &binary, |from, to| fs::rename(from, to))?;
+    info!("更新完成，旧版本已保留为 {}，可使用 zmou update --rollback 回滚", sibling(target, "old").display());
+    Ok(())
+}
+
+/// # 命令 zmou update --rollback
+/// 交换当前程序与 zmou.old，再次执行可撤销回滚。
+/// ## 返回值
+/// - Result<(), UpdateError>
+pub fn rollback() -> Result<(), UpdateError> {
+    let current = std::env::current_exe()?;
+    let old = sibling(&current, "old");
+    if !old.exists() {
+        return Err(UpdateError::Io(io::Error::new(io::ErrorKind::NotFound, format!("{} 不存在，没有可回滚的版本", old.display()))));
+    }
+    // 当前程序先保留为 zmou.swap，zmou.old 再通过一次重命名覆盖当前程序
+    let swap = sibling(&current, "swap");
+    keep(&current, &swap)?;
+    if let Err(e) = fs::rename(&old, &current) {
+        let _ = fs::remove_file(&swap);
+        return Err(e.into());
+    }
+    fs::rename(&swap, &old)?;
+    info!("已回滚到更新前的版本");
+    Ok(())
+}
+
+/// # 校验更新文件
+/// ## 参数
+/// - binary: 文件本体
+/// - checksum: <文件名>.sha256，格式为 sha256sum 的输出
+/// - signature: <文件名>.sig，64 字节签名或其 16 进制文本
+/// - key: 更新公钥
+/// ## 返回值
+/// - Result<(), UpdateError>
+fn verify(binary: &[u8], checksum: &[u8], signature: &[u8], key: &VerifyingKey) -> Result<(), UpdateError> {
+    let expected = String::from_utf8_lossy(checksum).split_whitespace().next().unwrap_or("").to_lowercase();
+    let actual = to_hex(&Sha256::digest(binary));
+    if expected != actual {
+        return Err(UpdateError::Verify(format!("SHA-256 不匹配，期望 {}，实际 {}", expected, actual)));
+    }
+
+    let bytes = match signature.len() {
+        64 => signature.to_vec(),
+        _ => from_hex(String::from_utf8_lossy(signature).trim()).unwrap_or_default(),
+    };
+    let signature = Signature::from_slice(&bytes).map_err(|_| UpdateError::Verify(String::from("签名格式错误")))?;
+    key.verify(binary, &signature).map_err(|_| UpdateError::Verify(String::from("签名无效")))
+}
+
+/// # 替换程序文件
+/// 新程序先完整写入 zmou.new，当前程序以硬链接（不支持时复制）保留为 zmou.old，
+/// 再通过一次重命名覆盖当前程序，任何时刻当前路径都是一个完整的程序文件。失败时删除 zmou.new。
+/// ## 参数
+/// - current: 当前程序路径
+/// - binary: 新程序
+/// - rename: 重命名文件，即 fs::rename，测试时用于模拟失败
+/// ## 返回值
+/// - io::Result<()>
+fn replace(current: &Path, binary: &[u8], rename: impl Fn(&Path, &Path) -> io::Result<()>) -> io::Result<()> {
+    let new = sibling(current, "new");
+    let result = stage(current, &new, binary).and_then(|_| keep(current, &sibling(current, "old"))).and_then(|_| rename(&new, current));
+    if result.is_err() {
+        let _ = fs::remove_file(&new);
+    }
+    result
+}
+
+/// # 完整写入新程序
+/// 写入并同步到磁盘，权限与当前程序相同。
+fn stage(current: &Path, new: &Path, binary: &[u8]) -> io::Result<()> {
+    let mut file = fs::File::create(new)?;
+    file.write_all(binary)?;
+    file.sync_all()?;
+    fs::set_permissions(new, fs::metadata(current)?.permissions())
+}
+
+/// # 保留程序文件的副本
+/// 优先使用硬链接，文件系统不支持时复制；已有的副本被覆盖。
+/// ## 参数
+/// - current: 程序文件
+/// - backup: 副本路径
+/// ## 返回值
+/// - io::Result<()>
+fn keep(current: &Path, backup: &Path) -> io::Result<()> {
+    match fs::remove_file(backup) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if fs::hard_link(current, backup).is_err() {
+        fs::copy(current, backup)?;
+    }
+    Ok(())
+}
+
+/// # 下载文件
+fn download(url: &str) -> Result<Vec<u8>, UpdateError> {
+    debug!("下载 {}", url);
+    let error = |e: &dyn fmt::Display| UpdateError::Download(url.to_string(), e.to_string());
+    let response = ureq::get(url).call().map_err(|e| error(&e))?;
+    let mut body = Vec::new();
+    response.into_body().into_reader().take(MAX_SIZE).read_to_end(&mut body).map_err(|e| error(&e))?;
+    Ok(body)
+}
+
+/// # 与程序同目录的文件，例如 zmou.old
+fn sibling(current: &Path, extension: &str) -> PathBuf {
+    let mut name = current.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(extension);
+    current.with_file_name(name)
+}
+
+fn parse_key(key: &str) -> Option<VerifyingKey> {
+    let bytes: [u8; 32] = from_hex(key.trim())?.try_into().ok()?;
+    VerifyingKey::from_bytes(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+    use std::collections::HashMap;
+    use std::io::BufRead;
+    use std::net::TcpListener;
+
+    const BINARY: &[u8] = b"new zmou binary";
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    /// 本地的下载服务器，返回 General.Server 形式的地址
+    fn serve(files: HashMap<String, Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut reader = io::BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let path = request.split_whitespace().nth(1).unwrap_or("/").trim_start_matches('/');
+                let response = match files.get(path) {
+                    Some(body) => [format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).into_bytes(), body.clone()].concat(),
+                    None => b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_vec(),
+                };
+                stream.write_all(&response).unwrap();
+            }
+        });
+        format!("http://{}/%file%", address)
+    }
+
+    /// 更新文件、校验值与签名
+    fn release(binary: &[u8], checksum: &[u8], signed: &[u8]) -> HashMap<String, Vec<u8>> {
+        let file = artifact();
+        HashMap::from([
+            (file.clone(), binary.to_vec()),
+            (format!("{}.sha256", file), [to_hex(&Sha256::digest(checksum)).as_bytes(), b"  ", file.as_bytes(), b"\n"].concat()),
+            (format!("{}.sig", file), signing_key().sign(signed).to_bytes().to_vec()),
+        ])
+    }
+
+    /// 测试用的程序文件，内容为 old zmou binary
+    fn target(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("zitmail-update-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        let target = directory.join("zmou");
+        fs::write(&target, b"old zmou binary").unwrap();
+        target
+    }
+
+    #[test]
+    fn installs_signed_release() {
+        let server = serve(release(BINARY, BINARY, BINARY));
+        let target = target("good");
+        install(&server, &signing_key().verifying_key(), &target).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), BINARY);
+        assert_eq!(fs::read(sibling(&target, "old")).unwrap(), b"old zmou binary");
+        assert!(!sibling(&target, "new").exists());
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let server = serve(release(BINARY, b"other binary", BINARY));
+        let target = target("checksum");
+        let result = install(&server, &signing_key().verifying_key(), &target);
+        assert!(matches!(result, Err(UpdateError::Verify(message)) if message.starts_with("SHA-256")));
+        assert_eq!(fs::read(&target).unwrap(), b"old zmou binary");
+        assert!(!sibling(&target, "old").exists());
+    }
+
+    #[test]
+    fn rejects_bad_signature() {
+        let server = serve(release(BINARY, BINARY, b"other binary"));
+        let target = target("signature");
+        let result = install(&server, &signing_key().verifying_key(), &target);
+        assert!(matches!(result, Err(UpdateError::Verify(message)) if message == "签名无效"));
+        assert_eq!(fs::read(&target).unwrap(), b"old zmou binary");
+        assert!(!sibling(&target, "old").exists());
+    }
+
+    #[test]
+    fn keeps_current_when_rename_fails() {
+        let target = target("rename");
+        let rename = |_: &Path, _: &Path| Err(io::Error::other("rename failed"));
+        assert!(replace(&target, BINARY, rename).is_err());
+        assert_eq!(fs::read(&target).unwrap(), b"old zmou binary");
+        assert!(!sibling(&target, "new").exists());
+    }
+
+    #[test]
+    fn replaces_existing_backup() {
+        let target = target("backup");
+        fs::write(sibling(&target, "old"), b"older zmou binary").unwrap();
+        replace(&target, BINARY, |from, to| fs::rename(from, to)).unwrap();
+        assert_eq!(fs::read(&target).unwrap(), BINARY);
+        assert_eq!(fs::read(sibling(&target, "old")).unwrap(), b"old zmou binary");
+        assert!(!sibling(&target, "new").exists());
+    }
+}
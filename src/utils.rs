@@ -1,6 +1,5 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
-use url;
-use chrono::{Utc, Local};
+use chrono::{DateTime, Local, SecondsFormat, Utc};
 use chrono_tz::Tz;
 use std::str::FromStr;
 use std::collections::HashMap;
@@ -46,7 +45,6 @@ pub fn hex_to_ansi(hex: &str) -> &str {
 /// - IP: &str
 /// ## 返回值
 /// - bool
-#[allow(dead_code)]
 pub fn is_valid_ip(ip: &str) -> bool {
     is_valid_ipv4(ip) || is_valid_ipv6(ip)
 }
@@ -77,11 +75,45 @@ pub fn is_valid_ipv6(ip: &str) -> bool {
 /// - url: &str
 /// ## 返回值
 /// - bool
-#[allow(dead_code)]
 pub fn is_valid_url(url: &str) -> bool {
     url::Url::parse(url).is_ok()
 }
 
+/// # 验证是否为主机名（域名）
+/// ## 参数
+/// - hostname: &str，例如 mail.example.com
+/// ## 返回值
+/// - bool
+pub fn is_valid_hostname(hostname: &str) -> bool {
+    let hostname = hostname.strip_suffix('.').unwrap_or(hostname);
+    !hostname.is_empty()
+        && hostname.len() <= 253
+        && hostname.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// # 验证是否为邮件地址
+/// 本地部分仅支持 dot-atom 形式，不支持带引号的本地部分。
+/// ## 参数
+/// - address: &str，例如 manser@example.com
+/// ## 返回值
+/// - bool
+pub fn is_valid_address(address: &str) -> bool {
+    let Some((local_part, domain)) = address.rsplit_once('@') else {
+        return false;
+    };
+    local_part.len() <= 64
+        && local_part.split('.').all(|atom| {
+            !atom.is_empty() && atom.chars().all(|c| (!c.is_ascii() && !c.is_control()) || c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c))
+        })
+        && is_valid_hostname(domain)
+}
+
 /// # 获取当前时间（格式化）
 /// ## 格式
 /// 2025年7月29日 16:30
@@ -90,16 +122,70 @@ pub fn is_valid_url(url: &str) -> bool {
 /// ## 返回值
 /// - String
 pub fn get_current_time(time_zone: &str) -> String {
+    format_time(Utc::now(), time_zone)
+}
+
+/// # 格式化时间
+/// ## 格式
+/// 2025年7月29日 16:30
+/// ## 参数
+/// - time: DateTime<Utc>
+/// - time_zone: &str
+/// ## 返回值
+/// - String
+pub fn format_time(time: DateTime<Utc>, time_zone: &str) -> String {
     match Tz::from_str(time_zone) {
         Ok(tz) => {
-            let now = Utc::now().with_timezone(&tz);
-            now.format("%Y年%-m月%d日 %H:%M").to_string()
+            time.with_timezone(&tz).format("%Y年%-m月%d日 %H:%M").to_string()
         },
         Err(_) => {
             // 如果时区解析失败，使用本地时间
-            let now = Local::now();
-            now.format("%Y年%-m月%d日 %H:%M").to_string()
+            time.with_timezone(&Local).format("%Y年%-m月%d日 %H:%M").to_string()
         }
     }
 }
 
+/// # 转换为带时区偏移的 RFC 3339 时间
+/// ## 格式
+/// 2025-07-29T16:30:00+08:00
+/// ## 参数
+/// - time: DateTime<Utc>
+/// - time_zone: &str
+/// ## 返回值
+/// - String
+pub fn to_rfc3339(time: DateTime<Utc>, time_zone: &str) -> String {
+    match Tz::from_str(time_zone) {
+        Ok(tz) => time.with_timezone(&tz).to_rfc3339_opts(SecondsFormat::Secs, true),
+        Err(_) => time.with_timezone(&Local).to_rfc3339_opts(SecondsFormat::Secs, true),
+    }
+}
+
+/// # 解析 RFC 3339 时间
+/// ## 参数
+/// - text: &str
+/// ## 返回值
+/// - Option<DateTime<Utc>>
+pub fn parse_rfc3339(text: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text).ok().map(|time| time.with_timezone(&Utc))
+}
+
+/// # 转换为小写 16 进制字符串
+/// ## 参数
+/// - bytes: &[u8]
+/// ## 返回值
+/// - String
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// # 解析 16 进制字符串
+/// ## 参数
+/// - text: &str
+/// ## 返回值
+/// - Option<Vec<u8>>
+pub fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}